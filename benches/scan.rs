@@ -0,0 +1,70 @@
+//! 字节扫描的吞吐量对比
+//!
+//! 用法: `cargo bench --bench scan`
+//!
+//! 在16MB的缓冲区上分别用逐字节比较与`scan`模块的向量化实现做全量扫描，输出MB/s
+
+use anti_debug::scan;
+use std::{hint::black_box, time::Instant};
+
+const SIZE: usize = 16 * 1024 * 1024;
+const ROUNDS: u32 = 20;
+
+/// 执行`ROUNDS`次`f`，返回吞吐量(MB/s)
+fn throughput<F: FnMut() -> Option<usize>>(mut f: F) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        black_box(f());
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    (SIZE as f64 * ROUNDS as f64) / (1024.0 * 1024.0) / seconds
+}
+
+fn report(name: &str, scalar: f64, vectorized: f64) {
+    println!(
+        "{:<16} scalar: {:>9.1} MB/s; scan: {:>9.1} MB/s; x{:.1}",
+        name,
+        scalar,
+        vectorized,
+        vectorized / scalar
+    );
+}
+
+fn main() {
+    // 不包含0xCC的数据，扫描必须走完整个缓冲区
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 0xCB) as u8).collect();
+    let copy = data.clone();
+    let data = black_box(&data[..]);
+    let copy = black_box(&copy[..]);
+
+    report(
+        "find_byte",
+        throughput(|| data.iter().position(|&b| b == 0xCC)),
+        throughput(|| scan::find_byte(data, 0xCC)),
+    );
+    report(
+        "find_mismatch",
+        throughput(|| data.iter().zip(copy).position(|(l, r)| l != r)),
+        throughput(|| scan::find_mismatch(data, copy)),
+    );
+    report(
+        "find_bytes",
+        throughput(|| {
+            data.windows(4)
+                .position(|window| window == [0xCC, 0x90, 0x90, 0xCC])
+        }),
+        throughput(|| scan::find_bytes(data, &[0xCC, 0x90, 0x90, 0xCC])),
+    );
+    report(
+        "find_differences",
+        throughput(|| {
+            data.iter()
+                .zip(copy)
+                .enumerate()
+                .filter(|(_, (l, r))| l != r)
+                .map(|(i, _)| i)
+                .next()
+        }),
+        throughput(|| scan::find_differences(data, copy, 64).first().copied()),
+    );
+}
@@ -0,0 +1,27 @@
+//! 为编译期字符串加密生成本次构建的密钥
+//!
+//! 设置`ANTI_DEBUG_OBF_SEED`环境变量可以固定密钥，得到可复现的构建
+
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=ANTI_DEBUG_OBF_SEED");
+    println!("cargo:rerun-if-changed=src");
+
+    let key = match env::var("ANTI_DEBUG_OBF_SEED") {
+        Ok(seed) => seed.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        }),
+        Err(_) => {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64);
+            nanos ^ (std::process::id() as u64).rotate_left(32)
+        }
+    };
+
+    println!("cargo:rustc-env=ANTI_DEBUG_OBF_KEY={}", key);
+}
@@ -0,0 +1,226 @@
+use crate::logging::{debug, warn};
+use crate::{
+    imports::{GetModuleHandleW, VirtualProtect},
+    ldr::{self, LoaderLock},
+    pe::PeImage,
+};
+use anyhow::{Error, Result};
+use std::{ffi::c_void, sync::Mutex};
+use windows::Win32::System::Memory::{PAGE_PROTECTION_FLAGS, PAGE_READWRITE};
+
+/// `enable`在真实SizeOfImage基础上增加的大小，转储工具按该大小读取时会读到未映射的内存
+pub const SIZE_OF_IMAGE_PADDING: u32 = 0x10_0000;
+
+/// 被擦除的PE头
+///
+/// - `base`: 主模块基址
+/// - `bytes`: 原始PE头
+/// - `active`: 是否需要保持擦除状态，`restore_headers`后为false
+/// - `erased`: 当前内存中的PE头是否处于擦除状态
+/// - `depth`: 正在执行的`with_headers`数量，为0时才重新擦除
+struct ErasedHeaders {
+    base: usize,
+    bytes: Vec<u8>,
+    active: bool,
+    erased: bool,
+    depth: usize,
+}
+
+static HEADERS: Mutex<Option<ErasedHeaders>> = Mutex::new(None);
+
+/// 修改可能只读的内存，完成后恢复原有权限
+fn write_protected(address: usize, bytes: &[u8]) -> Result<()> {
+    let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+    unsafe {
+        VirtualProtect(
+            address as *const c_void,
+            bytes.len(),
+            PAGE_READWRITE,
+            &mut old_protect,
+        )
+    }?;
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len()) };
+    let mut protect = PAGE_PROTECTION_FLAGS::default();
+    unsafe {
+        VirtualProtect(
+            address as *const c_void,
+            bytes.len(),
+            old_protect,
+            &mut protect,
+        )
+    }?;
+    Ok(())
+}
+
+/// 主模块的PE头是否处于擦除状态
+pub fn is_headers_erased() -> bool {
+    HEADERS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|headers| headers.erased)
+}
+
+/// 擦除主模块内存中的DOS头、NT头与节表
+///
+/// Scylla、ProcDump等工具依赖内存中的PE头重建映像，擦除后无法直接转储出可用的文件。
+/// 原始PE头保存在堆中，可以通过`restore_headers`或`with_headers`恢复
+///
+/// # 返回值
+///
+/// - `Err`: 主模块PE头无效或者修改内存权限失败
+/// - `Ok(())`: 擦除成功，已经擦除时直接返回
+///
+/// # 注意
+///
+/// 需要在初始化完成后调用。擦除后`GetProcAddress(主模块)`、资源加载等依赖PE头的系统API会失败，
+/// 本库中需要主模块PE头的检测(IAT hook、HMAC自校验)会通过`with_headers`临时恢复
+pub fn erase_headers() -> Result<()> {
+    let mut guard = HEADERS.lock().unwrap();
+    if let Some(headers) = guard.as_mut() {
+        // 有with_headers正在执行时推迟到其结束后擦除
+        if !headers.erased && headers.depth == 0 {
+            write_protected(headers.base, &vec![0; headers.bytes.len()])?;
+            headers.erased = true;
+        }
+        headers.active = true;
+        return Ok(());
+    }
+
+    let hmodule = unsafe { GetModuleHandleW(None) }?;
+    let image = PeImage::from_module(hmodule)?;
+    let size_of_headers = image.nt_headers()?.OptionalHeader.SizeOfHeaders as usize;
+    let Some(bytes) = image.bytes(0, size_of_headers) else {
+        return Err(Error::msg("invalid SizeOfHeaders"));
+    };
+    let bytes = bytes.to_vec();
+
+    write_protected(image.base(), &vec![0; bytes.len()])?;
+    debug!(
+        "erase headers ==> base: {:#x}; size: {:#x}",
+        image.base(),
+        bytes.len()
+    );
+    *guard = Some(ErasedHeaders {
+        base: image.base(),
+        bytes,
+        active: true,
+        erased: true,
+        depth: 0,
+    });
+
+    Ok(())
+}
+
+/// 将主模块的PE头恢复为原始内容，之后不再擦除
+///
+/// # 返回值
+///
+/// - `Err`: 修改内存权限失败
+/// - `Ok(())`: 恢复成功，没有擦除过时直接返回
+pub fn restore_headers() -> Result<()> {
+    let mut guard = HEADERS.lock().unwrap();
+    if let Some(headers) = guard.as_mut() {
+        if headers.erased {
+            write_protected(headers.base, &headers.bytes)?;
+            headers.erased = false;
+        }
+        headers.active = false;
+    }
+
+    Ok(())
+}
+
+/// 临时恢复主模块PE头执行`f`，执行完毕后重新擦除
+///
+/// 可以嵌套调用，也可以在多个线程中同时调用，最外层调用结束后才重新擦除。
+/// 没有擦除过PE头时直接执行`f`
+///
+/// # 示例
+///
+/// ```ignore
+/// let image_size = with_headers(|| {
+///     let hmodule = unsafe { GetModuleHandleW(None) }.unwrap();
+///     PeImage::from_module(hmodule).unwrap().size()
+/// });
+/// ```
+pub fn with_headers<R, F: FnOnce() -> R>(f: F) -> R {
+    {
+        let mut guard = HEADERS.lock().unwrap();
+        if let Some(headers) = guard.as_mut() {
+            if headers.erased {
+                match write_protected(headers.base, &headers.bytes) {
+                    Ok(()) => headers.erased = false,
+                    Err(e) => warn!("rematerialize headers failed; error: {:?}", e),
+                }
+            }
+            headers.depth += 1;
+        }
+    }
+
+    let result = f();
+
+    let mut guard = HEADERS.lock().unwrap();
+    if let Some(headers) = guard.as_mut() {
+        headers.depth -= 1;
+        if headers.depth == 0 && headers.active && !headers.erased {
+            match write_protected(headers.base, &vec![0; headers.bytes.len()]) {
+                Ok(()) => headers.erased = true,
+                Err(e) => warn!("erase headers failed; error: {:?}", e),
+            }
+        }
+    }
+
+    result
+}
+
+/// 修改PEB Ldr链表中主模块条目的SizeOfImage
+///
+/// 转储工具通过Toolhelp/PSAPI获取的模块大小来自该字段，改大后按该大小读取会失败，
+/// 改小后只能转储出映像的一部分
+///
+/// # 参数
+///
+/// - `size`: 新的SizeOfImage
+///
+/// # 返回值
+///
+/// - `Err`: 获取加载器锁失败或者Ldr链表中没有主模块
+/// - `Ok(original)`: 修改前的SizeOfImage
+pub fn spoof_size_of_image(size: u32) -> Result<u32> {
+    let hmodule = unsafe { GetModuleHandleW(None) }?;
+    let _lock = LoaderLock::acquire()?;
+    let Some(entry) = (unsafe { ldr::find_entry(hmodule.0 as usize) }) else {
+        return Err(Error::msg("main module not found in ldr list"));
+    };
+
+    let original = unsafe { (*entry).size_of_image };
+    unsafe { (*entry).size_of_image = size };
+    debug!("spoof SizeOfImage ==> {:#x} -> {:#x}", original, size);
+
+    Ok(original)
+}
+
+/// 启用防转储：擦除PE头，并将Ldr条目中的SizeOfImage增大`SIZE_OF_IMAGE_PADDING`
+///
+/// # 示例
+///
+/// ```ignore
+/// fn main() {
+///     init();
+///     anti_dump::enable().unwrap();
+///     run();
+/// }
+/// ```
+pub fn enable() -> Result<()> {
+    let hmodule = unsafe { GetModuleHandleW(None) }?;
+    let size_of_image = with_headers(|| -> Result<u32> {
+        Ok(PeImage::from_module(hmodule)?
+            .nt_headers()?
+            .OptionalHeader
+            .SizeOfImage)
+    })?;
+
+    spoof_size_of_image(size_of_image.saturating_add(SIZE_OF_IMAGE_PADDING))?;
+    erase_headers()
+}
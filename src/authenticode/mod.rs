@@ -0,0 +1,272 @@
+use crate::logging::{debug, warn};
+use crate::{hook::get_module_path, module::get_loaded_modules, util::to_wide};
+use anyhow::Result;
+use std::{
+    ffi::c_void,
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+use windows::{
+    core::{w, PCWSTR},
+    Win32::{
+        Foundation::{CloseHandle, GENERIC_READ, HANDLE, HWND, TRUST_E_NOSIGNATURE},
+        Security::{
+            Cryptography::Catalog::{
+                CryptCATAdminAcquireContext2, CryptCATAdminCalcHashFromFileHandle2,
+                CryptCATAdminEnumCatalogFromHash, CryptCATAdminReleaseCatalogContext,
+                CryptCATAdminReleaseContext, CryptCATCatalogInfoFromContext, CATALOG_INFO,
+            },
+            WinTrust::{
+                WinVerifyTrust, WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_CATALOG_INFO,
+                WINTRUST_DATA, WINTRUST_DATA_0, WINTRUST_FILE_INFO, WTD_CACHE_ONLY_URL_RETRIEVAL,
+                WTD_CHOICE_CATALOG, WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE,
+                WTD_STATEACTION_VERIFY, WTD_UI_NONE,
+            },
+        },
+        Storage::FileSystem::{
+            CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_DELETE, FILE_SHARE_READ, OPEN_EXISTING,
+        },
+    },
+};
+
+/// 文件签名状态
+///
+/// - `Embedded`: 文件内嵌Authenticode签名且验证通过
+/// - `Catalog`: 文件哈希位于系统catalog中且catalog签名验证通过，系统DLL大多属于这种情况
+/// - `Unsigned`: 文件没有签名，也不在任何catalog中
+/// - `Invalid(code)`: 签名验证失败，`code`为WinVerifyTrust返回的错误码
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrustStatus {
+    Embedded,
+    Catalog,
+    Unsigned,
+    Invalid(i32),
+}
+
+impl TrustStatus {
+    /// 签名是否验证通过
+    pub fn is_trusted(&self) -> bool {
+        matches!(self, TrustStatus::Embedded | TrustStatus::Catalog)
+    }
+}
+
+/// 签名验证未通过的已加载模块
+///
+/// - `path`: 模块文件路径
+/// - `status`: 签名状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct UntrustedModule {
+    pub path: PathBuf,
+    pub status: TrustStatus,
+}
+
+/// 调用WinVerifyTrust验证，完成后释放验证状态
+fn win_verify_trust(data: &mut WINTRUST_DATA) -> i32 {
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    data.cbStruct = size_of::<WINTRUST_DATA>() as u32;
+    data.dwUIChoice = WTD_UI_NONE;
+    // 不联网检查吊销状态，避免验证被网络阻塞
+    data.fdwRevocationChecks = WTD_REVOKE_NONE;
+    data.dwProvFlags = WTD_CACHE_ONLY_URL_RETRIEVAL;
+    data.dwStateAction = WTD_STATEACTION_VERIFY;
+    let status = unsafe {
+        WinVerifyTrust(
+            HWND::default(),
+            &mut action,
+            data as *mut WINTRUST_DATA as *mut c_void,
+        )
+    };
+
+    data.dwStateAction = WTD_STATEACTION_CLOSE;
+    unsafe {
+        WinVerifyTrust(
+            HWND::default(),
+            &mut action,
+            data as *mut WINTRUST_DATA as *mut c_void,
+        )
+    };
+
+    status
+}
+
+/// 验证文件内嵌的Authenticode签名
+fn verify_embedded(path: &[u16]) -> i32 {
+    let mut file_info = WINTRUST_FILE_INFO {
+        cbStruct: size_of::<WINTRUST_FILE_INFO>() as u32,
+        pcwszFilePath: PCWSTR(path.as_ptr()),
+        ..Default::default()
+    };
+    let mut data = WINTRUST_DATA {
+        dwUnionChoice: WTD_CHOICE_FILE,
+        Anonymous: WINTRUST_DATA_0 {
+            pFile: &mut file_info,
+        },
+        ..Default::default()
+    };
+
+    win_verify_trust(&mut data)
+}
+
+/// 在系统catalog中查找文件哈希并验证
+///
+/// 依次使用SHA256(Windows 8及以上)与SHA1的catalog数据库
+///
+/// # 返回值
+///
+/// - `Some(status)`: WinVerifyTrust的返回值
+/// - `None`: 文件不在任何catalog中
+fn verify_catalog(path: &[u16]) -> Result<Option<i32>> {
+    let hfile = unsafe {
+        CreateFileW(
+            PCWSTR(path.as_ptr()),
+            GENERIC_READ.0,
+            FILE_SHARE_READ | FILE_SHARE_DELETE,
+            None,
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            HANDLE::default(),
+        )
+    }?;
+
+    let mut result = None;
+    for algorithm in [w!("SHA256"), PCWSTR::null()] {
+        result = verify_catalog_with(hfile, path, algorithm);
+        if result.is_some() {
+            break;
+        }
+    }
+
+    let _ = unsafe { CloseHandle(hfile) };
+    Ok(result)
+}
+
+fn verify_catalog_with(hfile: HANDLE, path: &[u16], algorithm: PCWSTR) -> Option<i32> {
+    let mut hcatadmin: isize = 0;
+    unsafe { CryptCATAdminAcquireContext2(&mut hcatadmin, None, algorithm, None, 0) }.ok()?;
+
+    let mut result = None;
+    let mut hash_size: u32 = 0;
+    let _ =
+        unsafe { CryptCATAdminCalcHashFromFileHandle2(hcatadmin, hfile, &mut hash_size, None, 0) };
+    let mut hash: Vec<u8> = vec![0; hash_size as usize];
+    let calculated = hash_size > 0
+        && unsafe {
+            CryptCATAdminCalcHashFromFileHandle2(
+                hcatadmin,
+                hfile,
+                &mut hash_size,
+                Some(hash.as_mut_ptr()),
+                0,
+            )
+        }
+        .is_ok();
+
+    let hcatinfo = match calculated {
+        true => unsafe { CryptCATAdminEnumCatalogFromHash(hcatadmin, &hash, 0, None) },
+        false => 0,
+    };
+    if hcatinfo != 0 {
+        let mut catalog = CATALOG_INFO {
+            cbStruct: size_of::<CATALOG_INFO>() as u32,
+            ..Default::default()
+        };
+        if unsafe { CryptCATCatalogInfoFromContext(hcatinfo, &mut catalog, 0) }.is_ok() {
+            // catalog中的成员标签为哈希的大写十六进制字符串
+            let tag: String = hash.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let tag = to_wide(&tag);
+            let mut catalog_info = WINTRUST_CATALOG_INFO {
+                cbStruct: size_of::<WINTRUST_CATALOG_INFO>() as u32,
+                pcwszCatalogFilePath: PCWSTR(catalog.wszCatalogFile.as_ptr()),
+                pcwszMemberTag: PCWSTR(tag.as_ptr()),
+                pcwszMemberFilePath: PCWSTR(path.as_ptr()),
+                hMemberFile: hfile,
+                pbCalculatedFileHash: hash.as_mut_ptr(),
+                cbCalculatedFileHash: hash_size,
+                hCatAdmin: hcatadmin,
+                ..Default::default()
+            };
+            let mut data = WINTRUST_DATA {
+                dwUnionChoice: WTD_CHOICE_CATALOG,
+                Anonymous: WINTRUST_DATA_0 {
+                    pCatalog: &mut catalog_info,
+                },
+                ..Default::default()
+            };
+            result = Some(win_verify_trust(&mut data));
+        }
+
+        let _ = unsafe { CryptCATAdminReleaseCatalogContext(hcatadmin, hcatinfo, 0) };
+    }
+
+    let _ = unsafe { CryptCATAdminReleaseContext(hcatadmin, 0) };
+    result
+}
+
+/// 验证文件的Authenticode签名
+///
+/// 先验证内嵌签名，没有内嵌签名时再查找系统catalog
+///
+/// # 参数
+///
+/// - `path`: 文件路径
+///
+/// # 返回值
+///
+/// - `Err`: 打开文件失败
+/// - `Ok(status)`: 签名状态
+///
+/// # 示例
+///
+/// ```ignore
+/// let status = verify_file("C:\\Windows\\System32\\ntdll.dll").unwrap();
+/// assert!(status.is_trusted());
+/// ```
+pub fn verify_file<P: AsRef<Path>>(path: P) -> Result<TrustStatus> {
+    let wide = to_wide(&path.as_ref().to_string_lossy());
+
+    let status = verify_embedded(&wide);
+    if status == 0 {
+        return Ok(TrustStatus::Embedded);
+    }
+    if status != TRUST_E_NOSIGNATURE.0 {
+        return Ok(TrustStatus::Invalid(status));
+    }
+
+    Ok(match verify_catalog(&wide)? {
+        Some(0) => TrustStatus::Catalog,
+        Some(status) => TrustStatus::Invalid(status),
+        None => TrustStatus::Unsigned,
+    })
+}
+
+/// 验证当前进程中所有已加载DLL的签名
+///
+/// 注入的插桩DLL(调试器插件、hook框架、游戏外挂等)通常没有签名，
+/// 主模块本身不在检查范围内
+///
+/// # 返回值
+///
+/// - `Err`: 枚举模块失败
+/// - `Ok(modules)`: 签名验证未通过的DLL列表
+pub fn check_loaded_modules() -> Result<Vec<UntrustedModule>> {
+    let mut untrusted: Vec<UntrustedModule> = Vec::new();
+    for hmodule in get_loaded_modules()?.into_iter().skip(1) {
+        let Ok(path) = get_module_path(hmodule) else {
+            continue;
+        };
+
+        let status = match verify_file(&path) {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("verify {:?} failed; error: {:?}", path, e);
+                continue;
+            }
+        };
+        if !status.is_trusted() {
+            debug!("untrusted module ==> {:?}; status: {:?}", path, status);
+            untrusted.push(UntrustedModule { path, status });
+        }
+    }
+
+    Ok(untrusted)
+}
@@ -0,0 +1,373 @@
+use core::{arch::asm, ptr, slice};
+
+/// PEB字段偏移(Vista及以上)
+#[cfg(target_pointer_width = "64")]
+mod offsets {
+    pub const PEB_LDR: usize = 0x18;
+    pub const PEB_PROCESS_HEAP: usize = 0x30;
+    pub const PEB_NT_GLOBAL_FLAG: usize = 0xBC;
+    pub const HEAP_FLAGS: usize = 0x70;
+    pub const HEAP_FORCE_FLAGS: usize = 0x74;
+    pub const LDR_IN_LOAD_ORDER: usize = 0x10;
+    pub const ENTRY_DLL_BASE: usize = 0x30;
+    pub const ENTRY_BASE_DLL_NAME: usize = 0x58;
+    pub const UNICODE_STRING_BUFFER: usize = 0x8;
+    pub const NT_EXPORT_DIRECTORY: usize = 0x88;
+}
+
+#[cfg(target_pointer_width = "32")]
+mod offsets {
+    pub const PEB_LDR: usize = 0x0C;
+    pub const PEB_PROCESS_HEAP: usize = 0x18;
+    pub const PEB_NT_GLOBAL_FLAG: usize = 0x68;
+    pub const HEAP_FLAGS: usize = 0x40;
+    pub const HEAP_FORCE_FLAGS: usize = 0x44;
+    pub const LDR_IN_LOAD_ORDER: usize = 0x0C;
+    pub const ENTRY_DLL_BASE: usize = 0x18;
+    pub const ENTRY_BASE_DLL_NAME: usize = 0x2C;
+    pub const UNICODE_STRING_BUFFER: usize = 0x4;
+    pub const NT_EXPORT_DIRECTORY: usize = 0x78;
+}
+
+use offsets::*;
+
+/// `ntdll.dll`的哈希
+#[cfg(target_arch = "x86_64")]
+const NTDLL: u32 = hash(b"ntdll.dll");
+/// `NtQueryInformationProcess`的哈希
+#[cfg(target_arch = "x86_64")]
+const NT_QUERY_INFORMATION_PROCESS: u32 = hash(b"NtQueryInformationProcess");
+/// `NtGetContextThread`的哈希
+#[cfg(target_arch = "x86_64")]
+const NT_GET_CONTEXT_THREAD: u32 = hash(b"NtGetContextThread");
+
+/// x64 syscall存根开头：`mov r10, rcx; mov eax, SSN`
+#[cfg(target_arch = "x86_64")]
+const STUB_PREFIX: [u8; 4] = [0x4c, 0x8b, 0xd1, 0xb8];
+/// 相邻syscall存根之间的距离
+#[cfg(target_arch = "x86_64")]
+const STUB_SIZE: usize = 0x20;
+/// 存根被hook时向两侧查找完好存根的数量
+#[cfg(target_arch = "x86_64")]
+const STUB_NEIGHBORS: usize = 16;
+
+/// x64 CONTEXT结构体的大小与字段偏移
+#[cfg(target_arch = "x86_64")]
+const CONTEXT_SIZE: usize = 0x4D0;
+#[cfg(target_arch = "x86_64")]
+const CONTEXT_FLAGS: usize = 0x30;
+#[cfg(target_arch = "x86_64")]
+const CONTEXT_DR0: usize = 0x48;
+/// CONTEXT_AMD64 | CONTEXT_DEBUG_REGISTERS
+#[cfg(target_arch = "x86_64")]
+const CONTEXT_DEBUG_REGISTERS: u32 = 0x0010_0010;
+/// ProcessDebugPort信息类别
+#[cfg(target_arch = "x86_64")]
+const PROCESS_DEBUG_PORT: usize = 7;
+
+/// x64 CONTEXT，只按偏移读写需要的字段
+#[cfg(target_arch = "x86_64")]
+#[repr(C, align(16))]
+struct Context([u8; CONTEXT_SIZE]);
+
+/// 计算模块名或者导出函数名的哈希，不区分大小写，编译期求值
+pub const fn hash(name: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < name.len() {
+        hash = step(hash, name[i] as u16);
+        i += 1;
+    }
+    hash
+}
+
+/// FNV-1a的一步，ASCII大写字母按小写处理
+const fn step(hash: u32, unit: u16) -> u32 {
+    let unit = if unit >= b'A' as u16 && unit <= b'Z' as u16 {
+        unit + 32
+    } else {
+        unit
+    };
+    (hash ^ unit as u32).wrapping_mul(0x0100_0193)
+}
+
+/// 读取指定地址的值
+#[inline(always)]
+unsafe fn read<T: Copy>(address: *const u8) -> T {
+    ptr::read_volatile(address as *const T)
+}
+
+/// 当前进程的PEB地址，64位读取gs:[0x60]，32位读取fs:[0x30]
+#[inline(always)]
+pub fn peb() -> *const u8 {
+    let peb: *const u8;
+
+    #[cfg(target_pointer_width = "64")]
+    unsafe {
+        asm!(
+            "mov {}, gs:[0x60]",
+            out(reg) peb,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    unsafe {
+        asm!(
+            "mov {}, fs:[0x30]",
+            out(reg) peb,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+
+    peb
+}
+
+/// 检测PEB.BeingDebugged
+#[inline(always)]
+pub fn being_debugged() -> bool {
+    unsafe { read::<u8>(peb().add(2)) != 0 }
+}
+
+/// 检测PEB.NtGlobalFlag是否为0x70(FLG_HEAP_ENABLE_TAIL_CHECK等三个调试堆标志)
+#[inline(always)]
+pub fn nt_global_flag() -> bool {
+    unsafe { read::<u32>(peb().add(PEB_NT_GLOBAL_FLAG)) & 0x70 == 0x70 }
+}
+
+/// 检测进程堆的Flags与ForceFlags
+///
+/// # 返回值
+///
+/// - `false`: 进程未被调试，或者PEB.ProcessHeap为null
+/// - `true`：进程正在被调试
+#[inline(always)]
+pub fn heap_flags() -> bool {
+    let heap: *const u8 = unsafe { read(peb().add(PEB_PROCESS_HEAP)) };
+    if heap.is_null() {
+        return false;
+    }
+
+    let (flags, force_flags): (u32, u32) =
+        unsafe { (read(heap.add(HEAP_FLAGS)), read(heap.add(HEAP_FORCE_FLAGS))) };
+    flags != 2 || force_flags != 0
+}
+
+/// 遍历PEB Ldr，查找文件名哈希匹配的模块
+///
+/// # 参数
+///
+/// - `module`: 模块文件名的哈希，例如`hash(b"ntdll.dll")`
+///
+/// # 返回值
+///
+/// - `Some(base)`: 模块基址
+/// - `None`: 模块没有加载
+pub fn find_module(module: u32) -> Option<*const u8> {
+    unsafe {
+        let ldr: *const u8 = read(peb().add(PEB_LDR));
+        let head = ldr.add(LDR_IN_LOAD_ORDER);
+        let mut current: *const u8 = read(head);
+        while !current.is_null() && current != head {
+            let name = current.add(ENTRY_BASE_DLL_NAME);
+            let length = read::<u16>(name) as usize / 2;
+            let buffer: *const u16 = read(name.add(UNICODE_STRING_BUFFER));
+            if !buffer.is_null() {
+                let name = slice::from_raw_parts(buffer, length);
+                let mut hash: u32 = 0x811c_9dc5;
+                for &unit in name {
+                    hash = step(hash, unit);
+                }
+                if hash == module {
+                    return Some(read(current.add(ENTRY_DLL_BASE)));
+                }
+            }
+            current = read(current);
+        }
+    }
+
+    None
+}
+
+/// 解析模块导出表，查找名称哈希匹配的导出函数，不处理转发导出
+///
+/// # 参数
+///
+/// - `base`: 模块基址
+/// - `function`: 导出函数名的哈希
+///
+/// # Safety
+///
+/// `base`必须是已加载模块的基址
+pub unsafe fn find_export(base: *const u8, function: u32) -> Option<*const u8> {
+    let nt = base.add(read::<u32>(base.add(0x3C)) as usize);
+    let directory = read::<u32>(nt.add(NT_EXPORT_DIRECTORY)) as usize;
+    if directory == 0 {
+        return None;
+    }
+
+    let export = base.add(directory);
+    let count = read::<u32>(export.add(0x18)) as usize;
+    let functions = base.add(read::<u32>(export.add(0x1C)) as usize);
+    let names = base.add(read::<u32>(export.add(0x20)) as usize);
+    let ordinals = base.add(read::<u32>(export.add(0x24)) as usize);
+    for i in 0..count {
+        let name = base.add(read::<u32>(names.add(i * 4)) as usize);
+        let mut length = 0;
+        while read::<u8>(name.add(length)) != 0 {
+            length += 1;
+        }
+        if hash(slice::from_raw_parts(name, length)) == function {
+            let index = read::<u16>(ordinals.add(i * 2)) as usize;
+            return Some(base.add(read::<u32>(functions.add(index * 4)) as usize));
+        }
+    }
+
+    None
+}
+
+/// 读取syscall存根中的系统调用号，存根格式异常时返回None
+#[cfg(target_arch = "x86_64")]
+unsafe fn stub_ssn(stub: *const u8) -> Option<u32> {
+    let code: [u8; 8] = read(stub);
+    (code[..4] == STUB_PREFIX).then(|| u32::from_le_bytes([code[4], code[5], code[6], code[7]]))
+}
+
+/// 获取ntdll中Nt函数的系统调用号
+///
+/// 存根被inline hook改写时，向两侧查找完好的相邻存根，按距离推算系统调用号
+///
+/// # 参数
+///
+/// - `function`: Nt函数名的哈希
+#[cfg(target_arch = "x86_64")]
+pub fn syscall_number(function: u32) -> Option<u32> {
+    unsafe {
+        let stub = find_export(find_module(NTDLL)?, function)?;
+        if let Some(ssn) = stub_ssn(stub) {
+            return Some(ssn);
+        }
+        for i in 1..=STUB_NEIGHBORS {
+            if let Some(ssn) = stub_ssn(stub.add(i * STUB_SIZE)) {
+                return ssn.checked_sub(i as u32);
+            }
+            if let Some(ssn) = stub_ssn(stub.sub(i * STUB_SIZE)) {
+                return Some(ssn + i as u32);
+            }
+        }
+    }
+
+    None
+}
+
+/// 直接执行syscall指令，不经过ntdll中的存根
+///
+/// # 参数
+///
+/// - `ssn`: 系统调用号
+/// - `args`: 前5个参数，不足5个时其余参数任意
+///
+/// # 返回值
+///
+/// - NTSTATUS
+///
+/// # Safety
+///
+/// 参数必须与系统调用的定义一致
+#[cfg(target_arch = "x86_64")]
+pub unsafe fn syscall(ssn: u32, args: [usize; 5]) -> i32 {
+    let status: usize;
+    // 第5个参数位于返回地址与32字节影子空间之后，即[rsp+0x28]
+    asm!(
+        "sub rsp, 0x30",
+        "mov [rsp + 0x28], {arg5}",
+        "mov r10, rcx",
+        "syscall",
+        "add rsp, 0x30",
+        arg5 = in(reg) args[4],
+        inlateout("rax") ssn as usize => status,
+        inlateout("rcx") args[0] => _,
+        inlateout("rdx") args[1] => _,
+        inlateout("r8") args[2] => _,
+        inlateout("r9") args[3] => _,
+        lateout("r10") _,
+        lateout("r11") _,
+    );
+    status as i32
+}
+
+/// 通过直接syscall调用NtQueryInformationProcess查询ProcessDebugPort
+///
+/// # 返回值
+///
+/// - `None`: 没有找到系统调用号或者查询失败
+/// - `Some(true)`: 调试端口存在
+/// - `Some(false)`: 调试端口不存在
+#[cfg(target_arch = "x86_64")]
+pub fn debug_port() -> Option<bool> {
+    let ssn = syscall_number(NT_QUERY_INFORMATION_PROCESS)?;
+    let mut port: usize = 0;
+    let status = unsafe {
+        syscall(
+            ssn,
+            [
+                usize::MAX,
+                PROCESS_DEBUG_PORT,
+                &mut port as *mut usize as usize,
+                8,
+                0,
+            ],
+        )
+    };
+    (status >= 0).then_some(port != 0)
+}
+
+/// 通过直接syscall调用NtGetContextThread读取当前线程的Dr0~Dr3
+///
+/// # 返回值
+///
+/// - `None`: 没有找到系统调用号或者读取失败
+/// - `Some(true)`: 设置了硬件断点
+/// - `Some(false)`: 没有硬件断点
+#[cfg(target_arch = "x86_64")]
+pub fn hardware_breakpoints() -> Option<bool> {
+    let ssn = syscall_number(NT_GET_CONTEXT_THREAD)?;
+    let mut context = Context([0; CONTEXT_SIZE]);
+    context.0[CONTEXT_FLAGS..CONTEXT_FLAGS + 4]
+        .copy_from_slice(&CONTEXT_DEBUG_REGISTERS.to_le_bytes());
+    // -2为当前线程的伪句柄
+    let status = unsafe {
+        syscall(
+            ssn,
+            [
+                usize::MAX - 1,
+                &mut context as *mut Context as usize,
+                0,
+                0,
+                0,
+            ],
+        )
+    };
+    if status < 0 {
+        return None;
+    }
+
+    let registers = unsafe { slice::from_raw_parts(context.0.as_ptr().add(CONTEXT_DR0), 32) };
+    Some(registers.iter().any(|&byte| byte != 0))
+}
+
+/// 执行所有不依赖std的检测，任意一项命中即返回true
+///
+/// # 示例
+///
+/// ```ignore
+/// if anti_debug::bare::is_debugged() {
+///     unsafe { core::arch::asm!("ud2") };
+/// }
+/// ```
+pub fn is_debugged() -> bool {
+    let detected = being_debugged() || nt_global_flag() || heap_flags();
+    #[cfg(target_arch = "x86_64")]
+    let detected = detected || debug_port() == Some(true) || hardware_breakpoints() == Some(true);
+    detected
+}
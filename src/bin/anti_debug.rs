@@ -0,0 +1,379 @@
+//! 命令行工具
+//!
+//! 用法:
+//!
+//! - `anti_debug snapshot [output.json]`
+//! - `anti_debug tui [interval_ms]`
+//! - `anti_debug protect [--dll <path>] [--interval <ms>] [--log-only] [--attacker suspend|terminate] -- <exe> [args]`
+//! - `anti_debug inject --pid <pid> --dll <path> [--method remote-thread|nt-create-thread-ex]`
+//!
+//! `snapshot`采集当前机器上的取证快照(PEB字段、堆标志、模块哈希、指向本进程的句柄、
+//! 各线程的调试寄存器、hook差异与检测报告)，输出JSON用于离线分析，不指定文件时写到标准输出
+//!
+//! `tui`通过`sink::install`订阅检测报告，实时显示每个技术的状态、执行与命中次数，以及滚动的证据日志，
+//! 后台监视模块发布的命中同样会出现在表格中；后台线程每隔`interval_ms`毫秒(默认2000)执行一次内置检测引擎产生报告，
+//! 适合通过RDP/SSH排查无法使用图形界面的机器，界面使用ratatui绘制，需要开启`tui` feature，按q退出
+//!
+//! `protect`以挂起状态启动第三方程序，按需注入保护DLL后恢复运行，随后在进程外检查目标的调试端口、
+//! 线程调试寄存器与外部进程句柄；目标被攻击时默认结束目标(`--log-only`只记录)，
+//! `--attacker`指定对持有目标可疑句柄的进程的处置，启动器的退出码与目标一致
+//!
+//! `inject`把保护DLL注入到已经运行的进程中，给无法重新编译的程序追加保护；
+//! 默认通过`CreateRemoteThread`调用`LoadLibraryW`，`nt-create-thread-ex`创建对调试器隐藏的加载线程
+
+#[cfg(windows)]
+use anti_debug::{
+    inject::{inject_process, InjectMethod},
+    launcher::{ProtectConfig, ProtectResponse, Protected},
+    response::ProcessAction,
+    snapshot::Snapshot,
+};
+#[cfg(windows)]
+use std::path::Path;
+#[cfg(any(windows, feature = "tui"))]
+use std::time::Duration;
+use std::{env, process};
+
+fn usage() -> ! {
+    eprintln!("usage: anti_debug snapshot [output.json]");
+    eprintln!("       anti_debug tui [interval_ms]");
+    eprintln!(
+        "       anti_debug protect [--dll <path>] [--interval <ms>] [--log-only] \
+         [--attacker suspend|terminate] -- <exe> [args]"
+    );
+    eprintln!(
+        "       anti_debug inject --pid <pid> --dll <path> \
+         [--method remote-thread|nt-create-thread-ex]"
+    );
+    process::exit(2);
+}
+
+#[cfg(windows)]
+fn snapshot(output: Option<&str>) {
+    let json = Snapshot::capture().to_json();
+    match output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, json) {
+                eprintln!("write {} failed: {:?}", path, e);
+                process::exit(1);
+            }
+            println!("snapshot written ==> {}", path);
+        }
+        None => println!("{}", json),
+    }
+}
+
+#[cfg(not(windows))]
+fn snapshot(_: Option<&str>) {
+    eprintln!("anti_debug snapshot only supports windows");
+    process::exit(2);
+}
+
+/// 解析`protect`的选项，返回配置、目标程序与参数
+#[cfg(windows)]
+fn protect_options<'a>(args: &[&'a str]) -> (ProtectConfig, &'a str, Vec<&'a str>) {
+    let Some(separator) = args.iter().position(|arg| *arg == "--") else {
+        usage();
+    };
+    let mut config = ProtectConfig::default();
+    let options = &args[..separator];
+    let mut index = 0;
+    while index < options.len() {
+        index += match (options[index], options.get(index + 1).copied()) {
+            ("--log-only", _) => {
+                config.response = ProtectResponse::Log;
+                1
+            }
+            ("--dll", Some(path)) => {
+                config.dll = Some(path.into());
+                2
+            }
+            ("--interval", Some(interval)) => match interval.parse::<u64>() {
+                Ok(interval) => {
+                    config.interval = Duration::from_millis(interval);
+                    2
+                }
+                Err(_) => usage(),
+            },
+            ("--attacker", Some(action)) => {
+                config.attackers.action = match action {
+                    "suspend" => Some(ProcessAction::Suspend),
+                    "terminate" => Some(ProcessAction::Terminate),
+                    _ => usage(),
+                };
+                2
+            }
+            _ => usage(),
+        };
+    }
+
+    match args[separator + 1..] {
+        [exe, ref args @ ..] => (config, exe, args.to_vec()),
+        [] => usage(),
+    }
+}
+
+#[cfg(windows)]
+fn protect(args: &[&str]) {
+    let (config, exe, args) = protect_options(args);
+    let target = match Protected::launch(exe, &args, config) {
+        Ok(target) => target,
+        Err(e) => {
+            eprintln!("launch {} failed: {:?}", exe, e);
+            process::exit(1);
+        }
+    };
+
+    println!("protecting {} ==> pid {}", exe, target.pid());
+    let exit_code = target.run(|reason, outcomes| {
+        eprintln!("target attacked ==> {}", reason);
+        for outcome in outcomes {
+            eprintln!(
+                "  attacker {:?} ({}): {}; action: {:?}; error: {:?}",
+                outcome.name, outcome.pid, outcome.reason, outcome.action, outcome.error
+            );
+        }
+    });
+    match exit_code {
+        Ok(exit_code) => process::exit(exit_code as i32),
+        Err(e) => {
+            eprintln!("wait for {} failed: {:?}", exe, e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn protect(_: &[&str]) {
+    eprintln!("anti_debug protect only supports windows");
+    process::exit(2);
+}
+
+#[cfg(windows)]
+fn inject(args: &[&str]) {
+    let (mut pid, mut dll, mut method) = (None, None, InjectMethod::RemoteThread);
+    for option in args.chunks(2) {
+        match option {
+            ["--pid", value] => pid = Some(value.parse::<u32>().unwrap_or_else(|_| usage())),
+            ["--dll", path] => dll = Some(Path::new(*path)),
+            ["--method", "remote-thread"] => method = InjectMethod::RemoteThread,
+            ["--method", "nt-create-thread-ex"] => method = InjectMethod::NtCreateThreadEx,
+            _ => usage(),
+        }
+    }
+    let (Some(pid), Some(dll)) = (pid, dll) else {
+        usage();
+    };
+
+    match inject_process(pid, dll, method) {
+        Ok(()) => println!("injected {} ==> pid {}", dll.display(), pid),
+        Err(e) => {
+            eprintln!("inject {} into {} failed: {:?}", dll.display(), pid, e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn inject(_: &[&str]) {
+    eprintln!("anti_debug inject only supports windows");
+    process::exit(2);
+}
+
+/// 终端界面，需要开启`tui` feature
+#[cfg(feature = "tui")]
+mod tui {
+    use anti_debug::{
+        dashboard::{Dashboard, DashboardSink, Status, TechniqueRow},
+        engine::{builtin_techniques, Engine},
+        sink, util,
+    };
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use ratatui::{
+        layout::{Constraint, Layout},
+        style::{Color, Modifier, Style},
+        text::Line,
+        widgets::{Block, Cell, List, ListItem, Row, Table},
+        Frame,
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// 终端界面的刷新间隔，同时也是等待按键的超时
+    const REFRESH: Duration = Duration::from_millis(500);
+
+    /// 终端界面中证据日志占用的行数(不含边框)
+    const LOG_LINES: u16 = 10;
+
+    /// 状态的颜色，命中为红色，失败为黄色，未命中为绿色，未执行为灰色
+    fn style(status: Status) -> Style {
+        match status {
+            Status::Detected => Style::new().fg(Color::Red).add_modifier(Modifier::BOLD),
+            Status::Failed => Style::new().fg(Color::Yellow),
+            Status::Clean => Style::new().fg(Color::Green),
+            Status::Pending => Style::new().fg(Color::DarkGray),
+        }
+    }
+
+    /// 绘制一帧：标题、技术表格(命中与失败的排在前面)和最近的证据日志
+    fn render(frame: &mut Frame, dashboard: &Dashboard) {
+        let [title, table, log] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(LOG_LINES + 2),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Line::styled(
+                format!(
+                    "anti_debug tui | report {} | {}/{} detected | uptime {}s | q to quit",
+                    dashboard.rounds,
+                    dashboard.detected(),
+                    dashboard.rows.len(),
+                    dashboard.uptime().as_secs()
+                ),
+                Style::new().add_modifier(Modifier::BOLD),
+            ),
+            title,
+        );
+
+        let mut rows: Vec<&TechniqueRow> = dashboard.rows.iter().collect();
+        rows.sort_by_key(|row| match row.status {
+            Status::Detected => 0,
+            Status::Failed => 1,
+            _ => 2,
+        });
+        let header = Row::new([
+            "technique",
+            "category",
+            "weight",
+            "status",
+            "runs",
+            "hits",
+            "evidence",
+        ])
+        .style(Style::new().add_modifier(Modifier::REVERSED));
+        let rows = rows.into_iter().map(|row| {
+            Row::new([
+                Cell::from(row.name),
+                Cell::from(row.category.name()),
+                Cell::from(row.weight.to_string()),
+                Cell::from(row.status.label()),
+                Cell::from(row.runs.to_string()),
+                Cell::from(row.hits.to_string()),
+                Cell::from(row.evidence.clone().unwrap_or_default()),
+            ])
+            .style(style(row.status))
+        });
+        let widths = [
+            Constraint::Length(28),
+            Constraint::Length(14),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Fill(1),
+        ];
+        frame.render_widget(Table::new(rows, widths).header(header), table);
+
+        let skip = dashboard.log.len().saturating_sub(LOG_LINES as usize);
+        let entries: Vec<ListItem> = dashboard
+            .log
+            .iter()
+            .skip(skip)
+            .map(|entry| ListItem::new(entry.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(entries).block(Block::bordered().title("evidence log")),
+            log,
+        );
+    }
+
+    /// 等待一次刷新间隔，按下q、Esc或者Ctrl+C时返回`true`
+    fn quit_requested() -> bool {
+        if !event::poll(REFRESH).unwrap_or(false) {
+            return false;
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                    || (key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL))
+            }
+            _ => false,
+        }
+    }
+
+    /// 订阅检测报告并在终端中刷新界面，直到按下q
+    pub fn run(interval: Duration) {
+        let dashboard = Arc::new(Mutex::new(Dashboard::new(&builtin_techniques())));
+        sink::install(Arc::new(DashboardSink(dashboard.clone())));
+
+        // 界面只消费输出目标收到的报告，后台线程周期性执行检测引擎产生报告
+        let quit = Arc::new(AtomicBool::new(false));
+        let stopped = quit.clone();
+        let scanner = util::spawn(move || {
+            let engine = Engine::default();
+            while !stopped.load(Ordering::SeqCst) {
+                engine.run();
+                let deadline = Instant::now() + interval;
+                while !stopped.load(Ordering::SeqCst) && Instant::now() < deadline {
+                    thread::sleep(REFRESH.min(deadline.saturating_duration_since(Instant::now())));
+                }
+            }
+        });
+
+        let mut terminal = ratatui::init();
+        loop {
+            let drawn = match dashboard.lock() {
+                Ok(dashboard) => terminal.draw(|frame| render(frame, &dashboard)).is_ok(),
+                Err(_) => false,
+            };
+            if !drawn || quit_requested() {
+                break;
+            }
+        }
+        ratatui::restore();
+
+        quit.store(true, Ordering::SeqCst);
+        let _ = scanner.join();
+        sink::uninstall_all();
+    }
+}
+
+/// 解析刷新间隔并启动终端界面
+#[cfg(feature = "tui")]
+fn tui(interval: Option<&str>) {
+    let interval = match interval.map(str::parse::<u64>) {
+        None => 2000,
+        Some(Ok(interval)) => interval,
+        Some(Err(_)) => usage(),
+    };
+    tui::run(Duration::from_millis(interval));
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui(_: Option<&str>) {
+    eprintln!("anti_debug tui requires the tui feature");
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        [_, "snapshot"] => snapshot(None),
+        [_, "snapshot", output] => snapshot(Some(output)),
+        [_, "tui"] => tui(None),
+        [_, "tui", interval] => tui(Some(interval)),
+        [_, "protect", ref rest @ ..] => protect(rest),
+        [_, "inject", ref rest @ ..] => inject(rest),
+        _ => usage(),
+    }
+}
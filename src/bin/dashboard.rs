@@ -0,0 +1,158 @@
+//! 检测面板
+//!
+//! 用法: `dashboard [interval_ms]`
+//!
+//! 在窗口中实时显示每个内置检测技术最近一次的结果、执行次数、命中次数、耗时与证据，
+//! 下方列出命中与失败的记录(最新的在最上面)，用于评估当前环境中哪些检测会被触发。
+//! 每轮执行所有技术后等待`interval_ms`毫秒(默认2000)，需要开启`gui` feature，界面使用egui绘制
+
+use anti_debug::{
+    dashboard::{Dashboard, DashboardMonitor, Status, TechniqueRow},
+    engine::builtin_techniques,
+};
+use eframe::egui::{self, Color32, RichText};
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// 表格的列名
+const COLUMNS: [&str; 8] = [
+    "technique",
+    "category",
+    "weight",
+    "status",
+    "runs",
+    "hits",
+    "time",
+    "evidence",
+];
+
+/// 界面的刷新间隔，检测在后台线程中执行，界面只读取面板数据
+const REFRESH: Duration = Duration::from_millis(500);
+
+/// 下方日志区域的高度
+const LOG_HEIGHT: f32 = 160.0;
+
+/// 状态的颜色，命中为红色，失败为黄色，未命中为绿色，未执行为灰色
+fn color(status: Status) -> Color32 {
+    match status {
+        Status::Detected => Color32::from_rgb(0xe0, 0x40, 0x40),
+        Status::Failed => Color32::from_rgb(0xd0, 0xa0, 0x20),
+        Status::Clean => Color32::from_rgb(0x40, 0xa0, 0x40),
+        Status::Pending => Color32::GRAY,
+    }
+}
+
+/// 一行中的各列
+fn cells(row: &TechniqueRow) -> [String; 8] {
+    [
+        row.name.to_string(),
+        row.category.name().to_string(),
+        row.weight.to_string(),
+        row.status.label().to_string(),
+        row.runs.to_string(),
+        row.hits.to_string(),
+        format!("{:.2}ms", row.elapsed.as_secs_f64() * 1000.0),
+        row.evidence.clone().unwrap_or_default(),
+    ]
+}
+
+struct DashboardApp {
+    dashboard: Arc<Mutex<Dashboard>>,
+}
+
+impl eframe::App for DashboardApp {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        let Ok(dashboard) = self.dashboard.lock().map(|dashboard| dashboard.clone()) else {
+            return;
+        };
+
+        egui::Panel::top("summary").show(ui, |ui| {
+            ui.heading(format!(
+                "round {}, {}/{} detected, uptime {}s",
+                dashboard.rounds,
+                dashboard.detected(),
+                dashboard.rows.len(),
+                dashboard.uptime().as_secs()
+            ));
+        });
+
+        egui::Panel::bottom("log")
+            .resizable(true)
+            .default_size(LOG_HEIGHT)
+            .show(ui, |ui| {
+                egui::ScrollArea::vertical()
+                    .id_salt("log")
+                    .auto_shrink(false)
+                    .show(ui, |ui| {
+                        for entry in dashboard.log.iter().rev() {
+                            ui.monospace(entry);
+                        }
+                    });
+            });
+
+        egui::CentralPanel::default_margins().show(ui, |ui| {
+            egui::ScrollArea::both()
+                .id_salt("techniques")
+                .auto_shrink(false)
+                .show(ui, |ui| {
+                    egui::Grid::new("techniques")
+                        .num_columns(COLUMNS.len())
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for title in COLUMNS {
+                                ui.strong(title);
+                            }
+                            ui.end_row();
+
+                            for row in dashboard.rows.iter() {
+                                for (column, text) in cells(row).into_iter().enumerate() {
+                                    match column {
+                                        3 => ui.label(RichText::new(text).color(color(row.status))),
+                                        _ => ui.label(text),
+                                    };
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+
+        ui.ctx().request_repaint_after(REFRESH);
+    }
+}
+
+fn main() {
+    let interval = env::args()
+        .nth(1)
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(2000);
+
+    let techniques = builtin_techniques();
+    let dashboard = Arc::new(Mutex::new(Dashboard::new(&techniques)));
+    let monitor = DashboardMonitor::start(
+        dashboard.clone(),
+        techniques,
+        Duration::from_millis(interval),
+    );
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("anti_debug dashboard")
+            .with_inner_size([1160.0, 720.0]),
+        ..Default::default()
+    };
+    let result = eframe::run_native(
+        "anti_debug dashboard",
+        options,
+        Box::new(|_| Ok(Box::new(DashboardApp { dashboard }))),
+    );
+
+    monitor.stop();
+    if let Err(e) = result {
+        eprintln!("dashboard failed: {:?}", e);
+        std::process::exit(1);
+    }
+}
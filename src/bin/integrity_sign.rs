@@ -0,0 +1,37 @@
+//! 构建完成后为可执行文件嵌入完整性HMAC
+//!
+//! 用法: `integrity_sign <exe> <key>`
+//!
+//! 签名之后不能再修改可执行节(包括再次链接或者打补丁)，否则需要重新签名
+
+#[cfg(any(windows, target_os = "linux"))]
+use anti_debug::integrity;
+#[cfg(any(windows, target_os = "linux"))]
+use std::env;
+use std::process;
+
+#[cfg(any(windows, target_os = "linux"))]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let [_, path, key] = &args[..] else {
+        eprintln!("usage: integrity_sign <exe> <key>");
+        process::exit(2);
+    };
+
+    match integrity::sign_file(path, key.as_bytes()) {
+        Ok(mac) => {
+            let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+            println!("{} signed ==> {}", path, hex);
+        }
+        Err(e) => {
+            eprintln!("sign {} failed: {:?}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn main() {
+    eprintln!("integrity_sign only supports windows and linux executables");
+    process::exit(2);
+}
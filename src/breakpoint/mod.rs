@@ -1,9 +1,46 @@
-use crate::util::BeingDebug;
-use anyhow::Result;
-use log::debug;
+use crate::logging::{debug, warn};
+use crate::{
+    engine::{Category, Severity, Verdict},
+    exception,
+    hook::get_module_path,
+    imports::{GetThreadContext, OpenProcess, SetThreadContext},
+    pe::{self, PeImage},
+    scan, sink, thread_monitor,
+    thread_monitor::thread_ids,
+    util::BeingDebug,
+    wow64::{ContextApi, ProcessArch},
+};
+use anyhow::{Error, Result};
+use std::{
+    arch::asm,
+    ffi::c_void,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+#[cfg(target_arch = "x86_64")]
+use windows::Win32::System::Diagnostics::Debug::CONTEXT_DEBUG_REGISTERS_AMD64 as CONTEXT_DEBUG_REGISTERS;
+#[cfg(target_arch = "x86")]
+use windows::Win32::System::Diagnostics::Debug::CONTEXT_DEBUG_REGISTERS_X86 as CONTEXT_DEBUG_REGISTERS;
+#[cfg(target_arch = "x86_64")]
+use windows::Win32::System::Diagnostics::Debug::{
+    Wow64GetThreadContext, WOW64_CONTEXT, WOW64_CONTEXT_DEBUG_REGISTERS,
+};
 use windows::Win32::{
-    Foundation::HANDLE,
-    System::Diagnostics::Debug::{GetThreadContext, SetThreadContext, CONTEXT},
+    Foundation::{CloseHandle, EXCEPTION_BREAKPOINT, HANDLE, HMODULE},
+    System::{
+        Diagnostics::Debug::{CONTEXT, IMAGE_SCN_MEM_EXECUTE},
+        Threading::{
+            CreateThread, GetCurrentThread, GetCurrentThreadId, OpenThread, ResumeThread,
+            TerminateThread, WaitForSingleObject, CREATE_SUSPENDED, INFINITE,
+            PROCESS_QUERY_LIMITED_INFORMATION, THREAD_CREATION_FLAGS, THREAD_GET_CONTEXT,
+            THREAD_QUERY_LIMITED_INFORMATION,
+        },
+    },
 };
 
 impl BeingDebug for CONTEXT {
@@ -16,6 +53,9 @@ impl BeingDebug for CONTEXT {
     }
 }
 
+/// `clean_hardware_breakpoint`成功的次数，`DebugRegisterGuard`据此区分自己清除与外部修改
+static CLEANED: AtomicUsize = AtomicUsize::new(0);
+
 pub struct HardwareBreakPoint {}
 
 impl HardwareBreakPoint {
@@ -42,7 +82,7 @@ impl HardwareBreakPoint {
     /// ```
     pub fn is_hardware_breakpoint_set(thread_hanle: HANDLE) -> Result<bool> {
         let mut context: CONTEXT = CONTEXT::default();
-        unsafe { GetThreadContext(thread_hanle, &mut context) }?;
+        unsafe { GetThreadContext(thread_hanle, &mut context) }.ok()?;
 
         debug!(
             "Thread Context ==> Dr0: {}; Dr1: {}; Dr2: {}; Dr3: {}",
@@ -64,15 +104,596 @@ impl HardwareBreakPoint {
     /// - `Ok(())`: 清空硬件断点成功
     pub fn clean_hardware_breakpoint(thread_hanle: HANDLE) -> Result<()> {
         let mut context: CONTEXT = CONTEXT::default();
-        unsafe { GetThreadContext(thread_hanle, &mut context) }?;
+        unsafe { GetThreadContext(thread_hanle, &mut context) }.ok()?;
 
         context.Dr0 = 0;
         context.Dr1 = 0;
         context.Dr2 = 0;
         context.Dr3 = 0;
 
-        unsafe { SetThreadContext(thread_hanle, &mut context) }?;
+        unsafe { SetThreadContext(thread_hanle, &context) }.ok()?;
+        CLEANED.fetch_add(1, Ordering::SeqCst);
 
         Ok(())
     }
 }
+
+/// DR7中DR0-DR3的局部与全局启用位(L0-G3)
+const DR7_ENABLE_MASK: usize = 0xFF;
+
+/// 线程函数与保存返回值的位置
+type ThreadPayload<F, T> = (F, Arc<Mutex<Option<T>>>);
+
+/// 新线程的入口，登记到`thread_monitor`，再次清除调试寄存器后执行闭包并保存返回值
+///
+/// 挂起期间清除之后、恢复运行之前调试器仍然可以重新写入调试寄存器，
+/// 因此在执行闭包前在线程内部再清除一次；清除失败或者闭包panic时不保存返回值，`join`返回错误
+unsafe extern "system" fn clean_thread_start<F, T>(parameter: *mut c_void) -> u32
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (f, result) = *Box::from_raw(parameter as *mut ThreadPayload<F, T>);
+    thread_monitor::run_registered(false, || {
+        if let Err(e) = clean_context(GetCurrentThread()) {
+            warn!("clean thread context failed before start; error: {:?}", e);
+            return 1;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => {
+                if let Ok(mut result) = result.lock() {
+                    *result = Some(value);
+                }
+                0
+            }
+            Err(_) => 1,
+        }
+    })
+}
+
+/// 通过`spawn_with_clean_context`创建的线程
+///
+/// drop时只关闭线程句柄，线程继续运行
+pub struct CleanThread<T> {
+    hthread: HANDLE,
+    thread_id: u32,
+    preset: bool,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> CleanThread<T> {
+    /// 线程ID
+    pub fn thread_id(&self) -> u32 {
+        self.thread_id
+    }
+
+    /// 线程恢复运行前是否已经被预先设置了硬件断点(不包括线程内部再次清除时发现的)
+    pub fn had_preset_breakpoints(&self) -> bool {
+        self.preset
+    }
+
+    /// 等待线程结束并取得返回值
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 线程函数panic，或者执行前无法清除调试寄存器
+    /// - `Ok(value)`: 线程函数的返回值
+    pub fn join(self) -> Result<T> {
+        unsafe { WaitForSingleObject(self.hthread, INFINITE) };
+        let value = self.result.lock().ok().and_then(|mut result| result.take());
+        value.ok_or_else(|| Error::msg("clean thread panicked or was not cleaned"))
+    }
+}
+
+impl<T> Drop for CleanThread<T> {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.hthread) };
+    }
+}
+
+/// 以挂起状态创建线程，检查并清除其初始Context中的硬件断点后再恢复运行
+///
+/// 部分调试器与插件会在新线程创建时预先写入DR0-DR3/DR7，使得清除过的硬件断点在新线程中重新生效；
+/// 在线程执行第一条指令之前清除可以堵住这一途径。线程开始执行后、调用`f`之前会在线程内部再清除一次，
+/// 线程登记到`thread_monitor`，不会被`ThreadMonitor`报告为外部线程
+///
+/// # 参数
+///
+/// - `f`: 线程函数
+///
+/// # 返回值
+///
+/// - `Err`: 创建线程、读写线程Context或者恢复线程失败，此时线程没有运行过，已经被结束
+/// - `Ok(thread)`: 已经恢复运行的线程
+///
+/// # 示例
+///
+/// ```ignore
+/// let worker = spawn_with_clean_context(|| do_work())?;
+/// if worker.had_preset_breakpoints() {
+///     println!("debug registers were seeded in the new thread");
+/// }
+/// let value = worker.join()?;
+/// ```
+pub fn spawn_with_clean_context<F, T>(f: F) -> Result<CleanThread<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result: Arc<Mutex<Option<T>>> = Arc::new(Mutex::new(None));
+    let parameter = Box::into_raw(Box::new((f, result.clone()))) as *mut c_void;
+    let mut thread_id: u32 = 0;
+    thread_monitor::expect_spawn();
+    let hthread = match unsafe {
+        CreateThread(
+            None,
+            0,
+            Some(clean_thread_start::<F, T>),
+            Some(parameter),
+            THREAD_CREATION_FLAGS(CREATE_SUSPENDED.0),
+            Some(&mut thread_id),
+        )
+    } {
+        Ok(hthread) => hthread,
+        Err(e) => {
+            thread_monitor::cancel_spawn();
+            drop(unsafe { Box::from_raw(parameter as *mut ThreadPayload<F, T>) });
+            return Err(e.into());
+        }
+    };
+
+    // 线程还没有执行过，直接结束并回收线程参数
+    let abort = |error: Error| unsafe {
+        let _ = TerminateThread(hthread, 1);
+        WaitForSingleObject(hthread, INFINITE);
+        let _ = CloseHandle(hthread);
+        drop(Box::from_raw(parameter as *mut ThreadPayload<F, T>));
+        thread_monitor::cancel_spawn();
+        Err(error)
+    };
+    let preset = match clean_context(hthread) {
+        Ok(preset) => preset,
+        Err(e) => return abort(e),
+    };
+    if unsafe { ResumeThread(hthread) } == u32::MAX {
+        return abort(windows::core::Error::from_win32().into());
+    }
+
+    Ok(CleanThread {
+        hthread,
+        thread_id,
+        preset,
+        result,
+    })
+}
+
+/// 检查并清除挂起线程或者当前线程的硬件断点
+///
+/// # 参数
+///
+/// - `hthread`: 挂起的线程或者当前线程的句柄，需要`THREAD_GET_CONTEXT`与`THREAD_SET_CONTEXT`权限
+///
+/// # 返回值
+///
+/// - `Err`: GetThreadContext/SetThreadContext失败
+/// - `Ok(true)`: 清除前设置了硬件断点
+/// - `Ok(false)`: 没有设置硬件断点
+pub fn clean_context(hthread: HANDLE) -> Result<bool> {
+    let mut context = CONTEXT {
+        ContextFlags: CONTEXT_DEBUG_REGISTERS,
+        ..Default::default()
+    };
+    unsafe { GetThreadContext(hthread, &mut context) }.ok()?;
+
+    let dr = [context.Dr0, context.Dr1, context.Dr2, context.Dr3].map(|dr| dr as usize);
+    let dr7 = context.Dr7 as usize;
+    debug!(
+        "suspended thread context ==> dr: {:x?}; dr7: {:#x}",
+        dr, dr7
+    );
+    let preset = dr.iter().any(|dr| *dr != 0) || dr7 & DR7_ENABLE_MASK != 0;
+    if !preset {
+        return Ok(false);
+    }
+
+    warn!(
+        "debug registers preset in new thread ==> dr: {:x?}; dr7: {:#x}",
+        dr, dr7
+    );
+    context.Dr0 = 0;
+    context.Dr1 = 0;
+    context.Dr2 = 0;
+    context.Dr3 = 0;
+    context.Dr7 = 0;
+    unsafe { SetThreadContext(hthread, &context) }.ok()?;
+
+    Ok(true)
+}
+
+/// 调试寄存器的值
+///
+/// - `dr`: DR0-DR3
+/// - `dr7`: DR7
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DebugRegisters {
+    pub dr: [usize; 4],
+    pub dr7: usize,
+}
+
+impl DebugRegisters {
+    /// 通过GetThreadContext读取指定线程的调试寄存器
+    pub fn from_thread(hthread: HANDLE) -> Result<Self> {
+        let mut context = CONTEXT {
+            ContextFlags: CONTEXT_DEBUG_REGISTERS,
+            ..Default::default()
+        };
+        unsafe { GetThreadContext(hthread, &mut context) }.ok()?;
+
+        Ok(Self {
+            dr: [context.Dr0, context.Dr1, context.Dr2, context.Dr3].map(|dr| dr as usize),
+            dr7: context.Dr7 as usize,
+        })
+    }
+
+    /// 通过Wow64GetThreadContext读取WOW64线程的32位调试寄存器(仅64位)
+    ///
+    /// 64位进程对WOW64线程调用GetThreadContext得到的是64位的Context，
+    /// 32位代码使用的调试寄存器需要通过Wow64GetThreadContext读取
+    #[cfg(target_arch = "x86_64")]
+    pub fn from_wow64_thread(hthread: HANDLE) -> Result<Self> {
+        let mut context = WOW64_CONTEXT {
+            ContextFlags: WOW64_CONTEXT_DEBUG_REGISTERS,
+            ..Default::default()
+        };
+        unsafe { Wow64GetThreadContext(hthread, &mut context) }?;
+
+        Ok(Self {
+            dr: [context.Dr0, context.Dr1, context.Dr2, context.Dr3].map(|dr| dr as usize),
+            dr7: context.Dr7 as usize,
+        })
+    }
+
+    /// 读取其他进程中线程的调试寄存器，按目标架构选择GetThreadContext或者Wow64GetThreadContext
+    ///
+    /// # 参数
+    ///
+    /// - `hthread`: 带有`THREAD_GET_CONTEXT`权限的线程句柄
+    /// - `arch`: 线程所属进程的架构
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 读取失败，或者32位进程读取64位线程
+    /// - `Ok(registers)`: 调试寄存器
+    pub fn from_target_thread(hthread: HANDLE, arch: ProcessArch) -> Result<Self> {
+        match arch.context_api() {
+            #[cfg(target_arch = "x86_64")]
+            ContextApi::Wow64 => Self::from_wow64_thread(hthread),
+            ContextApi::Unsupported => Err(Error::msg(
+                "cannot read 64-bit thread context from a 32-bit process",
+            )),
+            _ => Self::from_thread(hthread),
+        }
+    }
+
+    /// 是否设置了硬件断点
+    pub fn is_set(&self) -> bool {
+        self.dr.iter().any(|dr| *dr != 0) || self.dr7 & DR7_ENABLE_MASK != 0
+    }
+
+    /// 在VEH中读取当前线程的调试寄存器：触发一个断点，取异常Context中的值
+    ///
+    /// 不经过GetThreadContext，hook了GetThreadContext来隐藏硬件断点的工具看不到这条路径
+    pub fn from_exception() -> Result<Self> {
+        let caught = exception::guarded(&[EXCEPTION_BREAKPOINT], 1, || unsafe { asm!("int3") })?
+            .ok_or_else(|| Error::msg("breakpoint not delivered"))?;
+
+        Ok(Self {
+            dr: caught.dr,
+            dr7: caught.dr7,
+        })
+    }
+
+    /// 地址相同且DR7的启用位相同，DR7的其他位在不同读取路径中可能不一致
+    pub fn same_breakpoints(&self, other: &Self) -> bool {
+        self.dr == other.dr && self.dr7 & DR7_ENABLE_MASK == other.dr7 & DR7_ENABLE_MASK
+    }
+}
+
+/// 读取指定进程中所有线程的调试寄存器
+///
+/// 按目标进程的架构选择GetThreadContext或者Wow64GetThreadContext，
+/// 64位的监视进程也可以正确读取32位目标的调试寄存器
+///
+/// # 参数
+///
+/// - `pid`: 目标进程ID
+///
+/// # 返回值
+///
+/// - `Err`: 打开进程或者枚举线程失败
+/// - `Ok(threads)`: 线程ID与调试寄存器，无法打开或者读取的线程被跳过
+///
+/// # 示例
+///
+/// ```ignore
+/// for (tid, registers) in process_debug_registers(pid)? {
+///     if registers.is_set() {
+///         println!("thread {} has hardware breakpoints: {:x?}", tid, registers);
+///     }
+/// }
+/// ```
+pub fn process_debug_registers(pid: u32) -> Result<Vec<(u32, DebugRegisters)>> {
+    let hprocess = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }?;
+    let arch = ProcessArch::of(hprocess);
+    let _ = unsafe { CloseHandle(hprocess) };
+    let arch = arch?;
+
+    let mut threads: Vec<(u32, DebugRegisters)> = Vec::new();
+    for tid in thread_ids(pid)? {
+        let hthread = match unsafe {
+            OpenThread(
+                THREAD_GET_CONTEXT | THREAD_QUERY_LIMITED_INFORMATION,
+                false,
+                tid,
+            )
+        } {
+            Ok(hthread) => hthread,
+            Err(e) => {
+                debug!("open thread {} failed; error: {:?}", tid, e);
+                continue;
+            }
+        };
+        match DebugRegisters::from_target_thread(hthread, arch) {
+            Ok(registers) => threads.push((tid, registers)),
+            Err(e) => debug!("read thread {} context failed; error: {:?}", tid, e),
+        }
+        let _ = unsafe { CloseHandle(hthread) };
+    }
+
+    debug!(
+        "process debug registers ==> pid: {}; arch: {:?}; {:x?}",
+        pid, arch, threads
+    );
+    Ok(threads)
+}
+
+/// 监视一个线程的调试寄存器，发现不是由`clean_hardware_breakpoint`造成的变化
+///
+/// 创建时记录线程当前的调试寄存器作为基线，之后通过GetThreadContext与VEH两条路径读取并对比。
+/// 调试器通过SetThreadContext或者NtContinue写入调试寄存器时值会变化；
+/// `clean_hardware_breakpoint`清除之后基线更新为清除后的值，不报告
+///
+/// # 示例
+///
+/// ```ignore
+/// // 在主线程中创建
+/// let mut guard = DebugRegisterGuard::new()?;
+/// loop {
+///     if let Some(evidence) = guard.check()? {
+///         println!("debug registers tampered: {}", evidence);
+///     }
+/// }
+/// ```
+pub struct DebugRegisterGuard {
+    hthread: HANDLE,
+    thread_id: u32,
+    baseline: DebugRegisters,
+    cleaned: usize,
+}
+
+unsafe impl Send for DebugRegisterGuard {}
+
+impl DebugRegisterGuard {
+    /// 记录当前线程的调试寄存器作为基线
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 打开线程或者读取Context失败
+    /// - `Ok(guard)`: 监视当前线程的guard
+    pub fn new() -> Result<Self> {
+        let thread_id = unsafe { GetCurrentThreadId() };
+        let hthread = unsafe {
+            OpenThread(
+                THREAD_GET_CONTEXT | THREAD_QUERY_LIMITED_INFORMATION,
+                false,
+                thread_id,
+            )
+        }?;
+        let cleaned = CLEANED.load(Ordering::SeqCst);
+        match DebugRegisters::from_thread(hthread) {
+            Ok(baseline) => Ok(Self {
+                hthread,
+                thread_id,
+                baseline,
+                cleaned,
+            }),
+            Err(e) => {
+                let _ = unsafe { CloseHandle(hthread) };
+                Err(e)
+            }
+        }
+    }
+
+    /// 基线
+    pub fn baseline(&self) -> DebugRegisters {
+        self.baseline
+    }
+
+    /// 对比读取到的值与基线；`clean_hardware_breakpoint`执行过时基线重置为全零后继续对比，
+    /// 清除之后立即写入的断点同样会被发现
+    fn compare(&mut self, route: &str, current: DebugRegisters) -> Option<String> {
+        let cleaned = CLEANED.load(Ordering::SeqCst);
+        if cleaned != self.cleaned {
+            debug!("debug registers cleaned ==> baseline reset to zero");
+            self.cleaned = cleaned;
+            self.baseline = DebugRegisters::default();
+        }
+        if current.same_breakpoints(&self.baseline) {
+            return None;
+        }
+
+        debug!(
+            "debug registers changed via {} ==> {:x?}; baseline: {:x?}",
+            route, current, self.baseline
+        );
+        Some(format!(
+            "{}: dr {:x?} dr7 {:#x}, baseline dr {:x?} dr7 {:#x}",
+            route, current.dr, current.dr7, self.baseline.dr, self.baseline.dr7
+        ))
+    }
+
+    /// 通过GetThreadContext检查，可以在任意线程中调用
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 读取Context失败
+    /// - `Ok(None)`: 与基线一致
+    /// - `Ok(Some(evidence))`: 调试寄存器被修改
+    pub fn check_context(&mut self) -> Result<Option<String>> {
+        let current = DebugRegisters::from_thread(self.hthread)?;
+        Ok(self.compare("GetThreadContext", current))
+    }
+
+    /// 通过VEH检查，只能在被监视的线程中调用
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 不在被监视的线程中，或者断点没有送达
+    /// - `Ok(None)`: 与基线一致
+    /// - `Ok(Some(evidence))`: 调试寄存器被修改
+    pub fn check_exception(&mut self) -> Result<Option<String>> {
+        if unsafe { GetCurrentThreadId() } != self.thread_id {
+            return Err(Error::msg("check_exception called from another thread"));
+        }
+        let current = DebugRegisters::from_exception()?;
+        Ok(self.compare("VEH", current))
+    }
+
+    /// 依次通过GetThreadContext与VEH检查，只能在被监视的线程中调用
+    ///
+    /// 两条路径的结果分别与基线对比，GetThreadContext被hook时只有VEH路径能发现修改
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 读取Context失败，或者不在被监视的线程中
+    /// - `Ok(None)`: 与基线一致
+    /// - `Ok(Some(evidence))`: 调试寄存器被修改
+    pub fn check(&mut self) -> Result<Option<String>> {
+        let context = self.check_context()?;
+        let exception = self.check_exception()?;
+        let evidence: Vec<String> = context.into_iter().chain(exception).collect();
+        Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+    }
+
+    /// 启动后台线程，定期通过GetThreadContext检查被监视的线程
+    ///
+    /// VEH路径只能在被监视的线程中执行，需要在该线程中另外定期调用`check_exception`
+    ///
+    /// # 参数
+    ///
+    /// - `interval`: 检查间隔
+    /// - `on_tamper`: 发现修改时的回调，调用前修改已经通过`sink::publish`交给全局输出目标
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// DebugRegisterGuard::new()?.spawn_monitor(Duration::from_secs(1), |evidence| {
+    ///     println!("debug registers tampered: {}", evidence);
+    /// });
+    /// ```
+    pub fn spawn_monitor<F>(mut self, interval: Duration, on_tamper: F) -> JoinHandle<()>
+    where
+        F: Fn(String) + Send + 'static,
+    {
+        thread_monitor::spawn_hidden(move || loop {
+            match self.check_context() {
+                Ok(Some(evidence)) => {
+                    sink::publish(Verdict::observed(
+                        "debug_register_guard",
+                        Category::Debugger,
+                        Severity::Confirmed,
+                        evidence.clone(),
+                    ));
+                    on_tamper(evidence)
+                }
+                Ok(None) => {}
+                Err(e) => warn!("debug register guard failed; error: {:?}", e),
+            }
+            thread::sleep(interval);
+        })
+    }
+}
+
+impl Drop for DebugRegisterGuard {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.hthread) };
+    }
+}
+
+/// 软件断点指令int3
+pub const INT3: u8 = 0xCC;
+
+pub struct SoftwareBreakPoint {}
+
+impl SoftwareBreakPoint {
+    /// 查找调试器在指定模块代码段中写入的软件断点
+    ///
+    /// 编译器会用int3填充函数之间的空隙，直接搜索0xCC会有大量误报，
+    /// 因此先用向量化比较找出内存与磁盘(按加载基址重定位后)不一致的字节，只保留内存中为0xCC的位置
+    ///
+    /// # 参数
+    ///
+    /// - `hmodule`: 模块句柄
+    /// - `limit`: 最多返回的数量，找到后立即停止扫描
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 读取磁盘文件失败
+    /// - `Ok(addresses)`: 软件断点的地址，为空则未发现
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// let hmodule = unsafe { GetModuleHandleW(None) }.unwrap();
+    /// for address in SoftwareBreakPoint::find_in_module(hmodule, 16).unwrap() {
+    ///     println!("int3 ==> {:#x}", address);
+    /// }
+    /// ```
+    pub fn find_in_module(hmodule: HMODULE, limit: usize) -> Result<Vec<usize>> {
+        let memory = PeImage::from_module(hmodule)?;
+        let buffer = pe::map_from_disk(get_module_path(hmodule)?, memory.base())?;
+        let disk = PeImage::from_buffer(&buffer)?;
+
+        let mut breakpoints: Vec<usize> = Vec::new();
+        for section in memory.sections() {
+            if section.Characteristics.0 & IMAGE_SCN_MEM_EXECUTE.0 == 0 {
+                continue;
+            }
+
+            let rva = section.VirtualAddress as usize;
+            let size = unsafe { section.Misc.VirtualSize } as usize;
+            let (Some(memory_bytes), Some(disk_bytes)) =
+                (memory.bytes(rva, size), disk.bytes(rva, size))
+            else {
+                continue;
+            };
+
+            // 被修改的字节很少，差异扫描的结果再按0xCC过滤
+            for offset in scan::find_differences(memory_bytes, disk_bytes, usize::MAX) {
+                if memory_bytes[offset] == INT3 {
+                    breakpoints.push(memory.base() + rva + offset);
+                    if breakpoints.len() >= limit {
+                        break;
+                    }
+                }
+            }
+            if breakpoints.len() >= limit {
+                break;
+            }
+        }
+
+        debug!("software breakpoints ==> {:#x?}", breakpoints);
+
+        Ok(breakpoints)
+    }
+}
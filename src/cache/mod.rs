@@ -0,0 +1,263 @@
+use crate::logging::debug;
+use crate::util::{self, query_system_information_into};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    mem::size_of,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use windows::Wdk::System::SystemInformation::SYSTEM_INFORMATION_CLASS;
+
+/// 缓存条目
+///
+/// - `ttl`: 缓存有效期，为0时不缓存
+/// - `pinned`: 进行中的`Sweep`数量，大于0时快照不会过期
+/// - `snapshot`: 最近一次查询的结果
+#[derive(Default)]
+struct CacheEntry {
+    ttl: Duration,
+    pinned: usize,
+    snapshot: Option<Snapshot>,
+}
+
+impl CacheEntry {
+    /// 是否缓存该类别的查询结果
+    fn enabled(&self) -> bool {
+        !self.ttl.is_zero() || self.pinned > 0
+    }
+}
+
+/// 以信息类别为键的缓存
+static CACHE: Mutex<Option<HashMap<i32, CacheEntry>>> = Mutex::new(None);
+
+/// 一次NtQuerySystemInformation查询结果的快照，可以在线程间共享
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    buffer: Arc<Vec<u64>>,
+    length: usize,
+    captured: Instant,
+}
+
+impl Snapshot {
+    /// 查询系统信息并生成快照
+    ///
+    /// # 参数
+    ///
+    /// - `class`: 信息类别
+    pub fn capture(class: SYSTEM_INFORMATION_CLASS) -> Result<Self> {
+        let mut buffer: Vec<u64> = Vec::new();
+        let length = query_system_information_into(class, &mut buffer)?;
+        buffer.truncate(length.div_ceil(size_of::<u64>()));
+
+        Ok(Self {
+            buffer: Arc::new(buffer),
+            length,
+            captured: Instant::now(),
+        })
+    }
+
+    /// 查询结果，按8字节对齐
+    pub fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.buffer.as_ptr() as *const u8, self.length) }
+    }
+
+    /// 快照生成的时间
+    pub fn captured(&self) -> Instant {
+        self.captured
+    }
+
+    /// 快照生成至今的时间
+    pub fn age(&self) -> Duration {
+        self.captured.elapsed()
+    }
+}
+
+/// 设置指定信息类别的缓存有效期
+///
+/// 默认不缓存。句柄表、进程列表的查询开销较大，频繁检测时可以设置几百毫秒的有效期，
+/// 诱饵线程、句柄审计与进程黑名单扫描会共用同一份缓存
+///
+/// # 参数
+///
+/// - `class`: 信息类别，例如`SYSTEM_EXTENDED_HANDLE_INFORMATION`
+/// - `ttl`: 有效期，为0时关闭该类别的缓存并丢弃已有结果
+///
+/// # 示例
+///
+/// ```ignore
+/// cache::set_ttl(SYSTEM_EXTENDED_HANDLE_INFORMATION, Duration::from_millis(500));
+/// cache::set_ttl(SystemProcessInformation, Duration::from_secs(2));
+/// ```
+pub fn set_ttl(class: SYSTEM_INFORMATION_CLASS, ttl: Duration) {
+    let mut guard = CACHE.lock().unwrap();
+    let entry = guard
+        .get_or_insert_with(HashMap::new)
+        .entry(class.0)
+        .or_default();
+    entry.ttl = ttl;
+    if !entry.enabled() {
+        entry.snapshot = None;
+    }
+}
+
+/// 指定信息类别的缓存有效期，没有设置过时为0
+pub fn ttl(class: SYSTEM_INFORMATION_CLASS) -> Duration {
+    CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|cache| cache.get(&class.0))
+        .map_or(Duration::ZERO, |entry| entry.ttl)
+}
+
+/// 丢弃指定信息类别的缓存，下次访问时重新查询
+pub fn invalidate(class: SYSTEM_INFORMATION_CLASS) {
+    if let Some(entry) = CACHE
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|cache| cache.get_mut(&class.0))
+    {
+        entry.snapshot = None;
+    }
+}
+
+/// 丢弃所有缓存
+pub fn invalidate_all() {
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        for entry in cache.values_mut() {
+            entry.snapshot = None;
+        }
+    }
+}
+
+/// 获取指定信息类别的快照，缓存有效时直接返回缓存
+///
+/// # 返回值
+///
+/// - `Err`: NtQuerySystemInformation调用失败
+/// - `Ok((snapshot, cached))`: 快照，以及是否来自缓存
+fn snapshot(class: SYSTEM_INFORMATION_CLASS) -> Result<(Snapshot, bool)> {
+    let mut guard = CACHE.lock().unwrap();
+    let entry = guard
+        .get_or_insert_with(HashMap::new)
+        .entry(class.0)
+        .or_default();
+    if let Some(snapshot) = entry.snapshot.as_ref() {
+        if entry.pinned > 0 || snapshot.age() < entry.ttl {
+            return Ok((snapshot.clone(), true));
+        }
+    }
+
+    // 持有锁查询，多个线程同时过期时只查询一次
+    let snapshot = Snapshot::capture(class)?;
+    debug!(
+        "system information cached ==> class: {}; size: {:#x}",
+        class.0, snapshot.length
+    );
+    entry.snapshot = Some(snapshot.clone());
+    Ok((snapshot, false))
+}
+
+/// 使用缓存的系统信息执行`f`
+///
+/// 没有为该类别设置有效期、也不在`Sweep`期间时等同于`util::with_system_information`。
+/// 结果来自缓存且`f`返回错误时(例如新打开的句柄还不在缓存的句柄表中)，刷新缓存后重试一次
+///
+/// # 参数
+///
+/// - `class`: 信息类别
+/// - `f`: 处理查询结果的函数
+///
+/// # 返回值
+///
+/// - `Err`: NtQuerySystemInformation调用失败或者`f`返回错误
+/// - `Ok(result)`: `f`的返回值
+pub fn with_system_information<R, F: FnMut(&[u8]) -> Result<R>>(
+    class: SYSTEM_INFORMATION_CLASS,
+    mut f: F,
+) -> Result<R> {
+    let enabled = CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|cache| cache.get(&class.0))
+        .is_some_and(CacheEntry::enabled);
+    if !enabled {
+        return util::with_system_information(class, f)?;
+    }
+
+    let (snapshot, cached) = snapshot(class)?;
+    match f(snapshot.data()) {
+        Err(e) if cached => {
+            debug!("cached system information is stale; error: {:?}", e);
+            invalidate(class);
+            let (snapshot, _) = self::snapshot(class)?;
+            f(snapshot.data())
+        }
+        result => result,
+    }
+}
+
+/// 一次检测(sweep)期间共用的系统信息快照
+///
+/// 存在期间指定类别的快照不会过期：第一次使用时查询一次，之后所有检测技术
+/// (诱饵线程、句柄审计、进程黑名单等)共用同一份结果，drop后恢复按有效期缓存
+pub struct Sweep {
+    classes: Vec<SYSTEM_INFORMATION_CLASS>,
+}
+
+impl Drop for Sweep {
+    fn drop(&mut self) {
+        let mut guard = CACHE.lock().unwrap();
+        let Some(cache) = guard.as_mut() else {
+            return;
+        };
+        for class in self.classes.iter() {
+            if let Some(entry) = cache.get_mut(&class.0) {
+                entry.pinned = entry.pinned.saturating_sub(1);
+                if !entry.enabled() {
+                    entry.snapshot = None;
+                }
+            }
+        }
+    }
+}
+
+/// 开始一次共用快照的检测
+///
+/// 快照在第一次使用时才查询，没有检测技术需要的类别不会产生额外的调用。
+/// 同时进行的多个`Sweep`共用同一份快照，已经超过有效期的旧快照在开始时丢弃
+///
+/// # 参数
+///
+/// - `classes`: 共用的信息类别
+///
+/// # 示例
+///
+/// ```ignore
+/// let _sweep = cache::sweep(&[SystemProcessInformation, SYSTEM_EXTENDED_HANDLE_INFORMATION]);
+/// let blacklisted = environment::find_blacklisted_processes()?;
+/// let honey = HoneyThread::default().check()?;
+/// ```
+pub fn sweep(classes: &[SYSTEM_INFORMATION_CLASS]) -> Sweep {
+    let mut guard = CACHE.lock().unwrap();
+    let cache = guard.get_or_insert_with(HashMap::new);
+    for class in classes.iter() {
+        let entry = cache.entry(class.0).or_default();
+        if entry.pinned == 0
+            && entry
+                .snapshot
+                .as_ref()
+                .is_some_and(|snapshot| snapshot.age() >= entry.ttl)
+        {
+            entry.snapshot = None;
+        }
+        entry.pinned += 1;
+    }
+
+    Sweep {
+        classes: classes.to_vec(),
+    }
+}
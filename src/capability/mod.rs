@@ -0,0 +1,255 @@
+use crate::logging::debug;
+use crate::{
+    imports::NtQuerySystemInformation,
+    nt_query,
+    peb::WinPeb,
+    thread::SYSTEM_EXTENDED_HANDLE_INFORMATION,
+    wow64::{self, ProcessArch},
+};
+use std::{ffi::c_void, ptr, sync::OnceLock};
+use windows::{
+    Wdk::System::{
+        SystemInformation::{SystemProcessInformation, SYSTEM_INFORMATION_CLASS},
+        Threading::{
+            ProcessBasicInformation, ProcessDebugFlags, ProcessDebugObjectHandle, ProcessDebugPort,
+            PROCESSINFOCLASS,
+        },
+    },
+    Win32::{
+        Foundation::{NTSTATUS, STATUS_INVALID_INFO_CLASS, STATUS_NOT_IMPLEMENTED},
+        System::Threading::GetCurrentProcess,
+    },
+};
+
+/// SystemKernelDebuggerInformation信息类别
+pub const SYSTEM_KERNEL_DEBUGGER_INFORMATION: SYSTEM_INFORMATION_CLASS =
+    SYSTEM_INFORMATION_CLASS(35);
+
+/// 初始化时探测的进程信息类别
+const PROCESS_CLASSES: [PROCESSINFOCLASS; 4] = [
+    ProcessBasicInformation,
+    ProcessDebugPort,
+    ProcessDebugObjectHandle,
+    ProcessDebugFlags,
+];
+
+/// 初始化时探测的系统信息类别
+const SYSTEM_CLASSES: [SYSTEM_INFORMATION_CLASS; 3] = [
+    SystemProcessInformation,
+    SYSTEM_EXTENDED_HANDLE_INFORMATION,
+    SYSTEM_KERNEL_DEBUGGER_INFORMATION,
+];
+
+static CAPABILITIES: OnceLock<Capabilities> = OnceLock::new();
+
+/// 操作系统版本，读取自PEB，不受兼容性清单影响
+///
+/// - `major`: 主版本号
+/// - `minor`: 次版本号
+/// - `build`: 构建号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OsVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+}
+
+/// PEB中检测使用的字段偏移
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PebOffsets {
+    pub being_debugged: usize,
+    pub process_heap: usize,
+    pub nt_global_flag: usize,
+}
+
+impl PebOffsets {
+    /// 32位PEB(包括WOW64进程中的32位PEB)
+    pub const PEB32: Self = Self {
+        being_debugged: 0x2,
+        process_heap: 0x18,
+        nt_global_flag: 0x68,
+    };
+    /// 64位PEB
+    pub const PEB64: Self = Self {
+        being_debugged: 0x2,
+        process_heap: 0x30,
+        nt_global_flag: 0xBC,
+    };
+}
+
+/// 进程堆(_HEAP)中检测使用的字段偏移，Vista前后不同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapOffsets {
+    pub flags: usize,
+    pub force_flags: usize,
+}
+
+/// 进程运行环境的能力表，第一次访问时计算，之后所有模块共用
+///
+/// - `version`: 操作系统版本
+/// - `wow64`: 是否是运行在64位系统上的32位进程
+/// - `arch`: 进程架构，各检测据此选择PEB布局、上下文API与系统调用路径
+/// - `peb`: PEB字段偏移
+/// - `heap`: 进程堆字段偏移
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub version: OsVersion,
+    pub wow64: bool,
+    pub arch: ProcessArch,
+    pub peb: PebOffsets,
+    pub heap: HeapOffsets,
+    process_classes: u128,
+    system_classes: u128,
+}
+
+impl Capabilities {
+    /// 探测当前进程的运行环境
+    fn detect() -> Self {
+        // 查询失败时按编译目标处理，32位进程视为运行在32位系统上
+        let wow64 = wow64::wow64_peb(unsafe { GetCurrentProcess() }).is_ok_and(|peb| peb.is_some());
+        let native = if cfg!(target_pointer_width = "64") {
+            ProcessArch::Native64
+        } else {
+            ProcessArch::Native32
+        };
+        let arch = ProcessArch::from_wow64(wow64, native);
+        let peb = arch.peb_offsets();
+        let pointer64 = arch.pointer_size() == 8;
+
+        // PEB.OSMajorVersion/OSMinorVersion/OSBuildNumber
+        let (major, minor, build) = if pointer64 {
+            (0x118, 0x11C, 0x120)
+        } else {
+            (0xA4, 0xA8, 0xAC)
+        };
+        let peb_address = WinPeb::fast_peb_address() as *const u8;
+        let version = unsafe {
+            OsVersion {
+                major: ptr::read_volatile(peb_address.add(major) as *const u32),
+                minor: ptr::read_volatile(peb_address.add(minor) as *const u32),
+                build: ptr::read_volatile(peb_address.add(build) as *const u16) as u32,
+            }
+        };
+
+        let heap = match (pointer64, version.major >= 6) {
+            (true, true) => HeapOffsets {
+                flags: 0x70,
+                force_flags: 0x74,
+            },
+            (true, false) => HeapOffsets {
+                flags: 0x14,
+                force_flags: 0x18,
+            },
+            (false, true) => HeapOffsets {
+                flags: 0x40,
+                force_flags: 0x44,
+            },
+            (false, false) => HeapOffsets {
+                flags: 0x0C,
+                force_flags: 0x10,
+            },
+        };
+
+        let capabilities = Self {
+            version,
+            wow64,
+            arch,
+            peb,
+            heap,
+            process_classes: PROCESS_CLASSES
+                .iter()
+                .filter(|class| probe_process_class(**class))
+                .fold(0, |mask, class| mask | bit(class.0)),
+            system_classes: SYSTEM_CLASSES
+                .iter()
+                .filter(|class| probe_system_class(**class))
+                .fold(0, |mask, class| mask | bit(class.0)),
+        };
+        debug!("capabilities ==> {:?}", capabilities);
+        capabilities
+    }
+
+    /// 指定的进程信息类别是否可用，没有探测过的类别视为可用
+    pub fn process_class_available(&self, class: PROCESSINFOCLASS) -> bool {
+        !PROCESS_CLASSES.contains(&class) || self.process_classes & bit(class.0) != 0
+    }
+
+    /// 指定的系统信息类别是否可用，没有探测过的类别视为可用
+    pub fn system_class_available(&self, class: SYSTEM_INFORMATION_CLASS) -> bool {
+        !SYSTEM_CLASSES.contains(&class) || self.system_classes & bit(class.0) != 0
+    }
+}
+
+/// 信息类别在掩码中对应的位，超出范围的类别不占位
+fn bit(class: i32) -> u128 {
+    1u128.checked_shl(class as u32).unwrap_or(0)
+}
+
+/// 信息类别是否被系统支持，只有"无效类别/未实现"表示不可用，其他错误(例如长度不匹配)都表示可用
+fn class_supported(status: NTSTATUS) -> bool {
+    status != STATUS_INVALID_INFO_CLASS && status != STATUS_NOT_IMPLEMENTED
+}
+
+fn probe_process_class(class: PROCESSINFOCLASS) -> bool {
+    let Some(query) = nt_query::nt_query_information_process() else {
+        return false;
+    };
+    let mut information = [0u64; 8];
+    let mut return_length: u32 = 0;
+    let status = unsafe {
+        query(
+            GetCurrentProcess(),
+            class,
+            information.as_mut_ptr() as *mut c_void,
+            size_of_class(class),
+            &mut return_length,
+        )
+    };
+    class_supported(status)
+}
+
+/// 探测时使用的缓冲区大小，ProcessBasicInformation需要完整的结构体
+fn size_of_class(class: PROCESSINFOCLASS) -> u32 {
+    if class == ProcessBasicInformation {
+        6 * std::mem::size_of::<usize>() as u32
+    } else if class == ProcessDebugFlags {
+        4
+    } else {
+        std::mem::size_of::<usize>() as u32
+    }
+}
+
+fn probe_system_class(class: SYSTEM_INFORMATION_CLASS) -> bool {
+    let mut return_length: u32 = 0;
+    let status = unsafe { NtQuerySystemInformation(class, ptr::null_mut(), 0, &mut return_length) };
+    class_supported(status)
+}
+
+/// 获取能力表，第一次调用时探测运行环境
+///
+/// 探测只执行一次(读取PEB中的系统版本、查询WOW64状态、逐个尝试信息类别)，
+/// 之后的调用只是一次原子读取，peb、nt_query、thread等模块都从这里获取偏移与可用性
+///
+/// # 示例
+///
+/// ```ignore
+/// let capabilities = capability::get();
+/// println!("windows build {}", capabilities.version.build);
+/// ```
+pub fn get() -> &'static Capabilities {
+    CAPABILITIES.get_or_init(Capabilities::detect)
+}
+
+/// 提前探测运行环境，避免第一次检测时产生额外的系统调用
+///
+/// # 示例
+///
+/// ```ignore
+/// capability::init();
+/// loop {
+///     if WinPeb::fast_heap_flags() { break; }
+/// }
+/// ```
+pub fn init() {
+    get();
+}
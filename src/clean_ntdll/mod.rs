@@ -0,0 +1,400 @@
+use crate::logging::{debug, warn};
+use crate::{
+    hook,
+    imports::VirtualProtect,
+    obf,
+    pe::{self, PeImage},
+    resolve,
+    util::to_wide,
+};
+use anyhow::{Error, Result};
+use std::{
+    ffi::c_void,
+    mem::{size_of, transmute_copy},
+    ptr,
+    sync::OnceLock,
+};
+use windows::{
+    core::PWSTR,
+    Wdk::{
+        Foundation::OBJECT_ATTRIBUTES,
+        System::Memory::{NtMapViewOfSection, NtOpenSection, NtUnmapViewOfSection, ViewUnmap},
+    },
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, UNICODE_STRING},
+        System::{
+            Kernel::OBJ_CASE_INSENSITIVE,
+            Memory::{
+                VirtualAlloc, VirtualFree, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE_READ,
+                PAGE_PROTECTION_FLAGS, PAGE_READONLY, PAGE_READWRITE, SECTION_MAP_READ,
+            },
+            Threading::GetCurrentProcess,
+        },
+    },
+};
+
+/// \KnownDlls中ntdll的section对象名，WOW64进程需要使用32位的\KnownDlls32
+#[cfg(target_pointer_width = "32")]
+pub const KNOWN_DLLS_NTDLL: [&str; 2] = ["\\KnownDlls32\\ntdll.dll", "\\KnownDlls\\ntdll.dll"];
+#[cfg(target_pointer_width = "64")]
+pub const KNOWN_DLLS_NTDLL: [&str; 1] = ["\\KnownDlls\\ntdll.dll"];
+
+/// 私有映射的干净ntdll副本
+///
+/// 副本位于`VirtualAlloc`申请的内存中，已按照自身基址完成重定位，权限为`PAGE_EXECUTE_READ`，
+/// 释放时自动回收内存
+///
+/// # 注意
+///
+/// 副本没有经过加载器初始化(没有PEB登记、没有TLS、全局变量未初始化)，
+/// 只有`Nt*`/`Zw*`这类直接执行syscall的存根可以安全调用，
+/// `Rtl*`/`Ldr*`等依赖ntdll内部状态的函数不能通过副本调用
+#[derive(Debug)]
+pub struct CleanNtdll {
+    base: *mut c_void,
+    size: usize,
+}
+
+// 副本在创建完成后只读，可以在线程间共享
+unsafe impl Send for CleanNtdll {}
+unsafe impl Sync for CleanNtdll {}
+
+impl Drop for CleanNtdll {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { VirtualFree(self.base, 0, MEM_RELEASE) } {
+            warn!("VirtualFree clean ntdll failed; error: {:?}", e);
+        }
+    }
+}
+
+impl CleanNtdll {
+    /// 加载干净的ntdll副本，优先从\KnownDlls读取，失败时从磁盘文件读取
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 两种方式都失败
+    /// - `Ok(ntdll)`: 干净的ntdll副本
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// let ntdll = CleanNtdll::load().unwrap();
+    /// println!("clean ntdll at {:#x}", ntdll.base());
+    /// ```
+    pub fn load() -> Result<Self> {
+        Self::from_known_dlls().or_else(|e| {
+            warn!("map ntdll from KnownDlls failed; error: {:?}", e);
+            Self::from_disk()
+        })
+    }
+
+    /// 从\KnownDlls中的section对象复制ntdll
+    ///
+    /// KnownDlls的section在系统启动时创建，进程内的hook无法修改其内容
+    pub fn from_known_dlls() -> Result<Self> {
+        let mut last_error = Error::msg("no KnownDlls section");
+        for name in KNOWN_DLLS_NTDLL {
+            match map_known_dll(name).and_then(|view| Self::from_image(view.as_slice())) {
+                Ok(ntdll) => {
+                    debug!("clean ntdll from {} ==> {:#x}", name, ntdll.base());
+                    return Ok(ntdll);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// 从磁盘文件复制ntdll
+    pub fn from_disk() -> Result<Self> {
+        let path = hook::get_module_path(hook::get_module("ntdll.dll")?)?;
+        let image = pe::map_from_disk(&path, 0)?;
+        let ntdll = Self::from_image(&image)?;
+        debug!("clean ntdll from {:?} ==> {:#x}", path, ntdll.base());
+        Ok(ntdll)
+    }
+
+    /// 将映像布局的缓冲区复制到新申请的内存中，重定位后设置为可执行
+    fn from_image(image: &[u8]) -> Result<Self> {
+        let size = image.len();
+        let base = unsafe { VirtualAlloc(None, size, MEM_COMMIT | MEM_RESERVE, PAGE_READWRITE) };
+        if base.is_null() {
+            return Err(windows::core::Error::from_win32().into());
+        }
+
+        // 先构造出来，后续失败时由Drop释放内存
+        let ntdll = Self { base, size };
+        let buffer = unsafe { std::slice::from_raw_parts_mut(base as *mut u8, size) };
+        buffer.copy_from_slice(image);
+        pe::relocate(buffer, base as usize)?;
+
+        let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+        unsafe { VirtualProtect(base, size, PAGE_EXECUTE_READ, &mut old_protect) }?;
+
+        Ok(ntdll)
+    }
+
+    /// 副本基址
+    pub fn base(&self) -> usize {
+        self.base as usize
+    }
+
+    /// 副本大小
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 副本的PE视图
+    pub fn image(&self) -> PeImage<'_> {
+        PeImage::from_buffer(unsafe {
+            std::slice::from_raw_parts(self.base as *const u8, self.size)
+        })
+        .expect("clean ntdll is not a valid PE image")
+    }
+
+    /// 获取导出函数在副本中的地址
+    ///
+    /// # 参数
+    ///
+    /// - `name`: 导出函数名，例如`NtQueryInformationProcess`
+    ///
+    /// # 返回值
+    ///
+    /// - `Some(address)`: 函数地址
+    /// - `None`: 没有该导出函数或者是转发导出
+    pub fn get_proc_address(&self, name: &str) -> Option<usize> {
+        self.image()
+            .exports()
+            .into_iter()
+            .find(|export| export.name.as_deref() == Some(name) && !export.forwarded)
+            .map(|export| self.base() + export.rva as usize)
+    }
+
+    /// 按名称哈希获取导出函数在副本中的地址
+    ///
+    /// # 参数
+    ///
+    /// - `function`: 导出函数名的哈希，例如`resolve::hash("NtQueryInformationProcess")`
+    ///
+    /// # 返回值
+    ///
+    /// - `Some(address)`: 函数地址
+    /// - `None`: 没有该导出函数或者是转发导出
+    pub fn find_export(&self, function: u32) -> Option<usize> {
+        self.image()
+            .exports()
+            .into_iter()
+            .find(|export| {
+                !export.forwarded
+                    && export
+                        .name
+                        .as_deref()
+                        .is_some_and(|name| resolve::hash(name) == function)
+            })
+            .map(|export| self.base() + export.rva as usize)
+    }
+
+    /// 获取导出函数在副本中的函数指针
+    ///
+    /// # Safety
+    ///
+    /// `T`必须是与导出函数签名一致的`unsafe extern "system" fn`类型
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// type NtCloseFn = unsafe extern "system" fn(HANDLE) -> NTSTATUS;
+    /// let nt_close: NtCloseFn = unsafe { ntdll.get_function("NtClose") }.unwrap();
+    /// ```
+    pub unsafe fn get_function<T: Copy>(&self, name: &str) -> Option<T> {
+        assert_eq!(size_of::<T>(), size_of::<usize>());
+        let address = self.get_proc_address(name)?;
+        Some(transmute_copy(&address))
+    }
+
+    /// 按名称哈希获取导出函数在副本中的函数指针
+    ///
+    /// # Safety
+    ///
+    /// 同`get_function`
+    pub unsafe fn find_function<T: Copy>(&self, function: u32) -> Option<T> {
+        assert_eq!(size_of::<T>(), size_of::<usize>());
+        let address = self.find_export(function)?;
+        Some(transmute_copy(&address))
+    }
+}
+
+/// 映射到当前进程中的section视图，释放时自动取消映射
+struct SectionView {
+    base: *mut c_void,
+    size: usize,
+}
+
+impl SectionView {
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.base as *const u8, self.size) }
+    }
+}
+
+impl Drop for SectionView {
+    fn drop(&mut self) {
+        let _ = unsafe { NtUnmapViewOfSection(GetCurrentProcess(), Some(self.base)) };
+    }
+}
+
+/// 打开\KnownDlls下的section对象并只读映射到当前进程
+fn map_known_dll(name: &str) -> Result<SectionView> {
+    let mut wide = to_wide(name);
+    let length = ((wide.len() - 1) * 2) as u16;
+    let object_name = UNICODE_STRING {
+        Length: length,
+        MaximumLength: length + 2,
+        Buffer: PWSTR(wide.as_mut_ptr()),
+    };
+    let object_attributes = OBJECT_ATTRIBUTES {
+        Length: size_of::<OBJECT_ATTRIBUTES>() as u32,
+        ObjectName: &object_name,
+        Attributes: OBJ_CASE_INSENSITIVE as u32,
+        ..Default::default()
+    };
+
+    let mut hsection = HANDLE::default();
+    let status = unsafe { NtOpenSection(&mut hsection, SECTION_MAP_READ.0, &object_attributes) };
+    if status.is_err() {
+        warn!("NtOpenSection {} failed; error code: {:?}", name, status);
+        return Err(Error::msg(obf!("NtOpenSection failed").decrypt()));
+    }
+
+    let mut base: *mut c_void = ptr::null_mut();
+    let mut size: usize = 0;
+    // ntdll已经加载在首选基址上，这里通常返回STATUS_IMAGE_NOT_AT_BASE，属于成功状态
+    let status = unsafe {
+        NtMapViewOfSection(
+            hsection,
+            GetCurrentProcess(),
+            &mut base,
+            0,
+            0,
+            None,
+            &mut size,
+            ViewUnmap,
+            0,
+            PAGE_READONLY.0,
+        )
+    };
+    let _ = unsafe { CloseHandle(hsection) };
+    if status.is_err() {
+        warn!(
+            "NtMapViewOfSection {} failed; error code: {:?}",
+            name, status
+        );
+        return Err(Error::msg(obf!("NtMapViewOfSection failed").decrypt()));
+    }
+
+    debug!("map {} ==> base: {:?}; size: {:#x}", name, base, size);
+
+    // 视图大小按页对齐，按照SizeOfImage截断
+    let mut view = SectionView { base, size };
+    let size_of_image = PeImage::from_buffer(view.as_slice())?
+        .nt_headers()?
+        .OptionalHeader
+        .SizeOfImage as usize;
+    view.size = size_of_image.min(size);
+
+    Ok(view)
+}
+
+/// 全局的干净ntdll副本，首次使用时加载
+pub fn global() -> Option<&'static CleanNtdll> {
+    static CLEAN_NTDLL: OnceLock<Option<CleanNtdll>> = OnceLock::new();
+    CLEAN_NTDLL
+        .get_or_init(|| {
+            CleanNtdll::load()
+                .map_err(|e| warn!("load clean ntdll failed; error: {:?}", e))
+                .ok()
+        })
+        .as_ref()
+}
+
+/// 判断已加载的ntdll中的导出函数当前是否被inline hook
+///
+/// 只缓存磁盘映像(`hook::disk_image`)，内存中的函数开头每次调用都重新读取，
+/// 调试器在检测开始之后安装的hook也能被发现
+///
+/// # 参数
+///
+/// - `function`: 导出函数名的哈希，例如`resolve::hash("NtClose")`
+///
+/// # 返回值
+///
+/// - `true`: 函数开头与磁盘文件不一致
+/// - `false`: 函数未被修改，或者无法读取磁盘映像
+pub fn is_hooked(function: u32) -> bool {
+    let Ok(hmodule) = hook::get_module("ntdll.dll") else {
+        return false;
+    };
+    let buffer = match hook::disk_image(hmodule) {
+        Ok(buffer) => buffer,
+        Err(e) => {
+            warn!("map ntdll from disk failed; error: {:?}", e);
+            return false;
+        }
+    };
+    let (Ok(disk), Ok(memory)) = (PeImage::from_buffer(&buffer), PeImage::from_module(hmodule))
+    else {
+        return false;
+    };
+    let Some(export) = disk.exports().into_iter().find(|export| {
+        !export.forwarded
+            && export
+                .name
+                .as_deref()
+                .is_some_and(|name| resolve::hash(name) == function)
+    }) else {
+        return false;
+    };
+
+    let rva = export.rva as usize;
+    let hooked = memory.bytes(rva, hook::PROLOGUE_SIZE) != disk.bytes(rva, hook::PROLOGUE_SIZE);
+    if hooked {
+        debug!("ntdll!{:#x} prologue differs from disk", function);
+    }
+    hooked
+}
+
+/// 当已加载的ntdll中的函数被hook时，返回干净副本中的函数指针
+///
+/// 调用方在返回`None`时直接调用正常导入的函数即可
+///
+/// # 参数
+///
+/// - `function`: 导出函数名的哈希，应当用常量求值，名称不会进入二进制
+///
+/// # 返回值
+///
+/// - `Some(function)`: 函数被hook，返回干净副本中的函数指针
+/// - `None`: 函数未被hook，或者干净副本加载失败
+///
+/// # Safety
+///
+/// 同`CleanNtdll::get_function`，且`function`只能是syscall存根
+///
+/// # 示例
+///
+/// ```ignore
+/// const NT_CLOSE: u32 = resolve::hash("NtClose");
+/// let status = match unsafe { resolve_if_hooked::<NtCloseFn>(NT_CLOSE) } {
+///     Some(nt_close) => nt_close(handle),
+///     None => NtClose(handle),
+/// };
+/// ```
+pub unsafe fn resolve_if_hooked<T: Copy>(function: u32) -> Option<T> {
+    if !is_hooked(function) {
+        return None;
+    }
+
+    let resolved = global()?.find_function(function)?;
+    debug!("{:#x} is hooked, call through clean ntdll", function);
+    Some(resolved)
+}
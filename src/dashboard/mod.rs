@@ -0,0 +1,298 @@
+use crate::engine::{Category, Engine, Report, Technique, Verdict};
+use crate::sink::DetectionSink;
+use crate::util;
+use anyhow::{Error, Result};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// 证据日志保留的最大条数，超出后丢弃最早的记录
+pub const LOG_CAPACITY: usize = 256;
+
+/// 技术最近一次执行的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// 还没有执行过
+    Pending,
+    /// 未命中
+    Clean,
+    /// 命中
+    Detected,
+    /// 检测函数执行失败
+    Failed,
+}
+
+impl Status {
+    /// 由执行结果得到状态
+    pub fn from_verdict(verdict: &Verdict) -> Self {
+        match (verdict.detected, verdict.error.is_some()) {
+            (true, _) => Self::Detected,
+            (false, true) => Self::Failed,
+            (false, false) => Self::Clean,
+        }
+    }
+
+    /// 面板中显示的文字
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Clean => "clean",
+            Self::Detected => "DETECTED",
+            Self::Failed => "error",
+        }
+    }
+}
+
+/// 面板中一个技术的状态
+///
+/// - `name`: 技术名称
+/// - `category`: 技术类别
+/// - `weight`: 计分权重
+/// - `status`: 最近一次的结果
+/// - `runs`: 执行次数
+/// - `hits`: 命中次数
+/// - `evidence`: 最近一次的证据或者错误信息
+/// - `elapsed`: 最近一次的执行耗时
+#[derive(Debug, Clone, PartialEq)]
+pub struct TechniqueRow {
+    pub name: &'static str,
+    pub category: Category,
+    pub weight: u32,
+    pub status: Status,
+    pub runs: u64,
+    pub hits: u64,
+    pub evidence: Option<String>,
+    pub elapsed: Duration,
+}
+
+/// 检测面板的数据，图形界面(`dashboard`)与终端界面(`anti_debug tui`)共用
+///
+/// - `rows`: 每个技术的状态，顺序与注册顺序一致
+/// - `log`: 命中与失败的记录，最多保留`LOG_CAPACITY`条
+/// - `rounds`: 完成的轮数
+#[derive(Debug, Clone)]
+pub struct Dashboard {
+    pub rows: Vec<TechniqueRow>,
+    pub log: VecDeque<String>,
+    pub rounds: u64,
+    started: Instant,
+}
+
+impl Dashboard {
+    /// 为一组技术创建面板，所有技术的状态都是`Pending`
+    pub fn new(techniques: &[Technique]) -> Self {
+        Self {
+            rows: techniques
+                .iter()
+                .map(|technique| TechniqueRow {
+                    name: technique.name,
+                    category: technique.category,
+                    weight: technique.weight,
+                    status: Status::Pending,
+                    runs: 0,
+                    hits: 0,
+                    evidence: None,
+                    elapsed: Duration::ZERO,
+                })
+                .collect(),
+            log: VecDeque::new(),
+            rounds: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// 记录一次执行结果，命中或者失败时追加一条日志
+    ///
+    /// # 参数
+    ///
+    /// - `verdict`: 执行结果
+    /// - `elapsed`: 执行耗时
+    pub fn record(&mut self, verdict: &Verdict, elapsed: Duration) {
+        let Some(index) = self.rows.iter().position(|row| row.name == verdict.name) else {
+            return;
+        };
+        self.rows[index].elapsed = elapsed;
+        self.apply(index, verdict);
+    }
+
+    /// 记录输出目标收到的一份报告，`rounds`按报告计数
+    ///
+    /// 面板中没有的技术(例如后台监视模块通过`sink::publish`发布的命中)追加到表格末尾；
+    /// 报告中没有耗时，`elapsed`保持不变
+    ///
+    /// # 参数
+    ///
+    /// - `report`: 检测引擎或者后台监视模块分发的报告
+    pub fn record_report(&mut self, report: &Report) {
+        for verdict in report.verdicts.iter() {
+            let index = match self.rows.iter().position(|row| row.name == verdict.name) {
+                Some(index) => index,
+                None => {
+                    self.rows.push(TechniqueRow {
+                        name: verdict.name,
+                        category: verdict.category,
+                        weight: verdict.weight,
+                        status: Status::Pending,
+                        runs: 0,
+                        hits: 0,
+                        evidence: None,
+                        elapsed: Duration::ZERO,
+                    });
+                    self.rows.len() - 1
+                }
+            };
+            self.apply(index, verdict);
+        }
+        self.rounds += 1;
+    }
+
+    /// 更新一行的状态，命中或者失败时追加一条日志
+    fn apply(&mut self, index: usize, verdict: &Verdict) {
+        let status = Status::from_verdict(verdict);
+        let row = &mut self.rows[index];
+        row.status = status;
+        row.runs += 1;
+        row.hits += verdict.detected as u64;
+        row.evidence = verdict.evidence.clone().or_else(|| verdict.error.clone());
+
+        if status == Status::Detected || status == Status::Failed {
+            let entry = format!(
+                "[{:>8.1}s] {} {} ==> {}",
+                self.started.elapsed().as_secs_f64(),
+                status.label(),
+                verdict.name,
+                row.evidence.as_deref().unwrap_or_default()
+            );
+            if self.log.len() == LOG_CAPACITY {
+                self.log.pop_front();
+            }
+            self.log.push_back(entry);
+        }
+    }
+
+    /// 最近一次结果为命中的技术数
+    pub fn detected(&self) -> usize {
+        self.rows
+            .iter()
+            .filter(|row| row.status == Status::Detected)
+            .count()
+    }
+
+    /// 面板创建以来的时长
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// 把收到的报告记录到面板中的输出目标，通过`sink::install`订阅检测引擎与后台监视模块的命中
+///
+/// # 示例
+///
+/// ```ignore
+/// let dashboard = Arc::new(Mutex::new(Dashboard::new(&builtin_techniques())));
+/// sink::install(Arc::new(DashboardSink(dashboard.clone())));
+/// Engine::default().run();
+/// println!("{} detected", dashboard.lock().unwrap().detected());
+/// ```
+#[derive(Debug, Clone)]
+pub struct DashboardSink(pub Arc<Mutex<Dashboard>>);
+
+impl DetectionSink for DashboardSink {
+    fn name(&self) -> &str {
+        "dashboard"
+    }
+
+    fn emit(&self, report: &Report) -> Result<()> {
+        self.0
+            .lock()
+            .map_err(|_| Error::msg("dashboard lock poisoned"))?
+            .record_report(report);
+        Ok(())
+    }
+}
+
+/// 在后台线程中循环执行检测并更新面板，drop时停止
+///
+/// # 示例
+///
+/// ```ignore
+/// let techniques = builtin_techniques();
+/// let dashboard = Arc::new(Mutex::new(Dashboard::new(&techniques)));
+/// let monitor = DashboardMonitor::start(dashboard.clone(), techniques, Duration::from_secs(2));
+/// loop {
+///     println!("{} detected", dashboard.lock().unwrap().detected());
+///     thread::sleep(Duration::from_secs(1));
+/// }
+/// ```
+pub struct DashboardMonitor {
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DashboardMonitor {
+    /// 启动后台线程，每轮按注册顺序执行所有技术
+    ///
+    /// # 参数
+    ///
+    /// - `dashboard`: 要更新的面板，执行检测期间不持有锁
+    /// - `techniques`: 检测技术
+    /// - `interval`: 两轮之间的间隔
+    pub fn start(
+        dashboard: Arc<Mutex<Dashboard>>,
+        techniques: Vec<Technique>,
+        interval: Duration,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let worker = util::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                for technique in techniques.iter() {
+                    if stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+
+                    let started = Instant::now();
+                    let verdict = Engine::run_technique(technique);
+                    let elapsed = started.elapsed();
+                    if let Ok(mut dashboard) = dashboard.lock() {
+                        dashboard.record(&verdict, elapsed);
+                    }
+                }
+                if let Ok(mut dashboard) = dashboard.lock() {
+                    dashboard.rounds += 1;
+                }
+
+                let deadline = Instant::now() + interval;
+                while !stopped.load(Ordering::SeqCst) && Instant::now() < deadline {
+                    thread::park_timeout(deadline.saturating_duration_since(Instant::now()));
+                }
+            }
+        });
+
+        Self {
+            stop,
+            worker: Some(worker),
+        }
+    }
+
+    /// 停止检测并等待当前技术执行完成
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for DashboardMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,596 @@
+use crate::logging::{debug, warn};
+use crate::{
+    imports::{
+        ContinueDebugEvent, DebugActiveProcess, DebugSetProcessKillOnExit, IsDebuggerPresent,
+        OutputDebugStringW, ReadProcessMemory, WaitForDebugEvent,
+    },
+    nt_query::get_parent_process_id,
+    obf,
+    obfstr::ObfStr,
+    thread_monitor, timing,
+    util::to_wide,
+    watchdog::WATCHDOG_ENV,
+};
+use anyhow::{Error, Result};
+use std::{
+    env,
+    ffi::{c_void, OsString},
+    mem::size_of,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{
+            CloseHandle, BOOL, DBG_CONTINUE, DBG_EXCEPTION_NOT_HANDLED, EXCEPTION_BREAKPOINT,
+            HANDLE, NTSTATUS, STATUS_WX86_BREAKPOINT, WAIT_OBJECT_0,
+        },
+        System::{
+            Diagnostics::Debug::{
+                WriteProcessMemory, CREATE_PROCESS_DEBUG_EVENT, DEBUG_EVENT, EXCEPTION_DEBUG_EVENT,
+                EXIT_PROCESS_DEBUG_EVENT, LOAD_DLL_DEBUG_EVENT, OUTPUT_DEBUG_STRING_EVENT,
+                OUTPUT_DEBUG_STRING_INFO,
+            },
+            Environment::GetCommandLineW,
+            Threading::{
+                CreateProcessW, GetCurrentProcess, GetCurrentProcessId, GetExitCodeProcess,
+                TerminateProcess, WaitForSingleObject, CREATE_UNICODE_ENVIRONMENT,
+                DEBUG_ONLY_THIS_PROCESS, INFINITE, PROCESS_INFORMATION, STARTUPINFOW,
+            },
+        },
+    },
+};
+
+/// 标记子进程处于保护模式的环境变量，值为作为调试器的父进程ID
+pub const PROTECTED_CHILD_ENV: &str = "ANTI_DEBUG_PROTECTED_CHILD";
+
+/// 标记子进程为自调试辅助进程的环境变量，值为被调试的父进程ID
+pub const SELF_DEBUG_ENV: &str = "ANTI_DEBUG_SELF_DEBUG";
+
+/// 辅助进程附加失败(`DebugActiveProcess`被拒绝)时的退出码
+pub const ATTACH_REFUSED_EXIT_CODE: u32 = 0xdeb0_0001;
+
+/// 被调试的进程通过`OutputDebugStringW`发给调试器的确认请求前缀，
+/// 后面是确认值的地址与随机数(十六进制，以`:`分隔)
+static ACK_PREFIX: ObfStr = obf!("anti_debug-ack:");
+
+/// 调试器写回的确认值
+static ACK: AtomicU64 = AtomicU64::new(0);
+
+/// 当前进程是否是由`run_protected`启动的受保护子进程
+///
+/// 环境变量可以被预先设置，因此只把它当作线索，还要确认：
+///
+/// - 父进程ID与环境变量中的一致
+/// - 当前进程处于被调试状态，并且调试器对确认请求写回了本次生成的随机数，
+///   只有运行`debug_loop`的保护父进程会这样做
+///
+/// 结果在首次调用时确定
+///
+/// # 注意
+///
+/// 受保护子进程的调试器就是父进程，`IsDebuggerPresent`、调试端口等调试器检测会返回真，
+/// 子进程中应结合该函数解释检测结果
+pub fn is_protected_child() -> bool {
+    static VERIFIED: OnceLock<bool> = OnceLock::new();
+    *VERIFIED.get_or_init(verify_protecting_parent)
+}
+
+/// 确认调试器是启动当前进程的保护父进程
+fn verify_protecting_parent() -> bool {
+    let Some(expected) = env::var(PROTECTED_CHILD_ENV)
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+    else {
+        return false;
+    };
+
+    match get_parent_process_id(unsafe { GetCurrentProcess() }) {
+        Ok(parent) if parent == expected => {}
+        parent => {
+            warn!(
+                "protected child marker does not match parent ==> expected: {}; parent: {:?}",
+                expected, parent
+            );
+            return false;
+        }
+    }
+    if !unsafe { IsDebuggerPresent() }.as_bool() {
+        warn!("protected child marker set but no debugger attached");
+        return false;
+    }
+
+    if !request_ack() {
+        warn!("debugger did not acknowledge protected child");
+        return false;
+    }
+
+    debug!("protected by parent ==> {}", expected);
+    true
+}
+
+/// 向调试器发送确认请求，返回调试器是否写回了本次生成的随机数
+///
+/// 输出调试字符串时当前线程挂起，直到调试器处理完事件，返回时确认值已经写回。
+/// 没有调试器、或者调试器不是运行`debug_loop`的本库进程时返回`false`
+fn request_ack() -> bool {
+    let nonce = timing::rdtsc() | 1;
+    let request = format!(
+        "{}{:x}:{:x}",
+        ACK_PREFIX.decrypt(),
+        ACK.as_ptr() as usize,
+        nonce
+    );
+    unsafe { OutputDebugStringW(PCWSTR(to_wide(&request).as_ptr())) };
+    ACK.load(Ordering::SeqCst) == nonce
+}
+
+/// 构造传给`CreateProcessW`的Unicode环境块：当前环境变量加上`name=value`
+///
+/// 不修改当前进程的环境变量，避免与其他线程读取环境变量竞争。
+/// 当前环境中已有的保护、自调试与守护对标记会被去掉，条目按名称排序(不区分大小写)
+pub(crate) fn environment_block(name: &str, value: &str) -> Vec<u16> {
+    let mut variables: Vec<(OsString, OsString)> = env::vars_os()
+        .filter(|(key, _)| {
+            ![PROTECTED_CHILD_ENV, SELF_DEBUG_ENV, WATCHDOG_ENV]
+                .iter()
+                .any(|marker| key.eq_ignore_ascii_case(marker))
+        })
+        .collect();
+    variables.push((name.into(), value.into()));
+    variables.sort_by_key(|(key, _)| key.to_ascii_uppercase());
+
+    let mut block: Vec<u16> = Vec::new();
+    for (key, value) in variables {
+        block.extend(key.to_string_lossy().encode_utf16());
+        block.push(b'=' as u16);
+        block.extend(value.to_string_lossy().encode_utf16());
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
+
+/// 回应被调试进程的确认请求，把随机数写回被调试进程中请求指定的地址
+fn acknowledge(hprocess: HANDLE, info: &OUTPUT_DEBUG_STRING_INFO) {
+    // 只处理Unicode字符串，请求由OutputDebugStringW发出
+    if info.fUnicode == 0 || info.nDebugStringLength == 0 {
+        return;
+    }
+
+    let mut buffer = vec![0u16; info.nDebugStringLength as usize];
+    let read = unsafe {
+        ReadProcessMemory(
+            hprocess,
+            info.lpDebugStringData.0 as *const c_void,
+            buffer.as_mut_ptr().cast(),
+            buffer.len() * 2,
+            std::ptr::null_mut(),
+        )
+    };
+    if !read.as_bool() {
+        return;
+    }
+
+    let message = String::from_utf16_lossy(&buffer);
+    let Some(request) = message
+        .trim_end_matches('\0')
+        .strip_prefix(ACK_PREFIX.decrypt().as_str())
+    else {
+        return;
+    };
+    let Some((address, nonce)) = request.split_once(':').and_then(|(address, nonce)| {
+        Some((
+            usize::from_str_radix(address, 16).ok()?,
+            u64::from_str_radix(nonce, 16).ok()?,
+        ))
+    }) else {
+        return;
+    };
+
+    if let Err(e) = unsafe {
+        WriteProcessMemory(
+            hprocess,
+            address as *const c_void,
+            &nonce as *const u64 as *const c_void,
+            size_of::<u64>(),
+            None,
+        )
+    } {
+        warn!("acknowledge protected child failed; error: {:?}", e);
+    }
+}
+
+/// 以调试模式启动子进程，并在当前线程处理其调试事件直到子进程退出
+///
+/// 一个进程同一时间只能被一个调试器附加，子进程被父进程占用后，外部调试器无法再附加。
+/// 父进程退出时子进程随之被终止(`DebugSetProcessKillOnExit`的默认行为)
+///
+/// 异常转发：除加载器的初始断点外，所有异常都以`DBG_EXCEPTION_NOT_HANDLED`继续，
+/// 子进程自身的SEH/VEH照常处理；第二次机会的异常说明子进程未处理，系统随后终止子进程
+///
+/// # 参数
+///
+/// - `exe`: 可执行文件路径
+/// - `command_line`: 完整命令行(包括程序名)
+///
+/// # 返回值
+///
+/// - `Err`: 创建子进程失败或者等待调试事件失败
+/// - `Ok(exit_code)`: 子进程的退出码
+///
+/// # 示例
+///
+/// ```ignore
+/// let exit_code = spawn_protected("C:\\app\\worker.exe", "worker.exe --job 1").unwrap();
+/// std::process::exit(exit_code as i32);
+/// ```
+pub fn spawn_protected<P: AsRef<Path>>(exe: P, command_line: &str) -> Result<u32> {
+    let application = to_wide(&exe.as_ref().to_string_lossy());
+    // CreateProcessW可能会修改命令行缓冲区
+    let mut command_line = to_wide(command_line);
+    let startup_info = STARTUPINFOW {
+        cb: size_of::<STARTUPINFOW>() as u32,
+        ..Default::default()
+    };
+    let mut process_info = PROCESS_INFORMATION::default();
+
+    let environment = environment_block(
+        PROTECTED_CHILD_ENV,
+        &unsafe { GetCurrentProcessId() }.to_string(),
+    );
+    unsafe {
+        CreateProcessW(
+            PCWSTR(application.as_ptr()),
+            PWSTR(command_line.as_mut_ptr()),
+            None,
+            None,
+            false,
+            DEBUG_ONLY_THIS_PROCESS | CREATE_UNICODE_ENVIRONMENT,
+            Some(environment.as_ptr().cast()),
+            PCWSTR::null(),
+            &startup_info,
+            &mut process_info,
+        )
+    }?;
+
+    debug!(
+        "protected child ==> pid: {}; exe: {:?}",
+        process_info.dwProcessId,
+        exe.as_ref()
+    );
+    let _ = unsafe { CloseHandle(process_info.hThread) };
+    let _ = unsafe { CloseHandle(process_info.hProcess) };
+
+    debug_loop()
+}
+
+/// 以受保护子进程的方式重新启动当前程序，命令行保持不变
+///
+/// # 返回值
+///
+/// - `Err`: 启动子进程失败
+/// - `Ok(exit_code)`: 子进程的退出码
+pub fn run_protected() -> Result<u32> {
+    let exe = env::current_exe()?;
+    let command_line = unsafe { GetCommandLineW().to_string() }?;
+    spawn_protected(exe, &command_line)
+}
+
+/// 启动器入口：父进程中启动受保护子进程并以其退出码退出，子进程中直接返回
+///
+/// 在`main`开头调用，之后的代码只在受保护子进程中执行
+///
+/// # 返回值
+///
+/// - `Err`: 启动子进程失败，或者设置了保护标记但调试器不是保护父进程(标记被伪造)
+/// - `Ok(())`: 当前进程是经过确认的受保护子进程
+///
+/// # 示例
+///
+/// ```ignore
+/// fn main() {
+///     anti_debug::debug_blocker::protect().unwrap();
+///     // 真正的业务代码
+/// }
+/// ```
+pub fn protect() -> Result<()> {
+    if is_protected_child() {
+        return Ok(());
+    }
+    if env::var_os(PROTECTED_CHILD_ENV).is_some() {
+        return Err(Error::msg(
+            "protected child marker without protecting parent",
+        ));
+    }
+
+    let exit_code = run_protected()?;
+    std::process::exit(exit_code as i32);
+}
+
+/// 处理调试事件，直到子进程退出
+fn debug_loop() -> Result<u32> {
+    let mut initial_breakpoint = false;
+    // 由系统在CREATE_PROCESS_DEBUG_EVENT中提供，调试器不需要关闭
+    let mut hprocess = HANDLE::default();
+    loop {
+        let mut event = DEBUG_EVENT::default();
+        unsafe { WaitForDebugEvent(&mut event, INFINITE) }.ok()?;
+
+        let mut status: NTSTATUS = DBG_CONTINUE;
+        let mut exit_code = None;
+        match event.dwDebugEventCode {
+            EXCEPTION_DEBUG_EVENT => {
+                let exception = unsafe { event.u.Exception };
+                let code = exception.ExceptionRecord.ExceptionCode;
+                // 加载器在进程初始化时触发的断点只发给调试器，WOW64进程还有一个32位的初始断点
+                let loader_breakpoint = (!initial_breakpoint && code == EXCEPTION_BREAKPOINT)
+                    || code == STATUS_WX86_BREAKPOINT;
+                if loader_breakpoint {
+                    initial_breakpoint = true;
+                } else {
+                    if exception.dwFirstChance == 0 {
+                        warn!(
+                            "protected child crashed ==> code: {:#x}; address: {:?}",
+                            code.0, exception.ExceptionRecord.ExceptionAddress
+                        );
+                    }
+                    status = DBG_EXCEPTION_NOT_HANDLED;
+                }
+            }
+            CREATE_PROCESS_DEBUG_EVENT => {
+                let _ = unsafe { CloseHandle(event.u.CreateProcessInfo.hFile) };
+                hprocess = unsafe { event.u.CreateProcessInfo.hProcess };
+            }
+            OUTPUT_DEBUG_STRING_EVENT => {
+                acknowledge(hprocess, unsafe { &event.u.DebugString });
+            }
+            LOAD_DLL_DEBUG_EVENT => {
+                let _ = unsafe { CloseHandle(event.u.LoadDll.hFile) };
+            }
+            EXIT_PROCESS_DEBUG_EVENT => {
+                exit_code = Some(unsafe { event.u.ExitProcess.dwExitCode });
+            }
+            _ => {}
+        }
+
+        unsafe { ContinueDebugEvent(event.dwProcessId, event.dwThreadId, status) }.ok()?;
+        if let Some(exit_code) = exit_code {
+            debug!("protected child exited ==> {:#x}", exit_code);
+            return Ok(exit_code);
+        }
+    }
+}
+
+/// 当前进程是否是`SelfDebug`启动的辅助进程
+pub fn is_self_debug_helper() -> bool {
+    env::var_os(SELF_DEBUG_ENV).is_some()
+}
+
+/// 自调试：由辅助子进程通过`DebugActiveProcess`附加到当前进程，占用调试端口
+///
+/// 与`spawn_protected`相反，真正的业务代码留在父进程中，辅助进程只转发调试事件。
+/// 辅助进程退出时调试器自动分离(`DebugSetProcessKillOnExit(false)`)，当前进程不受影响，
+/// 监控线程会重新启动辅助进程并附加；释放时结束辅助进程并分离
+///
+/// 辅助进程必须确认已经附加：`DebugActiveProcess`被拒绝(调试端口已被占用)，
+/// 或者附加到当前进程的调试器没有回应确认请求，都说明有其他调试器，
+/// 启动时返回`Err`，监控期间通过`is_detected`报告
+///
+/// # 注意
+///
+/// 附加期间当前进程处于被调试状态，`IsDebuggerPresent`、调试端口等检测会返回真
+pub struct SelfDebug {
+    stop: Arc<AtomicBool>,
+    detected: Arc<AtomicBool>,
+    monitor: Option<JoinHandle<()>>,
+}
+
+impl SelfDebug {
+    /// 等待辅助进程附加的最长时间
+    const ATTACH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// 启动辅助进程并等待其附加到当前进程
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 启动辅助进程失败，或者辅助进程没有确认附加(调试端口已被其他调试器占用)
+    /// - `Ok(self_debug)`: 已附加并确认
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// fn main() {
+    ///     // 辅助进程以相同的命令行启动，在这里进入调试事件循环并退出
+    ///     let _self_debug = anti_debug::debug_blocker::self_debug().unwrap();
+    ///     // 真正的业务代码
+    /// }
+    /// ```
+    pub fn start() -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let detected = Arc::new(AtomicBool::new(false));
+        let (ready, attached) = mpsc::channel::<Result<()>>();
+
+        // 句柄不能跨线程传递，辅助进程在监控线程中启动，结果通过channel返回
+        let stopped = stop.clone();
+        let detection = detected.clone();
+        let monitor = thread_monitor::spawn_hidden(move || {
+            let mut helper = match attach_helper() {
+                Ok(helper) => {
+                    let _ = ready.send(Ok(()));
+                    helper
+                }
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                    return;
+                }
+            };
+
+            loop {
+                if stopped.load(Ordering::SeqCst) {
+                    // 辅助进程退出后调试器自动分离
+                    let _ = unsafe { TerminateProcess(helper, 0) };
+                    let _ = unsafe { CloseHandle(helper) };
+                    return;
+                }
+                if unsafe { WaitForSingleObject(helper, 100) } != WAIT_OBJECT_0 {
+                    continue;
+                }
+
+                warn!("self debug helper exited; re-attaching");
+                let _ = unsafe { CloseHandle(helper) };
+                helper = loop {
+                    if stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    match attach_helper() {
+                        Ok(helper) => break helper,
+                        Err(e) => {
+                            // 辅助进程退出的间隙调试端口被其他调试器占用
+                            warn!("re-attach self debug helper failed; error: {:?}", e);
+                            detection.store(true, Ordering::SeqCst);
+                            thread::sleep(Duration::from_secs(1));
+                        }
+                    }
+                };
+            }
+        });
+
+        match attached.recv() {
+            Ok(Ok(())) => Ok(Self {
+                stop,
+                detected,
+                monitor: Some(monitor),
+            }),
+            Ok(Err(e)) => {
+                let _ = monitor.join();
+                Err(e)
+            }
+            Err(_) => Err(Error::msg("self debug monitor exited")),
+        }
+    }
+
+    /// 监控期间是否有辅助进程未能确认附加，说明调试端口曾被其他调试器占用
+    pub fn is_detected(&self) -> bool {
+        self.detected.load(Ordering::SeqCst)
+    }
+
+    /// 结束辅助进程并分离
+    pub fn stop(self) {}
+}
+
+impl Drop for SelfDebug {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(monitor) = self.monitor.take() {
+            let _ = monitor.join();
+        }
+    }
+}
+
+/// 启动辅助进程，等待其附加并回应确认请求后返回辅助进程句柄
+fn attach_helper() -> Result<HANDLE> {
+    let application = to_wide(&env::current_exe()?.to_string_lossy());
+    let mut command_line = to_wide(&unsafe { GetCommandLineW().to_string() }?);
+    let startup_info = STARTUPINFOW {
+        cb: size_of::<STARTUPINFOW>() as u32,
+        ..Default::default()
+    };
+    let mut process_info = PROCESS_INFORMATION::default();
+
+    let environment = environment_block(
+        SELF_DEBUG_ENV,
+        &unsafe { GetCurrentProcessId() }.to_string(),
+    );
+    unsafe {
+        CreateProcessW(
+            PCWSTR(application.as_ptr()),
+            PWSTR(command_line.as_mut_ptr()),
+            None,
+            None,
+            false,
+            CREATE_UNICODE_ENVIRONMENT,
+            Some(environment.as_ptr().cast()),
+            PCWSTR::null(),
+            &startup_info,
+            &mut process_info,
+        )
+    }?;
+    let _ = unsafe { CloseHandle(process_info.hThread) };
+
+    let hprocess = process_info.hProcess;
+    let fail = |message: &'static str| {
+        let _ = unsafe { TerminateProcess(hprocess, 0) };
+        let _ = unsafe { CloseHandle(hprocess) };
+        Err(Error::msg(message))
+    };
+
+    let start = Instant::now();
+    while !unsafe { IsDebuggerPresent() }.as_bool() {
+        if unsafe { WaitForSingleObject(hprocess, 10) } == WAIT_OBJECT_0 {
+            let mut exit_code: u32 = 0;
+            let _ = unsafe { GetExitCodeProcess(hprocess, &mut exit_code) };
+            if exit_code == ATTACH_REFUSED_EXIT_CODE {
+                return fail("debug port is already in use");
+            }
+            return fail("self debug helper exited before attaching");
+        }
+        if start.elapsed() > SelfDebug::ATTACH_TIMEOUT {
+            return fail("self debug helper failed to attach");
+        }
+    }
+
+    // 被调试不代表调试器就是辅助进程，只有辅助进程的debug_loop会写回确认值
+    if !request_ack() {
+        return fail("attached debugger is not the self debug helper");
+    }
+
+    debug!(
+        "self debug helper attached ==> pid: {}",
+        process_info.dwProcessId
+    );
+    Ok(process_info.hProcess)
+}
+
+/// 辅助进程入口：附加到父进程并转发调试事件，直到父进程退出
+///
+/// # 返回值
+///
+/// - `Err`: 当前进程不是辅助进程，或者附加失败(调试端口已被占用)
+/// - `Ok(exit_code)`: 父进程的退出码
+pub fn run_self_debug_helper() -> Result<u32> {
+    let Some(parent_pid) = env::var(SELF_DEBUG_ENV)
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+    else {
+        return Err(Error::msg("not a self debug helper"));
+    };
+
+    unsafe { DebugActiveProcess(parent_pid) }.ok()?;
+    // 辅助进程被结束时只分离调试器，不结束父进程
+    unsafe { DebugSetProcessKillOnExit(BOOL::from(false)) }.ok()?;
+    debug!("self debug attached ==> {}", parent_pid);
+
+    debug_loop()
+}
+
+/// 自调试入口：辅助进程中进入调试事件循环并退出，父进程中启动辅助进程并等待其附加
+///
+/// 在`main`开头调用，并在需要保护的期间持有返回值
+pub fn self_debug() -> Result<SelfDebug> {
+    if is_self_debug_helper() {
+        let exit_code = run_self_debug_helper().unwrap_or_else(|e| {
+            warn!("self debug helper failed; error: {:?}", e);
+            ATTACH_REFUSED_EXIT_CODE
+        });
+        std::process::exit(exit_code as i32);
+    }
+
+    SelfDebug::start()
+}
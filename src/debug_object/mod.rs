@@ -0,0 +1,316 @@
+use crate::logging::{debug, warn};
+use crate::{
+    imports::{NtQueryObject, OpenProcess},
+    nt_query::{nt_query_information_process, query_information_process},
+    obf,
+    thread::{
+        SystemHandleInformationEx, SystemHandleTableEntryInfoEx, SYSTEM_EXTENDED_HANDLE_INFORMATION,
+    },
+    util::{self, enumerate_processes},
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    mem::{size_of, size_of_val},
+};
+use windows::{
+    Wdk::{Foundation::OBJECT_INFORMATION_CLASS, System::Threading::ProcessDebugObjectHandle},
+    Win32::{
+        Foundation::{
+            CloseHandle, HANDLE, STATUS_INFO_LENGTH_MISMATCH, STATUS_SUCCESS, UNICODE_STRING,
+        },
+        Security::GENERIC_MAPPING,
+        System::{
+            Diagnostics::ToolHelp::{
+                Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+            },
+            Threading::{GetCurrentProcess, GetCurrentProcessId, PROCESS_QUERY_INFORMATION},
+        },
+    },
+};
+
+/// ObjectTypesInformation信息类别，返回系统中所有对象类型
+const OBJECT_TYPES_INFORMATION: OBJECT_INFORMATION_CLASS = OBJECT_INFORMATION_CLASS(3);
+
+/// 调试对象的类型名
+const DEBUG_OBJECT_TYPE: &str = "DebugObject";
+
+/// Windows 8.1之前类型信息中没有TypeIndex，按顺序推算时第一个类型的索引
+const FIRST_TYPE_INDEX: usize = 2;
+
+/// NtQueryObject返回的对象类型信息
+#[repr(C)]
+struct ObjectTypeInformation {
+    type_name: UNICODE_STRING,
+    total_number_of_objects: u32,
+    total_number_of_handles: u32,
+    total_paged_pool_usage: u32,
+    total_non_paged_pool_usage: u32,
+    total_name_pool_usage: u32,
+    total_handle_table_usage: u32,
+    high_water_number_of_objects: u32,
+    high_water_number_of_handles: u32,
+    high_water_paged_pool_usage: u32,
+    high_water_non_paged_pool_usage: u32,
+    high_water_name_pool_usage: u32,
+    high_water_handle_table_usage: u32,
+    invalid_attributes: u32,
+    generic_mapping: GENERIC_MAPPING,
+    valid_access_mask: u32,
+    security_required: u8,
+    maintain_handle_count: u8,
+    type_index: u8,
+    reserved_byte: u8,
+    pool_type: u32,
+    default_paged_pool_charge: u32,
+    default_non_paged_pool_charge: u32,
+}
+
+/// 系统中的一个调试对象句柄
+///
+/// - `pid`: 持有句柄的进程ID
+/// - `name`: 持有句柄的进程名
+/// - `handle`: 句柄值
+/// - `object`: 调试对象的内核地址
+/// - `access`: 句柄的访问权限
+/// - `target`: 调试对象附加到的进程，只能解析出附加到当前进程或者子进程的调试对象
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugObjectHandle {
+    pub pid: u32,
+    pub name: String,
+    pub handle: usize,
+    pub object: usize,
+    pub access: u32,
+    pub target: Option<u32>,
+}
+
+/// 通过NtQueryObject(ObjectTypesInformation)查找DebugObject类型在句柄表中的类型索引
+///
+/// 类型索引在不同系统版本、甚至不同启动之间都可能变化，不能写死
+///
+/// # 返回值
+///
+/// - `Err`: NtQueryObject调用失败或者没有找到DebugObject类型
+/// - `Ok(index)`: 句柄表中DebugObject的类型索引
+pub fn debug_object_type_index() -> Result<u16> {
+    let mut buffer: Vec<u64> = vec![0; 0x2000];
+    loop {
+        let mut return_length: u32 = 0;
+        let status = unsafe {
+            NtQueryObject(
+                HANDLE::default(),
+                OBJECT_TYPES_INFORMATION,
+                buffer.as_mut_ptr() as *mut c_void,
+                size_of_val(buffer.as_slice()) as u32,
+                &mut return_length,
+            )
+        };
+        if status == STATUS_INFO_LENGTH_MISMATCH {
+            let required = return_length as usize / size_of::<u64>() + 1;
+            buffer.resize(required.max(buffer.len() * 2), 0);
+            continue;
+        }
+        if status != STATUS_SUCCESS {
+            warn!("NtQueryObject failed; status: {:?}", status);
+            return Err(Error::msg(
+                obf!("NtQueryObject ObjectTypesInformation failed").decrypt(),
+            ));
+        }
+        break;
+    }
+
+    let base = buffer.as_ptr() as *const u8;
+    let end = size_of_val(buffer.as_slice());
+    let count = unsafe { (base as *const u32).read() } as usize;
+    let mut offset = size_of::<usize>();
+    for position in 0..count {
+        if offset + size_of::<ObjectTypeInformation>() > end {
+            break;
+        }
+        let info = unsafe { &*(base.add(offset) as *const ObjectTypeInformation) };
+        let name = String::from_utf16_lossy(unsafe {
+            std::slice::from_raw_parts(info.type_name.Buffer.0, info.type_name.Length as usize / 2)
+        });
+        if name == DEBUG_OBJECT_TYPE {
+            let index = match info.type_index {
+                0 => position + FIRST_TYPE_INDEX,
+                index => index as usize,
+            };
+            debug!("debug object type index ==> {}", index);
+            return Ok(index as u16);
+        }
+
+        // 类型名紧跟在结构体之后，下一项按指针大小对齐
+        offset += size_of::<ObjectTypeInformation>() + info.type_name.MaximumLength as usize;
+        offset = (offset + size_of::<usize>() - 1) & !(size_of::<usize>() - 1);
+    }
+
+    Err(Error::msg(obf!("DebugObject type not found").decrypt()))
+}
+
+/// 枚举指定进程的子进程ID
+fn child_pids(pid: u32) -> Result<Vec<u32>> {
+    let hsnapshot = util::create_snapshot(TH32CS_SNAPPROCESS, 0)?;
+    let mut entry = PROCESSENTRY32W {
+        dwSize: size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+
+    let mut children: Vec<u32> = Vec::new();
+    let mut next = unsafe { Process32FirstW(hsnapshot, &mut entry) };
+    while next.is_ok() {
+        if entry.th32ParentProcessID == pid && entry.th32ProcessID != pid {
+            children.push(entry.th32ProcessID);
+        }
+        next = unsafe { Process32NextW(hsnapshot, &mut entry) };
+    }
+    let _ = unsafe { CloseHandle(hsnapshot) };
+
+    Ok(children)
+}
+
+/// 通过ProcessDebugObjectHandle取得附加到指定进程的调试对象，返回当前进程中的新句柄
+fn attached_debug_object(pid: u32) -> Option<HANDLE> {
+    let query = nt_query_information_process()?;
+    let hprocess = match pid == unsafe { GetCurrentProcessId() } {
+        true => unsafe { GetCurrentProcess() },
+        false => unsafe { OpenProcess(PROCESS_QUERY_INFORMATION, false, pid) }.ok()?,
+    };
+
+    let mut hdebug = HANDLE::default();
+    let mut return_length: u32 = 0;
+    let status = unsafe {
+        query_information_process(
+            query,
+            hprocess,
+            ProcessDebugObjectHandle,
+            &mut hdebug as *mut HANDLE as *mut c_void,
+            size_of::<HANDLE>() as u32,
+            &mut return_length,
+        )
+    };
+    if pid != unsafe { GetCurrentProcessId() } {
+        let _ = unsafe { CloseHandle(hprocess) };
+    }
+
+    (status == STATUS_SUCCESS && !hdebug.is_invalid() && !hdebug.0.is_null()).then_some(hdebug)
+}
+
+/// 枚举系统中所有的调试对象句柄，并解析附加到当前进程或者子进程的调试对象由谁持有
+///
+/// - 通过NtQueryObject解析DebugObject的类型索引，在扩展句柄表中筛选出所有调试对象句柄
+/// - 对当前进程与子进程查询ProcessDebugObjectHandle，得到附加在它们上的调试对象在当前进程中的句柄
+/// - 在句柄表中找到这些句柄的内核地址，指向同一地址的其他句柄的持有者就是调试器
+///
+/// # 返回值
+///
+/// - `Err`: 解析类型索引或者查询扩展句柄表失败
+/// - `Ok(handles)`: 系统中所有的调试对象句柄，不包括查询时在当前进程中临时打开的句柄
+///
+/// # 示例
+///
+/// ```ignore
+/// for handle in find_debug_objects().unwrap() {
+///     println!("{} ({}) holds a debug object", handle.name, handle.pid);
+/// }
+/// ```
+pub fn find_debug_objects() -> Result<Vec<DebugObjectHandle>> {
+    let type_index = debug_object_type_index()?;
+    let self_pid = unsafe { GetCurrentProcessId() };
+
+    let mut targets = vec![self_pid];
+    targets.extend(child_pids(self_pid)?);
+    let attached: Vec<(u32, HANDLE)> = targets
+        .into_iter()
+        .filter_map(|pid| attached_debug_object(pid).map(|hdebug| (pid, hdebug)))
+        .collect();
+
+    // 句柄表必须在打开调试对象句柄之后重新查询，不能使用缓存
+    let entries = util::with_system_information(SYSTEM_EXTENDED_HANDLE_INFORMATION, |data| {
+        let information = unsafe { &*(data.as_ptr() as *const SystemHandleInformationEx) };
+        let entries: &[SystemHandleTableEntryInfoEx] = unsafe {
+            std::slice::from_raw_parts(information.handles.as_ptr(), information.number_of_handles)
+        };
+        entries
+            .iter()
+            .filter(|entry| entry.object_type_index == type_index)
+            .cloned()
+            .collect::<Vec<SystemHandleTableEntryInfoEx>>()
+    });
+    let own: HashMap<usize, u32> = match &entries {
+        Ok(entries) => attached
+            .iter()
+            .filter_map(|(pid, hdebug)| {
+                entries
+                    .iter()
+                    .find(|entry| {
+                        entry.unique_process_id == self_pid as usize
+                            && entry.handle_value == hdebug.0 as usize
+                    })
+                    .map(|entry| (entry.object as usize, *pid))
+            })
+            .collect(),
+        Err(_) => HashMap::new(),
+    };
+    for (_, hdebug) in &attached {
+        let _ = unsafe { CloseHandle(*hdebug) };
+    }
+    let entries = entries?;
+
+    let names: HashMap<u32, String> = enumerate_processes()?.into_iter().collect();
+    let handles: Vec<DebugObjectHandle> = entries
+        .iter()
+        .filter(|entry| {
+            entry.unique_process_id != self_pid as usize
+                || !attached
+                    .iter()
+                    .any(|(_, hdebug)| hdebug.0 as usize == entry.handle_value)
+        })
+        .map(|entry| {
+            let pid = entry.unique_process_id as u32;
+            DebugObjectHandle {
+                pid,
+                name: names.get(&pid).cloned().unwrap_or_default(),
+                handle: entry.handle_value,
+                object: entry.object as usize,
+                access: entry.granted_access,
+                target: own.get(&(entry.object as usize)).copied(),
+            }
+        })
+        .collect();
+
+    debug!(
+        "debug objects ==> type index: {}; attached: {:?}; {:?}",
+        type_index, own, handles
+    );
+    Ok(handles)
+}
+
+/// 检查是否有其他进程持有附加到当前进程或者子进程的调试对象
+///
+/// 当前进程自己持有的调试对象(例如`debug_blocker`调试子进程)不算
+///
+/// # 返回值
+///
+/// - `Err`: 枚举调试对象失败
+/// - `Ok(handles)`: 调试当前进程或者子进程的调试对象句柄，为空则没有发现
+///
+/// # 示例
+///
+/// ```ignore
+/// for handle in check_debug_objects().unwrap() {
+///     println!("{} debugs {:?}", handle.name, handle.target);
+/// }
+/// ```
+pub fn check_debug_objects() -> Result<Vec<DebugObjectHandle>> {
+    let self_pid = unsafe { GetCurrentProcessId() };
+    let handles: Vec<DebugObjectHandle> = find_debug_objects()?
+        .into_iter()
+        .filter(|handle| handle.target.is_some() && handle.pid != self_pid)
+        .collect();
+    if !handles.is_empty() {
+        warn!("debug object held by another process ==> {:?}", handles);
+    }
+    Ok(handles)
+}
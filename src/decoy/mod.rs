@@ -0,0 +1,101 @@
+use crate::{capability, peb::WinPeb, timing::rdtsc, util::to_wide};
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::BOOL,
+        System::{
+            Diagnostics::Debug::{CheckRemoteDebuggerPresent, IsDebuggerPresent},
+            SystemInformation::GetTickCount64,
+            Threading::GetCurrentProcess,
+        },
+        UI::WindowsAndMessaging::FindWindowW,
+    },
+};
+
+/// 诱饵检测写入的"检测结果"
+///
+/// 看起来像全局的调试器标志，但没有任何代码根据它做判断，写入的值也经过随机翻转。
+/// 修补诱饵检测或者该标志都不会影响`Engine`的真正判定，
+/// 真正的判定由`Report::is_debugged`通过混淆路径计算
+pub static DEBUGGER_DETECTED: AtomicBool = AtomicBool::new(false);
+
+/// 诱饵检测的原始结果在这里累加，防止编译器优化掉检测代码
+static SINK: AtomicU64 = AtomicU64::new(0);
+
+/// 诱饵检测函数，返回值会被丢弃
+pub type DecoyFn = fn() -> bool;
+
+/// 所有诱饵检测，外观上与真正的检测相同：调用调试API、读取PEB、查找调试器窗口、计时
+pub const DECOYS: [(&str, DecoyFn); 6] = [
+    ("is_debugger_present", || {
+        unsafe { IsDebuggerPresent() }.as_bool()
+    }),
+    ("peb_being_debugged", || {
+        let peb = WinPeb::get_peb_address() as *const u8;
+        unsafe { peb.add(2).read_volatile() != 0 }
+    }),
+    ("peb_nt_global_flag", || {
+        let peb = WinPeb::get_peb_address() as *const u8;
+        let offset = capability::get().peb.nt_global_flag;
+        unsafe { peb.add(offset).cast::<u32>().read_volatile() & 0x70 != 0 }
+    }),
+    ("remote_debugger", || {
+        let mut present = BOOL::default();
+        let _ = unsafe { CheckRemoteDebuggerPresent(GetCurrentProcess(), &mut present) };
+        present.as_bool()
+    }),
+    ("debugger_window", || {
+        // 只用调试器独有的窗口类名，Qt等通用框架的类名会被普通程序命中
+        [
+            "OLLYDBG",
+            "WinDbgFrameClass",
+            "Zeta Debugger",
+            "Rock Debugger",
+            "ObsidianGUI",
+        ]
+        .iter()
+        .any(|class| {
+            let class = to_wide(class);
+            unsafe { FindWindowW(PCWSTR(class.as_ptr()), PCWSTR::null()) }
+                .is_ok_and(|hwnd| !hwnd.is_invalid())
+        })
+    }),
+    ("tick_delta", || {
+        let start = unsafe { GetTickCount64() };
+        black_box(rdtsc());
+        let end = unsafe { GetTickCount64() };
+        end - start > 1000
+    }),
+];
+
+/// 执行一个诱饵检测，并把随机翻转后的结果写入`DEBUGGER_DETECTED`
+///
+/// # 参数
+///
+/// - `index`: 诱饵检测的序号，超出范围时取模
+pub fn run_decoy(index: usize) {
+    let (_, decoy) = DECOYS[index % DECOYS.len()];
+    let detected = black_box(decoy());
+    SINK.fetch_add(detected as u64, Ordering::Relaxed);
+    DEBUGGER_DETECTED.store(detected ^ (rdtsc() & 1 == 1), Ordering::Relaxed);
+}
+
+/// 随机执行`count`个诱饵检测
+///
+/// 每次执行的诱饵与顺序都不同，分析人员无法通过固定的调用序列区分诱饵与真正的检测
+///
+/// # 示例
+///
+/// ```ignore
+/// decoy::run_decoys(3);
+/// let report = Engine::default().run();
+/// ```
+pub fn run_decoys(count: usize) {
+    for _ in 0..count {
+        run_decoy((rdtsc() >> 4) as usize);
+    }
+}
@@ -0,0 +1,1761 @@
+#[cfg(any(windows, target_os = "linux"))]
+use crate::integrity;
+#[cfg(target_os = "linux")]
+use crate::linux;
+use crate::logging::{debug, warn};
+#[cfg(target_os = "macos")]
+use crate::macos;
+#[cfg(feature = "flatten")]
+use crate::obfstr;
+#[cfg(feature = "simulate")]
+use crate::simulate;
+#[cfg(windows)]
+use crate::{
+    anti_dump,
+    breakpoint::{HardwareBreakPoint, SoftwareBreakPoint},
+    cache, debug_object, decoy, environment,
+    exception::{self, BreakRoute, CloseRoute},
+    hook, hypervisor,
+    imports::GetModuleHandleW,
+    ipt, module,
+    nt_query::{self, NtQueryDebug},
+    peb::WinPeb,
+    sandbox::{self, HardwareProfile},
+    syscall,
+    thread::{HoneyThread, SYSTEM_EXTENDED_HANDLE_INFORMATION},
+    timing, vm,
+};
+use crate::{
+    json, metrics, shuffle,
+    sink::{self, DetectionSink},
+    taxonomy::{self, Tag},
+    util::{self, BeingDebug},
+};
+use anyhow::Result;
+#[cfg(feature = "flatten")]
+use std::hint::black_box;
+use std::{
+    collections::VecDeque,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+#[cfg(windows)]
+use windows::{
+    Wdk::System::SystemInformation::{SystemProcessInformation, SYSTEM_INFORMATION_CLASS},
+    Win32::System::Threading::{GetCurrentProcess, GetCurrentThread},
+};
+
+/// 检测技术的类别，不同类别分开计分，调用方可以对不同类别采取不同策略
+///
+/// - `Debugger`: 调试器附加
+/// - `Environment`: 分析环境(虚拟机、沙箱、远程会话等)
+/// - `Tampering`: 代码被篡改(hook、补丁、注入等)
+/// - `TimeVirtualization`: 时间被加速、跳过或伪造(沙箱、变速工具)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Category {
+    Debugger,
+    Environment,
+    Tampering,
+    TimeVirtualization,
+}
+
+impl Category {
+    /// 所有类别
+    pub const ALL: [Category; 4] = [
+        Category::Debugger,
+        Category::Environment,
+        Category::Tampering,
+        Category::TimeVirtualization,
+    ];
+
+    /// 类别名称，与`Debug`输出一致，用于序列化
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Debugger => "Debugger",
+            Category::Environment => "Environment",
+            Category::Tampering => "Tampering",
+            Category::TimeVirtualization => "TimeVirtualization",
+        }
+    }
+
+    /// 由名称解析类别，见`name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.name() == name)
+    }
+}
+
+/// 技术命中时的严重程度，与误报可能性一起决定命中的置信度
+///
+/// - `Info`: 只说明环境特征，单独命中不代表正在被分析(例如远程会话、虚拟机)
+/// - `Suspicious`: 可能被调试或篡改，但存在已知的误报来源(例如计时、安全软件的hook)
+/// - `Confirmed`: 命中基本可以确认(例如调试端口、PEB标志)
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Severity {
+    Info,
+    Suspicious,
+    Confirmed,
+}
+
+impl Severity {
+    /// 所有严重程度
+    pub const ALL: [Severity; 3] = [Severity::Info, Severity::Suspicious, Severity::Confirmed];
+
+    /// 严重程度名称，用于序列化
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Suspicious => "suspicious",
+            Severity::Confirmed => "confirmed",
+        }
+    }
+
+    /// 由名称解析严重程度，见`name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|severity| severity.name() == name)
+    }
+
+    /// 不考虑误报时命中的置信度，范围0~100
+    pub fn base_confidence(&self) -> u32 {
+        match self {
+            Severity::Info => 40,
+            Severity::Suspicious => 70,
+            Severity::Confirmed => 100,
+        }
+    }
+}
+
+/// 检测函数，返回`Some(evidence)`表示命中，`None`表示未命中
+pub type CheckFn = fn() -> Result<Option<String>>;
+
+/// 单个检测技术
+///
+/// - `name`: 技术名称
+/// - `category`: 技术类别
+/// - `weight`: 计分权重
+/// - `severity`: 命中时的严重程度
+/// - `false_positive`: 误报可能性，百分比0~100
+/// - `tags`: ATT&CK与Check Point分类标识(见`taxonomy`模块)
+/// - `check`: 检测函数
+#[derive(Clone, Debug)]
+pub struct Technique {
+    pub name: &'static str,
+    pub category: Category,
+    pub weight: u32,
+    pub severity: Severity,
+    pub false_positive: u32,
+    pub tags: &'static [Tag],
+    pub check: CheckFn,
+}
+
+impl Technique {
+    /// 依赖调用线程状态的技术：读取当前线程的调试寄存器、设置当前线程的陷阱标志，
+    /// 在工作线程中执行时检查的是工作线程而不是调用线程
+    pub const THREAD_AFFINE: [&'static str; 4] = [
+        "hardware_breakpoint",
+        "trap_flag",
+        "api_trap_flag",
+        "mov_ss",
+    ];
+
+    /// 是否必须在调用线程中执行，见`THREAD_AFFINE`
+    pub fn is_thread_affine(&self) -> bool {
+        Self::THREAD_AFFINE.contains(&self.name)
+    }
+
+    /// 命中时的置信度：严重程度的基础置信度按误报可能性折算，范围0~100
+    pub fn confidence(&self) -> u32 {
+        self.severity.base_confidence() * (100 - self.false_positive.min(100)) / 100
+    }
+}
+
+/// 单个检测技术的执行结果
+///
+/// - `severity`、`tags`: 与`Technique`相同
+/// - `detected`: 是否命中
+/// - `confidence`: 命中时为`Technique::confidence`，未命中或者执行失败为0
+/// - `evidence`: 命中时的证据
+/// - `error`: 检测函数执行失败时的错误信息，失败的技术不计分
+/// - `timed_out`: 并行执行时没有在截止时间前完成，同时会设置`error`
+#[derive(Clone, Debug)]
+pub struct Verdict {
+    pub name: &'static str,
+    pub category: Category,
+    pub weight: u32,
+    pub severity: Severity,
+    pub tags: &'static [Tag],
+    pub detected: bool,
+    pub confidence: u32,
+    pub evidence: Option<String>,
+    pub error: Option<String>,
+    pub timed_out: bool,
+}
+
+impl Verdict {
+    /// 后台监视模块发现的命中，不经过检测引擎，见`sink::publish`
+    ///
+    /// # 参数
+    ///
+    /// - `name`: 监视模块名称
+    /// - `category`: 类别
+    /// - `severity`: 严重程度，置信度取其基础置信度
+    /// - `evidence`: 证据
+    pub fn observed(
+        name: &'static str,
+        category: Category,
+        severity: Severity,
+        evidence: String,
+    ) -> Self {
+        Self {
+            name,
+            category,
+            weight: Report::DEBUGGER_THRESHOLD,
+            severity,
+            tags: &[],
+            detected: true,
+            confidence: severity.base_confidence(),
+            evidence: Some(evidence),
+            error: None,
+            timed_out: false,
+        }
+    }
+
+    /// 序列化为JSON对象
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"name":{},"category":{},"weight":{},"severity":{},"tags":{},"detected":{},"confidence":{},"evidence":{},"error":{},"timed_out":{}}}"#,
+            json::quote(self.name),
+            json::quote(self.category.name()),
+            self.weight,
+            json::quote(self.severity.name()),
+            taxonomy::tags_to_json(self.tags),
+            self.detected,
+            self.confidence,
+            json::quote_option(self.evidence.as_deref()),
+            json::quote_option(self.error.as_deref()),
+            self.timed_out
+        )
+    }
+}
+
+/// 一次检测中所有技术共用快照的系统信息：进程列表与系统句柄表
+#[cfg(windows)]
+pub const SWEEP_CLASSES: [SYSTEM_INFORMATION_CLASS; 2] =
+    [SystemProcessInformation, SYSTEM_EXTENDED_HANDLE_INFORMATION];
+
+/// 一次完整检测的结果
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub verdicts: Vec<Verdict>,
+}
+
+impl Report {
+    /// 命中的调试器类技术权重之和达到该阈值则认为被调试，低权重的弱信号需要多个同时命中
+    pub const DEBUGGER_THRESHOLD: u32 = 10;
+
+    /// 分析环境得分达到该阈值则认为运行在分析环境中
+    pub const ENVIRONMENT_THRESHOLD: u32 = 50;
+
+    /// 计算指定类别的得分
+    ///
+    /// 得分为命中技术的权重之和占该类别成功执行技术权重之和的百分比，范围0~100
+    pub fn score(&self, category: Category) -> u32 {
+        let (detected, total) = self
+            .verdicts
+            .iter()
+            .filter(|verdict| verdict.category == category && verdict.error.is_none())
+            .fold((0, 0), |(detected, total), verdict| {
+                let hit = if verdict.detected { verdict.weight } else { 0 };
+                (detected + hit, total + verdict.weight)
+            });
+
+        match total {
+            0 => 0,
+            _ => detected * 100 / total,
+        }
+    }
+
+    /// 指定类别中命中技术的权重之和
+    pub fn detected_weight(&self, category: Category) -> u32 {
+        self.detections()
+            .filter(|verdict| verdict.category == category)
+            .map(|verdict| verdict.weight)
+            .sum()
+    }
+
+    /// 调试器附加得分
+    pub fn debugger_score(&self) -> u32 {
+        self.score(Category::Debugger)
+    }
+
+    /// 分析环境得分
+    pub fn environment_score(&self) -> u32 {
+        self.score(Category::Environment)
+    }
+
+    /// 代码篡改得分
+    pub fn tampering_score(&self) -> u32 {
+        self.score(Category::Tampering)
+    }
+
+    /// 时间虚拟化得分
+    pub fn time_virtualization_score(&self) -> u32 {
+        self.score(Category::TimeVirtualization)
+    }
+
+    /// 命中的调试器类技术权重之和达到`DEBUGGER_THRESHOLD`则认为被调试
+    ///
+    /// 真正的判定走混淆路径：累加过程中权重只以与构建密钥派生的掩码异或后的形式出现，
+    /// 判定逻辑包裹在`obfuscate!`中，与诱饵检测写入的`decoy::DEBUGGER_DETECTED`没有关系
+    pub fn is_debugged(&self) -> bool {
+        const MASK: u32 = crate::obfstr::derive_key(file!(), line!(), column!()) as u32;
+        crate::obfuscate! {
+            let sealed = self
+                .detections()
+                .filter(|verdict| verdict.category == Category::Debugger)
+                .fold(MASK, |sealed, verdict| {
+                    (sealed ^ MASK).saturating_add(verdict.weight) ^ MASK
+                });
+            sealed ^ MASK >= Self::DEBUGGER_THRESHOLD
+        }
+    }
+
+    /// 任意一个代码篡改类技术命中即认为被篡改
+    pub fn is_tampered(&self) -> bool {
+        crate::obfuscate! {
+            self.verdicts
+                .iter()
+                .any(|verdict| verdict.category == Category::Tampering && verdict.detected)
+        }
+    }
+
+    /// 任意一个时间虚拟化类技术命中即认为时间被虚拟化
+    pub fn is_time_virtualized(&self) -> bool {
+        crate::obfuscate! {
+            self.verdicts
+                .iter()
+                .any(|verdict| verdict.category == Category::TimeVirtualization && verdict.detected)
+        }
+    }
+
+    /// 分析环境得分达到阈值则认为运行在分析环境中
+    pub fn is_analysis_environment(&self) -> bool {
+        self.environment_score() >= Self::ENVIRONMENT_THRESHOLD
+    }
+
+    /// 计算指定类别的置信度
+    ///
+    /// 把每个命中视为独立的证据，置信度为至少一个命中不是误报的概率`1 - Π(1 - c/100)`，范围0~100
+    pub fn confidence(&self, category: Category) -> u32 {
+        let miss = self
+            .detections()
+            .filter(|verdict| verdict.category == category)
+            .fold(1.0, |miss, verdict| {
+                miss * (1.0 - verdict.confidence.min(100) as f64 / 100.0)
+            });
+        ((1.0 - miss) * 100.0).round() as u32
+    }
+
+    /// 所有类别中最高的置信度
+    pub fn max_confidence(&self) -> u32 {
+        Category::ALL
+            .into_iter()
+            .map(|category| self.confidence(category))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// 所有命中的技术
+    pub fn detections(&self) -> impl Iterator<Item = &Verdict> {
+        self.verdicts.iter().filter(|verdict| verdict.detected)
+    }
+
+    /// 去掉置信度低于`min_confidence`的命中，输出目标与响应策略可以只处理可信的命中
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// let report = Engine::default().run().filtered(60);
+    /// if report.is_debugged() {
+    ///     std::process::exit(1);
+    /// }
+    /// ```
+    pub fn filtered(&self, min_confidence: u32) -> Report {
+        Report {
+            verdicts: self
+                .verdicts
+                .iter()
+                .filter(|verdict| !verdict.detected || verdict.confidence >= min_confidence)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// 序列化为JSON对象，包含各类别得分与置信度以及所有技术的结果
+    pub fn to_json(&self) -> String {
+        let verdicts: Vec<String> = self.verdicts.iter().map(Verdict::to_json).collect();
+        let confidence: Vec<String> = Category::ALL
+            .into_iter()
+            .map(|category| {
+                format!(
+                    "{}:{}",
+                    json::quote(category.name()),
+                    self.confidence(category)
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"debugger_score":{},"environment_score":{},"tampering_score":{},"time_virtualization_score":{},"confidence":{{{}}},"verdicts":[{}]}}"#,
+            self.debugger_score(),
+            self.environment_score(),
+            self.tampering_score(),
+            self.time_virtualization_score(),
+            confidence.join(","),
+            verdicts.join(",")
+        )
+    }
+}
+
+impl BeingDebug for Report {
+    fn is_being_debug(&self) -> bool {
+        self.is_debugged()
+    }
+}
+
+/// 检测引擎，按顺序执行注册的所有检测技术并汇总结果
+///
+/// 每个检测技术执行前会随机执行`decoys`个诱饵检测(见`decoy`模块，仅Windows)，诱饵的结果不参与判定。
+/// 每次检测完成后报告由后台线程分发给`sinks`与通过`sink::install`注册的全局输出目标，检测线程不等待输出目标完成(见`sink`模块)
+///
+/// # 示例
+///
+/// ```ignore
+/// let report = Engine::default().run();
+/// println!(
+///     "debugger: {}; environment: {}",
+///     report.debugger_score(),
+///     report.environment_score()
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Engine {
+    pub techniques: Vec<Technique>,
+    pub decoys: usize,
+    pub sinks: Vec<Arc<dyn DetectionSink>>,
+}
+
+impl Default for Engine {
+    /// 包含所有内置检测技术的引擎
+    fn default() -> Self {
+        let mut engine = Self::new();
+        for technique in builtin_techniques() {
+            engine.register(technique);
+        }
+        engine
+    }
+}
+
+impl Engine {
+    /// 创建一个没有任何检测技术的引擎
+    pub fn new() -> Self {
+        Self {
+            techniques: Vec::new(),
+            decoys: 1,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// 注册一个检测技术
+    pub fn register(&mut self, technique: Technique) {
+        self.techniques.push(technique);
+    }
+
+    /// 修改已注册技术的严重程度与误报可能性，例如在已知会误报的环境中降低某个技术的置信度
+    ///
+    /// # 参数
+    ///
+    /// - `name`: 技术名称
+    /// - `severity`: 命中时的严重程度
+    /// - `false_positive`: 误报可能性，百分比0~100
+    ///
+    /// # 返回值
+    ///
+    /// - 是否找到该技术
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// let mut engine = Engine::default();
+    /// engine.configure("remote_session", Severity::Info, 90);
+    /// ```
+    pub fn configure(&mut self, name: &str, severity: Severity, false_positive: u32) -> bool {
+        let mut found = false;
+        for technique in self.techniques.iter_mut() {
+            if technique.name == name {
+                technique.severity = severity;
+                technique.false_positive = false_positive.min(100);
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// 注册一个输出目标
+    pub fn add_sink(&mut self, sink: Arc<dyn DetectionSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// 把报告交给后台分发线程，输出目标为`sinks`与通过`sink::install`注册的全局输出目标
+    fn dispatch(&self, report: &Report) {
+        let sinks: Vec<Arc<dyn DetectionSink>> = self
+            .sinks
+            .iter()
+            .cloned()
+            .chain(sink::installed())
+            .collect();
+        sink::dispatch(sinks, report.clone());
+    }
+
+    /// 执行单个检测技术
+    pub fn run_technique(technique: &Technique) -> Verdict {
+        let mut verdict = Verdict {
+            name: technique.name,
+            category: technique.category,
+            weight: technique.weight,
+            severity: technique.severity,
+            tags: technique.tags,
+            detected: false,
+            confidence: 0,
+            evidence: None,
+            error: None,
+            timed_out: false,
+        };
+
+        let started = Instant::now();
+        crate::obfuscate! {
+            match (technique.check)() {
+                Ok(evidence) => {
+                    verdict.detected = evidence.is_some();
+                    verdict.evidence = evidence;
+                }
+                Err(error) => {
+                    warn!("technique {} failed; {:?}", technique.name, error);
+                    verdict.error = Some(error.to_string());
+                }
+            }
+        }
+
+        #[cfg(feature = "simulate")]
+        if !verdict.detected && simulate::is_forced(technique) {
+            verdict.detected = true;
+            verdict.evidence = Some("simulated".to_string());
+            verdict.error = None;
+        }
+        if verdict.detected {
+            verdict.confidence = technique.confidence();
+        }
+
+        metrics::record_verdict(&verdict, started.elapsed());
+        debug!("technique verdict ==> {:?}", verdict);
+
+        verdict
+    }
+
+    /// 按注册顺序执行所有检测技术
+    ///
+    /// 执行期间`SWEEP_CLASSES`中的系统信息只查询一次，所有技术共用同一份快照。
+    /// 开启`flatten` feature时改为通过平坦化的状态机调度(见`dispatch_flattened`)
+    pub fn run(&self) -> Report {
+        let started = Instant::now();
+        #[cfg(windows)]
+        let _sweep = cache::sweep(&SWEEP_CLASSES);
+        #[cfg(feature = "flatten")]
+        let verdicts = self.dispatch_flattened();
+        #[cfg(not(feature = "flatten"))]
+        let verdicts = self
+            .techniques
+            .iter()
+            .map(|technique| {
+                #[cfg(windows)]
+                decoy::run_decoys(self.decoys);
+                Self::run_technique(technique)
+            })
+            .collect();
+
+        let report = Report { verdicts };
+        metrics::record_scan(&report, "sequential", started.elapsed());
+        self.dispatch(&report);
+        report
+    }
+
+    /// 以平坦化状态机的形式依次执行所有检测技术
+    ///
+    /// 所有步骤都放在同一个循环的分支中，下一步的状态编号与当前技术序号派生的掩码异或后保存，
+    /// 分支的目标只有在运行时才能解出；状态编号由构建密钥派生，每次构建都不同。
+    /// 状态被篡改时不再执行剩余技术，并追加一个命中的`dispatcher_integrity`结果
+    #[cfg(feature = "flatten")]
+    fn dispatch_flattened(&self) -> Vec<Verdict> {
+        let mut verdicts = Vec::with_capacity(self.techniques.len());
+        let mut index = 0;
+        let mut state = STATE_NEXT ^ state_mask(index);
+        loop {
+            // black_box阻止编译器跨迭代传播状态，把状态机还原成普通循环
+            match black_box(state) ^ state_mask(index) {
+                STATE_NEXT => {
+                    let next = match index < self.techniques.len() {
+                        true => STATE_DECOY,
+                        false => STATE_DONE,
+                    };
+                    state = next ^ state_mask(index);
+                }
+                STATE_DECOY => {
+                    #[cfg(windows)]
+                    decoy::run_decoys(self.decoys);
+                    state = STATE_RUN ^ state_mask(index);
+                }
+                STATE_RUN => {
+                    verdicts.push(Self::run_technique(&self.techniques[index]));
+                    index += 1;
+                    state = STATE_NEXT ^ state_mask(index);
+                }
+                STATE_DONE => return verdicts,
+                _ => {
+                    warn!("engine dispatcher state corrupted");
+                    verdicts.push(Verdict {
+                        name: "dispatcher_integrity",
+                        category: Category::Tampering,
+                        weight: 20,
+                        severity: Severity::Confirmed,
+                        tags: &[taxonomy::IMPAIR_DEFENSES],
+                        detected: true,
+                        confidence: Severity::Confirmed.base_confidence(),
+                        evidence: Some("dispatcher state corrupted".to_string()),
+                        error: None,
+                        timed_out: false,
+                    });
+                    return verdicts;
+                }
+            }
+        }
+    }
+
+    /// 在`workers`个线程中并行执行所有检测技术，到达截止时间后立即返回
+    ///
+    /// 截止时间前没有完成的技术标记为超时，不计分。已经开始执行的技术无法中断，
+    /// 会在后台继续执行完毕后丢弃结果；尚未开始的技术不再执行
+    ///
+    /// 线程相关的技术(见`Technique::THREAD_AFFINE`)不进入线程池，在调用线程中依次执行，
+    /// 检查的是调用线程自身的调试寄存器与陷阱标志
+    ///
+    /// # 参数
+    ///
+    /// - `workers`: 线程数量，至少为1
+    /// - `budget`: 整体时间预算，例如游戏主循环中的5ms
+    ///
+    /// # 返回值
+    ///
+    /// - 与`run`相同顺序的结果，超时的技术`timed_out`为true
+    ///
+    /// # 注意
+    ///
+    /// 返回前会等待所有输出目标完成，输出目标的耗时不计入`budget`，
+    /// 对延迟敏感时应使用只入队、不阻塞的输出目标(例如`HttpSink`)
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// let report = Engine::default().run_parallel(4, Duration::from_millis(5));
+    /// let completed = report.verdicts.iter().filter(|verdict| !verdict.timed_out).count();
+    /// ```
+    pub fn run_parallel(&self, workers: usize, budget: Duration) -> Report {
+        let started = Instant::now();
+        let deadline = started + budget;
+        #[cfg(windows)]
+        let _sweep = cache::sweep(&SWEEP_CLASSES);
+        let (pinned, pooled): (Vec<_>, Vec<_>) = self
+            .techniques
+            .iter()
+            .cloned()
+            .enumerate()
+            .partition(|(_, technique)| technique.is_thread_affine());
+        let pool_size = workers.clamp(1, pooled.len().max(1));
+        let queue: Arc<Mutex<VecDeque<(usize, Technique)>>> =
+            Arc::new(Mutex::new(pooled.into_iter().collect()));
+        let (sender, receiver) = mpsc::channel::<(usize, Verdict)>();
+
+        for _ in 0..pool_size {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            #[cfg(windows)]
+            let decoys = self.decoys;
+            util::spawn(move || loop {
+                let Some((index, technique)) = queue.lock().unwrap().pop_front() else {
+                    return;
+                };
+                #[cfg(windows)]
+                decoy::run_decoys(decoys);
+                let verdict = Self::run_technique(&technique);
+                if sender.send((index, verdict)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut verdicts: Vec<Option<Verdict>> = vec![None; self.techniques.len()];
+        let mut remaining = self.techniques.len() - pinned.len();
+        for (index, technique) in pinned {
+            if Instant::now() >= deadline {
+                break;
+            }
+            #[cfg(windows)]
+            decoy::run_decoys(self.decoys);
+            verdicts[index] = Some(Self::run_technique(&technique));
+        }
+        while remaining > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok((index, verdict)) => {
+                    verdicts[index] = Some(verdict);
+                    remaining -= 1;
+                }
+                Err(_) => break,
+            }
+        }
+        // 超时后清空队列，工作线程不再开始新的技术
+        queue.lock().unwrap().clear();
+
+        let report = Report {
+            verdicts: verdicts
+                .into_iter()
+                .zip(&self.techniques)
+                .map(|(verdict, technique)| {
+                    verdict.unwrap_or_else(|| {
+                        debug!("technique timed out ==> {}", technique.name);
+                        Verdict {
+                            name: technique.name,
+                            category: technique.category,
+                            weight: technique.weight,
+                            severity: technique.severity,
+                            tags: technique.tags,
+                            detected: false,
+                            confidence: 0,
+                            evidence: None,
+                            error: Some("timed out".to_string()),
+                            timed_out: true,
+                        }
+                    })
+                })
+                .collect(),
+        };
+        metrics::record_scan(&report, "parallel", started.elapsed());
+        self.dispatch(&report);
+        report
+    }
+}
+
+/// 平坦化调度的状态编号
+#[cfg(feature = "flatten")]
+const STATE_NEXT: u64 = obfstr::derive_key(file!(), 0x464c_4154, 1);
+#[cfg(feature = "flatten")]
+const STATE_DECOY: u64 = obfstr::derive_key(file!(), 0x464c_4154, 2);
+#[cfg(feature = "flatten")]
+const STATE_RUN: u64 = obfstr::derive_key(file!(), 0x464c_4154, 3);
+#[cfg(feature = "flatten")]
+const STATE_DONE: u64 = obfstr::derive_key(file!(), 0x464c_4154, 4);
+
+/// 第`index`个技术的状态掩码
+#[cfg(feature = "flatten")]
+fn state_mask(index: usize) -> u64 {
+    obfstr::derive_key(file!(), index as u32, 0x4d41_534b)
+}
+
+/// 将bool结果转换为检测结果，命中时以技术名称作为证据
+#[cfg(windows)]
+fn flag(detected: bool, evidence: &str) -> Option<String> {
+    detected.then(|| evidence.to_string())
+}
+
+/// 内置的所有检测技术
+///
+/// 顺序按构建密钥打乱(见`shuffle`模块)，每次构建的执行顺序都不同
+pub fn builtin_techniques() -> Vec<Technique> {
+    shuffle::shuffled(platform_techniques())
+}
+
+/// 当前平台的检测技术(Windows)
+///
+/// 用户活跃度检查使用进程内共享的观察器，每次执行采样一次，观察时间随引擎的多轮执行累积
+#[cfg(windows)]
+fn platform_techniques() -> Vec<Technique> {
+    #[cfg_attr(
+        not(any(
+            target_arch = "x86_64",
+            target_arch = "x86",
+            feature = "wmi",
+            feature = "authenticode"
+        )),
+        allow(unused_mut)
+    )]
+    let mut techniques = vec![
+        Technique {
+            name: "peb_being_debugged",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || {
+                if let Some(evidence) = hook::check_debug_api_neutered("IsDebuggerPresent")? {
+                    return Ok(Some(evidence));
+                }
+                Ok(flag(WinPeb::peb_being_debugged(), "IsDebuggerPresent"))
+            },
+        },
+        Technique {
+            name: "peb_being_debugged_asm",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || Ok(flag(WinPeb::peb_being_debugged_asm(), "PEB.BeingDebugged")),
+        },
+        Technique {
+            name: "peb_nt_global_flag",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || Ok(flag(WinPeb::peb_nt_global_flag_asm(), "PEB.NtGlobalFlag")),
+        },
+        Technique {
+            name: "peb_process_heap",
+            category: Category::Debugger,
+            weight: 5,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || Ok(flag(WinPeb::peb_process_heap()?, "ProcessHeap.Flags")),
+        },
+        Technique {
+            name: "remote_debugger_present",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || {
+                if let Some(evidence) =
+                    hook::check_debug_api_neutered("CheckRemoteDebuggerPresent")?
+                {
+                    return Ok(Some(evidence));
+                }
+                Ok(flag(
+                    nt_query::check_remote_debugger_present()?,
+                    "CheckRemoteDebuggerPresent",
+                ))
+            },
+        },
+        Technique {
+            name: "debug_port",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || {
+                let hprocess = unsafe { GetCurrentProcess() };
+                Ok(flag(
+                    NtQueryDebug::check_debug_port(hprocess),
+                    "ProcessDebugPort",
+                ))
+            },
+        },
+        Technique {
+            name: "debug_object",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || {
+                let hprocess = unsafe { GetCurrentProcess() };
+                Ok(flag(
+                    NtQueryDebug::check_debug_object(hprocess),
+                    "ProcessDebugObjectHandle",
+                ))
+            },
+        },
+        Technique {
+            name: "debug_object_hunt",
+            category: Category::Debugger,
+            weight: 30,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::OBJECT_HANDLES],
+            check: || {
+                let evidence: Vec<String> = debug_object::check_debug_objects()?
+                    .into_iter()
+                    .map(|handle| {
+                        format!(
+                            "{} ({}) holds debug object of {:?}",
+                            handle.name, handle.pid, handle.target
+                        )
+                    })
+                    .collect();
+                Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+            },
+        },
+        Technique {
+            name: "debug_flags",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::DEBUG_FLAGS],
+            check: || {
+                let hprocess = unsafe { GetCurrentProcess() };
+                Ok(flag(
+                    NtQueryDebug::check_debug_flags(hprocess),
+                    "ProcessDebugFlags",
+                ))
+            },
+        },
+        Technique {
+            name: "close_invalid_handle",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::OBJECT_HANDLES],
+            check: || {
+                Ok(flag(
+                    exception::check_invalid_handle(CloseRoute::CloseHandle)?,
+                    "CloseHandle(invalid)",
+                ))
+            },
+        },
+        Technique {
+            name: "debug_break_delivery",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::ASSEMBLY],
+            check: || {
+                Ok(flag(
+                    exception::check_breakpoint_delivery(BreakRoute::DebugBreak)?,
+                    "DebugBreak swallowed",
+                ))
+            },
+        },
+        Technique {
+            name: "int3_delivery",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::ASSEMBLY],
+            check: || {
+                Ok(flag(
+                    exception::check_breakpoint_delivery(BreakRoute::Int3)?,
+                    "int3 swallowed",
+                ))
+            },
+        },
+        Technique {
+            name: "trap_flag",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Suspicious,
+            false_positive: 10,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::ASSEMBLY],
+            check: || Ok(flag(exception::check_trap_flag()?, "single step swallowed")),
+        },
+        Technique {
+            name: "api_trap_flag",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Suspicious,
+            false_positive: 10,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::ASSEMBLY],
+            check: || {
+                Ok(flag(
+                    exception::check_api_trap_flag()?,
+                    "trap flag diverged across call",
+                ))
+            },
+        },
+        Technique {
+            name: "int3_record",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Suspicious,
+            false_positive: 10,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::ASSEMBLY],
+            check: || {
+                Ok(flag(
+                    exception::check_int3_record()?,
+                    "int3 record mismatch",
+                ))
+            },
+        },
+        Technique {
+            name: "prefixed_int3",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Suspicious,
+            false_positive: 10,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::ASSEMBLY],
+            check: || Ok(flag(exception::check_prefixed_int3()?, "rep int3 diverged")),
+        },
+        Technique {
+            name: "msr_probe",
+            category: Category::Debugger,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::EXCEPTIONS],
+            check: || {
+                let evidence = hypervisor::check_msr_probe()?;
+                Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+            },
+        },
+        Technique {
+            name: "ept_hooks",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Suspicious,
+            false_positive: 30,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::TIMING],
+            check: || {
+                let evidence = hypervisor::check_ept_hooks();
+                Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+            },
+        },
+        Technique {
+            name: "hyperdbg_artifacts",
+            category: Category::Debugger,
+            weight: 25,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::EVASION_REGISTRY],
+            check: || {
+                let artifacts = hypervisor::scan_hyperdbg_artifacts();
+                Ok((!artifacts.is_empty()).then(|| artifacts.join("; ")))
+            },
+        },
+        Technique {
+            name: "intel_pt",
+            category: Category::Debugger,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::EVASION_CPU],
+            check: || {
+                let evidence = ipt::check_intel_pt()?;
+                Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+            },
+        },
+        Technique {
+            name: "hardware_breakpoint",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let hthread = unsafe { GetCurrentThread() };
+                Ok(flag(
+                    HardwareBreakPoint::is_hardware_breakpoint_set(hthread)?,
+                    "Dr0-Dr3",
+                ))
+            },
+        },
+        Technique {
+            name: "software_breakpoints",
+            category: Category::Debugger,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 15,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let hmodule = unsafe { GetModuleHandleW(None) }?;
+                // 防转储擦除了PE头时临时恢复
+                let breakpoints =
+                    anti_dump::with_headers(|| SoftwareBreakPoint::find_in_module(hmodule, 16))?;
+                Ok((!breakpoints.is_empty()).then(|| format!("int3 at {:#x?}", breakpoints)))
+            },
+        },
+        Technique {
+            name: "honey_thread",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::MISC],
+            check: || {
+                let mut honey = HoneyThread::default();
+                honey.set_honey_thread_current_process()?;
+                Ok(flag(honey.check()?, "honey thread handle opened"))
+            },
+        },
+        Technique {
+            name: "environment_anomaly",
+            category: Category::Debugger,
+            weight: 5,
+            severity: Severity::Suspicious,
+            false_positive: 30,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::MISC],
+            check: || Ok(environment::check_environment_anomaly()),
+        },
+        Technique {
+            name: "symbol_engine",
+            category: Category::Debugger,
+            weight: 3,
+            severity: Severity::Info,
+            false_positive: 50,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::MISC],
+            check: || Ok(module::check_symbol_engine_loaded(&[])),
+        },
+        Technique {
+            name: "debug_privilege",
+            category: Category::Debugger,
+            weight: 5,
+            severity: Severity::Info,
+            false_positive: 40,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::OBJECT_HANDLES],
+            check: || {
+                let audit = environment::audit_debug_privilege()?;
+                Ok(audit.is_being_debug().then(|| format!("{:?}", audit)))
+            },
+        },
+        Technique {
+            name: "cpuid_hypervisor",
+            category: Category::Environment,
+            weight: 10,
+            severity: Severity::Info,
+            false_positive: 60,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_CPU],
+            check: || Ok(vm::check_cpuid_hypervisor().map(|hypervisor| format!("{:?}", hypervisor))),
+        },
+        Technique {
+            name: "vm_artifacts",
+            category: Category::Environment,
+            weight: 20,
+            severity: Severity::Suspicious,
+            false_positive: 30,
+            tags: &[
+                taxonomy::SYSTEM_CHECKS,
+                taxonomy::EVASION_FILESYSTEM,
+                taxonomy::EVASION_REGISTRY,
+            ],
+            check: || Ok(join_artifacts(&vm::scan_vm_artifacts())),
+        },
+        Technique {
+            name: "mac_address",
+            category: Category::Environment,
+            weight: 10,
+            severity: Severity::Info,
+            false_positive: 40,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_NETWORK],
+            check: || Ok(join_artifacts(&vm::check_mac_address()?)),
+        },
+        Technique {
+            name: "firmware_tables",
+            category: Category::Environment,
+            weight: 20,
+            severity: Severity::Suspicious,
+            false_positive: 30,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_FIRMWARE_TABLES],
+            check: || Ok(join_artifacts(&vm::scan_firmware_tables())),
+        },
+        Technique {
+            name: "hardware_profile",
+            category: Category::Environment,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 30,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_HARDWARE],
+            check: || {
+                let profile = HardwareProfile::query()?;
+                Ok(profile.is_being_debug().then(|| format!("{:?}", profile)))
+            },
+        },
+        Technique {
+            name: "user_liveness",
+            category: Category::Environment,
+            weight: 10,
+            severity: Severity::Info,
+            false_positive: 40,
+            tags: &[
+                taxonomy::USER_ACTIVITY_CHECKS,
+                taxonomy::EVASION_UI_ARTIFACTS,
+            ],
+            check: || {
+                let report = sandbox::sample_liveness();
+                Ok(report.is_being_debug().then(|| format!("{:?}", report)))
+            },
+        },
+        Technique {
+            name: "sandbox_dlls",
+            category: Category::Environment,
+            weight: 20,
+            severity: Severity::Confirmed,
+            false_positive: 10,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_PROCESSES],
+            check: || {
+                Ok(sandbox::check_sandbox_dlls()
+                    .map(|(sandbox, dll)| format!("{}: {}", sandbox, dll)))
+            },
+        },
+        Technique {
+            name: "process_blacklist",
+            category: Category::Environment,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[
+                taxonomy::DEBUGGER_EVASION,
+                taxonomy::SYSTEM_CHECKS,
+                taxonomy::EVASION_PROCESSES,
+            ],
+            check: || {
+                let processes = environment::find_blacklisted_processes()?;
+                Ok((!processes.is_empty()).then(|| {
+                    processes
+                        .into_iter()
+                        .map(|process| process.name)
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                }))
+            },
+        },
+        Technique {
+            name: "window_blacklist",
+            category: Category::Environment,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 15,
+            tags: &[
+                taxonomy::DEBUGGER_EVASION,
+                taxonomy::MISC,
+                taxonomy::EVASION_UI_ARTIFACTS,
+            ],
+            check: || Ok(join_blacklisted(environment::find_blacklisted_windows())),
+        },
+        Technique {
+            name: "driver_blacklist",
+            category: Category::Debugger,
+            weight: 25,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::MISC],
+            check: || Ok(join_blacklisted(environment::find_blacklisted_drivers()?)),
+        },
+        Technique {
+            name: "object_blacklist",
+            category: Category::Debugger,
+            weight: 25,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::MISC],
+            check: || Ok(join_blacklisted(environment::find_blacklisted_objects())),
+        },
+        Technique {
+            name: "desktop_anomaly",
+            category: Category::Environment,
+            weight: 10,
+            severity: Severity::Info,
+            false_positive: 40,
+            tags: &[
+                taxonomy::USER_ACTIVITY_CHECKS,
+                taxonomy::EVASION_UI_ARTIFACTS,
+            ],
+            check: || Ok(environment::check_desktop_anomaly()?.map(|info| format!("{:?}", info))),
+        },
+        Technique {
+            name: "remote_session",
+            category: Category::Environment,
+            weight: 5,
+            severity: Severity::Info,
+            false_positive: 60,
+            tags: &[
+                taxonomy::SYSTEM_CHECKS,
+                taxonomy::EVASION_GENERIC_OS_QUERIES,
+            ],
+            check: || Ok(environment::check_remote_session()?.map(|info| format!("{:?}", info))),
+        },
+        Technique {
+            name: "console_ownership",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::EVASION_PROCESSES],
+            check: || {
+                Ok(environment::check_console_ownership()?
+                    .map(|ownership| format!("{:?}", ownership)))
+            },
+        },
+        Technique {
+            name: "wine",
+            category: Category::Environment,
+            weight: 10,
+            severity: Severity::Info,
+            false_positive: 20,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_OS_FEATURES],
+            check: || Ok(environment::check_wine()),
+        },
+        Technique {
+            name: "driver_signing",
+            category: Category::Environment,
+            weight: 20,
+            severity: Severity::Suspicious,
+            false_positive: 30,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_OS_FEATURES],
+            check: environment::check_driver_signing,
+        },
+        Technique {
+            name: "secure_boot",
+            category: Category::Environment,
+            weight: 5,
+            severity: Severity::Info,
+            false_positive: 60,
+            tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_FIRMWARE_TABLES],
+            check: || {
+                Ok(flag(
+                    environment::check_secure_boot_disabled()?,
+                    "secure boot disabled",
+                ))
+            },
+        },
+        Technique {
+            name: "inline_hooks",
+            category: Category::Tampering,
+            weight: 20,
+            severity: Severity::Suspicious,
+            false_positive: 25,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let detours = hook::scan_system_inline_hooks()?;
+                let functions: Vec<String> = detours
+                    .iter()
+                    .map(|detour| format!("{}!{}", detour.module, detour.function))
+                    .collect();
+                Ok((!functions.is_empty()).then(|| functions.join("; ")))
+            },
+        },
+        Technique {
+            name: "eat_hooks",
+            category: Category::Tampering,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let hooks = hook::scan_system_eat_hooks()?;
+                let functions: Vec<String> = hooks
+                    .iter()
+                    .map(|hook| format!("{}!{}", hook.module, hook.function))
+                    .collect();
+                Ok((!functions.is_empty()).then(|| functions.join("; ")))
+            },
+        },
+        Technique {
+            name: "trampolines",
+            category: Category::Tampering,
+            weight: 20,
+            severity: Severity::Suspicious,
+            false_positive: 25,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let trampolines: Vec<String> = hook::scan_trampolines()
+                    .iter()
+                    .map(|t| format!("{}!{} ({:?})", t.module, t.function, t.style))
+                    .collect();
+                Ok((!trampolines.is_empty()).then(|| trampolines.join("; ")))
+            },
+        },
+        Technique {
+            name: "self_integrity",
+            category: Category::Tampering,
+            weight: 20,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: check_self_integrity,
+        },
+        Technique {
+            name: "timing_api_hooks",
+            category: Category::TimeVirtualization,
+            weight: 20,
+            severity: Severity::Suspicious,
+            false_positive: 25,
+            tags: &[taxonomy::TIME_BASED_EVASION, taxonomy::TIMING],
+            check: || {
+                let evidence = timing::check_timing_api_hooks()?;
+                Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+            },
+        },
+        Technique {
+            name: "time_virtualization",
+            category: Category::TimeVirtualization,
+            weight: 30,
+            severity: Severity::Suspicious,
+            false_positive: 30,
+            tags: &[taxonomy::TIME_BASED_EVASION, taxonomy::EVASION_TIMING],
+            check: || {
+                let evidence = timing::check_time_virtualization(50)?;
+                Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+            },
+        },
+        Technique {
+            name: "yield_starvation",
+            category: Category::Debugger,
+            weight: 3,
+            severity: Severity::Info,
+            false_positive: 50,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::MISC],
+            check: || {
+                let stats = timing::check_yield_starvation(8)?;
+                Ok(stats.is_starved().then(|| {
+                    format!(
+                        "NtYieldExecution yielded {}/{}",
+                        stats.yielded, stats.rounds
+                    )
+                }))
+            },
+        },
+        Technique {
+            name: "debug_string_latency",
+            category: Category::Debugger,
+            weight: 5,
+            severity: Severity::Info,
+            false_positive: 40,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::TIMING],
+            check: || {
+                let latency = timing::debug_string_latency(32);
+                Ok(latency.is_delivered().then(|| {
+                    format!(
+                        "OutputDebugStringW took {} cycles, payload shift {} cycles",
+                        latency.short,
+                        latency.shift()
+                    )
+                }))
+            },
+        },
+        Technique {
+            name: "syscall_stubs",
+            category: Category::Tampering,
+            weight: 25,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let functions: Vec<String> = syscall::verify_crate_stubs()?
+                    .into_iter()
+                    .map(|anomaly| anomaly.function)
+                    .collect();
+                Ok((!functions.is_empty()).then(|| functions.join("; ")))
+            },
+        },
+        Technique {
+            name: "iat_hooks",
+            category: Category::Tampering,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let hooks = hook::scan_iat_hooks()?;
+                let functions: Vec<String> = hooks
+                    .iter()
+                    .map(|hook| format!("{}!{}", hook.module, hook.function))
+                    .collect();
+                Ok((!functions.is_empty()).then(|| functions.join("; ")))
+            },
+        },
+        Technique {
+            name: "iat_checksum",
+            category: Category::Tampering,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 15,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let functions: Vec<String> = hook::verify_iat_baseline()?
+                    .iter()
+                    .map(|entry| format!("{}!{}", entry.module, entry.function))
+                    .collect();
+                Ok((!functions.is_empty()).then(|| functions.join("; ")))
+            },
+        },
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    techniques.push(Technique {
+        name: "nt_close_syscall",
+        category: Category::Debugger,
+        weight: 10,
+        severity: Severity::Confirmed,
+        false_positive: 5,
+        tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::OBJECT_HANDLES],
+        check: || {
+            Ok(flag(
+                exception::check_invalid_handle(CloseRoute::Syscall)?,
+                "NtClose(invalid) syscall",
+            ))
+        },
+    });
+
+    #[cfg(target_arch = "x86")]
+    techniques.push(Technique {
+        name: "mov_ss",
+        category: Category::Debugger,
+        weight: 10,
+        severity: Severity::Suspicious,
+        false_positive: 15,
+        tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::ASSEMBLY],
+        check: || Ok(flag(exception::check_mov_ss()?, "mov ss single step")),
+    });
+
+    #[cfg(target_arch = "x86")]
+    techniques.push(Technique {
+        name: "wow64_selectors",
+        category: Category::Tampering,
+        weight: 15,
+        severity: Severity::Suspicious,
+        false_positive: 15,
+        tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::ASSEMBLY],
+        check: || {
+            let evidence = crate::wow64::check_selectors()?;
+            Ok((!evidence.is_empty()).then(|| evidence.join("; ")))
+        },
+    });
+
+    #[cfg(feature = "wmi")]
+    techniques.push(Technique {
+        name: "wmi_environment",
+        category: Category::Environment,
+        weight: 15,
+        severity: Severity::Suspicious,
+        false_positive: 30,
+        tags: &[taxonomy::SYSTEM_CHECKS, taxonomy::EVASION_WMI],
+        check: || Ok(join_artifacts(&crate::wmi::check_wmi_environment()?)),
+    });
+
+    #[cfg(feature = "authenticode")]
+    techniques.push(Technique {
+        name: "unsigned_modules",
+        category: Category::Tampering,
+        weight: 15,
+        severity: Severity::Info,
+        false_positive: 40,
+        tags: &[taxonomy::PROCESS_INJECTION],
+        check: || {
+            let modules: Vec<String> = crate::authenticode::check_loaded_modules()?
+                .iter()
+                .map(|module| format!("{:?} ({:?})", module.path, module.status))
+                .collect();
+            Ok((!modules.is_empty()).then(|| modules.join("; ")))
+        },
+    });
+
+    techniques
+}
+
+/// 使用全局密钥校验自身代码，没有设置密钥时不检测
+#[cfg(any(windows, target_os = "linux"))]
+fn check_self_integrity() -> Result<Option<String>> {
+    let Some(report) = integrity::verify_with_global_key()? else {
+        return Ok(None);
+    };
+    Ok(match (report.memory, report.disk) {
+        (true, true) => None,
+        (false, true) => Some("executable sections patched in memory".to_string()),
+        (true, false) => Some("executable file patched on disk".to_string()),
+        (false, false) => Some("executable patched in memory and on disk".to_string()),
+    })
+}
+
+/// 当前平台的检测技术(Linux)
+#[cfg(target_os = "linux")]
+fn platform_techniques() -> Vec<Technique> {
+    vec![
+        Technique {
+            name: "tracer_pid",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION],
+            check: || {
+                let tracer = linux::tracer_pid()?;
+                Ok(linux::is_foreign_tracer(tracer).then(|| format!("TracerPid: {}", tracer)))
+            },
+        },
+        Technique {
+            name: "traced_threads",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::DEBUGGER_EVASION],
+            check: || {
+                let threads = linux::find_traced_threads()?;
+                Ok((!threads.is_empty()).then(|| format!("traced threads: {:?}", threads)))
+            },
+        },
+        Technique {
+            name: "software_breakpoints",
+            category: Category::Debugger,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 15,
+            tags: &[taxonomy::DEBUGGER_EVASION, taxonomy::PROCESS_MEMORY],
+            check: || {
+                let breakpoints = linux::find_text_breakpoints(16)?;
+                Ok((!breakpoints.is_empty()).then(|| format!("int3 at {:#x?}", breakpoints)))
+            },
+        },
+        Technique {
+            name: "ld_preload",
+            category: Category::Tampering,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::DYNAMIC_LINKER_HIJACKING],
+            check: || {
+                let mut libraries: Vec<String> = linux::preload_env()
+                    .into_iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                libraries.extend(linux::read_preload_file(linux::PRELOAD_FILE)?);
+                Ok((!libraries.is_empty()).then(|| libraries.join(", ")))
+            },
+        },
+        Technique {
+            name: "injected_mappings",
+            category: Category::Tampering,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::PROCESS_INJECTION],
+            check: || {
+                let regions = linux::find_injected_mappings()?;
+                Ok((!regions.is_empty()).then(|| {
+                    regions
+                        .into_iter()
+                        .map(|region| format!("{:#x} {}", region.start, region.path))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                }))
+            },
+        },
+        Technique {
+            name: "self_integrity",
+            category: Category::Tampering,
+            weight: 20,
+            severity: Severity::Confirmed,
+            false_positive: 5,
+            tags: &[taxonomy::IMPAIR_DEFENSES, taxonomy::PROCESS_MEMORY],
+            check: check_self_integrity,
+        },
+    ]
+}
+
+/// 当前平台的检测技术(macOS)
+#[cfg(target_os = "macos")]
+fn platform_techniques() -> Vec<Technique> {
+    vec![
+        Technique {
+            name: "p_traced",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 2,
+            tags: &[taxonomy::DEBUGGER_EVASION],
+            check: || Ok(macos::is_traced()?.then(|| "P_TRACED".to_string())),
+        },
+        Technique {
+            name: "debugger_parent",
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::DEBUGGER_EVASION],
+            check: || Ok(macos::check_debugger_parent()?.map(|path| format!("parent: {}", path))),
+        },
+        Technique {
+            name: "dyld_insert_libraries",
+            category: Category::Tampering,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::DYNAMIC_LINKER_HIJACKING],
+            check: || {
+                let variables: Vec<String> = macos::dyld_env()
+                    .into_iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                Ok((!variables.is_empty()).then(|| variables.join(", ")))
+            },
+        },
+        Technique {
+            name: "injected_images",
+            category: Category::Tampering,
+            weight: 15,
+            severity: Severity::Suspicious,
+            false_positive: 20,
+            tags: &[taxonomy::PROCESS_INJECTION],
+            check: || {
+                let images = macos::find_injected_images();
+                Ok((!images.is_empty()).then(|| {
+                    images
+                        .into_iter()
+                        .map(|image| image.name)
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                }))
+            },
+        },
+    ]
+}
+
+/// 当前平台没有内置的检测技术
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+fn platform_techniques() -> Vec<Technique> {
+    Vec::new()
+}
+
+/// 将虚拟机痕迹列表合并为证据字符串，列表为空时返回None
+#[cfg(windows)]
+fn join_artifacts(artifacts: &[vm::VmArtifact]) -> Option<String> {
+    if artifacts.is_empty() {
+        return None;
+    }
+
+    let evidence: Vec<String> = artifacts
+        .iter()
+        .map(|artifact| format!("{:?}: {}", artifact.hypervisor, artifact.evidence))
+        .collect();
+    Some(evidence.join("; "))
+}
+
+/// 命中黑名单的窗口、驱动或者内核对象转换为检测结果
+#[cfg(windows)]
+fn join_blacklisted(artifacts: Vec<environment::BlacklistedArtifact>) -> Option<String> {
+    (!artifacts.is_empty()).then(|| {
+        artifacts
+            .into_iter()
+            .map(|artifact| artifact.name)
+            .collect::<Vec<String>>()
+            .join("; ")
+    })
+}
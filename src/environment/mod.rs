@@ -0,0 +1,1135 @@
+use crate::logging::{debug, warn};
+use crate::{
+    imports::{NtQuerySystemInformation, OpenProcess},
+    ipt::loaded_drivers,
+    nt_query::get_parent_process_id,
+    obf,
+    obfstr::ObfStr,
+    resolve,
+    signature::{self, SignatureKind},
+    util::{
+        enumerate_processes, is_device_object_exists, is_registry_key_exists, BeingDebug,
+        KUSER_SHARED_DATA,
+    },
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{c_char, c_void, CStr},
+    mem::{size_of, size_of_val},
+    path::Path,
+    ptr::{addr_of_mut, null_mut},
+};
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Wdk::System::SystemInformation::SYSTEM_INFORMATION_CLASS,
+    Win32::{
+        Foundation::{CloseHandle, BOOL, HANDLE, HWND, LPARAM, LUID},
+        Graphics::Gdi::{
+            EnumDisplayDevicesW, DISPLAY_DEVICEW, DISPLAY_DEVICE_ATTACHED_TO_DESKTOP,
+            DISPLAY_DEVICE_MIRRORING_DRIVER,
+        },
+        Security::{
+            GetTokenInformation, IsWellKnownSid, LookupPrivilegeValueW, TokenElevation,
+            TokenGroups, TokenPrivileges, TokenUser, WinLocalServiceSid, WinLocalSystemSid,
+            WinNetworkServiceSid, WinServiceSid, LUID_AND_ATTRIBUTES, SE_DEBUG_NAME,
+            SE_PRIVILEGE_ENABLED, SID_AND_ATTRIBUTES, TOKEN_ELEVATION, TOKEN_GROUPS,
+            TOKEN_INFORMATION_CLASS, TOKEN_PRIVILEGES, TOKEN_QUERY, TOKEN_USER,
+        },
+        System::{
+            Console::GetConsoleWindow,
+            Registry::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE},
+            RemoteDesktop::{
+                ProcessIdToSessionId, WTSClientName, WTSClientProtocolType, WTSFreeMemory,
+                WTSGetActiveConsoleSessionId, WTSQuerySessionInformationW,
+                WTS_CURRENT_SERVER_HANDLE, WTS_CURRENT_SESSION, WTS_INFO_CLASS,
+            },
+            StationsAndDesktops::{
+                CloseDesktop, GetProcessWindowStation, GetThreadDesktop, GetUserObjectInformationW,
+                OpenInputDesktop, DESKTOP_CONTROL_FLAGS, DESKTOP_READOBJECTS, UOI_FLAGS, UOI_NAME,
+                USEROBJECTFLAGS,
+            },
+            Threading::{
+                GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId, OpenProcessToken,
+                PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, GetClassNameW, GetSystemMetrics, GetWindowTextW, GetWindowThreadProcessId,
+            SM_REMOTECONTROL, SM_REMOTESESSION,
+        },
+    },
+};
+
+/// 检查当前进程的环境变量中是否存在分析工具留下的标记
+///
+/// 环境变量名与特征列表中`[env]`段落匹配，内置列表包括:
+///
+/// - `_NO_DEBUG_HEAP`: 调试器用来关闭调试堆的变量
+/// - `COR_ENABLE_PROFILING`/`CORECLR_ENABLE_PROFILING`: .NET profiler 注入
+/// - `RUNNING_UNDER_TEAMS` 一类: 注入器/宿主留下的标记变量
+///
+/// # 返回值
+///
+/// - `Some(name)`: 命中的环境变量名
+/// - `None`: 未发现可疑环境变量
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(name) = check_env_vars() {
+///     println!("suspicious env var: {}", name);
+/// }
+/// ```
+pub fn check_env_vars() -> Option<String> {
+    for (name, value) in env::vars_os() {
+        let name = name.to_string_lossy();
+        if let Some(pattern) = signature::matches(SignatureKind::Env, &name) {
+            debug!(
+                "suspicious env var ==> {}={:?}; pattern: {}",
+                name, value, pattern
+            );
+            return Some(name.into_owned());
+        }
+    }
+
+    None
+}
+
+/// 检查命令行是否被调试器改写
+///
+/// 正常启动时argv[0]的文件名与当前映像文件名一致，
+/// 部分调试器启动进程时会改写argv[0]或者插入自己的参数
+///
+/// # 返回值
+///
+/// - `Some(argv0)`: argv[0]与映像文件名不一致，返回argv[0]
+/// - `None`: 命令行正常，或者无法获取映像路径
+pub fn check_command_line() -> Option<String> {
+    let argv0 = env::args_os().next()?;
+    let image = env::current_exe().ok()?;
+
+    let argv0_stem = Path::new(&argv0)
+        .file_stem()?
+        .to_string_lossy()
+        .to_lowercase();
+    let image_stem = image.file_stem()?.to_string_lossy().to_lowercase();
+
+    debug!("argv[0] ==> {:?}; image ==> {:?}", argv0, image);
+
+    if argv0_stem != image_stem {
+        return Some(argv0.to_string_lossy().into_owned());
+    }
+
+    None
+}
+
+/// 综合检查环境变量与命令行
+///
+/// # 返回值
+///
+/// - `Some(marker)`: 命中的环境变量名或被改写的argv[0]
+/// - `None`: 未发现异常
+pub fn check_environment_anomaly() -> Option<String> {
+    check_env_vars().or_else(check_command_line)
+}
+
+/// 进程令牌中SeDebugPrivilege的审计结果
+///
+/// - `self_enabled`: 当前进程令牌是否启用了SeDebugPrivilege
+/// - `self_elevated`: 当前进程是否以管理员权限(提升)运行
+/// - `parent_enabled`: 父进程令牌是否启用了SeDebugPrivilege，无法打开父进程时为None
+/// - `parent_service`: 父进程是否以LocalSystem/LocalService/NetworkService运行或者是服务进程，
+///   无法查询父进程令牌时为None
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugPrivilegeAudit {
+    pub self_enabled: bool,
+    pub self_elevated: bool,
+    pub parent_enabled: Option<bool>,
+    pub parent_service: Option<bool>,
+}
+
+impl BeingDebug for DebugPrivilegeAudit {
+    /// 未提升的普通程序启用了SeDebugPrivilege，说明令牌继承自调试器/注入器；
+    /// 未提升的程序的父进程是启用了SeDebugPrivilege的普通用户进程同样可疑。
+    /// 服务与系统账户默认启用SeDebugPrivilege，由它们启动的进程只记录父进程的状态
+    fn is_being_debug(&self) -> bool {
+        !self.self_elevated
+            && (self.self_enabled
+                || (self.parent_enabled == Some(true) && self.parent_service == Some(false)))
+    }
+}
+
+/// 查询指定进程令牌是否启用了SeDebugPrivilege
+///
+/// # 参数
+///
+/// - `hprocess`: 进程句柄，需要有PROCESS_QUERY_LIMITED_INFORMATION权限
+///
+/// # 返回值
+///
+/// - `Err`: OpenProcessToken/GetTokenInformation/LookupPrivilegeValueW调用失败
+/// - `Ok(true)`: SeDebugPrivilege已启用
+/// - `Ok(false)`: SeDebugPrivilege未启用或不存在
+pub fn is_debug_privilege_enabled(hprocess: HANDLE) -> Result<bool> {
+    let mut htoken: HANDLE = Default::default();
+    unsafe { OpenProcessToken(hprocess, TOKEN_QUERY, &mut htoken) }?;
+
+    let result = query_debug_privilege(htoken);
+    let _ = unsafe { CloseHandle(htoken) };
+    result
+}
+
+/// 查询指定进程是否以系统账户或者服务身份运行
+///
+/// 令牌用户为LocalSystem/LocalService/NetworkService，或者令牌组中包含SERVICE(S-1-5-6)时认为是服务
+///
+/// # 参数
+///
+/// - `hprocess`: 进程句柄，需要有PROCESS_QUERY_LIMITED_INFORMATION权限
+///
+/// # 返回值
+///
+/// - `Err`: OpenProcessToken/GetTokenInformation调用失败
+/// - `Ok(true)`: 系统账户或者服务进程
+/// - `Ok(false)`: 普通用户进程
+pub fn is_service_process(hprocess: HANDLE) -> Result<bool> {
+    let mut htoken: HANDLE = Default::default();
+    unsafe { OpenProcessToken(hprocess, TOKEN_QUERY, &mut htoken) }?;
+
+    let result = query_service_account(htoken);
+    let _ = unsafe { CloseHandle(htoken) };
+    result
+}
+
+/// 读取变长的令牌信息
+fn query_token_information(htoken: HANDLE, class: TOKEN_INFORMATION_CLASS) -> Result<Vec<u8>> {
+    // 第一次调用获取所需缓冲区大小
+    let mut return_length: u32 = 0;
+    let _ = unsafe { GetTokenInformation(htoken, class, None, 0, &mut return_length) };
+
+    let mut buffer: Vec<u8> = vec![0; return_length as usize];
+    unsafe {
+        GetTokenInformation(
+            htoken,
+            class,
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            return_length,
+            &mut return_length,
+        )
+    }?;
+
+    Ok(buffer)
+}
+
+fn query_service_account(htoken: HANDLE) -> Result<bool> {
+    let buffer = query_token_information(htoken, TokenUser)?;
+    let user: &TOKEN_USER = unsafe { &*(buffer.as_ptr() as *const TOKEN_USER) };
+    let system_account = [WinLocalSystemSid, WinLocalServiceSid, WinNetworkServiceSid]
+        .into_iter()
+        .any(|kind| unsafe { IsWellKnownSid(user.User.Sid, kind) }.as_bool());
+    if system_account {
+        return Ok(true);
+    }
+
+    let buffer = query_token_information(htoken, TokenGroups)?;
+    let groups: &TOKEN_GROUPS = unsafe { &*(buffer.as_ptr() as *const TOKEN_GROUPS) };
+    let entries: &[SID_AND_ATTRIBUTES] =
+        unsafe { std::slice::from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize) };
+
+    Ok(entries
+        .iter()
+        .any(|entry| unsafe { IsWellKnownSid(entry.Sid, WinServiceSid) }.as_bool()))
+}
+
+fn query_debug_privilege(htoken: HANDLE) -> Result<bool> {
+    let mut debug_luid: LUID = Default::default();
+    unsafe { LookupPrivilegeValueW(None, SE_DEBUG_NAME, &mut debug_luid) }?;
+
+    let buffer = query_token_information(htoken, TokenPrivileges)?;
+    let privileges: &TOKEN_PRIVILEGES = unsafe { &*(buffer.as_ptr() as *const TOKEN_PRIVILEGES) };
+    let entries: &[LUID_AND_ATTRIBUTES] = unsafe {
+        std::slice::from_raw_parts(
+            privileges.Privileges.as_ptr(),
+            privileges.PrivilegeCount as usize,
+        )
+    };
+
+    Ok(entries.iter().any(|entry| {
+        entry.Luid.LowPart == debug_luid.LowPart
+            && entry.Luid.HighPart == debug_luid.HighPart
+            && entry.Attributes.0 & SE_PRIVILEGE_ENABLED.0 != 0
+    }))
+}
+
+/// 判断当前进程是否以提升权限运行
+///
+/// # 返回值
+///
+/// - `Err`: OpenProcessToken/GetTokenInformation调用失败
+/// - `Ok(true)`: 已提升
+/// - `Ok(false)`: 未提升
+pub fn is_process_elevated() -> Result<bool> {
+    let mut htoken: HANDLE = Default::default();
+    unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut htoken) }?;
+
+    let mut elevation = TOKEN_ELEVATION::default();
+    let mut return_length: u32 = 0;
+    let result = unsafe {
+        GetTokenInformation(
+            htoken,
+            TokenElevation,
+            Some(addr_of_mut!(elevation).cast()),
+            size_of::<TOKEN_ELEVATION>() as u32,
+            &mut return_length,
+        )
+    };
+    let _ = unsafe { CloseHandle(htoken) };
+    result?;
+
+    Ok(elevation.TokenIsElevated != 0)
+}
+
+/// 审计当前进程与父进程令牌中的SeDebugPrivilege
+///
+/// # 返回值
+///
+/// - `Err`: 查询当前进程令牌失败
+/// - `Ok(audit)`: 审计结果，可以通过`is_being_debug`判断是否可疑
+///
+/// # 示例
+///
+/// ```ignore
+/// let audit = audit_debug_privilege().unwrap();
+/// if audit.is_being_debug() {
+///     println!("inherited a debugger token");
+/// }
+/// ```
+pub fn audit_debug_privilege() -> Result<DebugPrivilegeAudit> {
+    let hprocess: HANDLE = unsafe { GetCurrentProcess() };
+    let mut audit = DebugPrivilegeAudit {
+        self_enabled: is_debug_privilege_enabled(hprocess)?,
+        self_elevated: is_process_elevated()?,
+        parent_enabled: None,
+        parent_service: None,
+    };
+
+    match get_parent_process_id(hprocess)
+        .and_then(|pid| Ok(unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }?))
+    {
+        Ok(hparent) => {
+            audit.parent_enabled = is_debug_privilege_enabled(hparent).ok();
+            audit.parent_service = is_service_process(hparent).ok();
+            let _ = unsafe { CloseHandle(hparent) };
+        }
+        Err(error) => warn!("open parent process failed; {:?}", error),
+    }
+
+    debug!("debug privilege audit ==> {:?}", audit);
+
+    Ok(audit)
+}
+
+/// 窗口站可见标志，只有交互式窗口站(WinSta0)才会设置
+const WSF_VISIBLE: u32 = 0x0001;
+
+/// 当前线程所在的窗口站与桌面信息
+///
+/// - `window_station`: 进程窗口站名称，正常交互式会话为`WinSta0`
+/// - `interactive`: 窗口站是否可见(可交互)
+/// - `desktop`: 当前线程所在桌面名称，正常为`Default`
+/// - `input_desktop`: 当前接收用户输入的桌面名称，无法打开时为None
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DesktopInfo {
+    pub window_station: String,
+    pub interactive: bool,
+    pub desktop: String,
+    pub input_desktop: Option<String>,
+}
+
+impl BeingDebug for DesktopInfo {
+    fn is_being_debug(&self) -> bool {
+        !self.interactive
+            || !self.window_station.eq_ignore_ascii_case("WinSta0")
+            || self
+                .input_desktop
+                .as_ref()
+                .is_some_and(|input| !input.eq_ignore_ascii_case(&self.desktop))
+    }
+}
+
+/// 获取用户对象(窗口站/桌面)的名称
+fn get_user_object_name(hobject: HANDLE) -> Result<String> {
+    let mut buffer: [u16; 256] = [0; 256];
+    let mut needed: u32 = 0;
+    unsafe {
+        GetUserObjectInformationW(
+            hobject,
+            UOI_NAME,
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            size_of_val(&buffer) as u32,
+            Some(&mut needed),
+        )
+    }?;
+
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    Ok(String::from_utf16_lossy(&buffer[..len]))
+}
+
+/// 获取当前进程窗口站与线程桌面信息
+///
+/// # 返回值
+///
+/// - `Err`: GetProcessWindowStation/GetThreadDesktop/GetUserObjectInformationW调用失败
+/// - `Ok(info)`: 窗口站与桌面信息
+pub fn get_desktop_info() -> Result<DesktopInfo> {
+    let hwinsta = unsafe { GetProcessWindowStation() }?;
+    let hdesk = unsafe { GetThreadDesktop(GetCurrentThreadId()) }?;
+
+    let mut flags = USEROBJECTFLAGS::default();
+    unsafe {
+        GetUserObjectInformationW(
+            HANDLE(hwinsta.0),
+            UOI_FLAGS,
+            Some(addr_of_mut!(flags).cast()),
+            size_of::<USEROBJECTFLAGS>() as u32,
+            None,
+        )
+    }?;
+
+    let mut info = DesktopInfo {
+        window_station: get_user_object_name(HANDLE(hwinsta.0))?,
+        interactive: flags.dwFlags & WSF_VISIBLE != 0,
+        desktop: get_user_object_name(HANDLE(hdesk.0))?,
+        input_desktop: None,
+    };
+
+    match unsafe { OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS) } {
+        Ok(hinput) => {
+            info.input_desktop = get_user_object_name(HANDLE(hinput.0)).ok();
+            let _ = unsafe { CloseDesktop(hinput) };
+        }
+        Err(error) => warn!("OpenInputDesktop failed; {:?}", error),
+    }
+
+    debug!("desktop info ==> {:?}", info);
+
+    Ok(info)
+}
+
+/// 检测是否运行在非交互式或新建的窗口站/桌面上
+///
+/// 沙箱和部分调试沙箱会在新建的窗口站/桌面中运行程序
+///
+/// # 返回值
+///
+/// - `Err`: 获取桌面信息失败
+/// - `Ok(Some(info))`: 桌面环境异常，返回桌面信息
+/// - `Ok(None)`: 桌面环境正常
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(info) = check_desktop_anomaly().unwrap() {
+///     println!("running on {}\\{}", info.window_station, info.desktop);
+/// }
+/// ```
+pub fn check_desktop_anomaly() -> Result<Option<DesktopInfo>> {
+    let info = get_desktop_info()?;
+    match info.is_being_debug() {
+        true => Ok(Some(info)),
+        false => Ok(None),
+    }
+}
+
+/// Wine在ntdll.dll中额外导出的函数，Windows原生ntdll中不存在
+type WineGetVersion = unsafe extern "C" fn() -> *const c_char;
+
+/// 获取Wine版本号
+///
+/// 通过ntdll.dll导出的wine_get_version函数获取，原生Windows中不存在该导出
+///
+/// # 返回值
+///
+/// - `Some(version)`: 运行在Wine中，返回Wine版本号
+/// - `None`: 未找到wine_get_version导出
+pub fn get_wine_version() -> Option<String> {
+    let func = resolve!("ntdll.dll", "wine_get_version")?;
+    let wine_get_version: WineGetVersion = unsafe { resolve::function(func) };
+
+    let version = unsafe { wine_get_version() };
+    if version.is_null() {
+        return Some(String::new());
+    }
+
+    Some(
+        unsafe { CStr::from_ptr(version) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// 检查KUSER_SHARED_DATA中原生系统一定会填充的字段
+///
+/// Wine只模拟了部分KUSER_SHARED_DATA，TickCountMultiplier与NtMajorVersion
+/// 在部分版本中为0
+fn is_shared_data_incomplete() -> bool {
+    let tick_count_multiplier =
+        unsafe { std::ptr::read_volatile((KUSER_SHARED_DATA + 0x4) as *const u32) };
+    let nt_major_version =
+        unsafe { std::ptr::read_volatile((KUSER_SHARED_DATA + 0x26c) as *const u32) };
+
+    debug!(
+        "KUSER_SHARED_DATA ==> TickCountMultiplier: {:#x}; NtMajorVersion: {}",
+        tick_count_multiplier, nt_major_version
+    );
+
+    tick_count_multiplier == 0 || nt_major_version == 0
+}
+
+/// 检测是否运行在Wine/CrossOver环境中
+///
+/// Wine中的反调试语义与原生Windows不同，分析人员也经常使用基于Wine的工具
+///
+/// - ntdll.dll导出wine_get_version
+/// - 存在`HKCU\Software\Wine`或`HKLM\Software\Wine`注册表项
+/// - KUSER_SHARED_DATA关键字段缺失
+///
+/// # 返回值
+///
+/// - `Some(marker)`: 命中的Wine特征描述
+/// - `None`: 未发现Wine特征
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(marker) = check_wine() {
+///     println!("running under wine: {}", marker);
+/// }
+/// ```
+pub fn check_wine() -> Option<String> {
+    if let Some(version) = get_wine_version() {
+        debug!("wine version ==> {}", version);
+        return Some(format!("wine_get_version: {}", version));
+    }
+
+    for (root, name) in [(HKEY_CURRENT_USER, "HKCU"), (HKEY_LOCAL_MACHINE, "HKLM")] {
+        if is_registry_key_exists(root, "Software\\Wine") {
+            return Some(format!("{}\\Software\\Wine", name));
+        }
+    }
+
+    if is_shared_data_incomplete() {
+        return Some("KUSER_SHARED_DATA incomplete".to_string());
+    }
+
+    None
+}
+
+/// 远程会话信息
+///
+/// - `remote_session`: GetSystemMetrics(SM_REMOTESESSION)，当前是否为远程会话
+/// - `remote_control`: GetSystemMetrics(SM_REMOTECONTROL)，当前会话是否被远程控制
+/// - `protocol_type`: WTSClientProtocolType，0为控制台，2为RDP
+/// - `client_name`: 远程客户端名称，控制台会话为空
+/// - `console_session`: 当前进程是否运行在物理控制台会话中
+/// - `virtual_display`: 连接在桌面上的虚拟机显示适配器，说明会话通过虚拟机控制台查看
+/// - `mirror_drivers`: 已加载的镜像显示驱动，VNC/远程协助/录屏工具用它们截取屏幕
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteSessionInfo {
+    pub remote_session: bool,
+    pub remote_control: bool,
+    pub protocol_type: u16,
+    pub client_name: String,
+    pub console_session: bool,
+    pub virtual_display: Option<String>,
+    pub mirror_drivers: Vec<String>,
+}
+
+impl BeingDebug for RemoteSessionInfo {
+    /// 远程会话、被远程控制、不在物理控制台会话中，或者屏幕通过虚拟机控制台与镜像驱动被查看
+    fn is_being_debug(&self) -> bool {
+        self.remote_session
+            || self.remote_control
+            || self.protocol_type != 0
+            || !self.console_session
+            || self.virtual_display.is_some()
+            || !self.mirror_drivers.is_empty()
+    }
+}
+
+/// 虚拟机控制台使用的显示适配器名称片段(不区分大小写)
+const VIRTUAL_DISPLAYS: [ObfStr; 6] = [
+    obf!("Hyper-V Video"),
+    obf!("VirtualBox Graphics"),
+    obf!("VMware SVGA"),
+    obf!("QXL"),
+    obf!("Red Hat VirtIO GPU"),
+    obf!("Parallels Display"),
+];
+
+/// 显示设备
+///
+/// - `name`: 适配器描述，例如`Microsoft Hyper-V Video`
+/// - `attached`: 是否连接在桌面上
+/// - `mirroring`: 是否为镜像驱动
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayDevice {
+    pub name: String,
+    pub attached: bool,
+    pub mirroring: bool,
+}
+
+/// 枚举系统中的显示设备
+pub fn get_display_devices() -> Vec<DisplayDevice> {
+    let mut devices: Vec<DisplayDevice> = Vec::new();
+    for index in 0.. {
+        let mut device = DISPLAY_DEVICEW {
+            cb: size_of::<DISPLAY_DEVICEW>() as u32,
+            ..Default::default()
+        };
+        if !unsafe { EnumDisplayDevicesW(PCWSTR::null(), index, &mut device, 0) }.as_bool() {
+            break;
+        }
+        let length = device
+            .DeviceString
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(device.DeviceString.len());
+        devices.push(DisplayDevice {
+            name: String::from_utf16_lossy(&device.DeviceString[..length]),
+            attached: device.StateFlags & DISPLAY_DEVICE_ATTACHED_TO_DESKTOP != 0,
+            mirroring: device.StateFlags & DISPLAY_DEVICE_MIRRORING_DRIVER != 0,
+        });
+    }
+    devices
+}
+
+/// 显示适配器是否属于虚拟机控制台
+fn is_virtual_display(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    VIRTUAL_DISPLAYS
+        .iter()
+        .any(|display| name.contains(&display.decrypt().to_ascii_lowercase()))
+}
+
+/// 查询当前会话的WTS信息，返回原始缓冲区内容
+fn query_session_information(info_class: WTS_INFO_CLASS) -> Result<Vec<u8>> {
+    let mut buffer: PWSTR = PWSTR::null();
+    let mut bytes: u32 = 0;
+    unsafe {
+        WTSQuerySessionInformationW(
+            WTS_CURRENT_SERVER_HANDLE,
+            WTS_CURRENT_SESSION,
+            info_class,
+            &mut buffer,
+            &mut bytes,
+        )
+    }?;
+
+    let data =
+        unsafe { std::slice::from_raw_parts(buffer.0 as *const u8, bytes as usize) }.to_vec();
+    unsafe { WTSFreeMemory(buffer.0 as *mut c_void) };
+
+    Ok(data)
+}
+
+/// 获取当前进程的远程会话信息
+///
+/// # 返回值
+///
+/// - `Err`: WTSQuerySessionInformationW/ProcessIdToSessionId调用失败
+/// - `Ok(info)`: 远程会话信息
+pub fn get_remote_session_info() -> Result<RemoteSessionInfo> {
+    let protocol = query_session_information(WTSClientProtocolType)?;
+    let client_name = query_session_information(WTSClientName)?;
+    let client_name: Vec<u16> = client_name
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+
+    let mut session_id: u32 = 0;
+    unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session_id) }?;
+    let displays = get_display_devices();
+
+    let info = RemoteSessionInfo {
+        remote_session: unsafe { GetSystemMetrics(SM_REMOTESESSION) } != 0,
+        remote_control: unsafe { GetSystemMetrics(SM_REMOTECONTROL) } != 0,
+        protocol_type: protocol
+            .get(..2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .unwrap_or_default(),
+        client_name: String::from_utf16_lossy(&client_name),
+        console_session: session_id == unsafe { WTSGetActiveConsoleSessionId() },
+        virtual_display: displays
+            .iter()
+            .find(|display| display.attached && is_virtual_display(&display.name))
+            .map(|display| display.name.clone()),
+        mirror_drivers: displays
+            .iter()
+            .filter(|display| display.mirroring)
+            .map(|display| display.name.clone())
+            .collect(),
+    };
+
+    debug!("remote session info ==> {:?}", info);
+
+    Ok(info)
+}
+
+/// 检测是否运行在RDP/远程会话、虚拟机控制台会话或者屏幕被截取的环境中
+///
+/// 分析环境经常通过RDP或虚拟机控制台(Hyper-V增强会话同样基于RDP)访问，
+/// 基本控制台会话则通过虚拟显示适配器呈现；镜像显示驱动说明屏幕内容正在被VNC一类工具截取
+///
+/// # 返回值
+///
+/// - `Err`: 获取会话信息失败
+/// - `Ok(Some(info))`: 运行在远程会话中，返回会话信息
+/// - `Ok(None)`: 运行在本地物理控制台会话中
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(info) = check_remote_session().unwrap() {
+///     println!("remote client: {}", info.client_name);
+/// }
+/// ```
+pub fn check_remote_session() -> Result<Option<RemoteSessionInfo>> {
+    let info = get_remote_session_info()?;
+    match info.is_being_debug() {
+        true => Ok(Some(info)),
+        false => Ok(None),
+    }
+}
+
+/// 正常承载控制台窗口的进程，Windows 7之前的控制台窗口属于csrss
+const CONSOLE_HOSTS: [ObfStr; 4] = [
+    obf!("conhost.exe"),
+    obf!("openconsole.exe"),
+    obf!("windowsterminal.exe"),
+    obf!("csrss.exe"),
+];
+
+/// 控制台的归属
+///
+/// - `owner`: 控制台窗口所属的进程ID与进程名
+/// - `creator`: 创建控制台的进程，即控制台宿主的父进程；控制台在启动时为当前进程创建时取当前进程的父进程
+/// - `creator_pattern`: 创建者命中的黑名单特征
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsoleOwnership {
+    pub owner: (u32, String),
+    pub creator: Option<(u32, String)>,
+    pub creator_pattern: Option<String>,
+}
+
+impl BeingDebug for ConsoleOwnership {
+    /// 控制台窗口不属于控制台宿主，或者控制台由调试器前端创建
+    fn is_being_debug(&self) -> bool {
+        !CONSOLE_HOSTS
+            .iter()
+            .any(|host| host.decrypt().eq_ignore_ascii_case(&self.owner.1))
+            || self.creator_pattern.is_some()
+    }
+}
+
+/// 查询进程的父进程ID
+fn parent_of(pid: u32) -> Result<u32> {
+    let hprocess = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }?;
+    let parent = get_parent_process_id(hprocess);
+    let _ = unsafe { CloseHandle(hprocess) };
+    parent
+}
+
+/// 获取当前控制台的归属
+///
+/// 通过GetConsoleWindow/GetWindowThreadProcessId找到控制台窗口所属的进程，
+/// 再沿控制台宿主(conhost)的父进程找到创建控制台的进程
+///
+/// # 返回值
+///
+/// - `Err`: 枚举进程失败
+/// - `Ok(None)`: 当前进程没有附加控制台
+/// - `Ok(Some(ownership))`: 控制台的归属
+pub fn get_console_ownership() -> Result<Option<ConsoleOwnership>> {
+    let hwnd = unsafe { GetConsoleWindow() };
+    if hwnd.0.is_null() {
+        return Ok(None);
+    }
+    let mut owner_pid: u32 = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, Some(&mut owner_pid)) };
+
+    let names: HashMap<u32, String> = enumerate_processes()?.into_iter().collect();
+    let name_of = |pid: u32| names.get(&pid).cloned().unwrap_or_default();
+    let owner = (owner_pid, name_of(owner_pid));
+
+    // csrss承载的控制台没有宿主进程，无法追溯创建者
+    let creator = match owner.1.eq_ignore_ascii_case("csrss.exe") {
+        true => None,
+        false => parent_of(owner_pid)
+            .and_then(|pid| match pid == unsafe { GetCurrentProcessId() } {
+                true => get_parent_process_id(unsafe { GetCurrentProcess() }),
+                false => Ok(pid),
+            })
+            .map_err(|e| debug!("console creator not found; error: {:?}", e))
+            .ok()
+            .map(|pid| (pid, name_of(pid))),
+    };
+    let creator_pattern = creator
+        .as_ref()
+        .and_then(|(_, name)| signature::matches(SignatureKind::Process, name));
+
+    let ownership = ConsoleOwnership {
+        owner,
+        creator,
+        creator_pattern,
+    };
+    debug!("console ownership ==> {:?}", ownership);
+    Ok(Some(ownership))
+}
+
+/// 检查控制台是否由调试器前端创建或者持有，而不是普通的命令行外壳
+///
+/// 调试器直接启动控制台程序时，系统为被调试进程新建控制台，控制台宿主的父进程就是被调试进程本身，
+/// 此时沿父进程找到的创建者是调试器；部分调试器前端也会用自己的窗口承载控制台
+///
+/// # 返回值
+///
+/// - `Err`: 枚举进程失败
+/// - `Ok(Some(ownership))`: 控制台归属异常
+/// - `Ok(None)`: 控制台归属正常，或者没有附加控制台
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(ownership) = check_console_ownership().unwrap() {
+///     println!("console created by {:?}", ownership.creator);
+/// }
+/// ```
+pub fn check_console_ownership() -> Result<Option<ConsoleOwnership>> {
+    Ok(get_console_ownership()?.filter(|ownership| ownership.is_being_debug()))
+}
+
+/// 正在运行的黑名单进程
+///
+/// - `pid`: 进程ID
+/// - `name`: 进程名
+/// - `pattern`: 命中的特征
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlacklistedProcess {
+    pub pid: u32,
+    pub name: String,
+    pub pattern: String,
+}
+
+/// 枚举系统中的进程，查找调试器、逆向与转储工具
+///
+/// 进程名与特征列表中`[process]`段落匹配
+///
+/// # 返回值
+///
+/// - `Err`: 枚举进程失败
+/// - `Ok(processes)`: 命中黑名单的进程列表
+///
+/// # 示例
+///
+/// ```ignore
+/// for process in find_blacklisted_processes().unwrap() {
+///     println!("{} ({}) is running", process.name, process.pid);
+/// }
+/// ```
+pub fn find_blacklisted_processes() -> Result<Vec<BlacklistedProcess>> {
+    let mut processes: Vec<BlacklistedProcess> = Vec::new();
+    for (pid, name) in enumerate_processes()? {
+        if let Some(pattern) = signature::matches(SignatureKind::Process, &name) {
+            debug!("blacklisted process ==> {} ({})", name, pid);
+            processes.push(BlacklistedProcess { pid, name, pattern });
+        }
+    }
+
+    Ok(processes)
+}
+
+/// 命中黑名单的窗口、驱动或者内核对象
+///
+/// - `name`: 窗口类名与标题、驱动文件名或者对象路径
+/// - `pattern`: 命中的特征
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlacklistedArtifact {
+    pub name: String,
+    pub pattern: String,
+}
+
+/// 顶层窗口的类名与标题
+fn enumerate_windows() -> Vec<(String, String)> {
+    unsafe extern "system" fn collect(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let windows = unsafe { &mut *(lparam.0 as *mut Vec<HWND>) };
+        windows.push(hwnd);
+        BOOL(1)
+    }
+
+    let mut hwnds: Vec<HWND> = Vec::new();
+    let _ = unsafe { EnumWindows(Some(collect), LPARAM(&mut hwnds as *mut Vec<HWND> as isize)) };
+
+    let mut buffer = [0u16; 256];
+    hwnds
+        .into_iter()
+        .map(|hwnd| {
+            let length = unsafe { GetClassNameW(hwnd, &mut buffer) } as usize;
+            let class = String::from_utf16_lossy(&buffer[..length]);
+            let length = unsafe { GetWindowTextW(hwnd, &mut buffer) } as usize;
+            (class, String::from_utf16_lossy(&buffer[..length]))
+        })
+        .collect()
+}
+
+/// 枚举顶层窗口，查找调试器与逆向工具的窗口
+///
+/// 窗口类名与标题分别与特征列表中`[window]`段落匹配
+///
+/// # 返回值
+///
+/// - 命中黑名单的窗口，`name`为`类名: 标题`
+///
+/// # 示例
+///
+/// ```ignore
+/// for window in find_blacklisted_windows() {
+///     println!("{} matches {}", window.name, window.pattern);
+/// }
+/// ```
+pub fn find_blacklisted_windows() -> Vec<BlacklistedArtifact> {
+    let mut windows: Vec<BlacklistedArtifact> = Vec::new();
+    for (class, title) in enumerate_windows() {
+        let pattern =
+            signature::matches(SignatureKind::Window, &class).or_else(|| match title.is_empty() {
+                true => None,
+                false => signature::matches(SignatureKind::Window, &title),
+            });
+        if let Some(pattern) = pattern {
+            debug!("blacklisted window ==> {}: {}", class, title);
+            windows.push(BlacklistedArtifact {
+                name: format!("{}: {}", class, title),
+                pattern,
+            });
+        }
+    }
+
+    windows
+}
+
+/// 枚举已加载的内核驱动，查找调试器隐藏、内存读写一类驱动
+///
+/// 驱动文件名与特征列表中`[driver]`段落匹配
+///
+/// # 返回值
+///
+/// - `Err`: 枚举驱动失败
+/// - `Ok(drivers)`: 命中黑名单的驱动
+pub fn find_blacklisted_drivers() -> Result<Vec<BlacklistedArtifact>> {
+    let mut drivers: Vec<BlacklistedArtifact> = Vec::new();
+    for name in loaded_drivers()? {
+        if let Some(pattern) = signature::matches(SignatureKind::Driver, &name) {
+            debug!("blacklisted driver ==> {}", name);
+            drivers.push(BlacklistedArtifact { name, pattern });
+        }
+    }
+
+    Ok(drivers)
+}
+
+/// 逐个打开特征列表中`[object]`段落列出的设备对象，查找调试工具的驱动
+///
+/// 内核对象无法按名称枚举，只有完整匹配的规则会被探测
+///
+/// # 返回值
+///
+/// - 存在的设备对象
+pub fn find_blacklisted_objects() -> Vec<BlacklistedArtifact> {
+    let objects: Vec<BlacklistedArtifact> = signature::exact_blacklist(SignatureKind::Object)
+        .into_iter()
+        .filter(|path| is_device_object_exists(path))
+        .map(|path| BlacklistedArtifact {
+            name: path.clone(),
+            pattern: path,
+        })
+        .collect();
+    debug!("blacklisted objects ==> {:?}", objects);
+
+    objects
+}
+
+/// SystemCodeIntegrityInformation信息类别
+const SYSTEM_CODE_INTEGRITY_INFORMATION: SYSTEM_INFORMATION_CLASS = SYSTEM_INFORMATION_CLASS(103);
+
+/// SystemSecureBootInformation信息类别
+const SYSTEM_SECURE_BOOT_INFORMATION: SYSTEM_INFORMATION_CLASS = SYSTEM_INFORMATION_CLASS(145);
+
+/// 启用了内核模式代码完整性(驱动签名强制，DSE)
+pub const CODEINTEGRITY_OPTION_ENABLED: u32 = 0x01;
+/// 启用了测试签名模式(`bcdedit /set testsigning on`)
+pub const CODEINTEGRITY_OPTION_TESTSIGN: u32 = 0x02;
+/// 启用了内核调试模式，调试模式下代码完整性不阻止加载未签名驱动
+pub const CODEINTEGRITY_OPTION_DEBUGMODE_ENABLED: u32 = 0x80;
+/// 启用了基于虚拟化的内核代码完整性(HVCI)
+pub const CODEINTEGRITY_OPTION_HVCI_KMCI_ENABLED: u32 = 0x400;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SystemCodeIntegrityInformation {
+    length: u32,
+    options: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SystemSecureBootInformation {
+    enabled: u8,
+    capable: u8,
+}
+
+/// 系统的安全启动与驱动签名强制状态
+///
+/// - `code_integrity_options`: SystemCodeIntegrityInformation返回的`CODEINTEGRITY_OPTION_*`标志
+/// - `secure_boot_capable`: 固件支持安全启动
+/// - `secure_boot_enabled`: 安全启动已开启
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BootIntegrity {
+    pub code_integrity_options: u32,
+    pub secure_boot_capable: bool,
+    pub secure_boot_enabled: bool,
+}
+
+impl BootIntegrity {
+    /// 驱动签名强制已开启
+    pub fn dse_enabled(&self) -> bool {
+        self.code_integrity_options & CODEINTEGRITY_OPTION_ENABLED != 0
+    }
+
+    /// 处于测试签名模式
+    pub fn test_signing(&self) -> bool {
+        self.code_integrity_options & CODEINTEGRITY_OPTION_TESTSIGN != 0
+    }
+
+    /// 处于内核调试模式
+    pub fn debug_mode(&self) -> bool {
+        self.code_integrity_options & CODEINTEGRITY_OPTION_DEBUGMODE_ENABLED != 0
+    }
+
+    /// HVCI已开启，此时即使关闭了安全启动也难以加载未签名驱动
+    pub fn hvci_enabled(&self) -> bool {
+        self.code_integrity_options & CODEINTEGRITY_OPTION_HVCI_KMCI_ENABLED != 0
+    }
+
+    /// 允许加载未签名驱动的原因
+    pub fn enforcement_gaps(&self) -> Vec<&'static str> {
+        let mut gaps: Vec<&'static str> = Vec::new();
+        if !self.dse_enabled() {
+            gaps.push("driver signature enforcement disabled");
+        }
+        if self.test_signing() {
+            gaps.push("test signing enabled");
+        }
+        if self.debug_mode() {
+            gaps.push("kernel debug mode enabled");
+        }
+        gaps
+    }
+}
+
+impl BeingDebug for BootIntegrity {
+    /// TitanHide、HyperHide等隐藏调试器的驱动没有有效签名，需要关闭DSE或者开启测试签名才能加载
+    fn is_being_debug(&self) -> bool {
+        !self.enforcement_gaps().is_empty()
+    }
+}
+
+/// 查询安全启动与驱动签名强制状态
+///
+/// # 返回值
+///
+/// - `Err`: 查询代码完整性信息失败
+/// - `Ok(integrity)`: 状态，传统BIOS启动等不支持安全启动的系统上`secure_boot_capable`为false
+pub fn get_boot_integrity() -> Result<BootIntegrity> {
+    let mut code_integrity = SystemCodeIntegrityInformation {
+        length: size_of::<SystemCodeIntegrityInformation>() as u32,
+        options: 0,
+    };
+    let status = unsafe {
+        NtQuerySystemInformation(
+            SYSTEM_CODE_INTEGRITY_INFORMATION,
+            addr_of_mut!(code_integrity) as *mut c_void,
+            size_of_val(&code_integrity) as u32,
+            null_mut(),
+        )
+    };
+    if status.is_err() {
+        warn!("query code integrity failed; error code: {:?}", status);
+        return Err(Error::msg("query code integrity failed"));
+    }
+
+    let mut secure_boot = SystemSecureBootInformation::default();
+    let status = unsafe {
+        NtQuerySystemInformation(
+            SYSTEM_SECURE_BOOT_INFORMATION,
+            addr_of_mut!(secure_boot) as *mut c_void,
+            size_of_val(&secure_boot) as u32,
+            null_mut(),
+        )
+    };
+    if status.is_err() {
+        debug!("query secure boot failed; error code: {:?}", status);
+        secure_boot = SystemSecureBootInformation::default();
+    }
+
+    let integrity = BootIntegrity {
+        code_integrity_options: code_integrity.options,
+        secure_boot_capable: secure_boot.capable != 0,
+        secure_boot_enabled: secure_boot.enabled != 0,
+    };
+    debug!("boot integrity ==> {:?}", integrity);
+
+    Ok(integrity)
+}
+
+/// 检测驱动签名强制是否被关闭，或者系统处于测试签名、内核调试模式
+///
+/// # 返回值
+///
+/// - `Err`: 查询代码完整性信息失败
+/// - `Ok(Some(evidence))`: 允许加载未签名驱动的原因
+/// - `Ok(None)`: 驱动签名强制正常
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(evidence) = check_driver_signing().unwrap() {
+///     println!("unsigned drivers allowed: {}", evidence);
+/// }
+/// ```
+pub fn check_driver_signing() -> Result<Option<String>> {
+    let integrity = get_boot_integrity()?;
+    if !integrity.is_being_debug() {
+        return Ok(None);
+    }
+
+    let gaps = integrity.enforcement_gaps().join("; ");
+    debug!("driver signing weakened ==> {}", gaps);
+    Ok(Some(gaps))
+}
+
+/// 检测固件支持安全启动但没有开启
+///
+/// 关闭安全启动是修改启动配置(关闭DSE、开启测试签名)的前提，单独出现时信号较弱
+///
+/// # 返回值
+///
+/// - `Err`: 查询代码完整性信息失败
+/// - `Ok(true)`: 支持但没有开启安全启动
+/// - `Ok(false)`: 已开启，或者固件不支持
+pub fn check_secure_boot_disabled() -> Result<bool> {
+    let integrity = get_boot_integrity()?;
+    Ok(integrity.secure_boot_capable && !integrity.secure_boot_enabled)
+}
@@ -0,0 +1,286 @@
+use crate::engine::{Category, Report, Verdict};
+use crate::logging::{debug, warn};
+use crate::obf;
+use crate::obfstr::ObfStr;
+use crate::sink::DetectionSink;
+use crate::util::to_wide;
+use anyhow::{Error, Result};
+use std::{env, path::Path};
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{ERROR_SUCCESS, HANDLE},
+        Security::PSID,
+        System::{
+            EventLog::{
+                DeregisterEventSource, RegisterEventSourceW, ReportEventW,
+                EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+            },
+            Registry::{
+                RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE,
+                KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE, REG_VALUE_TYPE,
+            },
+        },
+    },
+};
+
+/// 默认的事件源名称
+pub const DEFAULT_SOURCE: &str = "anti_debug";
+
+/// Application日志下事件源的注册表路径
+const APPLICATION_LOG_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application";
+
+/// .NET Framework自带的消息文件，任意事件ID的描述都是`%1`，即第一个插入字符串
+const MESSAGE_FILE: ObfStr = obf!(r"Microsoft.NET\Framework\v4.0.30319\EventLogMessages.dll");
+
+/// 事件源支持的类型：错误、警告、信息
+const TYPES_SUPPORTED: u32 = 7;
+
+/// 一次检测汇总的事件ID
+pub const EVENT_SUMMARY: u32 = 1000;
+
+/// 命中的检测技术按类别使用的事件ID
+///
+/// - `Debugger`: 1001
+/// - `Environment`: 1002
+/// - `Tampering`: 1003
+/// - `TimeVirtualization`: 1004
+pub fn event_id(category: Category) -> u32 {
+    match category {
+        Category::Debugger => 1001,
+        Category::Environment => 1002,
+        Category::Tampering => 1003,
+        Category::TimeVirtualization => 1004,
+    }
+}
+
+/// 事件类别编号，与事件ID的末位一致，汇总事件为0
+fn event_category(category: Category) -> u16 {
+    (event_id(category) - EVENT_SUMMARY) as u16
+}
+
+/// 在Application日志下注册事件源，需要管理员权限，只需要在安装时执行一次
+///
+/// 系统中安装了.NET Framework 4时使用其`EventLogMessages.dll`作为消息文件，
+/// 事件查看器直接显示第一个插入字符串；否则只注册事件源，描述中仍然包含所有插入字符串
+///
+/// # 参数
+///
+/// - `source`: 事件源名称，例如`DEFAULT_SOURCE`
+///
+/// # 返回值
+///
+/// - `Err`: 没有权限或者写注册表失败
+/// - `Ok(())`: 注册成功
+pub fn register_source(source: &str) -> Result<()> {
+    let key = to_wide(&format!(r"{}\{}", APPLICATION_LOG_KEY, source));
+    let mut hkey = HKEY::default();
+    let status = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR(key.as_ptr()),
+            0,
+            PCWSTR::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            None,
+            &mut hkey,
+            None,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        warn!("RegCreateKeyExW failed; error code: {:?}", status);
+        return Err(Error::msg("RegCreateKeyExW failed"));
+    }
+
+    let result = set_source_values(hkey);
+    let _ = unsafe { RegCloseKey(hkey) };
+    result
+}
+
+/// 写入事件源的`TypesSupported`与`EventMessageFile`
+fn set_source_values(hkey: HKEY) -> Result<()> {
+    set_value(
+        hkey,
+        "TypesSupported",
+        REG_DWORD,
+        &TYPES_SUPPORTED.to_le_bytes(),
+    )?;
+
+    let message_file = MESSAGE_FILE.decrypt();
+    let installed = env::var("SystemRoot")
+        .map(|root| Path::new(&root).join(&*message_file).exists())
+        .unwrap_or(false);
+    if !installed {
+        debug!("event message file not installed");
+        return Ok(());
+    }
+
+    let message_file: Vec<u8> = to_wide(&format!(r"%SystemRoot%\{}", message_file))
+        .into_iter()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    set_value(hkey, "EventMessageFile", REG_EXPAND_SZ, &message_file)
+}
+
+fn set_value(hkey: HKEY, name: &str, kind: REG_VALUE_TYPE, data: &[u8]) -> Result<()> {
+    let name = to_wide(name);
+    let status = unsafe { RegSetValueExW(hkey, PCWSTR(name.as_ptr()), 0, kind, Some(data)) };
+    if status != ERROR_SUCCESS {
+        warn!("RegSetValueExW failed; error code: {:?}", status);
+        return Err(Error::msg("RegSetValueExW failed"));
+    }
+    Ok(())
+}
+
+/// Application日志的写入句柄，离开作用域时调用DeregisterEventSource
+///
+/// 用于没有ETW采集的环境，检测结果写入Windows事件日志，可以由事件转发或者SIEM收集
+///
+/// # 示例
+///
+/// ```ignore
+/// let report = Engine::default().run();
+/// let log = eventlog::EventLog::open(eventlog::DEFAULT_SOURCE)?;
+/// log.report(&report)?;
+/// ```
+pub struct EventLog {
+    handle: HANDLE,
+}
+
+// 事件源句柄可以在多个线程中同时调用ReportEventW
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    /// 打开事件源，事件源没有注册时系统仍然会写入Application日志，但事件查看器无法显示描述
+    ///
+    /// # 参数
+    ///
+    /// - `source`: 事件源名称
+    pub fn open(source: &str) -> Result<Self> {
+        let source = to_wide(source);
+        let handle = unsafe { RegisterEventSourceW(PCWSTR::null(), PCWSTR(source.as_ptr())) }?;
+        Ok(Self { handle })
+    }
+
+    /// 写入一条事件
+    ///
+    /// # 参数
+    ///
+    /// - `kind`: 事件类型，例如`EVENTLOG_WARNING_TYPE`
+    /// - `category`: 事件类别编号
+    /// - `event_id`: 事件ID
+    /// - `strings`: 插入字符串，第一个是完整的描述
+    pub fn write(
+        &self,
+        kind: REPORT_EVENT_TYPE,
+        category: u16,
+        event_id: u32,
+        strings: &[&str],
+    ) -> Result<()> {
+        let wide: Vec<Vec<u16>> = strings.iter().map(|string| to_wide(string)).collect();
+        let pointers: Vec<PCWSTR> = wide.iter().map(|string| PCWSTR(string.as_ptr())).collect();
+        unsafe {
+            ReportEventW(
+                self.handle,
+                kind,
+                category,
+                event_id,
+                PSID::default(),
+                0,
+                Some(&pointers),
+                None,
+            )
+        }?;
+
+        debug!(
+            "event log ==> {}; {}",
+            event_id,
+            strings.first().unwrap_or(&"")
+        );
+        Ok(())
+    }
+
+    /// 写入一个检测技术的结果，事件ID按类别区分(见`event_id`)
+    ///
+    /// 插入字符串依次为：描述、技术名称、类别、权重、证据
+    pub fn report_verdict(&self, verdict: &Verdict) -> Result<()> {
+        let evidence = verdict.evidence.as_deref().unwrap_or_default();
+        let message = format!(
+            "Detection: {} ({:?}, weight {})\r\nEvidence: {}",
+            verdict.name, verdict.category, verdict.weight, evidence
+        );
+        self.write(
+            EVENTLOG_WARNING_TYPE,
+            event_category(verdict.category),
+            event_id(verdict.category),
+            &[
+                &message,
+                verdict.name,
+                &format!("{:?}", verdict.category),
+                &verdict.weight.to_string(),
+                evidence,
+            ],
+        )
+    }
+
+    /// 写入一次检测的汇总事件与所有命中的检测技术
+    ///
+    /// 汇总事件ID为`EVENT_SUMMARY`，有命中时为警告，否则为信息；
+    /// 插入字符串依次为：描述、调试器、分析环境、代码篡改、时间虚拟化得分
+    ///
+    /// # 返回值
+    ///
+    /// - `Ok(count)`: 写入的事件数量
+    pub fn report(&self, report: &Report) -> Result<usize> {
+        let scores = [
+            report.debugger_score(),
+            report.environment_score(),
+            report.tampering_score(),
+            report.time_virtualization_score(),
+        ]
+        .map(|score| score.to_string());
+        let detections = report.detections().count();
+        let message = format!(
+            "{} of {} techniques detected\r\nDebugger: {}; Environment: {}; Tampering: {}; TimeVirtualization: {}",
+            detections,
+            report.verdicts.len(),
+            scores[0],
+            scores[1],
+            scores[2],
+            scores[3]
+        );
+        let kind = match detections {
+            0 => EVENTLOG_INFORMATION_TYPE,
+            _ => EVENTLOG_WARNING_TYPE,
+        };
+        self.write(
+            kind,
+            0,
+            EVENT_SUMMARY,
+            &[&message, &scores[0], &scores[1], &scores[2], &scores[3]],
+        )?;
+
+        for verdict in report.detections() {
+            self.report_verdict(verdict)?;
+        }
+        Ok(detections + 1)
+    }
+}
+
+impl DetectionSink for EventLog {
+    fn name(&self) -> &str {
+        "eventlog"
+    }
+
+    fn emit(&self, report: &Report) -> Result<()> {
+        self.report(report).map(|_| ())
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        let _ = unsafe { DeregisterEventSource(self.handle) };
+    }
+}
@@ -0,0 +1,456 @@
+use crate::hook::{get_module, get_proc_address};
+use crate::imports::AddVectoredExceptionHandler;
+use crate::logging::debug;
+#[cfg(target_arch = "x86_64")]
+use crate::{resolve, syscall};
+use anyhow::{Error, Result};
+use std::{arch::asm, ffi::c_void, ptr::addr_of_mut, sync::OnceLock};
+use windows::Win32::{
+    Foundation::{
+        CloseHandle, EXCEPTION_BREAKPOINT, EXCEPTION_SINGLE_STEP, HANDLE, NTSTATUS,
+        STATUS_INVALID_HANDLE,
+    },
+    System::{
+        Diagnostics::Debug::{
+            DebugBreak, EXCEPTION_CONTINUE_EXECUTION, EXCEPTION_CONTINUE_SEARCH, EXCEPTION_POINTERS,
+        },
+        Threading::{FlsAlloc, FlsGetValue, FlsSetValue, GetCurrentProcessId, FLS_OUT_OF_INDEXES},
+    },
+};
+
+/// 探测期间捕获到的异常
+///
+/// - `code`: 第一个异常的异常码
+/// - `address`: 第一个异常的地址
+/// - `count`: 捕获到的异常数量
+/// - `stack_top`: 第一个异常发生时栈顶的值，`call`之后产生的单步异常中为返回地址
+/// - `dr`: 第一个异常的Context中的DR0-DR3
+/// - `dr7`: 第一个异常的Context中的DR7
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caught {
+    pub code: NTSTATUS,
+    pub address: usize,
+    pub count: u32,
+    pub stack_top: usize,
+    pub dr: [usize; 4],
+    pub dr7: usize,
+}
+
+/// 正在探测的纤程等待的异常与捕获结果
+struct Probe {
+    codes: &'static [NTSTATUS],
+    skip: usize,
+    caught: Option<Caught>,
+}
+
+// VEH对整个进程生效，探测状态保存在纤程本地存储(FLS)中：只处理正在探测的纤程中的异常，
+// 其他线程与纤程的异常继续向后传递，探测期间切换纤程也不会互相干扰
+static SLOT: OnceLock<u32> = OnceLock::new();
+static HANDLER: OnceLock<usize> = OnceLock::new();
+
+unsafe extern "system" fn probe_handler(info: *mut EXCEPTION_POINTERS) -> i32 {
+    let Some(&slot) = SLOT.get() else {
+        return EXCEPTION_CONTINUE_SEARCH;
+    };
+    let probe = FlsGetValue(slot) as *mut Probe;
+    if probe.is_null() {
+        return EXCEPTION_CONTINUE_SEARCH;
+    }
+    let record = (*info).ExceptionRecord;
+    let context = (*info).ContextRecord;
+    if record.is_null() || context.is_null() || !(*probe).codes.contains(&(*record).ExceptionCode) {
+        return EXCEPTION_CONTINUE_SEARCH;
+    }
+
+    (*probe).caught = Some(match (*probe).caught {
+        Some(caught) => Caught {
+            count: caught.count + 1,
+            ..caught
+        },
+        None => {
+            #[cfg(target_arch = "x86_64")]
+            let stack = (*context).Rsp as *const usize;
+            #[cfg(target_arch = "x86")]
+            let stack = (*context).Esp as *const usize;
+            Caught {
+                code: (*record).ExceptionCode,
+                address: (*record).ExceptionAddress as usize,
+                count: 1,
+                stack_top: stack.read(),
+                dr: [
+                    (*context).Dr0 as usize,
+                    (*context).Dr1 as usize,
+                    (*context).Dr2 as usize,
+                    (*context).Dr3 as usize,
+                ],
+                dr7: (*context).Dr7 as usize,
+            }
+        }
+    });
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        (*context).Rip += (*probe).skip as u64;
+    }
+    #[cfg(target_arch = "x86")]
+    {
+        (*context).Eip += (*probe).skip as u32;
+    }
+    EXCEPTION_CONTINUE_EXECUTION
+}
+
+/// 探测结束或者`body`panic时解除等待
+struct Disarm(u32);
+
+impl Drop for Disarm {
+    fn drop(&mut self) {
+        let _ = unsafe { FlsSetValue(self.0, None) };
+    }
+}
+
+/// 在VEH保护下执行`body`，捕获其中抛出的指定异常，相当于`__try`/`__except`
+///
+/// VEH在第一次调用时注册到处理链的最前面，之后一直保留。捕获到的异常在处理后继续执行：
+/// 指令指针前进`skip`字节(例如`int3`为1)，其他异常交给后续的处理程序
+///
+/// # 参数
+///
+/// - `codes`: 要捕获的异常码
+/// - `skip`: 继续执行前指令指针前进的字节数
+/// - `body`: 探测代码
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(None)`: 没有捕获到异常
+/// - `Ok(Some(caught))`: 捕获到的异常
+///
+/// # 示例
+///
+/// ```ignore
+/// let caught = guarded(&[EXCEPTION_BREAKPOINT], 1, || unsafe { asm!("int3") })?;
+/// ```
+///
+/// # 注意
+///
+/// 调试器在第一次机会中处理掉的异常不会到达VEH
+pub fn guarded<F: FnOnce()>(
+    codes: &'static [NTSTATUS],
+    skip: usize,
+    body: F,
+) -> Result<Option<Caught>> {
+    let slot = *SLOT.get_or_init(|| unsafe { FlsAlloc(None) });
+    if slot == FLS_OUT_OF_INDEXES {
+        return Err(Error::msg("FlsAlloc failed"));
+    }
+    let handler = *HANDLER
+        .get_or_init(|| unsafe { AddVectoredExceptionHandler(1, Some(probe_handler)) } as usize);
+    if handler == 0 {
+        return Err(Error::msg("AddVectoredExceptionHandler failed"));
+    }
+
+    let mut probe = Probe {
+        codes,
+        skip,
+        caught: None,
+    };
+    unsafe { FlsSetValue(slot, Some(addr_of_mut!(probe) as *const c_void)) }?;
+    let disarm = Disarm(slot);
+    body();
+    drop(disarm);
+    Ok(probe.caught)
+}
+
+/// 句柄表最多容纳2^24个句柄(句柄值小于0x4000000)，该值一定不是有效句柄，也不是伪句柄
+const INVALID_HANDLE_PROBE: usize = 0x0BAD_C0DC;
+
+/// 关闭无效句柄的调用路径
+///
+/// - `CloseHandle`: 经过kernel32与ntdll中的导出函数
+/// - `Syscall`: 直接执行syscall指令调用NtClose(仅x64)，hook库通常只处理`CloseHandle`与`NtClose`存根
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CloseRoute {
+    CloseHandle,
+    Syscall,
+}
+
+/// 关闭无效句柄，检查内核是否抛出`STATUS_INVALID_HANDLE`异常
+///
+/// 进程被调试时(或者开启了`FLG_ENABLE_HANDLE_EXCEPTIONS`)内核在关闭无效句柄时抛出异常，
+/// 没有调试器时只返回错误码
+///
+/// # 参数
+///
+/// - `route`: 调用路径
+///
+/// # 返回值
+///
+/// - `Err`: VEH注册失败、x86上使用`Syscall`或者没有找到NtClose的系统调用号
+/// - `Ok(true)`: 抛出了异常，当前进程被调试
+/// - `Ok(false)`: 没有抛出异常
+pub fn check_invalid_handle(route: CloseRoute) -> Result<bool> {
+    let handle = HANDLE(INVALID_HANDLE_PROBE as *mut c_void);
+    let caught = match route {
+        CloseRoute::CloseHandle => guarded(&[STATUS_INVALID_HANDLE], 0, || {
+            let _ = unsafe { CloseHandle(handle) };
+        })?,
+        #[cfg(target_arch = "x86_64")]
+        CloseRoute::Syscall => {
+            const NT_CLOSE: u32 = resolve::hash("NtClose");
+            let ssn = syscall::syscall_number(NT_CLOSE)?;
+            guarded(&[STATUS_INVALID_HANDLE], 0, || {
+                let _ = unsafe { syscall::syscall1(ssn, handle.0 as usize) };
+            })?
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        CloseRoute::Syscall => return Err(Error::msg("direct syscall requires x64")),
+    };
+
+    if let Some(caught) = caught {
+        debug!("invalid handle exception ==> {:?}; {:?}", route, caught);
+    }
+    Ok(caught.is_some())
+}
+
+/// 触发断点的方式
+///
+/// - `DebugBreak`: 调用kernelbase中的`DebugBreak`
+/// - `Int3`: 内联的`int3`指令
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BreakRoute {
+    DebugBreak,
+    Int3,
+}
+
+/// 触发一个断点异常，检查它是否送达了自己的异常处理程序
+///
+/// 没有调试器时断点异常由VEH处理；调试器在第一次机会中处理掉断点(把它当作自己的断点继续执行)时，
+/// VEH不会收到异常
+///
+/// # 参数
+///
+/// - `route`: 触发断点的方式
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(true)`: 断点被调试器吞掉
+/// - `Ok(false)`: 自己的处理程序收到了断点
+pub fn check_breakpoint_delivery(route: BreakRoute) -> Result<bool> {
+    // x86与x64上断点异常的指令指针都指向int3本身，继续执行前跳过这一个字节
+    let caught = guarded(&[EXCEPTION_BREAKPOINT], 1, || match route {
+        BreakRoute::DebugBreak => unsafe { DebugBreak() },
+        BreakRoute::Int3 => unsafe { asm!("int3") },
+    })?;
+
+    debug!("breakpoint delivery ==> {:?}; {:?}", route, caught);
+    Ok(caught.is_none())
+}
+
+/// 通过`pushf`/`or TF`/`popf`设置陷阱标志，随后执行一条`nop`，在VEH保护下捕获单步异常
+///
+/// `popf`之后的第一条指令执行完毕时CPU产生单步异常并清除陷阱标志，指令指针已经指向下一条指令，
+/// 继续执行时不需要调整。供异常与计时两类检测共用
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(None)`: 单步异常没有送达，被调试器吞掉
+/// - `Ok(Some(caught))`: 捕获到的单步异常
+pub fn trap_flag_step() -> Result<Option<Caught>> {
+    guarded(&[EXCEPTION_SINGLE_STEP], 0, || unsafe {
+        #[cfg(target_arch = "x86_64")]
+        asm!("pushfq", "or qword ptr [rsp], 0x100", "popfq", "nop");
+        #[cfg(target_arch = "x86")]
+        asm!("pushfd", "or dword ptr [esp], 0x100", "popfd", "nop");
+    })
+}
+
+/// 检查陷阱标志产生的单步异常是否送达了自己的异常处理程序
+///
+/// 调试器单步跟踪时会把这个单步异常当作自己的，VEH不会收到
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(true)`: 单步异常被调试器吞掉
+/// - `Ok(false)`: 自己的处理程序收到了单步异常
+pub fn check_trap_flag() -> Result<bool> {
+    let caught = trap_flag_step()?;
+    debug!("trap flag ==> {:?}", caught);
+    Ok(caught.is_none())
+}
+
+/// 在VEH保护下执行`int3`，同时检查处理程序是否运行、异常记录是否与断点一致以及之后是否回到原处继续执行
+///
+/// 处理程序应当恰好收到一次`EXCEPTION_BREAKPOINT`，异常地址指向`int3`本身，跳过1字节后继续执行。
+/// 调试器把断点当作自己的断点修正指令指针并继续执行时，处理程序不会运行；
+/// 伪造异常记录或者重新执行断点时，异常码、地址或者次数不一致
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(true)`: 处理程序没有运行、异常记录与预期不一致或者没有回到断点之后
+/// - `Ok(false)`: 与CPU的行为一致
+pub fn check_int3_record() -> Result<bool> {
+    let mut address: usize = 0;
+    let mut resumed: usize = 0;
+    let caught = guarded(&[EXCEPTION_BREAKPOINT], 1, || unsafe {
+        #[cfg(target_arch = "x86_64")]
+        asm!(
+            "lea {address}, [rip + 2f]",
+            "xor {resumed}, {resumed}",
+            "2:",
+            "int3",
+            "mov {resumed}, 1",
+            address = out(reg) address,
+            resumed = out(reg) resumed,
+        );
+        #[cfg(target_arch = "x86")]
+        asm!(
+            "lea {address}, [2f]",
+            "xor {resumed}, {resumed}",
+            "2:",
+            "int3",
+            "mov {resumed}, 1",
+            address = out(reg) address,
+            resumed = out(reg) resumed,
+        );
+    })?;
+
+    debug!(
+        "int3 record ==> address: {:#x}; resumed: {}; {:?}",
+        address, resumed, caught
+    );
+    let record = caught.map(|caught| (caught.code, caught.address, caught.count));
+    Ok(record != Some((EXCEPTION_BREAKPOINT, address, 1)) || resumed != 1)
+}
+
+/// 设置陷阱标志后立即调用一个无害的API(`GetCurrentProcessId`)，检查陷阱标志在API调用前后的行为
+///
+/// `call`执行完毕时产生恰好一次单步异常，异常地址为API入口，栈顶为`call`的返回地址，
+/// 之后CPU清除陷阱标志，API正常执行并返回。调试器与跟踪器单步进入API时会吞掉这个异常、
+/// 在API内部重新设置陷阱标志(异常不止一次)，或者在其他位置报告异常
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败，或者没有找到API
+/// - `Ok(true)`: 单步异常的次数、地址或者返回地址与CPU的行为不一致
+/// - `Ok(false)`: 与CPU的行为一致
+pub fn check_api_trap_flag() -> Result<bool> {
+    let api = get_module("kernel32.dll")
+        .ok()
+        .and_then(|hmodule| get_proc_address(hmodule, "GetCurrentProcessId"))
+        .ok_or_else(|| Error::msg("GetCurrentProcessId not found"))?;
+
+    let mut pid: u32 = 0;
+    let mut return_address: usize = 0;
+    let caught = guarded(&[EXCEPTION_SINGLE_STEP], 0, || unsafe {
+        // popf之后的下一条指令就是call，单步异常在进入API时产生
+        #[cfg(target_arch = "x86_64")]
+        asm!(
+            "mov r12, rsp",
+            "and rsp, -16",
+            "sub rsp, 0x20",
+            "pushfq",
+            "or qword ptr [rsp], 0x100",
+            "popfq",
+            "call {api}",
+            "2:",
+            "mov rsp, r12",
+            "lea rcx, [rip + 2b]",
+            api = in(reg) api,
+            lateout("rcx") return_address,
+            lateout("eax") pid,
+            out("r12") _,
+            clobber_abi("system"),
+        );
+        #[cfg(target_arch = "x86")]
+        asm!(
+            "pushfd",
+            "or dword ptr [esp], 0x100",
+            "popfd",
+            "call {api}",
+            "2:",
+            "lea ecx, [2b]",
+            api = in(reg) api,
+            lateout("ecx") return_address,
+            lateout("eax") pid,
+            clobber_abi("system"),
+        );
+    })?;
+
+    debug!(
+        "api trap flag ==> api: {:#x}; return address: {:#x}; {:?}",
+        api, return_address, caught
+    );
+    let record = caught.map(|caught| (caught.code, caught.address, caught.count, caught.stack_top));
+    let expected = (EXCEPTION_SINGLE_STEP, api, 1, return_address);
+    Ok(record != Some(expected) || pid != unsafe { GetCurrentProcessId() })
+}
+
+/// 执行带`rep`前缀的`int3`(`F3 CC`)，对比处理程序看到的异常地址与CPU的实际行为
+///
+/// CPU忽略`int3`的前缀，异常地址指向`CC`本身；部分调试器按照自己的规则解析前缀，
+/// 把前缀连同断点一起跳过(异常不会送达)，或者报告指向前缀的地址
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(true)`: 异常没有送达、地址或者次数与CPU的行为不一致
+/// - `Ok(false)`: 与CPU的行为一致
+pub fn check_prefixed_int3() -> Result<bool> {
+    let mut start: usize = 0;
+    // 异常地址指向CC时跳过1字节恰好越过整条指令；地址指向前缀时会再次执行CC，次数变为2
+    let caught = guarded(&[EXCEPTION_BREAKPOINT], 1, || unsafe {
+        #[cfg(target_arch = "x86_64")]
+        asm!("lea {start}, [rip + 2f]", "2:", ".byte 0xf3, 0xcc", start = out(reg) start);
+        #[cfg(target_arch = "x86")]
+        asm!("lea {start}, [2f]", "2:", ".byte 0xf3, 0xcc", start = out(reg) start);
+    })?;
+
+    debug!("prefixed int3 ==> start: {:#x}; {:?}", start, caught);
+    Ok(!matches!(caught, Some(caught) if caught.address == start + 1 && caught.count == 1))
+}
+
+/// 利用`mov ss`抑制单步异常的特性检测单步跟踪(仅x86)
+///
+/// 加载SS之后的下一条指令不会产生单步异常：
+///
+/// - 被单步跟踪时，`push ss`/`pop ss`之后的`pushfd`保存的标志中TF仍然为1
+/// - 自己设置TF后紧接着加载SS，单步异常应当在加载SS之后的第二条指令处送达，
+///   跟踪器逐条单步时会失去同步，处理程序看到的异常地址或者次数与CPU的行为不一致
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(true)`: 发现单步跟踪
+/// - `Ok(false)`: 与CPU的行为一致
+#[cfg(target_arch = "x86")]
+pub fn check_mov_ss() -> Result<bool> {
+    let flags: usize;
+    unsafe { asm!("push ss", "pop ss", "pushfd", "pop {flags}", flags = out(reg) flags) };
+    if flags & 0x100 != 0 {
+        debug!("mov ss ==> trap flag visible: {:#x}", flags);
+        return Ok(true);
+    }
+
+    let mut expected: usize = 0;
+    let caught = guarded(&[EXCEPTION_SINGLE_STEP], 0, || unsafe {
+        asm!(
+            "lea {expected}, [2f]",
+            "mov {selector:x}, ss",
+            "pushfd",
+            "or dword ptr [esp], 0x100",
+            "popfd",
+            "mov ss, {selector:x}",
+            "nop",
+            "2:",
+            expected = out(reg) expected,
+            selector = out(reg) _,
+        );
+    })?;
+
+    debug!("mov ss ==> expected: {:#x}; {:?}", expected, caught);
+    Ok(!matches!(caught, Some(caught) if caught.address == expected && caught.count == 1))
+}
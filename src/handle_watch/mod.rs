@@ -0,0 +1,187 @@
+use crate::logging::debug;
+use crate::{
+    cache,
+    thread::{
+        SystemHandleInformationEx, SystemHandleTableEntryInfoEx, SYSTEM_EXTENDED_HANDLE_INFORMATION,
+    },
+};
+use anyhow::{Error, Result};
+use std::collections::HashSet;
+use windows::Win32::Foundation::HANDLE;
+
+/// 扩展系统句柄表中的一个句柄
+///
+/// 旧的`SystemHandleInformation`(16)把进程ID与句柄值截断为16位，
+/// 进程ID或句柄值超过65535时无法匹配，因此统一使用`SystemExtendedHandleInformation`(64)
+///
+/// - `pid`: 持有句柄的进程ID
+/// - `handle`: 句柄值
+/// - `object`: 句柄指向的内核对象地址
+/// - `access`: 句柄的访问权限
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandleEntry {
+    pub pid: u32,
+    pub handle: usize,
+    pub object: usize,
+    pub access: u32,
+}
+
+impl From<&SystemHandleTableEntryInfoEx> for HandleEntry {
+    fn from(entry: &SystemHandleTableEntryInfoEx) -> Self {
+        Self {
+            pid: entry.unique_process_id as u32,
+            handle: entry.handle_value,
+            object: entry.object as usize,
+            access: entry.granted_access,
+        }
+    }
+}
+
+/// 两次扫描之间句柄的变化
+///
+/// - `added`: 新出现的句柄
+/// - `removed`: 已关闭的句柄
+#[derive(Debug, Clone, Default)]
+pub struct HandleDiff {
+    pub added: Vec<HandleEntry>,
+    pub removed: Vec<HandleEntry>,
+}
+
+impl HandleDiff {
+    /// 两次扫描之间没有变化
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// 增量监视其他进程持有的、指向指定内核对象的句柄
+///
+/// 内核对象地址只在`watch`时解析一次；每次`poll`只保留指向关注对象的句柄，
+/// 与上一次的结果比较后返回增加与减少的部分，调用方只需要处理变化的句柄
+/// (例如只为新句柄查询进程名)，不必每次重新处理整个句柄表
+///
+/// # 示例
+///
+/// ```ignore
+/// let mut watch = HandleWatch::new(unsafe { GetCurrentProcessId() });
+/// watch.watch(hthread)?;
+/// loop {
+///     for entry in watch.poll()?.added {
+///         println!("process {} opened our thread", entry.pid);
+///     }
+///     std::thread::sleep(Duration::from_millis(500));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HandleWatch {
+    pid: u32,
+    objects: Vec<usize>,
+    entries: HashSet<HandleEntry>,
+}
+
+impl HandleWatch {
+    /// 创建监视器
+    ///
+    /// # 参数
+    ///
+    /// - `pid`: `watch`中句柄所属的进程ID，该进程自身的句柄不会被报告
+    pub fn new(pid: u32) -> Self {
+        Self {
+            pid,
+            objects: Vec::new(),
+            entries: HashSet::new(),
+        }
+    }
+
+    /// 关注`pid`进程中的`handle`指向的内核对象
+    ///
+    /// # 参数
+    ///
+    /// - `handle`: `pid`进程持有的句柄(不能是伪句柄)
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 查询系统句柄表失败，或者表中没有该句柄
+    /// - `Ok(object)`: 内核对象地址
+    pub fn watch(&mut self, handle: HANDLE) -> Result<usize> {
+        let pid = self.pid;
+        let object = with_handle_entries(|entries| {
+            entries
+                .iter()
+                .find(|entry| {
+                    entry.unique_process_id == pid as usize
+                        && entry.handle_value == handle.0 as usize
+                })
+                .map(|entry| entry.object as usize)
+                .ok_or_else(|| Error::msg("handle not found in system handle table"))
+        })?;
+
+        debug!(
+            "watch kernel object ==> {:#x}; handle: {:?}",
+            object, handle
+        );
+        if !self.objects.contains(&object) {
+            self.objects.push(object);
+        }
+        Ok(object)
+    }
+
+    /// 关注的内核对象地址
+    pub fn objects(&self) -> &[usize] {
+        &self.objects
+    }
+
+    /// 最近一次`poll`时其他进程持有的、指向关注对象的句柄
+    pub fn entries(&self) -> impl Iterator<Item = &HandleEntry> {
+        self.entries.iter()
+    }
+
+    /// 扫描系统句柄表并返回与上一次扫描相比的变化，第一次扫描时所有句柄都是新增的
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 查询系统句柄表失败
+    /// - `Ok(diff)`: 句柄的变化
+    pub fn poll(&mut self) -> Result<HandleDiff> {
+        if self.objects.is_empty() {
+            return Ok(HandleDiff::default());
+        }
+
+        let (pid, objects) = (self.pid, &self.objects);
+        let current: HashSet<HandleEntry> = with_handle_entries(|entries| {
+            Ok(entries
+                .iter()
+                .filter(|entry| {
+                    entry.unique_process_id != pid as usize
+                        && objects.contains(&(entry.object as usize))
+                })
+                .map(HandleEntry::from)
+                .collect())
+        })?;
+
+        let diff = HandleDiff {
+            added: current.difference(&self.entries).copied().collect(),
+            removed: self.entries.difference(&current).copied().collect(),
+        };
+        if !diff.is_empty() {
+            debug!("handle table diff ==> {:?}", diff);
+        }
+        self.entries = current;
+
+        Ok(diff)
+    }
+}
+
+/// 以切片的形式处理系统句柄表，使用`cache`模块中句柄表的缓存
+fn with_handle_entries<R, F: FnMut(&[SystemHandleTableEntryInfoEx]) -> Result<R>>(
+    mut f: F,
+) -> Result<R> {
+    cache::with_system_information(SYSTEM_EXTENDED_HANDLE_INFORMATION, |system_information| {
+        let handle_info: &SystemHandleInformationEx =
+            unsafe { &*(system_information.as_ptr() as *const SystemHandleInformationEx) };
+        let entries: &[SystemHandleTableEntryInfoEx] = unsafe {
+            std::slice::from_raw_parts(handle_info.handles.as_ptr(), handle_info.number_of_handles)
+        };
+        f(entries)
+    })
+}
@@ -0,0 +1,904 @@
+use crate::logging::{debug, warn};
+use crate::{
+    anti_dump,
+    imports::{GetModuleHandleW, GetProcAddress, ReadProcessMemory},
+    integrity::sha256,
+    obf,
+    obfstr::ObfStr,
+    pe::{self, PeImage},
+    resolve,
+    util::to_wide,
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    mem::size_of,
+    path::PathBuf,
+    ptr,
+    sync::{Arc, OnceLock, RwLock},
+};
+use windows::{
+    core::{PCSTR, PCWSTR},
+    Win32::{
+        Foundation::{HMODULE, MAX_PATH},
+        System::{
+            LibraryLoader::{
+                GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+                GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            },
+            Threading::GetCurrentProcess,
+        },
+    },
+};
+
+/// 对比导出函数开头的字节数
+pub const PROLOGUE_SIZE: usize = 16;
+
+/// 需要检查inline hook的系统模块
+pub const SYSTEM_MODULES: [&str; 3] = ["ntdll.dll", "kernel32.dll", "kernelbase.dll"];
+
+/// 被修改的导出函数
+///
+/// - `module`: 模块名
+/// - `function`: 导出函数名，按序号导出时为`#ordinal`
+/// - `address`: 函数在内存中的地址
+/// - `memory`: 内存中的开头字节
+/// - `disk`: 磁盘文件中(已重定位)的开头字节
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detour {
+    pub module: String,
+    pub function: String,
+    pub address: usize,
+    pub memory: Vec<u8>,
+    pub disk: Vec<u8>,
+}
+
+/// 被重定向的导入函数
+///
+/// - `module`: 导入的DLL名称
+/// - `function`: 导入函数名，按序号导入时为`#ordinal`
+/// - `address`: IAT中保存的函数地址
+/// - `owner`: 地址所在的模块路径，为None时地址位于私有内存(shellcode/跳板)中
+#[derive(Debug, Clone, PartialEq)]
+pub struct IatHook {
+    pub module: String,
+    pub function: String,
+    pub address: usize,
+    pub owner: Option<PathBuf>,
+}
+
+/// IAT表项
+///
+/// - `module`: 导入的DLL名称
+/// - `function`: 导入函数名，按序号导入时为`#ordinal`
+/// - `iat_rva`: IAT表项的RVA
+/// - `address`: IAT中保存的函数地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IatEntry {
+    pub module: String,
+    pub function: String,
+    pub iat_rva: u32,
+    pub address: usize,
+}
+
+/// 导入表的快照
+///
+/// - `checksum`: 所有表项RVA与函数地址的SHA-256
+/// - `entries`: 所有表项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IatSnapshot {
+    pub checksum: [u8; 32],
+    pub entries: Vec<IatEntry>,
+}
+
+/// 被篡改的导出表项
+///
+/// - `module`: 模块名
+/// - `function`: 导出函数名，按序号导出时为`#ordinal`
+/// - `memory_rva`: 内存中导出表记录的RVA
+/// - `disk_rva`: 磁盘文件导出表记录的RVA，磁盘文件中不存在该导出时为None
+#[derive(Debug, Clone, PartialEq)]
+pub struct EatHook {
+    pub module: String,
+    pub function: String,
+    pub memory_rva: u32,
+    pub disk_rva: Option<u32>,
+}
+
+/// 默认需要检查跳板的关键API
+pub const CRITICAL_APIS: [(ObfStr, ObfStr); 12] = [
+    (obf!("ntdll.dll"), obf!("NtQueryInformationProcess")),
+    (obf!("ntdll.dll"), obf!("NtSetInformationThread")),
+    (obf!("ntdll.dll"), obf!("NtQuerySystemInformation")),
+    (obf!("ntdll.dll"), obf!("NtQueryObject")),
+    (obf!("ntdll.dll"), obf!("NtClose")),
+    (obf!("ntdll.dll"), obf!("NtGetContextThread")),
+    (obf!("ntdll.dll"), obf!("NtSetContextThread")),
+    (obf!("ntdll.dll"), obf!("NtCreateThreadEx")),
+    (obf!("kernelbase.dll"), obf!("IsDebuggerPresent")),
+    (obf!("kernelbase.dll"), obf!("CheckRemoteDebuggerPresent")),
+    (obf!("kernelbase.dll"), obf!("OutputDebugStringA")),
+    (obf!("kernel32.dll"), obf!("GetTickCount")),
+];
+
+/// 导出调试检测API的模块，kernel32中的导出只是跳转到kernelbase的存根
+pub const DEBUG_API_MODULES: [&str; 2] = ["kernel32.dll", "kernelbase.dll"];
+
+/// kernelbase!IsDebuggerPresent的原始代码：读取PEB.BeingDebugged后返回
+#[cfg(target_pointer_width = "64")]
+pub const IS_DEBUGGER_PRESENT_PROLOGUES: [&[u8]; 1] = [&[
+    0x65, 0x48, 0x8b, 0x04, 0x25, 0x60, 0x00, 0x00, 0x00, // mov rax, gs:[60h]
+    0x0f, 0xb6, 0x40, 0x02, // movzx eax, byte ptr [rax+2]
+    0xc3, // ret
+]];
+#[cfg(target_pointer_width = "32")]
+pub const IS_DEBUGGER_PRESENT_PROLOGUES: [&[u8]; 2] = [
+    &[
+        0x64, 0xa1, 0x30, 0x00, 0x00, 0x00, // mov eax, fs:[30h]
+        0x0f, 0xb6, 0x40, 0x02, // movzx eax, byte ptr [eax+2]
+        0xc3, // ret
+    ],
+    &[
+        0x64, 0xa1, 0x18, 0x00, 0x00, 0x00, // mov eax, fs:[18h]
+        0x8b, 0x40, 0x30, // mov eax, [eax+30h]
+        0x0f, 0xb6, 0x40, 0x02, // movzx eax, byte ptr [eax+2]
+        0xc3, // ret
+    ],
+];
+
+/// 函数入口处的跳板类型
+///
+/// - `RelativeJump`: `jmp rel32`(E9)，Detours/MinHook的常见写法
+/// - `AbsoluteJump`: `jmp [mem]`(FF 25)，目标地址保存在内存中
+/// - `PushRet`: `push imm32; ret`
+/// - `MovJump`: `mov reg, imm; jmp reg`
+/// - `HotPatch`: 热补丁前导`mov edi, edi`被改写为短跳转，跳到函数前5字节处的长跳转
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookStyle {
+    RelativeJump,
+    AbsoluteJump,
+    PushRet,
+    MovJump,
+    HotPatch,
+}
+
+/// 关键API入口处发现的跳板
+///
+/// - `module`: 模块名
+/// - `function`: 函数名
+/// - `address`: 函数地址
+/// - `style`: 跳板类型
+/// - `target`: 跳转目标地址
+/// - `owner`: 跳转目标所在的模块路径，为None时位于私有内存中
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trampoline {
+    pub module: String,
+    pub function: String,
+    pub address: usize,
+    pub style: HookStyle,
+    pub target: usize,
+    pub owner: Option<PathBuf>,
+}
+
+/// 获取已加载模块的句柄
+pub fn get_module(name: &str) -> Result<HMODULE> {
+    let wide = to_wide(name);
+    Ok(unsafe { GetModuleHandleW(Some(PCWSTR(wide.as_ptr()))) }?)
+}
+
+/// 获取已加载模块对应的磁盘文件路径
+pub fn get_module_path(hmodule: HMODULE) -> Result<PathBuf> {
+    let mut buffer: [u16; MAX_PATH as usize] = [0; MAX_PATH as usize];
+    let len = unsafe { GetModuleFileNameW(hmodule, &mut buffer) } as usize;
+    if len == 0 {
+        return Err(windows::core::Error::from_win32().into());
+    }
+
+    Ok(PathBuf::from(String::from_utf16_lossy(&buffer[..len])))
+}
+
+/// 获取已加载模块中导出函数的地址
+pub fn get_proc_address(hmodule: HMODULE, function: &str) -> Option<usize> {
+    let name = format!("{}\0", function);
+    unsafe { GetProcAddress(hmodule, PCSTR(name.as_ptr())) }.map(|address| address as usize)
+}
+
+/// 获取按照已加载模块基址重定位后的磁盘映像，同一模块只读取一次磁盘文件
+///
+/// 缓存以模块基址和路径为键，模块卸载后在同一基址加载其他DLL时不会误用旧映像
+///
+/// # 参数
+///
+/// - `hmodule`: 已加载的模块
+///
+/// # 返回值
+///
+/// - `Err`: 读取磁盘文件失败或者PE格式错误
+/// - `Ok(image)`: 映像布局的缓冲区
+pub fn disk_image(hmodule: HMODULE) -> Result<Arc<Vec<u8>>> {
+    type ImageCache = RwLock<HashMap<(usize, PathBuf), Arc<Vec<u8>>>>;
+    static IMAGES: OnceLock<ImageCache> = OnceLock::new();
+    let images = IMAGES.get_or_init(Default::default);
+
+    let key = (hmodule.0 as usize, get_module_path(hmodule)?);
+    if let Some(image) = images
+        .read()
+        .ok()
+        .and_then(|images| images.get(&key).cloned())
+    {
+        return Ok(image);
+    }
+
+    let image = Arc::new(pe::map_from_disk(&key.1, key.0)?);
+    if let Ok(mut images) = images.write() {
+        images.insert(key, image.clone());
+    }
+    Ok(image)
+}
+
+/// 对比函数开头的`PROLOGUE_SIZE`个字节与磁盘文件(已重定位)中的字节
+///
+/// # 参数
+///
+/// - `hmodule`: 函数所在的模块
+/// - `address`: 函数地址
+///
+/// # 返回值
+///
+/// - `Err`: 读取磁盘文件失败
+/// - `Ok(Some((memory, disk)))`: 字节不一致
+/// - `Ok(None)`: 字节一致
+pub fn compare_with_disk(hmodule: HMODULE, address: usize) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let image = PeImage::from_module(hmodule)?;
+    let rva = address.wrapping_sub(image.base());
+    let Some(memory) = image.bytes(rva, PROLOGUE_SIZE) else {
+        return Err(Error::msg("address is outside of the module"));
+    };
+
+    let buffer = disk_image(hmodule)?;
+    let disk = PeImage::from_buffer(&buffer)?
+        .bytes(rva, PROLOGUE_SIZE)
+        .unwrap_or_default();
+    if memory == disk {
+        return Ok(None);
+    }
+
+    warn!(
+        "prologue at {:#x} differs from disk ==> memory: {:02x?}; disk: {:02x?}",
+        address, memory, disk
+    );
+    Ok(Some((memory.to_vec(), disk.to_vec())))
+}
+
+/// 获取指定地址所在的已加载模块句柄
+///
+/// # 返回值
+///
+/// - `Some(hmodule)`: 地址所在模块
+/// - `None`: 地址不属于任何已加载模块
+pub fn get_module_from_address(address: usize) -> Option<HMODULE> {
+    let mut hmodule = HMODULE::default();
+    unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            PCWSTR(address as *const u16),
+            &mut hmodule,
+        )
+    }
+    .ok()?;
+
+    Some(hmodule)
+}
+
+/// 对比指定模块内存中与磁盘文件中的导出函数开头字节，检测inline hook
+///
+/// 从磁盘读取模块文件并按照已加载模块的基址重定位，
+/// 逐个对比可执行节中导出函数开头的`PROLOGUE_SIZE`个字节
+///
+/// # 参数
+///
+/// - `name`: 已加载的模块名，例如`ntdll.dll`
+///
+/// # 返回值
+///
+/// - `Err`: 模块未加载或者读取磁盘文件失败
+/// - `Ok(detours)`: 被修改的导出函数列表，为空则未发现inline hook
+///
+/// # 示例
+///
+/// ```ignore
+/// for detour in scan_inline_hooks("ntdll.dll").unwrap() {
+///     println!("{}!{} is hooked", detour.module, detour.function);
+/// }
+/// ```
+pub fn scan_inline_hooks(name: &str) -> Result<Vec<Detour>> {
+    let hmodule = get_module(name)?;
+    let memory = PeImage::from_module(hmodule)?;
+    let buffer = disk_image(hmodule)?;
+    let disk = PeImage::from_buffer(&buffer)?;
+
+    let mut detours: Vec<Detour> = Vec::new();
+    for export in disk.exports() {
+        if export.forwarded || !disk.is_executable_rva(export.rva) {
+            continue;
+        }
+
+        let rva = export.rva as usize;
+        let (Some(memory_bytes), Some(disk_bytes)) = (
+            memory.bytes(rva, PROLOGUE_SIZE),
+            disk.bytes(rva, PROLOGUE_SIZE),
+        ) else {
+            continue;
+        };
+
+        if memory_bytes != disk_bytes {
+            let function = export
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("#{}", export.ordinal));
+            debug!(
+                "inline hook ==> {}!{}; memory: {:02x?}; disk: {:02x?}",
+                name, function, memory_bytes, disk_bytes
+            );
+            detours.push(Detour {
+                module: name.to_string(),
+                function,
+                address: memory.base() + rva,
+                memory: memory_bytes.to_vec(),
+                disk: disk_bytes.to_vec(),
+            });
+        }
+    }
+
+    Ok(detours)
+}
+
+/// 检测ntdll、kernel32、kernelbase中的inline hook
+///
+/// ScyllaHide、Frida等工具都会在这些模块的导出函数开头写入跳转
+///
+/// # 返回值
+///
+/// - `Err`: 任意一个模块扫描失败
+/// - `Ok(detours)`: 所有模块中被修改的导出函数列表
+pub fn scan_system_inline_hooks() -> Result<Vec<Detour>> {
+    let mut detours: Vec<Detour> = Vec::new();
+    for name in SYSTEM_MODULES {
+        detours.extend(scan_inline_hooks(name)?);
+    }
+
+    Ok(detours)
+}
+
+/// 检查指定模块的导入表，检测IAT hook
+///
+/// 逐个检查IAT表项中已解析的函数地址是否位于导出该函数的模块中；
+/// 导出为转发导出时(例如kernel32!HeapAlloc转发到ntdll)，地址应当位于转发目标模块中
+///
+/// # 参数
+///
+/// - `hmodule`: 需要检查导入表的已加载模块
+///
+/// # 返回值
+///
+/// - `Err`: 模块句柄无效
+/// - `Ok(hooks)`: 被重定向到其他模块或者私有内存的导入函数列表
+pub fn scan_module_iat_hooks(hmodule: HMODULE) -> Result<Vec<IatHook>> {
+    let image = PeImage::from_module(hmodule)?;
+
+    let mut hooks: Vec<IatHook> = Vec::new();
+    let mut exporter: Option<(String, HMODULE, Vec<pe::Export>)> = None;
+    for import in image.imports() {
+        let Some(address) = image.read::<usize>(import.iat_rva as usize) else {
+            continue;
+        };
+
+        // 同一个DLL的导入项是连续的，只在DLL变化时重新解析导出表
+        if exporter.as_ref().map(|(module, ..)| module) != Some(&import.module) {
+            exporter = match get_module(&import.module) {
+                Ok(expected) => {
+                    let exports = PeImage::from_module(expected)?.exports();
+                    Some((import.module.clone(), expected, exports))
+                }
+                Err(e) => {
+                    warn!(
+                        "imported module {} not loaded; error: {:?}",
+                        import.module, e
+                    );
+                    None
+                }
+            };
+        }
+        let Some((_, expected, exports)) = exporter.as_ref() else {
+            continue;
+        };
+
+        let owner = get_module_from_address(address);
+        if owner == Some(*expected)
+            || owner.is_some() && owner == forwarded_module(*expected, exports, &import)
+        {
+            continue;
+        }
+
+        let function = import
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("#{}", import.ordinal.unwrap_or_default()));
+        let owner = owner.and_then(|owner| get_module_path(owner).ok());
+        debug!(
+            "iat hook ==> {}!{}; address: {:#x}; owner: {:?}",
+            import.module, function, address, owner
+        );
+        hooks.push(IatHook {
+            module: import.module.clone(),
+            function,
+            address,
+            owner,
+        });
+    }
+
+    Ok(hooks)
+}
+
+/// 检测当前进程主模块的IAT hook
+///
+/// # 示例
+///
+/// ```ignore
+/// for hook in scan_iat_hooks().unwrap() {
+///     println!("{}!{} ==> {:?}", hook.module, hook.function, hook.owner);
+/// }
+/// ```
+pub fn scan_iat_hooks() -> Result<Vec<IatHook>> {
+    let hmodule = unsafe { GetModuleHandleW(None) }?;
+    // 防转储擦除了PE头时临时恢复
+    anti_dump::with_headers(|| scan_module_iat_hooks(hmodule))
+}
+
+/// 记录指定模块导入表的快照
+///
+/// # 参数
+///
+/// - `hmodule`: 已加载模块
+///
+/// # 返回值
+///
+/// - `Err`: 模块句柄无效
+/// - `Ok(snapshot)`: 所有已解析的IAT表项及其校验和
+pub fn iat_snapshot(hmodule: HMODULE) -> Result<IatSnapshot> {
+    let image = PeImage::from_module(hmodule)?;
+
+    let mut data: Vec<u8> = Vec::new();
+    let mut entries: Vec<IatEntry> = Vec::new();
+    for import in image.imports() {
+        let Some(address) = image.read::<usize>(import.iat_rva as usize) else {
+            continue;
+        };
+        data.extend_from_slice(&import.iat_rva.to_le_bytes());
+        data.extend_from_slice(&address.to_le_bytes());
+        entries.push(IatEntry {
+            function: import
+                .name
+                .unwrap_or_else(|| format!("#{}", import.ordinal.unwrap_or_default())),
+            module: import.module,
+            iat_rva: import.iat_rva,
+            address,
+        });
+    }
+
+    Ok(IatSnapshot {
+        checksum: sha256(&data),
+        entries,
+    })
+}
+
+/// 主模块导入表的基线，第一次记录之后不再改变
+static IAT_BASELINE: OnceLock<IatSnapshot> = OnceLock::new();
+
+/// 记录主模块导入表的快照
+fn main_iat_snapshot() -> Result<IatSnapshot> {
+    let hmodule = unsafe { GetModuleHandleW(None) }?;
+    anti_dump::with_headers(|| iat_snapshot(hmodule))
+}
+
+/// 记录主模块导入表的基线，供`verify_iat_baseline`周期性对比
+///
+/// 应当在初始化时、一次性的`scan_iat_hooks`通过之后调用；已经记录过时不再改变
+///
+/// # 返回值
+///
+/// - `Err`: 读取主模块导入表失败
+/// - `Ok(checksum)`: 基线的校验和
+///
+/// # 示例
+///
+/// ```ignore
+/// assert!(scan_iat_hooks()?.is_empty());
+/// record_iat_baseline()?;
+/// // 之后在后台周期性调用
+/// for entry in verify_iat_baseline()? {
+///     println!("{}!{} patched to {:#x}", entry.module, entry.function, entry.address);
+/// }
+/// ```
+pub fn record_iat_baseline() -> Result<[u8; 32]> {
+    if let Some(baseline) = IAT_BASELINE.get() {
+        return Ok(baseline.checksum);
+    }
+    let snapshot = main_iat_snapshot()?;
+    Ok(IAT_BASELINE.get_or_init(|| snapshot).checksum)
+}
+
+/// 重新计算主模块导入表的校验和并与基线对比，发现第一次检测通过之后才写入的IAT hook
+///
+/// 没有记录基线时先记录基线
+///
+/// # 返回值
+///
+/// - `Err`: 读取主模块导入表失败
+/// - `Ok(entries)`: 函数地址与基线不一致的表项(当前值)，校验和一致时为空
+pub fn verify_iat_baseline() -> Result<Vec<IatEntry>> {
+    record_iat_baseline()?;
+    let Some(baseline) = IAT_BASELINE.get() else {
+        return Ok(Vec::new());
+    };
+    let current = main_iat_snapshot()?;
+    if current.checksum == baseline.checksum {
+        return Ok(Vec::new());
+    }
+
+    let patched: Vec<IatEntry> = current
+        .entries
+        .into_iter()
+        .filter(|entry| {
+            !baseline.entries.iter().any(|original| {
+                original.iat_rva == entry.iat_rva && original.address == entry.address
+            })
+        })
+        .collect();
+    warn!("iat checksum mismatch ==> {:?}", patched);
+    Ok(patched)
+}
+
+/// 导入项对应的导出为转发导出时，返回转发目标模块
+fn forwarded_module(
+    hmodule: HMODULE,
+    exports: &[pe::Export],
+    import: &pe::Import,
+) -> Option<HMODULE> {
+    let export = exports.iter().find(|export| match &import.name {
+        Some(name) => export.name.as_ref() == Some(name),
+        None => Some(export.ordinal) == import.ordinal,
+    })?;
+    if !export.forwarded {
+        return None;
+    }
+
+    // 转发字符串格式为`MODULE.Function`或`MODULE.#ordinal`
+    let forwarder = PeImage::from_module(hmodule)
+        .ok()?
+        .read_cstr(export.rva as usize)?;
+    let (module, _) = forwarder.rsplit_once('.')?;
+    get_module(&format!("{}.dll", module)).ok()
+}
+
+/// 对比指定模块内存中与磁盘文件中的导出表，检测EAT hook
+///
+/// 修改导出表中的函数RVA可以让`GetProcAddress`返回跳板地址，而不需要改动函数本身的字节，
+/// inline hook检测无法发现这种重定向
+///
+/// # 参数
+///
+/// - `name`: 已加载的模块名，例如`kernel32.dll`
+///
+/// # 返回值
+///
+/// - `Err`: 模块未加载或者读取磁盘文件失败
+/// - `Ok(hooks)`: RVA超出映像范围或者与磁盘文件不一致的导出项
+pub fn scan_eat_hooks(name: &str) -> Result<Vec<EatHook>> {
+    let hmodule = get_module(name)?;
+    let memory = PeImage::from_module(hmodule)?;
+    let buffer = disk_image(hmodule)?;
+    let disk: HashMap<u32, u32> = PeImage::from_buffer(&buffer)?
+        .exports()
+        .into_iter()
+        .map(|export| (export.ordinal, export.rva))
+        .collect();
+
+    let mut hooks: Vec<EatHook> = Vec::new();
+    for export in memory.exports() {
+        let disk_rva = disk.get(&export.ordinal).copied();
+        if (export.rva as usize) < memory.size() && disk_rva == Some(export.rva) {
+            continue;
+        }
+
+        let function = export
+            .name
+            .unwrap_or_else(|| format!("#{}", export.ordinal));
+        debug!(
+            "eat hook ==> {}!{}; memory rva: {:#x}; disk rva: {:x?}",
+            name, function, export.rva, disk_rva
+        );
+        hooks.push(EatHook {
+            module: name.to_string(),
+            function,
+            memory_rva: export.rva,
+            disk_rva,
+        });
+    }
+
+    Ok(hooks)
+}
+
+/// 检测ntdll、kernel32、kernelbase中的EAT hook
+///
+/// # 返回值
+///
+/// - `Err`: 任意一个模块扫描失败
+/// - `Ok(hooks)`: 所有模块中被篡改的导出表项
+pub fn scan_system_eat_hooks() -> Result<Vec<EatHook>> {
+    let mut hooks: Vec<EatHook> = Vec::new();
+    for name in SYSTEM_MODULES {
+        hooks.extend(scan_eat_hooks(name)?);
+    }
+
+    Ok(hooks)
+}
+
+fn critical_apis_lock() -> &'static RwLock<Vec<(String, String)>> {
+    static APIS: OnceLock<RwLock<Vec<(String, String)>>> = OnceLock::new();
+    APIS.get_or_init(|| {
+        RwLock::new(
+            CRITICAL_APIS
+                .iter()
+                .map(|(module, function)| {
+                    (module.decrypt().to_string(), function.decrypt().to_string())
+                })
+                .collect(),
+        )
+    })
+}
+
+/// 注册需要检查跳板的关键API
+///
+/// # 示例
+///
+/// ```ignore
+/// register_critical_api("user32.dll", "FindWindowW");
+/// ```
+pub fn register_critical_api(module: &str, function: &str) {
+    if let Ok(mut apis) = critical_apis_lock().write() {
+        if !apis
+            .iter()
+            .any(|(m, f)| m.eq_ignore_ascii_case(module) && f == function)
+        {
+            apis.push((module.to_string(), function.to_string()));
+        }
+    }
+}
+
+/// 当前注册的关键API列表
+pub fn critical_apis() -> Vec<(String, String)> {
+    critical_apis_lock()
+        .read()
+        .map(|apis| apis.clone())
+        .unwrap_or_default()
+}
+
+/// 安全读取当前进程中的内存，地址不可读时返回None
+fn read_memory<T: Copy + Default>(address: usize) -> Option<T> {
+    let mut value = T::default();
+    let read = unsafe {
+        ReadProcessMemory(
+            GetCurrentProcess(),
+            address as *const c_void,
+            &mut value as *mut T as *mut c_void,
+            size_of::<T>(),
+            ptr::null_mut(),
+        )
+    };
+
+    read.as_bool().then_some(value)
+}
+
+/// 解码`jmp rel32`的跳转目标
+fn decode_relative_jump(address: usize, code: &[u8; 16]) -> Option<usize> {
+    (code[0] == 0xe9).then(|| {
+        let rel = i32::from_le_bytes([code[1], code[2], code[3], code[4]]);
+        address.wrapping_add(5).wrapping_add(rel as isize as usize)
+    })
+}
+
+/// 解析函数入口处的跳板指令
+///
+/// # 参数
+///
+/// - `address`: 函数入口地址
+///
+/// # 返回值
+///
+/// - `Some((style, target))`: 跳板类型与跳转目标
+/// - `None`: 入口处不是跳板或者地址不可读
+pub fn detect_trampoline(address: usize) -> Option<(HookStyle, usize)> {
+    let code: [u8; 16] = read_memory(address)?;
+
+    if let Some(target) = decode_relative_jump(address, &code) {
+        return Some((HookStyle::RelativeJump, target));
+    }
+
+    // jmp short -7，跳到热补丁区域(函数前5字节)中的jmp rel32
+    if code[0] == 0xeb && code[1] == 0xf9 {
+        let patch: [u8; 16] = read_memory(address.wrapping_sub(5))?;
+        let target = decode_relative_jump(address.wrapping_sub(5), &patch)?;
+        return Some((HookStyle::HotPatch, target));
+    }
+
+    // jmp [mem]，x64下为RIP相对寻址，x86下为绝对地址，可能带有REX.W前缀
+    let offset = usize::from(code[0] == 0x48);
+    if code[offset] == 0xff && code[offset + 1] == 0x25 {
+        let disp = i32::from_le_bytes(code[offset + 2..offset + 6].try_into().ok()?);
+        let pointer = if cfg!(target_pointer_width = "64") {
+            address
+                .wrapping_add(offset + 6)
+                .wrapping_add(disp as isize as usize)
+        } else {
+            disp as u32 as usize
+        };
+        return Some((HookStyle::AbsoluteJump, read_memory(pointer)?));
+    }
+
+    // push imm32; ret
+    if code[0] == 0x68 && code[5] == 0xc3 {
+        let target = u32::from_le_bytes(code[1..5].try_into().ok()?);
+        return Some((HookStyle::PushRet, target as usize));
+    }
+
+    // mov rax, imm64; jmp rax / mov r11, imm64; jmp r11
+    match code {
+        [0x48, 0xb8, .., _] if code[10..12] == [0xff, 0xe0] => {
+            let target = u64::from_le_bytes(code[2..10].try_into().ok()?);
+            Some((HookStyle::MovJump, target as usize))
+        }
+        [0x49, 0xbb, .., _] if code[10..13] == [0x41, 0xff, 0xe3] => {
+            let target = u64::from_le_bytes(code[2..10].try_into().ok()?);
+            Some((HookStyle::MovJump, target as usize))
+        }
+        // x86: mov eax, imm32; jmp eax
+        [0xb8, .., _] if code[5..7] == [0xff, 0xe0] => {
+            let target = u32::from_le_bytes(code[1..5].try_into().ok()?);
+            Some((HookStyle::MovJump, target as usize))
+        }
+        _ => None,
+    }
+}
+
+/// 扫描已注册关键API入口处的跳板
+///
+/// `jmp`类跳板只有在目标位于私有内存(不属于任何已加载模块)时才报告，
+/// 以排除kernel32到kernelbase这类合法的转发跳转；
+/// `push/ret`、`mov/jmp`与被改写的热补丁前导不会出现在正常的系统函数中，总是报告
+///
+/// # 返回值
+///
+/// 发现的跳板列表，未加载的模块或不存在的函数会被跳过
+///
+/// # 示例
+///
+/// ```ignore
+/// for trampoline in scan_trampolines() {
+///     println!("{}!{} ==> {:?}", trampoline.module, trampoline.function, trampoline.style);
+/// }
+/// ```
+pub fn scan_trampolines() -> Vec<Trampoline> {
+    let mut trampolines: Vec<Trampoline> = Vec::new();
+    for (module, function) in critical_apis() {
+        // 按哈希遍历Ldr与导出表，不经过可能被hook的GetModuleHandleW/GetProcAddress
+        let Some(address) = resolve::resolve(resolve::hash(&module), resolve::hash(&function))
+        else {
+            continue;
+        };
+
+        let Some((style, target)) = detect_trampoline(address) else {
+            continue;
+        };
+        let owner = get_module_from_address(target);
+        let private = owner.is_none();
+        if matches!(style, HookStyle::RelativeJump | HookStyle::AbsoluteJump) && !private {
+            continue;
+        }
+
+        let owner = owner.and_then(|owner| get_module_path(owner).ok());
+        debug!(
+            "trampoline ==> {}!{}; style: {:?}; target: {:#x}; owner: {:?}",
+            module, function, style, target, owner
+        );
+        trampolines.push(Trampoline {
+            module,
+            function,
+            address,
+            style,
+            target,
+            owner,
+        });
+    }
+
+    trampolines
+}
+
+/// 判断函数开头是否被改写为直接返回0
+///
+/// 识别`ret`、`xor eax, eax; ret`、`mov eax, 0; ret`等写法(包括`ret imm16`)
+fn is_return_zero(code: &[u8]) -> bool {
+    let rest = match code {
+        [0x33 | 0x31, 0xc0, rest @ ..] => rest,
+        [0x48, 0x33 | 0x31, 0xc0, rest @ ..] => rest,
+        [0xb8, 0x00, 0x00, 0x00, 0x00, rest @ ..] => rest,
+        _ => code,
+    };
+
+    matches!(rest.first(), Some(0xc3 | 0xc2))
+}
+
+/// 在信任调试检测API的返回值之前，检查它是否被改写为恒返回0
+///
+/// 常见的绕过方式是把`IsDebuggerPresent`、`CheckRemoteDebuggerPresent`的开头改为
+/// `xor eax, eax; ret`。依次检查kernel32与kernelbase中的导出函数：
+///
+/// - 开头是否为直接返回0的指令
+/// - `IsDebuggerPresent`是否与已知的原始代码一致(仅kernelbase)
+/// - 开头字节是否与磁盘文件一致
+///
+/// # 参数
+///
+/// - `function`: API名称，例如`IsDebuggerPresent`
+///
+/// # 返回值
+///
+/// - `Err`: 读取磁盘文件失败
+/// - `Ok(Some(evidence))`: API被改写
+/// - `Ok(None)`: API未被改写
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(evidence) = check_debug_api_neutered("IsDebuggerPresent").unwrap() {
+///     println!("API neutered: {}", evidence);
+/// }
+/// ```
+pub fn check_debug_api_neutered(function: &str) -> Result<Option<String>> {
+    for module in DEBUG_API_MODULES {
+        let Ok(hmodule) = get_module(module) else {
+            continue;
+        };
+        let Some(address) = get_proc_address(hmodule, function) else {
+            continue;
+        };
+        let Some(memory) = read_memory::<[u8; PROLOGUE_SIZE]>(address) else {
+            continue;
+        };
+
+        if is_return_zero(&memory) {
+            debug!("{}!{} returns 0 ==> {:02x?}", module, function, memory);
+            return Ok(Some(format!("{}!{} API neutered", module, function)));
+        }
+
+        if module == "kernelbase.dll"
+            && function == "IsDebuggerPresent"
+            && !IS_DEBUGGER_PRESENT_PROLOGUES
+                .iter()
+                .any(|prologue| memory.starts_with(prologue))
+        {
+            debug!(
+                "{}!{} prologue changed ==> {:02x?}",
+                module, function, memory
+            );
+            return Ok(Some(format!("{}!{} API neutered", module, function)));
+        }
+
+        if compare_with_disk(hmodule, address)?.is_some() {
+            return Ok(Some(format!("{}!{} API neutered", module, function)));
+        }
+    }
+
+    Ok(None)
+}
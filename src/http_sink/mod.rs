@@ -0,0 +1,476 @@
+use crate::engine::Report;
+use crate::logging::{debug, warn};
+use crate::sink::DetectionSink;
+use crate::util;
+use anyhow::{Error, Result};
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// HTTP上报配置
+///
+/// - `url`: 接收检测报告的地址，`http://`或者`https://`(https仅Windows，通过WinHTTP发送)
+/// - `headers`: 附加的请求头，例如鉴权用的`Authorization`
+/// - `batch_size`: 每个请求最多包含的报告数量，队列达到该数量时立即发送
+/// - `flush_interval`: 后台线程定期发送的间隔
+/// - `retries`: 单个请求失败后的重试次数，间隔按次数翻倍
+/// - `retry_delay`: 第一次重试前的等待时间
+/// - `max_queue`: 离线队列的最大长度，超过时丢弃最早的报告
+/// - `timeout`: 连接、发送与接收的超时时间
+/// - `shutdown_timeout`: drop时等待后台线程发送剩余报告的最长时间，超时后不再等待
+#[derive(Debug, Clone)]
+pub struct HttpSinkConfig {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub max_queue: usize,
+    pub timeout: Duration,
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for HttpSinkConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            headers: Vec::new(),
+            batch_size: 16,
+            flush_interval: Duration::from_secs(30),
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+            max_queue: 1024,
+            timeout: Duration::from_secs(10),
+            shutdown_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// 解析后的上报地址
+#[derive(Debug, Clone, PartialEq)]
+struct Url {
+    secure: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self> {
+        let (secure, rest) = match url.split_once("://") {
+            Some(("http", rest)) => (false, rest),
+            Some(("https", rest)) => (true, rest),
+            _ => return Err(Error::msg("unsupported url scheme")),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let default_port = if secure { 443 } else { 80 };
+        // IPv6地址写在方括号中，例如`[::1]:8080`，地址本身也包含冒号
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, default_port),
+                Some((host, port)) => match port.strip_prefix(':') {
+                    Some(port) => (host, port.parse()?),
+                    None => return Err(Error::msg("invalid url authority")),
+                },
+                None => return Err(Error::msg("unterminated ipv6 host")),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse()?),
+                None => (authority, default_port),
+            },
+        };
+        if host.is_empty() {
+            return Err(Error::msg("url host is empty"));
+        }
+
+        Ok(Self {
+            secure,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Host请求头中的主机名，IPv6地址需要加上方括号
+    #[cfg(not(windows))]
+    fn host_header(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port),
+            false => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// 后台线程的控制信号
+enum Signal {
+    Flush,
+    Stop,
+}
+
+/// 后台线程与调用方共享的状态
+struct Shared {
+    config: HttpSinkConfig,
+    url: Url,
+    queue: Mutex<VecDeque<String>>,
+}
+
+impl Shared {
+    /// 取出队列中的所有报告按批次发送，失败时未发送的报告按原顺序放回队列头部
+    ///
+    /// 发送期间不持有队列的锁，其他线程可以继续提交；同一份报告只会被一个线程取出，不会重复发送
+    fn flush(&self) -> Result<usize> {
+        let mut pending = std::mem::take(&mut *self.queue.lock().unwrap());
+        let mut sent = 0;
+        while !pending.is_empty() {
+            let count = pending.len().min(self.config.batch_size.max(1));
+            let batch: Vec<&str> = pending.range(..count).map(String::as_str).collect();
+            let body = format!("[{}]", batch.join(","));
+            if let Err(e) = self.post_with_retry(&body) {
+                self.requeue(pending);
+                return Err(e);
+            }
+            pending.drain(..count);
+            sent += count;
+        }
+        Ok(sent)
+    }
+
+    /// 把发送失败的报告放回队列，排在发送期间新提交的报告之前；超过`max_queue`时丢弃最早的报告
+    fn requeue(&self, mut failed: VecDeque<String>) {
+        let mut queue = self.queue.lock().unwrap();
+        failed.append(&mut queue);
+        let overflow = failed.len().saturating_sub(self.config.max_queue.max(1));
+        if overflow > 0 {
+            warn!("http sink queue full, dropping {} oldest reports", overflow);
+            failed.drain(..overflow);
+        }
+        *queue = failed;
+    }
+
+    /// 发送一个批次，网络错误、5xx、408与429会重试；其他4xx说明请求本身被拒绝，丢弃该批次
+    fn post_with_retry(&self, body: &str) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let error = match post(&self.url, &self.config.headers, body, self.config.timeout) {
+                Ok(status) if (200..300).contains(&status) => {
+                    debug!(
+                        "http sink posted ==> {}; status: {}",
+                        self.config.url, status
+                    );
+                    return Ok(());
+                }
+                Ok(status) if (400..500).contains(&status) && status != 408 && status != 429 => {
+                    warn!("http sink batch rejected; status: {}", status);
+                    return Ok(());
+                }
+                Ok(status) => Error::msg(format!("http status {}", status)),
+                Err(e) => e,
+            };
+
+            if attempt >= self.config.retries {
+                warn!("http sink post failed; error: {:?}", error);
+                return Err(error);
+            }
+            thread::sleep(self.config.retry_delay * 2u32.pow(attempt.min(16)));
+            attempt += 1;
+        }
+    }
+}
+
+/// 将检测报告批量POST到配置的地址，供反作弊、授权服务端集中查看篡改尝试
+///
+/// 报告先进入离线队列，由后台线程按`flush_interval`定期发送，队列达到`batch_size`时立即发送；
+/// 发送失败的报告留在队列中等待下一次发送。请求体是JSON数组，每个元素为
+/// `{"pid":进程ID,"timestamp":Unix毫秒时间戳,"report":Report::to_json()}`
+///
+/// # 注意
+///
+/// drop时会通知后台线程发送队列中剩余的报告，最多等待`shutdown_timeout`，
+/// 超时后不再等待，后台线程在当前请求结束后退出
+///
+/// # 示例
+///
+/// ```ignore
+/// let sink = HttpSink::start(HttpSinkConfig {
+///     url: "https://telemetry.example.com/anti_debug".to_string(),
+///     headers: vec![("Authorization".to_string(), "Bearer token".to_string())],
+///     ..Default::default()
+/// })?;
+/// sink.submit(&Engine::default().run());
+/// ```
+pub struct HttpSink {
+    shared: Arc<Shared>,
+    sender: Sender<Signal>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl HttpSink {
+    /// 解析上报地址并启动后台发送线程
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 地址格式错误
+    /// - `Ok(sink)`: 上报器
+    pub fn start(config: HttpSinkConfig) -> Result<Self> {
+        let shared = Arc::new(Shared {
+            url: Url::parse(&config.url)?,
+            config,
+            queue: Mutex::new(VecDeque::new()),
+        });
+        let (sender, receiver) = mpsc::channel();
+
+        let worker_shared = Arc::clone(&shared);
+        let worker = util::spawn(move || loop {
+            let signal = receiver.recv_timeout(worker_shared.config.flush_interval);
+            // 失败的报告留在队列中，等待下一次发送
+            let _ = worker_shared.flush();
+            if matches!(
+                signal,
+                Ok(Signal::Stop) | Err(RecvTimeoutError::Disconnected)
+            ) {
+                return;
+            }
+        });
+
+        Ok(Self {
+            shared,
+            sender,
+            worker: Some(worker),
+        })
+    }
+
+    /// 将检测报告加入队列，队列达到`batch_size`时通知后台线程立即发送
+    pub fn submit(&self, report: &Report) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        let item = format!(
+            r#"{{"pid":{},"timestamp":{},"report":{}}}"#,
+            std::process::id(),
+            timestamp,
+            report.to_json()
+        );
+
+        let length = {
+            let mut queue = self.shared.queue.lock().unwrap();
+            if queue.len() >= self.shared.config.max_queue.max(1) {
+                warn!("http sink queue full, dropping oldest report");
+                queue.pop_front();
+            }
+            queue.push_back(item);
+            queue.len()
+        };
+        if length >= self.shared.config.batch_size.max(1) {
+            let _ = self.sender.send(Signal::Flush);
+        }
+    }
+
+    /// 队列中等待发送的报告数量
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().len()
+    }
+
+    /// 在当前线程中立即发送队列中的所有报告
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 重试后仍然失败，未发送的报告留在队列中
+    /// - `Ok(count)`: 发送的报告数量
+    pub fn flush(&self) -> Result<usize> {
+        self.shared.flush()
+    }
+}
+
+impl DetectionSink for HttpSink {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    /// 只加入队列，由后台线程发送
+    fn emit(&self, report: &Report) -> Result<()> {
+        self.submit(report);
+        Ok(())
+    }
+}
+
+impl Drop for HttpSink {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Signal::Stop);
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+        let deadline = Instant::now() + self.shared.config.shutdown_timeout;
+        while !worker.is_finished() {
+            if Instant::now() >= deadline {
+                warn!("http sink worker still sending, detached");
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = worker.join();
+    }
+}
+
+/// 通过WinHTTP发送POST请求，支持https与系统代理
+///
+/// # 返回值
+///
+/// - `Ok(status)`: HTTP状态码
+#[cfg(windows)]
+fn post(url: &Url, headers: &[(String, String)], body: &str, timeout: Duration) -> Result<u16> {
+    use crate::util::to_wide;
+    use std::{ffi::c_void, mem::size_of, ptr};
+    use windows::{
+        core::PCWSTR,
+        Win32::Networking::WinHttp::{
+            WinHttpCloseHandle, WinHttpConnect, WinHttpOpen, WinHttpOpenRequest,
+            WinHttpQueryHeaders, WinHttpReceiveResponse, WinHttpSendRequest, WinHttpSetTimeouts,
+            WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY, WINHTTP_FLAG_SECURE, WINHTTP_OPEN_REQUEST_FLAGS,
+            WINHTTP_QUERY_FLAG_NUMBER, WINHTTP_QUERY_STATUS_CODE,
+        },
+    };
+
+    /// WinHTTP句柄，离开作用域时关闭
+    struct Handle(*mut c_void);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            let _ = unsafe { WinHttpCloseHandle(self.0) };
+        }
+    }
+
+    fn check(handle: *mut c_void) -> Result<Handle> {
+        match handle.is_null() {
+            true => Err(windows::core::Error::from_win32().into()),
+            false => Ok(Handle(handle)),
+        }
+    }
+
+    let agent = to_wide("anti_debug");
+    let session = check(unsafe {
+        WinHttpOpen(
+            PCWSTR(agent.as_ptr()),
+            WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            0,
+        )
+    })?;
+    let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+    unsafe { WinHttpSetTimeouts(session.0, millis, millis, millis, millis) }?;
+
+    let host = to_wide(&url.host);
+    let connection =
+        check(unsafe { WinHttpConnect(session.0, PCWSTR(host.as_ptr()), url.port, 0) })?;
+    let verb = to_wide("POST");
+    let path = to_wide(&url.path);
+    let flags = match url.secure {
+        true => WINHTTP_FLAG_SECURE,
+        false => WINHTTP_OPEN_REQUEST_FLAGS(0),
+    };
+    let request = check(unsafe {
+        WinHttpOpenRequest(
+            connection.0,
+            PCWSTR(verb.as_ptr()),
+            PCWSTR(path.as_ptr()),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            ptr::null(),
+            flags,
+        )
+    })?;
+
+    let header_lines: Vec<String> = Some("Content-Type: application/json".to_string())
+        .into_iter()
+        .chain(
+            headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value)),
+        )
+        .collect();
+    let header_text: Vec<u16> = header_lines.join("\r\n").encode_utf16().collect();
+    unsafe {
+        WinHttpSendRequest(
+            request.0,
+            Some(&header_text),
+            Some(body.as_ptr().cast()),
+            body.len() as u32,
+            body.len() as u32,
+            0,
+        )
+    }?;
+    unsafe { WinHttpReceiveResponse(request.0, ptr::null_mut()) }?;
+
+    let mut status: u32 = 0;
+    let mut size = size_of::<u32>() as u32;
+    let mut index: u32 = 0;
+    unsafe {
+        WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER,
+            PCWSTR::null(),
+            Some(&mut status as *mut u32 as *mut c_void),
+            &mut size,
+            &mut index,
+        )
+    }?;
+
+    Ok(status as u16)
+}
+
+/// 通过TCP发送HTTP/1.1 POST请求，只支持http
+///
+/// # 返回值
+///
+/// - `Ok(status)`: HTTP状态码
+#[cfg(not(windows))]
+fn post(url: &Url, headers: &[(String, String)], body: &str, timeout: Duration) -> Result<u16> {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpStream, ToSocketAddrs},
+    };
+
+    if url.secure {
+        return Err(Error::msg("https is only supported on windows"));
+    }
+    let address = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::msg("url host not resolved"))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path,
+        url.host_header(),
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream.write_all(request.as_bytes())?;
+
+    // 状态行格式为`HTTP/1.1 200 OK`
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| Error::msg("invalid http response"))
+}
@@ -0,0 +1,223 @@
+use crate::logging::{debug, warn};
+use crate::util::is_registry_key_exists;
+use crate::{
+    exception::{self, Caught},
+    obf,
+    obfstr::ObfStr,
+    peb::WinPeb,
+    timing::{self, rdtsc},
+    vm,
+};
+use anyhow::Result;
+use std::{arch::asm, hint::black_box, ptr};
+use windows::Win32::{
+    Foundation::EXCEPTION_PRIV_INSTRUCTION, System::Registry::HKEY_LOCAL_MACHINE,
+};
+
+/// 用户态执行`rdmsr`时探测的MSR
+///
+/// - `0x40000000`: Hyper-V规范中的HV_X64_MSR_GUEST_OS_ID，虚拟化调试器常用的合成MSR
+/// - `0x40000001`: HV_X64_MSR_HYPERCALL
+/// - `0xC0000080`: IA32_EFER
+/// - `0x1337`: 不存在的MSR
+pub const PROBED_MSRS: [u32; 4] = [0x4000_0000, 0x4000_0001, 0xC000_0080, 0x1337];
+
+/// `rdmsr`指令的长度(`0F 32`)
+const RDMSR_SIZE: usize = 2;
+
+/// 在VEH保护下于用户态执行`rdmsr`，返回捕获到的异常与指令地址
+fn probe_rdmsr(msr: u32) -> Result<(Option<Caught>, usize)> {
+    let mut address: usize = 0;
+    let caught = exception::guarded(&[EXCEPTION_PRIV_INSTRUCTION], RDMSR_SIZE, || unsafe {
+        #[cfg(target_arch = "x86_64")]
+        asm!(
+            "lea {address}, [rip + 2f]",
+            "2:",
+            "rdmsr",
+            address = out(reg) address,
+            inout("ecx") msr => _,
+            out("eax") _,
+            out("edx") _,
+        );
+        #[cfg(target_arch = "x86")]
+        asm!(
+            "lea {address}, [2f]",
+            "2:",
+            "rdmsr",
+            address = out(reg) address,
+            inout("ecx") msr => _,
+            out("eax") _,
+            out("edx") _,
+        );
+    })?;
+
+    Ok((caught, address))
+}
+
+/// 在用户态读取MSR，检查CPU的特权检查是否被虚拟化调试器改变
+///
+/// CPL3执行`rdmsr`时CPU在VM exit之前就产生#GP，系统以`EXCEPTION_PRIV_INSTRUCTION`送达，
+/// 异常地址指向`rdmsr`本身。拦截MSR访问的虚拟化调试器(HyperDbg等)处理不当时，
+/// 指令会返回合成的值而不产生异常，或者异常被调试器吞掉、地址不一致
+///
+/// # 返回值
+///
+/// - `Err`: FLS分配或者VEH注册失败
+/// - `Ok(evidence)`: 与CPU行为不一致的MSR，为空则正常
+///
+/// # 示例
+///
+/// ```ignore
+/// for evidence in check_msr_probe().unwrap() {
+///     println!("msr anomaly: {}", evidence);
+/// }
+/// ```
+pub fn check_msr_probe() -> Result<Vec<String>> {
+    let mut evidence: Vec<String> = Vec::new();
+    for msr in PROBED_MSRS {
+        let (caught, address) = probe_rdmsr(msr)?;
+        debug!("rdmsr {:#x} ==> address: {:#x}; {:?}", msr, address, caught);
+        match caught {
+            None => evidence.push(format!("rdmsr {:#x} did not fault", msr)),
+            Some(caught) if caught.address != address || caught.count != 1 => {
+                evidence.push(format!(
+                    "rdmsr {:#x} faulted at {:#x} ({} times)",
+                    msr, caught.address, caught.count
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    if !evidence.is_empty() {
+        warn!("msr probe anomaly ==> {:?}", evidence);
+    }
+    Ok(evidence)
+}
+
+/// 读取代码字节比读取普通数据多出的周期数超过该值时认为存在EPT hook
+///
+/// EPT hook把代码页拆分为只可执行的视图与可读的视图，执行之后的第一次读取会产生EPT violation与VM exit，
+/// 开销在数千周期以上；之后的读取停留在可读视图中，耗时与普通读取相同，直到页面再次被执行
+pub const EPT_READ_THRESHOLD: u64 = 1500;
+
+/// 测量一次读取的周期数
+fn read_once(address: *const u8) -> u64 {
+    let start = rdtsc();
+    black_box(unsafe { ptr::read_volatile(address) });
+    rdtsc().wrapping_sub(start)
+}
+
+/// 每轮先执行函数所在的页面，再测量第一次读取函数代码与读取栈上数据的周期数
+///
+/// # 参数
+///
+/// - `function`: 函数地址
+/// - `execute`: 执行该函数的回调，必须足够轻量并且没有副作用
+/// - `rounds`: 测量次数，返回其中的中位数以排除调度与缓存干扰
+///
+/// # 返回值
+///
+/// - `(code, data)`: 读取代码与读取数据的周期数中位数
+pub fn ept_read_latency(function: usize, execute: fn(), rounds: u32) -> (u64, u64) {
+    let rounds = rounds.max(1) as usize;
+    let mut data = [0u8; 64];
+    let mut code_samples: Vec<u64> = Vec::with_capacity(rounds);
+    let mut data_samples: Vec<u64> = Vec::with_capacity(rounds);
+
+    for round in 0..rounds {
+        execute();
+        code_samples.push(read_once(function as *const u8));
+
+        execute();
+        unsafe { ptr::write_volatile(data.as_mut_ptr(), round as u8) };
+        data_samples.push(read_once(data.as_ptr()));
+    }
+
+    let code = timing::median(&mut code_samples);
+    let data = timing::median(&mut data_samples);
+    debug!(
+        "ept read latency ==> function: {:#x}; code: {}; data: {}",
+        function, code, data
+    );
+    (code, data)
+}
+
+/// 检测自身热点函数上的EPT hook
+///
+/// 虚拟化调试器通过EPT把代码页拆分为执行视图与读取视图来隐藏断点与hook，
+/// 每次执行这些函数之后第一次读取其代码字节都会触发VM exit，耗时明显高于读取普通数据。
+/// 只选择可以反复执行而没有副作用的函数
+///
+/// # 返回值
+///
+/// - 读取耗时异常的函数，为空则未发现EPT hook
+pub fn check_ept_hooks() -> Vec<String> {
+    let functions: [(&str, usize, fn()); 3] = [
+        (
+            "IsDebuggerPresent",
+            WinPeb::peb_being_debugged as *const () as usize,
+            || {
+                black_box(WinPeb::peb_being_debugged());
+            },
+        ),
+        (
+            "check_cpuid_hypervisor",
+            vm::check_cpuid_hypervisor as *const () as usize,
+            || {
+                black_box(vm::check_cpuid_hypervisor());
+            },
+        ),
+        ("rdtsc", rdtsc as *const () as usize, || {
+            black_box(rdtsc());
+        }),
+    ];
+
+    let mut evidence: Vec<String> = Vec::new();
+    for (name, function, execute) in functions {
+        let (code, data) = ept_read_latency(function, execute, 32);
+        if code > data.saturating_add(EPT_READ_THRESHOLD) {
+            warn!(
+                "ept hook suspected ==> {}; code: {}; data: {}",
+                name, code, data
+            );
+            evidence.push(format!("{} read in {} cycles (data {})", name, code, data));
+        }
+    }
+    evidence
+}
+
+/// HyperDbg安装的服务名
+const HYPERDBG_SERVICES: [ObfStr; 2] = [obf!("hyperkd"), obf!("hprdbgkd")];
+
+/// 扫描HyperDbg留下的服务
+///
+/// HyperDbg驱动创建的设备对象与其他调试工具的设备一起由特征列表中`[object]`段落描述，
+/// 见`environment::find_blacklisted_objects`
+///
+/// # 返回值
+///
+/// - 命中的服务，为空则未发现痕迹
+///
+/// # 示例
+///
+/// ```ignore
+/// for artifact in scan_hyperdbg_artifacts() {
+///     println!("hyperdbg artifact: {}", artifact);
+/// }
+/// ```
+pub fn scan_hyperdbg_artifacts() -> Vec<String> {
+    let mut artifacts: Vec<String> = Vec::new();
+
+    for service in HYPERDBG_SERVICES {
+        let service = service.decrypt();
+        let key = format!("SYSTEM\\CurrentControlSet\\Services\\{}", service);
+        if is_registry_key_exists(HKEY_LOCAL_MACHINE, &key) {
+            artifacts.push(format!("service {}", service));
+        }
+    }
+
+    debug!("hyperdbg artifacts ==> {:?}", artifacts);
+
+    artifacts
+}
@@ -0,0 +1,284 @@
+#[cfg(feature = "stealth-imports")]
+use crate::resolve;
+use std::ffi::c_void;
+#[cfg(feature = "stealth-imports")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "stealth-imports")]
+use windows::Win32::Foundation::{SetLastError, ERROR_PROC_NOT_FOUND, STATUS_PROCEDURE_NOT_FOUND};
+use windows::{
+    core::{Error, Result, PCSTR, PCWSTR},
+    Wdk::{
+        Foundation::OBJECT_INFORMATION_CLASS, System::SystemInformation::SYSTEM_INFORMATION_CLASS,
+    },
+    Win32::{
+        Foundation::{BOOL, FARPROC, HANDLE, HMODULE, NTSTATUS},
+        System::{
+            Diagnostics::{
+                Debug::{CONTEXT, DEBUG_EVENT, PVECTORED_EXCEPTION_HANDLER},
+                ToolHelp::CREATE_TOOLHELP_SNAPSHOT_FLAGS,
+            },
+            Memory::PAGE_PROTECTION_FLAGS,
+            Threading::PROCESS_ACCESS_RIGHTS,
+        },
+    },
+};
+
+/// 加密保存的函数指针，保存的是函数地址与密钥的异或值，内存中不出现明文地址
+#[cfg(feature = "stealth-imports")]
+#[doc(hidden)]
+#[derive(Default)]
+pub struct Slot(AtomicUsize);
+
+#[cfg(feature = "stealth-imports")]
+impl Slot {
+    pub const fn new() -> Self {
+        Self(AtomicUsize::new(0))
+    }
+
+    /// 读取函数地址，第一次调用时通过PEB解析器查找并加密保存
+    ///
+    /// # 参数
+    ///
+    /// - `key`: 解密密钥
+    /// - `module`: 模块文件名的哈希
+    /// - `function`: 导出函数名的哈希
+    pub fn get(&self, key: usize, module: u32, function: u32) -> Option<usize> {
+        match self.0.load(Ordering::Acquire) {
+            0 => {
+                let address = resolve::resolve(module, function)?;
+                self.0.store(address ^ key, Ordering::Release);
+                Some(address)
+            }
+            encrypted => Some(encrypted ^ key),
+        }
+    }
+}
+
+/// 导出函数解析失败时包装函数的返回值，与系统函数失败时的返回值一致
+#[cfg(feature = "stealth-imports")]
+#[doc(hidden)]
+pub trait Unresolved {
+    fn unresolved() -> Self;
+}
+
+#[cfg(feature = "stealth-imports")]
+impl Unresolved for BOOL {
+    fn unresolved() -> Self {
+        BOOL(0)
+    }
+}
+
+#[cfg(feature = "stealth-imports")]
+impl Unresolved for NTSTATUS {
+    fn unresolved() -> Self {
+        STATUS_PROCEDURE_NOT_FOUND
+    }
+}
+
+#[cfg(feature = "stealth-imports")]
+impl Unresolved for HANDLE {
+    fn unresolved() -> Self {
+        HANDLE::default()
+    }
+}
+
+#[cfg(feature = "stealth-imports")]
+impl Unresolved for HMODULE {
+    fn unresolved() -> Self {
+        HMODULE::default()
+    }
+}
+
+#[cfg(feature = "stealth-imports")]
+impl Unresolved for FARPROC {
+    fn unresolved() -> Self {
+        None
+    }
+}
+
+#[cfg(feature = "stealth-imports")]
+impl Unresolved for *mut c_void {
+    fn unresolved() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+#[cfg(feature = "stealth-imports")]
+impl Unresolved for () {
+    fn unresolved() -> Self {}
+}
+
+/// 生成与系统导出同名、同签名(原始ABI)的包装函数
+///
+/// 默认直接链接到系统DLL；开启`stealth-imports` feature后，导入表中不再出现这些函数，
+/// 第一次调用时按名称哈希解析，函数指针加密保存在各自的`Slot`中。
+/// 解析失败时不会panic，而是把最后错误设置为`ERROR_PROC_NOT_FOUND`并返回函数失败时的值
+/// (`FALSE`、`STATUS_PROCEDURE_NOT_FOUND`、空句柄或空指针)
+macro_rules! imports {
+    ($($dll:literal {
+        $(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*
+    })*) => {
+        $($(
+            #[doc = concat!("`", $dll, "!", stringify!($name), "`，签名与系统导出一致")]
+            ///
+            /// # Safety
+            ///
+            /// 与直接调用系统导出的要求相同
+            #[cfg(feature = "stealth-imports")]
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            pub unsafe fn $name($($arg: $ty),*) -> $ret {
+                static SLOT: Slot = Slot::new();
+                const MODULE: u32 = resolve::hash(concat!($dll, ".dll"));
+                const FUNCTION: u32 = resolve::hash(stringify!($name));
+                const KEY: usize = crate::obfstr::derive_key(file!(), MODULE, FUNCTION) as usize;
+                let Some(address) = SLOT.get(KEY, MODULE, FUNCTION) else {
+                    SetLastError(ERROR_PROC_NOT_FOUND);
+                    return Unresolved::unresolved();
+                };
+                let function: unsafe extern "system" fn($($ty),*) -> $ret =
+                    resolve::function(address);
+                function($($arg),*)
+            }
+
+            #[doc = concat!("`", $dll, "!", stringify!($name), "`，签名与系统导出一致")]
+            ///
+            /// # Safety
+            ///
+            /// 与直接调用系统导出的要求相同
+            #[cfg(not(feature = "stealth-imports"))]
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            pub unsafe fn $name($($arg: $ty),*) -> $ret {
+                #[link(name = $dll)]
+                extern "system" {
+                    fn $name($($arg: $ty),*) -> $ret;
+                }
+                $name($($arg),*)
+            }
+        )*)*
+    };
+}
+
+imports! {
+    "kernel32" {
+        pub fn IsDebuggerPresent() -> BOOL;
+        pub fn CheckRemoteDebuggerPresent(hprocess: HANDLE, present: *mut BOOL) -> BOOL;
+        pub fn GetThreadContext(hthread: HANDLE, context: *mut CONTEXT) -> BOOL;
+        pub fn SetThreadContext(hthread: HANDLE, context: *const CONTEXT) -> BOOL;
+        pub fn OutputDebugStringW(message: PCWSTR) -> ();
+        pub fn AddVectoredExceptionHandler(
+            first: u32,
+            handler: PVECTORED_EXCEPTION_HANDLER,
+        ) -> *mut c_void;
+        pub fn ReadProcessMemory(
+            hprocess: HANDLE,
+            address: *const c_void,
+            buffer: *mut c_void,
+            size: usize,
+            read: *mut usize,
+        ) -> BOOL;
+        pub fn DebugActiveProcess(pid: u32) -> BOOL;
+        pub fn DebugSetProcessKillOnExit(kill_on_exit: BOOL) -> BOOL;
+        pub fn WaitForDebugEvent(event: *mut DEBUG_EVENT, milliseconds: u32) -> BOOL;
+        pub fn ContinueDebugEvent(pid: u32, tid: u32, status: NTSTATUS) -> BOOL;
+        pub fn GetProcAddress(hmodule: HMODULE, name: PCSTR) -> FARPROC;
+    }
+    "ntdll" {
+        pub fn NtQuerySystemInformation(
+            class: SYSTEM_INFORMATION_CLASS,
+            buffer: *mut c_void,
+            length: u32,
+            return_length: *mut u32,
+        ) -> NTSTATUS;
+        pub fn NtClose(handle: HANDLE) -> NTSTATUS;
+        pub fn NtQueryObject(
+            handle: HANDLE,
+            class: OBJECT_INFORMATION_CLASS,
+            buffer: *mut c_void,
+            length: u32,
+            return_length: *mut u32,
+        ) -> NTSTATUS;
+    }
+}
+
+/// 原始ABI的包装，由下面签名与`windows`中同名函数一致的函数调用
+mod raw {
+    use super::*;
+
+    imports! {
+        "kernel32" {
+            pub fn GetModuleHandleW(name: PCWSTR) -> HMODULE;
+            pub fn OpenProcess(access: PROCESS_ACCESS_RIGHTS, inherit: BOOL, pid: u32) -> HANDLE;
+            pub fn CreateToolhelp32Snapshot(
+                flags: CREATE_TOOLHELP_SNAPSHOT_FLAGS,
+                pid: u32,
+            ) -> HANDLE;
+            pub fn VirtualProtect(
+                address: *const c_void,
+                size: usize,
+                protect: PAGE_PROTECTION_FLAGS,
+                old_protect: *mut PAGE_PROTECTION_FLAGS,
+            ) -> BOOL;
+        }
+    }
+}
+
+/// `kernel32!GetModuleHandleW`，`None`表示当前进程的主模块
+///
+/// # Safety
+///
+/// 与直接调用系统导出的要求相同
+#[allow(non_snake_case)]
+pub unsafe fn GetModuleHandleW(name: Option<PCWSTR>) -> Result<HMODULE> {
+    let hmodule = raw::GetModuleHandleW(name.unwrap_or(PCWSTR::null()));
+    (!hmodule.is_invalid())
+        .then_some(hmodule)
+        .ok_or_else(Error::from_win32)
+}
+
+/// `kernel32!OpenProcess`
+///
+/// # Safety
+///
+/// 与直接调用系统导出的要求相同
+#[allow(non_snake_case)]
+pub unsafe fn OpenProcess(
+    access: PROCESS_ACCESS_RIGHTS,
+    inherit: bool,
+    pid: u32,
+) -> Result<HANDLE> {
+    let handle = raw::OpenProcess(access, inherit.into(), pid);
+    (!handle.is_invalid())
+        .then_some(handle)
+        .ok_or_else(Error::from_win32)
+}
+
+/// `kernel32!CreateToolhelp32Snapshot`
+///
+/// # Safety
+///
+/// 与直接调用系统导出的要求相同
+#[allow(non_snake_case)]
+pub unsafe fn CreateToolhelp32Snapshot(
+    flags: CREATE_TOOLHELP_SNAPSHOT_FLAGS,
+    pid: u32,
+) -> Result<HANDLE> {
+    let handle = raw::CreateToolhelp32Snapshot(flags, pid);
+    (!handle.is_invalid())
+        .then_some(handle)
+        .ok_or_else(Error::from_win32)
+}
+
+/// `kernel32!VirtualProtect`
+///
+/// # Safety
+///
+/// 与直接调用系统导出的要求相同
+#[allow(non_snake_case)]
+pub unsafe fn VirtualProtect(
+    address: *const c_void,
+    size: usize,
+    protect: PAGE_PROTECTION_FLAGS,
+    old_protect: *mut PAGE_PROTECTION_FLAGS,
+) -> Result<()> {
+    raw::VirtualProtect(address, size, protect, old_protect).ok()
+}
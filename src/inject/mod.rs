@@ -0,0 +1,177 @@
+use crate::logging::{debug, warn};
+use crate::{
+    hook::{get_module, get_proc_address},
+    imports::OpenProcess,
+    thread::DisableDebug,
+    util::to_wide,
+    wow64::ProcessArch,
+};
+use anyhow::{Error, Result};
+use std::{
+    ffi::c_void,
+    mem::{size_of_val, transmute},
+    path::Path,
+    time::Duration,
+};
+use windows::Win32::{
+    Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0},
+    System::{
+        Diagnostics::Debug::WriteProcessMemory,
+        Memory::{VirtualAllocEx, VirtualFreeEx, MEM_COMMIT, MEM_RELEASE, PAGE_READWRITE},
+        Threading::{
+            CreateRemoteThread, GetExitCodeThread, WaitForSingleObject, LPTHREAD_START_ROUTINE,
+            PROCESS_CREATE_THREAD, PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION,
+            PROCESS_VM_READ, PROCESS_VM_WRITE,
+        },
+    },
+};
+
+/// 等待远程`LoadLibraryW`线程结束的超时时间
+const INJECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 在目标进程中创建加载线程的方式
+///
+/// - `RemoteThread`: `CreateRemoteThread`
+/// - `NtCreateThreadEx`: 直接调用`NtCreateThreadEx`并带有`THREAD_CREATE_FLAGS_HIDE_FROM_DEBUGGER`，
+///   目标中已附加的调试器收不到该线程的创建与DLL加载事件
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InjectMethod {
+    RemoteThread,
+    NtCreateThreadEx,
+}
+
+/// 在目标进程中以`LoadLibraryW`为入口创建线程
+fn create_loader_thread(
+    hprocess: HANDLE,
+    start: LPTHREAD_START_ROUTINE,
+    argument: *mut c_void,
+    method: InjectMethod,
+) -> Result<HANDLE> {
+    match method {
+        InjectMethod::RemoteThread => {
+            Ok(unsafe { CreateRemoteThread(hprocess, None, 0, start, Some(argument), 0, None) }?)
+        }
+        InjectMethod::NtCreateThreadEx => {
+            DisableDebug::create_thread(hprocess, start, Some(argument))
+        }
+    }
+}
+
+/// 通过远程线程+`LoadLibraryW`把DLL加载到目标进程中
+///
+/// 目标必须与当前进程架构相同，`kernel32.dll`在同一次启动中的所有同架构进程中基址相同，
+/// 当前进程中`LoadLibraryW`的地址在目标中同样有效
+///
+/// # 参数
+///
+/// - `hprocess`: 目标进程句柄，需要`PROCESS_CREATE_THREAD`、`PROCESS_VM_OPERATION`、
+///   `PROCESS_VM_WRITE`与`PROCESS_QUERY_INFORMATION`权限
+/// - `dll`: DLL路径，会先转换为绝对路径
+/// - `method`: 创建远程线程的方式
+///
+/// # 返回值
+///
+/// - `Err`: 架构不同、写入路径或创建远程线程失败、超时，或者目标中`LoadLibraryW`返回NULL
+/// - `Ok(())`: 加载成功
+///
+/// # 注意
+///
+/// 以`CREATE_SUSPENDED`创建、尚未恢复的进程也可以注入，远程线程会先完成加载器的初始化
+pub fn inject_library(hprocess: HANDLE, dll: &Path, method: InjectMethod) -> Result<()> {
+    if ProcessArch::of(hprocess)? != ProcessArch::current() {
+        return Err(Error::msg("target architecture differs from the injector"));
+    }
+    let path = to_wide(&std::path::absolute(dll)?.to_string_lossy());
+    let Some(load_library) = get_proc_address(get_module("kernel32.dll")?, "LoadLibraryW") else {
+        return Err(Error::msg("LoadLibraryW not found"));
+    };
+
+    let remote = unsafe {
+        VirtualAllocEx(
+            hprocess,
+            None,
+            size_of_val(path.as_slice()),
+            MEM_COMMIT,
+            PAGE_READWRITE,
+        )
+    };
+    if remote.is_null() {
+        return Err(windows::core::Error::from_win32().into());
+    }
+
+    let mut finished = true;
+    let result = (|| -> Result<()> {
+        unsafe {
+            WriteProcessMemory(
+                hprocess,
+                remote,
+                path.as_ptr() as *const c_void,
+                size_of_val(path.as_slice()),
+                None,
+            )
+        }?;
+
+        let start = unsafe { transmute::<usize, LPTHREAD_START_ROUTINE>(load_library) };
+        let hthread = create_loader_thread(hprocess, start, remote, method)?;
+        let waited = unsafe { WaitForSingleObject(hthread, INJECT_TIMEOUT.as_millis() as u32) };
+        let mut exit_code: u32 = 0;
+        let queried = unsafe { GetExitCodeThread(hthread, &mut exit_code) };
+        let _ = unsafe { CloseHandle(hthread) };
+
+        if waited != WAIT_OBJECT_0 {
+            finished = false;
+            return Err(Error::msg("remote LoadLibraryW timed out"));
+        }
+        queried?;
+        // 线程退出码是模块基址的低32位
+        if exit_code == 0 {
+            return Err(Error::msg("remote LoadLibraryW failed"));
+        }
+        Ok(())
+    })();
+
+    // 超时的远程线程可能仍在读取路径，不能释放
+    if finished {
+        let _ = unsafe { VirtualFreeEx(hprocess, remote, 0, MEM_RELEASE) };
+    }
+    debug!("inject {:?} ({:?}) ==> {:?}", dll, method, result);
+    result
+}
+
+/// 把DLL注入到已经运行的进程中，用于给无法重新编译的程序追加保护
+///
+/// # 参数
+///
+/// - `pid`: 目标进程ID
+/// - `dll`: DLL路径
+/// - `method`: 创建远程线程的方式
+///
+/// # 返回值
+///
+/// - `Err`: 打开目标进程失败(权限不足或者目标是受保护进程)，或者注入失败
+/// - `Ok(())`: 加载成功
+///
+/// # 示例
+///
+/// ```ignore
+/// inject_process(4242, Path::new("protect.dll"), InjectMethod::NtCreateThreadEx).unwrap();
+/// ```
+pub fn inject_process(pid: u32, dll: &Path, method: InjectMethod) -> Result<()> {
+    let hprocess = unsafe {
+        OpenProcess(
+            PROCESS_CREATE_THREAD
+                | PROCESS_QUERY_INFORMATION
+                | PROCESS_VM_OPERATION
+                | PROCESS_VM_READ
+                | PROCESS_VM_WRITE,
+            false,
+            pid,
+        )
+    }?;
+    let result = inject_library(hprocess, dll, method);
+    let _ = unsafe { CloseHandle(hprocess) };
+    if let Err(e) = &result {
+        warn!("inject {:?} into {} failed; error: {:?}", dll, pid, e);
+    }
+    result
+}
@@ -0,0 +1,537 @@
+use crate::logging::{debug, warn};
+#[cfg(windows)]
+use crate::{
+    anti_dump,
+    imports::GetModuleHandleW,
+    pe::{self, PeImage},
+};
+use crate::{
+    engine::{Category, Severity, Verdict},
+    scan, sink, util,
+};
+use anyhow::{Error, Result};
+use std::{env, fs, path::Path, ptr};
+use std::{
+    sync::OnceLock,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+#[cfg(windows)]
+use windows::Win32::{Foundation::HMODULE, System::Diagnostics::Debug::IMAGE_SCN_MEM_EXECUTE};
+
+/// HMAC-SHA256的长度
+pub const MAC_SIZE: usize = 32;
+
+/// 嵌入区的魔数，签名工具在可执行文件中搜索它来定位HMAC的写入位置
+const MAGIC_SIZE: usize = 16;
+
+/// 嵌入区：魔数 + HMAC，构建完成后由签名工具填入HMAC
+///
+/// 嵌入区位于只读数据节(段)中，而HMAC只覆盖可执行节(段)，因此写入HMAC不会改变被校验的内容
+#[used]
+static INTEGRITY_BLOB: [u8; MAGIC_SIZE + MAC_SIZE] = *b"AD-INTEGRITY-V1\0\
+    \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+
+/// 全局的HMAC密钥
+static KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// 完整性校验结果
+///
+/// - `memory`: 内存中的可执行节与嵌入的HMAC一致
+/// - `disk`: 磁盘文件中的可执行节与嵌入的HMAC一致
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntegrityReport {
+    pub memory: bool,
+    pub disk: bool,
+}
+
+impl IntegrityReport {
+    /// 内存或磁盘是否被篡改
+    pub fn is_tampered(&self) -> bool {
+        !self.memory || !self.disk
+    }
+}
+
+/// 设置全局HMAC密钥，只有第一次设置生效
+///
+/// # 返回值
+///
+/// - `Err`: 密钥已经设置过
+/// - `Ok(())`: 设置成功
+pub fn set_key(key: &[u8]) -> Result<()> {
+    KEY.set(key.to_vec())
+        .map_err(|_| Error::msg("integrity key already set"))
+}
+
+/// 计算映像中所有可执行节的HMAC
+///
+/// 重定位位置上的字节在计算前被清零，因此结果与加载基址无关，
+/// 磁盘文件与内存中的映像可以得到相同的HMAC
+///
+/// # 参数
+///
+/// - `image`: 映像布局的PE视图
+/// - `key`: HMAC密钥
+#[cfg(windows)]
+pub fn compute_mac(image: &PeImage, key: &[u8]) -> [u8; MAC_SIZE] {
+    let relocations = image.relocations();
+    let mut message: Vec<u8> = Vec::new();
+
+    for section in image.sections() {
+        if section.Characteristics.0 & IMAGE_SCN_MEM_EXECUTE.0 == 0 {
+            continue;
+        }
+
+        let start = section.VirtualAddress as usize;
+        let size = unsafe { section.Misc.VirtualSize } as usize;
+        let Some(bytes) = image.bytes(start, size) else {
+            continue;
+        };
+
+        let offset = message.len();
+        message.extend_from_slice(&section.Name);
+        message.extend_from_slice(&(size as u32).to_le_bytes());
+        let data_offset = message.len();
+        message.extend_from_slice(bytes);
+
+        for &(rva, width) in &relocations {
+            if rva >= start && rva + width <= start + size {
+                let at = data_offset + rva - start;
+                message[at..at + width].fill(0);
+            }
+        }
+
+        debug!(
+            "integrity section ==> {}; rva: {:#x}; size: {:#x}; offset: {:#x}",
+            String::from_utf8_lossy(&section.Name).trim_end_matches('\0'),
+            start,
+            size,
+            offset
+        );
+    }
+
+    hmac_sha256(key, &message)
+}
+
+/// 读取嵌入的HMAC，使用volatile读取防止编译器把全零的初始值常量折叠
+fn embedded_mac() -> Option<[u8; MAC_SIZE]> {
+    let blob = unsafe { ptr::read_volatile(&INTEGRITY_BLOB) };
+    let mut mac = [0u8; MAC_SIZE];
+    mac.copy_from_slice(&blob[MAGIC_SIZE..]);
+    mac.iter().any(|&b| b != 0).then_some(mac)
+}
+
+/// 计算PE文件中所有可执行节的HMAC
+#[cfg(windows)]
+fn file_mac(path: &Path, _file: &[u8], key: &[u8]) -> Result<[u8; MAC_SIZE]> {
+    let image = pe::map_from_disk(path, 0)?;
+    Ok(compute_mac(&PeImage::from_buffer(&image)?, key))
+}
+
+/// 校验内存与磁盘中的可执行节
+///
+/// # 参数
+///
+/// - `key`: HMAC密钥，需要与签名工具使用的一致
+///
+/// # 返回值
+///
+/// - `Err`: 可执行文件没有嵌入HMAC，或者读取磁盘文件失败
+/// - `Ok(report)`: 校验结果
+///
+/// # 示例
+///
+/// ```ignore
+/// let report = verify(b"secret").unwrap();
+/// if report.is_tampered() {
+///     println!("executable is patched");
+/// }
+/// ```
+#[cfg(windows)]
+pub fn verify(key: &[u8]) -> Result<IntegrityReport> {
+    let Some(expected) = embedded_mac() else {
+        warn!("integrity hmac is not embedded");
+        return Err(Error::msg("integrity hmac is not embedded"));
+    };
+
+    let hmodule: HMODULE = unsafe { GetModuleHandleW(None) }?;
+    // 防转储擦除了PE头时临时恢复
+    let memory_mac = anti_dump::with_headers(|| -> Result<_> {
+        Ok(compute_mac(&PeImage::from_module(hmodule)?, key))
+    })?;
+
+    let buffer = pe::map_from_disk(env::current_exe()?, hmodule.0 as usize)?;
+    let disk_mac = compute_mac(&PeImage::from_buffer(&buffer)?, key);
+
+    let report = IntegrityReport {
+        memory: memory_mac == expected,
+        disk: disk_mac == expected,
+    };
+    debug!("integrity report ==> {:?}", report);
+
+    Ok(report)
+}
+
+/// ELF程序头的长度(64位)
+#[cfg(target_os = "linux")]
+const ELF_PHDR_SIZE: usize = 56;
+
+/// 可加载段与程序头表段的类型，以及可执行段的标志
+#[cfg(target_os = "linux")]
+const PT_LOAD: u32 = 1;
+#[cfg(target_os = "linux")]
+const PT_PHDR: u32 = 6;
+#[cfg(target_os = "linux")]
+const PF_X: u32 = 1;
+
+/// ELF程序头中与校验有关的字段
+#[cfg(target_os = "linux")]
+struct Segment {
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    size: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl Segment {
+    fn is_executable(&self) -> bool {
+        self.kind == PT_LOAD && self.flags & PF_X != 0
+    }
+}
+
+/// 解析64位ELF的程序头表
+#[cfg(target_os = "linux")]
+fn program_headers(table: &[u8]) -> Vec<Segment> {
+    let u32_at = |header: &[u8], at: usize| {
+        u32::from_le_bytes(header[at..at + 4].try_into().unwrap_or_default())
+    };
+    let usize_at = |header: &[u8], at: usize| {
+        u64::from_le_bytes(header[at..at + 8].try_into().unwrap_or_default()) as usize
+    };
+
+    table
+        .chunks_exact(ELF_PHDR_SIZE)
+        .map(|header| Segment {
+            kind: u32_at(header, 0),
+            flags: u32_at(header, 4),
+            offset: usize_at(header, 8),
+            vaddr: usize_at(header, 16),
+            size: usize_at(header, 32),
+        })
+        .collect()
+}
+
+/// 解析ELF文件的程序头表
+///
+/// # 返回值
+///
+/// - `Err`: 不是64位小端的ELF文件，或者程序头表越界
+/// - `Ok(segments)`: 所有段
+#[cfg(target_os = "linux")]
+fn file_segments(file: &[u8]) -> Result<Vec<Segment>> {
+    if file.len() < 64 || &file[..4] != b"\x7fELF" || file[4] != 2 || file[5] != 1 {
+        warn!("not a 64-bit little-endian elf file");
+        return Err(Error::msg("not a 64-bit little-endian elf file"));
+    }
+
+    let phoff = u64::from_le_bytes(file[0x20..0x28].try_into()?) as usize;
+    let phnum = u16::from_le_bytes(file[0x38..0x3a].try_into()?) as usize;
+    let table = file
+        .get(phoff..phoff + phnum * ELF_PHDR_SIZE)
+        .ok_or_else(|| Error::msg("elf program header table out of range"))?;
+
+    Ok(program_headers(table))
+}
+
+/// 计算所有可执行段的HMAC，`bytes`返回段在文件中的内容
+#[cfg(target_os = "linux")]
+fn segments_mac<'a, F>(segments: &[Segment], key: &[u8], mut bytes: F) -> Result<[u8; MAC_SIZE]>
+where
+    F: FnMut(&Segment) -> Option<&'a [u8]>,
+{
+    let mut message: Vec<u8> = Vec::new();
+    for segment in segments.iter().filter(|segment| segment.is_executable()) {
+        let data = bytes(segment).ok_or_else(|| Error::msg("executable segment out of range"))?;
+        message.extend_from_slice(&(segment.offset as u64).to_le_bytes());
+        message.extend_from_slice(&(segment.size as u64).to_le_bytes());
+        message.extend_from_slice(data);
+        debug!(
+            "integrity segment ==> offset: {:#x}; vaddr: {:#x}; size: {:#x}",
+            segment.offset, segment.vaddr, segment.size
+        );
+    }
+
+    if message.is_empty() {
+        warn!("no executable segment");
+        return Err(Error::msg("no executable segment"));
+    }
+    Ok(hmac_sha256(key, &message))
+}
+
+/// 计算ELF文件中所有可执行段的HMAC
+#[cfg(target_os = "linux")]
+fn file_mac(_path: &Path, file: &[u8], key: &[u8]) -> Result<[u8; MAC_SIZE]> {
+    segments_mac(&file_segments(file)?, key, |segment| {
+        file.get(segment.offset..segment.offset + segment.size)
+    })
+}
+
+/// 计算当前进程内存中所有可执行段的HMAC
+///
+/// 程序头表的地址来自辅助向量`AT_PHDR`，加载偏移由`PT_PHDR`段记录的虚拟地址计算
+#[cfg(target_os = "linux")]
+fn memory_mac(key: &[u8]) -> Result<[u8; MAC_SIZE]> {
+    let (phdr, phnum) = unsafe {
+        (
+            libc::getauxval(libc::AT_PHDR) as usize,
+            libc::getauxval(libc::AT_PHNUM) as usize,
+        )
+    };
+    if phdr == 0 {
+        warn!("AT_PHDR is not available");
+        return Err(Error::msg("AT_PHDR is not available"));
+    }
+
+    let table = unsafe { std::slice::from_raw_parts(phdr as *const u8, phnum * ELF_PHDR_SIZE) };
+    let segments = program_headers(table);
+    let Some(bias) = segments
+        .iter()
+        .find(|segment| segment.kind == PT_PHDR)
+        .map(|segment| phdr.wrapping_sub(segment.vaddr))
+    else {
+        warn!("no PT_PHDR segment");
+        return Err(Error::msg("no PT_PHDR segment"));
+    };
+
+    segments_mac(&segments, key, |segment| {
+        Some(unsafe {
+            std::slice::from_raw_parts(bias.wrapping_add(segment.vaddr) as *const u8, segment.size)
+        })
+    })
+}
+
+/// 校验内存与磁盘中的可执行段(Linux)
+///
+/// 与Windows相同，分别计算内存与磁盘文件中所有可执行段的HMAC，并与签名工具嵌入的HMAC比较，
+/// 上层的处理逻辑不需要区分平台
+///
+/// # 参数
+///
+/// - `key`: HMAC密钥，需要与签名工具使用的一致
+///
+/// # 返回值
+///
+/// - `Err`: 可执行文件没有嵌入HMAC，或者读取磁盘文件失败
+/// - `Ok(report)`: 校验结果
+///
+/// # 注意
+///
+/// 只支持64位ELF
+#[cfg(target_os = "linux")]
+pub fn verify(key: &[u8]) -> Result<IntegrityReport> {
+    let Some(expected) = embedded_mac() else {
+        warn!("integrity hmac is not embedded");
+        return Err(Error::msg("integrity hmac is not embedded"));
+    };
+
+    let path = env::current_exe()?;
+    let disk_mac = file_mac(&path, &fs::read(&path)?, key)?;
+
+    let report = IntegrityReport {
+        memory: memory_mac(key)? == expected,
+        disk: disk_mac == expected,
+    };
+    debug!("integrity report ==> {:?}", report);
+
+    Ok(report)
+}
+
+/// 使用`set_key`设置的全局密钥校验
+///
+/// # 返回值
+///
+/// - `Err`: 没有嵌入HMAC或者读取磁盘文件失败
+/// - `Ok(None)`: 没有设置密钥，不进行校验
+/// - `Ok(Some(report))`: 校验结果
+pub fn verify_with_global_key() -> Result<Option<IntegrityReport>> {
+    match KEY.get() {
+        Some(key) => verify(key).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// 启动后台线程定期校验
+///
+/// # 参数
+///
+/// - `key`: HMAC密钥
+/// - `interval`: 校验间隔
+/// - `on_tamper`: 发现篡改时的回调，调用前篡改结果已经通过`sink::publish`交给全局输出目标
+///
+/// # 示例
+///
+/// ```ignore
+/// spawn_monitor(b"secret".to_vec(), Duration::from_secs(30), |report| {
+///     println!("tampered: {:?}", report);
+///     std::process::exit(1);
+/// });
+/// ```
+pub fn spawn_monitor<F>(key: Vec<u8>, interval: Duration, on_tamper: F) -> JoinHandle<()>
+where
+    F: Fn(IntegrityReport) + Send + 'static,
+{
+    util::spawn(move || loop {
+        match verify(&key) {
+            Ok(report) if report.is_tampered() => {
+                sink::publish(Verdict::observed(
+                    "integrity_monitor",
+                    Category::Tampering,
+                    Severity::Confirmed,
+                    format!("memory: {}; disk: {}", report.memory, report.disk),
+                ));
+                on_tamper(report)
+            }
+            Ok(_) => {}
+            Err(e) => warn!("integrity verify failed; error: {:?}", e),
+        }
+        thread::sleep(interval);
+    })
+}
+
+/// 计算可执行文件的HMAC并写入其嵌入区，供构建后的签名工具使用
+///
+/// # 参数
+///
+/// - `path`: 链接了本库的可执行文件
+/// - `key`: HMAC密钥
+///
+/// # 返回值
+///
+/// - `Err`: 文件中没有或者有多个嵌入区，或者读写文件失败
+/// - `Ok(mac)`: 写入的HMAC
+pub fn sign_file<P: AsRef<Path>>(path: P, key: &[u8]) -> Result<[u8; MAC_SIZE]> {
+    let path = path.as_ref();
+    let mut file = fs::read(path)?;
+    let mac = file_mac(path, &file, key)?;
+
+    let magic = &INTEGRITY_BLOB[..MAGIC_SIZE];
+    let position = scan::find_bytes(&file, magic);
+    let duplicate = position.and_then(|position| scan::find_bytes(&file[position + 1..], magic));
+    let (Some(position), None) = (position, duplicate) else {
+        warn!("integrity blob not found or not unique in {:?}", path);
+        return Err(Error::msg("integrity blob not found or not unique"));
+    };
+
+    // 链接器没有把只读数据与代码分开时，写入HMAC会改变被校验的可执行段
+    #[cfg(target_os = "linux")]
+    if file_segments(&file)?.iter().any(|segment| {
+        segment.is_executable()
+            && (segment.offset..segment.offset + segment.size).contains(&position)
+    }) {
+        warn!(
+            "integrity blob is inside an executable segment of {:?}",
+            path
+        );
+        return Err(Error::msg("integrity blob is inside an executable segment"));
+    }
+
+    file[position + MAGIC_SIZE..position + MAGIC_SIZE + MAC_SIZE].copy_from_slice(&mac);
+    fs::write(path, &file)?;
+    debug!("sign {:?} ==> blob offset: {:#x}", path, position);
+
+    Ok(mac)
+}
+
+/// SHA-256轮常量
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// 计算SHA-256
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (chunk, s) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+/// 计算HMAC-SHA256
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; MAC_SIZE] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256(&inner));
+
+    sha256(&outer)
+}
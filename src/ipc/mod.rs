@@ -0,0 +1,487 @@
+use crate::engine::{Category, Report, Verdict};
+use crate::logging::{debug, warn};
+use crate::{
+    json,
+    sink::DetectionSink,
+    util::{get_process_path, to_wide},
+    watchdog::read_exact,
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, GENERIC_WRITE, HANDLE},
+        Storage::FileSystem::{
+            CreateFileW, WriteFile, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_NONE, OPEN_EXISTING,
+            PIPE_ACCESS_INBOUND,
+        },
+        System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, GetNamedPipeClientProcessId,
+            GetNamedPipeServerProcessId, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+        },
+    },
+};
+
+/// 单条消息的最大长度，超过时视为协议错误并断开连接
+pub const MAX_FRAME: usize = 64 * 1024;
+
+/// 管道缓冲区大小
+const PIPE_BUFFER: u32 = 4096;
+
+/// 管道对端的认证方式，连接后通过`GetNamedPipeClientProcessId`/`GetNamedPipeServerProcessId`确认
+///
+/// - `Any`: 不检查，只用于测试
+/// - `Pid`: 对端进程ID
+/// - `ImagePath`: 对端进程映像文件的完整路径，不区分大小写；只比较文件名时任何同名程序都会被信任
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerAuth {
+    Any,
+    Pid(u32),
+    ImagePath(PathBuf),
+}
+
+impl PeerAuth {
+    /// 判断对端进程是否可信
+    pub fn verify(&self, pid: u32) -> bool {
+        match self {
+            PeerAuth::Any => true,
+            PeerAuth::Pid(expected) => pid == *expected,
+            PeerAuth::ImagePath(expected) => {
+                let Ok(expected) = std::path::absolute(expected) else {
+                    return false;
+                };
+                get_process_path(pid).is_some_and(|path| {
+                    path.to_string_lossy()
+                        .eq_ignore_ascii_case(&expected.to_string_lossy())
+                })
+            }
+        }
+    }
+}
+
+/// 发送给外部守护进程的检测事件
+///
+/// - `pid`: 产生事件的进程ID
+/// - `technique`: 检测技术名称
+/// - `category`: 技术类别
+/// - `weight`: 计分权重
+/// - `confidence`: 命中的置信度(见`Verdict::confidence`)，守护进程可以据此过滤
+/// - `evidence`: 命中时的证据
+/// - `timestamp`: 产生时间，Unix毫秒时间戳
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub pid: u32,
+    pub technique: String,
+    pub category: Category,
+    pub weight: u32,
+    pub confidence: u32,
+    pub evidence: Option<String>,
+    pub timestamp: u64,
+}
+
+impl Event {
+    /// 由检测结果生成事件，时间戳为当前时间
+    pub fn from_verdict(verdict: &Verdict) -> Self {
+        Self {
+            pid: std::process::id(),
+            technique: verdict.name.to_string(),
+            category: verdict.category,
+            weight: verdict.weight,
+            confidence: verdict.confidence,
+            evidence: verdict.evidence.clone(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// 序列化为JSON
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"pid":{},"technique":{},"category":{},"weight":{},"confidence":{},"evidence":{},"timestamp":{}}}"#,
+            self.pid,
+            json::quote(&self.technique),
+            json::quote(self.category.name()),
+            self.weight,
+            self.confidence,
+            json::quote_option(self.evidence.as_deref()),
+            self.timestamp
+        )
+    }
+
+    /// 从JSON反序列化
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 格式错误或者缺少字段
+    /// - `Ok(event)`: 事件
+    ///
+    /// # 注意
+    ///
+    /// 旧版本的客户端不发送`confidence`，缺少该字段时置信度为0
+    pub fn from_json(text: &str) -> Result<Self> {
+        let value = json::parse(text)?;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| Error::msg(format!("event field missing ==> {}", name)))
+        };
+        let number = |name: &str| {
+            field(name)?
+                .as_i64()
+                .ok_or_else(|| Error::msg(format!("event field is not a number ==> {}", name)))
+        };
+        let category = field("category")?
+            .as_str()
+            .and_then(Category::from_name)
+            .ok_or_else(|| Error::msg("invalid event category"))?;
+        let evidence = field("evidence")?;
+
+        Ok(Self {
+            pid: u32::try_from(number("pid")?)?,
+            technique: field("technique")?
+                .as_str()
+                .ok_or_else(|| Error::msg("invalid event technique"))?
+                .to_string(),
+            category,
+            weight: u32::try_from(number("weight")?)?,
+            confidence: match value.get("confidence") {
+                Some(_) => u32::try_from(number("confidence")?)?,
+                None => 0,
+            },
+            evidence: match evidence.is_null() {
+                true => None,
+                false => Some(
+                    evidence
+                        .as_str()
+                        .ok_or_else(|| Error::msg("invalid event evidence"))?
+                        .to_string(),
+                ),
+            },
+            timestamp: u64::try_from(number("timestamp")?)?,
+        })
+    }
+}
+
+/// 完整的管道路径
+fn pipe_path(name: &str) -> Vec<u16> {
+    to_wide(&format!("\\\\.\\pipe\\{}", name))
+}
+
+/// 写入一条消息：4字节小端长度 + UTF-8编码的JSON
+fn write_frame(pipe: HANDLE, payload: &str) -> Result<()> {
+    if payload.len() > MAX_FRAME {
+        return Err(Error::msg("ipc frame too large"));
+    }
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload.as_bytes());
+
+    let mut written: u32 = 0;
+    unsafe { WriteFile(pipe, Some(&frame), Some(&mut written), None) }?;
+    if written as usize != frame.len() {
+        return Err(Error::msg("ipc frame partially written"));
+    }
+    Ok(())
+}
+
+/// 读取一条消息，见`write_frame`
+fn read_frame(pipe: HANDLE) -> Result<String> {
+    let mut length = [0u8; 4];
+    read_exact(pipe, &mut length)?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_FRAME {
+        return Err(Error::msg("ipc frame too large"));
+    }
+
+    let mut payload = vec![0u8; length];
+    read_exact(pipe, &mut payload)?;
+    Ok(String::from_utf8(payload)?)
+}
+
+/// 检测事件的发送端，运行在受保护进程(或者注入的保护DLL)中
+///
+/// 事件先进入待发送队列，连接断开时自动重连，重连失败的事件保留在队列中，
+/// 下一次`send`/`flush`时继续发送；队列满时丢弃最早的事件
+///
+/// # 示例
+///
+/// ```ignore
+/// let mut client = EventClient::new("my_protector", PeerAuth::ImagePath("C:\\Program Files\\Protector\\protector.exe".into()));
+/// let report = Engine::default().run();
+/// client.send_report(&report)?;
+/// ```
+pub struct EventClient {
+    name: String,
+    auth: PeerAuth,
+    pipe: Option<HANDLE>,
+    pending: VecDeque<Event>,
+    pub max_pending: usize,
+    pub retries: u32,
+    pub retry_delay: Duration,
+}
+
+// 管道句柄只由持有者使用
+unsafe impl Send for EventClient {}
+
+impl EventClient {
+    /// 创建发送端，第一次发送时才连接
+    ///
+    /// # 参数
+    ///
+    /// - `name`: 管道名，不含`\\.\pipe\`前缀
+    /// - `auth`: 管道服务端(守护进程)的认证方式
+    pub fn new(name: &str, auth: PeerAuth) -> Self {
+        Self {
+            name: name.to_string(),
+            auth,
+            pipe: None,
+            pending: VecDeque::new(),
+            max_pending: 256,
+            retries: 3,
+            retry_delay: Duration::from_millis(100),
+        }
+    }
+
+    /// 是否已经连接
+    pub fn is_connected(&self) -> bool {
+        self.pipe.is_some()
+    }
+
+    /// 待发送的事件数量
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// 连接管道并认证服务端
+    fn connect(&mut self) -> Result<HANDLE> {
+        if let Some(pipe) = self.pipe {
+            return Ok(pipe);
+        }
+
+        let path = pipe_path(&self.name);
+        let pipe = unsafe {
+            CreateFileW(
+                PCWSTR(path.as_ptr()),
+                GENERIC_WRITE.0,
+                FILE_SHARE_NONE,
+                None,
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                HANDLE::default(),
+            )
+        }?;
+
+        let mut server_pid: u32 = 0;
+        let verified = unsafe { GetNamedPipeServerProcessId(pipe, &mut server_pid) }.is_ok()
+            && self.auth.verify(server_pid);
+        if !verified {
+            warn!("untrusted ipc pipe server ==> {}", server_pid);
+            let _ = unsafe { CloseHandle(pipe) };
+            return Err(Error::msg("ipc pipe server authentication failed"));
+        }
+
+        debug!("ipc connected ==> {}; server: {}", self.name, server_pid);
+        self.pipe = Some(pipe);
+        Ok(pipe)
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(pipe) = self.pipe.take() {
+            let _ = unsafe { CloseHandle(pipe) };
+        }
+    }
+
+    /// 将事件加入队列并尝试发送
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 重连失败，事件保留在队列中
+    /// - `Ok(count)`: 本次发送的事件数量
+    pub fn send(&mut self, event: Event) -> Result<usize> {
+        self.enqueue(event);
+        self.flush()
+    }
+
+    /// 发送一次检测中所有命中的技术
+    pub fn send_report(&mut self, report: &Report) -> Result<usize> {
+        for verdict in report.detections() {
+            self.enqueue(Event::from_verdict(verdict));
+        }
+        self.flush()
+    }
+
+    fn enqueue(&mut self, event: Event) {
+        if self.pending.len() >= self.max_pending.max(1) {
+            warn!("ipc queue full, dropping oldest event");
+            self.pending.pop_front();
+        }
+        self.pending.push_back(event);
+    }
+
+    /// 发送队列中的所有事件，写入失败时断开并重连，最多重试`retries`次，间隔按次数递增
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 重连失败，未发送的事件保留在队列中
+    /// - `Ok(count)`: 发送的事件数量
+    pub fn flush(&mut self) -> Result<usize> {
+        let mut sent = 0;
+        let mut attempt = 0;
+        while let Some(event) = self.pending.front() {
+            let payload = event.to_json();
+            let result = self.connect().and_then(|pipe| write_frame(pipe, &payload));
+            match result {
+                Ok(()) => {
+                    self.pending.pop_front();
+                    sent += 1;
+                    attempt = 0;
+                }
+                Err(e) => {
+                    self.disconnect();
+                    attempt += 1;
+                    if attempt > self.retries {
+                        warn!("ipc send failed; error: {:?}", e);
+                        return Err(e);
+                    }
+                    thread::sleep(self.retry_delay * attempt);
+                }
+            }
+        }
+        Ok(sent)
+    }
+}
+
+/// 作为引擎的输出目标时需要包装在`Mutex`中，发送失败的事件保留在队列中
+impl DetectionSink for Mutex<EventClient> {
+    fn name(&self) -> &str {
+        "ipc"
+    }
+
+    fn emit(&self, report: &Report) -> Result<()> {
+        let mut client = self
+            .lock()
+            .map_err(|_| Error::msg("ipc client lock poisoned"))?;
+        client.send_report(report).map(|_| ())
+    }
+}
+
+impl Drop for EventClient {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+/// 检测事件的接收端，运行在外部守护/启动进程中
+///
+/// # 示例
+///
+/// ```ignore
+/// let server = EventServer::new("my_protector", PeerAuth::Pid(child_pid));
+/// let connection = server.accept()?;
+/// while let Ok(event) = connection.recv() {
+///     println!("{} ==> {:?}", event.technique, event.evidence);
+/// }
+/// ```
+pub struct EventServer {
+    name: String,
+    auth: PeerAuth,
+}
+
+impl EventServer {
+    /// # 参数
+    ///
+    /// - `name`: 管道名，不含`\\.\pipe\`前缀
+    /// - `auth`: 管道客户端(受保护进程)的认证方式
+    pub fn new(name: &str, auth: PeerAuth) -> Self {
+        Self {
+            name: name.to_string(),
+            auth,
+        }
+    }
+
+    /// 创建一个管道实例并阻塞等待客户端连接，拒绝远程客户端与认证失败的客户端
+    ///
+    /// 每次调用创建一个新的实例，可以在循环中调用并为每个连接启动线程
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 创建管道失败、连接失败或者客户端认证失败
+    /// - `Ok(connection)`: 已认证的连接
+    pub fn accept(&self) -> Result<EventConnection> {
+        let path = pipe_path(&self.name);
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                PCWSTR(path.as_ptr()),
+                PIPE_ACCESS_INBOUND,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                PIPE_BUFFER,
+                PIPE_BUFFER,
+                0,
+                None,
+            )
+        };
+        if pipe.is_invalid() {
+            return Err(windows::core::Error::from_win32().into());
+        }
+        let mut connection = EventConnection { pipe, pid: 0 };
+
+        match unsafe { ConnectNamedPipe(pipe, None) } {
+            Err(e) if e.code() != ERROR_PIPE_CONNECTED.to_hresult() => return Err(e.into()),
+            _ => {}
+        }
+
+        let mut client_pid: u32 = 0;
+        unsafe { GetNamedPipeClientProcessId(pipe, &mut client_pid) }?;
+        if !self.auth.verify(client_pid) {
+            warn!("untrusted ipc pipe client ==> {}", client_pid);
+            let _ = unsafe { DisconnectNamedPipe(pipe) };
+            return Err(Error::msg("ipc pipe client authentication failed"));
+        }
+
+        debug!("ipc client connected ==> {}", client_pid);
+        connection.pid = client_pid;
+        Ok(connection)
+    }
+}
+
+/// 已认证的客户端连接，离开作用域时关闭管道实例
+pub struct EventConnection {
+    pipe: HANDLE,
+    pid: u32,
+}
+
+unsafe impl Send for EventConnection {}
+
+impl EventConnection {
+    /// 客户端进程ID
+    pub fn peer_pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// 阻塞读取下一条事件
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 客户端断开或者消息格式错误
+    /// - `Ok(event)`: 事件
+    pub fn recv(&self) -> Result<Event> {
+        Event::from_json(&read_frame(self.pipe)?)
+    }
+}
+
+impl Drop for EventConnection {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.pipe) };
+    }
+}
@@ -0,0 +1,268 @@
+use crate::logging::{debug, warn};
+use crate::util::to_wide;
+use anyhow::{Error, Result};
+use std::{ffi::c_void, mem::size_of, ptr::null_mut};
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{CloseHandle, ERROR_SUCCESS, GENERIC_READ, HANDLE},
+        Storage::FileSystem::{
+            CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+        },
+        System::{
+            Diagnostics::Etw::{QueryAllTracesW, EVENT_TRACE_PROPERTIES},
+            ProcessStatus::{EnumDeviceDrivers, GetDeviceDriverBaseNameW},
+            Threading::GetCurrentProcess,
+            IO::DeviceIoControl,
+        },
+    },
+};
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__cpuid_count;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::__cpuid_count;
+
+/// 系统自带的Intel PT驱动(ipt.sys)创建的设备
+pub const IPT_DEVICE: &str = "\\\\.\\IPT";
+
+/// `CTL_CODE(FILE_DEVICE_UNKNOWN, 1, METHOD_BUFFERED, FILE_ANY_ACCESS)`
+const IOCTL_IPT_REQUEST: u32 = 0x22_0004;
+
+/// IPT_INPUT_TYPE中查询进程跟踪与核心跟踪的请求
+const IPT_QUERY_PROCESS_TRACING: u32 = 9;
+const IPT_QUERY_CORE_TRACING: u32 = 10;
+
+/// ipt.sys请求与应答的结构版本
+const IPT_BUFFER_MAJOR_VERSION: u16 = 1;
+
+/// ETW会话名称中与处理器跟踪相关的特征(去掉分隔符后比较，不区分大小写)
+const SESSION_PATTERNS: [&str; 3] = ["windowsperf", "intelpt", "processortrace"];
+
+/// QueryAllTracesW最多返回的会话数量
+const MAX_SESSIONS: usize = 64;
+
+/// 会话名称与日志文件名各自预留的字符数
+const SESSION_NAME_CHARS: usize = 1024;
+
+/// ipt.sys的IPT_INPUT_BUFFER
+#[repr(C)]
+#[derive(Default)]
+struct IptInputBuffer {
+    major_version: u16,
+    minor_version: u16,
+    input_type: u32,
+    arguments: [u64; 4],
+}
+
+/// ipt.sys的IPT_OUTPUT_BUFFER
+#[repr(C)]
+#[derive(Default)]
+struct IptOutputBuffer {
+    major_version: u16,
+    minor_version: u16,
+    output_type: u32,
+    result: [u64; 4],
+}
+
+/// 读取CPUID leaf 7 EBX第25位，判断处理器是否支持Intel Processor Trace
+pub fn is_pt_supported() -> bool {
+    let result = __cpuid_count(7, 0);
+    debug!("CPUID leaf 7 ==> ebx: {:#x}", result.ebx);
+    result.ebx & (1 << 25) != 0
+}
+
+/// 枚举已加载的内核驱动文件名
+pub fn loaded_drivers() -> Result<Vec<String>> {
+    let mut bases: Vec<*mut c_void> = vec![null_mut(); 1024];
+    let mut needed: u32 = 0;
+    loop {
+        let size = (bases.len() * size_of::<*mut c_void>()) as u32;
+        unsafe { EnumDeviceDrivers(bases.as_mut_ptr(), size, &mut needed) }?;
+        if needed <= size {
+            break;
+        }
+        bases.resize(needed as usize / size_of::<*mut c_void>() + 16, null_mut());
+    }
+    bases.truncate(needed as usize / size_of::<*mut c_void>());
+
+    let mut name = [0u16; 260];
+    Ok(bases
+        .into_iter()
+        .filter_map(|base| {
+            let length = unsafe { GetDeviceDriverBaseNameW(base, &mut name) } as usize;
+            (length > 0).then(|| String::from_utf16_lossy(&name[..length]))
+        })
+        .collect())
+}
+
+/// 枚举当前用户可见的ETW会话名称
+pub fn trace_sessions() -> Result<Vec<String>> {
+    let name_offset = size_of::<EVENT_TRACE_PROPERTIES>();
+    let file_offset = name_offset + SESSION_NAME_CHARS * size_of::<u16>();
+    let entry_size = file_offset + SESSION_NAME_CHARS * size_of::<u16>();
+
+    // 使用u64缓冲区保证EVENT_TRACE_PROPERTIES的对齐
+    let mut buffers: Vec<Vec<u64>> = (0..MAX_SESSIONS)
+        .map(|_| vec![0u64; entry_size.div_ceil(size_of::<u64>())])
+        .collect();
+    let mut properties: Vec<*mut EVENT_TRACE_PROPERTIES> = buffers
+        .iter_mut()
+        .map(|buffer| {
+            let property = buffer.as_mut_ptr() as *mut EVENT_TRACE_PROPERTIES;
+            unsafe {
+                (*property).Wnode.BufferSize = entry_size as u32;
+                (*property).LoggerNameOffset = name_offset as u32;
+                (*property).LogFileNameOffset = file_offset as u32;
+            }
+            property
+        })
+        .collect();
+
+    let mut count: u32 = 0;
+    let status = unsafe { QueryAllTracesW(&mut properties, &mut count) };
+    if status != ERROR_SUCCESS {
+        warn!("QueryAllTracesW failed; error code: {:?}", status);
+        return Err(Error::msg("QueryAllTracesW failed"));
+    }
+
+    Ok(properties
+        .iter()
+        .take(count as usize)
+        .map(|property| {
+            let name = unsafe {
+                std::slice::from_raw_parts(
+                    (*property as *const u8).add(name_offset) as *const u16,
+                    SESSION_NAME_CHARS,
+                )
+            };
+            let length = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+            String::from_utf16_lossy(&name[..length])
+        })
+        .collect())
+}
+
+/// ETW会话名称是否与处理器跟踪相关
+pub fn is_trace_session(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    let compact: String = lower.chars().filter(|c| c.is_alphanumeric()).collect();
+    SESSION_PATTERNS
+        .iter()
+        .any(|pattern| compact.contains(pattern))
+        || lower
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|token| token == "ipt")
+}
+
+/// 向ipt.sys发送一个查询请求，返回跟踪的IPT_OPTIONS
+///
+/// IPT_OPTIONS描述了驱动写入IA32_RTIT_CTL的跟踪配置(时序包、过滤、ToPA大小等)，
+/// 请求成功并且返回非零的配置说明跟踪正在进行
+fn query_ipt_options(device: HANDLE, input_type: u32, argument: u64) -> Option<u64> {
+    let input = IptInputBuffer {
+        major_version: IPT_BUFFER_MAJOR_VERSION,
+        input_type,
+        arguments: [argument, 0, 0, 0],
+        ..Default::default()
+    };
+    let mut output = IptOutputBuffer::default();
+    let mut returned: u32 = 0;
+    let result = unsafe {
+        DeviceIoControl(
+            device,
+            IOCTL_IPT_REQUEST,
+            Some(&input as *const IptInputBuffer as *const c_void),
+            size_of::<IptInputBuffer>() as u32,
+            Some(&mut output as *mut IptOutputBuffer as *mut c_void),
+            size_of::<IptOutputBuffer>() as u32,
+            Some(&mut returned),
+            None,
+        )
+    };
+    debug!(
+        "ipt query {} ==> {:?}; returned: {}; options: {:#x}",
+        input_type, result, returned, output.result[0]
+    );
+    (result.is_ok() && output.result[0] != 0).then_some(output.result[0])
+}
+
+/// 通过ipt.sys的IOCTL查询当前进程与各个核心上已经开启的Intel PT跟踪
+///
+/// # 返回值
+///
+/// - `Err`: 打开`\\.\IPT`失败，驱动没有加载或者没有权限
+/// - `Ok(evidence)`: 正在进行的跟踪及其配置，为空则未开启跟踪
+pub fn query_ipt_tracing() -> Result<Vec<String>> {
+    let path = to_wide(IPT_DEVICE);
+    let device = unsafe {
+        CreateFileW(
+            PCWSTR(path.as_ptr()),
+            GENERIC_READ.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            None,
+        )
+    }?;
+
+    let process = unsafe { GetCurrentProcess() }.0 as u64;
+    let mut evidence: Vec<String> = Vec::new();
+    if let Some(options) = query_ipt_options(device, IPT_QUERY_PROCESS_TRACING, process) {
+        evidence.push(format!("process trace active (options {:#x})", options));
+    }
+    if let Some(options) = query_ipt_options(device, IPT_QUERY_CORE_TRACING, 0) {
+        evidence.push(format!("core trace active (options {:#x})", options));
+    }
+
+    let _ = unsafe { CloseHandle(device) };
+    Ok(evidence)
+}
+
+/// 检测针对当前系统的Intel PT跟踪
+///
+/// Intel PT可以在不修改代码、不触发异常的情况下记录完整的控制流。只报告正在进行的跟踪：
+/// 处理器支持PT时通过ipt.sys的IOCTL查询当前进程与各个核心的跟踪配置，
+/// 同时查找名称与处理器跟踪相关的ETW会话。驱动已加载本身不作为证据，系统会按需自动加载ipt.sys
+///
+/// # 返回值
+///
+/// - `Ok(evidence)`: 发现的跟踪，为空则未发现
+///
+/// # 示例
+///
+/// ```ignore
+/// for evidence in check_intel_pt().unwrap() {
+///     println!("processor trace: {}", evidence);
+/// }
+/// ```
+///
+/// # 注意
+///
+/// 非管理员只能看到部分ETW会话，也可能无法打开`\\.\IPT`
+pub fn check_intel_pt() -> Result<Vec<String>> {
+    let mut evidence: Vec<String> = Vec::new();
+    if is_pt_supported() {
+        match query_ipt_tracing() {
+            Ok(tracing) => {
+                evidence.extend(tracing.into_iter().map(|trace| format!("ipt {}", trace)))
+            }
+            Err(e) => debug!("ipt device unavailable; error: {:?}", e),
+        }
+    }
+
+    match trace_sessions() {
+        Ok(sessions) => evidence.extend(
+            sessions
+                .into_iter()
+                .filter(|session| is_trace_session(session))
+                .map(|session| format!("etw session {}", session)),
+        ),
+        Err(e) => debug!("trace sessions unavailable; error: {:?}", e),
+    }
+
+    if !evidence.is_empty() {
+        warn!("intel pt tracing ==> {:?}", evidence);
+    }
+    Ok(evidence)
+}
@@ -0,0 +1,267 @@
+use anyhow::{Error, Result};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// JSON值，只支持检测事件用到的类型，数字只支持整数
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// 读取对象中的字段
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(object) => object.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+}
+
+/// 将字符串转义为带引号的JSON字符串
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// 可选字符串，None输出为`null`
+pub fn quote_option(value: Option<&str>) -> String {
+    value.map(quote).unwrap_or_else(|| "null".to_string())
+}
+
+/// 数组与对象的最大嵌套深度，解析来自管道等不可信来源的文本时避免递归耗尽栈空间
+pub const MAX_DEPTH: usize = 64;
+
+/// 解析JSON文本
+///
+/// # 返回值
+///
+/// - `Err`: 格式错误、包含小数、嵌套超过`MAX_DEPTH`或者末尾有多余内容
+/// - `Ok(value)`: 解析结果
+pub fn parse(text: &str) -> Result<Value> {
+    let mut parser = Parser {
+        text,
+        bytes: text.as_bytes(),
+        offset: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    if parser.offset != parser.bytes.len() {
+        return Err(Error::msg("trailing characters after json value"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    offset: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.offset)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.offset += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.bytes.get(self.offset).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        match self.peek() {
+            Some(current) if current == byte => {
+                self.offset += 1;
+                Ok(())
+            }
+            _ => Err(Error::msg(format!(
+                "expected '{}' at offset {}",
+                byte as char, self.offset
+            ))),
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value> {
+        if !self.bytes[self.offset..].starts_with(literal.as_bytes()) {
+            return Err(Error::msg(format!(
+                "invalid literal at offset {}",
+                self.offset
+            )));
+        }
+        self.offset += literal.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'{') => self.nested(Self::object),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(Error::msg(format!(
+                "unexpected token at offset {}",
+                self.offset
+            ))),
+        }
+    }
+
+    /// 进入一层数组或者对象
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value>) -> Result<Value> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::msg(format!(
+                "json nesting deeper than {} at offset {}",
+                MAX_DEPTH, self.offset
+            )));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.offset;
+        if self.bytes[self.offset] == b'-' {
+            self.offset += 1;
+        }
+        while self.bytes.get(self.offset).is_some_and(u8::is_ascii_digit) {
+            self.offset += 1;
+        }
+        Ok(Value::Number(self.text[start..self.offset].parse()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut value = String::new();
+        loop {
+            // 偏移总是位于字符边界
+            let rest = &self.text[self.offset..];
+            let mut chars = rest.chars();
+            let c = chars
+                .next()
+                .ok_or_else(|| Error::msg("unterminated json string"))?;
+            self.offset += c.len_utf8();
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    let escaped = chars
+                        .next()
+                        .ok_or_else(|| Error::msg("unterminated json escape"))?;
+                    self.offset += 1;
+                    match escaped {
+                        '"' | '\\' | '/' => value.push(escaped),
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'b' => value.push('\u{8}'),
+                        'f' => value.push('\u{c}'),
+                        'u' => {
+                            let hex = rest
+                                .get(2..6)
+                                .ok_or_else(|| Error::msg("invalid json unicode escape"))?;
+                            let unit = u32::from_str_radix(hex, 16)?;
+                            value.push(char::from_u32(unit).unwrap_or('\u{fffd}'));
+                            self.offset += 4;
+                        }
+                        _ => return Err(Error::msg("invalid json escape")),
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.expect(b'[')?;
+        let mut array = Vec::new();
+        if self.peek() == Some(b']') {
+            self.offset += 1;
+            return Ok(Value::Array(array));
+        }
+        loop {
+            array.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                _ => break,
+            }
+        }
+        self.expect(b']')?;
+        Ok(Value::Array(array))
+    }
+
+    fn object(&mut self) -> Result<Value> {
+        self.expect(b'{')?;
+        let mut object = BTreeMap::new();
+        if self.peek() == Some(b'}') {
+            self.offset += 1;
+            return Ok(Value::Object(object));
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            object.insert(key, self.value()?);
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                _ => break,
+            }
+        }
+        self.expect(b'}')?;
+        Ok(Value::Object(object))
+    }
+}
@@ -0,0 +1,325 @@
+use crate::logging::{debug, warn};
+use crate::{
+    engine::Severity,
+    inject::{inject_library, InjectMethod},
+    response::{respond_to_process, ProcessOutcome, ProcessPolicy},
+    util::to_wide,
+    watchdog::{ProcessAudit, TRUSTED_HANDLE_HOLDERS},
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::HashSet,
+    mem::size_of,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0},
+        System::Threading::{
+            CreateProcessW, GetExitCodeProcess, ResumeThread, TerminateProcess,
+            WaitForSingleObject, CREATE_SUSPENDED, PROCESS_INFORMATION, STARTUPINFOW,
+        },
+    },
+};
+
+/// 目标被攻击且策略为结束目标时的退出码
+pub const PROTECT_EXIT_CODE: u32 = 0xdead;
+
+/// 目标进程被攻击时对目标采取的动作
+///
+/// - `Log`: 只记录，目标继续运行
+/// - `Terminate`: 以`PROTECT_EXIT_CODE`结束目标进程
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProtectResponse {
+    Log,
+    Terminate,
+}
+
+/// 启动器配置
+///
+/// - `interval`: 检查间隔
+/// - `dll`: 目标启动前注入的保护DLL，为`None`时不注入
+/// - `method`: 注入保护DLL时创建远程线程的方式
+/// - `response`: 目标被攻击时对目标采取的动作
+/// - `attackers`: 对持有目标可疑句柄的进程的处置策略
+/// - `trusted_holders`: 允许持有目标进程句柄的进程名
+#[derive(Debug, Clone)]
+pub struct ProtectConfig {
+    pub interval: Duration,
+    pub dll: Option<PathBuf>,
+    pub method: InjectMethod,
+    pub response: ProtectResponse,
+    pub attackers: ProcessPolicy,
+    pub trusted_holders: Vec<String>,
+}
+
+impl Default for ProtectConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(500),
+            dll: None,
+            method: InjectMethod::RemoteThread,
+            response: ProtectResponse::Terminate,
+            attackers: ProcessPolicy::default(),
+            trusted_holders: TRUSTED_HANDLE_HOLDERS
+                .iter()
+                .map(|name| name.decrypt().to_string())
+                .collect(),
+        }
+    }
+}
+
+/// 按Windows命令行解析规则(`CommandLineToArgvW`)转义一个参数
+fn quote_argument(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains([' ', '\t', '\n', '\u{b}', '"']) {
+        return argument.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // 引号前的反斜杠需要加倍，引号本身也需要转义
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// 由程序路径与参数拼接完整命令行
+///
+/// # 参数
+///
+/// - `exe`: 可执行文件路径，作为命令行的第一个参数
+/// - `args`: 传递给目标的参数
+///
+/// # 示例
+///
+/// ```ignore
+/// assert_eq!(command_line("C:\\app\\a b.exe", &["-x", "1 2"]), "\"C:\\app\\a b.exe\" -x \"1 2\"");
+/// ```
+pub fn command_line<S: AsRef<str>>(exe: &str, args: &[S]) -> String {
+    std::iter::once(exe)
+        .chain(args.iter().map(AsRef::as_ref))
+        .map(quote_argument)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// 由启动器启动并保护的第三方进程
+///
+/// 启动器在进程外对目标执行守护对同样的检查(调试端口与调试对象、线程调试寄存器、外部进程句柄审计)，
+/// 适用于无法修改源码的程序；需要进程内检测时可以在启动前注入保护DLL
+///
+/// # 示例
+///
+/// ```ignore
+/// let target = Protected::launch("C:\\app\\game.exe", &["--windowed"], ProtectConfig::default()).unwrap();
+/// let exit_code = target.run(|reason, outcomes| eprintln!("attacked: {} {:?}", reason, outcomes)).unwrap();
+/// std::process::exit(exit_code as i32);
+/// ```
+pub struct Protected {
+    hprocess: HANDLE,
+    pid: u32,
+    config: ProtectConfig,
+    audit: ProcessAudit,
+}
+
+impl Drop for Protected {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.hprocess) };
+    }
+}
+
+impl Protected {
+    /// 以挂起状态创建目标进程，按配置注入保护DLL后再恢复运行
+    ///
+    /// # 参数
+    ///
+    /// - `exe`: 可执行文件路径
+    /// - `args`: 传递给目标的参数
+    /// - `config`: 启动器配置
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 创建进程或者注入失败，注入失败时目标会被结束
+    /// - `Ok(protected)`: 已经恢复运行的目标
+    pub fn launch<P: AsRef<Path>, S: AsRef<str>>(
+        exe: P,
+        args: &[S],
+        config: ProtectConfig,
+    ) -> Result<Self> {
+        let exe = exe.as_ref();
+        let application = to_wide(&exe.to_string_lossy());
+        // CreateProcessW可能会修改命令行缓冲区
+        let mut command_line = to_wide(&command_line(&exe.to_string_lossy(), args));
+        let startup_info = STARTUPINFOW {
+            cb: size_of::<STARTUPINFOW>() as u32,
+            ..Default::default()
+        };
+        let mut process_info = PROCESS_INFORMATION::default();
+        unsafe {
+            CreateProcessW(
+                PCWSTR(application.as_ptr()),
+                PWSTR(command_line.as_mut_ptr()),
+                None,
+                None,
+                false,
+                CREATE_SUSPENDED,
+                None,
+                PCWSTR::null(),
+                &startup_info,
+                &mut process_info,
+            )
+        }?;
+
+        let injected = match &config.dll {
+            Some(dll) => inject_library(process_info.hProcess, dll, config.method),
+            None => Ok(()),
+        };
+        let resumed = injected.and_then(|_| match unsafe { ResumeThread(process_info.hThread) } {
+            u32::MAX => Err(windows::core::Error::from_win32().into()),
+            _ => Ok(()),
+        });
+        let _ = unsafe { CloseHandle(process_info.hThread) };
+        if let Err(e) = resumed {
+            let _ = unsafe { TerminateProcess(process_info.hProcess, PROTECT_EXIT_CODE) };
+            let _ = unsafe { CloseHandle(process_info.hProcess) };
+            return Err(e);
+        }
+
+        debug!(
+            "protected target ==> pid: {}; exe: {:?}; dll: {:?}",
+            process_info.dwProcessId, exe, config.dll
+        );
+        Ok(Self {
+            audit: ProcessAudit::new(
+                process_info.hProcess,
+                process_info.dwProcessId,
+                "target",
+                config.trusted_holders.clone(),
+            ),
+            hprocess: process_info.hProcess,
+            pid: process_info.dwProcessId,
+            config,
+        })
+    }
+
+    /// 目标进程ID
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// 目标已经退出时返回退出码
+    pub fn exit_code(&self) -> Option<u32> {
+        if unsafe { WaitForSingleObject(self.hprocess, 0) } != WAIT_OBJECT_0 {
+            return None;
+        }
+        let mut exit_code: u32 = 0;
+        unsafe { GetExitCodeProcess(self.hprocess, &mut exit_code) }.ok()?;
+        Some(exit_code)
+    }
+
+    /// 对目标执行一次进程外检查
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 查询系统句柄表失败
+    /// - `Ok(evidence)`: 发现的攻击，为空则目标正常
+    pub fn check(&mut self) -> Result<Vec<String>> {
+        self.audit.check()
+    }
+
+    /// 按配置处置攻击：先处置持有目标可疑句柄的进程，再对目标采取动作
+    ///
+    /// # 返回值
+    ///
+    /// - 每个攻击进程的处置结果，豁免列表中的进程不处置
+    pub fn respond(&self) -> Vec<ProcessOutcome> {
+        let outcomes: Vec<ProcessOutcome> = self
+            .audit
+            .attackers()
+            .into_iter()
+            .filter(|holder| !self.config.attackers.is_exempt(holder.name.as_deref()))
+            .map(|holder| {
+                respond_to_process(
+                    &self.config.attackers,
+                    holder.pid,
+                    holder.name,
+                    format!("target handle with access {:#x}", holder.access),
+                    Severity::Suspicious.base_confidence(),
+                )
+            })
+            .collect();
+
+        if self.config.response == ProtectResponse::Terminate {
+            warn!("terminate protected target ==> {}", self.pid);
+            let _ = unsafe { TerminateProcess(self.hprocess, PROTECT_EXIT_CODE) };
+        }
+        outcomes
+    }
+
+    /// 循环检查目标直到目标退出
+    ///
+    /// 每次发现新的攻击时调用`on_attack`并按配置处置，同样的证据只处理一次
+    ///
+    /// # 参数
+    ///
+    /// - `on_attack`: 处置后的回调，参数为攻击原因与攻击进程的处置结果
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 查询目标退出码失败
+    /// - `Ok(exit_code)`: 目标的退出码，被启动器结束时为`PROTECT_EXIT_CODE`
+    pub fn run<F>(mut self, mut on_attack: F) -> Result<u32>
+    where
+        F: FnMut(&str, &[ProcessOutcome]),
+    {
+        let mut reported: HashSet<String> = HashSet::new();
+        loop {
+            let waited = unsafe {
+                WaitForSingleObject(self.hprocess, self.config.interval.as_millis() as u32)
+            };
+            if waited == WAIT_OBJECT_0 {
+                break;
+            }
+
+            let evidence = match self.check() {
+                Ok(evidence) => evidence,
+                Err(e) => {
+                    warn!("check protected target failed; error: {:?}", e);
+                    continue;
+                }
+            };
+            // 检查期间正常退出的目标不算被攻击
+            if self.exit_code().is_some() {
+                break;
+            }
+            let fresh: Vec<String> = evidence
+                .into_iter()
+                .filter(|item| reported.insert(item.clone()))
+                .collect();
+            if !fresh.is_empty() {
+                let outcomes = self.respond();
+                on_attack(&fresh.join("; "), &outcomes);
+            }
+        }
+
+        self.exit_code()
+            .ok_or_else(|| Error::msg("query target exit code failed"))
+    }
+}
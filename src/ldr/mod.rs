@@ -0,0 +1,240 @@
+use crate::logging::{debug, warn};
+use crate::{
+    hook::{get_module, get_module_from_address, get_proc_address},
+    imports::GetModuleHandleW,
+    obf,
+    peb::WinPeb,
+};
+use anyhow::{Error, Result};
+use std::{ffi::c_void, mem::transmute};
+use windows::Win32::{
+    Foundation::{NTSTATUS, UNICODE_STRING},
+    System::{Kernel::LIST_ENTRY, Threading::PEB},
+};
+
+/// LdrLockLoaderLock的函数签名
+type LdrLockLoaderLockFn = unsafe extern "system" fn(u32, *mut u32, *mut usize) -> NTSTATUS;
+/// LdrUnlockLoaderLock的函数签名
+type LdrUnlockLoaderLockFn = unsafe extern "system" fn(u32, usize) -> NTSTATUS;
+
+/// PEB->Ldr指向的加载器数据，windows crate中的定义缺少InLoadOrderModuleList
+#[repr(C)]
+pub struct PebLdrData {
+    pub length: u32,
+    pub initialized: u8,
+    pub ss_handle: *mut c_void,
+    pub in_load_order_module_list: LIST_ENTRY,
+    pub in_memory_order_module_list: LIST_ENTRY,
+    pub in_initialization_order_module_list: LIST_ENTRY,
+}
+
+/// 加载器为每个模块维护的LDR_DATA_TABLE_ENTRY，只定义到HashLinks为止
+///
+/// 同一个条目同时挂在PEB_LDR_DATA的三个链表与加载器的哈希表上
+#[repr(C)]
+pub struct LdrDataTableEntry {
+    pub in_load_order_links: LIST_ENTRY,
+    pub in_memory_order_links: LIST_ENTRY,
+    pub in_initialization_order_links: LIST_ENTRY,
+    pub dll_base: *mut c_void,
+    pub entry_point: *mut c_void,
+    pub size_of_image: u32,
+    pub full_dll_name: UNICODE_STRING,
+    pub base_dll_name: UNICODE_STRING,
+    pub flags: u32,
+    pub obsolete_load_count: u16,
+    pub tls_index: u16,
+    pub hash_links: LIST_ENTRY,
+}
+
+impl LdrDataTableEntry {
+    /// 模块文件名，例如`ntdll.dll`
+    pub fn base_name(&self) -> String {
+        unicode_string_to_string(&self.base_dll_name)
+    }
+
+    /// 模块完整路径
+    pub fn full_name(&self) -> String {
+        unicode_string_to_string(&self.full_dll_name)
+    }
+}
+
+fn unicode_string_to_string(value: &UNICODE_STRING) -> String {
+    if value.Buffer.is_null() {
+        return String::new();
+    }
+
+    let chars = unsafe { std::slice::from_raw_parts(value.Buffer.0, value.Length as usize / 2) };
+    String::from_utf16_lossy(chars)
+}
+
+/// 加载器锁，持有期间其他线程无法加载、卸载模块或修改Ldr链表，释放时自动解锁
+pub struct LoaderLock {
+    cookie: usize,
+    unlock: LdrUnlockLoaderLockFn,
+}
+
+impl LoaderLock {
+    /// 通过LdrLockLoaderLock获取加载器锁
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: ntdll中没有相关导出或者加锁失败
+    /// - `Ok(lock)`: 加载器锁
+    pub fn acquire() -> Result<Self> {
+        let ntdll = get_module("ntdll.dll")?;
+        let (Some(lock), Some(unlock)) = (
+            get_proc_address(ntdll, "LdrLockLoaderLock"),
+            get_proc_address(ntdll, "LdrUnlockLoaderLock"),
+        ) else {
+            return Err(Error::msg(obf!("LdrLockLoaderLock not found").decrypt()));
+        };
+        let lock = unsafe { transmute::<usize, LdrLockLoaderLockFn>(lock) };
+        let unlock = unsafe { transmute::<usize, LdrUnlockLoaderLockFn>(unlock) };
+
+        let mut cookie: usize = 0;
+        let status = unsafe { lock(0, std::ptr::null_mut(), &mut cookie) };
+        if status.is_err() {
+            warn!("LdrLockLoaderLock failed; error code: {:?}", status);
+            return Err(Error::msg(obf!("LdrLockLoaderLock failed").decrypt()));
+        }
+
+        Ok(Self { cookie, unlock })
+    }
+}
+
+impl Drop for LoaderLock {
+    fn drop(&mut self) {
+        let _ = unsafe { (self.unlock)(0, self.cookie) };
+    }
+}
+
+/// 当前进程的PEB_LDR_DATA
+pub fn peb_ldr_data() -> *mut PebLdrData {
+    let peb = WinPeb::get_peb_address() as *const PEB;
+    unsafe { (*peb).Ldr as *mut PebLdrData }
+}
+
+/// 按加载顺序遍历所有模块的Ldr条目
+///
+/// # Safety
+///
+/// 调用方需要持有`LoaderLock`，否则遍历期间链表可能被其他线程修改
+pub unsafe fn entries() -> Vec<*mut LdrDataTableEntry> {
+    let head = &mut (*peb_ldr_data()).in_load_order_module_list as *mut LIST_ENTRY;
+    let mut entries: Vec<*mut LdrDataTableEntry> = Vec::new();
+    let mut current = (*head).Flink;
+    while !current.is_null() && current != head {
+        // InLoadOrderLinks是条目的第一个字段，链表节点地址即条目地址
+        entries.push(current as *mut LdrDataTableEntry);
+        current = (*current).Flink;
+    }
+
+    entries
+}
+
+/// 查找指定基址的模块的Ldr条目
+///
+/// # Safety
+///
+/// 同`entries`，返回的指针只在持有`LoaderLock`期间有效
+pub unsafe fn find_entry(base: usize) -> Option<*mut LdrDataTableEntry> {
+    let entry = entries()
+        .into_iter()
+        .find(|entry| (**entry).dll_base as usize == base)?;
+    debug!(
+        "ldr entry ==> {:p}; module: {}",
+        entry,
+        (*entry).base_name()
+    );
+    Some(entry)
+}
+
+/// 将链表节点从所在链表中摘除，并指向自身，重复摘除不会破坏链表
+unsafe fn unlink(node: *mut LIST_ENTRY) {
+    let flink = (*node).Flink;
+    let blink = (*node).Blink;
+    if !flink.is_null() && !blink.is_null() {
+        (*blink).Flink = flink;
+        (*flink).Blink = blink;
+    }
+    (*node).Flink = node;
+    (*node).Blink = node;
+}
+
+/// 将指定模块从PEB Ldr的三个链表与加载器哈希表中摘除，并清空条目中的名称、入口与大小
+///
+/// 摘除后`EnumProcessModules`、`CreateToolhelp32Snapshot`、`GetModuleHandle`
+/// 以及遍历PEB的工具都看不到该模块
+///
+/// # 参数
+///
+/// - `base`: 模块基址
+///
+/// # 返回值
+///
+/// - `Err`: 获取加载器锁失败或者Ldr链表中没有该模块
+/// - `Ok(())`: 摘除成功
+///
+/// # 注意
+///
+/// - 摘除后不能再对该模块调用`FreeLibrary`，进程退出时也不会再收到`DLL_PROCESS_DETACH`
+/// - 再次`LoadLibrary`同名DLL会加载出第二份副本
+/// - 条目中的DllBase保持不变，加载器的基址索引树依赖该字段
+pub fn hide_module(base: usize) -> Result<()> {
+    let _lock = LoaderLock::acquire()?;
+    let Some(entry) = (unsafe { find_entry(base) }) else {
+        return Err(Error::msg("module not found in ldr list"));
+    };
+
+    unsafe {
+        unlink(&mut (*entry).in_load_order_links);
+        unlink(&mut (*entry).in_memory_order_links);
+        unlink(&mut (*entry).in_initialization_order_links);
+        unlink(&mut (*entry).hash_links);
+
+        for name in [&mut (*entry).full_dll_name, &mut (*entry).base_dll_name] {
+            if !name.Buffer.is_null() {
+                std::ptr::write_bytes(name.Buffer.0, 0, name.Length as usize / 2);
+            }
+            name.Length = 0;
+        }
+        (*entry).entry_point = std::ptr::null_mut();
+        (*entry).size_of_image = 0;
+    }
+
+    debug!("hide module ==> {:#x}", base);
+    Ok(())
+}
+
+/// 以保护DLL的方式运行时，将本库所在的DLL从Ldr链表中摘除
+///
+/// # 返回值
+///
+/// - `Err`: 本库链接在主程序中(不是DLL模式)，或者摘除失败
+/// - `Ok(())`: 摘除成功
+///
+/// # 示例
+///
+/// ```ignore
+/// #[no_mangle]
+/// extern "system" fn DllMain(_: HMODULE, reason: u32, _: *mut c_void) -> BOOL {
+///     if reason == DLL_PROCESS_ATTACH {
+///         // DllMain返回前加载器仍在处理本模块的条目，需要在其他线程中调用
+///         std::thread::spawn(|| anti_debug::ldr::hide_current_module());
+///     }
+///     TRUE
+/// }
+/// ```
+pub fn hide_current_module() -> Result<()> {
+    let Some(hmodule) = get_module_from_address(hide_current_module as fn() -> Result<()> as usize)
+    else {
+        return Err(Error::msg("current module not found"));
+    };
+    if hmodule == unsafe { GetModuleHandleW(None) }? {
+        warn!("hide current module refused; not running as a dll");
+        return Err(Error::msg("not running as a dll"));
+    }
+
+    hide_module(hmodule.0 as usize)
+}
@@ -1,5 +1,122 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod logging;
+#[cfg(feature = "std")]
+pub mod obfstr;
+#[cfg(feature = "std")]
+pub mod opaque;
+#[cfg(feature = "std")]
+pub mod shuffle;
+#[cfg(feature = "std")]
+pub mod random;
+#[cfg(all(windows, feature = "std"))]
 pub mod peb;
+#[cfg(feature = "std")]
 pub mod util;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod taxonomy;
+#[cfg(feature = "http-sink")]
+pub mod http_sink;
+#[cfg(all(windows, feature = "std"))]
 pub mod breakpoint;
+#[cfg(all(windows, feature = "std"))]
 pub mod nt_query;
+#[cfg(all(windows, feature = "std"))]
 pub mod thread;
+#[cfg(all(windows, feature = "std"))]
+pub mod thread_monitor;
+#[cfg(all(windows, feature = "std"))]
+pub mod environment;
+#[cfg(all(windows, feature = "std"))]
+pub mod module;
+#[cfg(feature = "std")]
+pub mod signature;
+#[cfg(all(windows, feature = "std"))]
+pub mod vm;
+#[cfg(all(windows, feature = "std"))]
+pub mod sandbox;
+#[cfg(all(windows, feature = "wmi", feature = "std"))]
+pub mod wmi;
+#[cfg(all(windows, feature = "eventlog"))]
+pub mod eventlog;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod dashboard;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod sink;
+#[cfg(feature = "simulate")]
+pub mod simulate;
+#[cfg(all(windows, feature = "std"))]
+pub mod pe;
+#[cfg(all(windows, feature = "std"))]
+pub mod hook;
+#[cfg(all(windows, feature = "std"))]
+pub mod clean_ntdll;
+#[cfg(all(any(windows, target_os = "linux"), feature = "std"))]
+pub mod integrity;
+#[cfg(all(windows, feature = "std"))]
+pub mod timing;
+#[cfg(all(windows, feature = "std"))]
+pub mod syscall;
+#[cfg(all(windows, feature = "std"))]
+pub mod exception;
+#[cfg(all(windows, feature = "std"))]
+pub mod wow64;
+#[cfg(all(windows, feature = "std"))]
+pub mod hypervisor;
+#[cfg(all(windows, feature = "std"))]
+pub mod ipt;
+#[cfg(all(windows, feature = "std"))]
+pub mod debug_object;
+#[cfg(all(windows, feature = "std"))]
+pub mod debug_blocker;
+#[cfg(all(windows, feature = "std"))]
+pub mod watchdog;
+#[cfg(all(windows, feature = "std"))]
+pub mod inject;
+#[cfg(all(windows, feature = "std"))]
+pub mod launcher;
+#[cfg(all(windows, feature = "std"))]
+pub mod ipc;
+#[cfg(all(windows, feature = "std"))]
+pub mod response;
+#[cfg(all(windows, feature = "std"))]
+pub mod ldr;
+#[cfg(all(windows, feature = "std"))]
+pub mod resolve;
+#[cfg(all(windows, feature = "std"))]
+pub mod imports;
+#[cfg(all(windows, target_arch = "x86_64", feature = "std"))]
+pub mod spoof;
+#[cfg(all(windows, feature = "std"))]
+pub mod anti_dump;
+#[cfg(all(windows, feature = "std"))]
+pub mod decoy;
+#[cfg(all(windows, feature = "std"))]
+pub mod cache;
+#[cfg(all(windows, feature = "std"))]
+pub mod handle_watch;
+#[cfg(all(windows, feature = "std"))]
+pub mod capability;
+#[cfg(all(windows, feature = "std"))]
+pub mod scheduler;
+#[cfg(all(windows, feature = "std"))]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod scan;
+#[cfg(feature = "std")]
+pub mod platform;
+#[cfg(all(windows, feature = "authenticode", feature = "std"))]
+pub mod authenticode;
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub mod linux;
+#[cfg(all(target_os = "macos", feature = "std"))]
+pub mod macos;
+#[cfg(all(windows, feature = "core"))]
+pub mod bare;
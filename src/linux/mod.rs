@@ -0,0 +1,741 @@
+use crate::logging::{debug, warn};
+use crate::{scan, util::BeingDebug};
+use anyhow::{Error, Result};
+use std::{
+    ffi::c_void,
+    fs, io, ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// 自跟踪辅助进程的进程ID，0表示没有附加
+static SELF_TRACER: AtomicI32 = AtomicI32::new(0);
+
+/// 软件断点指令int3
+pub const INT3: u8 = 0xCC;
+
+/// 动态链接器在加载程序前注入共享库的环境变量
+pub const PRELOAD_ENV_VARS: [&str; 2] = ["LD_PRELOAD", "LD_AUDIT"];
+
+/// 全局预加载配置文件，每行(或空白分隔)一个共享库路径
+pub const PRELOAD_FILE: &str = "/etc/ld.so.preload";
+
+/// 插桩工具注入的共享库名称关键字(小写)
+pub const INSTRUMENTATION_LIBRARIES: [&str; 5] = [
+    "frida",
+    "gum-js-loop",
+    "librrpreload",
+    "libvalgrind",
+    "vgpreload",
+];
+
+/// 辅助进程通过管道报告的附加结果
+const REPORT_ATTACHED: u8 = 1;
+const REPORT_REFUSED: u8 = 2;
+const REPORT_FAILED: u8 = 3;
+
+/// /proc/<pid>/stat中的进程状态
+///
+/// - `state`: 进程状态，`t`表示被跟踪而停止(调试器中断)
+/// - `ppid`: 父进程ID
+/// - `flags`: 内核的进程标志(PF_*)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcStat {
+    pub state: char,
+    pub ppid: u32,
+    pub flags: u32,
+}
+
+/// /proc/<pid>/maps中的一个映射区域
+///
+/// - `start`: 起始地址
+/// - `end`: 结束地址
+/// - `perms`: 权限，例如`r-xp`
+/// - `offset`: 映射在文件中的偏移
+/// - `path`: 映射的文件路径，匿名映射为空，特殊映射形如`[vdso]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapRegion {
+    pub start: usize,
+    pub end: usize,
+    pub perms: String,
+    pub offset: usize,
+    pub path: String,
+}
+
+impl MapRegion {
+    /// 映射是否可执行
+    pub fn is_executable(&self) -> bool {
+        self.perms.as_bytes().get(2) == Some(&b'x')
+    }
+}
+
+/// 读取/proc下status文件中`TracerPid`的值
+///
+/// # 参数
+///
+/// - `path`: status文件路径，例如`/proc/self/status`
+fn read_tracer_pid(path: &str) -> Result<u32> {
+    let status = fs::read_to_string(path)?;
+    let tracer = status
+        .lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))
+        .ok_or_else(|| Error::msg("TracerPid not found"))?;
+
+    Ok(tracer.trim().parse::<u32>()?)
+}
+
+/// 获取跟踪当前进程的进程ID
+///
+/// 读取/proc/self/status中的TracerPid，gdb、strace等通过ptrace附加后该值为调试器的进程ID
+///
+/// # 返回值
+///
+/// - `Err`: 读取或解析/proc/self/status失败
+/// - `Ok(0)`: 没有被跟踪
+/// - `Ok(pid)`: 跟踪者的进程ID
+///
+/// # 示例
+///
+/// ```ignore
+/// if linux::tracer_pid().unwrap() != 0 {
+///     println!("process is being traced");
+/// }
+/// ```
+pub fn tracer_pid() -> Result<u32> {
+    let tracer = read_tracer_pid("/proc/self/status")?;
+    debug!("TracerPid ==> {}", tracer);
+    Ok(tracer)
+}
+
+/// 自跟踪辅助进程的进程ID，没有附加时为0
+pub fn self_tracer_pid() -> u32 {
+    SELF_TRACER.load(Ordering::SeqCst) as u32
+}
+
+/// 跟踪者是否是外部进程，`SelfTrace`的辅助进程不算
+///
+/// # 参数
+///
+/// - `tracer`: TracerPid的值
+pub fn is_foreign_tracer(tracer: u32) -> bool {
+    tracer != 0 && tracer as i32 != SELF_TRACER.load(Ordering::SeqCst)
+}
+
+/// 解析/proc下的stat文件
+///
+/// 进程名(第2个字段)被括号包围且可能包含空格与括号，因此从最后一个`)`之后开始按空格分割
+///
+/// # 参数
+///
+/// - `path`: stat文件路径，例如`/proc/self/stat`
+///
+/// # 返回值
+///
+/// - `Err`: 读取或解析失败
+/// - `Ok(stat)`: 进程状态
+pub fn read_proc_stat(path: &str) -> Result<ProcStat> {
+    let stat = fs::read_to_string(path)?;
+    let (_, fields) = stat
+        .rsplit_once(')')
+        .ok_or_else(|| Error::msg("invalid stat format"))?;
+
+    // 字段3起: state ppid pgrp session tty_nr tpgid flags
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let [state, ppid, _, _, _, _, flags, ..] = fields[..] else {
+        return Err(Error::msg("invalid stat format"));
+    };
+
+    Ok(ProcStat {
+        state: state.chars().next().unwrap_or('?'),
+        ppid: ppid.parse()?,
+        flags: flags.parse()?,
+    })
+}
+
+/// 查找被调试器单独跟踪或者停止的线程
+///
+/// 调试器可以只附加到某一个线程(ptrace的目标是线程ID)，此时/proc/self/status中的TracerPid为0，
+/// 但该线程的/proc/self/task/<tid>/status中TracerPid不为0；被调试器中断的线程在stat中的状态为`t`
+///
+/// # 返回值
+///
+/// - `Err`: 无法读取/proc/self/task
+/// - `Ok(threads)`: 被跟踪的线程ID，为空则未发现
+pub fn find_traced_threads() -> Result<Vec<u32>> {
+    let mut threads: Vec<u32> = Vec::new();
+    for entry in fs::read_dir("/proc/self/task")? {
+        let Some(tid) = entry?
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        // 线程可能在遍历期间退出，读取失败的线程直接跳过
+        let tracer = read_tracer_pid(&format!("/proc/self/task/{}/status", tid)).unwrap_or(0);
+        let state =
+            read_proc_stat(&format!("/proc/self/task/{}/stat", tid)).map_or(' ', |stat| stat.state);
+        if is_foreign_tracer(tracer) || state == 't' {
+            warn!(
+                "traced thread ==> {}; tracer: {}; state: {}",
+                tid, tracer, state
+            );
+            threads.push(tid);
+        }
+    }
+
+    Ok(threads)
+}
+
+/// 通过/proc文件系统检测Linux下的调试器
+pub struct ProcDebug {}
+
+impl BeingDebug for ProcDebug {
+    fn is_being_debug(&self) -> bool {
+        let stat = read_proc_stat("/proc/self/stat");
+        debug!("proc stat ==> {:?}", stat);
+
+        tracer_pid().is_ok_and(is_foreign_tracer)
+            || stat.is_ok_and(|stat| stat.state == 't')
+            || find_traced_threads().is_ok_and(|threads| !threads.is_empty())
+    }
+}
+
+/// 读取预加载环境变量
+///
+/// # 返回值
+///
+/// - 设置了的环境变量与其值，例如`("LD_PRELOAD", "/tmp/hook.so")`
+pub fn preload_env() -> Vec<(&'static str, String)> {
+    PRELOAD_ENV_VARS
+        .iter()
+        .filter_map(|name| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(|value| (*name, value.to_string_lossy().into_owned()))
+        })
+        .collect()
+}
+
+/// 读取预加载配置文件中的共享库，文件不存在时返回空
+///
+/// # 参数
+///
+/// - `path`: 配置文件路径，通常为`PRELOAD_FILE`
+pub fn read_preload_file(path: &str) -> Result<Vec<String>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split_whitespace())
+        .map(String::from)
+        .collect())
+}
+
+/// 解析/proc下的maps文件
+///
+/// # 参数
+///
+/// - `path`: maps文件路径，例如`/proc/self/maps`
+pub fn read_maps(path: &str) -> Result<Vec<MapRegion>> {
+    let content = fs::read_to_string(path)?;
+    content
+        .lines()
+        .map(|line| {
+            // 地址 权限 偏移 设备 inode 路径，路径可能包含空格
+            let mut fields = line.splitn(6, ' ');
+            let (Some(range), Some(perms), Some(offset)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(Error::msg(format!("invalid maps line: {}", line)));
+            };
+            let (start, end) = range
+                .split_once('-')
+                .ok_or_else(|| Error::msg(format!("invalid maps range: {}", range)))?;
+            Ok(MapRegion {
+                start: usize::from_str_radix(start, 16)?,
+                end: usize::from_str_radix(end, 16)?,
+                perms: perms.to_string(),
+                offset: usize::from_str_radix(offset, 16)?,
+                path: fields.nth(2).unwrap_or_default().trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// 映射路径中表示文件已被删除的后缀
+const DELETED_SUFFIX: &str = " (deleted)";
+
+/// 可执行映射是否可疑
+///
+/// - 匿名映射：注入的shellcode、frida-gadget的trampoline等
+/// - memfd：不落地加载的共享库
+/// - 已删除且原路径不复存在的文件：加载后被删除的共享库
+/// - 路径包含`INSTRUMENTATION_LIBRARIES`中的关键字
+///
+/// # 参数
+///
+/// - `region`: 映射区域
+/// - `exe`: 当前可执行文件路径(`/proc/self/exe`)
+///
+/// # 注意
+///
+/// 进程运行中被重新编译的自身映像、被包管理器升级替换的共享库也会显示为`(deleted)`，
+/// 前者按自身映像排除，后者原路径上仍有新文件，均不视为可疑。
+/// 带JIT的运行时(例如嵌入的JavaScript引擎)也会产生匿名可执行映射
+pub fn is_suspicious_mapping(region: &MapRegion, exe: &str) -> bool {
+    if !region.is_executable() || region.path.starts_with('[') {
+        return false;
+    }
+
+    if let Some(original) = region.path.strip_suffix(DELETED_SUFFIX) {
+        let exe = exe.strip_suffix(DELETED_SUFFIX).unwrap_or(exe);
+        if original == exe || fs::metadata(original).is_ok() {
+            return false;
+        }
+        return true;
+    }
+
+    let path = region.path.to_lowercase();
+    path.is_empty()
+        || path.starts_with("/memfd:")
+        || INSTRUMENTATION_LIBRARIES
+            .iter()
+            .any(|library| path.contains(library))
+}
+
+/// 查找当前进程中可疑的可执行映射
+///
+/// # 返回值
+///
+/// - `Err`: 读取/proc/self/exe或者/proc/self/maps失败
+/// - `Ok(regions)`: 可疑的映射区域，为空表示没有发现
+///
+/// # 示例
+///
+/// ```ignore
+/// for region in linux::find_injected_mappings().unwrap() {
+///     println!("{:#x}-{:#x} {}", region.start, region.end, region.path);
+/// }
+/// ```
+pub fn find_injected_mappings() -> Result<Vec<MapRegion>> {
+    let exe = fs::read_link("/proc/self/exe")?;
+    let exe = exe.to_string_lossy();
+    let regions: Vec<MapRegion> = read_maps("/proc/self/maps")?
+        .into_iter()
+        .filter(|region| is_suspicious_mapping(region, &exe))
+        .collect();
+
+    if !regions.is_empty() {
+        warn!("suspicious executable mappings ==> {:?}", regions);
+    }
+    Ok(regions)
+}
+
+/// 当前可执行文件的可执行映射(代码段)
+///
+/// # 返回值
+///
+/// - `Err`: 读取/proc/self/exe或者/proc/self/maps失败
+/// - `Ok(regions)`: 代码段映射，按地址排列
+pub fn text_mappings() -> Result<Vec<MapRegion>> {
+    let exe = fs::read_link("/proc/self/exe")?;
+    let exe = exe.to_string_lossy();
+    Ok(read_maps("/proc/self/maps")?
+        .into_iter()
+        .filter(|region| region.is_executable() && region.path == exe)
+        .collect())
+}
+
+/// 读取映射在磁盘文件中对应的内容
+///
+/// 映射的最后一页超出文件末尾的部分在内存中填充为0，返回的长度可能小于映射的长度
+///
+/// # 参数
+///
+/// - `region`: 文件映射
+pub fn read_mapping_from_disk(region: &MapRegion) -> Result<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(&region.path)?;
+    file.seek(SeekFrom::Start(region.offset as u64))?;
+    let mut buffer: Vec<u8> = Vec::with_capacity(region.end - region.start);
+    file.take((region.end - region.start) as u64)
+        .read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// 内存中映射的内容，长度截断为`len`
+///
+/// # 注意
+///
+/// 映射必须属于当前进程且可读，代码段映射满足这个条件
+pub(crate) fn mapping_bytes(region: &MapRegion, len: usize) -> &'static [u8] {
+    let len = len.min(region.end - region.start);
+    unsafe { std::slice::from_raw_parts(region.start as *const u8, len) }
+}
+
+/// 查找调试器在当前可执行文件代码段中写入的软件断点
+///
+/// 与Windows下的`SoftwareBreakPoint::find_in_module`相同，先找出内存与磁盘不一致的字节，
+/// 只保留内存中为0xCC的位置。位置无关的可执行文件代码段没有重定位，可以直接比较
+///
+/// # 参数
+///
+/// - `limit`: 最多返回的数量，找到后立即停止扫描
+///
+/// # 返回值
+///
+/// - `Err`: 读取/proc或者磁盘文件失败
+/// - `Ok(addresses)`: 软件断点的地址，为空则未发现
+///
+/// # 示例
+///
+/// ```ignore
+/// for address in linux::find_text_breakpoints(16).unwrap() {
+///     println!("int3 ==> {:#x}", address);
+/// }
+/// ```
+pub fn find_text_breakpoints(limit: usize) -> Result<Vec<usize>> {
+    let mut breakpoints: Vec<usize> = Vec::new();
+    for region in text_mappings()? {
+        let disk = read_mapping_from_disk(&region)?;
+        let memory = mapping_bytes(&region, disk.len());
+
+        for offset in scan::find_differences(memory, &disk, usize::MAX) {
+            if memory[offset] == INT3 {
+                breakpoints.push(region.start + offset);
+                if breakpoints.len() >= limit {
+                    break;
+                }
+            }
+        }
+        if breakpoints.len() >= limit {
+            break;
+        }
+    }
+
+    debug!("software breakpoints ==> {:#x?}", breakpoints);
+
+    Ok(breakpoints)
+}
+
+/// 通过`ptrace(PTRACE_TRACEME)`占用跟踪者位置
+///
+/// 每个进程只能有一个跟踪者，已经被调试器跟踪时调用失败(EPERM)；
+/// 调用成功后父进程成为跟踪者，之后调试器无法再附加
+///
+/// # 返回值
+///
+/// - `Err`: ptrace调用失败(非EPERM)
+/// - `Ok(true)`: 已经被其他进程跟踪
+/// - `Ok(false)`: 占用成功
+///
+/// # 注意
+///
+/// 占用成功后，当前进程收到的每个信号都会使其停止并通知父进程，
+/// 父进程不调用`waitpid`处理时当前进程会一直停止。只适合由配合的启动器启动的进程，
+/// 其他情况使用`SelfTrace`
+pub fn trace_me() -> Result<bool> {
+    if unsafe { libc::ptrace(libc::PTRACE_TRACEME, 0, ptr::null_mut::<c_void>(), 0) } == 0 {
+        return Ok(false);
+    }
+
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EPERM) => {
+            warn!("PTRACE_TRACEME refused; process is already traced");
+            Ok(true)
+        }
+        _ => Err(error.into()),
+    }
+}
+
+/// fork一个辅助进程并允许它跟踪当前进程
+///
+/// 父进程通过`prctl(PR_SET_PTRACER)`放行后(Yama ptrace_scope=1时子进程默认不能附加父进程)
+/// 才通知子进程继续。fork后的子进程只能调用异步信号安全的函数，`child`中只使用libc调用
+///
+/// # 返回值
+///
+/// - `Err`: 创建管道或者fork失败
+/// - `Ok((pid, report))`: 子进程ID，以及读取附加结果的管道
+fn fork_tracer(child: fn(libc::pid_t, libc::c_int) -> !) -> Result<(libc::pid_t, libc::c_int)> {
+    let parent = unsafe { libc::getpid() };
+    let (mut go, mut report) = ([0 as libc::c_int; 2], [0 as libc::c_int; 2]);
+    unsafe {
+        if libc::pipe2(go.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if libc::pipe2(report.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            let error = io::Error::last_os_error();
+            libc::close(go[0]);
+            libc::close(go[1]);
+            return Err(error.into());
+        }
+    }
+
+    match unsafe { libc::fork() } {
+        -1 => {
+            let error = io::Error::last_os_error();
+            for fd in go.iter().chain(report.iter()) {
+                unsafe { libc::close(*fd) };
+            }
+            Err(error.into())
+        }
+        0 => unsafe {
+            libc::close(go[1]);
+            libc::close(report[0]);
+            // 父进程退出时辅助进程随之退出
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+            let mut byte: u8 = 0;
+            libc::read(go[0], &mut byte as *mut u8 as *mut c_void, 1);
+            libc::close(go[0]);
+            child(parent, report[1])
+        },
+        pid => unsafe {
+            libc::close(go[0]);
+            libc::close(report[1]);
+            // 没有启用Yama时调用失败，不影响附加
+            libc::prctl(libc::PR_SET_PTRACER, pid as libc::c_ulong);
+            libc::write(go[1], [1u8].as_ptr() as *const c_void, 1);
+            libc::close(go[1]);
+            Ok((pid, report[0]))
+        },
+    }
+}
+
+/// 读取辅助进程报告的附加结果并关闭管道，辅助进程异常退出时返回`REPORT_FAILED`
+fn read_report(report: libc::c_int) -> u8 {
+    let mut byte: u8 = REPORT_FAILED;
+    let read = unsafe { libc::read(report, &mut byte as *mut u8 as *mut c_void, 1) };
+    unsafe { libc::close(report) };
+    if read == 1 {
+        byte
+    } else {
+        REPORT_FAILED
+    }
+}
+
+/// 子进程中尝试附加父进程，失败时按errno报告
+unsafe fn seize(parent: libc::pid_t, report: libc::c_int) -> bool {
+    let attached = libc::ptrace(libc::PTRACE_SEIZE, parent, ptr::null_mut::<c_void>(), 0) == 0;
+    let code = match (attached, *libc::__errno_location()) {
+        (true, _) => REPORT_ATTACHED,
+        (false, libc::EPERM) => REPORT_REFUSED,
+        (false, _) => REPORT_FAILED,
+    };
+    libc::write(report, &code as *const u8 as *const c_void, 1);
+    libc::close(report);
+    attached
+}
+
+/// 探测辅助进程：附加成功后立即退出，跟踪关系随之解除
+fn probe_child(parent: libc::pid_t, report: libc::c_int) -> ! {
+    unsafe {
+        seize(parent, report);
+        libc::_exit(0)
+    }
+}
+
+/// 自跟踪辅助进程：附加后转发父进程收到的信号，直到父进程退出
+fn trace_child(parent: libc::pid_t, report: libc::c_int) -> ! {
+    unsafe {
+        if !seize(parent, report) {
+            libc::_exit(1);
+        }
+
+        loop {
+            let mut status: libc::c_int = 0;
+            if libc::waitpid(parent, &mut status, libc::__WALL) == -1 {
+                if *libc::__errno_location() == libc::EINTR {
+                    continue;
+                }
+                libc::_exit(0);
+            }
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                libc::_exit(0);
+            }
+            if !libc::WIFSTOPPED(status) {
+                continue;
+            }
+
+            // PTRACE_SEIZE下的组停止(SIGSTOP等)用PTRACE_LISTEN保持停止语义，其他信号原样转发
+            if status >> 16 == libc::PTRACE_EVENT_STOP {
+                libc::ptrace(libc::PTRACE_LISTEN, parent, ptr::null_mut::<c_void>(), 0);
+            } else {
+                let signal = libc::WSTOPSIG(status) as usize;
+                libc::ptrace(libc::PTRACE_CONT, parent, ptr::null_mut::<c_void>(), signal);
+            }
+        }
+    }
+}
+
+/// 等待辅助进程退出
+fn wait_child(pid: libc::pid_t) {
+    let mut status: libc::c_int = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } == -1
+        && io::Error::last_os_error().raw_os_error() == Some(libc::EINTR)
+    {}
+}
+
+/// 由子进程尝试附加当前进程，判断是否已经被其他进程跟踪
+///
+/// 与`trace_me`不同，探测结束后子进程退出，当前进程不会保持被跟踪的状态
+///
+/// # 返回值
+///
+/// - `Err`: fork失败，或者附加因为权限以外的原因失败
+/// - `Ok(true)`: 附加被拒绝(EPERM)，当前进程已经被其他进程跟踪
+/// - `Ok(false)`: 附加成功，没有其他跟踪者
+pub fn probe_attach() -> Result<bool> {
+    let (pid, report) = fork_tracer(probe_child)?;
+    let code = read_report(report);
+    wait_child(pid);
+
+    debug!("ptrace attach probe ==> {}", code);
+    match code {
+        REPORT_ATTACHED => Ok(false),
+        REPORT_REFUSED => Ok(true),
+        _ => Err(Error::msg("ptrace attach probe failed")),
+    }
+}
+
+/// 启动自跟踪辅助进程并等待附加结果
+///
+/// # 返回值
+///
+/// - `Err`: fork失败或者附加失败
+/// - `Ok(Some(pid))`: 已附加，辅助进程ID
+/// - `Ok(None)`: 附加被拒绝，当前进程已经被其他进程跟踪
+fn attach_tracer() -> Result<Option<libc::pid_t>> {
+    let (pid, report) = fork_tracer(trace_child)?;
+    match read_report(report) {
+        REPORT_ATTACHED => {
+            SELF_TRACER.store(pid, Ordering::SeqCst);
+            debug!("self trace helper attached ==> pid: {}", pid);
+            Ok(Some(pid))
+        }
+        code => {
+            wait_child(pid);
+            if code == REPORT_REFUSED {
+                Ok(None)
+            } else {
+                Err(Error::msg("self trace helper failed to attach"))
+            }
+        }
+    }
+}
+
+/// 自跟踪：由fork出的辅助进程通过`PTRACE_SEIZE`跟踪当前进程，占用跟踪者位置
+///
+/// 每个线程只能有一个跟踪者，gdb、strace等之后附加主线程时会失败(EPERM)。
+/// 辅助进程只转发信号，不影响当前进程运行；辅助进程被杀死后跟踪关系自动解除，
+/// 监控线程会重新启动辅助进程，重新附加被拒绝(说明其他进程抢先附加)时调用`on_detect`
+///
+/// # 注意
+///
+/// - 只跟踪主线程，调试器仍然可以单独附加其他线程，可以配合`find_traced_threads`检查
+/// - 附加期间/proc/self/status中的TracerPid为辅助进程ID，本模块的检测会忽略它
+pub struct SelfTrace {
+    stop: Arc<AtomicBool>,
+    monitor: Option<JoinHandle<()>>,
+}
+
+impl SelfTrace {
+    /// 重新附加被拒绝后的重试间隔
+    const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// 启动辅助进程并等待其附加到当前进程
+    ///
+    /// # 参数
+    ///
+    /// - `on_detect`: 重新附加被拒绝时的回调，参数为原因
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: fork失败，或者附加失败(例如当前进程已经被调试器跟踪)
+    /// - `Ok(self_trace)`: 已附加
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// let _self_trace = linux::SelfTrace::start(|reason| {
+    ///     eprintln!("{}", reason);
+    ///     std::process::exit(1);
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn start<F>(on_detect: F) -> Result<Self>
+    where
+        F: Fn(&str) + Send + 'static,
+    {
+        let Some(mut helper) = attach_tracer()? else {
+            warn!("self trace refused; process is already traced");
+            return Err(Error::msg("process is already traced"));
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let monitor = thread::spawn(move || loop {
+            wait_child(helper);
+            SELF_TRACER.store(0, Ordering::SeqCst);
+            if stopped.load(Ordering::SeqCst) {
+                return;
+            }
+
+            warn!("self trace helper exited; re-attaching");
+            helper = loop {
+                if stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                match attach_tracer() {
+                    Ok(Some(helper)) => break helper,
+                    Ok(None) => {
+                        on_detect("ptrace attach refused; process is traced by another tracer")
+                    }
+                    Err(e) => warn!("re-attach self trace helper failed; error: {:?}", e),
+                }
+                thread::sleep(Self::RETRY_INTERVAL);
+            };
+
+            // 重新附加与停止同时发生时，由监控线程结束新的辅助进程
+            if stopped.load(Ordering::SeqCst) {
+                unsafe { libc::kill(helper, libc::SIGKILL) };
+            }
+        });
+
+        Ok(Self {
+            stop,
+            monitor: Some(monitor),
+        })
+    }
+
+    /// 结束辅助进程并解除跟踪
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for SelfTrace {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let helper = SELF_TRACER.load(Ordering::SeqCst);
+        if helper != 0 {
+            unsafe { libc::kill(helper, libc::SIGKILL) };
+        }
+        if let Some(monitor) = self.monitor.take() {
+            let _ = monitor.join();
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub use log::Level;
+
+/// 结构化事件，不包含任何字符串
+///
+/// - `level`: 日志级别
+/// - `id`: 日志所在位置(`模块路径:行号`)的FNV-1a哈希，可以用同一份源码离线还原
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub level: Level,
+    pub id: u32,
+}
+
+/// 结构化事件的回调函数
+pub type EventHook = fn(Event);
+
+/// 以usize保存的回调函数地址，0表示没有设置
+static EVENT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// 设置结构化事件的回调函数
+///
+/// 开启`strip-logs` feature后日志字符串不会编译进二进制，事件仍然会发给回调函数，
+/// 调用方可以只上报事件编号
+///
+/// # 参数
+///
+/// - `hook`: 回调函数，为`None`时取消
+///
+/// # 示例
+///
+/// ```ignore
+/// logging::set_event_hook(Some(|event| telemetry::send(event.level as u8, event.id)));
+/// ```
+pub fn set_event_hook(hook: Option<EventHook>) {
+    EVENT_HOOK.store(hook.map_or(0, |hook| hook as usize), Ordering::Release);
+}
+
+/// 将事件发给回调函数，由日志宏调用
+#[doc(hidden)]
+pub fn emit(level: Level, id: u32) {
+    let hook = EVENT_HOOK.load(Ordering::Acquire);
+    if hook != 0 {
+        let hook = unsafe { std::mem::transmute::<usize, EventHook>(hook) };
+        hook(Event { level, id });
+    }
+}
+
+/// 计算日志位置的编号，编译期求值
+pub const fn event_id(location: &str) -> u32 {
+    let bytes = location.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// 发出结构化事件并记录日志；开启`strip-logs` feature时只发出事件，日志字符串不会编译进二进制
+macro_rules! log_event {
+    ($level:ident, $($arg:tt)+) => {{
+        const ID: u32 = $crate::logging::event_id(concat!(module_path!(), ":", line!()));
+        $crate::logging::emit($crate::logging::Level::$level, ID);
+        #[cfg(not(feature = "strip-logs"))]
+        log::log!($crate::logging::Level::$level, $($arg)+);
+        // 保留参数的类型检查与变量引用，分支在编译期被消除
+        #[cfg(feature = "strip-logs")]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// 替代`log::debug!`
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        $crate::logging::log_event!(Debug, $($arg)+)
+    };
+}
+
+/// 替代`log::warn!`
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        $crate::logging::log_event!(Warn, $($arg)+)
+    };
+}
+
+/// 替代`log::error!`
+#[allow(unused_macros)]
+macro_rules! log_error {
+    ($($arg:tt)+) => {
+        $crate::logging::log_event!(Error, $($arg)+)
+    };
+}
+
+// 与内置属性`warn`同名的宏不能直接定义，以别名导出；部分宏只在特定平台的模块中使用
+#[allow(unused_imports)]
+pub(crate) use {log_debug as debug, log_error as error, log_event, log_warn as warn};
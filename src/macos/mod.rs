@@ -0,0 +1,418 @@
+use crate::logging::{debug, warn};
+use crate::util::BeingDebug;
+use anyhow::{Error, Result};
+use std::{
+    ffi::{c_void, CStr},
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// 是否已经对当前进程调用了`PT_DENY_ATTACH`
+static ATTACH_DENIED: AtomicBool = AtomicBool::new(false);
+
+/// 调试器及其前端的进程名(小写)，当前进程由它们直接启动时父进程为其中之一
+pub const DEBUGGER_PARENTS: [&str; 6] = ["lldb", "debugserver", "gdb", "xcode", "dtrace", "dtruss"];
+
+/// dyld在加载程序前注入动态库的环境变量
+///
+/// `DYLD_LIBRARY_PATH`、`DYLD_FRAMEWORK_PATH`等搜索路径变量常由Xcode、cargo等构建工具设置，
+/// 不计入；通过它们替换的动态库由`find_injected_images`按路径与签名检查
+pub const DYLD_ENV_VARS: [&str; 1] = ["DYLD_INSERT_LIBRARIES"];
+
+/// 系统动态库所在的目录，其中的镜像来自dyld共享缓存或者系统签名卷，视为可信
+pub const SYSTEM_IMAGE_PREFIXES: [&str; 3] = ["/usr/lib/", "/System/", "/Library/Apple/"];
+
+/// 包管理器(Homebrew、MacPorts)安装动态库的目录，x86_64下其中的动态库通常没有签名，视为可信
+pub const PACKAGE_IMAGE_PREFIXES: [&str; 3] = ["/opt/homebrew/", "/usr/local/", "/opt/local/"];
+
+/// 注入工具与插桩框架的动态库名称关键字(小写)
+pub const INJECTED_LIBRARIES: [&str; 6] = [
+    "frida",
+    "cynject",
+    "substrate",
+    "substitute",
+    "libcycript",
+    "sslkillswitch",
+];
+
+/// 64位Mach-O头的魔数与大小
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const MACH_HEADER_64_SIZE: usize = 32;
+
+/// 代码签名加载命令
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+
+/// `kinfo_proc`的大小，x86_64与arm64相同
+const KINFO_PROC_SIZE: usize = 648;
+
+/// `kinfo_proc.kp_proc.p_flag`的偏移(p_un 16字节 + p_vmspace + p_sigacts)
+const P_FLAG_OFFSET: usize = 32;
+
+/// `p_flag`中表示进程被跟踪的标志
+pub const P_TRACED: i32 = 0x0000_0800;
+
+/// 通过`sysctl(KERN_PROC_PID)`读取进程的`p_flag`
+///
+/// libc没有导出`kinfo_proc`，按固定布局读取，只使用`kp_proc.p_flag`
+///
+/// # 参数
+///
+/// - `pid`: 进程ID
+///
+/// # 返回值
+///
+/// - `Err`: sysctl调用失败或者返回的长度不对
+/// - `Ok(flag)`: `p_flag`的值
+pub fn read_proc_flag(pid: libc::pid_t) -> Result<i32> {
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_PID, pid];
+    let mut info = [0u8; KINFO_PROC_SIZE];
+    let mut size: libc::size_t = KINFO_PROC_SIZE;
+    if unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as libc::c_uint,
+            info.as_mut_ptr() as *mut c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    } != 0
+    {
+        return Err(io::Error::last_os_error().into());
+    }
+    if size != KINFO_PROC_SIZE {
+        return Err(Error::msg(format!("unexpected kinfo_proc size: {}", size)));
+    }
+
+    let mut flag = [0u8; 4];
+    flag.copy_from_slice(&info[P_FLAG_OFFSET..P_FLAG_OFFSET + 4]);
+    Ok(i32::from_ne_bytes(flag))
+}
+
+/// 当前进程是否被跟踪
+///
+/// lldb、debugserver等通过ptrace附加后，内核在`p_flag`中设置`P_TRACED`，这也是Apple官方文档推荐的检测方式
+///
+/// # 返回值
+///
+/// - `Err`: sysctl调用失败
+/// - `Ok(true)`: 被跟踪
+/// - `Ok(false)`: 没有被跟踪
+///
+/// # 示例
+///
+/// ```ignore
+/// if macos::is_traced().unwrap() {
+///     println!("process is being traced");
+/// }
+/// ```
+pub fn is_traced() -> Result<bool> {
+    let flag = read_proc_flag(unsafe { libc::getpid() })?;
+    debug!("p_flag ==> {:#x}", flag);
+    Ok(flag & P_TRACED != 0)
+}
+
+/// 获取进程的可执行文件路径
+///
+/// # 参数
+///
+/// - `pid`: 进程ID
+pub fn process_path(pid: libc::pid_t) -> Result<String> {
+    let mut buffer = [0u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+    let len =
+        unsafe { libc::proc_pidpath(pid, buffer.as_mut_ptr() as *mut c_void, buffer.len() as u32) };
+    if len <= 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(String::from_utf8_lossy(&buffer[..len as usize]).into_owned())
+}
+
+/// 检查父进程是否是调试器
+///
+/// 在lldb或者Xcode中启动时，当前进程的父进程是debugserver/lldb；正常启动时父进程是launchd或者shell。
+/// 父进程路径的文件名或者所在的应用包(例如`Xcode.app`)包含`DEBUGGER_PARENTS`中的名称即视为调试器
+///
+/// # 返回值
+///
+/// - `Err`: 无法获取父进程路径
+/// - `Ok(Some(path))`: 父进程是调试器
+/// - `Ok(None)`: 父进程不是调试器
+pub fn check_debugger_parent() -> Result<Option<String>> {
+    let path = process_path(unsafe { libc::getppid() })?;
+    let lower = path.to_lowercase();
+    let name = Path::new(&lower)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let matched = DEBUGGER_PARENTS
+        .iter()
+        .any(|debugger| name.contains(debugger) || lower.contains(&format!("/{}.app/", debugger)));
+    debug!("parent process ==> {}; debugger: {}", path, matched);
+    if matched {
+        debug!("debugger parent process ==> {}", path);
+    }
+
+    Ok(matched.then_some(path))
+}
+
+/// 读取dyld注入环境变量
+///
+/// # 返回值
+///
+/// - 设置了的环境变量与其值，例如`("DYLD_INSERT_LIBRARIES", "/tmp/hook.dylib")`
+///
+/// # 注意
+///
+/// 启用Hardened Runtime且没有`allow-dyld-environment-variables`权限的程序，dyld会忽略并清除这些变量
+pub fn dyld_env() -> Vec<(&'static str, String)> {
+    DYLD_ENV_VARS
+        .iter()
+        .filter_map(|name| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(|value| (*name, value.to_string_lossy().into_owned()))
+        })
+        .collect()
+}
+
+/// dyld加载的镜像
+///
+/// - `name`: 镜像路径
+/// - `header`: 内存中Mach-O头的地址
+/// - `signed`: 是否有代码签名加载命令(`LC_CODE_SIGNATURE`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedImage {
+    pub name: String,
+    pub header: usize,
+    pub signed: bool,
+}
+
+/// 镜像的加载命令中是否有`LC_CODE_SIGNATURE`，只解析64位Mach-O
+fn has_code_signature(header: *const u8) -> bool {
+    unsafe {
+        let read = |offset: usize| (header.add(offset) as *const u32).read_unaligned();
+        if read(0) != MH_MAGIC_64 {
+            return false;
+        }
+
+        let (count, size) = (read(16), read(20) as usize);
+        let mut offset: usize = 0;
+        for _ in 0..count {
+            if offset + 8 > size {
+                break;
+            }
+            let (cmd, cmdsize) = (
+                read(MACH_HEADER_64_SIZE + offset),
+                read(MACH_HEADER_64_SIZE + offset + 4),
+            );
+            if cmd == LC_CODE_SIGNATURE {
+                return true;
+            }
+            if cmdsize < 8 {
+                break;
+            }
+            offset += cmdsize as usize;
+        }
+    }
+
+    false
+}
+
+/// 通过`_dyld_image_count`/`_dyld_get_image_name`枚举当前进程加载的镜像
+///
+/// # 注意
+///
+/// 遍历期间其他线程可能加载或卸载镜像，索引对应的镜像可能变化，取不到的索引直接跳过
+pub fn loaded_images() -> Vec<LoadedImage> {
+    let mut images: Vec<LoadedImage> = Vec::new();
+    for index in 0..unsafe { libc::_dyld_image_count() } {
+        let (name, header) = unsafe {
+            (
+                libc::_dyld_get_image_name(index),
+                libc::_dyld_get_image_header(index),
+            )
+        };
+        if name.is_null() || header.is_null() {
+            continue;
+        }
+
+        images.push(LoadedImage {
+            name: unsafe { CStr::from_ptr(name) }
+                .to_string_lossy()
+                .into_owned(),
+            header: header as usize,
+            signed: has_code_signature(header as *const u8),
+        });
+    }
+
+    images
+}
+
+/// 镜像是否可疑：名称包含`INJECTED_LIBRARIES`中的关键字，或者不在系统与包管理器目录且没有代码签名
+///
+/// # 参数
+///
+/// - `image`: 镜像
+/// - `main`: 是否是主程序，主程序不检查签名
+fn is_suspicious_image(image: &LoadedImage, main: bool) -> bool {
+    let name = image.name.to_lowercase();
+    if INJECTED_LIBRARIES
+        .iter()
+        .any(|library| name.contains(library))
+    {
+        return true;
+    }
+
+    !main
+        && !image.signed
+        && !SYSTEM_IMAGE_PREFIXES
+            .iter()
+            .chain(PACKAGE_IMAGE_PREFIXES.iter())
+            .any(|prefix| image.name.starts_with(prefix))
+}
+
+/// 查找注入的或者未签名的动态库
+///
+/// arm64下所有镜像都必须签名(至少是链接器生成的ad-hoc签名)，未签名的检查主要对x86_64有效。
+/// 第一个镜像是主程序，开发构建通常没有签名，只检查名称
+///
+/// # 返回值
+///
+/// - 可疑的镜像，为空表示没有发现
+///
+/// # 示例
+///
+/// ```ignore
+/// for image in macos::find_injected_images() {
+///     println!("{} signed: {}", image.name, image.signed);
+/// }
+/// ```
+pub fn find_injected_images() -> Vec<LoadedImage> {
+    let images: Vec<LoadedImage> = loaded_images()
+        .into_iter()
+        .enumerate()
+        .filter(|(index, image)| is_suspicious_image(image, *index == 0))
+        .map(|(_, image)| image)
+        .collect();
+
+    if !images.is_empty() {
+        warn!("suspicious dyld images ==> {:?}", images);
+    }
+    images
+}
+
+/// 通过sysctl检测macOS下的调试器
+pub struct SysctlDebug {}
+
+impl BeingDebug for SysctlDebug {
+    fn is_being_debug(&self) -> bool {
+        is_traced().unwrap_or(false)
+    }
+}
+
+/// 对当前进程调用`ptrace(PT_DENY_ATTACH)`，之后调试器无法再附加
+///
+/// 调用前先检查`P_TRACED`：已经被跟踪时调用`PT_DENY_ATTACH`会使进程立即以`ENOTSUP`(45)退出，
+/// 这里不调用并返回`Ok(true)`，由调用方决定如何处理
+///
+/// # 返回值
+///
+/// - `Err`: sysctl或者ptrace调用失败
+/// - `Ok(true)`: 已经被跟踪，没有调用
+/// - `Ok(false)`: 调用成功
+///
+/// # 注意
+///
+/// - 只阻止ptrace附加，`task_for_pid`等Mach接口读写内存不受影响
+/// - 在ptrace上设置断点或者修改系统调用的返回值即可绕过，只能提高调试门槛
+/// - 之后在lldb中启动或附加时进程会直接退出(状态码45)，开发调试时应通过配置关闭
+/// - 改变进程状态，检测引擎不会调用，需要由程序在启动时显式调用
+///
+/// # 示例
+///
+/// ```ignore
+/// if macos::deny_attach().unwrap() {
+///     std::process::exit(1);
+/// }
+/// ```
+pub fn deny_attach() -> Result<bool> {
+    if is_traced()? {
+        warn!("PT_DENY_ATTACH skipped; process is already traced");
+        return Ok(true);
+    }
+
+    if unsafe { libc::ptrace(libc::PT_DENY_ATTACH, 0, std::ptr::null_mut(), 0) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    ATTACH_DENIED.store(true, Ordering::SeqCst);
+    debug!("PT_DENY_ATTACH applied");
+
+    Ok(false)
+}
+
+/// 当前进程是否已经调用过`PT_DENY_ATTACH`
+pub fn is_attach_denied() -> bool {
+    ATTACH_DENIED.load(Ordering::SeqCst)
+}
+
+/// 在fork出的子进程中调用`PT_DENY_ATTACH`后执行`work`，等待子进程退出并返回其退出码
+///
+/// 敏感逻辑放在子进程中执行，父进程保持可调试(例如保留崩溃报告)，只有子进程拒绝附加。
+/// ptrace跟踪关系不会被子进程继承，子进程中可以直接调用`PT_DENY_ATTACH`
+///
+/// # 参数
+///
+/// - `work`: 子进程中执行的函数，返回值作为子进程的退出码
+///
+/// # 返回值
+///
+/// - `Err`: fork失败，或者子进程被信号终止
+/// - `Ok(code)`: 子进程的退出码
+///
+/// # 注意
+///
+/// 多线程进程fork后子进程中只有当前线程，其他线程持有的锁(包括内存分配器的锁)不会被释放，
+/// 应在创建其他线程之前调用，或者保证`work`中只调用异步信号安全的函数
+///
+/// # 示例
+///
+/// ```ignore
+/// let code = macos::run_denied(|| {
+///     verify_license();
+///     0
+/// })
+/// .unwrap();
+/// ```
+pub fn run_denied(work: fn() -> i32) -> Result<i32> {
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    if pid == 0 {
+        unsafe {
+            libc::ptrace(libc::PT_DENY_ATTACH, 0, std::ptr::null_mut(), 0);
+            libc::_exit(work());
+        }
+    }
+
+    let mut status: libc::c_int = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EINTR) {
+            return Err(error.into());
+        }
+    }
+    debug!("denied child ==> pid: {}; status: {:#x}", pid, status);
+
+    if libc::WIFEXITED(status) {
+        Ok(libc::WEXITSTATUS(status))
+    } else {
+        Err(Error::msg(format!(
+            "denied child terminated; status: {:#x}",
+            status
+        )))
+    }
+}
@@ -0,0 +1,227 @@
+use crate::engine::{Report, Verdict};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+/// 执行的检测技术数量，标签：`technique`、`category`
+pub const CHECKS_EXECUTED: &str = "anti_debug_checks_executed_total";
+
+/// 执行失败的检测技术数量，标签：`technique`、`category`
+pub const CHECK_FAILURES: &str = "anti_debug_check_failures_total";
+
+/// 并行执行时超时的检测技术数量，标签：`technique`、`category`
+pub const CHECK_TIMEOUTS: &str = "anti_debug_check_timeouts_total";
+
+/// 命中的检测技术数量，标签：`technique`、`category`
+pub const DETECTIONS: &str = "anti_debug_detections_total";
+
+/// 单个检测技术的耗时(秒)，标签：`technique`、`category`
+pub const CHECK_LATENCY: &str = "anti_debug_check_duration_seconds";
+
+/// 一次完整检测的耗时(秒)，标签：`mode`(`sequential`或者`parallel`)
+pub const SCAN_LATENCY: &str = "anti_debug_scan_duration_seconds";
+
+/// 指标标签，按(名称, 值)排列
+pub type Labels<'a> = [(&'a str, &'a str)];
+
+/// 可插拔的指标接口，宿主程序实现后通过`install`注册，由检测引擎在执行时上报
+///
+/// 实现只需要把调用转发到Prometheus、StatsD等导出器，所有方法都可能在多个线程中同时调用
+///
+/// # 示例
+///
+/// ```ignore
+/// struct StatsD(UdpSocket);
+///
+/// impl Metrics for StatsD {
+///     fn increment(&self, name: &str, labels: &Labels, value: u64) {
+///         let _ = self.0.send(format!("{}:{}|c", name, value).as_bytes());
+///     }
+///
+///     fn observe(&self, name: &str, labels: &Labels, value: f64) {
+///         let _ = self.0.send(format!("{}:{}|ms", name, value * 1000.0).as_bytes());
+///     }
+/// }
+///
+/// metrics::install(Arc::new(StatsD(socket)));
+/// ```
+pub trait Metrics: Send + Sync {
+    /// 计数器增加`value`
+    fn increment(&self, name: &str, labels: &Labels, value: u64);
+
+    /// 直方图记录一个观测值，耗时以秒为单位
+    fn observe(&self, name: &str, labels: &Labels, value: f64);
+}
+
+static METRICS: RwLock<Option<Arc<dyn Metrics>>> = RwLock::new(None);
+
+/// 注册全局指标实现，替换之前注册的实现
+pub fn install(metrics: Arc<dyn Metrics>) {
+    if let Ok(mut current) = METRICS.write() {
+        *current = Some(metrics);
+    }
+}
+
+/// 移除全局指标实现，之后不再上报
+pub fn uninstall() {
+    if let Ok(mut current) = METRICS.write() {
+        *current = None;
+    }
+}
+
+/// 当前注册的指标实现
+fn installed() -> Option<Arc<dyn Metrics>> {
+    METRICS.read().ok()?.clone()
+}
+
+/// 上报单个检测技术的执行结果与耗时
+pub(crate) fn record_verdict(verdict: &Verdict, elapsed: Duration) {
+    let Some(metrics) = installed() else {
+        return;
+    };
+    let labels = [
+        ("technique", verdict.name),
+        ("category", verdict.category.name()),
+    ];
+    metrics.increment(CHECKS_EXECUTED, &labels, 1);
+    if verdict.error.is_some() {
+        metrics.increment(CHECK_FAILURES, &labels, 1);
+    }
+    if verdict.detected {
+        metrics.increment(DETECTIONS, &labels, 1);
+    }
+    metrics.observe(CHECK_LATENCY, &labels, elapsed.as_secs_f64());
+}
+
+/// 上报一次完整检测的耗时与超时的检测技术
+pub(crate) fn record_scan(report: &Report, mode: &str, elapsed: Duration) {
+    let Some(metrics) = installed() else {
+        return;
+    };
+    for verdict in report.verdicts.iter().filter(|verdict| verdict.timed_out) {
+        metrics.increment(
+            CHECK_TIMEOUTS,
+            &[
+                ("technique", verdict.name),
+                ("category", verdict.category.name()),
+            ],
+            1,
+        );
+    }
+    metrics.observe(SCAN_LATENCY, &[("mode", mode)], elapsed.as_secs_f64());
+}
+
+/// 直方图的累计值
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// 指标名称与排序后的标签
+type Key = (String, Vec<(String, String)>);
+
+fn key(name: &str, labels: &Labels) -> Key {
+    let mut labels: Vec<(String, String)> = labels
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    labels.sort();
+    (name.to_string(), labels)
+}
+
+/// 保存在内存中的指标实现，可以直接读取，或者渲染为Prometheus文本格式由宿主的HTTP端点返回
+///
+/// # 示例
+///
+/// ```ignore
+/// let registry = Arc::new(MemoryMetrics::default());
+/// metrics::install(registry.clone());
+/// Engine::default().run();
+/// println!("{}", registry.to_prometheus());
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryMetrics {
+    counters: Mutex<BTreeMap<Key, u64>>,
+    histograms: Mutex<BTreeMap<Key, Histogram>>,
+}
+
+impl MemoryMetrics {
+    /// 读取计数器，没有记录时为0
+    pub fn counter(&self, name: &str, labels: &Labels) -> u64 {
+        let counters = self.counters.lock().unwrap();
+        counters.get(&key(name, labels)).copied().unwrap_or(0)
+    }
+
+    /// 读取直方图，没有记录时为None
+    pub fn histogram(&self, name: &str, labels: &Labels) -> Option<Histogram> {
+        let histograms = self.histograms.lock().unwrap();
+        histograms.get(&key(name, labels)).cloned()
+    }
+
+    /// 渲染为Prometheus文本格式，直方图输出为summary的`_count`与`_sum`
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        let mut last = String::new();
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if *name != last {
+                let _ = writeln!(text, "# TYPE {} counter", name);
+                last.clone_from(name);
+            }
+            let _ = writeln!(text, "{}{} {}", name, render_labels(labels), value);
+        }
+        for ((name, labels), histogram) in self.histograms.lock().unwrap().iter() {
+            if *name != last {
+                let _ = writeln!(text, "# TYPE {} summary", name);
+                last.clone_from(name);
+            }
+            let labels = render_labels(labels);
+            let _ = writeln!(text, "{}_count{} {}", name, labels, histogram.count);
+            let _ = writeln!(text, "{}_sum{} {}", name, labels, histogram.sum);
+        }
+        text
+    }
+}
+
+impl Metrics for MemoryMetrics {
+    fn increment(&self, name: &str, labels: &Labels, value: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.entry(key(name, labels)).or_default() += value;
+    }
+
+    fn observe(&self, name: &str, labels: &Labels, value: f64) {
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry(key(name, labels)).or_default();
+        if histogram.count == 0 || value < histogram.min {
+            histogram.min = value;
+        }
+        if histogram.count == 0 || value > histogram.max {
+            histogram.max = value;
+        }
+        histogram.count += 1;
+        histogram.sum += value;
+    }
+}
+
+/// Prometheus标签格式：`{name="value",...}`，没有标签时为空
+fn render_labels(labels: &[(String, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
@@ -0,0 +1,387 @@
+use crate::logging::{debug, warn};
+use crate::{
+    imports::GetModuleHandleW,
+    obf, resolve,
+    signature::{self, SignatureKind},
+    thread_monitor,
+    util::to_wide,
+};
+use anyhow::{Error, Result};
+use std::{
+    env,
+    ffi::c_void,
+    mem::size_of,
+    path::{Path, PathBuf},
+    ptr,
+    sync::mpsc::{self, Receiver, Sender},
+};
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{HMODULE, NTSTATUS, UNICODE_STRING},
+        System::{
+            ProcessStatus::EnumProcessModules,
+            SystemInformation::{
+                GetSystemDirectoryW, GetSystemWow64DirectoryW, GetWindowsDirectoryW,
+            },
+            Threading::GetCurrentProcess,
+        },
+    },
+};
+
+/// 调试符号引擎相关的DLL，普通程序一般不会加载，
+/// 通常是被注入的分析工具带进来的
+pub const SYMBOL_ENGINE_DLLS: [&str; 3] = ["dbghelp.dll", "symsrv.dll", "dbgcore.dll"];
+
+/// 判断指定名称的DLL是否已经加载到当前进程中
+///
+/// # 参数
+///
+/// - `name`: DLL名称，例如`dbghelp.dll`
+///
+/// # 返回值
+///
+/// - `true`: 已加载
+/// - `false`: 未加载
+pub fn is_module_loaded(name: &str) -> bool {
+    let wide = to_wide(name);
+    unsafe { GetModuleHandleW(Some(PCWSTR(wide.as_ptr()))) }.is_ok()
+}
+
+/// 获取当前进程中所有已加载模块的句柄
+///
+/// # 返回值
+///
+/// - `Err`: EnumProcessModules调用失败
+/// - `Ok(modules)`: 模块句柄列表，第一个为主模块
+pub fn get_loaded_modules() -> Result<Vec<HMODULE>> {
+    let hprocess = unsafe { GetCurrentProcess() };
+    let mut modules: Vec<HMODULE> = vec![HMODULE::default(); 256];
+    loop {
+        let cb = (modules.len() * size_of::<HMODULE>()) as u32;
+        let mut needed: u32 = 0;
+        unsafe { EnumProcessModules(hprocess, modules.as_mut_ptr(), cb, &mut needed) }?;
+
+        let count = needed as usize / size_of::<HMODULE>();
+        if count <= modules.len() {
+            modules.truncate(count);
+            return Ok(modules);
+        }
+
+        // 缓冲区不够时按照返回的大小重新分配
+        modules.resize(count, HMODULE::default());
+    }
+}
+
+/// 检测当前进程是否加载了调试符号引擎DLL
+///
+/// # 参数
+///
+/// - `allowlist`: 宿主程序自身会合法使用的DLL名称(不区分大小写)，这些DLL不会被报告
+///
+/// # 返回值
+///
+/// - `Some(name)`: 被加载且不在白名单中的DLL名称
+/// - `None`: 没有发现符号引擎DLL
+///
+/// # 注意
+///
+/// Rust标准库在打印backtrace时会加载dbghelp.dll，
+/// 如果宿主程序开启了`RUST_BACKTRACE`，需要将dbghelp.dll加入白名单
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some(name) = check_symbol_engine_loaded(&[]) {
+///     println!("{} is loaded", name);
+/// }
+/// ```
+pub fn check_symbol_engine_loaded(allowlist: &[&str]) -> Option<String> {
+    for name in SYMBOL_ENGINE_DLLS {
+        if allowlist
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+
+        if is_module_loaded(name) {
+            debug!("symbol engine dll loaded ==> {}", name);
+            return Some(name.to_string());
+        }
+    }
+
+    None
+}
+
+/// DLL加载通知的原因
+const LDR_DLL_NOTIFICATION_REASON_LOADED: u32 = 1;
+
+/// LDR_DLL_LOADED_NOTIFICATION_DATA，加载与卸载通知的数据结构相同
+#[repr(C)]
+struct LdrDllNotificationData {
+    flags: u32,
+    full_dll_name: *const UNICODE_STRING,
+    base_dll_name: *const UNICODE_STRING,
+    dll_base: *mut c_void,
+    size_of_image: u32,
+}
+
+type LdrDllNotificationFn =
+    unsafe extern "system" fn(u32, *const LdrDllNotificationData, *mut c_void);
+type LdrRegisterDllNotificationFn =
+    unsafe extern "system" fn(u32, LdrDllNotificationFn, *mut c_void, *mut *mut c_void) -> NTSTATUS;
+type LdrUnregisterDllNotificationFn = unsafe extern "system" fn(*mut c_void) -> NTSTATUS;
+
+/// 运行时加载的可疑DLL
+///
+/// - `name`: DLL名称
+/// - `path`: DLL完整路径
+/// - `base`: 加载基址
+/// - `size`: 映像大小
+/// - `reason`: 命中的特征或者路径策略
+#[derive(Debug, Clone, PartialEq)]
+pub struct DllEvent {
+    pub name: String,
+    pub path: PathBuf,
+    pub base: usize,
+    pub size: u32,
+    pub reason: String,
+}
+
+/// 新加载DLL的筛查策略
+///
+/// - `trusted_dirs`: 可信目录，位于这些目录之外的DLL会被报告
+///
+/// 不论路径如何，名称命中`module`特征列表的DLL都会被报告
+#[derive(Debug, Clone, PartialEq)]
+pub struct DllPolicy {
+    pub trusted_dirs: Vec<PathBuf>,
+}
+
+impl Default for DllPolicy {
+    /// 默认信任System32、SysWOW64、WinSxS以及当前程序所在目录
+    ///
+    /// 不信任整个Windows目录，`C:\Windows\Temp`、`C:\Windows\Tasks`等目录普通用户可写
+    fn default() -> Self {
+        let mut trusted_dirs: Vec<PathBuf> = Vec::new();
+        let mut buffer = [0u16; 260];
+
+        let len = unsafe { GetSystemDirectoryW(Some(&mut buffer)) } as usize;
+        if len > 0 && len < buffer.len() {
+            trusted_dirs.push(PathBuf::from(String::from_utf16_lossy(&buffer[..len])));
+        }
+        // 32位系统上没有SysWOW64，调用失败时返回0
+        let len = unsafe { GetSystemWow64DirectoryW(Some(&mut buffer)) } as usize;
+        if len > 0 && len < buffer.len() {
+            trusted_dirs.push(PathBuf::from(String::from_utf16_lossy(&buffer[..len])));
+        }
+        let len = unsafe { GetWindowsDirectoryW(Some(&mut buffer)) } as usize;
+        if len > 0 && len < buffer.len() {
+            trusted_dirs
+                .push(PathBuf::from(String::from_utf16_lossy(&buffer[..len])).join("WinSxS"));
+        }
+        if let Some(dir) = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        {
+            trusted_dirs.push(dir);
+        }
+
+        Self { trusted_dirs }
+    }
+}
+
+impl DllPolicy {
+    /// 按策略筛查DLL
+    ///
+    /// # 参数
+    ///
+    /// - `path`: DLL完整路径
+    ///
+    /// # 返回值
+    ///
+    /// - `Some(reason)`: DLL可疑，`reason`为命中的特征或者`untrusted path`
+    /// - `None`: DLL符合策略
+    pub fn screen(&self, path: &Path) -> Option<String> {
+        let name = path.file_name()?.to_string_lossy();
+        if let Some(pattern) = signature::matches(SignatureKind::Module, &name) {
+            return Some(pattern);
+        }
+
+        let path = path.to_string_lossy().to_lowercase();
+        let trusted = self.trusted_dirs.iter().any(|dir| {
+            let dir = dir.to_string_lossy().to_lowercase();
+            path.strip_prefix(dir.trim_end_matches('\\'))
+                .is_some_and(|rest| rest.starts_with('\\'))
+        });
+
+        (!trusted).then(|| "untrusted path".to_string())
+    }
+}
+
+/// 加载通知中复制出来的原始数据，由工作线程按策略筛查
+struct LoadedDll {
+    name: String,
+    path: PathBuf,
+    base: usize,
+    size: u32,
+}
+
+/// 回调上下文，生命周期与`DllWatcher`一致
+struct WatcherContext {
+    sender: Sender<LoadedDll>,
+}
+
+/// 运行时DLL注入监视器
+///
+/// 通过`LdrRegisterDllNotification`注册DLL加载通知。回调在加载器锁内执行，
+/// 只复制DLL的路径与基址并放入队列，特征匹配等可能读取文件或者加锁的筛查
+/// 由工作线程按`DllPolicy`完成，可疑的DLL作为事件通过`poll`取出
+///
+/// 释放时自动注销通知，队列关闭后工作线程退出
+pub struct DllWatcher {
+    cookie: *mut c_void,
+    context: *mut WatcherContext,
+    receiver: Receiver<DllEvent>,
+}
+
+impl DllWatcher {
+    /// 开始监视DLL加载
+    ///
+    /// # 参数
+    ///
+    /// - `policy`: 筛查策略
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 系统不支持DLL加载通知(Vista以前)或者注册失败
+    /// - `Ok(watcher)`: 监视器
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// let watcher = DllWatcher::start(DllPolicy::default()).unwrap();
+    /// loop {
+    ///     for event in watcher.poll() {
+    ///         println!("{:?} injected: {}", event.path, event.reason);
+    ///     }
+    ///     std::thread::sleep(std::time::Duration::from_secs(1));
+    /// }
+    /// ```
+    pub fn start(policy: DllPolicy) -> Result<Self> {
+        let register = resolve!("ntdll.dll", "LdrRegisterDllNotification")
+            .ok_or_else(|| Error::msg(obf!("LdrRegisterDllNotification not found").decrypt()))?;
+        let register: LdrRegisterDllNotificationFn = unsafe { resolve::function(register) };
+
+        let (sender, loaded) = mpsc::channel::<LoadedDll>();
+        let (events, receiver) = mpsc::channel();
+        thread_monitor::spawn_hidden(move || {
+            for dll in loaded {
+                if let Some(reason) = policy.screen(&dll.path) {
+                    debug!(
+                        "suspicious dll loaded ==> {:?}; reason: {}",
+                        dll.path, reason
+                    );
+                    let event = DllEvent {
+                        name: dll.name,
+                        path: dll.path,
+                        base: dll.base,
+                        size: dll.size,
+                        reason,
+                    };
+                    if events.send(event).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let context = Box::into_raw(Box::new(WatcherContext { sender }));
+        let mut cookie: *mut c_void = ptr::null_mut();
+        let status = unsafe { register(0, dll_notification, context.cast(), &mut cookie) };
+        if status.is_err() {
+            drop(unsafe { Box::from_raw(context) });
+            warn!(
+                "LdrRegisterDllNotification failed; error code: {:?}",
+                status
+            );
+            return Err(Error::msg(
+                obf!("LdrRegisterDllNotification failed").decrypt(),
+            ));
+        }
+
+        debug!("dll watcher started ==> cookie: {:?}", cookie);
+
+        Ok(Self {
+            cookie,
+            context,
+            receiver,
+        })
+    }
+
+    /// 取出工作线程目前为止筛查出的所有可疑DLL事件
+    pub fn poll(&self) -> Vec<DllEvent> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for DllWatcher {
+    fn drop(&mut self) {
+        let Some(unregister) = resolve!("ntdll.dll", "LdrUnregisterDllNotification") else {
+            // 无法注销时回调仍可能被调用，不能释放上下文
+            warn!("LdrUnregisterDllNotification not found");
+            return;
+        };
+
+        let unregister: LdrUnregisterDllNotificationFn = unsafe { resolve::function(unregister) };
+        let status = unsafe { unregister(self.cookie) };
+        if status.is_err() {
+            warn!(
+                "LdrUnregisterDllNotification failed; error code: {:?}",
+                status
+            );
+            return;
+        }
+
+        drop(unsafe { Box::from_raw(self.context) });
+    }
+}
+
+/// 将UNICODE_STRING转换为String
+fn unicode_string_to_string(string: *const UNICODE_STRING) -> Option<String> {
+    let string = unsafe { string.as_ref()? };
+    if string.Buffer.is_null() {
+        return None;
+    }
+
+    let len = string.Length as usize / 2;
+    Some(String::from_utf16_lossy(unsafe {
+        std::slice::from_raw_parts(string.Buffer.0, len)
+    }))
+}
+
+/// DLL加载通知回调，在加载器锁内执行
+unsafe extern "system" fn dll_notification(
+    reason: u32,
+    data: *const LdrDllNotificationData,
+    context: *mut c_void,
+) {
+    if reason != LDR_DLL_NOTIFICATION_REASON_LOADED {
+        return;
+    }
+    let (Some(data), Some(context)) = (data.as_ref(), (context as *const WatcherContext).as_ref())
+    else {
+        return;
+    };
+    let Some(path) = unicode_string_to_string(data.full_dll_name).map(PathBuf::from) else {
+        return;
+    };
+
+    let _ = context.sender.send(LoadedDll {
+        name: unicode_string_to_string(data.base_dll_name).unwrap_or_default(),
+        path,
+        base: data.dll_base as usize,
+        size: data.size_of_image,
+    });
+}
@@ -1,18 +1,75 @@
-use crate::util::BeingDebug;
-use anyhow::Result;
-use log::{debug, warn};
-use std::{mem::size_of_val, ptr::addr_of_mut};
+use crate::logging::{debug, warn};
+use crate::{
+    capability, clean_ntdll, imports::CheckRemoteDebuggerPresent, obf, resolve, shuffle,
+    util::BeingDebug,
+};
+use anyhow::{Error, Result};
+use std::{ffi::c_void, mem::size_of_val, ptr::addr_of_mut};
 use windows::{
     Wdk::System::Threading::{
-        NtQueryInformationProcess, ProcessDebugFlags, ProcessDebugObjectHandle, ProcessDebugPort,
+        ProcessBasicInformation, ProcessDebugFlags, ProcessDebugObjectHandle, ProcessDebugPort,
         PROCESSINFOCLASS,
     },
     Win32::{
         Foundation::{BOOL, HANDLE, NTSTATUS, STATUS_PORT_NOT_SET, STATUS_SUCCESS},
-        System::{Diagnostics::Debug::CheckRemoteDebuggerPresent, Threading::GetCurrentProcess},
+        System::Threading::{GetCurrentProcess, PROCESS_BASIC_INFORMATION},
     },
 };
 
+/// NtQueryInformationProcess的函数签名，用于通过干净的ntdll副本调用
+pub type NtQueryInformationProcessFn = unsafe extern "system" fn(
+    HANDLE,
+    PROCESSINFOCLASS,
+    *mut c_void,
+    u32,
+    *mut u32,
+) -> NTSTATUS;
+
+/// 按哈希从已加载的ntdll中解析NtQueryInformationProcess，导入表中没有该函数
+///
+/// # 返回值
+///
+/// - `Some(function)`: 函数指针
+/// - `None`: ntdll中没有找到该导出
+pub fn nt_query_information_process() -> Option<NtQueryInformationProcessFn> {
+    let address = resolve!("ntdll.dll", "NtQueryInformationProcess")?;
+    Some(unsafe { resolve::function(address) })
+}
+
+/// 获取NtQueryInformationProcess，被hook时(例如ScyllaHide)返回干净ntdll副本中的函数
+pub fn nt_query_information_process_unhooked() -> Option<NtQueryInformationProcessFn> {
+    const FUNCTION: u32 = resolve::hash("NtQueryInformationProcess");
+    unsafe { clean_ntdll::resolve_if_hooked::<NtQueryInformationProcessFn>(FUNCTION) }
+        .or_else(nt_query_information_process)
+}
+
+/// 调用NtQueryInformationProcess，开启返回地址伪造时经由系统模块中的gadget调用
+///
+/// # Safety
+///
+/// `buffer`必须指向至少`length`字节的可写内存，`ret_length`必须可写
+pub unsafe fn query_information_process(
+    query: NtQueryInformationProcessFn,
+    hprocess: HANDLE,
+    class: PROCESSINFOCLASS,
+    buffer: *mut c_void,
+    length: u32,
+    ret_length: *mut u32,
+) -> NTSTATUS {
+    #[cfg(target_arch = "x86_64")]
+    if crate::spoof::is_enabled() {
+        let args = [
+            hprocess.0 as usize,
+            class.0 as usize,
+            buffer as usize,
+            length as usize,
+            ret_length as usize,
+        ];
+        return NTSTATUS(crate::spoof::call(query as usize, &args) as i32);
+    }
+    query(hprocess, class, buffer, length, ret_length)
+}
+
 /// 检查当前进程是否被远程调试
 ///
 /// 通过调用CheckRemoteDebuggerPresentAPI来判断是否有调试端口
@@ -35,10 +92,54 @@ use windows::{
 pub fn check_remote_debugger_present() -> Result<bool> {
     let hprocess: HANDLE = unsafe { GetCurrentProcess() };
     let mut debug_port: BOOL = Default::default();
-    unsafe { CheckRemoteDebuggerPresent(hprocess, &mut debug_port) }?;
+    unsafe { CheckRemoteDebuggerPresent(hprocess, &mut debug_port) }.ok()?;
     Ok(debug_port.as_bool())
 }
 
+/// 获取指定进程的父进程ID
+///
+/// 通过NtQueryInformationProcess查询ProcessBasicInformation，
+/// 读取其中的InheritedFromUniqueProcessId
+///
+/// # 参数
+///
+/// - `hprocess`: 进程句柄
+///
+/// # 返回值
+///
+/// - `Err`: NtQueryInformationProcess调用失败
+/// - `Ok(pid)`: 父进程ID
+pub fn get_parent_process_id(hprocess: HANDLE) -> Result<u32> {
+    let mut basic_information = PROCESS_BASIC_INFORMATION::default();
+    let mut ret_length: u32 = Default::default();
+    let query = nt_query_information_process()
+        .ok_or_else(|| Error::msg(obf!("NtQueryInformationProcess not found").decrypt()))?;
+    let status: NTSTATUS = unsafe {
+        query_information_process(
+            query,
+            hprocess,
+            ProcessBasicInformation,
+            addr_of_mut!(basic_information).cast(),
+            u32::try_from(size_of_val(&basic_information)).expect("u32::try_from failed!"),
+            &mut ret_length,
+        )
+    };
+
+    if status != STATUS_SUCCESS {
+        warn!("NtQueryInformationProcess failed; error code: {:?}", status);
+        return Err(Error::msg(
+            obf!("NtQueryInformationProcess failed").decrypt(),
+        ));
+    }
+
+    debug!(
+        "parent process id ==> {}",
+        basic_information.InheritedFromUniqueProcessId
+    );
+
+    Ok(basic_information.InheritedFromUniqueProcessId as u32)
+}
+
 /// nt_query下的查询调试信息方法类型，
 /// 不同类型表示查询进程是否被调试的不同特征
 /// 
@@ -63,9 +164,12 @@ pub struct NtQueryDebug {}
 impl BeingDebug for NtQueryDebug {
     fn is_being_debug(&self) -> bool {
         let hprocess: HANDLE = unsafe { GetCurrentProcess() };
-        Self::check_debug_flags(hprocess)
-            && Self::check_debug_object(hprocess)
-            && Self::check_debug_port(hprocess)
+        let [flags, object, port] = shuffle!(
+            Self::check_debug_flags(hprocess),
+            Self::check_debug_object(hprocess),
+            Self::check_debug_port(hprocess)
+        );
+        flags && object && port
     }
 }
 
@@ -98,13 +202,28 @@ impl NtQueryDebug {
         debug!("process handle ==> {:?}; query type ==> {:?}", hprocess, query_type);
         let mut ret_length: u32 = Default::default();
         let process_information_class = PROCESSINFOCLASS(query_type as i32);
+        if !capability::get().process_class_available(process_information_class) {
+            debug!(
+                "process information class not available ==> {}",
+                process_information_class.0
+            );
+            return false;
+        }
         let mut process_information: u64 = Default::default();
+        let process_information_length =
+            u32::try_from(size_of_val(&process_information)).expect("u32::try_from failed!");
+        // NtQueryInformationProcess被hook时(例如ScyllaHide)，改为通过干净的ntdll副本调用
+        let Some(query) = nt_query_information_process_unhooked() else {
+            warn!("NtQueryInformationProcess not found");
+            return false;
+        };
         let status: NTSTATUS = unsafe {
-            NtQueryInformationProcess(
+            query_information_process(
+                query,
                 hprocess,
                 process_information_class,
                 addr_of_mut!(process_information).cast(),
-                u32::try_from(size_of_val(&process_information)).expect("u32::try_from failed!"),
+                process_information_length,
                 &mut ret_length,
             )
         };
@@ -0,0 +1,175 @@
+use crate::random::{mix, GAMMA};
+use std::{
+    fmt,
+    ops::Deref,
+    ptr,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+/// 构建脚本为本次构建生成的密钥
+pub const BUILD_KEY: u64 = parse_key(env!("ANTI_DEBUG_OBF_KEY"));
+
+/// 解析十进制的密钥，编译期求值
+const fn parse_key(value: &str) -> u64 {
+    let bytes = value.as_bytes();
+    let mut key: u64 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        key = key.wrapping_mul(10).wrapping_add((bytes[i] - b'0') as u64);
+        i += 1;
+    }
+    key
+}
+
+/// 源文件路径的FNV-1a哈希，编译期求值
+const fn file_hash(file: &str) -> u64 {
+    let bytes = file.as_bytes();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u64).wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+/// 由构建密钥与字面量所在的位置(文件、行、列)派生密钥，同一次构建中每个字面量的密钥都不同
+///
+/// 只用行列时，不同文件中相同位置的字面量会共用密钥，因此混入文件路径的哈希
+pub const fn derive_key(file: &str, line: u32, column: u32) -> u64 {
+    mix(BUILD_KEY ^ file_hash(file) ^ ((line as u64) << 32 | column as u64))
+}
+
+/// 第`index`个字节的密钥流
+const fn keystream(key: u64, index: usize) -> u8 {
+    let block = mix(key.wrapping_add(((index / 8) as u64).wrapping_mul(GAMMA)));
+    (block >> ((index % 8) * 8)) as u8
+}
+
+/// 加密字符串，结果末尾带一个加密的`\0`，编译期求值
+///
+/// # 参数
+///
+/// - `plain`: 明文
+/// - `key`: 密钥
+pub const fn encrypt<const N: usize>(plain: &str, key: u64) -> [u8; N] {
+    let bytes = plain.as_bytes();
+    let mut cipher = [0u8; N];
+    let mut i = 0;
+    while i < N {
+        let byte = if i < bytes.len() { bytes[i] } else { 0 };
+        cipher[i] = byte ^ keystream(key, i);
+        i += 1;
+    }
+    cipher
+}
+
+/// 编译期加密的字符串，二进制中只有密文，使用时调用`decrypt`
+///
+/// 由`obf!`宏创建，可以放在`const`数组中
+#[derive(Clone, Copy)]
+pub struct ObfStr {
+    cipher: &'static [u8],
+    key: u64,
+}
+
+impl ObfStr {
+    /// 由`obf!`宏调用
+    #[doc(hidden)]
+    pub const fn new(cipher: &'static [u8], key: u64) -> Self {
+        Self { cipher, key }
+    }
+
+    /// 解密到堆上的缓冲区，缓冲区drop时清零
+    ///
+    /// 逐字节volatile读取密文，编译器无法把解密过程常量折叠回明文
+    pub fn decrypt(&self) -> Decrypted {
+        let bytes = self
+            .cipher
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| unsafe { ptr::read_volatile(byte) } ^ keystream(self.key, i))
+            .collect();
+        Decrypted { bytes }
+    }
+}
+
+impl fmt::Debug for ObfStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObfStr({} bytes)", self.cipher.len() - 1)
+    }
+}
+
+/// 解密后的字符串，末尾带`\0`，可以直接作为C字符串传给系统API
+pub struct Decrypted {
+    bytes: Vec<u8>,
+}
+
+impl Decrypted {
+    /// 不含末尾`\0`的字符串
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.bytes.len() - 1]).unwrap_or_default()
+    }
+
+    /// 以`\0`结尾的字符串指针，例如`PCSTR(name.as_ptr())`
+    pub fn as_ptr(&self) -> *const u8 {
+        self.bytes.as_ptr()
+    }
+
+    /// 以`\0`结尾的UTF-16字符串，例如`PCWSTR(name.to_wide().as_ptr())`
+    pub fn to_wide(&self) -> Vec<u16> {
+        self.as_str().encode_utf16().chain(Some(0)).collect()
+    }
+}
+
+impl Deref for Decrypted {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Decrypted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Decrypted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Drop for Decrypted {
+    fn drop(&mut self) {
+        for byte in self.bytes.iter_mut() {
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// 编译期加密字符串字面量
+///
+/// 参数可以是任意`&'static str`常量表达式(包括`include_str!`)，明文只参与编译期求值，不会写入二进制。
+/// 密钥由构建脚本每次构建随机生成，并按字面量所在位置派生，相同的字符串在不同位置、不同构建中密文都不同
+///
+/// # 示例
+///
+/// ```ignore
+/// let name = obf!("NtQueryInformationProcess").decrypt();
+/// let address = unsafe { GetProcAddress(ntdll, PCSTR(name.as_ptr())) };
+///
+/// const DEVICES: [ObfStr; 1] = [obf!("\\\\.\\VBoxGuest")];
+/// ```
+#[macro_export]
+macro_rules! obf {
+    ($value:expr) => {{
+        const PLAIN: &str = $value;
+        const KEY: u64 = $crate::obfstr::derive_key(file!(), line!(), column!());
+        const CIPHER: [u8; PLAIN.len() + 1] = $crate::obfstr::encrypt(PLAIN, KEY);
+        $crate::obfstr::ObfStr::new(&CIPHER, KEY)
+    }};
+}
@@ -0,0 +1,100 @@
+use crate::{obfstr::BUILD_KEY, random::mix};
+use std::{
+    hint::black_box,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// 不透明谓词的输入，通过volatile读取，编译器无法推断其值
+static SEED: u64 = BUILD_KEY;
+
+/// 无用代码的输出，volatile写入，编译器无法删除
+static SINK: AtomicU64 = AtomicU64::new(0);
+
+/// 运行时才能确定的值：构建密钥与当前栈地址
+#[inline(always)]
+fn entropy(key: u64) -> u64 {
+    let local = 0u8;
+    let seed = unsafe { ptr::read_volatile(&SEED) };
+    seed ^ key ^ (&local as *const u8 as u64)
+}
+
+/// 恒为真的不透明谓词
+///
+/// 按`key`在几种数论恒等式中选择一种，`key`为常量时分支在编译期确定，
+/// 不同构建、不同调用位置生成的判断代码不同；中间结果经过`black_box`，编译器无法化简
+///
+/// # 参数
+///
+/// - `key`: 编译期常量，一般由`obfstr::derive_key`派生
+#[inline(always)]
+pub fn always(key: u64) -> bool {
+    let x = entropy(key);
+    match key % 4 {
+        // 相邻两数之积为偶数
+        0 => black_box(x.wrapping_mul(x.wrapping_add(1))) & 1 == 0,
+        // 平方数模4只能是0或1
+        1 => black_box(x.wrapping_mul(x)) & 3 != 2,
+        // 奇数的平方模8余1
+        2 => black_box((x | 1).wrapping_mul(x | 1)) & 7 == 1,
+        // 连续三个整数之积能被6整除
+        _ => {
+            let y = x & 0xffff;
+            black_box(y * (y + 1) * (y + 2)).is_multiple_of(6)
+        }
+    }
+}
+
+/// 恒为假的不透明谓词，见`always`
+#[inline(always)]
+pub fn never(key: u64) -> bool {
+    !always(key)
+}
+
+/// 永远不会执行的无用代码，看起来与正常的计算没有区别
+///
+/// # 参数
+///
+/// - `key`: 编译期常量，决定运算的轮数与移位量
+#[inline(always)]
+pub fn junk(key: u64) {
+    let mut state = entropy(key);
+    for _ in 0..key % 5 + 2 {
+        state = mix(state).rotate_left((key % 63) as u32 + 1);
+    }
+    unsafe { ptr::write_volatile(SINK.as_ptr(), state) };
+    SINK.fetch_xor(key, Ordering::Relaxed);
+}
+
+/// 用不透明谓词与死分支包裹检测逻辑，增加分析判定过程的工作量
+///
+/// 展开后在真实逻辑前插入一个永远不会进入的分支，真实逻辑本身再放在一个恒为真的谓词之后，
+/// 另一个分支是夹杂无用代码的副本。谓词的形式与无用代码都由构建密钥和调用位置决定，每次构建都不同
+///
+/// # 注意
+///
+/// 包裹的代码会被复制一份放在死分支中，代码体积翻倍，只适合包裹较短的判定逻辑
+///
+/// # 示例
+///
+/// ```ignore
+/// let detected = obfuscate! {
+///     let flag = WinPeb::fast_nt_global_flag();
+///     flag || WinPeb::fast_being_debugged()
+/// };
+/// ```
+#[macro_export]
+macro_rules! obfuscate {
+    ($($body:tt)*) => {{
+        const KEY: u64 = $crate::obfstr::derive_key(file!(), line!(), column!());
+        if $crate::opaque::never(KEY) {
+            $crate::opaque::junk(KEY);
+        }
+        if $crate::opaque::always(KEY.rotate_left(17)) {
+            $($body)*
+        } else {
+            $crate::opaque::junk(KEY.rotate_left(31));
+            $($body)*
+        }
+    }};
+}
@@ -0,0 +1,452 @@
+use crate::logging::{debug, warn};
+use anyhow::{Error, Result};
+use std::{fs, mem::size_of, path::Path, ptr};
+use windows::Win32::{
+    Foundation::HMODULE,
+    System::{
+        Diagnostics::Debug::{IMAGE_DATA_DIRECTORY, IMAGE_SCN_MEM_EXECUTE, IMAGE_SECTION_HEADER},
+        SystemServices::{
+            IMAGE_BASE_RELOCATION, IMAGE_DOS_HEADER, IMAGE_DOS_SIGNATURE, IMAGE_EXPORT_DIRECTORY,
+            IMAGE_IMPORT_DESCRIPTOR, IMAGE_NT_SIGNATURE,
+        },
+    },
+};
+
+#[cfg(target_pointer_width = "32")]
+pub type ImageNtHeaders = windows::Win32::System::Diagnostics::Debug::IMAGE_NT_HEADERS32;
+#[cfg(target_pointer_width = "64")]
+pub type ImageNtHeaders = windows::Win32::System::Diagnostics::Debug::IMAGE_NT_HEADERS64;
+
+/// 数据目录索引
+pub const IMAGE_DIRECTORY_ENTRY_EXPORT: usize = 0;
+pub const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+pub const IMAGE_DIRECTORY_ENTRY_BASERELOC: usize = 5;
+pub const IMAGE_DIRECTORY_ENTRY_IAT: usize = 12;
+
+/// 重定位项类型
+const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+/// 导出函数信息
+///
+/// - `name`: 导出名称，按序号导出时为None
+/// - `ordinal`: 导出序号
+/// - `rva`: 导出函数RVA
+/// - `forwarded`: 是否为转发导出(RVA指向导出目录内的转发字符串)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    pub name: Option<String>,
+    pub ordinal: u32,
+    pub rva: u32,
+    pub forwarded: bool,
+}
+
+/// 导入函数信息
+///
+/// - `module`: 导入的DLL名称
+/// - `name`: 导入名称，按序号导入时为None
+/// - `ordinal`: 按序号导入时的序号
+/// - `iat_rva`: 对应IAT表项的RVA，加载后保存解析出的函数地址
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub module: String,
+    pub name: Option<String>,
+    pub ordinal: Option<u32>,
+    pub iat_rva: u32,
+}
+
+/// 映像布局(按节对齐展开)的PE视图
+///
+/// 可以是已经加载到进程中的模块，也可以是通过`map_from_disk`展开的磁盘文件
+#[derive(Debug, Clone, Copy)]
+pub struct PeImage<'a> {
+    data: &'a [u8],
+}
+
+impl PeImage<'static> {
+    /// 从已加载模块的基址创建PE视图
+    ///
+    /// # 参数
+    ///
+    /// - `hmodule`: 模块句柄(即模块基址)
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 模块句柄为空或者不是有效的PE映像
+    /// - `Ok(image)`: PE视图，大小为SizeOfImage
+    pub fn from_module(hmodule: HMODULE) -> Result<Self> {
+        if hmodule.is_invalid() {
+            return Err(Error::msg("module handle is null"));
+        }
+
+        // 先只读取头部，获取SizeOfImage后再扩展到整个映像
+        let base = hmodule.0 as *const u8;
+        let headers = PeImage {
+            data: unsafe { std::slice::from_raw_parts(base, 0x1000) },
+        };
+        let size = headers.nt_headers()?.OptionalHeader.SizeOfImage as usize;
+
+        Ok(Self {
+            data: unsafe { std::slice::from_raw_parts(base, size) },
+        })
+    }
+}
+
+impl<'a> PeImage<'a> {
+    /// 从映像布局的缓冲区创建PE视图
+    pub fn from_buffer(data: &'a [u8]) -> Result<Self> {
+        let image = Self { data };
+        image.nt_headers()?;
+        Ok(image)
+    }
+
+    /// 映像基址
+    pub fn base(&self) -> usize {
+        self.data.as_ptr() as usize
+    }
+
+    /// 映像大小
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// 读取指定RVA处的结构体，越界时返回None
+    pub fn read<T: Copy>(&self, rva: usize) -> Option<T> {
+        let end = rva.checked_add(size_of::<T>())?;
+        if end > self.data.len() {
+            return None;
+        }
+
+        Some(unsafe { ptr::read_unaligned(self.data.as_ptr().add(rva) as *const T) })
+    }
+
+    /// 读取指定RVA处的字节，越界时返回None
+    pub fn bytes(&self, rva: usize, len: usize) -> Option<&'a [u8]> {
+        self.data.get(rva..rva.checked_add(len)?)
+    }
+
+    /// 读取指定RVA处以0结尾的ASCII字符串
+    pub fn read_cstr(&self, rva: usize) -> Option<String> {
+        let rest = self.data.get(rva..)?;
+        let len = rest.iter().position(|&c| c == 0)?;
+        Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+
+    /// 读取并校验NT头
+    pub fn nt_headers(&self) -> Result<ImageNtHeaders> {
+        let dos: IMAGE_DOS_HEADER = self
+            .read(0)
+            .ok_or_else(|| Error::msg("PE image is too small"))?;
+        if dos.e_magic != IMAGE_DOS_SIGNATURE {
+            return Err(Error::msg("invalid DOS signature"));
+        }
+
+        let nt: ImageNtHeaders = self
+            .read(dos.e_lfanew as usize)
+            .ok_or_else(|| Error::msg("NT headers out of range"))?;
+        if nt.Signature != IMAGE_NT_SIGNATURE {
+            return Err(Error::msg("invalid NT signature"));
+        }
+
+        Ok(nt)
+    }
+
+    /// 读取指定索引的数据目录
+    pub fn data_directory(&self, index: usize) -> Option<IMAGE_DATA_DIRECTORY> {
+        let nt = self.nt_headers().ok()?;
+        let directory = *nt.OptionalHeader.DataDirectory.get(index)?;
+        match directory.VirtualAddress {
+            0 => None,
+            _ => Some(directory),
+        }
+    }
+
+    /// 所有节表项
+    pub fn sections(&self) -> Vec<IMAGE_SECTION_HEADER> {
+        let Some(dos) = self.read::<IMAGE_DOS_HEADER>(0) else {
+            return Vec::new();
+        };
+        let Ok(nt) = self.nt_headers() else {
+            return Vec::new();
+        };
+
+        let first = dos.e_lfanew as usize
+            + size_of::<u32>()
+            + size_of_val(&nt.FileHeader)
+            + nt.FileHeader.SizeOfOptionalHeader as usize;
+
+        (0..nt.FileHeader.NumberOfSections as usize)
+            .filter_map(|i| self.read(first + i * size_of::<IMAGE_SECTION_HEADER>()))
+            .collect()
+    }
+
+    /// 判断RVA是否位于可执行节中
+    pub fn is_executable_rva(&self, rva: u32) -> bool {
+        self.sections().iter().any(|section| {
+            let size = unsafe { section.Misc.VirtualSize }.max(section.SizeOfRawData);
+            section.Characteristics.0 & IMAGE_SCN_MEM_EXECUTE.0 != 0
+                && rva >= section.VirtualAddress
+                && rva < section.VirtualAddress + size
+        })
+    }
+
+    /// 解析导出表
+    ///
+    /// # 返回值
+    ///
+    /// 所有导出函数，没有导出表时返回空列表
+    pub fn exports(&self) -> Vec<Export> {
+        let Some(directory) = self.data_directory(IMAGE_DIRECTORY_ENTRY_EXPORT) else {
+            return Vec::new();
+        };
+        let Some(export) = self.read::<IMAGE_EXPORT_DIRECTORY>(directory.VirtualAddress as usize)
+        else {
+            return Vec::new();
+        };
+
+        let range = directory.VirtualAddress..directory.VirtualAddress + directory.Size;
+        let mut names: Vec<Option<String>> = vec![None; export.NumberOfFunctions as usize];
+        for i in 0..export.NumberOfNames as usize {
+            let name_rva: Option<u32> = self.read(export.AddressOfNames as usize + i * 4);
+            let index: Option<u16> = self.read(export.AddressOfNameOrdinals as usize + i * 2);
+            if let (Some(name_rva), Some(index)) = (name_rva, index) {
+                if let Some(slot) = names.get_mut(index as usize) {
+                    *slot = self.read_cstr(name_rva as usize);
+                }
+            }
+        }
+
+        names
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, name)| {
+                let rva: u32 = self.read(export.AddressOfFunctions as usize + index * 4)?;
+                (rva != 0).then(|| Export {
+                    name,
+                    ordinal: export.Base + index as u32,
+                    rva,
+                    forwarded: range.contains(&rva),
+                })
+            })
+            .collect()
+    }
+
+    /// 解析基址重定位表
+    ///
+    /// # 返回值
+    ///
+    /// 所有需要重定位的位置`(rva, width)`，`width`为4(HIGHLOW)或8(DIR64)字节，
+    /// 超出映像范围与不支持的重定位项会被忽略
+    pub fn relocations(&self) -> Vec<(usize, usize)> {
+        let Some(directory) = self.data_directory(IMAGE_DIRECTORY_ENTRY_BASERELOC) else {
+            return Vec::new();
+        };
+
+        let mut relocations: Vec<(usize, usize)> = Vec::new();
+        let mut block_rva = directory.VirtualAddress as usize;
+        let end = block_rva + directory.Size as usize;
+        while block_rva + size_of::<IMAGE_BASE_RELOCATION>() <= end {
+            let Some(block) = self.read::<IMAGE_BASE_RELOCATION>(block_rva) else {
+                warn!("relocation block out of range");
+                break;
+            };
+            if block.SizeOfBlock < size_of::<IMAGE_BASE_RELOCATION>() as u32 {
+                break;
+            }
+
+            let count = (block.SizeOfBlock as usize - size_of::<IMAGE_BASE_RELOCATION>()) / 2;
+            for i in 0..count {
+                let entry_rva = block_rva + size_of::<IMAGE_BASE_RELOCATION>() + i * 2;
+                let Some(entry) = self.read::<u16>(entry_rva) else {
+                    break;
+                };
+                let target = block.VirtualAddress as usize + (entry & 0x0fff) as usize;
+
+                match entry >> 12 {
+                    IMAGE_REL_BASED_ABSOLUTE => {}
+                    IMAGE_REL_BASED_HIGHLOW if target + 4 <= self.data.len() => {
+                        relocations.push((target, 4))
+                    }
+                    IMAGE_REL_BASED_DIR64 if target + 8 <= self.data.len() => {
+                        relocations.push((target, 8))
+                    }
+                    kind => warn!("unsupported relocation type {} at {:#x}", kind, target),
+                }
+            }
+
+            block_rva += block.SizeOfBlock as usize;
+        }
+
+        relocations
+    }
+
+    /// 解析导入表
+    ///
+    /// 优先遍历OriginalFirstThunk(INT)获取导入名称，
+    /// 没有INT时回退到FirstThunk，此时只能用于尚未被加载器解析的映像
+    ///
+    /// # 返回值
+    ///
+    /// 所有导入函数，没有导入表时返回空列表
+    pub fn imports(&self) -> Vec<Import> {
+        let Some(directory) = self.data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT) else {
+            return Vec::new();
+        };
+
+        let ordinal_flag: usize = 1 << (usize::BITS - 1);
+        let mut imports: Vec<Import> = Vec::new();
+        let mut descriptor_rva = directory.VirtualAddress as usize;
+        while let Some(descriptor) = self.read::<IMAGE_IMPORT_DESCRIPTOR>(descriptor_rva) {
+            if descriptor.Name == 0 || descriptor.FirstThunk == 0 {
+                break;
+            }
+            descriptor_rva += size_of::<IMAGE_IMPORT_DESCRIPTOR>();
+
+            let Some(module) = self.read_cstr(descriptor.Name as usize) else {
+                continue;
+            };
+            let lookup_rva = match unsafe { descriptor.Anonymous.OriginalFirstThunk } {
+                0 => descriptor.FirstThunk,
+                rva => rva,
+            } as usize;
+
+            for index in 0.. {
+                let offset = index * size_of::<usize>();
+                let Some(thunk) = self.read::<usize>(lookup_rva + offset) else {
+                    break;
+                };
+                if thunk == 0 {
+                    break;
+                }
+
+                let (name, ordinal) = match thunk & ordinal_flag {
+                    0 => (
+                        self.read_cstr((thunk & !ordinal_flag) + size_of::<u16>()),
+                        None,
+                    ),
+                    _ => (None, Some((thunk & 0xffff) as u32)),
+                };
+                imports.push(Import {
+                    module: module.clone(),
+                    name,
+                    ordinal,
+                    iat_rva: descriptor.FirstThunk + offset as u32,
+                });
+            }
+        }
+
+        imports
+    }
+}
+
+/// 从磁盘读取PE文件，按节展开为映像布局，并以`load_base`为基址应用重定位
+///
+/// 展开后的缓冲区与加载到进程中的模块布局一致，可以按照RVA直接对比
+///
+/// # 参数
+///
+/// - `path`: PE文件路径
+/// - `load_base`: 重定位目标基址，通常为已加载模块的基址
+///
+/// # 返回值
+///
+/// - `Err`: 读取文件失败或者PE格式错误
+/// - `Ok(buffer)`: 映像布局的缓冲区
+pub fn map_from_disk<P: AsRef<Path>>(path: P, load_base: usize) -> Result<Vec<u8>> {
+    let file = fs::read(path.as_ref())?;
+    let raw = PeImage { data: &file };
+    let nt = raw.nt_headers()?;
+
+    let size_of_image = nt.OptionalHeader.SizeOfImage as usize;
+    let size_of_headers = (nt.OptionalHeader.SizeOfHeaders as usize).min(file.len());
+    let mut image: Vec<u8> = vec![0; size_of_image];
+    image[..size_of_headers].copy_from_slice(&file[..size_of_headers]);
+
+    for section in raw.sections() {
+        let raw_start = section.PointerToRawData as usize;
+        let raw_size = section.SizeOfRawData as usize;
+        let virtual_start = section.VirtualAddress as usize;
+        let len = raw_size
+            .min(file.len().saturating_sub(raw_start))
+            .min(size_of_image.saturating_sub(virtual_start));
+        if len == 0 {
+            continue;
+        }
+
+        image[virtual_start..virtual_start + len]
+            .copy_from_slice(&file[raw_start..raw_start + len]);
+    }
+
+    relocate(&mut image, load_base)?;
+
+    debug!(
+        "map {:?} from disk ==> size: {:#x}; base: {:#x}",
+        path.as_ref(),
+        size_of_image,
+        load_base
+    );
+
+    Ok(image)
+}
+
+/// 将映像布局的缓冲区重定位到`new_base`
+///
+/// 根据头部记录的ImageBase计算差值并应用基址重定位，
+/// 完成后将头部的ImageBase更新为`new_base`，因此可以对同一缓冲区重复调用
+///
+/// # 参数
+///
+/// - `image`: 映像布局的缓冲区
+/// - `new_base`: 新的基址
+///
+/// # 返回值
+///
+/// - `Err`: PE格式错误或者重定位表损坏
+/// - `Ok(())`: 重定位成功
+pub fn relocate(image: &mut [u8], new_base: usize) -> Result<()> {
+    let image_view = PeImage { data: image };
+    let e_lfanew = image_view
+        .read::<IMAGE_DOS_HEADER>(0)
+        .ok_or_else(|| Error::msg("PE image is too small"))?
+        .e_lfanew as usize;
+    let mut nt = image_view.nt_headers()?;
+
+    let delta = new_base.wrapping_sub(nt.OptionalHeader.ImageBase as usize);
+    if delta == 0 {
+        return Ok(());
+    }
+
+    apply_relocations(image, delta)?;
+
+    nt.OptionalHeader.ImageBase = new_base as _;
+    unsafe { ptr::write_unaligned(image.as_mut_ptr().add(e_lfanew) as *mut ImageNtHeaders, nt) };
+
+    Ok(())
+}
+
+/// 对映像布局的缓冲区应用基址重定位
+fn apply_relocations(image: &mut [u8], delta: usize) -> Result<()> {
+    let relocations = PeImage { data: image }.relocations();
+    if relocations.is_empty() {
+        warn!("PE image has no relocation directory");
+        return Ok(());
+    }
+
+    for (target, width) in relocations {
+        match width {
+            4 => {
+                let value = u32::from_le_bytes(image[target..target + 4].try_into()?);
+                let value = value.wrapping_add(delta as u32);
+                image[target..target + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            _ => {
+                let value = u64::from_le_bytes(image[target..target + 8].try_into()?);
+                let value = value.wrapping_add(delta as u64);
+                image[target..target + 8].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    Ok(())
+}
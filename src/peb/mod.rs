@@ -1,11 +1,8 @@
-use crate::util::BeingDebug;
+use crate::logging::{debug, error};
+use crate::{capability, imports::IsDebuggerPresent, util::BeingDebug};
 use anyhow::{Error, Result};
-use log::{debug, error};
 use std::{arch::asm, ptr};
-use windows::Win32::{
-    Foundation::HANDLE,
-    System::{Diagnostics::Debug::IsDebuggerPresent, Memory::GetProcessHeap},
-};
+use windows::Win32::{Foundation::HANDLE, System::Memory::GetProcessHeap};
 
 #[repr(C)]
 #[derive(Debug, Clone)]
@@ -124,31 +121,99 @@ impl AsRef<WinProcessHeap> for WinPeb {
 }
 
 impl WinPeb {
-    /// 获取进程的PEB地址
+    /// 获取进程的PEB地址，不记录日志
     ///
     /// 64位程序获取gs:[0x60]的值，32位程序则获取fs:[0x30]的值
-    ///
-    /// # 返回值
-    ///
-    /// 返回一个u64类型的值，这个值就是PEB块的首地址
-    pub fn get_peb_address() -> u64 {
+    #[inline(always)]
+    pub fn fast_peb_address() -> u64 {
         let mut peb_address: u64;
 
         #[cfg(target_pointer_width = "64")]
         unsafe {
-            asm!("mov {}, gs:[0x60]", out(reg) peb_address);
+            asm!(
+                "mov {}, gs:[0x60]",
+                out(reg) peb_address,
+                options(nostack, readonly, preserves_flags)
+            );
         };
 
         #[cfg(target_pointer_width = "32")]
         unsafe {
-            asm!("mov {}, fs:[0x30]", out(reg) peb_address);
+            asm!(
+                "mov {}, fs:[0x30]",
+                out(reg) peb_address,
+                options(nostack, readonly, preserves_flags)
+            );
         };
 
+        peb_address
+    }
+
+    /// 获取进程的PEB地址
+    ///
+    /// 64位程序获取gs:[0x60]的值，32位程序则获取fs:[0x30]的值
+    ///
+    /// # 返回值
+    ///
+    /// 返回一个u64类型的值，这个值就是PEB块的首地址
+    pub fn get_peb_address() -> u64 {
+        let peb_address: u64 = Self::fast_peb_address();
+
         debug!("peb address ==> {:#x}", peb_address);
 
         return peb_address;
     }
 
+    /// 快速检测PEB.BeingDebugged，不分配内存、不记录日志，可以在游戏主循环中每帧调用
+    ///
+    /// # 返回值
+    ///
+    /// - `false`: 进程未被调试
+    /// - `true`：进程正在被调试
+    #[inline(always)]
+    pub fn fast_being_debugged() -> bool {
+        let peb_ref: &WinPeb = unsafe { &*(Self::fast_peb_address() as *const WinPeb) };
+        unsafe { ptr::read_volatile(&peb_ref.being_debugged) != 0 }
+    }
+
+    /// 快速检测PEB.NtGlobalFlag是否为0x70，不分配内存、不记录日志
+    ///
+    /// # 返回值
+    ///
+    /// - `false`: 进程未被调试
+    /// - `true`：进程正在被调试
+    #[inline(always)]
+    pub fn fast_nt_global_flag() -> bool {
+        let offset = capability::get().peb.nt_global_flag;
+        let peb_address = Self::fast_peb_address() as *const u8;
+        unsafe { ptr::read_volatile(peb_address.add(offset) as *const u32) == 0x70 }
+    }
+
+    /// 快速检测PEB.ProcessHeap中的flags与force_flags，不分配内存、不记录日志
+    ///
+    /// 字段偏移取自`capability`能力表，可以先调用`capability::init`避免第一次检测时的探测开销
+    ///
+    /// # 返回值
+    ///
+    /// - `false`: 进程未被调试，或者PEB.ProcessHeap为null
+    /// - `true`：进程正在被调试
+    #[inline(always)]
+    pub fn fast_heap_flags() -> bool {
+        let capabilities = capability::get();
+        let peb_address = Self::fast_peb_address() as *const u8;
+        let heap = unsafe {
+            ptr::read_volatile(peb_address.add(capabilities.peb.process_heap) as *const *const u8)
+        };
+        if heap.is_null() {
+            return false;
+        }
+
+        let flags = unsafe { ptr::read_volatile(heap.add(capabilities.heap.flags) as *const u32) };
+        let force_flags =
+            unsafe { ptr::read_volatile(heap.add(capabilities.heap.force_flags) as *const u32) };
+        flags != 2 || force_flags != 0
+    }
+
     /// 检测进程是否被调试
     ///
     /// 通过调用IsDebuggerPresent Win API来判断是否被调试
@@ -194,7 +259,7 @@ impl WinPeb {
 
         debug!("PEB.BeingDebugged ==> {:#x}", peb_ref.being_debugged);
 
-        peb_ref.is_being_debug()
+        Self::fast_being_debugged() || Self::fast_nt_global_flag()
     }
 
     /// 获取peb中指定属性的值来判断进程是否被调试
@@ -221,7 +286,7 @@ impl WinPeb {
 
         debug!("PEB.NtGlobalFlag ==> {:#x}", peb_ref.nt_global_flag);
 
-        peb_ref.is_being_debug()
+        Self::fast_being_debugged() || Self::fast_nt_global_flag()
     }
 
     /// 获取peb.processheap中的flags和force_flags的值来判断进程是否被调试
@@ -260,7 +325,7 @@ impl WinPeb {
             process_ref.flags, process_ref.force_flags
         );
 
-        Ok(process_ref.is_being_debug())
+        Ok(Self::fast_heap_flags())
     }
 
     /// 通过检测ProcessHeap中属性值来判断进程是否被调试
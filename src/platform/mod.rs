@@ -0,0 +1,217 @@
+use crate::engine::{Engine, Report};
+#[cfg(target_os = "linux")]
+use crate::linux::ProcDebug;
+use crate::logging::debug;
+#[cfg(target_os = "macos")]
+use crate::macos::SysctlDebug;
+#[cfg(windows)]
+use crate::peb::WinPeb;
+use crate::util;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::util::BeingDebug;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// 监控命中时的回调
+pub type DetectFn = Box<dyn Fn(&Report) + Send + 'static>;
+
+/// 平台无关的检测接口，各平台的实现只在对应的目标上编译
+///
+/// 应用代码通过`platform::current()`获取当前平台的实现，不需要自己区分平台
+///
+/// # 示例
+///
+/// ```ignore
+/// let detector = anti_debug::platform::current();
+/// if detector.is_debugged() {
+///     std::process::exit(1);
+/// }
+/// let _watch = detector.watch(Duration::from_secs(5), Box::new(|report| {
+///     println!("{:?}", report.detections().collect::<Vec<_>>());
+/// }));
+/// ```
+pub trait Detector: Send + Sync {
+    /// 平台名称
+    fn platform(&self) -> &'static str;
+
+    /// 快速检测，只执行开销很小的检查，适合在热路径中频繁调用
+    fn is_debugged(&self) -> bool;
+
+    /// 执行当前平台的所有内置检测技术
+    fn report(&self) -> Report {
+        Engine::default().run()
+    }
+
+    /// 启动后台线程，每隔`interval`执行一次`report`，发现调试器或者代码篡改时调用`on_detect`
+    ///
+    /// # 返回值
+    ///
+    /// - 监控句柄，drop时停止监控
+    fn watch(&self, interval: Duration, on_detect: DetectFn) -> Watch;
+}
+
+/// Windows：读取PEB中的BeingDebugged、NtGlobalFlag与进程堆标志
+#[cfg(windows)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowsDetector;
+
+#[cfg(windows)]
+impl Detector for WindowsDetector {
+    fn platform(&self) -> &'static str {
+        "windows"
+    }
+
+    fn is_debugged(&self) -> bool {
+        crate::obfuscate! {
+            let [being_debugged, nt_global_flag, heap_flags] = crate::shuffle!(
+                WinPeb::fast_being_debugged(),
+                WinPeb::fast_nt_global_flag(),
+                WinPeb::fast_heap_flags()
+            );
+            being_debugged || nt_global_flag || heap_flags
+        }
+    }
+
+    fn watch(&self, interval: Duration, on_detect: DetectFn) -> Watch {
+        Watch::spawn(*self, interval, on_detect)
+    }
+}
+
+/// Linux：读取/proc/self/status中的TracerPid与进程状态
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinuxDetector;
+
+#[cfg(target_os = "linux")]
+impl Detector for LinuxDetector {
+    fn platform(&self) -> &'static str {
+        "linux"
+    }
+
+    fn is_debugged(&self) -> bool {
+        crate::obfuscate! { ProcDebug {}.is_being_debug() }
+    }
+
+    fn watch(&self, interval: Duration, on_detect: DetectFn) -> Watch {
+        Watch::spawn(*self, interval, on_detect)
+    }
+}
+
+/// macOS：通过sysctl读取`P_TRACED`
+#[cfg(target_os = "macos")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MacosDetector;
+
+#[cfg(target_os = "macos")]
+impl Detector for MacosDetector {
+    fn platform(&self) -> &'static str {
+        "macos"
+    }
+
+    fn is_debugged(&self) -> bool {
+        crate::obfuscate! { SysctlDebug {}.is_being_debug() }
+    }
+
+    fn watch(&self, interval: Duration, on_detect: DetectFn) -> Watch {
+        Watch::spawn(*self, interval, on_detect)
+    }
+}
+
+/// 不支持的平台：没有快速检测，`report`中也没有内置技术
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnsupportedDetector;
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+impl Detector for UnsupportedDetector {
+    fn platform(&self) -> &'static str {
+        "unsupported"
+    }
+
+    fn is_debugged(&self) -> bool {
+        false
+    }
+
+    fn watch(&self, interval: Duration, on_detect: DetectFn) -> Watch {
+        Watch::spawn(*self, interval, on_detect)
+    }
+}
+
+/// 获取当前平台的检测实现
+pub fn current() -> Box<dyn Detector> {
+    #[cfg(windows)]
+    return Box::new(WindowsDetector);
+    #[cfg(target_os = "linux")]
+    return Box::new(LinuxDetector);
+    #[cfg(target_os = "macos")]
+    return Box::new(MacosDetector);
+    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+    return Box::new(UnsupportedDetector);
+}
+
+/// 运行中的后台监控，drop时停止
+pub struct Watch {
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+    /// 在后台线程中定期执行`detector.report()`
+    fn spawn<D>(detector: D, interval: Duration, on_detect: DetectFn) -> Self
+    where
+        D: Detector + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let worker = util::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                let report = detector.report();
+                debug!(
+                    "{} watch ==> debugger: {}; tampering: {}",
+                    detector.platform(),
+                    report.debugger_score(),
+                    report.tampering_score()
+                );
+                if report.is_debugged() || report.is_tampered() {
+                    debug!("{} watch detected", detector.platform());
+                    on_detect(&report);
+                }
+
+                let deadline = Instant::now() + interval;
+                while !stopped.load(Ordering::SeqCst) {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        });
+
+        Self {
+            stop,
+            worker: Some(worker),
+        }
+    }
+
+    /// 停止监控并等待当前检测完成
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,29 @@
+/// splitmix64每一步给状态加上的常数(2^64除以黄金分割比)
+pub const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// splitmix64的输出函数，编译期也可以求值
+pub const fn mix(value: u64) -> u64 {
+    let mut z = value;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// splitmix64伪随机数：推进状态并返回下一个值
+///
+/// 只用于打乱顺序、随机间隔这类不需要密码学强度的场合
+///
+/// # 参数
+///
+/// - `state`: 随机数状态，通常以`rdtsc`等变化的值作为初始值
+///
+/// # 示例
+///
+/// ```ignore
+/// let mut state = rdtsc();
+/// let index = next(&mut state) % 16;
+/// ```
+pub fn next(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(GAMMA);
+    mix(*state)
+}
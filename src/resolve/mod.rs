@@ -0,0 +1,314 @@
+use crate::logging::{debug, warn};
+use crate::{
+    ldr::{peb_ldr_data, LdrDataTableEntry},
+    obfstr::BUILD_KEY,
+    pe::PeImage,
+    peb::WinPeb,
+};
+use std::{
+    mem::{size_of, transmute_copy},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use windows::Win32::{Foundation::HMODULE, System::Kernel::LIST_ENTRY};
+
+/// 转发导出最多跟随的层数，防止转发成环
+const MAX_FORWARD_DEPTH: usize = 4;
+
+/// PEB中ApiSetMap的偏移
+#[cfg(target_pointer_width = "32")]
+const PEB_API_SET_MAP: usize = 0x38;
+#[cfg(target_pointer_width = "64")]
+const PEB_API_SET_MAP: usize = 0x68;
+
+/// 支持的ApiSet架构版本(Windows 10及以上)
+const API_SET_SCHEMA_VERSION: u32 = 6;
+
+/// ApiSet架构的命名空间头
+#[repr(C)]
+struct ApiSetNamespace {
+    version: u32,
+    size: u32,
+    flags: u32,
+    count: u32,
+    entry_offset: u32,
+    hash_offset: u32,
+    hash_factor: u32,
+}
+
+/// ApiSet架构中的一个虚拟模块
+#[repr(C)]
+struct ApiSetNamespaceEntry {
+    flags: u32,
+    name_offset: u32,
+    name_length: u32,
+    hashed_length: u32,
+    value_offset: u32,
+    value_count: u32,
+}
+
+/// 虚拟模块对应的宿主模块，`name_length`为0的是默认宿主
+#[repr(C)]
+struct ApiSetValueEntry {
+    flags: u32,
+    name_offset: u32,
+    name_length: u32,
+    value_offset: u32,
+    value_length: u32,
+}
+
+/// 哈希初始值，混入构建密钥，不同构建中同一名称的哈希不同
+const SEED: u32 = 0x811c_9dc5 ^ (BUILD_KEY >> 32) as u32;
+
+/// FNV-1a的一步，ASCII大写字母按小写处理
+const fn step(hash: u32, unit: u16) -> u32 {
+    let unit = if unit >= b'A' as u16 && unit <= b'Z' as u16 {
+        unit + 32
+    } else {
+        unit
+    };
+    (hash ^ unit as u32).wrapping_mul(0x0100_0193)
+}
+
+/// 计算模块名或者导出函数名的哈希，不区分大小写，编译期求值
+///
+/// # 参数
+///
+/// - `name`: 模块名(例如`ntdll.dll`)或者导出函数名
+pub const fn hash(name: &str) -> u32 {
+    let bytes = name.as_bytes();
+    let mut hash = SEED;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = step(hash, bytes[i] as u16);
+        i += 1;
+    }
+    hash
+}
+
+/// 计算UTF-16模块名的哈希，与`hash`的结果一致
+fn hash_wide(name: &[u16]) -> u32 {
+    name.iter().fold(SEED, |hash, &unit| step(hash, unit))
+}
+
+/// 遍历PEB Ldr中的模块，查找文件名哈希匹配的模块，不调用`GetModuleHandleW`
+///
+/// # 参数
+///
+/// - `module`: 模块文件名的哈希，例如`hash("ntdll.dll")`
+///
+/// # 返回值
+///
+/// - `Some(hmodule)`: 模块基址
+/// - `None`: 模块没有加载
+///
+/// # 注意
+///
+/// 遍历时没有持有加载器锁，其他线程同时加载或者卸载模块时可能读到不完整的链表，
+/// 只应该查找进程生命周期内不会卸载的系统模块
+pub fn find_module(module: u32) -> Option<HMODULE> {
+    unsafe {
+        let head = &mut (*peb_ldr_data()).in_load_order_module_list as *mut LIST_ENTRY;
+        let mut current = (*head).Flink;
+        while !current.is_null() && current != head {
+            let entry = current as *const LdrDataTableEntry;
+            let name = &(*entry).base_dll_name;
+            if !name.Buffer.is_null() {
+                let name = std::slice::from_raw_parts(name.Buffer.0, name.Length as usize / 2);
+                if hash_wide(name) == module {
+                    return Some(HMODULE((*entry).dll_base));
+                }
+            }
+            current = (*current).Flink;
+        }
+    }
+
+    None
+}
+
+/// 解析模块导出表，查找名称哈希匹配的导出函数，不调用`GetProcAddress`
+///
+/// 转发导出(例如kernel32的`HeapAlloc`转发到`NTDLL.RtlAllocateHeap`)会继续在目标模块中查找，
+/// 转发到`api-ms-*`虚拟模块时通过ApiSet映射找到宿主模块
+///
+/// # 参数
+///
+/// - `hmodule`: 模块基址
+/// - `function`: 导出函数名的哈希
+///
+/// # 返回值
+///
+/// - `Some(address)`: 函数地址
+/// - `None`: 没有该导出函数，或者转发的目标模块没有加载、按序号转发
+pub fn find_export(hmodule: HMODULE, function: u32) -> Option<usize> {
+    find_export_depth(hmodule, function, 0)
+}
+
+fn find_export_depth(hmodule: HMODULE, function: u32, depth: usize) -> Option<usize> {
+    let image = PeImage::from_module(hmodule).ok()?;
+    let export = image.exports().into_iter().find(|export| {
+        export
+            .name
+            .as_deref()
+            .is_some_and(|name| hash(name) == function)
+    })?;
+    if !export.forwarded {
+        return Some(image.base() + export.rva as usize);
+    }
+
+    // 转发字符串格式为`模块名.函数名`，模块名不带扩展名
+    let forwarder = image.read_cstr(export.rva as usize)?;
+    let (module, name) = forwarder.split_once('.')?;
+    if depth >= MAX_FORWARD_DEPTH || name.starts_with('#') {
+        warn!("unsupported export forwarder ==> {}", forwarder);
+        return None;
+    }
+
+    debug!("export forwarded ==> {}", forwarder);
+    let module = format!("{}.dll", module);
+    let module = match is_api_set(&module) {
+        true => api_set_host(&module)?,
+        false => module,
+    };
+    let target = find_module(hash(&module))?;
+    find_export_depth(target, hash(name), depth + 1)
+}
+
+/// 模块名是否是ApiSet虚拟模块，例如`api-ms-win-core-synch-l1-2-0.dll`
+fn is_api_set(module: &str) -> bool {
+    let module = module.to_ascii_lowercase();
+    module.starts_with("api-") || module.starts_with("ext-")
+}
+
+/// 在PEB的ApiSet映射中查找虚拟模块的默认宿主模块
+///
+/// Windows 10及以上kernel32等模块的转发导出指向`api-ms-*`虚拟模块，
+/// 虚拟模块本身不会出现在Ldr链表中，需要先换成真正导出函数的宿主模块
+///
+/// # 参数
+///
+/// - `module`: 虚拟模块名，可以带或者不带`.dll`
+///
+/// # 返回值
+///
+/// - `Some(host)`: 宿主模块名，例如`kernelbase.dll`
+/// - `None`: 没有该虚拟模块、没有宿主模块，或者ApiSet架构版本不支持
+///
+/// # 示例
+///
+/// ```ignore
+/// let host = api_set_host("api-ms-win-core-synch-l1-2-0.dll").unwrap();
+/// assert!(host.eq_ignore_ascii_case("kernelbase.dll"));
+/// ```
+pub fn api_set_host(module: &str) -> Option<String> {
+    let module = module.to_ascii_lowercase();
+    let module = module.strip_suffix(".dll").unwrap_or(&module);
+    // 匹配时忽略最后一个`-`之后的次版本号，与加载器的规则一致
+    let (requested, _) = module.rsplit_once('-')?;
+
+    unsafe {
+        let peb = WinPeb::get_peb_address() as usize;
+        let map = *((peb + PEB_API_SET_MAP) as *const usize);
+        if map == 0 {
+            return None;
+        }
+        let namespace = &*(map as *const ApiSetNamespace);
+        if namespace.version != API_SET_SCHEMA_VERSION {
+            warn!("unsupported api set schema ==> {}", namespace.version);
+            return None;
+        }
+
+        let wide = |offset: u32, length: u32| {
+            String::from_utf16_lossy(std::slice::from_raw_parts(
+                (map + offset as usize) as *const u16,
+                length as usize / 2,
+            ))
+        };
+        let entries = std::slice::from_raw_parts(
+            (map + namespace.entry_offset as usize) as *const ApiSetNamespaceEntry,
+            namespace.count as usize,
+        );
+        let entry = entries.iter().find(|entry| {
+            wide(entry.name_offset, entry.hashed_length).eq_ignore_ascii_case(requested)
+        })?;
+
+        let values = std::slice::from_raw_parts(
+            (map + entry.value_offset as usize) as *const ApiSetValueEntry,
+            entry.value_count as usize,
+        );
+        let value = values
+            .iter()
+            .find(|value| value.name_length == 0)
+            .or(values.first())?;
+        let host = wide(value.value_offset, value.value_length);
+        debug!("api set {} ==> {}", module, host);
+
+        (!host.is_empty()).then_some(host)
+    }
+}
+
+/// 按哈希查找已加载模块中的导出函数
+///
+/// # 参数
+///
+/// - `module`: 模块文件名的哈希
+/// - `function`: 导出函数名的哈希
+pub fn resolve(module: u32, function: u32) -> Option<usize> {
+    let address = find_export(find_module(module)?, function);
+    debug!(
+        "resolve {:#x}!{:#x} ==> {:#x}",
+        module,
+        function,
+        address.unwrap_or_default()
+    );
+    address
+}
+
+/// 带缓存的`resolve`，由`resolve!`宏调用
+#[doc(hidden)]
+pub fn cached(cache: &AtomicUsize, module: u32, function: u32) -> Option<usize> {
+    match cache.load(Ordering::Acquire) {
+        0 => {
+            let address = resolve(module, function)?;
+            cache.store(address, Ordering::Release);
+            Some(address)
+        }
+        address => Some(address),
+    }
+}
+
+/// 将函数地址转换为函数指针
+///
+/// # Safety
+///
+/// `T`必须是与导出函数签名一致的`unsafe extern "system" fn`类型
+pub unsafe fn function<T: Copy>(address: usize) -> T {
+    assert_eq!(size_of::<T>(), size_of::<usize>());
+    transmute_copy(&address)
+}
+
+/// 按名称哈希解析导出函数，替代`GetModuleHandleW` + `GetProcAddress`
+///
+/// 名称只在编译期参与哈希计算，二进制中既没有函数名字符串也没有对应的导入表项；
+/// 解析结果缓存在每个调用点自己的静态变量中，只有第一次调用会遍历Ldr与导出表
+///
+/// # 返回值
+///
+/// - `Some(address)`: 函数地址
+/// - `None`: 模块没有加载或者没有该导出函数
+///
+/// # 示例
+///
+/// ```ignore
+/// let address = resolve!("ntdll.dll", "NtQueryInformationProcess").unwrap();
+/// let nt_query_information_process: NtQueryInformationProcessFn =
+///     unsafe { resolve::function(address) };
+/// ```
+#[macro_export]
+macro_rules! resolve {
+    ($module:expr, $function:expr) => {{
+        static CACHE: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
+        const MODULE: u32 = $crate::resolve::hash($module);
+        const FUNCTION: u32 = $crate::resolve::hash($function);
+        $crate::resolve::cached(&CACHE, MODULE, FUNCTION)
+    }};
+}
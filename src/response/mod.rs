@@ -0,0 +1,943 @@
+use crate::logging::{debug, warn};
+use crate::{
+    clean_ntdll,
+    engine::Severity,
+    environment::find_blacklisted_processes,
+    imports::{AddVectoredExceptionHandler, NtClose, OpenProcess, OutputDebugStringW},
+    nt_query::{
+        get_parent_process_id, nt_query_information_process_unhooked, query_information_process,
+    },
+    obf, random, resolve, thread_monitor,
+    timing::rdtsc,
+    util::{get_process_name, get_process_path, to_wide},
+    watchdog::{find_foreign_handles, TRUSTED_HANDLE_HOLDERS},
+};
+use anyhow::{Error, Result};
+use std::{
+    arch::asm,
+    env,
+    mem::{size_of, size_of_val},
+    path::{Path, PathBuf},
+    ptr::addr_of_mut,
+    sync::{
+        atomic::{compiler_fence, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+#[cfg(target_arch = "x86")]
+use windows::Win32::System::Memory::{VirtualAlloc, MEM_RESERVE, PAGE_NOACCESS};
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Wdk::System::Threading::ProcessDebugObjectHandle,
+    Win32::{
+        Foundation::{CloseHandle, BOOLEAN, HANDLE, NTSTATUS, STATUS_PORT_NOT_SET},
+        Storage::FileSystem::{
+            CreateFileW, FileDispositionInfo, FileRenameInfo, SetFileInformationByHandle, DELETE,
+            FILE_ATTRIBUTE_NORMAL, FILE_DISPOSITION_INFO, FILE_INFO_BY_HANDLE_CLASS,
+            FILE_RENAME_INFO, FILE_SHARE_DELETE, FILE_SHARE_READ, OPEN_EXISTING,
+        },
+        System::{
+            Diagnostics::Debug::{
+                RaiseException, RemoveVectoredExceptionHandler, EXCEPTION_CONTINUE_EXECUTION,
+                EXCEPTION_CONTINUE_SEARCH, EXCEPTION_POINTERS,
+            },
+            StationsAndDesktops::{
+                CloseDesktop, CreateDesktopW, OpenInputDesktop, SwitchDesktop,
+                DESKTOP_CONTROL_FLAGS, DESKTOP_CREATEWINDOW, DESKTOP_SWITCHDESKTOP, HDESK,
+            },
+            Threading::{
+                CreateProcessW, GetCurrentProcess, GetCurrentProcessId,
+                GetCurrentThreadStackLimits, TerminateProcess, CREATE_NO_WINDOW,
+                PROCESS_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SUSPEND_RESUME,
+                PROCESS_TERMINATE, STARTUPINFOW,
+            },
+        },
+        UI::Input::KeyboardAndMouse::BlockInput,
+    },
+};
+
+/// NtRemoveProcessDebug的函数签名，windows crate中没有该函数，需要从ntdll导出中获取
+pub type NtRemoveProcessDebugFn = unsafe extern "system" fn(HANDLE, HANDLE) -> NTSTATUS;
+/// NtSuspendProcess的函数签名，windows crate中没有该函数，需要从ntdll导出中获取
+pub type NtSuspendProcessFn = unsafe extern "system" fn(HANDLE) -> NTSTATUS;
+
+/// 获取当前进程的调试对象句柄
+///
+/// 通过NtQueryInformationProcess查询ProcessDebugObjectHandle，
+/// 被调试时系统会在当前进程中新建一个指向调试对象的句柄
+///
+/// # 返回值
+///
+/// - `Err`: NtQueryInformationProcess调用失败
+/// - `Ok(None)`: 当前进程没有被调试
+/// - `Ok(Some(handle))`: 调试对象句柄，使用完毕后需要调用`NtClose`关闭
+pub fn query_debug_object() -> Result<Option<HANDLE>> {
+    let hprocess = unsafe { GetCurrentProcess() };
+    let mut debug_object = HANDLE::default();
+    let length = size_of_val(&debug_object) as u32;
+    let mut ret_length: u32 = 0;
+    let query = nt_query_information_process_unhooked()
+        .ok_or_else(|| Error::msg(obf!("NtQueryInformationProcess not found").decrypt()))?;
+    let status = unsafe {
+        query_information_process(
+            query,
+            hprocess,
+            ProcessDebugObjectHandle,
+            addr_of_mut!(debug_object).cast(),
+            length,
+            &mut ret_length,
+        )
+    };
+
+    if status == STATUS_PORT_NOT_SET {
+        return Ok(None);
+    }
+    if status.is_err() {
+        warn!("NtQueryInformationProcess failed; error code: {:?}", status);
+        return Err(Error::msg(
+            obf!("NtQueryInformationProcess failed").decrypt(),
+        ));
+    }
+
+    debug!("debug object ==> {:?}", debug_object);
+    Ok((!debug_object.is_invalid() && !debug_object.0.is_null()).then_some(debug_object))
+}
+
+/// 将调试器从当前进程上强制分离
+///
+/// 取得当前进程的调试对象后调用NtRemoveProcessDebug解除关联，再关闭调试对象句柄。
+/// 分离后调试器收不到任何调试事件，相比直接退出，进程可以继续正常运行
+///
+/// # 返回值
+///
+/// - `Err`: 查询调试对象失败或者NtRemoveProcessDebug调用失败
+/// - `Ok(true)`: 已分离调试器
+/// - `Ok(false)`: 当前进程没有被调试
+///
+/// # 注意
+///
+/// 调试器写入的软件断点(0xCC)不会被还原，分离后执行到断点会触发未处理异常；
+/// 硬件断点需要另外通过`HardwareBreakPoint::clean_hardware_breakpoint`清除
+///
+/// # 示例
+///
+/// ```ignore
+/// let report = Engine::default().run();
+/// if report.is_debugged() && !detach_debugger().unwrap_or(false) {
+///     std::process::exit(1);
+/// }
+/// ```
+pub fn detach_debugger() -> Result<bool> {
+    let Some(debug_object) = query_debug_object()? else {
+        return Ok(false);
+    };
+
+    const NT_REMOVE_PROCESS_DEBUG: u32 = resolve::hash("NtRemoveProcessDebug");
+    let nt_remove_process_debug = unsafe {
+        clean_ntdll::resolve_if_hooked::<NtRemoveProcessDebugFn>(NT_REMOVE_PROCESS_DEBUG)
+    }
+    .or_else(|| {
+        let address = resolve!("ntdll.dll", "NtRemoveProcessDebug")?;
+        Some(unsafe { resolve::function::<NtRemoveProcessDebugFn>(address) })
+    });
+    let status = nt_remove_process_debug
+        .map(|function| unsafe { function(GetCurrentProcess(), debug_object) });
+    let _ = unsafe { NtClose(debug_object) };
+
+    match status {
+        Some(status) if status.is_ok() => {
+            debug!("debugger detached");
+            Ok(true)
+        }
+        Some(status) => {
+            warn!("NtRemoveProcessDebug failed; error code: {:?}", status);
+            Err(Error::msg(obf!("NtRemoveProcessDebug failed").decrypt()))
+        }
+        None => Err(Error::msg(obf!("NtRemoveProcessDebug not found").decrypt())),
+    }
+}
+
+/// 敏感代码(密钥派生、解壳等)执行期间阻止交互式调试的手段
+///
+/// - `BlockInput`: 屏蔽键盘与鼠标输入，需要管理员权限，Ctrl+Alt+Del可以解除
+/// - `HiddenDesktop`: 切换到新建的空白桌面，调试器窗口留在原桌面上无法操作
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputCountermeasure {
+    BlockInput,
+    HiddenDesktop,
+}
+
+/// 生效中的反交互措施，释放或超时后自动恢复
+///
+/// 措施在独立的工作线程中施加与恢复(BlockInput只能由施加它的线程解除)，
+/// 即使持有者线程被调试器挂起，超时后输入与桌面也会恢复
+pub struct InputGuard {
+    release: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl InputGuard {
+    /// 施加反交互措施
+    ///
+    /// # 参数
+    ///
+    /// - `countermeasure`: 使用的措施
+    /// - `timeout`: 最长持续时间，超时后自动恢复
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 施加失败，例如没有管理员权限时BlockInput失败
+    /// - `Ok(guard)`: 措施已生效，guard释放时恢复
+    pub fn engage(countermeasure: InputCountermeasure, timeout: Duration) -> Result<Self> {
+        let (release, released) = mpsc::channel::<()>();
+        let (ready, engaged) = mpsc::channel::<Result<()>>();
+        let worker = thread_monitor::spawn_hidden(move || match countermeasure {
+            InputCountermeasure::BlockInput => {
+                let result = unsafe { BlockInput(true) };
+                let blocked = result.is_ok();
+                let _ = ready.send(result.map_err(Into::into));
+                if blocked {
+                    let _ = released.recv_timeout(timeout);
+                    let _ = unsafe { BlockInput(false) };
+                }
+            }
+            InputCountermeasure::HiddenDesktop => match switch_to_hidden_desktop() {
+                Ok((original, hidden)) => {
+                    let _ = ready.send(Ok(()));
+                    let _ = released.recv_timeout(timeout);
+                    let _ = unsafe { SwitchDesktop(original) };
+                    let _ = unsafe { CloseDesktop(hidden) };
+                    let _ = unsafe { CloseDesktop(original) };
+                }
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                }
+            },
+        });
+
+        match engaged.recv() {
+            Ok(Ok(())) => {
+                debug!("input countermeasure engaged ==> {:?}", countermeasure);
+                Ok(Self {
+                    release: Some(release),
+                    worker: Some(worker),
+                })
+            }
+            Ok(Err(e)) => {
+                let _ = worker.join();
+                warn!("engage {:?} failed; error: {:?}", countermeasure, e);
+                Err(e)
+            }
+            Err(_) => Err(Error::msg("input countermeasure worker exited")),
+        }
+    }
+
+    /// 立即恢复输入与桌面
+    pub fn release(self) {}
+}
+
+impl Drop for InputGuard {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            let _ = release.send(());
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// 新建一个桌面并切换过去，返回原输入桌面与新桌面
+fn switch_to_hidden_desktop() -> Result<(HDESK, HDESK)> {
+    let original =
+        unsafe { OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_SWITCHDESKTOP) }?;
+
+    let name = to_wide(&format!("anti_debug_{:x}", rdtsc()));
+    let hidden = match unsafe {
+        CreateDesktopW(
+            PCWSTR(name.as_ptr()),
+            PCWSTR::null(),
+            None,
+            DESKTOP_CONTROL_FLAGS(0),
+            DESKTOP_SWITCHDESKTOP.0 | DESKTOP_CREATEWINDOW.0,
+            None,
+        )
+    } {
+        Ok(hidden) => hidden,
+        Err(e) => {
+            let _ = unsafe { CloseDesktop(original) };
+            return Err(e.into());
+        }
+    };
+
+    if let Err(e) = unsafe { SwitchDesktop(hidden) } {
+        let _ = unsafe { CloseDesktop(hidden) };
+        let _ = unsafe { CloseDesktop(original) };
+        return Err(e.into());
+    }
+
+    Ok((original, hidden))
+}
+
+/// 在反交互措施的保护下执行敏感代码，执行完毕或者超时后自动恢复
+///
+/// 措施施加失败时只记录日志，敏感代码照常执行
+///
+/// # 参数
+///
+/// - `countermeasure`: 使用的措施
+/// - `timeout`: 最长持续时间
+/// - `f`: 敏感代码
+///
+/// # 示例
+///
+/// ```ignore
+/// let key = run_guarded(InputCountermeasure::HiddenDesktop, Duration::from_secs(2), || {
+///     derive_key(&password)
+/// });
+/// ```
+pub fn run_guarded<R, F: FnOnce() -> R>(
+    countermeasure: InputCountermeasure,
+    timeout: Duration,
+    f: F,
+) -> R {
+    let guard = InputGuard::engage(countermeasure, timeout).ok();
+    let result = f();
+    drop(guard);
+    result
+}
+
+/// 对攻击进程采取的动作
+///
+/// - `Suspend`: 挂起进程的所有线程，调试器界面随之冻结
+/// - `Terminate`: 结束进程
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessAction {
+    Suspend,
+    Terminate,
+}
+
+/// 攻击进程的处置策略
+///
+/// - `action`: 采取的动作，为`None`时只记录日志
+/// - `exempt`: 不处置的进程名(不区分大小写)，避免误伤系统进程与终端
+/// - `exempt_pids`: 不处置的进程ID，例如启动当前进程的外部启动器
+/// - `min_confidence`: 识别依据的置信度低于该值时只记录日志，为0时不过滤
+#[derive(Debug, Clone)]
+pub struct ProcessPolicy {
+    pub action: Option<ProcessAction>,
+    pub exempt: Vec<String>,
+    pub exempt_pids: Vec<u32>,
+    pub min_confidence: u32,
+}
+
+impl Default for ProcessPolicy {
+    fn default() -> Self {
+        let mut exempt: Vec<String> = TRUSTED_HANDLE_HOLDERS
+            .iter()
+            .map(|name| name.decrypt().to_string())
+            .collect();
+        exempt.extend(
+            [
+                obf!("explorer.exe"),
+                obf!("conhost.exe"),
+                obf!("cmd.exe"),
+                obf!("powershell.exe"),
+                obf!("pwsh.exe"),
+                obf!("WindowsTerminal.exe"),
+                obf!("OpenConsole.exe"),
+            ]
+            .iter()
+            .map(|name| name.decrypt().to_string()),
+        );
+
+        Self {
+            action: None,
+            exempt,
+            exempt_pids: Vec::new(),
+            min_confidence: 0,
+        }
+    }
+}
+
+impl ProcessPolicy {
+    /// 进程名是否在豁免列表中
+    pub fn is_exempt(&self, name: Option<&str>) -> bool {
+        name.is_some_and(|name| {
+            self.exempt
+                .iter()
+                .any(|exempt| exempt.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+/// 一个攻击进程的处置结果
+///
+/// - `pid`: 进程ID
+/// - `name`: 进程名
+/// - `reason`: 识别出该进程的依据
+/// - `action`: 实际采取的动作，策略为只记录日志时为`None`
+/// - `error`: 动作失败的原因
+#[derive(Debug, Clone)]
+pub struct ProcessOutcome {
+    pub pid: u32,
+    pub name: Option<String>,
+    pub reason: String,
+    pub action: Option<ProcessAction>,
+    pub error: Option<String>,
+}
+
+/// 通过NtSuspendProcess挂起指定进程
+///
+/// # 参数
+///
+/// - `pid`: 进程ID
+///
+/// # 返回值
+///
+/// - `Err`: 没有`PROCESS_SUSPEND_RESUME`权限或者挂起失败
+/// - `Ok(())`: 挂起成功
+pub fn suspend_process(pid: u32) -> Result<()> {
+    let Some(address) = resolve!("ntdll.dll", "NtSuspendProcess") else {
+        return Err(Error::msg(obf!("NtSuspendProcess not found").decrypt()));
+    };
+    let nt_suspend_process: NtSuspendProcessFn = unsafe { resolve::function(address) };
+
+    let hprocess = unsafe { OpenProcess(PROCESS_SUSPEND_RESUME, false, pid) }?;
+    let status = unsafe { nt_suspend_process(hprocess) };
+    let _ = unsafe { CloseHandle(hprocess) };
+    if status.is_err() {
+        warn!("NtSuspendProcess failed; error code: {:?}", status);
+        return Err(Error::msg(obf!("NtSuspendProcess failed").decrypt()));
+    }
+
+    Ok(())
+}
+
+/// 结束指定进程
+///
+/// # 参数
+///
+/// - `pid`: 进程ID
+///
+/// # 返回值
+///
+/// - `Err`: 没有`PROCESS_TERMINATE`权限或者结束失败
+/// - `Ok(())`: 结束成功
+pub fn terminate_process(pid: u32) -> Result<()> {
+    let hprocess = unsafe { OpenProcess(PROCESS_TERMINATE, false, pid) }?;
+    let result = unsafe { TerminateProcess(hprocess, 1) };
+    let _ = unsafe { CloseHandle(hprocess) };
+    Ok(result?)
+}
+
+/// 查找正在攻击当前进程的进程：持有当前进程可疑句柄的进程
+///
+/// 只处置真正持有当前进程句柄的进程，运行中但没有句柄的黑名单进程只记录日志。
+/// 以下持有者不算攻击者：
+///
+/// - 父进程(启动器通常持有子进程的全部权限)，除非父进程本身命中黑名单(由调试器启动)
+/// - 与当前进程映像路径相同的进程，即本程序的启动器、自调试辅助进程与守护对
+/// - 策略中豁免的进程ID与进程名
+///
+/// # 参数
+///
+/// - `policy`: 处置策略
+///
+/// # 返回值
+///
+/// - `(pid, name, reason, confidence)`列表，同一进程只出现一次；命中黑名单的进程置信度为
+///   `Severity::Confirmed`，只持有句柄的进程为`Severity::Suspicious`
+pub fn find_attackers(policy: &ProcessPolicy) -> Vec<(u32, Option<String>, String, u32)> {
+    let current_pid = unsafe { GetCurrentProcessId() };
+    let blacklisted = find_blacklisted_processes().unwrap_or_else(|e| {
+        warn!("enumerate processes failed; error: {:?}", e);
+        Vec::new()
+    });
+    let parent_pid = get_parent_process_id(unsafe { GetCurrentProcess() }).ok();
+    let own_image = get_process_path(current_pid);
+
+    // 系统句柄表中需要真实句柄才能找到当前进程对应的内核对象
+    let holders =
+        match unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, current_pid) } {
+            Ok(hprocess) => {
+                let holders = find_foreign_handles(hprocess, &[current_pid]).unwrap_or_else(|e| {
+                    warn!("audit process handles failed; error: {:?}", e);
+                    Vec::new()
+                });
+                let _ = unsafe { CloseHandle(hprocess) };
+                holders
+            }
+            Err(e) => {
+                warn!("open current process failed; error: {:?}", e);
+                Vec::new()
+            }
+        };
+
+    let mut attackers: Vec<(u32, Option<String>, String, u32)> = Vec::new();
+    for holder in holders {
+        if policy.exempt_pids.contains(&holder.pid)
+            || attackers.iter().any(|(pid, _, _, _)| *pid == holder.pid)
+        {
+            continue;
+        }
+
+        let (reason, severity) = match blacklisted.iter().find(|process| process.pid == holder.pid)
+        {
+            Some(process) => (
+                format!("blacklisted process: {}", process.pattern),
+                Severity::Confirmed,
+            ),
+            None if Some(holder.pid) == parent_pid => continue,
+            None if is_same_image(&get_process_path(holder.pid), &own_image) => continue,
+            None => (
+                format!("process handle with access {:#x}", holder.access),
+                Severity::Suspicious,
+            ),
+        };
+        attackers.push((holder.pid, holder.name, reason, severity.base_confidence()));
+    }
+
+    for process in &blacklisted {
+        if !attackers.iter().any(|(pid, _, _, _)| *pid == process.pid) {
+            debug!(
+                "blacklisted process holds no handle ==> {} ({}); pattern: {}",
+                process.name, process.pid, process.pattern
+            );
+        }
+    }
+
+    attackers.retain(|(_, name, _, _)| !policy.is_exempt(name.as_deref()));
+    attackers
+}
+
+/// 两个映像路径是否相同(不区分大小写)
+fn is_same_image(a: &Option<PathBuf>, b: &Option<PathBuf>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a
+            .to_string_lossy()
+            .eq_ignore_ascii_case(&b.to_string_lossy()),
+        _ => false,
+    }
+}
+
+/// 按策略处置正在攻击当前进程的进程，并记录每个进程的处置结果
+///
+/// # 参数
+///
+/// - `policy`: 处置策略
+///
+/// # 返回值
+///
+/// - 每个攻击进程的处置结果
+///
+/// # 注意
+///
+/// 挂起或结束其他进程需要相应的权限，调试器以管理员身份运行时普通权限的进程无法处置，
+/// 失败原因记录在`ProcessOutcome::error`中
+///
+/// # 示例
+///
+/// ```ignore
+/// let policy = ProcessPolicy {
+///     action: Some(ProcessAction::Suspend),
+///     ..Default::default()
+/// };
+/// for outcome in respond_to_attackers(&policy) {
+///     println!("{:?}", outcome);
+/// }
+/// ```
+pub fn respond_to_attackers(policy: &ProcessPolicy) -> Vec<ProcessOutcome> {
+    find_attackers(policy)
+        .into_iter()
+        .map(|(pid, name, reason, confidence)| {
+            respond_to_process(policy, pid, name, reason, confidence)
+        })
+        .collect()
+}
+
+/// 按策略处置一个攻击进程，豁免检查由调用方负责
+///
+/// 置信度低于`ProcessPolicy::min_confidence`时只记录日志，不采取动作
+///
+/// # 参数
+///
+/// - `policy`: 处置策略
+/// - `pid`: 攻击进程ID
+/// - `name`: 攻击进程名，为`None`时重新查询
+/// - `reason`: 识别出该进程的依据
+/// - `confidence`: 识别依据的置信度，范围0~100(见`Severity::base_confidence`)
+pub fn respond_to_process(
+    policy: &ProcessPolicy,
+    pid: u32,
+    name: Option<String>,
+    reason: String,
+    confidence: u32,
+) -> ProcessOutcome {
+    let name = name.or_else(|| get_process_name(pid));
+    let action = match confidence < policy.min_confidence {
+        true => None,
+        false => policy.action,
+    };
+    let result = match action {
+        Some(ProcessAction::Suspend) => suspend_process(pid),
+        Some(ProcessAction::Terminate) => terminate_process(pid),
+        None => Ok(()),
+    };
+
+    let outcome = ProcessOutcome {
+        pid,
+        name,
+        reason,
+        action,
+        error: result.err().map(|e| e.to_string()),
+    };
+    match &outcome.error {
+        Some(error) => warn!(
+            "respond to attacker failed ==> {:?} ({}); action: {:?}; error: {}",
+            outcome.name, pid, outcome.action, error
+        ),
+        None => debug!(
+            "respond to attacker ==> {:?} ({}); reason: {}; action: {:?}",
+            outcome.name, pid, outcome.reason, outcome.action
+        ),
+    }
+    outcome
+}
+
+/// 骚扰调试器时抛出的异常码，由`harass_debugger`注册的VEH处理后继续执行
+pub const HARASS_EXCEPTION_CODE: u32 = 0xE0AD_DB61;
+
+/// 骚扰调试器的参数
+///
+/// - `rate`: 每秒发送的调试字符串数量
+/// - `duration`: 持续时间
+/// - `exceptions`: 是否同时抛出伪造的异常，调试器对每个第一次机会异常都会中断或记录
+#[derive(Debug, Clone)]
+pub struct HarassConfig {
+    pub rate: u32,
+    pub duration: Duration,
+    pub exceptions: bool,
+}
+
+impl Default for HarassConfig {
+    fn default() -> Self {
+        Self {
+            rate: 1000,
+            duration: Duration::from_secs(10),
+            exceptions: true,
+        }
+    }
+}
+
+/// 只处理`HARASS_EXCEPTION_CODE`，其余异常交给后续处理程序
+unsafe extern "system" fn harass_exception_handler(info: *mut EXCEPTION_POINTERS) -> i32 {
+    let record = (*info).ExceptionRecord;
+    if !record.is_null() && (*record).ExceptionCode.0 as u32 == HARASS_EXCEPTION_CODE {
+        EXCEPTION_CONTINUE_EXECUTION
+    } else {
+        EXCEPTION_CONTINUE_SEARCH
+    }
+}
+
+/// 用大量调试字符串与伪造异常淹没已附加的调试器，降低分析效率
+///
+/// 在独立线程中按`config.rate`发送随机内容的`OutputDebugString`，
+/// 开启`exceptions`时每条字符串后再抛出一个`HARASS_EXCEPTION_CODE`异常。
+/// 没有调试器时字符串被系统丢弃，异常由VEH处理，对进程没有影响
+///
+/// # 参数
+///
+/// - `config`: 发送频率、持续时间与是否抛出异常
+///
+/// # 返回值
+///
+/// - 骚扰线程的句柄，线程结束时返回发送的字符串数量
+///
+/// # 示例
+///
+/// ```ignore
+/// if Engine::default().run().is_debugged() {
+///     let _ = harass_debugger(&HarassConfig::default()).join();
+///     std::process::exit(1);
+/// }
+/// ```
+pub fn harass_debugger(config: &HarassConfig) -> JoinHandle<u64> {
+    let config = config.clone();
+    thread_monitor::spawn_registered(move || {
+        let handler = config
+            .exceptions
+            .then(|| unsafe { AddVectoredExceptionHandler(1, Some(harass_exception_handler)) })
+            .filter(|handler| !handler.is_null());
+
+        // 每10ms发送一批，避免逐条sleep时频率受计时器精度限制
+        let tick = Duration::from_millis(10);
+        let batch = (config.rate / 100).max(1);
+        let start = Instant::now();
+        let mut count: u64 = 0;
+        while start.elapsed() < config.duration {
+            for _ in 0..batch {
+                let seed = rdtsc();
+                let message = to_wide(&format!(
+                    "[{:08x}] {:016x} {:016x}\n",
+                    seed as u32,
+                    seed.rotate_left(17) ^ count,
+                    seed.wrapping_mul(random::GAMMA)
+                ));
+                unsafe { OutputDebugStringW(PCWSTR(message.as_ptr())) };
+                if handler.is_some() {
+                    unsafe { RaiseException(HARASS_EXCEPTION_CODE, 0, Some(&[seed as usize])) };
+                }
+                count += 1;
+            }
+            thread::sleep(tick);
+        }
+
+        if let Some(handler) = handler {
+            let _ = unsafe { RemoveVectoredExceptionHandler(handler) };
+        }
+        debug!("harass debugger finished ==> {} messages", count);
+        count
+    })
+}
+
+/// `self_delete`重命名默认数据流时使用的备用数据流名
+const SELF_DELETE_STREAM: &str = ":anti_debug";
+
+/// 删除当前程序的可执行文件
+///
+/// 优先将正在运行的映像的默认数据流重命名为备用数据流，再设置删除标志，句柄关闭后文件立即消失；
+/// 该方式失败时(例如Windows 10之前的系统)启动一个隐藏的`cmd.exe`，等待当前进程退出后删除文件
+///
+/// # 返回值
+///
+/// - `Err`: 两种方式都失败
+/// - `Ok(())`: 文件已删除，或者已启动删除文件的辅助进程
+///
+/// # 注意
+///
+/// 辅助进程只等待约两秒，调用后应尽快退出当前进程
+pub fn self_delete() -> Result<()> {
+    let exe = env::current_exe()?;
+    match delete_running_image(&exe) {
+        Ok(()) => {
+            debug!("self delete ==> {:?}", exe);
+            Ok(())
+        }
+        Err(e) => {
+            warn!("delete running image failed; error: {:?}", e);
+            spawn_delete_helper(&exe)
+        }
+    }
+}
+
+/// 打开文件并设置文件信息，完成后关闭句柄
+fn set_file_information<T>(
+    path: &[u16],
+    class: FILE_INFO_BY_HANDLE_CLASS,
+    information: *const T,
+    size: usize,
+) -> Result<()> {
+    let hfile = unsafe {
+        CreateFileW(
+            PCWSTR(path.as_ptr()),
+            DELETE.0,
+            FILE_SHARE_READ | FILE_SHARE_DELETE,
+            None,
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            HANDLE::default(),
+        )
+    }?;
+    let result =
+        unsafe { SetFileInformationByHandle(hfile, class, information.cast(), size as u32) };
+    let _ = unsafe { CloseHandle(hfile) };
+    Ok(result?)
+}
+
+/// 映像节引用的是文件对象而不是默认数据流，重命名后默认数据流可以被删除
+fn delete_running_image(exe: &Path) -> Result<()> {
+    let path = to_wide(&exe.to_string_lossy());
+
+    let stream: Vec<u16> = SELF_DELETE_STREAM.encode_utf16().collect();
+    let size = size_of::<FILE_RENAME_INFO>() + stream.len() * 2;
+    // 以u64分配保证FILE_RENAME_INFO的对齐
+    let mut buffer = vec![0u64; size.div_ceil(8)];
+    let rename_info = buffer.as_mut_ptr() as *mut FILE_RENAME_INFO;
+    unsafe {
+        (*rename_info).FileNameLength = (stream.len() * 2) as u32;
+        std::ptr::copy_nonoverlapping(
+            stream.as_ptr(),
+            addr_of_mut!((*rename_info).FileName).cast::<u16>(),
+            stream.len(),
+        );
+    }
+    set_file_information(&path, FileRenameInfo, rename_info, size)?;
+
+    let disposition = FILE_DISPOSITION_INFO {
+        DeleteFile: BOOLEAN(1),
+    };
+    set_file_information(
+        &path,
+        FileDispositionInfo,
+        &disposition,
+        size_of::<FILE_DISPOSITION_INFO>(),
+    )
+}
+
+/// 启动隐藏的cmd.exe，等待约两秒后删除文件
+fn spawn_delete_helper(exe: &Path) -> Result<()> {
+    let mut command_line = to_wide(&format!(
+        "cmd.exe /c ping -n 3 127.0.0.1 >nul & del /f /q \"{}\"",
+        exe.to_string_lossy()
+    ));
+    let startup_info = STARTUPINFOW {
+        cb: size_of::<STARTUPINFOW>() as u32,
+        ..Default::default()
+    };
+    let mut process_info = PROCESS_INFORMATION::default();
+    unsafe {
+        CreateProcessW(
+            PCWSTR::null(),
+            PWSTR(command_line.as_mut_ptr()),
+            None,
+            None,
+            false,
+            CREATE_NO_WINDOW,
+            None,
+            PCWSTR::null(),
+            &startup_info,
+            &mut process_info,
+        )
+    }?;
+
+    debug!("self delete helper ==> pid: {}", process_info.dwProcessId);
+    let _ = unsafe { CloseHandle(process_info.hThread) };
+    let _ = unsafe { CloseHandle(process_info.hProcess) };
+    Ok(())
+}
+
+/// 清零内存，不会被编译器优化掉
+///
+/// # 参数
+///
+/// - `buffer`: 需要清零的内存，例如密钥、解密后的数据
+pub fn wipe(buffer: &mut [u8]) {
+    for byte in buffer.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// 一次性载荷的安全退出：删除自身可执行文件，清零敏感内存后退出进程
+///
+/// # 参数
+///
+/// - `regions`: 需要清零的内存
+/// - `exit_code`: 进程退出码
+///
+/// # 示例
+///
+/// ```ignore
+/// if Engine::default().run().is_debugged() {
+///     secure_shutdown(&mut [&mut key, &mut payload], 0);
+/// }
+/// ```
+pub fn secure_shutdown(regions: &mut [&mut [u8]], exit_code: i32) -> ! {
+    if let Err(e) = self_delete() {
+        warn!("self delete failed; error: {:?}", e);
+    }
+    for region in regions.iter_mut() {
+        wipe(region);
+    }
+
+    std::process::exit(exit_code);
+}
+
+/// `crash`保留的不可访问区域大小，等于分配粒度，区域内低16位的任意偏移都不会越界
+#[cfg(target_arch = "x86")]
+const CRASH_REGION: usize = 0x10000;
+
+/// 以不可恢复的方式崩溃
+///
+/// 用随机值覆盖调用者的栈帧，将栈指针与帧指针改为随机的未映射地址后跳转到随机的未映射地址。
+/// 调试器或崩溃转储中看到的是无意义的指令地址与无法回溯的调用栈，
+/// 也不会像`ExitProcess`一样停在分析人员设置的断点上
+///
+/// - x64: 使用内核地址空间(高半部分)中的地址，用户态一定无法访问
+/// - x86: 使用刚刚保留的`PAGE_NOACCESS`区域中的地址，保留失败时使用从不映射的前64KB
+/// - 其他架构: 直接`abort`
+///
+/// # 注意
+///
+/// 不会执行任何析构函数与退出回调。栈指针无效时系统无法分发异常，
+/// 没有调试器时进程被直接终止
+///
+/// # 示例
+///
+/// ```ignore
+/// if Engine::default().run().is_debugged() {
+///     anti_debug::response::crash();
+/// }
+/// ```
+pub fn crash() -> ! {
+    // 每次崩溃的地址都不同
+    let mut seed = rdtsc();
+    let mut next = || random::next(&mut seed) as usize;
+
+    // 只覆盖到栈底为止，避免在清理栈的过程中就触发异常
+    let (mut low, mut high) = (0usize, 0usize);
+    unsafe { GetCurrentThreadStackLimits(&mut low, &mut high) };
+    let marker = 0u8;
+    let count =
+        (high.saturating_sub(&marker as *const u8 as usize) / size_of::<usize>()).min(0x200);
+    let fill = next();
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        // 内核地址空间，用户态访问一定会失败
+        let stack = 0xFFFF_8000_0000_0000 | (next() & 0x0000_7FFF_FFFF_FFF0);
+        let target = 0xFFFF_8000_0000_0000 | (next() & 0x0000_7FFF_FFFF_FFFF);
+        asm!(
+            "mov rdi, rsp",
+            "rep stosq",
+            "mov rsp, rdx",
+            "mov rbp, rdx",
+            "jmp r8",
+            in("rax") fill,
+            in("rcx") count,
+            in("rdx") stack,
+            in("r8") target,
+            options(noreturn),
+        );
+    }
+
+    #[cfg(target_arch = "x86")]
+    unsafe {
+        // 32位进程的用户态地址空间可能扩展到4GB，内核地址不可靠；
+        // 保留一段不可访问的区域，栈指针与跳转地址都落在这个64KB对齐的区域中
+        let base = VirtualAlloc(None, CRASH_REGION, MEM_RESERVE, PAGE_NOACCESS) as usize;
+        let stack = base | (next() & 0xFFF0);
+        asm!(
+            "mov edi, esp",
+            "rep stosd",
+            "mov esp, edx",
+            "mov ebp, edx",
+            "mov eax, edx",
+            "xor eax, 0x5a5a",
+            "jmp eax",
+            in("eax") fill,
+            in("ecx") count,
+            in("edx") stack,
+            options(noreturn),
+        );
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+    {
+        let _ = (count, fill);
+        std::process::abort();
+    }
+}
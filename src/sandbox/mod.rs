@@ -0,0 +1,400 @@
+use crate::logging::debug;
+use crate::{module::is_module_loaded, obf, obfstr::ObfStr, util::BeingDebug};
+use anyhow::Result;
+use std::{
+    env, fs,
+    mem::size_of,
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::POINT,
+        Storage::FileSystem::GetDiskFreeSpaceExW,
+        System::{
+            Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS},
+            SystemInformation::{
+                GetSystemInfo, GetTickCount64, GlobalMemoryStatusEx, MEMORYSTATUSEX, SYSTEM_INFO,
+            },
+        },
+        UI::WindowsAndMessaging::{
+            GetCursorPos, GetForegroundWindow, GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN,
+        },
+    },
+};
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// SYSTEM_POWER_STATUS.BatteryFlag中表示没有电池的值
+const BATTERY_FLAG_NO_BATTERY: u8 = 128;
+
+/// 硬件配置信息，用于区分一次性分析虚拟机与真实用户机器
+///
+/// - `cpu_cores`: 逻辑处理器数量
+/// - `memory_bytes`: 物理内存大小
+/// - `disk_bytes`: 系统盘容量
+/// - `screen_width`/`screen_height`: 主屏幕分辨率
+/// - `has_battery`: 是否存在电池
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HardwareProfile {
+    pub cpu_cores: u32,
+    pub memory_bytes: u64,
+    pub disk_bytes: u64,
+    pub screen_width: i32,
+    pub screen_height: i32,
+    pub has_battery: bool,
+}
+
+/// 单项沙箱检查结果
+///
+/// - `name`: 检查项名称
+/// - `weight`: 检查项权重
+/// - `suspicious`: 是否命中沙箱特征
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxCheck {
+    pub name: &'static str,
+    pub weight: u32,
+    pub suspicious: bool,
+}
+
+impl BeingDebug for HardwareProfile {
+    fn is_being_debug(&self) -> bool {
+        self.score() >= Self::THRESHOLD
+    }
+}
+
+impl HardwareProfile {
+    /// 总分达到该阈值则认为是分析环境
+    pub const THRESHOLD: u32 = 50;
+
+    /// 查询当前机器的硬件配置
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: GlobalMemoryStatusEx/GetDiskFreeSpaceExW调用失败
+    /// - `Ok(profile)`: 硬件配置信息
+    pub fn query() -> Result<Self> {
+        let mut system_info = SYSTEM_INFO::default();
+        unsafe { GetSystemInfo(&mut system_info) };
+
+        let mut memory_status = MEMORYSTATUSEX {
+            dwLength: size_of::<MEMORYSTATUSEX>() as u32,
+            ..Default::default()
+        };
+        unsafe { GlobalMemoryStatusEx(&mut memory_status) }?;
+
+        let mut disk_bytes: u64 = 0;
+        let root = obf!("C:\\").decrypt().to_wide();
+        unsafe { GetDiskFreeSpaceExW(PCWSTR(root.as_ptr()), None, Some(&mut disk_bytes), None) }?;
+
+        // 无法获取电源状态时按照存在电池处理，避免误报
+        let mut power_status = SYSTEM_POWER_STATUS::default();
+        let has_battery = match unsafe { GetSystemPowerStatus(&mut power_status) } {
+            Ok(()) => power_status.BatteryFlag != BATTERY_FLAG_NO_BATTERY,
+            Err(_) => true,
+        };
+
+        let profile = Self {
+            cpu_cores: system_info.dwNumberOfProcessors,
+            memory_bytes: memory_status.ullTotalPhys,
+            disk_bytes,
+            screen_width: unsafe { GetSystemMetrics(SM_CXSCREEN) },
+            screen_height: unsafe { GetSystemMetrics(SM_CYSCREEN) },
+            has_battery,
+        };
+
+        debug!("hardware profile ==> {:?}", profile);
+
+        Ok(profile)
+    }
+
+    /// 计算每一项硬件检查的结果
+    ///
+    /// - CPU核心数少于2: 权重30
+    /// - 物理内存少于4GB: 权重30
+    /// - 系统盘小于80GB: 权重25
+    /// - 分辨率低于1024x768: 权重15
+    /// - 没有电池: 权重5，台式机同样没有电池，所以权重较低
+    pub fn checks(&self) -> Vec<SandboxCheck> {
+        vec![
+            SandboxCheck {
+                name: "cpu_cores",
+                weight: 30,
+                suspicious: self.cpu_cores < 2,
+            },
+            SandboxCheck {
+                name: "memory",
+                weight: 30,
+                suspicious: self.memory_bytes < 4 * GB,
+            },
+            SandboxCheck {
+                name: "disk",
+                weight: 25,
+                suspicious: self.disk_bytes < 80 * GB,
+            },
+            SandboxCheck {
+                name: "screen",
+                weight: 15,
+                suspicious: self.screen_width < 1024 || self.screen_height < 768,
+            },
+            SandboxCheck {
+                name: "battery",
+                weight: 5,
+                suspicious: !self.has_battery,
+            },
+        ]
+    }
+
+    /// 命中沙箱特征的检查项权重之和
+    pub fn score(&self) -> u32 {
+        self.checks()
+            .iter()
+            .filter(|check| check.suspicious)
+            .map(|check| check.weight)
+            .sum()
+    }
+}
+
+/// 用户交互活跃度的观察结果
+///
+/// - `samples`: 采样次数
+/// - `cursor_moves`: 鼠标位置发生变化的次数
+/// - `foreground_changes`: 前台窗口发生变化的次数
+/// - `uptime_ms`: 系统启动时长
+/// - `recent_documents`: 最近打开文档的数量
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LivenessReport {
+    pub samples: u32,
+    pub cursor_moves: u32,
+    pub foreground_changes: u32,
+    pub uptime_ms: u64,
+    pub recent_documents: usize,
+}
+
+impl BeingDebug for LivenessReport {
+    fn is_being_debug(&self) -> bool {
+        self.score() >= Self::THRESHOLD
+    }
+}
+
+impl LivenessReport {
+    /// 总分达到该阈值则认为是无人操作的分析环境
+    pub const THRESHOLD: u32 = 50;
+
+    /// 计算每一项活跃度检查的结果
+    ///
+    /// - 观察期间鼠标没有移动: 权重35
+    /// - 观察期间前台窗口没有切换: 权重15
+    /// - 系统启动不足10分钟: 权重25
+    /// - 最近文档少于5个: 权重25
+    ///
+    /// 采样次数少于2时无法判断鼠标与窗口变化，这两项不计分
+    pub fn checks(&self) -> Vec<SandboxCheck> {
+        let observed = self.samples >= 2;
+        vec![
+            SandboxCheck {
+                name: "cursor",
+                weight: 35,
+                suspicious: observed && self.cursor_moves == 0,
+            },
+            SandboxCheck {
+                name: "foreground",
+                weight: 15,
+                suspicious: observed && self.foreground_changes == 0,
+            },
+            SandboxCheck {
+                name: "uptime",
+                weight: 25,
+                suspicious: self.uptime_ms < 10 * 60 * 1000,
+            },
+            SandboxCheck {
+                name: "recent_documents",
+                weight: 25,
+                suspicious: self.recent_documents < 5,
+            },
+        ]
+    }
+
+    /// 命中沙箱特征的检查项权重之和
+    pub fn score(&self) -> u32 {
+        self.checks()
+            .iter()
+            .filter(|check| check.suspicious)
+            .map(|check| check.weight)
+            .sum()
+    }
+}
+
+/// 用户交互活跃度观察器
+///
+/// 这是一个慢速检查，需要在较长时间内(数分钟)多次调用`sample`采样，
+/// 再通过`report`获取观察结果
+///
+/// # 示例
+///
+/// ```ignore
+/// let mut observer = LivenessObserver::default();
+/// for _ in 0..60 {
+///     observer.sample();
+///     std::thread::sleep(std::time::Duration::from_secs(5));
+/// }
+/// println!("{:?}", observer.report());
+/// ```
+#[derive(Debug, Default)]
+pub struct LivenessObserver {
+    samples: u32,
+    cursor_moves: u32,
+    foreground_changes: u32,
+    last_cursor: Option<(i32, i32)>,
+    last_foreground: Option<usize>,
+}
+
+impl LivenessObserver {
+    /// 采样一次鼠标位置与前台窗口
+    pub fn sample(&mut self) {
+        let mut point = POINT::default();
+        if unsafe { GetCursorPos(&mut point) }.is_ok() {
+            let cursor = (point.x, point.y);
+            if self.last_cursor.is_some_and(|last| last != cursor) {
+                self.cursor_moves += 1;
+            }
+            self.last_cursor = Some(cursor);
+        }
+
+        let foreground = unsafe { GetForegroundWindow() }.0 as usize;
+        if self.last_foreground.is_some_and(|last| last != foreground) {
+            self.foreground_changes += 1;
+        }
+        self.last_foreground = Some(foreground);
+
+        self.samples += 1;
+    }
+
+    /// 生成观察结果，同时读取系统启动时长与最近文档数量
+    pub fn report(&self) -> LivenessReport {
+        let report = LivenessReport {
+            samples: self.samples,
+            cursor_moves: self.cursor_moves,
+            foreground_changes: self.foreground_changes,
+            uptime_ms: unsafe { GetTickCount64() },
+            recent_documents: count_recent_documents(),
+        };
+
+        debug!("liveness report ==> {:?}", report);
+
+        report
+    }
+
+    /// 阻塞观察指定时长，每隔`interval`采样一次
+    ///
+    /// # 参数
+    ///
+    /// - `duration`: 观察总时长
+    /// - `interval`: 采样间隔
+    pub fn observe(duration: Duration, interval: Duration) -> LivenessReport {
+        let mut observer = Self::default();
+        let count = (duration.as_millis() / interval.as_millis().max(1)).max(1);
+        for _ in 0..count {
+            observer.sample();
+            thread::sleep(interval);
+        }
+        observer.sample();
+
+        observer.report()
+    }
+}
+
+/// 全局观察器两次采样的最小间隔，短时间内的重复调用不计入采样
+pub const LIVENESS_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 进程内共享的活跃度观察器，由检测引擎与守护对的检查循环持续采样
+static LIVENESS: Mutex<Option<(LivenessObserver, Instant)>> = Mutex::new(None);
+
+/// 对进程内共享的观察器采样一次，并返回到目前为止的观察结果
+///
+/// 检测引擎每轮执行与守护对的每次检查都会调用，观察时间随进程运行不断累积，
+/// 距上次采样不足`LIVENESS_SAMPLE_INTERVAL`时只返回结果不采样
+///
+/// # 示例
+///
+/// ```ignore
+/// let report = sample_liveness();
+/// if report.is_being_debug() {
+///     println!("nobody is using this machine: {:?}", report);
+/// }
+/// ```
+pub fn sample_liveness() -> LivenessReport {
+    let mut liveness = LIVENESS.lock().unwrap_or_else(|e| e.into_inner());
+    match liveness.as_mut() {
+        Some((observer, last)) => {
+            if last.elapsed() >= LIVENESS_SAMPLE_INTERVAL {
+                observer.sample();
+                *last = Instant::now();
+            }
+            observer.report()
+        }
+        None => {
+            let mut observer = LivenessObserver::default();
+            observer.sample();
+            let report = observer.report();
+            *liveness = Some((observer, Instant::now()));
+            report
+        }
+    }
+}
+
+/// 统计`%APPDATA%\Microsoft\Windows\Recent`中的最近文档数量
+fn count_recent_documents() -> usize {
+    let Some(appdata) = env::var_os("APPDATA") else {
+        return 0;
+    };
+
+    let recent = PathBuf::from(appdata).join("Microsoft\\Windows\\Recent");
+
+    fs::read_dir(recent)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).count())
+        .unwrap_or(0)
+}
+
+/// 沙箱注入到被分析进程中的代理DLL，以及对应的沙箱名称
+///
+/// 杀毒软件的常驻hook DLL(例如Avast的`snxhk.dll`)在普通用户机器上同样存在，不在列表中
+pub const SANDBOX_DLLS: [(ObfStr, ObfStr); 8] = [
+    (obf!("Sandboxie"), obf!("sbiedll.dll")),
+    (obf!("Comodo"), obf!("cmdvrt32.dll")),
+    (obf!("Comodo"), obf!("cmdvrt64.dll")),
+    (obf!("Cuckoo"), obf!("cuckoomon.dll")),
+    (obf!("iDefense"), obf!("dir_watch.dll")),
+    (obf!("iDefense"), obf!("api_log.dll")),
+    (obf!("WPE Pro"), obf!("wpespy.dll")),
+    (obf!("360"), obf!("sxin.dll")),
+];
+
+/// 检测当前进程中是否加载了已知沙箱的代理DLL
+///
+/// # 返回值
+///
+/// - `Some((sandbox, dll))`: 识别到的沙箱名称与命中的DLL
+/// - `None`: 未发现沙箱DLL
+///
+/// # 示例
+///
+/// ```ignore
+/// if let Some((sandbox, dll)) = check_sandbox_dlls() {
+///     println!("running in {} ({})", sandbox, dll);
+/// }
+/// ```
+pub fn check_sandbox_dlls() -> Option<(String, String)> {
+    for (sandbox, dll) in SANDBOX_DLLS {
+        let (sandbox, dll) = (sandbox.decrypt(), dll.decrypt());
+        if is_module_loaded(&dll) {
+            debug!("sandbox dll loaded ==> {}; sandbox: {}", dll, sandbox);
+            return Some((sandbox.to_string(), dll.to_string()));
+        }
+    }
+
+    None
+}
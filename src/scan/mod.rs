@@ -0,0 +1,187 @@
+#[cfg(all(target_arch = "x86", target_feature = "sse2"))]
+use std::arch::x86::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8};
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+use std::arch::x86_64::{
+    __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+};
+use std::thread;
+
+/// 批量扫描时每处理这么多字节让出一次CPU，扫描数MB的代码段时不会长时间占满一个核心
+pub const CHUNK_SIZE: usize = 0x40000;
+
+/// 一次比较的字节数
+#[cfg(any(
+    all(target_arch = "x86", target_feature = "sse2"),
+    all(target_arch = "x86_64", target_feature = "sse2")
+))]
+const LANES: usize = 16;
+
+/// 16字节中等于`needle`的位置掩码
+#[cfg(any(
+    all(target_arch = "x86", target_feature = "sse2"),
+    all(target_arch = "x86_64", target_feature = "sse2")
+))]
+#[inline(always)]
+unsafe fn mask_eq(data: *const u8, needle: __m128i) -> u32 {
+    _mm_movemask_epi8(_mm_cmpeq_epi8(
+        _mm_loadu_si128(data as *const __m128i),
+        needle,
+    )) as u32
+}
+
+/// 查找第一个等于`byte`的位置
+///
+/// 支持SSE2时每次比较16字节，否则逐字节比较
+///
+/// # 参数
+///
+/// - `haystack`: 被扫描的数据
+/// - `byte`: 要查找的字节，例如软件断点`0xCC`
+///
+/// # 返回值
+///
+/// - `Some(offset)`: 第一次出现的偏移
+/// - `None`: 不存在
+pub fn find_byte(haystack: &[u8], byte: u8) -> Option<usize> {
+    #[allow(unused_mut)]
+    let mut offset: usize = 0;
+
+    #[cfg(any(
+        all(target_arch = "x86", target_feature = "sse2"),
+        all(target_arch = "x86_64", target_feature = "sse2")
+    ))]
+    unsafe {
+        let needle = _mm_set1_epi8(byte as i8);
+        while offset + LANES <= haystack.len() {
+            let mask = mask_eq(haystack.as_ptr().add(offset), needle);
+            if mask != 0 {
+                return Some(offset + mask.trailing_zeros() as usize);
+            }
+            offset += LANES;
+        }
+    }
+
+    haystack[offset..]
+        .iter()
+        .position(|&b| b == byte)
+        .map(|position| offset + position)
+}
+
+/// 查找两段数据第一个不相同的位置，只比较较短的长度
+///
+/// # 返回值
+///
+/// - `Some(offset)`: 第一个不相同字节的偏移
+/// - `None`: 公共部分完全相同
+pub fn find_mismatch(left: &[u8], right: &[u8]) -> Option<usize> {
+    let len = left.len().min(right.len());
+    #[allow(unused_mut)]
+    let mut offset: usize = 0;
+
+    #[cfg(any(
+        all(target_arch = "x86", target_feature = "sse2"),
+        all(target_arch = "x86_64", target_feature = "sse2")
+    ))]
+    unsafe {
+        while offset + LANES <= len {
+            let mask = mask_eq(
+                left.as_ptr().add(offset),
+                _mm_loadu_si128(right.as_ptr().add(offset) as *const __m128i),
+            );
+            if mask != 0xFFFF {
+                return Some(offset + (!mask).trailing_zeros() as usize);
+            }
+            offset += LANES;
+        }
+    }
+
+    left[offset..len]
+        .iter()
+        .zip(&right[offset..len])
+        .position(|(l, r)| l != r)
+        .map(|position| offset + position)
+}
+
+/// 查找子序列第一次出现的位置，用首字节的向量扫描筛选候选位置后再逐个比较
+///
+/// # 返回值
+///
+/// - `Some(offset)`: 第一次出现的偏移，`needle`为空时为0
+/// - `None`: 不存在
+pub fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let Some((&first, _)) = needle.split_first() else {
+        return Some(0);
+    };
+
+    let mut offset: usize = 0;
+    while offset + needle.len() <= haystack.len() {
+        let candidate =
+            offset + find_byte(&haystack[offset..=haystack.len() - needle.len()], first)?;
+        if &haystack[candidate..candidate + needle.len()] == needle {
+            return Some(candidate);
+        }
+        offset = candidate + 1;
+    }
+
+    None
+}
+
+/// 查找所有等于`byte`的位置
+///
+/// 按`CHUNK_SIZE`分块处理，块之间让出CPU；找到`limit`个后立即返回
+///
+/// # 参数
+///
+/// - `haystack`: 被扫描的数据
+/// - `byte`: 要查找的字节
+/// - `limit`: 最多返回的数量
+pub fn find_all_bytes(haystack: &[u8], byte: u8, limit: usize) -> Vec<usize> {
+    let mut found: Vec<usize> = Vec::new();
+    for (index, chunk) in haystack.chunks(CHUNK_SIZE).enumerate() {
+        let base = index * CHUNK_SIZE;
+        let mut offset: usize = 0;
+        while let Some(position) = find_byte(&chunk[offset..], byte) {
+            if found.len() >= limit {
+                return found;
+            }
+            found.push(base + offset + position);
+            offset += position + 1;
+        }
+        thread::yield_now();
+    }
+
+    found.truncate(limit);
+    found
+}
+
+/// 查找两段数据所有不相同的位置，只比较较短的长度
+///
+/// 按`CHUNK_SIZE`分块处理，块之间让出CPU；找到`limit`个后立即返回
+///
+/// # 示例
+///
+/// ```ignore
+/// // 内存中的代码段与磁盘上的代码段
+/// for offset in scan::find_differences(memory, disk, 64) {
+///     println!("patched ==> {:#x}", offset);
+/// }
+/// ```
+pub fn find_differences(left: &[u8], right: &[u8], limit: usize) -> Vec<usize> {
+    let len = left.len().min(right.len());
+    let mut found: Vec<usize> = Vec::new();
+    for base in (0..len).step_by(CHUNK_SIZE) {
+        let end = (base + CHUNK_SIZE).min(len);
+        let mut offset = base;
+        while let Some(position) = find_mismatch(&left[offset..end], &right[offset..end]) {
+            if found.len() >= limit {
+                return found;
+            }
+            found.push(offset + position);
+            offset += position + 1;
+        }
+        thread::yield_now();
+    }
+
+    found.truncate(limit);
+    found
+}
@@ -0,0 +1,245 @@
+use crate::logging::{debug, warn};
+use crate::{
+    engine::{builtin_techniques, Engine, Technique, Verdict},
+    random,
+    timing::rdtsc,
+    util,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use windows::Win32::{
+    Foundation::FILETIME,
+    System::Threading::{
+        GetCurrentThread, GetThreadTimes, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN,
+        THREAD_PRIORITY_LOWEST,
+    },
+};
+
+/// 默认在后台调度的内置技术，它们需要遍历句柄表、进程列表或者模块，单次开销较大
+pub const BACKGROUND_TECHNIQUES: [&str; 7] = [
+    "honey_thread",
+    "process_blacklist",
+    "inline_hooks",
+    "eat_hooks",
+    "iat_checksum",
+    "self_integrity",
+    "unsigned_modules",
+];
+
+/// 后台调度的配置
+///
+/// - `min_gap`: 两次检测之间的最短间隔
+/// - `max_gap`: 两次检测之间的最长间隔，实际间隔在两者之间随机选取
+/// - `cpu_limit`: 检测占用单个核心的CPU时间比例上限，例如0.05表示5%，为0时不限制
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    pub min_gap: Duration,
+    pub max_gap: Duration,
+    pub cpu_limit: f64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            min_gap: Duration::from_secs(1),
+            max_gap: Duration::from_secs(5),
+            cpu_limit: 0.05,
+        }
+    }
+}
+
+/// 在低优先级线程中以随机间隔执行开销较大的检测
+///
+/// 固定周期的检测会在CPU占用上留下有规律的尖峰，分析人员可以据此定位检测代码并掐准时机操作。
+/// 调度器每轮打乱检测顺序，检测之间的间隔随机，且根据检测实际消耗的CPU时间延长间隔，
+/// 保证平均占用不超过`cpu_limit`
+///
+/// # 示例
+///
+/// ```ignore
+/// let scan = Scheduler::default().start(|verdict| {
+///     if verdict.detected {
+///         println!("{} ==> {:?}", verdict.name, verdict.evidence);
+///     }
+/// });
+/// // scan被drop时停止调度
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    pub config: SchedulerConfig,
+    pub techniques: Vec<Technique>,
+}
+
+impl Default for Scheduler {
+    /// 使用默认配置调度`BACKGROUND_TECHNIQUES`中的内置技术
+    fn default() -> Self {
+        let mut scheduler = Self::new(SchedulerConfig::default());
+        for technique in builtin_techniques() {
+            if BACKGROUND_TECHNIQUES.contains(&technique.name) {
+                scheduler.register(technique);
+            }
+        }
+        scheduler
+    }
+}
+
+impl Scheduler {
+    /// 创建一个没有任何检测技术的调度器
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            techniques: Vec::new(),
+        }
+    }
+
+    /// 注册一个检测技术
+    pub fn register(&mut self, technique: Technique) {
+        self.techniques.push(technique);
+    }
+
+    /// 启动后台调度线程
+    ///
+    /// # 参数
+    ///
+    /// - `on_verdict`: 每个技术执行完成后的回调，在调度线程中调用
+    ///
+    /// # 返回值
+    ///
+    /// - 调度句柄，drop时停止调度并等待当前检测完成
+    pub fn start<F>(self, on_verdict: F) -> ScheduledScan
+    where
+        F: Fn(&Verdict) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let worker = util::spawn(move || {
+            // 后台模式同时降低CPU、I/O与内存优先级，不支持时退回最低优先级
+            unsafe {
+                if SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN).is_err() {
+                    let _ = SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_LOWEST);
+                }
+            }
+
+            let mut seed = rdtsc();
+            let mut order: Vec<usize> = (0..self.techniques.len()).collect();
+            while !stopped.load(Ordering::SeqCst) {
+                if order.is_empty() {
+                    return;
+                }
+                for i in (1..order.len()).rev() {
+                    order.swap(i, (random::next(&mut seed) % (i as u64 + 1)) as usize);
+                }
+
+                for index in order.iter() {
+                    if stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+
+                    let cpu_start = thread_cpu_time();
+                    let verdict = Engine::run_technique(&self.techniques[*index]);
+                    let cpu = thread_cpu_time().saturating_sub(cpu_start);
+                    on_verdict(&verdict);
+
+                    let gap = self.gap(&mut seed, cpu);
+                    debug!(
+                        "scheduled technique ==> {}; cpu: {:?}; next in {:?}",
+                        verdict.name, cpu, gap
+                    );
+                    park_until(Instant::now() + gap, &stopped);
+                }
+            }
+        });
+
+        ScheduledScan {
+            stop,
+            worker: Some(worker),
+        }
+    }
+
+    /// 下一次检测前的间隔：随机间隔与CPU占用限制要求的间隔中较大的一个
+    fn gap(&self, seed: &mut u64, cpu: Duration) -> Duration {
+        let (min_gap, max_gap) = (
+            self.config.min_gap,
+            self.config.max_gap.max(self.config.min_gap),
+        );
+        let span = (max_gap - min_gap).as_micros() as u64;
+        let gap = min_gap + Duration::from_micros(random::next(seed) % (span + 1));
+
+        if self.config.cpu_limit <= 0.0 {
+            return gap;
+        }
+        let throttle = cpu
+            .div_f64(self.config.cpu_limit.min(1.0))
+            .saturating_sub(cpu);
+        if throttle > gap {
+            warn!("scheduled technique exceeded cpu limit; cpu: {:?}", cpu);
+        }
+        gap.max(throttle)
+    }
+}
+
+/// 运行中的后台调度，drop时停止
+pub struct ScheduledScan {
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ScheduledScan {
+    /// 停止调度并等待当前检测完成
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for ScheduledScan {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
+
+/// 等待到`deadline`，`stop`被设置并唤醒线程时提前返回
+fn park_until(deadline: Instant, stop: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        thread::park_timeout(deadline - now);
+    }
+}
+
+/// 当前线程消耗的CPU时间(内核态 + 用户态)
+fn thread_cpu_time() -> Duration {
+    let (mut creation, mut exit, mut kernel, mut user) = (
+        FILETIME::default(),
+        FILETIME::default(),
+        FILETIME::default(),
+        FILETIME::default(),
+    );
+    if unsafe {
+        GetThreadTimes(
+            GetCurrentThread(),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        )
+    }
+    .is_err()
+    {
+        return Duration::ZERO;
+    }
+
+    let ticks = |time: FILETIME| (time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64;
+    Duration::from_nanos((ticks(kernel) + ticks(user)) * 100)
+}
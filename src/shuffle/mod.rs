@@ -0,0 +1,77 @@
+use crate::{
+    obfstr::BUILD_KEY,
+    random::{mix, GAMMA},
+};
+
+/// 第`index`轮Fisher-Yates交换的位置，范围`0..=index`
+const fn swap_index(key: u64, index: usize) -> usize {
+    (mix(key.wrapping_add((index as u64).wrapping_mul(GAMMA))) % (index as u64 + 1)) as usize
+}
+
+/// 由密钥生成`0..N`的排列，编译期求值
+///
+/// # 参数
+///
+/// - `key`: 密钥，相同密钥得到相同排列
+pub const fn permutation<const N: usize>(key: u64) -> [usize; N] {
+    let mut order = [0usize; N];
+    let mut i = 0;
+    while i < N {
+        order[i] = i;
+        i += 1;
+    }
+
+    while i > 1 {
+        i -= 1;
+        let j = swap_index(key, i);
+        let value = order[i];
+        order[i] = order[j];
+        order[j] = value;
+    }
+    order
+}
+
+/// 按构建密钥打乱列表顺序，同一次构建中结果固定，不同构建之间不同
+///
+/// # 参数
+///
+/// - `items`: 需要打乱的列表
+pub fn shuffled<T>(mut items: Vec<T>) -> Vec<T> {
+    for i in (1..items.len()).rev() {
+        items.swap(i, swap_index(BUILD_KEY, i));
+    }
+    items
+}
+
+/// 按构建密钥决定的顺序依次执行多个检测，返回值仍按书写顺序排列
+///
+/// 执行顺序在编译期确定，并按调用位置派生，每次构建的执行顺序与生成的代码布局都不同，
+/// 针对某一个版本制作的补丁无法直接用于其他版本
+///
+/// # 注意
+///
+/// - 所有检测都会执行，不会像`||`/`&&`一样短路
+/// - 所有检测的返回值类型必须相同
+///
+/// # 示例
+///
+/// ```ignore
+/// let [being_debugged, nt_global_flag] =
+///     shuffle!(WinPeb::fast_being_debugged(), WinPeb::fast_nt_global_flag());
+/// if being_debugged || nt_global_flag {
+///     std::process::exit(1);
+/// }
+/// ```
+#[macro_export]
+macro_rules! shuffle {
+    ($($check:expr),+ $(,)?) => {{
+        const ORDER: [usize; [$(stringify!($check)),+].len()] =
+            $crate::shuffle::permutation($crate::obfstr::derive_key(file!(), line!(), column!()));
+        let checks: [&mut dyn FnMut() -> _; ORDER.len()] = [$(&mut || $check),+];
+        let mut results: [Option<_>; ORDER.len()] = ::core::array::from_fn(|_| None);
+        for index in ORDER {
+            results[index] = Some(checks[index]());
+        }
+        results.map(Option::unwrap)
+    }};
+}
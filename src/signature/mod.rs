@@ -0,0 +1,283 @@
+use crate::logging::{debug, warn};
+use crate::{obf, obfstr::ObfStr};
+use anyhow::{Error, Result};
+use regex::{Regex, RegexBuilder};
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{OnceLock, RwLock},
+};
+
+/// 内置的默认特征列表，编译期加密
+const DEFAULT_SIGNATURES: ObfStr = obf!(include_str!("default.txt"));
+
+/// 特征列表类型，对应各个特征扫描
+///
+/// - `Window`: 窗口标题/类名
+/// - `Process`: 进程名
+/// - `Module`: 模块(DLL)名
+/// - `Driver`: 驱动名
+/// - `Object`: 命名内核对象(设备、互斥体等)
+/// - `Env`: 环境变量名
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum SignatureKind {
+    Window,
+    Process,
+    Module,
+    Driver,
+    Object,
+    Env,
+}
+
+impl TryFrom<&str> for SignatureKind {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "window" => Ok(Self::Window),
+            "process" => Ok(Self::Process),
+            "module" => Ok(Self::Module),
+            "driver" => Ok(Self::Driver),
+            "object" => Ok(Self::Object),
+            "env" => Ok(Self::Env),
+            _ => Err(Error::msg(format!("unknown signature kind: {}", value))),
+        }
+    }
+}
+
+/// 单条特征匹配规则
+///
+/// - `Exact`: 完整匹配
+/// - `Wildcard`: `*`/`?`通配符匹配
+/// - `Regex`: 正则表达式匹配
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Exact(String),
+    Wildcard(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// 解析一条规则，`re:`前缀为正则，包含`*`/`?`为通配符，其余为完整匹配
+    pub fn parse(value: &str) -> Result<Self> {
+        if let Some(expr) = value.strip_prefix("re:") {
+            let regex = RegexBuilder::new(expr).case_insensitive(true).build()?;
+            return Ok(Self::Regex(regex));
+        }
+
+        if value.contains(['*', '?']) {
+            return Ok(Self::Wildcard(value.to_lowercase()));
+        }
+
+        Ok(Self::Exact(value.to_lowercase()))
+    }
+
+    /// 判断名称是否命中规则，不区分大小写
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            Self::Exact(value) => value.eq_ignore_ascii_case(name),
+            Self::Wildcard(value) => {
+                wildcard_match(value.as_bytes(), name.to_lowercase().as_bytes())
+            }
+            Self::Regex(regex) => regex.is_match(name),
+        }
+    }
+
+    /// 规则原文，用于报告命中的特征
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Exact(value) | Self::Wildcard(value) => value,
+            Self::Regex(regex) => regex.as_str(),
+        }
+    }
+}
+
+/// 通配符匹配，`*`匹配任意长度字符，`?`匹配单个字符
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// 某一类型的黑名单与白名单
+#[derive(Clone, Debug, Default)]
+pub struct SignatureList {
+    pub blacklist: Vec<Pattern>,
+    pub whitelist: Vec<Pattern>,
+}
+
+impl SignatureList {
+    /// 判断名称是否命中黑名单且不在白名单中
+    ///
+    /// # 返回值
+    ///
+    /// - `Some(pattern)`: 命中的黑名单规则
+    /// - `None`: 未命中或者被白名单放行
+    pub fn matches(&self, name: &str) -> Option<&Pattern> {
+        if self.whitelist.iter().any(|pattern| pattern.is_match(name)) {
+            return None;
+        }
+
+        self.blacklist.iter().find(|pattern| pattern.is_match(name))
+    }
+}
+
+/// 所有类型的特征列表
+#[derive(Clone, Debug, Default)]
+pub struct Signatures {
+    pub lists: HashMap<SignatureKind, SignatureList>,
+}
+
+impl Signatures {
+    /// 只包含内置默认特征的列表
+    pub fn embedded() -> Self {
+        let mut signatures = Self::default();
+        signatures
+            .parse(&DEFAULT_SIGNATURES.decrypt())
+            .expect("embedded signatures are invalid");
+        signatures
+    }
+
+    /// 解析特征文本并追加到当前列表中
+    ///
+    /// # 参数
+    ///
+    /// - `text`: 特征文本，格式参考内置的default.txt
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 段落类型未知、规则出现在段落之前或者正则表达式错误
+    /// - `Ok(())`: 解析成功
+    pub fn parse(&mut self, text: &str) -> Result<()> {
+        let mut kind: Option<SignatureKind> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                kind = Some(SignatureKind::try_from(section.trim())?);
+                continue;
+            }
+
+            let Some(kind) = kind else {
+                warn!("signature line {} is outside of any section", index + 1);
+                return Err(Error::msg(format!(
+                    "signature line {} is outside of any section",
+                    index + 1
+                )));
+            };
+
+            let list = self.lists.entry(kind).or_default();
+            match line.as_bytes()[0] {
+                b'-' => list.whitelist.push(Pattern::parse(line[1..].trim())?),
+                b'+' => list.blacklist.push(Pattern::parse(line[1..].trim())?),
+                _ => list.blacklist.push(Pattern::parse(line)?),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 加载用户提供的特征文件，追加到当前列表中
+    ///
+    /// # 参数
+    ///
+    /// - `path`: 特征文件路径
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 读取文件失败或者文件格式错误
+    /// - `Ok(())`: 加载成功
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let text = fs::read_to_string(path.as_ref())?;
+        self.parse(&text)?;
+
+        debug!("load signature file ==> {:?}", path.as_ref());
+
+        Ok(())
+    }
+
+    /// 判断指定类型的名称是否命中黑名单
+    ///
+    /// # 返回值
+    ///
+    /// - `Some(pattern)`: 命中的规则原文
+    /// - `None`: 未命中或者被白名单放行
+    pub fn matches(&self, kind: SignatureKind, name: &str) -> Option<String> {
+        self.lists
+            .get(&kind)?
+            .matches(name)
+            .map(|pattern| pattern.as_str().to_string())
+    }
+
+    /// 指定类型黑名单中的完整匹配规则
+    ///
+    /// 设备对象一类无法枚举的名称只能逐个探测，通配符与正则规则不适用，会被忽略
+    pub fn exact_blacklist(&self, kind: SignatureKind) -> Vec<String> {
+        self.lists
+            .get(&kind)
+            .map(|list| {
+                list.blacklist
+                    .iter()
+                    .filter(|pattern| matches!(pattern, Pattern::Exact(_)))
+                    .filter(|pattern| list.matches(pattern.as_str()).is_some())
+                    .map(|pattern| pattern.as_str().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// 全局特征列表，所有特征扫描共享
+fn global() -> &'static RwLock<Signatures> {
+    static SIGNATURES: OnceLock<RwLock<Signatures>> = OnceLock::new();
+    SIGNATURES.get_or_init(|| RwLock::new(Signatures::embedded()))
+}
+
+/// 在全局特征列表上追加用户提供的特征文件
+///
+/// # 示例
+///
+/// ```ignore
+/// signature::load_user_file("signatures.txt").expect("load signature file failed");
+/// ```
+pub fn load_user_file<P: AsRef<Path>>(path: P) -> Result<()> {
+    let mut signatures = global()
+        .write()
+        .map_err(|_| Error::msg("signature lock poisoned"))?;
+    signatures.load_file(path)
+}
+
+/// 使用全局特征列表判断名称是否命中黑名单
+pub fn matches(kind: SignatureKind, name: &str) -> Option<String> {
+    global().read().ok()?.matches(kind, name)
+}
+
+/// 全局特征列表中指定类型黑名单的完整匹配规则
+pub fn exact_blacklist(kind: SignatureKind) -> Vec<String> {
+    global()
+        .read()
+        .map(|signatures| signatures.exact_blacklist(kind))
+        .unwrap_or_default()
+}
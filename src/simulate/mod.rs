@@ -0,0 +1,85 @@
+use crate::engine::Technique;
+use crate::logging::warn;
+use std::{
+    collections::BTreeSet,
+    env,
+    sync::{Mutex, MutexGuard, Once},
+};
+
+/// 启动时读取的环境变量，值为逗号分隔的技术名称
+pub const ENV_VAR: &str = "ANTI_DEBUG_SIMULATE";
+
+static FORCED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+static FROM_ENV: Once = Once::new();
+
+/// 强制命中的技术名称，第一次访问时加载`ENV_VAR`
+fn forced() -> MutexGuard<'static, BTreeSet<String>> {
+    let mut forced = FORCED.lock().unwrap_or_else(|e| e.into_inner());
+    FROM_ENV.call_once(|| {
+        if let Ok(value) = env::var(ENV_VAR) {
+            forced.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(String::from),
+            );
+        }
+    });
+    forced
+}
+
+/// 强制检测技术报告命中，用于在不附加调试器的情况下测试响应策略、遥测与退出路径
+///
+/// 检测函数仍然正常执行，只是未命中的结果被改为命中，证据为`simulated`
+///
+/// # 参数
+///
+/// - `name`: 技术名称(`Technique::name`)、类别名称(`Category::name`，例如`Debugger`)或者`*`表示所有技术
+///
+/// # 示例
+///
+/// ```ignore
+/// simulate::force("debug_port");
+/// assert!(Engine::default().run().is_debugged());
+/// simulate::clear();
+/// ```
+///
+/// 也可以通过环境变量设置：`ANTI_DEBUG_SIMULATE=debug_port,Environment`
+///
+/// # 注意
+///
+/// 只在开启`simulate` feature时编译，发布版本不要开启
+pub fn force(name: &str) {
+    forced().insert(name.to_string());
+}
+
+/// 取消`force`
+pub fn release(name: &str) {
+    forced().remove(name);
+}
+
+/// 取消所有强制命中，包括由环境变量设置的
+pub fn clear() {
+    forced().clear();
+}
+
+/// 当前强制命中的名称
+pub fn forced_names() -> Vec<String> {
+    forced().iter().cloned().collect()
+}
+
+/// 检测技术是否被强制命中
+pub(crate) fn is_forced(technique: &Technique) -> bool {
+    let forced = forced();
+    if forced.is_empty() {
+        return false;
+    }
+    let forced = forced.contains("*")
+        || forced.contains(technique.name)
+        || forced.contains(technique.category.name());
+    if forced {
+        warn!("technique {} is simulated", technique.name);
+    }
+    forced
+}
@@ -0,0 +1,379 @@
+use crate::engine::{Report, Verdict};
+use crate::logging::{debug, warn};
+use crate::util;
+use anyhow::{Error, Result};
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Condvar, Mutex, OnceLock, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(windows)]
+use windows::{
+    core::{GUID, PCWSTR},
+    Win32::System::Diagnostics::Etw::{
+        EventRegister, EventUnregister, EventWriteString, REGHANDLE,
+    },
+};
+
+/// 检测结果的输出目标，检测引擎执行完毕后把报告分发给所有注册的输出目标
+///
+/// 内置的实现：
+///
+/// - `LogSink`: 写入`log`日志
+/// - `EtwSink`: 写入ETW事件(Windows)
+/// - `eventlog::EventLog`: 写入Windows事件日志(`eventlog` feature)
+/// - `Mutex<ipc::EventClient>`: 通过命名管道发送给守护进程
+/// - `http_sink::HttpSink`: POST到服务端(`http-sink` feature)
+/// - 任意`Fn(&Report) -> Result<()>`闭包
+///
+/// 报告由后台分发线程交给输出目标，检测线程不会被输出目标阻塞；
+/// 多个输出目标在各自的线程中同时执行，一个目标阻塞或者失败不影响其他目标。
+/// 通过`install`注册的全局输出目标同时接收检测引擎与后台监视模块(完整性、调试寄存器、外部线程、守护进程)的命中
+///
+/// # 示例
+///
+/// ```ignore
+/// let mut engine = Engine::default();
+/// engine.add_sink(Arc::new(LogSink));
+/// engine.add_sink(Arc::new(|report: &Report| {
+///     if report.is_debugged() {
+///         std::process::exit(1);
+///     }
+///     Ok(())
+/// }));
+/// engine.run();
+/// ```
+pub trait DetectionSink: Send + Sync {
+    /// 输出目标名称，用于日志
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    /// 输出一次检测的报告
+    fn emit(&self, report: &Report) -> Result<()>;
+}
+
+impl fmt::Debug for dyn DetectionSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DetectionSink({})", self.name())
+    }
+}
+
+impl<F> DetectionSink for F
+where
+    F: Fn(&Report) -> Result<()> + Send + Sync,
+{
+    fn emit(&self, report: &Report) -> Result<()> {
+        self(report)
+    }
+}
+
+/// 写入`log`日志：每个命中的技术一条warn，汇总一条debug
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+impl DetectionSink for LogSink {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn emit(&self, report: &Report) -> Result<()> {
+        for verdict in report.detections() {
+            warn!(
+                "detection ==> {}; category: {:?}; confidence: {}; evidence: {:?}",
+                verdict.name, verdict.category, verdict.confidence, verdict.evidence
+            );
+        }
+        debug!(
+            "report ==> debugger: {}; environment: {}; tampering: {}; time virtualization: {}",
+            report.debugger_score(),
+            report.environment_score(),
+            report.tampering_score(),
+            report.time_virtualization_score()
+        );
+        Ok(())
+    }
+}
+
+/// 写入ETW事件，可以用`logman`、`tracelog`或者EDR的ETW采集按provider GUID订阅
+///
+/// 每个命中的技术一条WARNING级别的字符串事件，汇总一条INFORMATION级别的事件；
+/// 没有会话订阅该provider时写入事件几乎没有开销
+///
+/// # 示例
+///
+/// ```ignore
+/// engine.add_sink(Arc::new(EtwSink::register(&EtwSink::DEFAULT_PROVIDER)?));
+/// // logman start anti_debug -p {5d1b3c2a-7e44-4f0a-9c61-2b8ea4d3f017} -ets
+/// ```
+#[cfg(windows)]
+#[derive(Debug)]
+pub struct EtwSink {
+    handle: REGHANDLE,
+}
+
+#[cfg(windows)]
+impl EtwSink {
+    /// 默认的provider GUID
+    pub const DEFAULT_PROVIDER: GUID = GUID::from_u128(0x5d1b3c2a_7e44_4f0a_9c61_2b8ea4d3f017);
+
+    /// ETW事件级别：WARNING
+    const LEVEL_WARNING: u8 = 3;
+    /// ETW事件级别：INFORMATION
+    const LEVEL_INFORMATION: u8 = 4;
+
+    /// 注册ETW provider
+    ///
+    /// # 参数
+    ///
+    /// - `provider`: provider GUID，例如`DEFAULT_PROVIDER`
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 注册失败
+    /// - `Ok(sink)`: 输出目标，drop时注销provider
+    pub fn register(provider: &GUID) -> Result<Self> {
+        let mut handle: u64 = 0;
+        let status = unsafe { EventRegister(provider, None, None, &mut handle) };
+        if status != 0 {
+            return Err(Error::msg(format!("EventRegister failed: {}", status)));
+        }
+        Ok(Self {
+            handle: REGHANDLE(handle as i64),
+        })
+    }
+
+    /// 写入一条字符串事件
+    fn write(&self, level: u8, message: &str) -> Result<()> {
+        let wide: Vec<u16> = message.encode_utf16().chain(Some(0)).collect();
+        let status = unsafe { EventWriteString(self.handle, level, 0, PCWSTR(wide.as_ptr())) };
+        match status {
+            0 => Ok(()),
+            _ => Err(Error::msg(format!("EventWriteString failed: {}", status))),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl DetectionSink for EtwSink {
+    fn name(&self) -> &str {
+        "etw"
+    }
+
+    fn emit(&self, report: &Report) -> Result<()> {
+        for verdict in report.detections() {
+            self.write(Self::LEVEL_WARNING, &verdict.to_json())?;
+        }
+        self.write(
+            Self::LEVEL_INFORMATION,
+            &format!(
+                r#"{{"debugger_score":{},"environment_score":{},"tampering_score":{},"time_virtualization_score":{}}}"#,
+                report.debugger_score(),
+                report.environment_score(),
+                report.tampering_score(),
+                report.time_virtualization_score()
+            ),
+        )
+    }
+}
+
+#[cfg(windows)]
+impl Drop for EtwSink {
+    fn drop(&mut self) {
+        let _ = unsafe { EventUnregister(self.handle) };
+    }
+}
+
+/// 按置信度过滤报告后再交给内部输出目标，过滤后没有命中时不输出
+///
+/// - `sink`: 内部输出目标
+/// - `min_confidence`: 命中的最低置信度(见`Verdict::confidence`)
+///
+/// # 示例
+///
+/// ```ignore
+/// engine.add_sink(Arc::new(Threshold {
+///     sink: LogSink,
+///     min_confidence: 60,
+/// }));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Threshold<S> {
+    pub sink: S,
+    pub min_confidence: u32,
+}
+
+impl<S: DetectionSink> DetectionSink for Threshold<S> {
+    fn name(&self) -> &str {
+        self.sink.name()
+    }
+
+    fn emit(&self, report: &Report) -> Result<()> {
+        let report = report.filtered(self.min_confidence);
+        if report.detections().next().is_none() {
+            return Ok(());
+        }
+        self.sink.emit(&report)
+    }
+}
+
+/// 把报告同时分发给所有输出目标，等待全部完成
+///
+/// 只有一个输出目标时直接在当前线程执行；输出目标panic时按失败处理
+///
+/// # 返回值
+///
+/// - 与`sinks`相同顺序的执行结果
+pub fn fan_out(sinks: &[Arc<dyn DetectionSink>], report: &Report) -> Vec<Result<()>> {
+    let results: Vec<Result<()>> = match sinks {
+        [] => Vec::new(),
+        [sink] => vec![sink.emit(report)],
+        _ => thread::scope(|scope| {
+            let handles: Vec<_> = sinks
+                .iter()
+                .map(|sink| util::spawn_scoped(scope, || sink.emit(report)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(Error::msg("detection sink panicked")))
+                })
+                .collect()
+        }),
+    };
+
+    for (sink, result) in sinks.iter().zip(&results) {
+        if let Err(e) = result {
+            warn!("detection sink {} failed; error: {:?}", sink.name(), e);
+        }
+    }
+    results
+}
+
+/// 通过`install`注册的全局输出目标
+static INSTALLED: RwLock<Vec<Arc<dyn DetectionSink>>> = RwLock::new(Vec::new());
+
+/// 注册全局输出目标，所有检测引擎与后台监视模块的命中都会分发给它
+///
+/// # 示例
+///
+/// ```ignore
+/// sink::install(Arc::new(EtwSink::register(&EtwSink::DEFAULT_PROVIDER)?));
+/// integrity::spawn_monitor(key, Duration::from_secs(5), |_| std::process::exit(1));
+/// ```
+pub fn install(sink: Arc<dyn DetectionSink>) {
+    INSTALLED.write().unwrap().push(sink);
+}
+
+/// 移除所有全局输出目标
+pub fn uninstall_all() {
+    INSTALLED.write().unwrap().clear();
+}
+
+/// 当前注册的全局输出目标
+pub fn installed() -> Vec<Arc<dyn DetectionSink>> {
+    INSTALLED.read().unwrap().clone()
+}
+
+/// 后台监视模块发现命中时调用，把命中交给全局输出目标；没有注册全局输出目标时写入日志
+///
+/// # 参数
+///
+/// - `verdict`: 命中结果，见`Verdict::observed`
+pub fn publish(verdict: Verdict) {
+    let mut sinks = installed();
+    if sinks.is_empty() {
+        sinks.push(Arc::new(LogSink));
+    }
+    dispatch(
+        sinks,
+        Report {
+            verdicts: vec![verdict],
+        },
+    );
+}
+
+/// 等待分发的一份报告
+struct Job {
+    sinks: Vec<Arc<dyn DetectionSink>>,
+    report: Report,
+}
+
+/// 后台分发线程
+struct Dispatcher {
+    sender: Sender<Job>,
+    /// 已经提交但还没有分发完成的报告数量
+    pending: Mutex<usize>,
+    idle: Condvar,
+}
+
+/// 后台分发线程，第一次分发时启动
+fn dispatcher() -> &'static Dispatcher {
+    static DISPATCHER: OnceLock<Dispatcher> = OnceLock::new();
+    DISPATCHER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        util::spawn(move || {
+            for job in receiver {
+                // 输出目标panic时不能让分发线程退出
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| fan_out(&job.sinks, &job.report)));
+                let dispatcher = dispatcher();
+                *dispatcher.pending.lock().unwrap() -= 1;
+                dispatcher.idle.notify_all();
+            }
+        });
+        Dispatcher {
+            sender,
+            pending: Mutex::new(0),
+            idle: Condvar::new(),
+        }
+    })
+}
+
+/// 把报告交给后台分发线程，立即返回
+///
+/// # 参数
+///
+/// - `sinks`: 输出目标
+/// - `report`: 检测报告
+pub fn dispatch(sinks: Vec<Arc<dyn DetectionSink>>, report: Report) {
+    if sinks.is_empty() {
+        return;
+    }
+    let dispatcher = dispatcher();
+    *dispatcher.pending.lock().unwrap() += 1;
+    if dispatcher.sender.send(Job { sinks, report }).is_err() {
+        *dispatcher.pending.lock().unwrap() -= 1;
+        debug!("detection sink dispatcher stopped");
+    }
+}
+
+/// 等待已经提交的报告全部分发完成，例如在进程退出前调用
+///
+/// # 返回值
+///
+/// - `true`: 全部分发完成
+/// - `false`: 超时
+pub fn wait_idle(timeout: Duration) -> bool {
+    let dispatcher = dispatcher();
+    let deadline = Instant::now() + timeout;
+    let mut pending = dispatcher.pending.lock().unwrap();
+    while *pending > 0 {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        pending = dispatcher
+            .idle
+            .wait_timeout(pending, deadline - now)
+            .unwrap()
+            .0;
+    }
+    true
+}
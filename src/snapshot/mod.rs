@@ -0,0 +1,422 @@
+use crate::engine::{Engine, Report};
+use crate::handle_watch::{HandleEntry, HandleWatch};
+use crate::hook::{self, Detour, EatHook, IatHook};
+use crate::logging::{debug, warn};
+use crate::{
+    capability,
+    imports::{GetThreadContext, OpenProcess},
+    integrity, json, module,
+    pe::PeImage,
+    peb::WinPeb,
+    thread_monitor::thread_ids,
+};
+use anyhow::Result;
+use std::{
+    fmt::Write,
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+#[cfg(target_arch = "x86_64")]
+use windows::Win32::System::Diagnostics::Debug::CONTEXT_DEBUG_REGISTERS_AMD64 as CONTEXT_DEBUG_REGISTERS;
+#[cfg(target_arch = "x86")]
+use windows::Win32::System::Diagnostics::Debug::CONTEXT_DEBUG_REGISTERS_X86 as CONTEXT_DEBUG_REGISTERS;
+use windows::Win32::{
+    Foundation::{CloseHandle, HANDLE},
+    System::{
+        Diagnostics::Debug::CONTEXT,
+        Threading::{
+            GetCurrentProcessId, OpenThread, PROCESS_QUERY_LIMITED_INFORMATION, THREAD_GET_CONTEXT,
+            THREAD_QUERY_LIMITED_INFORMATION,
+        },
+    },
+};
+
+/// PEB与进程堆中和调试相关的字段，偏移来自`capability`能力表
+///
+/// - `address`: PEB地址
+/// - `being_debugged`: PEB.BeingDebugged
+/// - `nt_global_flag`: PEB.NtGlobalFlag
+/// - `process_heap`: PEB.ProcessHeap
+/// - `heap_flags`: 进程堆的Flags
+/// - `heap_force_flags`: 进程堆的ForceFlags
+#[derive(Debug, Clone, Default)]
+pub struct PebSnapshot {
+    pub address: usize,
+    pub being_debugged: u8,
+    pub nt_global_flag: u32,
+    pub process_heap: usize,
+    pub heap_flags: u32,
+    pub heap_force_flags: u32,
+}
+
+impl PebSnapshot {
+    /// 读取当前进程的PEB
+    pub fn capture() -> Self {
+        let capabilities = capability::get();
+        let address = WinPeb::fast_peb_address() as usize;
+        let read_u32 = |address: usize| unsafe { (address as *const u32).read_unaligned() };
+
+        let being_debugged = unsafe { *((address + capabilities.peb.being_debugged) as *const u8) };
+        let nt_global_flag = read_u32(address + capabilities.peb.nt_global_flag);
+        let process_heap =
+            unsafe { ((address + capabilities.peb.process_heap) as *const usize).read_unaligned() };
+        let (heap_flags, heap_force_flags) = match process_heap {
+            0 => (0, 0),
+            heap => (
+                read_u32(heap + capabilities.heap.flags),
+                read_u32(heap + capabilities.heap.force_flags),
+            ),
+        };
+
+        Self {
+            address,
+            being_debugged,
+            nt_global_flag,
+            process_heap,
+            heap_flags,
+            heap_force_flags,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"address":{},"being_debugged":{},"nt_global_flag":{},"process_heap":{},"heap_flags":{},"heap_force_flags":{}}}"#,
+            json::quote(&format!("{:#x}", self.address)),
+            self.being_debugged,
+            self.nt_global_flag,
+            json::quote(&format!("{:#x}", self.process_heap)),
+            self.heap_flags,
+            self.heap_force_flags
+        )
+    }
+}
+
+/// 已加载的模块
+///
+/// - `base`: 模块基址
+/// - `size`: 映像大小
+/// - `path`: 磁盘文件路径
+/// - `sha256`: 磁盘文件的SHA-256，读取失败时为None
+#[derive(Debug, Clone)]
+pub struct ModuleSnapshot {
+    pub base: usize,
+    pub size: usize,
+    pub path: String,
+    pub sha256: Option<String>,
+}
+
+impl ModuleSnapshot {
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"base":{},"size":{},"path":{},"sha256":{}}}"#,
+            json::quote(&format!("{:#x}", self.base)),
+            self.size,
+            json::quote(&self.path),
+            json::quote_option(self.sha256.as_deref())
+        )
+    }
+}
+
+/// 线程的调试寄存器
+///
+/// - `tid`: 线程ID
+/// - `dr`: Dr0~Dr3
+/// - `dr6`: 调试状态寄存器
+/// - `dr7`: 调试控制寄存器
+/// - `error`: 打开线程或者读取Context失败时的错误信息
+#[derive(Debug, Clone, Default)]
+pub struct ThreadSnapshot {
+    pub tid: u32,
+    pub dr: [usize; 4],
+    pub dr6: usize,
+    pub dr7: usize,
+    pub error: Option<String>,
+}
+
+impl ThreadSnapshot {
+    fn to_json(&self) -> String {
+        let dr: Vec<String> = self
+            .dr
+            .iter()
+            .map(|value| json::quote(&format!("{:#x}", value)))
+            .collect();
+        format!(
+            r#"{{"tid":{},"dr":[{}],"dr6":{},"dr7":{},"error":{}}}"#,
+            self.tid,
+            dr.join(","),
+            json::quote(&format!("{:#x}", self.dr6)),
+            json::quote(&format!("{:#x}", self.dr7)),
+            json::quote_option(self.error.as_deref())
+        )
+    }
+}
+
+/// 其他进程持有的、指向当前进程的句柄
+///
+/// - `entry`: 系统句柄表中的表项
+/// - `process`: 持有句柄的进程名，无法打开时为None
+#[derive(Debug, Clone)]
+pub struct HandleReference {
+    pub entry: HandleEntry,
+    pub process: Option<String>,
+}
+
+impl HandleReference {
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"pid":{},"process":{},"handle":{},"access":{},"object":{}}}"#,
+            self.entry.pid,
+            json::quote_option(self.process.as_deref()),
+            self.entry.handle,
+            json::quote(&format!("{:#x}", self.entry.access)),
+            json::quote(&format!("{:#x}", self.entry.object))
+        )
+    }
+}
+
+/// 一次完整的取证快照，用于离线分析可疑机器
+///
+/// 各部分分别采集，某一部分失败时记录在`errors`中，其余部分照常输出
+///
+/// # 示例
+///
+/// ```ignore
+/// let snapshot = Snapshot::capture();
+/// std::fs::write("snapshot.json", snapshot.to_json())?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub timestamp: u64,
+    pub pid: u32,
+    pub peb: PebSnapshot,
+    pub modules: Vec<ModuleSnapshot>,
+    pub handles: Vec<HandleReference>,
+    pub threads: Vec<ThreadSnapshot>,
+    pub inline_hooks: Vec<Detour>,
+    pub iat_hooks: Vec<IatHook>,
+    pub eat_hooks: Vec<EatHook>,
+    pub report: Report,
+    pub errors: Vec<String>,
+}
+
+impl Snapshot {
+    /// 采集当前进程的快照，并执行一次所有内置检测技术
+    pub fn capture() -> Self {
+        let mut errors: Vec<String> = Vec::new();
+        let mut section = |name: &str, result: Result<()>| {
+            if let Err(e) = result {
+                warn!("snapshot {} failed; error: {:?}", name, e);
+                errors.push(format!("{}: {}", name, e));
+            }
+        };
+
+        let mut snapshot = Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+            pid: unsafe { GetCurrentProcessId() },
+            peb: PebSnapshot::capture(),
+            modules: Vec::new(),
+            handles: Vec::new(),
+            threads: Vec::new(),
+            inline_hooks: Vec::new(),
+            iat_hooks: Vec::new(),
+            eat_hooks: Vec::new(),
+            report: Engine::default().run(),
+            errors: Vec::new(),
+        };
+
+        section(
+            "modules",
+            capture_modules().map(|modules| snapshot.modules = modules),
+        );
+        section(
+            "handles",
+            capture_handles(snapshot.pid).map(|handles| snapshot.handles = handles),
+        );
+        section(
+            "threads",
+            capture_threads(snapshot.pid).map(|threads| snapshot.threads = threads),
+        );
+        section(
+            "inline_hooks",
+            hook::scan_system_inline_hooks().map(|hooks| snapshot.inline_hooks = hooks),
+        );
+        section(
+            "iat_hooks",
+            hook::scan_iat_hooks().map(|hooks| snapshot.iat_hooks = hooks),
+        );
+        section(
+            "eat_hooks",
+            hook::scan_system_eat_hooks().map(|hooks| snapshot.eat_hooks = hooks),
+        );
+
+        snapshot.errors = errors;
+        debug!(
+            "snapshot captured ==> modules: {}; handles: {}; threads: {}; errors: {}",
+            snapshot.modules.len(),
+            snapshot.handles.len(),
+            snapshot.threads.len(),
+            snapshot.errors.len()
+        );
+        snapshot
+    }
+
+    /// 序列化为JSON对象
+    pub fn to_json(&self) -> String {
+        let inline_hooks: Vec<String> = self
+            .inline_hooks
+            .iter()
+            .map(|detour| {
+                format!(
+                    r#"{{"module":{},"function":{},"address":{},"memory":{},"disk":{}}}"#,
+                    json::quote(&detour.module),
+                    json::quote(&detour.function),
+                    json::quote(&format!("{:#x}", detour.address)),
+                    json::quote(&hex(&detour.memory)),
+                    json::quote(&hex(&detour.disk))
+                )
+            })
+            .collect();
+        let iat_hooks: Vec<String> = self
+            .iat_hooks
+            .iter()
+            .map(|hook| {
+                format!(
+                    r#"{{"module":{},"function":{},"address":{},"owner":{}}}"#,
+                    json::quote(&hook.module),
+                    json::quote(&hook.function),
+                    json::quote(&format!("{:#x}", hook.address)),
+                    json::quote_option(
+                        hook.owner
+                            .as_ref()
+                            .map(|owner| owner.to_string_lossy())
+                            .as_deref()
+                    )
+                )
+            })
+            .collect();
+        let eat_hooks: Vec<String> = self
+            .eat_hooks
+            .iter()
+            .map(|hook| {
+                format!(
+                    r#"{{"module":{},"function":{},"memory_rva":{},"disk_rva":{}}}"#,
+                    json::quote(&hook.module),
+                    json::quote(&hook.function),
+                    hook.memory_rva,
+                    hook.disk_rva
+                        .map(|rva| rva.to_string())
+                        .unwrap_or_else(|| "null".to_string())
+                )
+            })
+            .collect();
+        let errors: Vec<String> = self.errors.iter().map(|error| json::quote(error)).collect();
+
+        let mut text = String::new();
+        let _ = write!(
+            text,
+            r#"{{"timestamp":{},"pid":{},"peb":{},"modules":[{}],"handles":[{}],"threads":[{}],"inline_hooks":[{}],"iat_hooks":[{}],"eat_hooks":[{}],"report":{},"errors":[{}]}}"#,
+            self.timestamp,
+            self.pid,
+            self.peb.to_json(),
+            join(self.modules.iter().map(ModuleSnapshot::to_json)),
+            join(self.handles.iter().map(HandleReference::to_json)),
+            join(self.threads.iter().map(ThreadSnapshot::to_json)),
+            inline_hooks.join(","),
+            iat_hooks.join(","),
+            eat_hooks.join(","),
+            self.report.to_json(),
+            errors.join(",")
+        );
+        text
+    }
+}
+
+fn join(items: impl Iterator<Item = String>) -> String {
+    items.collect::<Vec<String>>().join(",")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 枚举已加载的模块并计算磁盘文件的哈希
+fn capture_modules() -> Result<Vec<ModuleSnapshot>> {
+    let mut modules: Vec<ModuleSnapshot> = Vec::new();
+    for hmodule in module::get_loaded_modules()? {
+        let path = hook::get_module_path(hmodule)?;
+        let sha256 = fs::read(&path)
+            .ok()
+            .map(|data| hex(&integrity::sha256(&data)));
+        modules.push(ModuleSnapshot {
+            base: hmodule.0 as usize,
+            size: PeImage::from_module(hmodule)
+                .map(|image| image.size())
+                .unwrap_or_default(),
+            path: path.to_string_lossy().into_owned(),
+            sha256,
+        });
+    }
+    Ok(modules)
+}
+
+/// 查找其他进程持有的、指向当前进程的句柄
+fn capture_handles(pid: u32) -> Result<Vec<HandleReference>> {
+    // 句柄表中不包含伪句柄，需要打开一个真实的进程句柄来解析内核对象地址
+    let hprocess = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }?;
+    let mut watch = HandleWatch::new(pid);
+    let result = watch.watch(hprocess).and_then(|_| watch.poll());
+    let _ = unsafe { CloseHandle(hprocess) };
+
+    Ok(result?
+        .added
+        .into_iter()
+        .map(|entry| HandleReference {
+            process: crate::util::get_process_name(entry.pid),
+            entry,
+        })
+        .collect())
+}
+
+/// 读取当前进程中所有线程的调试寄存器
+fn capture_threads(pid: u32) -> Result<Vec<ThreadSnapshot>> {
+    Ok(thread_ids(pid)?.into_iter().map(capture_thread).collect())
+}
+
+fn capture_thread(tid: u32) -> ThreadSnapshot {
+    let mut thread = ThreadSnapshot {
+        tid,
+        ..Default::default()
+    };
+    let hthread: HANDLE = match unsafe {
+        OpenThread(
+            THREAD_GET_CONTEXT | THREAD_QUERY_LIMITED_INFORMATION,
+            false,
+            tid,
+        )
+    } {
+        Ok(hthread) => hthread,
+        Err(e) => {
+            thread.error = Some(e.to_string());
+            return thread;
+        }
+    };
+
+    let mut context = CONTEXT {
+        ContextFlags: CONTEXT_DEBUG_REGISTERS,
+        ..Default::default()
+    };
+    match unsafe { GetThreadContext(hthread, &mut context) }.ok() {
+        Ok(()) => {
+            thread.dr = [context.Dr0, context.Dr1, context.Dr2, context.Dr3].map(|dr| dr as usize);
+            thread.dr6 = context.Dr6 as usize;
+            thread.dr7 = context.Dr7 as usize;
+        }
+        Err(e) => thread.error = Some(e.to_string()),
+    }
+    let _ = unsafe { CloseHandle(hthread) };
+
+    thread
+}
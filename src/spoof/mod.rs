@@ -0,0 +1,219 @@
+use crate::logging::{debug, warn};
+use crate::{pe::PeImage, resolve, scan};
+use std::{
+    arch::asm,
+    ffi::c_void,
+    mem::{size_of, transmute},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+use windows::Win32::System::{
+    Diagnostics::Debug::IMAGE_SCN_MEM_EXECUTE,
+    SystemServices::PROCESS_MITIGATION_USER_SHADOW_STACK_POLICY,
+    Threading::{GetCurrentProcess, GetProcessMitigationPolicy, ProcessUserShadowStackPolicy},
+};
+
+/// `jmp rbx`，被调用函数返回到gadget后跳回rbx中保存的真实返回地址
+const GADGET: [u8; 2] = [0xFF, 0xE3];
+
+/// 依次查找gadget的系统模块
+const GADGET_MODULES: [u32; 3] = [
+    resolve::hash("kernelbase.dll"),
+    resolve::hash("kernel32.dll"),
+    resolve::hash("ntdll.dll"),
+];
+
+/// 最多支持的参数个数
+pub const MAX_ARGS: usize = 6;
+
+/// 是否通过gadget调用敏感API，默认关闭
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 开启或者关闭返回地址伪造
+///
+/// 开启后`nt_query`等模块调用NtQueryInformationProcess时，
+/// 函数看到的返回地址位于系统模块中，而不是本库的代码中
+///
+/// # 注意
+///
+/// 与CET影子栈(硬件强制栈保护)不兼容：被调用函数返回到gadget时与影子栈中的返回地址不一致，
+/// 进程会因`#CP`异常被终止。当前进程启用了影子栈时`call`忽略该开关，直接调用函数
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// 是否开启了返回地址伪造
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 当前进程是否启用了CET用户态影子栈，启用时不能伪造返回地址
+pub fn is_shadow_stack_enabled() -> bool {
+    static SHADOW_STACK: OnceLock<bool> = OnceLock::new();
+    *SHADOW_STACK.get_or_init(|| {
+        let mut policy = PROCESS_MITIGATION_USER_SHADOW_STACK_POLICY::default();
+        let queried = unsafe {
+            GetProcessMitigationPolicy(
+                GetCurrentProcess(),
+                ProcessUserShadowStackPolicy,
+                &mut policy as *mut _ as *mut c_void,
+                size_of::<PROCESS_MITIGATION_USER_SHADOW_STACK_POLICY>(),
+            )
+        };
+        // EnableUserShadowStack是第0位；旧系统不支持该策略时查询失败，视为没有启用
+        let enabled = queried.is_ok() && unsafe { policy.Anonymous.Flags } & 1 != 0;
+        if enabled {
+            warn!("user shadow stack is enabled, return address spoofing is disabled");
+        }
+        enabled
+    })
+}
+
+/// 在系统模块的可执行节中查找`jmp rbx`
+///
+/// # 返回值
+///
+/// - `Some(address)`: gadget地址
+/// - `None`: 所有候选模块中都没有找到
+pub fn find_gadget() -> Option<usize> {
+    for module in GADGET_MODULES {
+        let Some(hmodule) = resolve::find_module(module) else {
+            continue;
+        };
+        let Ok(image) = PeImage::from_module(hmodule) else {
+            continue;
+        };
+
+        for section in image.sections() {
+            if section.Characteristics.0 & IMAGE_SCN_MEM_EXECUTE.0 == 0 {
+                continue;
+            }
+            let size = unsafe { section.Misc.VirtualSize } as usize;
+            let Some(code) = image.bytes(section.VirtualAddress as usize, size) else {
+                continue;
+            };
+            if let Some(offset) = scan::find_bytes(code, &GADGET) {
+                let address = code.as_ptr() as usize + offset;
+                debug!("spoof gadget ==> {:#x}", address);
+                return Some(address);
+            }
+        }
+    }
+
+    warn!("spoof gadget not found");
+    None
+}
+
+/// 缓存的gadget地址，首次使用时查找
+pub fn gadget() -> Option<usize> {
+    static GADGET_ADDRESS: OnceLock<Option<usize>> = OnceLock::new();
+    *GADGET_ADDRESS.get_or_init(find_gadget)
+}
+
+/// 调用函数，开启返回地址伪造且找到gadget时，被调用函数看到的返回地址是系统模块中的gadget
+///
+/// 在API入口下断点并检查调用者的分析流程，只能看到调用来自kernelbase/kernel32/ntdll
+///
+/// # 参数
+///
+/// - `function`: 函数地址，例如`resolve!`的结果
+/// - `args`: 参数，最多`MAX_ARGS`个
+///
+/// # 返回值
+///
+/// - 函数的返回值(rax)
+///
+/// # Safety
+///
+/// `function`必须是x64调用约定的函数，参数个数与类型必须与函数定义一致
+///
+/// # 注意
+///
+/// - 被调用函数中抛出的异常无法经过gadget展开回调用方，只应该用于不会抛出异常的API
+/// - 启用了CET影子栈时不经过gadget，见`set_enabled`
+///
+/// # 示例
+///
+/// ```ignore
+/// let address = resolve!("kernel32.dll", "CreateToolhelp32Snapshot").unwrap();
+/// let snapshot = unsafe { spoof::call(address, &[TH32CS_SNAPPROCESS.0 as usize, 0]) };
+/// ```
+pub unsafe fn call(function: usize, args: &[usize]) -> usize {
+    assert!(args.len() <= MAX_ARGS, "too many arguments");
+    let mut padded = [0usize; MAX_ARGS];
+    padded[..args.len()].copy_from_slice(args);
+
+    if is_enabled() {
+        if let Some(result) = call_spoofed(function, &padded) {
+            return result;
+        }
+    }
+
+    // x64调用约定由调用方清理栈，多传的参数不影响被调用函数
+    let function: unsafe extern "system" fn(usize, usize, usize, usize, usize, usize) -> usize =
+        transmute(function);
+    function(
+        padded[0], padded[1], padded[2], padded[3], padded[4], padded[5],
+    )
+}
+
+/// 不论`set_enabled`的开关，经由gadget调用函数
+///
+/// # 返回值
+///
+/// - `Some(result)`: 函数的返回值(rax)
+/// - `None`: 没有找到gadget或者启用了CET影子栈，函数没有被调用
+///
+/// # Safety
+///
+/// 同`call`
+pub unsafe fn call_spoofed(function: usize, args: &[usize]) -> Option<usize> {
+    assert!(args.len() <= MAX_ARGS, "too many arguments");
+    if is_shadow_stack_enabled() {
+        return None;
+    }
+    let gadget = gadget()?;
+
+    let mut padded = [0usize; MAX_ARGS];
+    padded[..args.len()].copy_from_slice(args);
+    Some(spoofed(function, gadget, padded))
+}
+
+/// 以gadget作为返回地址跳转到函数
+///
+/// rbx与r12/r13是非易失寄存器，被调用函数返回前会恢复原值：
+/// rbx保存真实的返回地址，r12/r13保存调用前的栈指针与rbx
+#[inline(never)]
+unsafe fn spoofed(function: usize, gadget: usize, args: [usize; MAX_ARGS]) -> usize {
+    let result: usize;
+    asm!(
+        "mov r12, rsp",
+        "mov r13, rbx",
+        "and rsp, -16",
+        // 返回地址之后是32字节影子空间，第5、6个参数位于影子空间之后
+        "sub rsp, 0x40",
+        "mov [rsp + 0x20], {arg5}",
+        "mov [rsp + 0x28], {arg6}",
+        "lea rbx, [rip + 2f]",
+        "push {gadget}",
+        "jmp {function}",
+        "2:",
+        "mov rsp, r12",
+        "mov rbx, r13",
+        function = in(reg) function,
+        gadget = in(reg) gadget,
+        arg5 = in(reg) args[4],
+        arg6 = in(reg) args[5],
+        in("rcx") args[0],
+        in("rdx") args[1],
+        in("r8") args[2],
+        in("r9") args[3],
+        out("r12") _,
+        out("r13") _,
+        lateout("rax") result,
+        clobber_abi("system"),
+    );
+    result
+}
@@ -0,0 +1,227 @@
+use crate::logging::{debug, warn};
+use crate::{
+    hook::get_module,
+    obf,
+    obfstr::{Decrypted, ObfStr},
+    pe::PeImage,
+    resolve,
+    wow64::{ProcessArch, SyscallPath},
+};
+use anyhow::{Error, Result};
+#[cfg(target_arch = "x86_64")]
+use std::arch::asm;
+use std::collections::HashMap;
+#[cfg(target_arch = "x86_64")]
+use windows::Win32::Foundation::NTSTATUS;
+
+/// 本库依赖的Nt函数
+pub const CRATE_SYSCALLS: [ObfStr; 10] = [
+    obf!("NtQueryInformationProcess"),
+    obf!("NtQuerySystemInformation"),
+    obf!("NtSetInformationThread"),
+    obf!("NtCreateThreadEx"),
+    obf!("NtGetContextThread"),
+    obf!("NtSetContextThread"),
+    obf!("NtOpenSection"),
+    obf!("NtMapViewOfSection"),
+    obf!("NtUnmapViewOfSection"),
+    obf!("NtClose"),
+];
+
+/// x64 syscall存根开头：`mov r10, rcx; mov eax, SSN`
+#[cfg(target_pointer_width = "64")]
+const STUB_PREFIX: &[u8] = &[0x4c, 0x8b, 0xd1, 0xb8];
+/// x86 syscall存根开头：`mov eax, SSN`
+#[cfg(target_pointer_width = "32")]
+const STUB_PREFIX: &[u8] = &[0xb8];
+
+/// x64 SSN之后的部分
+///
+/// - Windows 7: `syscall; ret`
+/// - Windows 10及以上: `test byte ptr [7FFE0308h], 1; jne +3; syscall; ret`
+#[cfg(target_pointer_width = "64")]
+const STUB_SUFFIXES: [&[u8]; 2] = [
+    &[0x0f, 0x05, 0xc3],
+    &[
+        0xf6, 0x04, 0x25, 0x08, 0x03, 0xfe, 0x7f, 0x01, 0x75, 0x03, 0x0f, 0x05, 0xc3,
+    ],
+];
+/// x86 SSN之后为`mov edx, imm32`，随后通过KiFastSystemCall或WOW64跳转进入内核
+#[cfg(target_pointer_width = "32")]
+const STUB_SUFFIXES: [&[u8]; 1] = [&[0xba]];
+
+/// 异常的syscall存根
+///
+/// - `function`: 函数名
+/// - `address`: 存根地址
+/// - `ssn`: 存根中的系统调用号，存根格式异常时为None
+/// - `expected_ssn`: 按照导出地址顺序推算的系统调用号
+#[derive(Debug, Clone, PartialEq)]
+pub struct StubAnomaly {
+    pub function: String,
+    pub address: usize,
+    pub ssn: Option<u32>,
+    pub expected_ssn: Option<u32>,
+}
+
+/// 解析syscall存根，返回其中的系统调用号
+///
+/// # 返回值
+///
+/// - `Some(ssn)`: 存根格式正确
+/// - `None`: 存根被修改(例如开头被改写为跳转)
+pub fn parse_stub(code: &[u8]) -> Option<u32> {
+    let rest = code.strip_prefix(STUB_PREFIX)?;
+    let ssn = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+    let rest = &rest[4..];
+    STUB_SUFFIXES
+        .iter()
+        .any(|suffix| rest.starts_with(suffix))
+        .then_some(ssn)
+}
+
+/// 按照导出地址推算所有系统调用号
+///
+/// ntdll中的syscall存根按照系统调用号顺序排列，
+/// 将所有`Zw`开头的导出按地址排序后，序号即为系统调用号。
+/// 这个顺序只取决于导出表，存根本身被hook也不受影响
+///
+/// # 返回值
+///
+/// 函数名(`Nt`前缀)的哈希(`resolve::hash`)到系统调用号的映射
+pub fn expected_ssns(ntdll: &PeImage) -> HashMap<u32, u32> {
+    let mut stubs: Vec<(u32, u32)> = ntdll
+        .exports()
+        .into_iter()
+        .filter_map(|export| {
+            let name = export.name?;
+            let suffix = name.strip_prefix("Zw")?;
+            (!export.forwarded).then(|| (export.rva, resolve::hash(&format!("Nt{}", suffix))))
+        })
+        .collect();
+    stubs.sort();
+    stubs.dedup_by_key(|(rva, _)| *rva);
+
+    stubs
+        .into_iter()
+        .enumerate()
+        .map(|(ssn, (_, name))| (name, ssn as u32))
+        .collect()
+}
+
+/// 检查指定Nt函数的syscall存根
+///
+/// 存根开头必须是`mov r10, rcx; mov eax, SSN`，随后是`syscall; ret`，
+/// 且SSN与按导出地址推算的值一致(仅x64)。存根被改写说明存在hook层，
+/// 经过这些函数的其他检测结果都可能被伪造
+///
+/// # 参数
+///
+/// - `functions`: Nt函数名列表
+///
+/// # 返回值
+///
+/// - `Err`: ntdll未加载
+/// - `Ok(anomalies)`: 异常的存根，为空则所有存根正常
+///
+/// # 示例
+///
+/// ```ignore
+/// for anomaly in verify_stubs(&CRATE_SYSCALLS).unwrap() {
+///     println!("{} stub modified", anomaly.function);
+/// }
+/// ```
+pub fn verify_stubs(functions: &[&str]) -> Result<Vec<StubAnomaly>> {
+    let hmodule = get_module("ntdll.dll")?;
+    let ntdll = PeImage::from_module(hmodule)?;
+    let expected = expected_ssns(&ntdll);
+
+    let mut anomalies: Vec<StubAnomaly> = Vec::new();
+    for function in functions {
+        let Some(address) = resolve::find_export(hmodule, resolve::hash(function)) else {
+            warn!("{} not exported by ntdll", function);
+            continue;
+        };
+        let Some(code) = ntdll.bytes(address - ntdll.base(), 32) else {
+            continue;
+        };
+
+        let ssn = parse_stub(code);
+        let expected_ssn = expected.get(&resolve::hash(function)).copied();
+        debug!(
+            "syscall stub ==> {}; ssn: {:x?}; expected: {:x?}",
+            function, ssn, expected_ssn
+        );
+        // 经由KiFastSystemCall或Wow64Transition的存根，SSN编码在不同版本中不一致，只检查存根格式
+        let plausible =
+            ProcessArch::current().syscall_path() != SyscallPath::Direct || ssn == expected_ssn;
+        if ssn.is_some() && plausible {
+            continue;
+        }
+
+        debug!("{} stub modified ==> {:02x?}", function, &code[..16]);
+        anomalies.push(StubAnomaly {
+            function: function.to_string(),
+            address,
+            ssn,
+            expected_ssn,
+        });
+    }
+
+    Ok(anomalies)
+}
+
+/// 检查本库依赖的所有Nt函数
+pub fn verify_crate_stubs() -> Result<Vec<StubAnomaly>> {
+    let names: Vec<Decrypted> = CRATE_SYSCALLS.iter().map(ObfStr::decrypt).collect();
+    verify_stubs(&names.iter().map(Decrypted::as_str).collect::<Vec<&str>>())
+}
+
+/// 按照导出地址推算Nt函数的系统调用号，不读取可能被hook的存根
+///
+/// # 参数
+///
+/// - `function`: Nt函数名的哈希，应当用常量求值，例如`const NT_CLOSE: u32 = resolve::hash("NtClose")`
+pub fn syscall_number(function: u32) -> Result<u32> {
+    let ntdll = PeImage::from_module(get_module("ntdll.dll")?)?;
+    expected_ssns(&ntdll)
+        .get(&function)
+        .copied()
+        .ok_or_else(|| Error::msg(format!("{:#x} not exported by ntdll", function)))
+}
+
+/// 直接执行syscall指令调用单参数的Nt函数，不经过ntdll中的存根
+///
+/// 通过`call`进入一个本地存根，栈布局与ntdll存根一致：内核在返回用户态时抛出异常(例如调试状态下关闭无效句柄)，
+/// 经由`KiRaiseUserExceptionDispatcher`处理后仍然返回到存根的调用方
+///
+/// # 参数
+///
+/// - `ssn`: 系统调用号
+/// - `arg`: 第一个参数
+///
+/// # Safety
+///
+/// 参数必须与系统调用的定义一致
+#[cfg(target_arch = "x86_64")]
+pub unsafe fn syscall1(ssn: u32, arg: usize) -> NTSTATUS {
+    let status: usize;
+    asm!(
+        "mov r12, rsp",
+        "and rsp, -16",
+        "sub rsp, 0x20",
+        "call 2f",
+        "mov rsp, r12",
+        "jmp 3f",
+        "2:",
+        "mov r10, rcx",
+        "syscall",
+        "ret",
+        "3:",
+        inlateout("rax") ssn as usize => status,
+        inlateout("rcx") arg => _,
+        out("r12") _,
+        clobber_abi("system"),
+    );
+    NTSTATUS(status as i32)
+}
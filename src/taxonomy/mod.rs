@@ -0,0 +1,135 @@
+use crate::json;
+use std::fmt;
+
+/// 检测技术的分类标识，用于威胁情报与SOC工具关联检测结果
+///
+/// - `Attack`: MITRE ATT&CK技术编号，例如`T1622`
+/// - `AntiDebug`: Check Point Anti-Debug Tricks(anti-debug.checkpoint.com)的分类，值为页面名称
+/// - `Evasion`: Check Point Evasion Techniques(evasions.checkpoint.com)的分类，值为页面名称
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum Tag {
+    Attack(&'static str),
+    AntiDebug(&'static str),
+    Evasion(&'static str),
+}
+
+impl Tag {
+    /// 分类体系名称，用于序列化
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            Tag::Attack(_) => "mitre-attack",
+            Tag::AntiDebug(_) => "checkpoint-anti-debug",
+            Tag::Evasion(_) => "checkpoint-evasions",
+        }
+    }
+
+    /// 分类体系中的标识
+    pub fn id(&self) -> &'static str {
+        match self {
+            Tag::Attack(id) | Tag::AntiDebug(id) | Tag::Evasion(id) => id,
+        }
+    }
+
+    /// 序列化为JSON对象：`{"scheme":...,"id":...}`
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"scheme":{},"id":{}}}"#,
+            json::quote(self.scheme()),
+            json::quote(self.id())
+        )
+    }
+}
+
+impl fmt::Display for Tag {
+    /// `scheme:id`，例如`mitre-attack:T1622`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.scheme(), self.id())
+    }
+}
+
+/// 标签列表序列化为JSON数组
+pub fn tags_to_json(tags: &[Tag]) -> String {
+    let tags: Vec<String> = tags.iter().map(Tag::to_json).collect();
+    format!("[{}]", tags.join(","))
+}
+
+/// ATT&CK T1622 Debugger Evasion
+pub const DEBUGGER_EVASION: Tag = Tag::Attack("T1622");
+
+/// ATT&CK T1497.001 Virtualization/Sandbox Evasion: System Checks
+pub const SYSTEM_CHECKS: Tag = Tag::Attack("T1497.001");
+
+/// ATT&CK T1497.002 Virtualization/Sandbox Evasion: User Activity Based Checks
+pub const USER_ACTIVITY_CHECKS: Tag = Tag::Attack("T1497.002");
+
+/// ATT&CK T1497.003 Virtualization/Sandbox Evasion: Time Based Evasion
+pub const TIME_BASED_EVASION: Tag = Tag::Attack("T1497.003");
+
+/// ATT&CK T1055 Process Injection
+pub const PROCESS_INJECTION: Tag = Tag::Attack("T1055");
+
+/// ATT&CK T1574.006 Hijack Execution Flow: Dynamic Linker Hijacking
+pub const DYNAMIC_LINKER_HIJACKING: Tag = Tag::Attack("T1574.006");
+
+/// ATT&CK T1562.001 Impair Defenses: Disable or Modify Tools，保护代码被补丁或者hook
+pub const IMPAIR_DEFENSES: Tag = Tag::Attack("T1562.001");
+
+/// Anti-Debug: Debug Flags
+pub const DEBUG_FLAGS: Tag = Tag::AntiDebug("debug-flags");
+
+/// Anti-Debug: Object Handles
+pub const OBJECT_HANDLES: Tag = Tag::AntiDebug("object-handles");
+
+/// Anti-Debug: Exceptions
+pub const EXCEPTIONS: Tag = Tag::AntiDebug("exceptions");
+
+/// Anti-Debug: Timing
+pub const TIMING: Tag = Tag::AntiDebug("timing");
+
+/// Anti-Debug: Process Memory
+pub const PROCESS_MEMORY: Tag = Tag::AntiDebug("process-memory");
+
+/// Anti-Debug: Assembly instructions
+pub const ASSEMBLY: Tag = Tag::AntiDebug("assembly");
+
+/// Anti-Debug: Direct debugger interaction
+pub const INTERACTIVE: Tag = Tag::AntiDebug("interactive");
+
+/// Anti-Debug: Misc
+pub const MISC: Tag = Tag::AntiDebug("misc");
+
+/// Evasions: Filesystem
+pub const EVASION_FILESYSTEM: Tag = Tag::Evasion("filesystem");
+
+/// Evasions: Registry
+pub const EVASION_REGISTRY: Tag = Tag::Evasion("registry");
+
+/// Evasions: Generic OS queries
+pub const EVASION_GENERIC_OS_QUERIES: Tag = Tag::Evasion("generic-os-queries");
+
+/// Evasions: UI artifacts
+pub const EVASION_UI_ARTIFACTS: Tag = Tag::Evasion("ui-artifacts");
+
+/// Evasions: OS features
+pub const EVASION_OS_FEATURES: Tag = Tag::Evasion("os-features");
+
+/// Evasions: Processes
+pub const EVASION_PROCESSES: Tag = Tag::Evasion("processes");
+
+/// Evasions: Network
+pub const EVASION_NETWORK: Tag = Tag::Evasion("network");
+
+/// Evasions: CPU
+pub const EVASION_CPU: Tag = Tag::Evasion("cpu");
+
+/// Evasions: Hardware
+pub const EVASION_HARDWARE: Tag = Tag::Evasion("hardware");
+
+/// Evasions: Firmware tables
+pub const EVASION_FIRMWARE_TABLES: Tag = Tag::Evasion("firmware-tables");
+
+/// Evasions: Timing
+pub const EVASION_TIMING: Tag = Tag::Evasion("timing");
+
+/// Evasions: WMI
+pub const EVASION_WMI: Tag = Tag::Evasion("wmi");
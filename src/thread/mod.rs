@@ -1,22 +1,20 @@
+use crate::logging::{debug, warn};
+use crate::{capability, handle_watch::HandleWatch, obf, resolve, util};
 use anyhow::{Error, Result};
-use log::{debug, warn};
 use std::{
     ffi::c_void,
     ptr::{null, null_mut},
 };
 use windows::{
-    core::{s, w},
     Wdk::System::{
-        SystemInformation::{NtQuerySystemInformation, SYSTEM_INFORMATION_CLASS},
-        Threading::{ThreadHideFromDebugger, ZwSetInformationThread},
+        SystemInformation::SYSTEM_INFORMATION_CLASS,
+        Threading::{ThreadHideFromDebugger, THREADINFOCLASS},
     },
     Win32::{
         Foundation::{
-            CloseHandle, HANDLE, HMODULE, NTSTATUS, STATUS_INFO_LENGTH_MISMATCH,
-            STATUS_SUCCESS,
+            CloseHandle, HANDLE, NTSTATUS, STATUS_SUCCESS,
         },
         System::{
-            LibraryLoader::{GetModuleHandleW, GetProcAddress},
             Threading::{
                 CreateThread, GetCurrentProcessId, GetCurrentThread, SetThreadPriority,
                 WaitForSingleObject, INFINITE, LPTHREAD_START_ROUTINE, THREAD_ALL_ACCESS,
@@ -40,6 +38,10 @@ type NtCreateThreadEx = unsafe extern "system" fn(
     *mut c_void,               // attribute list
 ) -> NTSTATUS;
 
+/// ZwSetInformationThread的函数签名，通过`resolve!`按哈希获取，不出现在导入表中
+type ZwSetInformationThread =
+    unsafe extern "system" fn(HANDLE, THREADINFOCLASS, *const c_void, u32) -> NTSTATUS;
+
 pub struct DisableDebug {}
 
 impl DisableDebug {
@@ -64,15 +66,16 @@ impl DisableDebug {
         func: LPTHREAD_START_ROUTINE,
         func_argv: Option<*mut c_void>,
     ) -> Result<HANDLE> {
-        let ntdll: HMODULE = unsafe { GetModuleHandleW(w!("ntdll.dll")) }?;
-        let nt_create_thread_ex_warp = unsafe { GetProcAddress(ntdll, s!("NtCreateThreadEx")) };
+        let nt_create_thread_ex_warp = resolve!("ntdll.dll", "NtCreateThreadEx");
         if nt_create_thread_ex_warp.is_none() {
             warn!("Get NtCreateThreadEx func address failed");
-            return Err(Error::msg("Get NtCreateThreadEx func address failed"));
+            return Err(Error::msg(
+                obf!("Get NtCreateThreadEx func address failed").decrypt(),
+            ));
         }
 
         let nt_create_thread_ex: NtCreateThreadEx =
-            unsafe { std::mem::transmute_copy(&nt_create_thread_ex_warp.unwrap()) };
+            unsafe { resolve::function(nt_create_thread_ex_warp.unwrap()) };
         let mut hthread: HANDLE = Default::default();
         let status = unsafe {
             nt_create_thread_ex(
@@ -94,7 +97,7 @@ impl DisableDebug {
 
         if hthread.is_invalid() {
             warn!("NtCreateThreadEx failed");
-            return Err(Error::msg("NtCreateThreadEx failed"));
+            return Err(Error::msg(obf!("NtCreateThreadEx failed").decrypt()));
         }
 
         Ok(hthread)
@@ -108,12 +111,19 @@ impl DisableDebug {
 /// - `Err`: ZwSetInformationThread API调用失败
 /// - `Ok(())`: 成功禁止线程调试
 pub fn disable_thread_debug(hthread: HANDLE) -> Result<()> {
+    let Some(address) = resolve!("ntdll.dll", "ZwSetInformationThread") else {
+        warn!("Get ZwSetInformationThread func address failed");
+        return Err(Error::msg(
+            obf!("Get ZwSetInformationThread func address failed").decrypt(),
+        ));
+    };
+    let zw_set_information_thread: ZwSetInformationThread = unsafe { resolve::function(address) };
     let status: NTSTATUS =
-        unsafe { ZwSetInformationThread(hthread, ThreadHideFromDebugger, null(), 0) };
+        unsafe { zw_set_information_thread(hthread, ThreadHideFromDebugger, null(), 0) };
 
     if status != STATUS_SUCCESS {
         warn!("ZwSetInformationThread failed; error code: {:?}", status);
-        return Err(Error::msg("ZwSetInformationThread failed"));
+        return Err(Error::msg(obf!("ZwSetInformationThread failed").decrypt()));
     }
 
     Ok(())
@@ -130,26 +140,30 @@ pub fn disable_current_thread_debug() -> Result<()> {
     disable_thread_debug(hthread)
 }
 
+/// 扩展句柄表中的一个句柄，进程ID与句柄值不会被截断为16位，类型索引为16位
 #[repr(C)]
 #[derive(Debug, Clone)]
-pub struct SystemHandleTableEntryInfo {
-    pub unique_process_id: u16,
-    pub creator_back_trace_index: u16,
-    pub object_type_index: u8,
-    pub handle_attributes: u8,
-    pub handle_value: u16,
+pub struct SystemHandleTableEntryInfoEx {
     pub object: *mut c_void,
+    pub unique_process_id: usize,
+    pub handle_value: usize,
     pub granted_access: u32,
+    pub creator_back_trace_index: u16,
+    pub object_type_index: u16,
+    pub handle_attributes: u32,
+    pub reserved: u32,
 }
 
 #[repr(C)]
 #[derive(Debug, Clone)]
-pub struct SystemHandleInformation {
-    pub number_of_handles: u32,
-    pub handles: [SystemHandleTableEntryInfo; 1],
+pub struct SystemHandleInformationEx {
+    pub number_of_handles: usize,
+    pub reserved: usize,
+    pub handles: [SystemHandleTableEntryInfoEx; 1],
 }
 
-pub const SYSTEM_HANDLE_INFORMATION: SYSTEM_INFORMATION_CLASS = SYSTEM_INFORMATION_CLASS(16);
+pub const SYSTEM_EXTENDED_HANDLE_INFORMATION: SYSTEM_INFORMATION_CLASS =
+    SYSTEM_INFORMATION_CLASS(64);
 
 /// 诱饵线程。当调试器调试进程的时候就会获取所有线程的句柄设置一个空白/特殊的诱饵线程。
 /// 通过检查系统句柄表，来判断诱饵进程是否被外部进程(调试器)打开句柄
@@ -158,6 +172,7 @@ pub struct HoneyThread {
     pub thread_object: *mut c_void,
     pub process_uid: u32,
     pub thread_uid: u32,
+    watch: Option<HandleWatch>,
 }
 
 impl Default for HoneyThread {
@@ -167,6 +182,7 @@ impl Default for HoneyThread {
             thread_object: null_mut(),
             process_uid: Default::default(),
             thread_uid: Default::default(),
+            watch: None,
         }
     }
 }
@@ -180,6 +196,7 @@ impl Drop for HoneyThread {
         self.thread_object = null_mut();
         self.process_uid = 0;
         self.thread_uid = 0;
+        self.watch = None;
     }
 }
 
@@ -260,6 +277,8 @@ impl HoneyThread {
 
     /// 查询系统句柄表内容
     ///
+    /// 返回结果是复制出来的`Vec<u8>`，不保证对齐，需要按结构体访问时使用`util::with_system_information`
+    ///
     /// # 返回值
     ///
     /// - 如果查询成功返回系统句柄表所有内容，以Vec<u8>的形式
@@ -271,33 +290,7 @@ impl HoneyThread {
     /// let data = HoneyThread::query_system_information().except("NtQuerySystemInformation failed");
     /// ```
     pub fn query_system_information() -> Result<Vec<u8>> {
-        let mut handle_info_size: usize = 0x10000;
-        let mut handle_info_buffer: Vec<u8> = Vec::with_capacity(handle_info_size);
-        let mut status: NTSTATUS = STATUS_INFO_LENGTH_MISMATCH;
-        let mut return_length: u32 = 0;
-
-        while status == STATUS_INFO_LENGTH_MISMATCH {
-            handle_info_buffer.clear();
-            handle_info_size = return_length as usize;
-            handle_info_buffer.reserve(handle_info_size);
-            status = unsafe {
-                NtQuerySystemInformation(
-                    SYSTEM_HANDLE_INFORMATION,
-                    handle_info_buffer.as_mut_ptr() as *mut c_void,
-                    handle_info_size as u32,
-                    &mut return_length,
-                )
-            };
-        }
-
-        if status != STATUS_SUCCESS {
-            warn!("NtQuerySystemInformation failed! status: {:?}", status);
-            return Err(Error::msg("NtQuerySystemInformation failed!"));
-        }
-
-        debug!("NtQuerySystemInformation query system handle infomation successfully");
-
-        Ok(handle_info_buffer)
+        util::query_system_information(SYSTEM_EXTENDED_HANDLE_INFORMATION)
     }
 
     /// 设置空白诱饵线程在当前进程下
@@ -329,6 +322,7 @@ impl HoneyThread {
     ///
     /// - 通过遍历判断句柄值与进程号来找到对应句柄的内核地址值
     /// - 通过对比内核地址值与进程号来判断，改句柄是否被其他进程打开
+    /// - 重复调用时通过`HandleWatch`只处理两次检查之间句柄表的变化
     ///
     /// # 返回值
     ///
@@ -358,51 +352,27 @@ impl HoneyThread {
             ));
         }
 
-        // 获取系统句柄表信息
-        let system_information: Vec<u8> = Self::query_system_information()?;
-        let handle_info: *const SystemHandleInformation =
-            system_information.as_ptr() as *const SystemHandleInformation;
-        let handle_info_ref: &SystemHandleInformation = unsafe { &*handle_info };
-        let handles_ptr: *const SystemHandleTableEntryInfo = handle_info_ref.handles.as_ptr();
-
-        if self.thread_object.is_null() {
-            // 获取当前线程内核对象地址
-            for i in 0..handle_info_ref.number_of_handles {
-                let handle: *const SystemHandleTableEntryInfo =
-                    unsafe { handles_ptr.add(i as usize) };
-                let uid: u32 = unsafe { (*handle).unique_process_id }.into();
-                let handle_val: usize = unsafe { (*handle).handle_value }.into();
-
-                if uid == self.process_uid && handle_val == self.thread_handle.unwrap().0 as usize {
-                    self.thread_object = unsafe { (*handle).object };
-                    debug!("Get current thread object ==> {:p}", self.thread_object);
-                }
-            }
+        if !capability::get().system_class_available(SYSTEM_EXTENDED_HANDLE_INFORMATION) {
+            warn!("SystemExtendedHandleInformation is not available");
+            return Err(Error::msg(
+                "SystemExtendedHandleInformation is not available",
+            ));
         }
 
-        if self.thread_object.is_null() {
-            warn!("Could't found currnet thread object");
-            return Err(Error::msg("Could't found currnet thread object"));
+        // 第一次检查时解析线程内核对象地址，之后只比较句柄表的变化
+        if self.watch.is_none() {
+            let mut watch = HandleWatch::new(self.process_uid);
+            self.thread_object = watch.watch(self.thread_handle.unwrap())? as *mut c_void;
+            debug!("Get current thread object ==> {:p}", self.thread_object);
+            self.watch = Some(watch);
         }
 
-        // 对比所有内核地址，判断是否存在其他进程也获取了对应的线程内核对象
-        for i in 0..handle_info_ref.number_of_handles {
-            let handle: *const SystemHandleTableEntryInfo = unsafe { handles_ptr.add(i as usize) };
-            let uid: u32 = unsafe { (*handle).unique_process_id }.into();
-
-            if uid == self.process_uid {
-                continue;
-            }
-
-            let object_addr = unsafe { (*handle).object } as usize;
-            if self.thread_object as usize == object_addr {
-                debug!("Found attack process is debug ==> {:?}", unsafe {
-                    &*handle
-                });
-                return Ok(true);
-            }
+        let watch = self.watch.as_mut().unwrap();
+        for entry in watch.poll()?.added {
+            debug!("Found attack process is debug ==> {:?}", entry);
         }
 
-        Ok(false)
+        // 对比所有内核地址，判断是否存在其他进程也获取了对应的线程内核对象
+        Ok(watch.entries().next().is_some())
     }
 }
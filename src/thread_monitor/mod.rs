@@ -0,0 +1,417 @@
+use crate::logging::{debug, warn};
+use crate::{
+    engine::{Category, Severity, Verdict},
+    hook::{get_module, get_module_from_address, get_module_path, get_proc_address},
+    obf, resolve, sink,
+    thread::disable_current_thread_debug,
+    util,
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::BTreeSet,
+    ffi::c_void,
+    mem::size_of,
+    ptr::null_mut,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread::{self, JoinHandle, Scope, ScopedJoinHandle},
+    time::Duration,
+};
+use windows::{
+    Wdk::System::Threading::{ThreadQuerySetWin32StartAddress, THREADINFOCLASS},
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, NTSTATUS},
+        System::{
+            Diagnostics::ToolHelp::{
+                Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+            },
+            Threading::{
+                GetCurrentProcessId, GetCurrentThreadId, OpenThread, THREAD_QUERY_INFORMATION,
+            },
+        },
+    },
+};
+
+/// NtQueryInformationThread的函数签名，通过`resolve!`按哈希获取，不出现在导入表中
+type NtQueryInformationThreadFn =
+    unsafe extern "system" fn(HANDLE, THREADINFOCLASS, *mut c_void, u32, *mut u32) -> NTSTATUS;
+
+/// 通过`spawn_hidden`创建或者通过`register_current_thread`登记的线程
+static OWN_THREADS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// 已经创建但还没有登记的`spawn_hidden`线程数量
+static SPAWNING: AtomicUsize = AtomicUsize::new(0);
+
+/// 线程退出时取消登记，避免线程ID被复用后掩盖外部线程
+struct Registration(u32);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Ok(mut threads) = OWN_THREADS.lock() {
+            threads.remove(&self.0);
+        }
+    }
+}
+
+/// 登记当前线程为自己创建的线程，`ThreadMonitor`不再报告
+///
+/// 用于宿主程序在`ThreadMonitor::new`之后通过其他方式创建的线程
+pub fn register_current_thread() {
+    if let Ok(mut threads) = OWN_THREADS.lock() {
+        threads.insert(unsafe { GetCurrentThreadId() });
+    }
+}
+
+/// 即将创建一个通过`run_registered`执行的线程，在线程登记前`ThreadMonitor::poll`不报告新线程
+pub(crate) fn expect_spawn() {
+    SPAWNING.fetch_add(1, Ordering::SeqCst);
+}
+
+/// `expect_spawn`之后线程没有创建成功，或者在执行前被结束
+pub(crate) fn cancel_spawn() {
+    SPAWNING.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// 在新线程中登记并执行线程函数，线程退出时取消登记
+///
+/// 由创建线程的一方在创建前调用`expect_spawn`，登记完成后减少计数，
+/// 避免`ThreadMonitor::poll`在登记前看到该线程
+pub(crate) fn run_registered<T>(hide: bool, f: impl FnOnce() -> T) -> T {
+    let tid = unsafe { GetCurrentThreadId() };
+    if let Ok(mut threads) = OWN_THREADS.lock() {
+        threads.insert(tid);
+    }
+    let _registration = Registration(tid);
+    SPAWNING.fetch_sub(1, Ordering::SeqCst);
+
+    if hide {
+        if let Err(e) = disable_current_thread_debug() {
+            warn!("hide spawned thread failed; error: {:?}", e);
+        }
+    }
+    f()
+}
+
+/// 创建一个隐藏的线程：登记为自己创建的线程，并对调试器隐藏(`ThreadHideFromDebugger`)
+///
+/// # 参数
+///
+/// - `f`: 线程函数
+///
+/// # 返回值
+///
+/// - 线程句柄
+///
+/// # 示例
+///
+/// ```ignore
+/// let worker = spawn_hidden(|| do_work());
+/// worker.join().unwrap();
+/// ```
+pub fn spawn_hidden<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    expect_spawn();
+    thread::spawn(move || run_registered(true, f))
+}
+
+/// 创建一个登记为自己创建的线程，但不对调试器隐藏
+///
+/// 用于需要被调试器看到的线程，例如执行检测技术的工作线程与`response::harass_debugger`：
+/// 隐藏后线程中的调试输出与异常不再发送给调试器，依赖调试器处理异常的检测技术会失效
+pub fn spawn_registered<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    expect_spawn();
+    thread::spawn(move || run_registered(false, f))
+}
+
+/// 在`thread::scope`中创建登记为自己创建的线程，不对调试器隐藏，见`spawn_registered`
+pub fn spawn_scoped_registered<'scope, 'env, F, T>(
+    scope: &'scope Scope<'scope, 'env>,
+    f: F,
+) -> ScopedJoinHandle<'scope, T>
+where
+    F: FnOnce() -> T + Send + 'scope,
+    T: Send + 'scope,
+{
+    expect_spawn();
+    scope.spawn(move || run_registered(false, f))
+}
+
+/// 枚举指定进程的所有线程ID
+pub fn thread_ids(pid: u32) -> Result<Vec<u32>> {
+    let hsnapshot = util::create_snapshot(TH32CS_SNAPTHREAD, 0)?;
+    let mut entry = THREADENTRY32 {
+        dwSize: size_of::<THREADENTRY32>() as u32,
+        ..Default::default()
+    };
+
+    let mut tids: Vec<u32> = Vec::new();
+    let mut next = unsafe { Thread32First(hsnapshot, &mut entry) };
+    while next.is_ok() {
+        if entry.th32OwnerProcessID == pid {
+            tids.push(entry.th32ThreadID);
+        }
+        next = unsafe { Thread32Next(hsnapshot, &mut entry) };
+    }
+    let _ = unsafe { CloseHandle(hsnapshot) };
+
+    Ok(tids)
+}
+
+/// 读取线程的起始地址(Win32StartAddress)
+pub fn thread_start_address(tid: u32) -> Result<usize> {
+    let address = resolve!("ntdll.dll", "NtQueryInformationThread")
+        .ok_or_else(|| Error::msg(obf!("NtQueryInformationThread not found").decrypt()))?;
+    let query: NtQueryInformationThreadFn = unsafe { resolve::function(address) };
+
+    let hthread = unsafe { OpenThread(THREAD_QUERY_INFORMATION, false, tid) }?;
+    let mut start: usize = 0;
+    let status = unsafe {
+        query(
+            hthread,
+            ThreadQuerySetWin32StartAddress,
+            &mut start as *mut usize as *mut c_void,
+            size_of::<usize>() as u32,
+            null_mut(),
+        )
+    };
+    let _ = unsafe { CloseHandle(hthread) };
+
+    if status.is_err() {
+        warn!("NtQueryInformationThread failed; error code: {:?}", status);
+        return Err(Error::msg(
+            obf!("NtQueryInformationThread failed").decrypt(),
+        ));
+    }
+    Ok(start)
+}
+
+/// 外部线程起始地址的类型
+///
+/// - `BreakIn`: `DbgUiRemoteBreakin`，调试器附加或者中断时创建
+/// - `LoadLibrary`: `LoadLibrary*`，经典的DLL注入
+/// - `Unbacked`: 不属于任何已加载模块，通常是注入的shellcode
+/// - `Image`: 位于已加载模块中，例如系统线程池的工作线程
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum StartKind {
+    BreakIn,
+    LoadLibrary,
+    Unbacked,
+    Image,
+}
+
+/// 不是自己创建的新线程
+///
+/// - `tid`: 线程ID
+/// - `start_address`: 线程起始地址
+/// - `module`: 起始地址所在的模块文件名
+/// - `kind`: 起始地址类型
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignThread {
+    pub tid: u32,
+    pub start_address: usize,
+    pub module: Option<String>,
+    pub kind: StartKind,
+}
+
+impl ForeignThread {
+    /// 起始地址不在普通模块中：调试器中断线程、DLL注入或者shellcode
+    pub fn is_suspicious(&self) -> bool {
+        self.kind != StartKind::Image
+    }
+
+    /// 转换为命中结果：调试器中断线程属于调试器类，其他可疑线程属于代码篡改类
+    pub fn to_verdict(&self) -> Verdict {
+        let (category, severity) = match self.kind {
+            StartKind::BreakIn => (Category::Debugger, Severity::Confirmed),
+            _ => (Category::Tampering, Severity::Suspicious),
+        };
+        Verdict::observed(
+            "thread_monitor",
+            category,
+            severity,
+            format!(
+                "thread {} started at {:#x} ({:?}, {:?})",
+                self.tid, self.start_address, self.module, self.kind
+            ),
+        )
+    }
+}
+
+/// 按起始地址判断线程类型
+fn classify(start_address: usize) -> (Option<String>, StartKind) {
+    let module = get_module_from_address(start_address).and_then(|hmodule| {
+        let path = get_module_path(hmodule).ok()?;
+        Some(path.file_name()?.to_string_lossy().into_owned())
+    });
+
+    let export = |module: &str, function: &str| {
+        get_module(module)
+            .ok()
+            .and_then(|hmodule| get_proc_address(hmodule, function))
+    };
+    let kind = if export("ntdll.dll", "DbgUiRemoteBreakin") == Some(start_address) {
+        StartKind::BreakIn
+    } else if ["kernel32.dll", "kernelbase.dll"].iter().any(|module| {
+        [
+            "LoadLibraryA",
+            "LoadLibraryW",
+            "LoadLibraryExA",
+            "LoadLibraryExW",
+        ]
+        .iter()
+        .any(|function| export(module, function) == Some(start_address))
+    }) {
+        StartKind::LoadLibrary
+    } else if module.is_none() {
+        StartKind::Unbacked
+    } else {
+        StartKind::Image
+    };
+    (module, kind)
+}
+
+/// 监视当前进程中新创建的线程
+///
+/// 创建时记录已有的线程，之后每次`poll`报告新出现的、不是通过`spawn_hidden`创建
+/// (或者通过`register_current_thread`登记)的线程及其起始地址。
+/// 调试器附加与中断时会在目标进程中创建起始于`DbgUiRemoteBreakin`的线程，远程线程注入同样会产生新线程
+///
+/// # 示例
+///
+/// ```ignore
+/// let mut monitor = ThreadMonitor::new()?;
+/// for thread in monitor.poll()? {
+///     if thread.is_suspicious() {
+///         println!("foreign thread {} at {:#x}", thread.tid, thread.start_address);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ThreadMonitor {
+    pid: u32,
+    known: BTreeSet<u32>,
+    pending: BTreeSet<u32>,
+}
+
+impl ThreadMonitor {
+    /// 记录当前进程已有的线程
+    pub fn new() -> Result<Self> {
+        let pid = unsafe { GetCurrentProcessId() };
+        Ok(Self {
+            pid,
+            known: thread_ids(pid)?.into_iter().collect(),
+            pending: BTreeSet::new(),
+        })
+    }
+
+    /// 报告上次调用之后新出现的外部线程
+    ///
+    /// 有`spawn_hidden`线程正在启动时，尚未登记的新线程推迟到下一次调用再判断
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 枚举线程失败
+    /// - `Ok(threads)`: 新出现的外部线程，已经退出的线程不报告
+    pub fn poll(&mut self) -> Result<Vec<ForeignThread>> {
+        let current: BTreeSet<u32> = thread_ids(self.pid)?.into_iter().collect();
+        self.known.retain(|tid| current.contains(tid));
+        self.pending.retain(|tid| current.contains(tid));
+
+        let spawning = SPAWNING.load(Ordering::SeqCst) > 0;
+        let own = OWN_THREADS
+            .lock()
+            .map(|threads| threads.clone())
+            .unwrap_or_default();
+        let mut foreign: Vec<ForeignThread> = Vec::new();
+        for tid in current {
+            if self.known.contains(&tid) {
+                continue;
+            }
+            if own.contains(&tid) {
+                self.pending.remove(&tid);
+                self.known.insert(tid);
+                continue;
+            }
+            if spawning && self.pending.insert(tid) {
+                continue;
+            }
+            self.pending.remove(&tid);
+            self.known.insert(tid);
+
+            let start_address = match thread_start_address(tid) {
+                Ok(start_address) => start_address,
+                Err(e) => {
+                    debug!("thread {} start address unavailable; error: {:?}", tid, e);
+                    continue;
+                }
+            };
+            let (module, kind) = classify(start_address);
+            warn!(
+                "foreign thread ==> tid: {}; start: {:#x}; module: {:?}; kind: {:?}",
+                tid, start_address, module, kind
+            );
+            foreign.push(ForeignThread {
+                tid,
+                start_address,
+                module,
+                kind,
+            });
+        }
+
+        Ok(foreign)
+    }
+}
+
+/// 记录当前进程已有的线程，启动后台线程定期报告新出现的外部线程
+///
+/// 后台线程本身通过`spawn_hidden`创建，不会被报告
+///
+/// # 参数
+///
+/// - `interval`: 检查间隔
+/// - `on_thread`: 发现外部线程时的回调，可疑的线程(见`ForeignThread::is_suspicious`)
+///   同时通过`sink::publish`交给全局输出目标
+///
+/// # 返回值
+///
+/// - `Err`: 枚举线程失败
+/// - `Ok(handle)`: 后台线程句柄
+///
+/// # 示例
+///
+/// ```ignore
+/// spawn_monitor(Duration::from_millis(200), |thread| {
+///     if thread.kind == StartKind::BreakIn {
+///         std::process::exit(1);
+///     }
+/// })?;
+/// ```
+pub fn spawn_monitor<F>(interval: Duration, on_thread: F) -> Result<JoinHandle<()>>
+where
+    F: Fn(ForeignThread) + Send + 'static,
+{
+    let mut monitor = ThreadMonitor::new()?;
+    Ok(spawn_hidden(move || loop {
+        match monitor.poll() {
+            Ok(threads) => {
+                for thread in threads {
+                    if thread.is_suspicious() {
+                        sink::publish(thread.to_verdict());
+                    }
+                    on_thread(thread);
+                }
+            }
+            Err(e) => warn!("thread monitor poll failed; error: {:?}", e),
+        }
+        thread::sleep(interval);
+    }))
+}
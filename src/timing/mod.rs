@@ -0,0 +1,421 @@
+use crate::logging::{debug, warn};
+use crate::{
+    exception,
+    hook::{
+        compare_with_disk, detect_trampoline, get_module, get_module_from_address, get_proc_address,
+    },
+    imports::OutputDebugStringW,
+    obf, resolve,
+    util::KUSER_SHARED_DATA,
+};
+use anyhow::{Error, Result};
+use std::ptr;
+use windows::core::PCWSTR;
+use windows::Win32::{
+    Foundation::NTSTATUS,
+    System::{
+        Performance::{QueryPerformanceCounter, QueryPerformanceFrequency},
+        SystemInformation::GetTickCount64,
+        Threading::Sleep,
+    },
+};
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_rdtsc;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::_rdtsc;
+
+/// 沙箱与变速工具常用来加速或伪造时间的API
+pub const TIMING_APIS: [(&str, &str); 7] = [
+    ("kernel32.dll", "Sleep"),
+    ("kernelbase.dll", "Sleep"),
+    ("kernel32.dll", "GetTickCount64"),
+    ("kernelbase.dll", "GetTickCount64"),
+    ("kernel32.dll", "QueryPerformanceCounter"),
+    ("ntdll.dll", "RtlQueryPerformanceCounter"),
+    ("ntdll.dll", "NtDelayExecution"),
+];
+
+/// KUSER_SHARED_DATA中的字段偏移
+const TICK_COUNT_MULTIPLIER: usize = 0x4;
+const INTERRUPT_TIME: usize = 0x8;
+const TICK_COUNT: usize = 0x320;
+
+/// 读取KUSER_SHARED_DATA中的KSYSTEM_TIME
+///
+/// 内核先写High2Time，再写LowPart，最后写High1Time，
+/// High1Time与High2Time一致时读到的才是完整的值
+fn read_ksystem_time(offset: usize) -> u64 {
+    let base = KUSER_SHARED_DATA + offset;
+    loop {
+        let high1 = unsafe { ptr::read_volatile((base + 4) as *const u32) };
+        let low = unsafe { ptr::read_volatile(base as *const u32) };
+        let high2 = unsafe { ptr::read_volatile((base + 8) as *const u32) };
+        if high1 == high2 {
+            return (high1 as u64) << 32 | low as u64;
+        }
+    }
+}
+
+/// 从KUSER_SHARED_DATA读取系统启动以来的中断时间，单位为100纳秒
+///
+/// 该值由内核时钟中断更新，用户态hook无法修改
+pub fn interrupt_time() -> u64 {
+    read_ksystem_time(INTERRUPT_TIME)
+}
+
+/// 从KUSER_SHARED_DATA直接计算GetTickCount64的值，单位为毫秒
+pub fn tick_count() -> u64 {
+    let multiplier =
+        unsafe { ptr::read_volatile((KUSER_SHARED_DATA + TICK_COUNT_MULTIPLIER) as *const u32) };
+    ((read_ksystem_time(TICK_COUNT) as u128 * multiplier as u128) >> 24) as u64
+}
+
+/// 读取CPU时间戳计数器
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// 检查时间相关API是否被hook
+///
+/// 对比函数开头字节与磁盘文件，并检查入口处是否有跳到私有内存的跳板。
+/// `kernel32!QueryPerformanceCounter`等转发导出由`GetProcAddress`解析到其他模块中，
+/// 按函数地址实际所在的模块读取磁盘文件对比
+///
+/// # 返回值
+///
+/// - `Err`: 所有API都无法与磁盘文件对比
+/// - `Ok(evidence)`: 被hook的API列表，为空则未发现hook
+pub fn check_timing_api_hooks() -> Result<Vec<String>> {
+    let mut evidence: Vec<String> = Vec::new();
+    let mut checked = 0;
+    for (module, function) in TIMING_APIS {
+        let Ok(hmodule) = get_module(module) else {
+            continue;
+        };
+        let Some(address) = get_proc_address(hmodule, function) else {
+            continue;
+        };
+
+        // 导出地址不在任何模块中，说明导出表被改写到了私有内存
+        let Some(owner) = get_module_from_address(address) else {
+            debug!(
+                "{}!{} resolved outside modules ==> {:#x}",
+                module, function, address
+            );
+            evidence.push(format!("{}!{} redirected", module, function));
+            continue;
+        };
+
+        match compare_with_disk(owner, address) {
+            Ok(Some(_)) => {
+                evidence.push(format!("{}!{} patched", module, function));
+                continue;
+            }
+            Ok(None) => checked += 1,
+            Err(e) => warn!(
+                "compare {}!{} with disk failed; error: {:?}",
+                module, function, e
+            ),
+        }
+
+        if let Some((style, target)) = detect_trampoline(address) {
+            if get_module_from_address(target).is_none() {
+                debug!(
+                    "{}!{} redirected ==> {:?} {:#x}",
+                    module, function, style, target
+                );
+                evidence.push(format!("{}!{} redirected", module, function));
+            }
+        }
+    }
+
+    if checked == 0 && evidence.is_empty() {
+        return Err(Error::msg("no timing api could be compared with disk"));
+    }
+
+    Ok(evidence)
+}
+
+/// 对比时间API的结果与不可hook的时间源，检测时间虚拟化
+///
+/// 调用`Sleep(sleep_ms)`，以KUSER_SHARED_DATA中的中断时间为基准，检查：
+///
+/// - `Sleep`是否提前返回(沙箱跳过休眠)
+/// - `GetTickCount64`、`QueryPerformanceCounter`的增量是否与基准一致(变速)
+/// - `GetTickCount64`与KUSER_SHARED_DATA中的TickCount是否一致(偏移伪造)
+/// - RDTSC的增量是否合理(TSC被虚拟化或冻结)
+///
+/// # 参数
+///
+/// - `sleep_ms`: 休眠时长，越长越准确
+///
+/// # 返回值
+///
+/// - `Err`: QueryPerformanceCounter调用失败
+/// - `Ok(evidence)`: 发现的时间异常，为空则时间正常
+///
+/// # 示例
+///
+/// ```ignore
+/// for evidence in check_time_virtualization(50).unwrap() {
+///     println!("time virtualization: {}", evidence);
+/// }
+/// ```
+pub fn check_time_virtualization(sleep_ms: u32) -> Result<Vec<String>> {
+    let mut frequency: i64 = 0;
+    unsafe { QueryPerformanceFrequency(&mut frequency) }?;
+
+    let mut qpc_start: i64 = 0;
+    let interrupt_start = interrupt_time();
+    let tick_start = unsafe { GetTickCount64() };
+    unsafe { QueryPerformanceCounter(&mut qpc_start) }?;
+    let tsc_start = rdtsc();
+
+    unsafe { Sleep(sleep_ms) };
+
+    let mut qpc_end: i64 = 0;
+    let tsc_end = rdtsc();
+    unsafe { QueryPerformanceCounter(&mut qpc_end) }?;
+    let tick_end = unsafe { GetTickCount64() };
+    let interrupt_end = interrupt_time();
+
+    let reference_ms = interrupt_end.saturating_sub(interrupt_start) / 10_000;
+    let tick_ms = tick_end.saturating_sub(tick_start);
+    let qpc_ms = (qpc_end.saturating_sub(qpc_start).max(0) as u64)
+        .saturating_mul(1000)
+        .checked_div(frequency as u64)
+        .unwrap_or_default();
+    let tsc_delta = tsc_end.wrapping_sub(tsc_start);
+    debug!(
+        "time deltas ==> interrupt: {}ms; tick: {}ms; qpc: {}ms; tsc: {}",
+        reference_ms, tick_ms, qpc_ms, tsc_delta
+    );
+
+    // 时钟中断的精度约为15.6ms，允许一定的误差
+    let tolerance = (reference_ms / 2).max(32);
+    let mut evidence: Vec<String> = Vec::new();
+    if reference_ms < sleep_ms as u64 / 2 {
+        evidence.push(format!(
+            "Sleep({}) returned after {}ms",
+            sleep_ms, reference_ms
+        ));
+    }
+    if tick_ms.abs_diff(reference_ms) > tolerance {
+        evidence.push(format!(
+            "GetTickCount64 advanced {}ms in {}ms",
+            tick_ms, reference_ms
+        ));
+    }
+    if qpc_ms.abs_diff(reference_ms) > tolerance {
+        evidence.push(format!(
+            "QueryPerformanceCounter advanced {}ms in {}ms",
+            qpc_ms, reference_ms
+        ));
+    }
+
+    let offset = unsafe { GetTickCount64() }.abs_diff(tick_count());
+    if offset > 1000 {
+        evidence.push(format!(
+            "GetTickCount64 differs from KUSER_SHARED_DATA by {}ms",
+            offset
+        ));
+    }
+
+    // 现代CPU的TSC频率都在1GHz以上，低于100MHz说明TSC被虚拟化或者冻结
+    if reference_ms > 0 && tsc_delta / reference_ms < 100_000 {
+        evidence.push(format!(
+            "RDTSC advanced {} cycles in {}ms",
+            tsc_delta, reference_ms
+        ));
+    }
+
+    if !evidence.is_empty() {
+        warn!("time virtualization ==> {:?}", evidence);
+    }
+
+    Ok(evidence)
+}
+
+/// NtYieldExecution没有可切换的就绪线程时返回的状态
+const STATUS_NO_YIELD_PERFORMED: NTSTATUS = NTSTATUS(0x4000_0024);
+
+/// NtYieldExecution的函数签名，通过`resolve!`按哈希获取，不出现在导入表中
+type NtYieldExecutionFn = unsafe extern "system" fn() -> NTSTATUS;
+
+/// 每轮让出处理器前的休眠时长，让其他线程先用完各自的时间片；
+/// 实际休眠时间取决于系统计时器精度(1~15.6ms)
+const YIELD_SLEEP_MS: u32 = 1;
+
+/// `check_yield_starvation`的统计结果
+///
+/// - `rounds`: 调用NtYieldExecution的次数
+/// - `yielded`: 实际切换到其他线程(没有返回`STATUS_NO_YIELD_PERFORMED`)的次数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct YieldStats {
+    pub rounds: u32,
+    pub yielded: u32,
+}
+
+impl YieldStats {
+    /// 实际切换的比例
+    pub fn ratio(&self) -> f64 {
+        if self.rounds == 0 {
+            return 0.0;
+        }
+        self.yielded as f64 / self.rounds as f64
+    }
+
+    /// 超过半数的调用切换到了其他线程：调试器正在调度或者挂起、恢复本进程的线程
+    ///
+    /// 系统繁忙时也会有少量调用切换到其他线程，只有多数调用都切换时才认为异常
+    pub fn is_starved(&self) -> bool {
+        self.yielded * 2 > self.rounds
+    }
+}
+
+/// 反复休眠后调用NtYieldExecution，统计实际让出处理器的次数
+///
+/// 正常情况下休眠结束时当前处理器上很少有其他就绪线程，大多数调用返回`STATUS_NO_YIELD_PERFORMED`；
+/// 调试器单步、挂起与恢复线程时会不断产生就绪线程，实际切换的比例明显升高。
+/// 该方法受系统负载影响，只适合作为低权重的辅助判断
+///
+/// # 参数
+///
+/// - `rounds`: 调用次数，每次调用前休眠`YIELD_SLEEP_MS`毫秒
+///
+/// # 返回值
+///
+/// - `Err`: 没有找到NtYieldExecution
+/// - `Ok(stats)`: 统计结果，见`YieldStats::is_starved`
+pub fn check_yield_starvation(rounds: u32) -> Result<YieldStats> {
+    let address = resolve!("ntdll.dll", "NtYieldExecution")
+        .ok_or_else(|| Error::msg(obf!("NtYieldExecution not found").decrypt()))?;
+    let yield_execution: NtYieldExecutionFn = unsafe { resolve::function(address) };
+
+    let mut yielded = 0;
+    for _ in 0..rounds {
+        unsafe { Sleep(YIELD_SLEEP_MS) };
+        if unsafe { yield_execution() } != STATUS_NO_YIELD_PERFORMED {
+            yielded += 1;
+        }
+    }
+
+    let stats = YieldStats { rounds, yielded };
+    debug!("yield execution ==> {:?}", stats);
+    Ok(stats)
+}
+
+/// 测量一次陷阱标志单步异常从触发到处理完毕的CPU周期数
+///
+/// 异常先经过调试器再回到进程时，往返耗时比直接分发到VEH高出几个数量级
+///
+/// # 参数
+///
+/// - `rounds`: 测量次数，返回其中的最小值以排除调度干扰
+///
+/// # 返回值
+///
+/// - `Err`: VEH注册失败，或者单步异常没有送达
+/// - `Ok(cycles)`: 最小的往返周期数
+pub fn single_step_latency(rounds: u32) -> Result<u64> {
+    let mut best = u64::MAX;
+    for _ in 0..rounds.max(1) {
+        let start = rdtsc();
+        let caught = exception::trap_flag_step()?;
+        let cycles = rdtsc().wrapping_sub(start);
+        if caught.is_none() {
+            return Err(Error::msg("single step exception not delivered"));
+        }
+        best = best.min(cycles);
+    }
+
+    debug!("single step latency ==> {} cycles", best);
+    Ok(best)
+}
+
+/// 大负载消息的字符数，调试器需要读取并显示整条消息，耗时随长度增长
+const DEBUG_STRING_PAYLOAD: usize = 4096;
+
+/// OutputDebugStringW耗时的判定阈值(CPU周期)，没有调试器时一次调用只有几万周期
+const DEBUG_STRING_THRESHOLD: u64 = 500_000;
+
+/// `debug_string_latency`的统计结果
+///
+/// - `rounds`: 每种消息的采样次数
+/// - `short`: 短消息耗时的中位数(CPU周期)
+/// - `long`: 大负载消息耗时的中位数(CPU周期)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugStringLatency {
+    pub rounds: u32,
+    pub short: u64,
+    pub long: u64,
+}
+
+impl DebugStringLatency {
+    /// 大负载带来的额外耗时
+    pub fn shift(&self) -> u64 {
+        self.long.saturating_sub(self.short)
+    }
+
+    /// 消息被送达调试器：每次调用都要等待调试器处理调试事件，或者调试器按长度复制消息带来明显的额外耗时
+    pub fn is_delivered(&self) -> bool {
+        self.short > DEBUG_STRING_THRESHOLD || self.shift() > DEBUG_STRING_THRESHOLD
+    }
+}
+
+/// 多次采样的中位数
+pub(crate) fn median(samples: &mut [u64]) -> u64 {
+    samples.sort_unstable();
+    samples.get(samples.len() / 2).copied().unwrap_or_default()
+}
+
+/// 交替发送短消息与大负载消息，测量OutputDebugStringW的耗时
+///
+/// 没有调试器时OutputDebugStringW抛出的`DBG_PRINTEXCEPTION_WIDE_C`由自身的SEH处理，耗时只与字符转换有关；
+/// 消息被送达调试器时，每次调用都要等待调试器读取进程内存并继续执行，耗时整体升高并且随消息长度明显增长。
+/// 与依赖错误码的检测不同，隐藏调试器的工具很难让这种耗时差异消失；DebugView一类的监听工具同样会带来类似的差异
+///
+/// # 参数
+///
+/// - `rounds`: 每种消息的采样次数，取中位数以排除调度干扰
+///
+/// # 返回值
+///
+/// 统计结果，见`DebugStringLatency::is_delivered`
+///
+/// # 示例
+///
+/// ```ignore
+/// let latency = debug_string_latency(32);
+/// if latency.is_delivered() {
+///     println!("debug string delivered, shift: {} cycles", latency.shift());
+/// }
+/// ```
+pub fn debug_string_latency(rounds: u32) -> DebugStringLatency {
+    let rounds = rounds.max(1);
+    let message = |length: usize| -> Vec<u16> {
+        let mut message = vec![b'.' as u16; length + 1];
+        message[length] = 0;
+        message
+    };
+    let (short, long) = (message(1), message(DEBUG_STRING_PAYLOAD));
+
+    let mut short_samples: Vec<u64> = Vec::with_capacity(rounds as usize);
+    let mut long_samples: Vec<u64> = Vec::with_capacity(rounds as usize);
+    for _ in 0..rounds {
+        for (message, samples) in [(&short, &mut short_samples), (&long, &mut long_samples)] {
+            let start = rdtsc();
+            unsafe { OutputDebugStringW(PCWSTR(message.as_ptr())) };
+            samples.push(rdtsc().wrapping_sub(start));
+        }
+    }
+
+    let latency = DebugStringLatency {
+        rounds,
+        short: median(&mut short_samples),
+        long: median(&mut long_samples),
+    };
+    debug!("debug string latency ==> {:?}", latency);
+    latency
+}
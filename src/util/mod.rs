@@ -1,7 +1,62 @@
+#[cfg(windows)]
+use crate::logging::warn;
+#[cfg(windows)]
+use crate::{
+    cache,
+    imports::{CreateToolhelp32Snapshot, NtQuerySystemInformation, OpenProcess},
+    obf,
+};
+#[cfg(windows)]
+use anyhow::{Error, Result};
 use std::io::{self, Write};
+#[cfg(not(windows))]
+use std::thread;
+use std::thread::{JoinHandle, Scope, ScopedJoinHandle};
+#[cfg(windows)]
+use std::{cell::RefCell, ffi::c_void, mem::size_of, path::PathBuf};
+#[cfg(windows)]
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Wdk::System::SystemInformation::{SystemProcessInformation, SYSTEM_INFORMATION_CLASS},
+    Win32::{
+        Foundation::{
+            CloseHandle, ERROR_ACCESS_DENIED, ERROR_SUCCESS, HANDLE, INVALID_HANDLE_VALUE,
+            STATUS_BUFFER_TOO_SMALL, STATUS_INFO_LENGTH_MISMATCH,
+        },
+        Storage::FileSystem::{
+            CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+        },
+        System::{
+            Diagnostics::ToolHelp::CREATE_TOOLHELP_SNAPSHOT_FLAGS,
+            Registry::{RegCloseKey, RegOpenKeyExW, HKEY, KEY_READ},
+            Threading::{
+                QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+            WindowsProgramming::SYSTEM_PROCESS_INFORMATION,
+        },
+    },
+};
 
-pub trait BeingDebug{
-    fn is_being_debug(&self) -> bool; 
+/// KUSER_SHARED_DATA在所有Windows版本中固定映射的地址
+#[cfg(windows)]
+pub const KUSER_SHARED_DATA: usize = 0x7ffe_0000;
+
+/// NtQuerySystemInformation缓冲区的初始大小
+#[cfg(windows)]
+const SYSTEM_INFORMATION_INITIAL_SIZE: usize = 0x10000;
+
+/// 缓冲区不足时最多重试的次数，系统句柄表在两次调用之间可能继续增长
+#[cfg(windows)]
+const SYSTEM_INFORMATION_RETRIES: usize = 8;
+
+#[cfg(windows)]
+thread_local! {
+    /// 每个线程复用的NtQuerySystemInformation缓冲区，以u64分配保证结果中结构体的对齐
+    static SYSTEM_INFORMATION_BUFFER: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+pub trait BeingDebug {
+    fn is_being_debug(&self) -> bool;
 }
 
 pub fn pause() {
@@ -9,4 +64,320 @@ pub fn pause() {
     io::stdout().flush().unwrap();
     let mut input = String::new();
     io::stdin().read_line(&mut input).unwrap();
-}
\ No newline at end of file
+}
+
+/// 将字符串转换为以0结尾的UTF-16字符串，用于传给W结尾的Windows API
+pub fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(Some(0)).collect()
+}
+
+/// 判断注册表项是否存在
+///
+/// # 参数
+///
+/// - `root`: 根键，例如`HKEY_LOCAL_MACHINE`
+/// - `path`: 子键路径
+///
+/// # 返回值
+///
+/// - `true`: 注册表项存在且可以读取
+/// - `false`: 注册表项不存在或无法打开
+#[cfg(windows)]
+pub fn is_registry_key_exists(root: HKEY, path: &str) -> bool {
+    let wide = to_wide(path);
+    let mut hkey: HKEY = Default::default();
+    let status = unsafe { RegOpenKeyExW(root, PCWSTR(wide.as_ptr()), 0, KEY_READ, &mut hkey) };
+    if status != ERROR_SUCCESS {
+        return false;
+    }
+
+    let _ = unsafe { RegCloseKey(hkey) };
+    true
+}
+
+/// 判断设备对象是否存在
+///
+/// 以0访问权限打开设备，打开成功或者被拒绝访问都说明设备对象存在
+///
+/// # 参数
+///
+/// - `path`: 设备路径，例如`\\.\VBoxGuest`
+///
+/// # 返回值
+///
+/// - `true`: 设备对象存在
+/// - `false`: 设备对象不存在
+#[cfg(windows)]
+pub fn is_device_object_exists(path: &str) -> bool {
+    let wide = to_wide(path);
+    let result = unsafe {
+        CreateFileW(
+            PCWSTR(wide.as_ptr()),
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            None,
+        )
+    };
+
+    match result {
+        Ok(handle) => {
+            let _ = unsafe { CloseHandle(handle) };
+            true
+        }
+        Err(error) => error.code() == ERROR_ACCESS_DENIED.to_hresult(),
+    }
+}
+
+/// 获取进程的可执行文件完整路径
+///
+/// # 参数
+///
+/// - `pid`: 进程ID
+///
+/// # 返回值
+///
+/// - `Some(path)`: 可执行文件的Win32路径，例如`C:\Windows\System32\csrss.exe`
+/// - `None`: 进程不存在或者无法打开
+#[cfg(windows)]
+pub fn get_process_path(pid: u32) -> Option<PathBuf> {
+    let hprocess = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.ok()?;
+    let mut buffer = [0u16; 1024];
+    let mut size = buffer.len() as u32;
+    let result = unsafe {
+        QueryFullProcessImageNameW(
+            hprocess,
+            PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut size,
+        )
+    };
+    let _ = unsafe { CloseHandle(hprocess) };
+    result.ok()?;
+
+    let path = String::from_utf16_lossy(&buffer[..size as usize]);
+    Some(PathBuf::from(path))
+}
+
+/// 获取进程的可执行文件名
+///
+/// # 参数
+///
+/// - `pid`: 进程ID
+///
+/// # 返回值
+///
+/// - `Some(name)`: 可执行文件名，例如`csrss.exe`
+/// - `None`: 进程不存在或者无法打开
+#[cfg(windows)]
+pub fn get_process_name(pid: u32) -> Option<String> {
+    get_process_path(pid)?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// 在缓冲区中调用NtQuerySystemInformation，缓冲区不足时按返回的长度扩大后重试
+///
+/// 返回结果的有效字节数
+#[cfg(windows)]
+pub(crate) fn query_system_information_into(
+    class: SYSTEM_INFORMATION_CLASS,
+    buffer: &mut Vec<u64>,
+) -> Result<usize> {
+    let mut size = (buffer.len() * size_of::<u64>()).max(SYSTEM_INFORMATION_INITIAL_SIZE);
+    for _ in 0..SYSTEM_INFORMATION_RETRIES {
+        buffer.resize(size.div_ceil(size_of::<u64>()), 0);
+        let mut return_length: u32 = 0;
+        let status = unsafe {
+            NtQuerySystemInformation(
+                class,
+                buffer.as_mut_ptr() as *mut c_void,
+                (buffer.len() * size_of::<u64>()) as u32,
+                &mut return_length,
+            )
+        };
+
+        if status == STATUS_INFO_LENGTH_MISMATCH || status == STATUS_BUFFER_TOO_SMALL {
+            // 预留余量，两次调用之间新增的条目不至于再次失败
+            size = (return_length as usize + return_length as usize / 8).max(size * 2);
+            continue;
+        }
+        if status.is_err() {
+            warn!(
+                "NtQuerySystemInformation failed; class: {:?}; status: {:?}",
+                class, status
+            );
+            return Err(Error::msg(
+                obf!("NtQuerySystemInformation failed").decrypt(),
+            ));
+        }
+
+        return Ok((return_length as usize).min(buffer.len() * size_of::<u64>()));
+    }
+
+    Err(Error::msg(
+        obf!("NtQuerySystemInformation buffer keeps growing").decrypt(),
+    ))
+}
+
+/// 查询系统信息，并在当前线程复用的缓冲区中处理查询结果
+///
+/// 句柄表等查询结果可能有数MB，频繁检测时复用缓冲区可以避免每次重新分配。
+/// 嵌套调用时内层使用临时缓冲区
+///
+/// # 参数
+///
+/// - `class`: 信息类别，例如`SystemProcessInformation`
+/// - `f`: 处理查询结果的函数，参数中的数据按8字节对齐
+///
+/// # 返回值
+///
+/// - `Err`: NtQuerySystemInformation调用失败
+/// - `Ok(result)`: `f`的返回值
+///
+/// # 示例
+///
+/// ```ignore
+/// let count = with_system_information(SYSTEM_EXTENDED_HANDLE_INFORMATION, |data| {
+///     unsafe { (*(data.as_ptr() as *const SystemHandleInformationEx)).number_of_handles }
+/// })
+/// .unwrap();
+/// ```
+#[cfg(windows)]
+pub fn with_system_information<R, F: FnOnce(&[u8]) -> R>(
+    class: SYSTEM_INFORMATION_CLASS,
+    f: F,
+) -> Result<R> {
+    SYSTEM_INFORMATION_BUFFER.with(|pool| {
+        let mut local: Vec<u64> = Vec::new();
+        let mut pooled = pool.try_borrow_mut();
+        let buffer = match pooled.as_mut() {
+            Ok(buffer) => &mut **buffer,
+            Err(_) => &mut local,
+        };
+
+        let length = query_system_information_into(class, buffer)?;
+        let data = unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+        Ok(f(data))
+    })
+}
+
+/// 查询系统信息并复制一份结果
+///
+/// # 参数
+///
+/// - `class`: 信息类别
+///
+/// # 返回值
+///
+/// - `Err`: NtQuerySystemInformation调用失败
+/// - `Ok(data)`: 查询结果
+#[cfg(windows)]
+pub fn query_system_information(class: SYSTEM_INFORMATION_CLASS) -> Result<Vec<u8>> {
+    with_system_information(class, |data| data.to_vec())
+}
+
+/// 通过SystemProcessInformation枚举系统中的所有进程
+///
+/// 使用`cache`模块中该类别的缓存
+///
+/// # 返回值
+///
+/// - `Err`: NtQuerySystemInformation调用失败
+/// - `Ok(processes)`: `(进程ID, 进程名)`列表，System Idle Process的进程名为空
+#[cfg(windows)]
+pub fn enumerate_processes() -> Result<Vec<(u32, String)>> {
+    cache::with_system_information(SystemProcessInformation, |data| {
+        let mut processes: Vec<(u32, String)> = Vec::new();
+        let mut offset: usize = 0;
+        while offset + size_of::<SYSTEM_PROCESS_INFORMATION>() <= data.len() {
+            let process =
+                unsafe { &*(data.as_ptr().add(offset) as *const SYSTEM_PROCESS_INFORMATION) };
+            let name = match process.ImageName.Buffer.is_null() {
+                true => String::new(),
+                false => String::from_utf16_lossy(unsafe {
+                    std::slice::from_raw_parts(
+                        process.ImageName.Buffer.0,
+                        process.ImageName.Length as usize / 2,
+                    )
+                }),
+            };
+            processes.push((process.UniqueProcessId.0 as u32, name));
+
+            if process.NextEntryOffset == 0 {
+                break;
+            }
+            offset += process.NextEntryOffset as usize;
+        }
+        Ok(processes)
+    })
+}
+
+/// 创建进程或者线程快照，开启返回地址伪造时(x64)经由系统模块中的gadget调用
+///
+/// # 参数
+///
+/// - `flags`: 快照类型，例如`TH32CS_SNAPPROCESS`
+/// - `pid`: 进程ID，进程与线程快照忽略该参数
+///
+/// # 返回值
+///
+/// - `Err`: 没有找到导出函数或者创建快照失败
+/// - `Ok(handle)`: 快照句柄，使用完毕后需要调用`CloseHandle`关闭
+#[cfg(windows)]
+pub fn create_snapshot(flags: CREATE_TOOLHELP_SNAPSHOT_FLAGS, pid: u32) -> Result<HANDLE> {
+    #[cfg(target_arch = "x86_64")]
+    if crate::spoof::is_enabled() {
+        let Some(address) = crate::resolve!("kernel32.dll", "CreateToolhelp32Snapshot") else {
+            return Err(Error::msg(
+                obf!("CreateToolhelp32Snapshot not found").decrypt(),
+            ));
+        };
+        let handle = unsafe { crate::spoof::call(address, &[flags.0 as usize, pid as usize]) };
+        let handle = HANDLE(handle as *mut c_void);
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error().into());
+        }
+        return Ok(handle);
+    }
+
+    Ok(unsafe { CreateToolhelp32Snapshot(flags, pid) }?)
+}
+
+/// 创建本库的后台线程，Windows下通过`thread_monitor::spawn_registered`创建，
+/// `ThreadMonitor`不会把它报告为外部线程；线程不对调试器隐藏，检测技术可以在其中正常执行
+///
+/// # 参数
+///
+/// - `f`: 线程函数
+///
+/// # 返回值
+///
+/// - 线程句柄
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    #[cfg(windows)]
+    return crate::thread_monitor::spawn_registered(f);
+    #[cfg(not(windows))]
+    thread::spawn(f)
+}
+
+/// 在`thread::scope`中创建本库的线程，见`spawn`
+pub fn spawn_scoped<'scope, 'env, F, T>(
+    scope: &'scope Scope<'scope, 'env>,
+    f: F,
+) -> ScopedJoinHandle<'scope, T>
+where
+    F: FnOnce() -> T + Send + 'scope,
+    T: Send + 'scope,
+{
+    #[cfg(windows)]
+    return crate::thread_monitor::spawn_scoped_registered(scope, f);
+    #[cfg(not(windows))]
+    scope.spawn(f)
+}
@@ -0,0 +1,445 @@
+use crate::logging::{debug, warn};
+use crate::util::{is_device_object_exists, is_registry_key_exists};
+use crate::{obf, obfstr::ObfStr};
+use anyhow::{Error, Result};
+use windows::Win32::{
+    Foundation::{ERROR_BUFFER_OVERFLOW, ERROR_SUCCESS},
+    NetworkManagement::IpHelper::{
+        GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER,
+        GAA_FLAG_SKIP_MULTICAST, GAA_FLAG_SKIP_UNICAST, IP_ADAPTER_ADDRESSES_LH,
+    },
+    Networking::WinSock::AF_UNSPEC,
+    System::{
+        Registry::HKEY_LOCAL_MACHINE,
+        SystemInformation::{
+            EnumSystemFirmwareTables, GetSystemFirmwareTable, ACPI, FIRMWARE_TABLE_PROVIDER, RSMB,
+        },
+    },
+};
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__cpuid;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::__cpuid;
+
+/// CPUID识别到的虚拟化平台
+///
+/// - `VMware`: VMwareVMware
+/// - `HyperV`: Microsoft Hv
+/// - `Kvm`: KVMKVMKVM
+/// - `VirtualBox`: VBoxVBoxVBox
+/// - `Xen`: XenVMMXenVMM
+/// - `Parallels`: prl hyperv
+/// - `Bochs`: 没有CPUID厂商字符串，只会出现在固件表中
+/// - `Unknown`: 设置了hypervisor位但厂商字符串未知，保存原始字符串
+#[derive(PartialEq, Clone, Debug)]
+pub enum Hypervisor {
+    VMware,
+    HyperV,
+    Kvm,
+    VirtualBox,
+    Xen,
+    Parallels,
+    Bochs,
+    Unknown(String),
+}
+
+impl From<&str> for Hypervisor {
+    fn from(vendor: &str) -> Self {
+        match vendor.trim_end_matches('\0') {
+            "VMwareVMware" => Self::VMware,
+            "Microsoft Hv" => Self::HyperV,
+            "KVMKVMKVM" => Self::Kvm,
+            "VBoxVBoxVBox" => Self::VirtualBox,
+            "XenVMMXenVMM" => Self::Xen,
+            "prl hyperv  " | " lrpepyh vr" => Self::Parallels,
+            other => Self::Unknown(other.to_string()),
+        }
+    }
+}
+
+/// 读取CPUID leaf 1 ECX第31位(hypervisor present)
+///
+/// # 返回值
+///
+/// - `true`: 运行在虚拟机管理程序之上
+/// - `false`: 未设置hypervisor位
+pub fn is_hypervisor_present() -> bool {
+    let result = __cpuid(1);
+
+    debug!("CPUID leaf 1 ==> ecx: {:#x}", result.ecx);
+
+    result.ecx & (1 << 31) != 0
+}
+
+/// 读取CPUID leaf 0x40000000返回的虚拟化厂商字符串
+///
+/// 厂商字符串由EBX、ECX、EDX三个寄存器按顺序拼接而成
+pub fn get_hypervisor_vendor() -> String {
+    let result = __cpuid(0x4000_0000);
+    let mut vendor: Vec<u8> = Vec::with_capacity(12);
+    vendor.extend_from_slice(&result.ebx.to_le_bytes());
+    vendor.extend_from_slice(&result.ecx.to_le_bytes());
+    vendor.extend_from_slice(&result.edx.to_le_bytes());
+
+    let vendor = String::from_utf8_lossy(&vendor).into_owned();
+
+    debug!("CPUID leaf 0x40000000 ==> vendor: {:?}", vendor);
+
+    vendor
+}
+
+/// 通过CPUID检测当前是否运行在虚拟机中
+///
+/// # 返回值
+///
+/// - `Some(hypervisor)`: 识别到的虚拟化平台
+/// - `None`: 未设置hypervisor位
+///
+/// # 注意
+///
+/// 开启了VBS/Hyper-V的物理机同样会返回`Hypervisor::HyperV`
+///
+/// # 示例
+///
+/// ```ignore
+/// match check_cpuid_hypervisor() {
+///     Some(hypervisor) => println!("running under {:?}", hypervisor),
+///     None => println!("no hypervisor"),
+/// }
+/// ```
+pub fn check_cpuid_hypervisor() -> Option<Hypervisor> {
+    if !is_hypervisor_present() {
+        return None;
+    }
+
+    Some(Hypervisor::from(get_hypervisor_vendor().as_str()))
+}
+
+/// 虚拟化平台留下的痕迹
+///
+/// - `hypervisor`: 痕迹所属的虚拟化平台
+/// - `evidence`: 命中的注册表项、服务或设备对象
+#[derive(PartialEq, Clone, Debug)]
+pub struct VmArtifact {
+    pub hypervisor: Hypervisor,
+    pub evidence: String,
+}
+
+/// 虚拟机增强工具/集成服务留下的注册表项(HKLM下)
+const VM_REGISTRY_KEYS: [(Hypervisor, ObfStr); 6] = [
+    (
+        Hypervisor::VirtualBox,
+        obf!("SOFTWARE\\Oracle\\VirtualBox Guest Additions"),
+    ),
+    (Hypervisor::VirtualBox, obf!("HARDWARE\\ACPI\\DSDT\\VBOX__")),
+    (
+        Hypervisor::VMware,
+        obf!("SOFTWARE\\VMware, Inc.\\VMware Tools"),
+    ),
+    (
+        Hypervisor::HyperV,
+        obf!("SOFTWARE\\Microsoft\\Virtual Machine\\Guest\\Parameters"),
+    ),
+    (Hypervisor::Kvm, obf!("SOFTWARE\\QEMU Guest Agent")),
+    (Hypervisor::Kvm, obf!("SOFTWARE\\RedHat\\Virtio-Win")),
+];
+
+/// 虚拟机增强工具/集成服务安装的服务名
+const VM_SERVICES: [(Hypervisor, ObfStr); 8] = [
+    (Hypervisor::VirtualBox, obf!("VBoxGuest")),
+    (Hypervisor::VirtualBox, obf!("VBoxService")),
+    (Hypervisor::VirtualBox, obf!("VBoxSF")),
+    (Hypervisor::VMware, obf!("vmci")),
+    (Hypervisor::VMware, obf!("VMTools")),
+    (Hypervisor::VMware, obf!("vmhgfs")),
+    (Hypervisor::Kvm, obf!("QEMU-GA")),
+    (Hypervisor::Kvm, obf!("vioser")),
+];
+
+/// 虚拟机驱动创建的设备对象
+const VM_DEVICES: [(Hypervisor, ObfStr); 5] = [
+    (Hypervisor::VirtualBox, obf!("\\\\.\\VBoxGuest")),
+    (Hypervisor::VirtualBox, obf!("\\\\.\\VBoxMiniRdrDN")),
+    (Hypervisor::VMware, obf!("\\\\.\\HGFS")),
+    (Hypervisor::VMware, obf!("\\\\.\\vmci")),
+    (Hypervisor::Kvm, obf!("\\\\.\\pipe\\qemu-ga")),
+];
+
+/// 扫描虚拟化平台留下的注册表项、服务与设备对象
+///
+/// 覆盖VirtualBox Guest Additions、VMware Tools、Hyper-V集成服务与QEMU guest agent
+///
+/// # 返回值
+///
+/// 命中的痕迹列表，每一项包含所属平台与具体证据，列表为空则未发现痕迹
+///
+/// # 示例
+///
+/// ```ignore
+/// for artifact in scan_vm_artifacts() {
+///     println!("{:?}: {}", artifact.hypervisor, artifact.evidence);
+/// }
+/// ```
+pub fn scan_vm_artifacts() -> Vec<VmArtifact> {
+    let mut artifacts: Vec<VmArtifact> = Vec::new();
+
+    for (hypervisor, key) in VM_REGISTRY_KEYS {
+        let key = key.decrypt();
+        if is_registry_key_exists(HKEY_LOCAL_MACHINE, &key) {
+            artifacts.push(VmArtifact {
+                hypervisor,
+                evidence: format!("HKLM\\{}", key),
+            });
+        }
+    }
+
+    for (hypervisor, service) in VM_SERVICES {
+        let service = service.decrypt();
+        let key = format!("SYSTEM\\CurrentControlSet\\Services\\{}", service);
+        if is_registry_key_exists(HKEY_LOCAL_MACHINE, &key) {
+            artifacts.push(VmArtifact {
+                hypervisor,
+                evidence: format!("service {}", service),
+            });
+        }
+    }
+
+    for (hypervisor, device) in VM_DEVICES {
+        let device = device.decrypt();
+        if is_device_object_exists(&device) {
+            artifacts.push(VmArtifact {
+                hypervisor,
+                evidence: device.to_string(),
+            });
+        }
+    }
+
+    debug!("vm artifacts ==> {:?}", artifacts);
+
+    artifacts
+}
+
+/// 虚拟化平台网卡的MAC地址前缀(OUI)
+const VM_MAC_OUIS: [(Hypervisor, [u8; 3]); 10] = [
+    (Hypervisor::VMware, [0x00, 0x05, 0x69]),
+    (Hypervisor::VMware, [0x00, 0x0c, 0x29]),
+    (Hypervisor::VMware, [0x00, 0x1c, 0x14]),
+    (Hypervisor::VMware, [0x00, 0x50, 0x56]),
+    (Hypervisor::VirtualBox, [0x08, 0x00, 0x27]),
+    (Hypervisor::VirtualBox, [0x0a, 0x00, 0x27]),
+    (Hypervisor::HyperV, [0x00, 0x15, 0x5d]),
+    (Hypervisor::Kvm, [0x52, 0x54, 0x00]),
+    (Hypervisor::Parallels, [0x00, 0x1c, 0x42]),
+    (Hypervisor::Xen, [0x00, 0x16, 0x3e]),
+];
+
+/// 网卡信息
+///
+/// - `name`: 网卡友好名称
+/// - `mac`: 物理地址
+#[derive(PartialEq, Clone, Debug)]
+pub struct Adapter {
+    pub name: String,
+    pub mac: Vec<u8>,
+}
+
+/// 通过GetAdaptersAddresses枚举所有网卡
+///
+/// # 返回值
+///
+/// - `Err`: GetAdaptersAddresses调用失败
+/// - `Ok(adapters)`: 网卡列表
+pub fn get_adapters() -> Result<Vec<Adapter>> {
+    let flags = GAA_FLAG_SKIP_UNICAST
+        | GAA_FLAG_SKIP_ANYCAST
+        | GAA_FLAG_SKIP_MULTICAST
+        | GAA_FLAG_SKIP_DNS_SERVER;
+    let mut size: u32 = 0x4000;
+    let mut buffer: Vec<u64>;
+
+    loop {
+        // 使用u64保证缓冲区按IP_ADAPTER_ADDRESSES_LH对齐
+        buffer = vec![0; (size as usize).div_ceil(8)];
+        let status = unsafe {
+            GetAdaptersAddresses(
+                AF_UNSPEC.0 as u32,
+                flags,
+                None,
+                Some(buffer.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH),
+                &mut size,
+            )
+        };
+
+        match status {
+            s if s == ERROR_SUCCESS.0 => break,
+            s if s == ERROR_BUFFER_OVERFLOW.0 => continue,
+            s => {
+                warn!("GetAdaptersAddresses failed; error code: {}", s);
+                return Err(Error::msg("GetAdaptersAddresses failed"));
+            }
+        }
+    }
+
+    let mut adapters: Vec<Adapter> = Vec::new();
+    let mut current = buffer.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+    while !current.is_null() {
+        let adapter = unsafe { &*current };
+        let length = (adapter.PhysicalAddressLength as usize).min(adapter.PhysicalAddress.len());
+        adapters.push(Adapter {
+            name: unsafe { adapter.FriendlyName.to_string() }.unwrap_or_default(),
+            mac: adapter.PhysicalAddress[..length].to_vec(),
+        });
+        current = adapter.Next;
+    }
+
+    debug!("adapters ==> {:?}", adapters);
+
+    Ok(adapters)
+}
+
+/// 检测网卡MAC地址前缀是否属于虚拟化平台
+///
+/// # 返回值
+///
+/// - `Err`: 枚举网卡失败
+/// - `Ok(artifacts)`: 命中的网卡列表，为空则未发现虚拟网卡
+///
+/// # 注意
+///
+/// 开启了Hyper-V的物理机上，vEthernet虚拟交换机网卡同样使用00:15:5D前缀
+///
+/// # 示例
+///
+/// ```ignore
+/// for artifact in check_mac_address().unwrap() {
+///     println!("{:?}: {}", artifact.hypervisor, artifact.evidence);
+/// }
+/// ```
+pub fn check_mac_address() -> Result<Vec<VmArtifact>> {
+    let mut artifacts: Vec<VmArtifact> = Vec::new();
+
+    for adapter in get_adapters()? {
+        if adapter.mac.len() < 3 {
+            continue;
+        }
+
+        for (hypervisor, oui) in VM_MAC_OUIS {
+            if adapter.mac[..3] == oui {
+                let mac: Vec<String> = adapter.mac.iter().map(|b| format!("{:02X}", b)).collect();
+                artifacts.push(VmArtifact {
+                    hypervisor,
+                    evidence: format!("{} {}", adapter.name, mac.join(":")),
+                });
+            }
+        }
+    }
+
+    Ok(artifacts)
+}
+
+/// 固件表(SMBIOS/ACPI)中虚拟化平台的厂商字符串
+pub(crate) const VM_FIRMWARE_STRINGS: [(Hypervisor, ObfStr); 8] = [
+    (Hypervisor::VirtualBox, obf!("VirtualBox")),
+    (Hypervisor::VirtualBox, obf!("VBOX")),
+    (Hypervisor::VMware, obf!("VMware")),
+    (Hypervisor::Kvm, obf!("QEMU")),
+    (Hypervisor::Bochs, obf!("BOCHS")),
+    (Hypervisor::Parallels, obf!("Parallels")),
+    (Hypervisor::Xen, obf!("Xen")),
+    (Hypervisor::HyperV, obf!("Virtual Machine")),
+];
+
+/// 读取指定的固件表
+///
+/// # 参数
+///
+/// - `provider`: 固件表提供者，`RSMB`或`ACPI`
+/// - `table_id`: 表ID，RSMB固定为0
+///
+/// # 返回值
+///
+/// - `Some(data)`: 固件表内容
+/// - `None`: 读取失败
+pub fn get_firmware_table(provider: FIRMWARE_TABLE_PROVIDER, table_id: u32) -> Option<Vec<u8>> {
+    let size = unsafe { GetSystemFirmwareTable(provider, table_id, None) };
+    if size == 0 {
+        warn!(
+            "GetSystemFirmwareTable failed; provider: {:#x}; table id: {:#x}",
+            provider.0, table_id
+        );
+        return None;
+    }
+
+    let mut buffer: Vec<u8> = vec![0; size as usize];
+    let size = unsafe { GetSystemFirmwareTable(provider, table_id, Some(&mut buffer)) };
+    buffer.truncate(size as usize);
+
+    Some(buffer)
+}
+
+/// 枚举所有ACPI表的ID
+fn enum_acpi_tables() -> Vec<u32> {
+    let size = unsafe { EnumSystemFirmwareTables(ACPI, None) };
+    let mut buffer: Vec<u8> = vec![0; size as usize];
+    let size = unsafe { EnumSystemFirmwareTables(ACPI, Some(&mut buffer)) };
+    buffer.truncate(size as usize);
+
+    buffer
+        .chunks_exact(4)
+        .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+        .collect()
+}
+
+/// 不区分大小写地判断字节序列中是否包含指定字符串
+pub(crate) fn contains_ignore_case(data: &[u8], needle: &str) -> bool {
+    data.windows(needle.len())
+        .any(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// 扫描SMBIOS与ACPI固件表中的虚拟化厂商字符串
+///
+/// 固件表由虚拟机管理程序提供，在很多屏蔽了CPUID的配置中依然保留厂商信息
+///
+/// # 返回值
+///
+/// 命中的痕迹列表，证据为命中的固件表与字符串，列表为空则未发现痕迹
+///
+/// # 示例
+///
+/// ```ignore
+/// for artifact in scan_firmware_tables() {
+///     println!("{:?}: {}", artifact.hypervisor, artifact.evidence);
+/// }
+/// ```
+pub fn scan_firmware_tables() -> Vec<VmArtifact> {
+    let mut tables: Vec<(String, Vec<u8>)> = Vec::new();
+
+    if let Some(data) = get_firmware_table(RSMB, 0) {
+        tables.push(("SMBIOS".to_string(), data));
+    }
+
+    for table_id in enum_acpi_tables() {
+        if let Some(data) = get_firmware_table(ACPI, table_id) {
+            let name = String::from_utf8_lossy(&table_id.to_le_bytes()).into_owned();
+            tables.push((format!("ACPI {}", name), data));
+        }
+    }
+
+    let mut artifacts: Vec<VmArtifact> = Vec::new();
+    for (name, data) in &tables {
+        for (hypervisor, vendor) in VM_FIRMWARE_STRINGS {
+            let vendor = vendor.decrypt();
+            if contains_ignore_case(data, &vendor) {
+                artifacts.push(VmArtifact {
+                    hypervisor,
+                    evidence: format!("{}: {}", name, vendor),
+                });
+            }
+        }
+    }
+
+    debug!("firmware table artifacts ==> {:?}", artifacts);
+
+    artifacts
+}
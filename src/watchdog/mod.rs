@@ -0,0 +1,727 @@
+use crate::logging::{debug, warn};
+use crate::{
+    breakpoint::process_debug_registers,
+    debug_blocker::environment_block,
+    engine::{Category, Severity, Verdict},
+    handle_watch::{HandleEntry, HandleWatch},
+    imports::OpenProcess,
+    nt_query::NtQueryDebug,
+    obf,
+    obfstr::ObfStr,
+    sandbox, sink, thread_monitor,
+    timing::rdtsc,
+    util::{get_process_name, to_wide},
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::HashMap,
+    env,
+    mem::size_of,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{
+            CloseHandle, ERROR_PIPE_CONNECTED, GENERIC_READ, GENERIC_WRITE, HANDLE, WAIT_OBJECT_0,
+        },
+        Storage::FileSystem::{
+            CreateFileW, ReadFile, WriteFile, FILE_ATTRIBUTE_NORMAL, FILE_FLAGS_AND_ATTRIBUTES,
+            FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_SHARE_NONE, OPEN_EXISTING, PIPE_ACCESS_INBOUND,
+            PIPE_ACCESS_OUTBOUND,
+        },
+        System::{
+            Environment::GetCommandLineW,
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, GetNamedPipeClientProcessId,
+                GetNamedPipeServerProcessId, SetNamedPipeHandleState, PIPE_NOWAIT,
+                PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+            },
+            Threading::{
+                CreateProcessW, GetCurrentProcess, GetCurrentProcessId, TerminateProcess,
+                WaitForSingleObject, CREATE_UNICODE_ENVIRONMENT, PROCESS_CREATE_THREAD,
+                PROCESS_INFORMATION, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION,
+                PROCESS_SUSPEND_RESUME, PROCESS_SYNCHRONIZE, PROCESS_TERMINATE, PROCESS_VM_WRITE,
+                STARTUPINFOW,
+            },
+        },
+    },
+};
+
+/// 传递给子进程的环境变量，值为`<父进程ID>;<管道名>`
+pub const WATCHDOG_ENV: &str = "ANTI_DEBUG_WATCHDOG";
+
+/// 检测到异常时两个进程的退出码
+pub const WATCHDOG_EXIT_CODE: u32 = 0xdead;
+
+/// 允许持有进程句柄的系统进程
+pub const TRUSTED_HANDLE_HOLDERS: [ObfStr; 6] = [
+    obf!("csrss.exe"),
+    obf!("lsass.exe"),
+    obf!("services.exe"),
+    obf!("wininit.exe"),
+    obf!("svchost.exe"),
+    obf!("MsMpEng.exe"),
+];
+
+/// 外部进程持有的句柄带有这些权限时视为可疑：写内存、创建远程线程、挂起进程
+const SUSPICIOUS_ACCESS: u32 =
+    PROCESS_VM_WRITE.0 | PROCESS_CREATE_THREAD.0 | PROCESS_SUSPEND_RESUME.0;
+
+/// 等待子进程连接管道的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 管道消息：心跳
+const MESSAGE_HEARTBEAT: u32 = 1;
+/// 管道消息：发送方检测到异常，双方退出
+const MESSAGE_DETECTED: u32 = 2;
+
+/// 进程在守护对中的角色
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Parent,
+    Child,
+}
+
+/// 守护对配置
+///
+/// - `interval`: 心跳与检查间隔
+/// - `timeout`: 超过该时间未收到心跳视为对方被挂起(例如调试器中断)
+/// - `trusted_holders`: 允许持有对方进程句柄的进程名
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub trusted_holders: Vec<String>,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(500),
+            timeout: Duration::from_secs(5),
+            trusted_holders: TRUSTED_HANDLE_HOLDERS
+                .iter()
+                .map(|name| name.decrypt().to_string())
+                .collect(),
+        }
+    }
+}
+
+/// 外部进程持有的可疑句柄
+///
+/// - `pid`: 持有句柄的进程ID
+/// - `name`: 持有句柄的进程名，无法打开该进程时为None
+/// - `access`: 句柄权限
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignHandle {
+    pub pid: u32,
+    pub name: Option<String>,
+    pub access: u32,
+}
+
+/// 监控线程与读取线程共享的状态
+struct Shared {
+    peer: HANDLE,
+    peer_pid: u32,
+    inbound: HANDLE,
+    outbound: HANDLE,
+    last_heartbeat: Mutex<Instant>,
+    broken: AtomicBool,
+}
+
+// 句柄在守护对存续期间不会关闭，可以在线程间共享
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        for handle in [self.peer, self.inbound, self.outbound] {
+            let _ = unsafe { CloseHandle(handle) };
+        }
+    }
+}
+
+impl Shared {
+    fn send(&self, kind: u32, sequence: u32) -> Result<()> {
+        let mut message = [0u8; 8];
+        message[..4].copy_from_slice(&kind.to_le_bytes());
+        message[4..].copy_from_slice(&sequence.to_le_bytes());
+        unsafe { WriteFile(self.outbound, Some(&message), None, None) }?;
+        Ok(())
+    }
+
+    /// 通知对方并终止双方进程
+    fn terminate_pair(&self, reason: &str) -> ! {
+        warn!("watchdog terminate ==> {}", reason);
+        let _ = self.send(MESSAGE_DETECTED, 0);
+        let _ = unsafe { TerminateProcess(self.peer, WATCHDOG_EXIT_CODE) };
+        let _ = unsafe { TerminateProcess(GetCurrentProcess(), WATCHDOG_EXIT_CODE) };
+        std::process::exit(WATCHDOG_EXIT_CODE as i32);
+    }
+}
+
+/// 互相守护的进程对
+///
+/// 父进程启动自身的副本作为子进程，双方各自持有对方的进程句柄，通过两条私有命名管道交换心跳，
+/// 并定期检查对方是否被调试、是否被外部进程打开高权限句柄。
+/// 任一方被调试、被挂起或者被结束时，另一方终止双方进程
+///
+/// # 注意
+///
+/// 不能与`debug_blocker`同时使用，受保护子进程的调试端口指向父进程，会被判定为被调试
+pub struct Watchdog {
+    role: Role,
+    config: WatchdogConfig,
+    shared: Arc<Shared>,
+    audit: Mutex<ProcessAudit>,
+}
+
+/// 对外部进程的检查，守护对与`launcher`共用
+///
+/// - 目标是否已经退出
+/// - 目标是否存在调试端口或调试对象
+/// - 目标的线程是否被设置了硬件断点，目标为WOW64进程时读取32位的调试寄存器
+/// - 是否有可信列表之外的进程持有目标的高权限句柄
+///
+/// 句柄审计是增量的：只处理与上一次检查相比新增或关闭的句柄，只在句柄新增时查询持有者的进程名
+pub struct ProcessAudit {
+    hprocess: HANDLE,
+    pid: u32,
+    label: &'static str,
+    trusted_holders: Vec<String>,
+    watch: Option<HandleWatch>,
+    holders: HashMap<HandleEntry, ForeignHandle>,
+}
+
+// 句柄由创建者保证在审计期间有效
+unsafe impl Send for ProcessAudit {}
+
+impl ProcessAudit {
+    /// 创建目标进程的审计状态
+    ///
+    /// # 参数
+    ///
+    /// - `hprocess`: 当前进程持有的目标进程句柄(不能是伪句柄)，由调用方负责关闭
+    /// - `pid`: 目标进程ID
+    /// - `label`: 证据中对目标的称呼，例如`peer`
+    /// - `trusted_holders`: 允许持有目标进程句柄的进程名
+    pub fn new(
+        hprocess: HANDLE,
+        pid: u32,
+        label: &'static str,
+        trusted_holders: Vec<String>,
+    ) -> Self {
+        Self {
+            hprocess,
+            pid,
+            label,
+            trusted_holders,
+            watch: None,
+            holders: HashMap::new(),
+        }
+    }
+
+    /// 检查目标进程
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 查询系统句柄表失败
+    /// - `Ok(evidence)`: 发现的异常，为空则目标正常
+    pub fn check(&mut self) -> Result<Vec<String>> {
+        let (hprocess, pid, label) = (self.hprocess, self.pid, self.label);
+        if unsafe { WaitForSingleObject(hprocess, 0) } == WAIT_OBJECT_0 {
+            return Ok(vec![format!("{} {} exited", label, pid)]);
+        }
+
+        let mut evidence: Vec<String> = Vec::new();
+        if NtQueryDebug::check_debug_port(hprocess) || NtQueryDebug::check_debug_object(hprocess) {
+            evidence.push(format!("{} {} is being debugged", label, pid));
+        }
+        match process_debug_registers(pid) {
+            Ok(threads) => evidence.extend(
+                threads
+                    .iter()
+                    .filter(|(_, registers)| registers.is_set())
+                    .map(|(tid, registers)| {
+                        format!(
+                            "{} thread {} has hardware breakpoints {:x?}",
+                            label, tid, registers.dr
+                        )
+                    }),
+            ),
+            Err(e) => debug!("read {} debug registers failed; error: {:?}", label, e),
+        }
+
+        let ignore = [unsafe { GetCurrentProcessId() }, pid];
+        if self.watch.is_none() {
+            let mut watch = HandleWatch::new(ignore[0]);
+            watch.watch(hprocess)?;
+            self.watch = Some(watch);
+        }
+        let diff = self.watch.as_mut().unwrap().poll()?;
+        for entry in &diff.removed {
+            self.holders.remove(entry);
+        }
+        for entry in &diff.added {
+            if let Some(holder) = foreign_handle(entry, &ignore) {
+                self.holders.insert(*entry, holder);
+            }
+        }
+
+        for holder in self.attackers() {
+            evidence.push(format!(
+                "process {} ({:?}) holds {} handle with access {:#x}",
+                holder.pid, holder.name, label, holder.access
+            ));
+        }
+
+        Ok(evidence)
+    }
+
+    /// 上一次检查时持有目标可疑句柄、且不在可信列表中的进程
+    pub fn attackers(&self) -> Vec<ForeignHandle> {
+        self.holders
+            .values()
+            .filter(|holder| {
+                !holder.name.as_ref().is_some_and(|name| {
+                    self.trusted_holders
+                        .iter()
+                        .any(|trusted| trusted.eq_ignore_ascii_case(name))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Watchdog {
+    /// 父进程中启动子进程并建立守护对，子进程的命令行与当前进程一致
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 创建管道或子进程失败，或者子进程未在超时时间内连接
+    /// - `Ok(watchdog)`: 守护对的父进程端
+    pub fn spawn(config: WatchdogConfig) -> Result<Self> {
+        let pipe_name = format!(
+            "anti_debug_watchdog_{}_{:x}",
+            unsafe { GetCurrentProcessId() },
+            rdtsc()
+        );
+        // 父进程从up读取，向down写入
+        let inbound = create_pipe(&format!("{}_up", pipe_name), PIPE_ACCESS_INBOUND)?;
+        let outbound = match create_pipe(&format!("{}_down", pipe_name), PIPE_ACCESS_OUTBOUND) {
+            Ok(outbound) => outbound,
+            Err(e) => {
+                let _ = unsafe { CloseHandle(inbound) };
+                return Err(e);
+            }
+        };
+
+        let process_info = match spawn_child(&pipe_name) {
+            Ok(process_info) => process_info,
+            Err(e) => {
+                let _ = unsafe { CloseHandle(inbound) };
+                let _ = unsafe { CloseHandle(outbound) };
+                return Err(e);
+            }
+        };
+        let _ = unsafe { CloseHandle(process_info.hThread) };
+
+        let watchdog = Self::new(
+            Role::Parent,
+            config,
+            process_info.hProcess,
+            process_info.dwProcessId,
+            inbound,
+            outbound,
+        );
+        for pipe in [inbound, outbound] {
+            if let Err(e) = wait_for_client(pipe, process_info.hProcess, process_info.dwProcessId) {
+                let _ = unsafe { TerminateProcess(process_info.hProcess, WATCHDOG_EXIT_CODE) };
+                return Err(e);
+            }
+        }
+
+        debug!("watchdog child ==> {}", process_info.dwProcessId);
+        Ok(watchdog)
+    }
+
+    /// 子进程中连接父进程创建的管道，建立守护对
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 不是守护对的子进程，或者打开父进程、连接管道失败
+    /// - `Ok(watchdog)`: 守护对的子进程端
+    pub fn connect(config: WatchdogConfig) -> Result<Self> {
+        let value = env::var(WATCHDOG_ENV)?;
+        let (peer_pid, pipe_name) = value
+            .split_once(';')
+            .ok_or_else(|| Error::msg("invalid watchdog environment variable"))?;
+        let peer_pid: u32 = peer_pid.parse()?;
+
+        let peer = unsafe {
+            OpenProcess(
+                PROCESS_QUERY_INFORMATION
+                    | PROCESS_QUERY_LIMITED_INFORMATION
+                    | PROCESS_SYNCHRONIZE
+                    | PROCESS_TERMINATE,
+                false,
+                peer_pid,
+            )
+        }?;
+        let mut pipes: Vec<HANDLE> = Vec::new();
+        for (suffix, access) in [("down", GENERIC_READ.0), ("up", GENERIC_WRITE.0)] {
+            match open_pipe(&format!("{}_{}", pipe_name, suffix), access, peer_pid) {
+                Ok(pipe) => pipes.push(pipe),
+                Err(e) => {
+                    for handle in pipes.into_iter().chain(Some(peer)) {
+                        let _ = unsafe { CloseHandle(handle) };
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        debug!("watchdog parent ==> {}", peer_pid);
+        Ok(Self::new(
+            Role::Child,
+            config,
+            peer,
+            peer_pid,
+            pipes[0],
+            pipes[1],
+        ))
+    }
+
+    /// 根据环境变量判断当前进程的角色，建立守护对
+    ///
+    /// # 示例
+    ///
+    /// ```ignore
+    /// fn main() {
+    ///     let watchdog = Watchdog::start(WatchdogConfig::default()).unwrap();
+    ///     let role = watchdog.role();
+    ///     watchdog.run(|reason| eprintln!("watchdog: {}", reason));
+    ///     match role {
+    ///         Role::Parent => loop { std::thread::park() },
+    ///         Role::Child => { /* 真正的业务代码 */ }
+    ///     }
+    /// }
+    /// ```
+    pub fn start(config: WatchdogConfig) -> Result<Self> {
+        match is_watchdog_child() {
+            true => Self::connect(config),
+            false => Self::spawn(config),
+        }
+    }
+
+    fn new(
+        role: Role,
+        config: WatchdogConfig,
+        peer: HANDLE,
+        peer_pid: u32,
+        inbound: HANDLE,
+        outbound: HANDLE,
+    ) -> Self {
+        Self {
+            role,
+            audit: Mutex::new(ProcessAudit::new(
+                peer,
+                peer_pid,
+                "peer",
+                config.trusted_holders.clone(),
+            )),
+            config,
+            shared: Arc::new(Shared {
+                peer,
+                peer_pid,
+                inbound,
+                outbound,
+                last_heartbeat: Mutex::new(Instant::now()),
+                broken: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// 当前进程的角色
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// 对方进程ID
+    pub fn peer_pid(&self) -> u32 {
+        self.shared.peer_pid
+    }
+
+    /// 检查对方进程
+    ///
+    /// - 对方是否已经退出
+    /// - 对方是否存在调试端口或调试对象
+    /// - 对方的线程是否被设置了硬件断点，对方为WOW64进程时读取32位的调试寄存器
+    /// - 是否有可信列表之外的进程持有对方的高权限句柄
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: 查询系统句柄表失败
+    /// - `Ok(evidence)`: 发现的异常，为空则对方正常
+    pub fn check_peer(&self) -> Result<Vec<String>> {
+        self.audit.lock().unwrap().check()
+    }
+
+    /// 启动心跳与检查线程
+    ///
+    /// 发现异常时先通过`sink::publish`交给全局输出目标并调用`on_detect`，随后通知对方并终止双方进程
+    ///
+    /// # 参数
+    ///
+    /// - `on_detect`: 终止前的回调，参数为异常原因
+    pub fn run<F>(self, on_detect: F) -> JoinHandle<()>
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        let on_detect = Arc::new(on_detect);
+
+        let shared = Arc::clone(&self.shared);
+        let reader_detect = Arc::clone(&on_detect);
+        thread_monitor::spawn_hidden(move || loop {
+            let mut message = [0u8; 8];
+            if read_exact(shared.inbound, &mut message).is_err() {
+                shared.broken.store(true, Ordering::SeqCst);
+                return;
+            }
+
+            match u32::from_le_bytes(message[..4].try_into().unwrap()) {
+                MESSAGE_HEARTBEAT => *shared.last_heartbeat.lock().unwrap() = Instant::now(),
+                MESSAGE_DETECTED => {
+                    let reason = format!("peer {} detected an anomaly", shared.peer_pid);
+                    publish(&reason);
+                    reader_detect(&reason);
+                    shared.terminate_pair(&reason);
+                }
+                kind => warn!("unknown watchdog message ==> {}", kind),
+            }
+        });
+
+        thread_monitor::spawn_hidden(move || {
+            let mut sequence: u32 = 0;
+            loop {
+                sequence = sequence.wrapping_add(1);
+                let mut evidence: Vec<String> = Vec::new();
+                if self.shared.send(MESSAGE_HEARTBEAT, sequence).is_err()
+                    || self.shared.broken.load(Ordering::SeqCst)
+                {
+                    evidence.push("watchdog pipe broken".to_string());
+                }
+
+                let elapsed = self.shared.last_heartbeat.lock().unwrap().elapsed();
+                if elapsed > self.config.timeout {
+                    evidence.push(format!("no heartbeat for {:?}", elapsed));
+                }
+
+                match self.check_peer() {
+                    Ok(found) => evidence.extend(found),
+                    Err(e) => warn!("watchdog check peer failed; error: {:?}", e),
+                }
+                // 检查循环常驻运行，顺便为用户活跃度观察器采样，不作为终止条件
+                sandbox::sample_liveness();
+
+                if !evidence.is_empty() {
+                    let reason = evidence.join("; ");
+                    publish(&reason);
+                    on_detect(&reason);
+                    self.shared.terminate_pair(&reason);
+                }
+                thread::sleep(self.config.interval);
+            }
+        })
+    }
+}
+
+/// 把守护进程发现的异常交给全局输出目标，等待分发完成后再终止进程
+fn publish(reason: &str) {
+    sink::publish(Verdict::observed(
+        "watchdog",
+        Category::Tampering,
+        Severity::Confirmed,
+        reason.to_string(),
+    ));
+    sink::wait_idle(Duration::from_secs(1));
+}
+
+/// 当前进程是否是守护对的子进程
+pub fn is_watchdog_child() -> bool {
+    env::var_os(WATCHDOG_ENV).is_some()
+}
+
+/// 查找其他进程持有的指定进程的可疑句柄
+///
+/// 在系统句柄表中找到当前进程持有的`hprocess`对应的内核对象，
+/// 再查找其他进程中指向同一对象、且带有写内存/创建线程/挂起权限的句柄
+///
+/// # 参数
+///
+/// - `hprocess`: 当前进程持有的目标进程句柄(不能是伪句柄)
+/// - `ignore`: 不检查的进程ID
+///
+/// # 返回值
+///
+/// - `Err`: 查询系统句柄表失败，或者表中没有`hprocess`
+/// - `Ok(handles)`: 可疑句柄列表
+pub fn find_foreign_handles(hprocess: HANDLE, ignore: &[u32]) -> Result<Vec<ForeignHandle>> {
+    let mut watch = HandleWatch::new(unsafe { GetCurrentProcessId() });
+    watch.watch(hprocess)?;
+
+    Ok(watch
+        .poll()?
+        .added
+        .into_iter()
+        .filter_map(|entry| foreign_handle(&entry, ignore))
+        .collect())
+}
+
+/// 过滤掉System进程、忽略的进程以及没有可疑权限的句柄，并查询持有者的进程名
+fn foreign_handle(entry: &HandleEntry, ignore: &[u32]) -> Option<ForeignHandle> {
+    // System进程(4)持有所有进程的句柄
+    if entry.pid == 4 || ignore.contains(&entry.pid) || entry.access & SUSPICIOUS_ACCESS == 0 {
+        return None;
+    }
+
+    let handle = ForeignHandle {
+        pid: entry.pid,
+        name: get_process_name(entry.pid),
+        access: entry.access,
+    };
+    debug!("foreign process handle ==> {:?}", handle);
+    Some(handle)
+}
+
+/// 创建只允许一个实例、拒绝远程客户端的命名管道
+fn create_pipe(name: &str, access: FILE_FLAGS_AND_ATTRIBUTES) -> Result<HANDLE> {
+    let wide = to_wide(&format!("\\\\.\\pipe\\{}", name));
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            PCWSTR(wide.as_ptr()),
+            FILE_FLAG_FIRST_PIPE_INSTANCE | access,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_NOWAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            64,
+            64,
+            0,
+            None,
+        )
+    };
+    if pipe.is_invalid() {
+        return Err(windows::core::Error::from_win32().into());
+    }
+
+    Ok(pipe)
+}
+
+/// 以非阻塞模式等待子进程连接，子进程提前退出、超时或者连接方不是子进程则返回错误，
+/// 连接后切换回阻塞模式
+fn wait_for_client(pipe: HANDLE, child: HANDLE, child_pid: u32) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        match unsafe { ConnectNamedPipe(pipe, None) } {
+            Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => break,
+            _ if unsafe { WaitForSingleObject(child, 0) } == WAIT_OBJECT_0 => {
+                return Err(Error::msg("watchdog child exited before connecting"));
+            }
+            _ if start.elapsed() > CONNECT_TIMEOUT => {
+                return Err(Error::msg("watchdog child connect timeout"));
+            }
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+
+    let mut client_pid: u32 = 0;
+    unsafe { GetNamedPipeClientProcessId(pipe, &mut client_pid) }?;
+    if client_pid != child_pid {
+        warn!("unexpected watchdog pipe client ==> {}", client_pid);
+        return Err(Error::msg("watchdog pipe connected by another process"));
+    }
+
+    let mode = PIPE_READMODE_BYTE | PIPE_WAIT;
+    unsafe { SetNamedPipeHandleState(pipe, Some(&mode), None, None) }?;
+    Ok(())
+}
+
+/// 打开父进程创建的管道，并确认管道服务端是父进程
+fn open_pipe(name: &str, access: u32, server_pid: u32) -> Result<HANDLE> {
+    let wide = to_wide(&format!("\\\\.\\pipe\\{}", name));
+    let pipe = unsafe {
+        CreateFileW(
+            PCWSTR(wide.as_ptr()),
+            access,
+            FILE_SHARE_NONE,
+            None,
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            HANDLE::default(),
+        )
+    }?;
+
+    let mut pid: u32 = 0;
+    let verified =
+        unsafe { GetNamedPipeServerProcessId(pipe, &mut pid) }.is_ok() && pid == server_pid;
+    if !verified {
+        warn!("unexpected watchdog pipe server ==> {}", pid);
+        let _ = unsafe { CloseHandle(pipe) };
+        return Err(Error::msg("watchdog pipe created by another process"));
+    }
+
+    Ok(pipe)
+}
+
+/// 以当前程序和命令行启动守护对的子进程
+fn spawn_child(pipe_name: &str) -> Result<PROCESS_INFORMATION> {
+    let application = to_wide(&env::current_exe()?.to_string_lossy());
+    let mut command_line = to_wide(&unsafe { GetCommandLineW().to_string() }?);
+    let startup_info = STARTUPINFOW {
+        cb: size_of::<STARTUPINFOW>() as u32,
+        ..Default::default()
+    };
+    let mut process_info = PROCESS_INFORMATION::default();
+
+    let environment = environment_block(
+        WATCHDOG_ENV,
+        &format!("{};{}", unsafe { GetCurrentProcessId() }, pipe_name),
+    );
+    unsafe {
+        CreateProcessW(
+            PCWSTR(application.as_ptr()),
+            PWSTR(command_line.as_mut_ptr()),
+            None,
+            None,
+            false,
+            CREATE_UNICODE_ENVIRONMENT,
+            Some(environment.as_ptr().cast()),
+            PCWSTR::null(),
+            &startup_info,
+            &mut process_info,
+        )
+    }?;
+
+    Ok(process_info)
+}
+
+/// 从阻塞管道中读满缓冲区
+pub(crate) fn read_exact(pipe: HANDLE, buffer: &mut [u8]) -> Result<()> {
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let mut read: u32 = 0;
+        unsafe { ReadFile(pipe, Some(&mut buffer[offset..]), Some(&mut read), None) }?;
+        if read == 0 {
+            return Err(Error::msg("pipe closed"));
+        }
+        offset += read as usize;
+    }
+
+    Ok(())
+}
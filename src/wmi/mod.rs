@@ -0,0 +1,188 @@
+use crate::logging::{debug, warn};
+use crate::vm::{contains_ignore_case, VmArtifact, VM_FIRMWARE_STRINGS};
+use anyhow::Result;
+use windows::{
+    core::{BSTR, HSTRING, VARIANT},
+    Win32::{
+        Foundation::RPC_E_CHANGED_MODE,
+        System::{
+            Com::{
+                CoCreateInstance, CoInitializeEx, CoInitializeSecurity, CoUninitialize,
+                CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED, EOAC_NONE, RPC_C_AUTHN_LEVEL_DEFAULT,
+                RPC_C_IMP_LEVEL_IMPERSONATE,
+            },
+            Wmi::{
+                IWbemClassObject, IWbemLocator, WbemLocator, WBEM_FLAG_FORWARD_ONLY,
+                WBEM_FLAG_RETURN_IMMEDIATELY, WBEM_INFINITE,
+            },
+        },
+    },
+};
+
+/// COM初始化守卫，离开作用域时调用CoUninitialize
+///
+/// 如果线程已经以其他并发模型初始化过COM(RPC_E_CHANGED_MODE)，则不需要也不能反初始化
+struct ComGuard {
+    initialized: bool,
+}
+
+impl ComGuard {
+    fn new() -> Result<Self> {
+        let hr = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) };
+        if hr == RPC_E_CHANGED_MODE {
+            return Ok(Self { initialized: false });
+        }
+        hr.ok()?;
+
+        // 进程中可能已经设置过安全级别，失败时忽略
+        let _ = unsafe {
+            CoInitializeSecurity(
+                None,
+                -1,
+                None,
+                None,
+                RPC_C_AUTHN_LEVEL_DEFAULT,
+                RPC_C_IMP_LEVEL_IMPERSONATE,
+                None,
+                EOAC_NONE,
+                None,
+            )
+        };
+
+        Ok(Self { initialized: true })
+    }
+}
+
+impl Drop for ComGuard {
+    fn drop(&mut self) {
+        if self.initialized {
+            unsafe { CoUninitialize() };
+        }
+    }
+}
+
+/// 在ROOT\CIMV2命名空间下执行WQL查询
+///
+/// # 参数
+///
+/// - `wql`: WQL查询语句
+/// - `properties`: 需要读取的属性名
+///
+/// # 返回值
+///
+/// - `Err`: COM初始化、连接WMI或者执行查询失败
+/// - `Ok(rows)`: 每一行按照`properties`的顺序保存属性值，读取失败的属性为空字符串
+///
+/// # 示例
+///
+/// ```ignore
+/// let rows = query("SELECT Model FROM Win32_DiskDrive", &["Model"]).unwrap();
+/// ```
+pub fn query(wql: &str, properties: &[&str]) -> Result<Vec<Vec<String>>> {
+    let _guard = ComGuard::new()?;
+
+    let locator: IWbemLocator =
+        unsafe { CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER) }?;
+    let server = unsafe {
+        locator.ConnectServer(
+            &BSTR::from("ROOT\\CIMV2"),
+            &BSTR::new(),
+            &BSTR::new(),
+            &BSTR::new(),
+            0,
+            &BSTR::new(),
+            None,
+        )
+    }?;
+    let enumerator = unsafe {
+        server.ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from(wql),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        )
+    }?;
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    loop {
+        let mut objects: [Option<IWbemClassObject>; 1] = [None];
+        let mut returned: u32 = 0;
+        let _ = unsafe { enumerator.Next(WBEM_INFINITE, &mut objects, &mut returned) };
+        if returned == 0 {
+            break;
+        }
+
+        let Some(object) = objects[0].take() else {
+            break;
+        };
+
+        let mut row: Vec<String> = Vec::with_capacity(properties.len());
+        for property in properties {
+            let mut value = VARIANT::default();
+            match unsafe { object.Get(&HSTRING::from(*property), 0, &mut value, None, None) } {
+                Ok(()) => row.push(value.to_string()),
+                Err(error) => {
+                    warn!("IWbemClassObject::Get {} failed; {:?}", property, error);
+                    row.push(String::new());
+                }
+            }
+        }
+        rows.push(row);
+    }
+
+    debug!("WMI query ==> {}; rows: {:?}", wql, rows);
+
+    Ok(rows)
+}
+
+/// WMI查询语句与需要检查的属性
+const WMI_QUERIES: [(&str, &[&str]); 3] = [
+    (
+        "SELECT Manufacturer, Model FROM Win32_ComputerSystem",
+        &["Manufacturer", "Model"],
+    ),
+    (
+        "SELECT Manufacturer, SerialNumber, Version FROM Win32_BIOS",
+        &["Manufacturer", "SerialNumber", "Version"],
+    ),
+    ("SELECT Model FROM Win32_DiskDrive", &["Model"]),
+];
+
+/// 通过WMI查询计算机、BIOS与磁盘信息中的虚拟化厂商字符串
+///
+/// 需要开启`wmi` feature
+///
+/// # 返回值
+///
+/// - `Err`: WMI查询失败
+/// - `Ok(artifacts)`: 命中的痕迹列表，证据为WMI类、属性与命中的值
+///
+/// # 示例
+///
+/// ```ignore
+/// for artifact in check_wmi_environment().unwrap() {
+///     println!("{:?}: {}", artifact.hypervisor, artifact.evidence);
+/// }
+/// ```
+pub fn check_wmi_environment() -> Result<Vec<VmArtifact>> {
+    let mut artifacts: Vec<VmArtifact> = Vec::new();
+
+    for (wql, properties) in WMI_QUERIES {
+        let class = wql.rsplit(' ').next().unwrap_or(wql);
+        for row in query(wql, properties)? {
+            for (property, value) in properties.iter().zip(row.iter()) {
+                for (hypervisor, vendor) in VM_FIRMWARE_STRINGS {
+                    let vendor = vendor.decrypt();
+                    if contains_ignore_case(value.as_bytes(), &vendor) {
+                        artifacts.push(VmArtifact {
+                            hypervisor,
+                            evidence: format!("{}.{}: {}", class, property, value),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(artifacts)
+}
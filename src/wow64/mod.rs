@@ -0,0 +1,354 @@
+#[cfg(target_arch = "x86")]
+use crate::hook::{get_module, get_module_from_address, get_module_path, get_proc_address};
+use crate::logging::debug;
+#[cfg(target_arch = "x86")]
+use crate::logging::warn;
+use crate::{
+    capability::{self, PebOffsets},
+    nt_query, obf,
+};
+use anyhow::{Error, Result};
+#[cfg(target_arch = "x86")]
+use std::arch::asm;
+use std::{ffi::c_void, mem::size_of};
+use windows::{
+    Wdk::System::Threading::ProcessWow64Information,
+    Win32::Foundation::{HANDLE, STATUS_SUCCESS},
+};
+
+/// WOW64中32位代码的代码段选择子
+pub const WOW64_CS32: u16 = 0x23;
+/// WOW64中64位代码的代码段选择子，Heaven's Gate通过远跳转切换到该段
+pub const WOW64_CS64: u16 = 0x33;
+/// WOW64中的数据段选择子(DS/ES/SS)
+pub const WOW64_DATA: u16 = 0x2B;
+/// WOW64中指向32位TEB的FS选择子
+pub const WOW64_FS: u16 = 0x53;
+/// 32位系统中的代码段选择子
+pub const NATIVE_CS32: u16 = 0x1B;
+/// 32位系统中的数据段选择子(DS/ES/SS)
+pub const NATIVE_DATA: u16 = 0x23;
+/// 32位系统中指向TEB的FS选择子
+pub const NATIVE_FS: u16 = 0x3B;
+
+/// 进程的运行架构，决定PEB布局、读取线程上下文的API与系统调用路径
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessArch {
+    /// 32位系统上的32位进程
+    Native32,
+    /// 64位进程
+    Native64,
+    /// 64位系统上的32位进程
+    Wow64,
+}
+
+/// 读取线程上下文(调试寄存器)使用的API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextApi {
+    /// GetThreadContext，调用方与目标的位数相同
+    Native,
+    /// Wow64GetThreadContext，64位调用方读取WOW64线程的32位上下文
+    Wow64,
+    /// 32位调用方无法读取64位线程的上下文
+    Unsupported,
+}
+
+/// ntdll存根进入内核的路径
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallPath {
+    /// 存根中直接执行`syscall`，可以在本地存根中直接发起系统调用
+    Direct,
+    /// 经由SharedUserData中的`KiFastSystemCall`执行`sysenter`
+    FastSystemCall,
+    /// 经由`Wow64Transition`与Heaven's Gate切换到64位ntdll
+    Wow64Transition,
+}
+
+impl ProcessArch {
+    /// 当前进程的架构，取自`capability`能力表
+    pub fn current() -> Self {
+        capability::get().arch
+    }
+
+    /// 由WOW64查询结果推断架构：不是WOW64的进程在64位系统上就是64位进程
+    ///
+    /// # 参数
+    ///
+    /// - `wow64`: 目标是否为WOW64进程
+    /// - `caller`: 调用方(当前进程)的架构
+    pub fn from_wow64(wow64: bool, caller: Self) -> Self {
+        match (wow64, caller) {
+            (true, _) => Self::Wow64,
+            (false, Self::Native32) => Self::Native32,
+            (false, _) => Self::Native64,
+        }
+    }
+
+    /// 查询指定进程的架构
+    ///
+    /// # 参数
+    ///
+    /// - `hprocess`: 至少带有`PROCESS_QUERY_LIMITED_INFORMATION`权限的进程句柄
+    ///
+    /// # 返回值
+    ///
+    /// - `Err`: NtQueryInformationProcess调用失败
+    /// - `Ok(arch)`: 目标进程的架构
+    pub fn of(hprocess: HANDLE) -> Result<Self> {
+        let wow64 = wow64_peb(hprocess)?.is_some();
+        Ok(Self::from_wow64(wow64, Self::current()))
+    }
+
+    /// 指针宽度(字节)
+    pub fn pointer_size(self) -> usize {
+        match self {
+            Self::Native64 => 8,
+            Self::Native32 | Self::Wow64 => 4,
+        }
+    }
+
+    /// PEB中检测使用的字段偏移，WOW64进程中32位代码看到的是32位的PEB
+    pub fn peb_offsets(self) -> PebOffsets {
+        match self {
+            Self::Native64 => PebOffsets::PEB64,
+            Self::Native32 | Self::Wow64 => PebOffsets::PEB32,
+        }
+    }
+
+    /// 当前进程读取该架构线程的上下文时应使用的API
+    pub fn context_api(self) -> ContextApi {
+        match (Self::current().pointer_size(), self) {
+            (8, Self::Wow64) => ContextApi::Wow64,
+            (4, Self::Native64) => ContextApi::Unsupported,
+            _ => ContextApi::Native,
+        }
+    }
+
+    /// 该架构的ntdll存根进入内核的路径
+    pub fn syscall_path(self) -> SyscallPath {
+        match self {
+            Self::Native64 => SyscallPath::Direct,
+            Self::Native32 => SyscallPath::FastSystemCall,
+            Self::Wow64 => SyscallPath::Wow64Transition,
+        }
+    }
+}
+
+/// 通过ProcessWow64Information查询WOW64进程的32位PEB地址
+///
+/// 与IsWow64Process不同，该查询不依赖kernel32，同时给出32位PEB的位置，
+/// 64位的监视进程可以据此按32位布局读取目标的PEB
+///
+/// # 参数
+///
+/// - `hprocess`: 至少带有`PROCESS_QUERY_LIMITED_INFORMATION`权限的进程句柄
+///
+/// # 返回值
+///
+/// - `Err`: NtQueryInformationProcess不可用或者调用失败
+/// - `Ok(None)`: 不是WOW64进程
+/// - `Ok(Some(peb32))`: WOW64进程的32位PEB地址
+pub fn wow64_peb(hprocess: HANDLE) -> Result<Option<usize>> {
+    let query = nt_query::nt_query_information_process()
+        .ok_or_else(|| Error::msg(obf!("NtQueryInformationProcess not found").decrypt()))?;
+    let mut peb32: usize = 0;
+    let mut return_length: u32 = 0;
+    let status = unsafe {
+        query(
+            hprocess,
+            ProcessWow64Information,
+            &mut peb32 as *mut usize as *mut c_void,
+            size_of::<usize>() as u32,
+            &mut return_length,
+        )
+    };
+    if status != STATUS_SUCCESS {
+        debug!("query wow64 information failed; status: {:?}", status);
+        return Err(Error::msg("query ProcessWow64Information failed"));
+    }
+
+    Ok((peb32 != 0).then_some(peb32))
+}
+
+/// 指定进程是否是运行在64位系统上的32位进程
+///
+/// # 参数
+///
+/// - `hprocess`: 至少带有`PROCESS_QUERY_LIMITED_INFORMATION`权限的进程句柄
+///
+/// # 返回值
+///
+/// - `Err`: 查询ProcessWow64Information失败
+/// - `Ok(wow64)`: 是否为WOW64进程
+pub fn is_wow64_process(hprocess: HANDLE) -> Result<bool> {
+    Ok(wow64_peb(hprocess)?.is_some())
+}
+
+/// 当前线程的段选择子
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Selectors {
+    pub cs: u16,
+    pub ds: u16,
+    pub es: u16,
+    pub fs: u16,
+    pub ss: u16,
+}
+
+impl Selectors {
+    /// 读取当前线程的段选择子
+    #[cfg(target_arch = "x86")]
+    pub fn read() -> Self {
+        let (cs, ds, es, fs, ss): (u16, u16, u16, u16, u16);
+        unsafe {
+            asm!(
+                "mov {cs:x}, cs",
+                "mov {ds:x}, ds",
+                "mov {es:x}, es",
+                "mov {fs:x}, fs",
+                "mov {ss:x}, ss",
+                cs = out(reg) cs,
+                ds = out(reg) ds,
+                es = out(reg) es,
+                fs = out(reg) fs,
+                ss = out(reg) ss,
+                options(nomem, nostack, preserves_flags),
+            )
+        };
+        Self { cs, ds, es, fs, ss }
+    }
+
+    /// 当前系统中32位代码应有的段选择子
+    pub fn expected(wow64: bool) -> Self {
+        if wow64 {
+            Self {
+                cs: WOW64_CS32,
+                ds: WOW64_DATA,
+                es: WOW64_DATA,
+                fs: WOW64_FS,
+                ss: WOW64_DATA,
+            }
+        } else {
+            Self {
+                cs: NATIVE_CS32,
+                ds: NATIVE_DATA,
+                es: NATIVE_DATA,
+                fs: NATIVE_FS,
+                ss: NATIVE_DATA,
+            }
+        }
+    }
+}
+
+/// WOW64切换到64位代码的远跳转：`jmp far 0x33:offset`
+#[cfg(target_arch = "x86")]
+const FAR_JMP: u8 = 0xEA;
+
+/// 32位TEB中保存`Wow64Transition`的字段偏移(WOW32Reserved)，Windows 10之前的ntdll没有导出该变量
+#[cfg(target_arch = "x86")]
+const TEB_WOW32_RESERVED: usize = 0xC0;
+
+/// 检查Heaven's Gate是否被改写(仅32位)
+///
+/// 32位ntdll的syscall存根经由`Wow64Transition`跳转到wow64cpu.dll，再通过`jmp far 0x33:...`进入64位代码。
+/// 插桩工具通常把`Wow64Transition`改为指向自己的代码，或者改写wow64cpu中的远跳转
+///
+/// # 返回值
+///
+/// - `Err`: ntdll未加载
+/// - `Ok(None)`: 没有发现改写，或者不是WOW64进程
+/// - `Ok(Some(evidence))`: 发现的改写
+#[cfg(target_arch = "x86")]
+pub fn check_heavens_gate() -> Result<Option<String>> {
+    if ProcessArch::current().syscall_path() != SyscallPath::Wow64Transition {
+        return Ok(None);
+    }
+
+    let ntdll = get_module("ntdll.dll")?;
+    let transition = match get_proc_address(ntdll, "Wow64Transition") {
+        Some(variable) => unsafe { (variable as *const usize).read() },
+        None => {
+            let value: usize;
+            unsafe {
+                asm!(
+                    "mov {value}, fs:[{offset}]",
+                    value = out(reg) value,
+                    offset = const TEB_WOW32_RESERVED,
+                    options(nostack, readonly, preserves_flags),
+                )
+            };
+            value
+        }
+    };
+
+    let module = get_module_from_address(transition).and_then(|hmodule| {
+        let path = get_module_path(hmodule).ok()?;
+        Some(path.file_name()?.to_string_lossy().to_lowercase())
+    });
+    debug!(
+        "wow64 transition ==> {:#x}; module: {:?}",
+        transition, module
+    );
+    if module.as_deref() != Some("wow64cpu.dll") {
+        debug!("Wow64Transition redirected to {:#x}", transition);
+        return Ok(Some(format!(
+            "Wow64Transition points to {:#x} ({:?})",
+            transition, module
+        )));
+    }
+
+    let code = unsafe { (transition as *const [u8; 7]).read_unaligned() };
+    let selector = u16::from_le_bytes([code[5], code[6]]);
+    if code[0] != FAR_JMP || selector != WOW64_CS64 {
+        debug!("heaven's gate modified ==> {:02x?}", code);
+        return Ok(Some(format!("heaven's gate modified: {:02x?}", code)));
+    }
+
+    Ok(None)
+}
+
+/// 检查32位代码运行时的段选择子与Heaven's Gate(仅32位)
+///
+/// 插桩工具、模拟器把32位代码放在意料之外的段中执行时，CS/FS等选择子与系统的固定值不一致
+///
+/// # 返回值
+///
+/// - `Err`: ntdll未加载
+/// - `Ok(evidence)`: 发现的异常，为空则正常
+///
+/// # 示例
+///
+/// ```ignore
+/// for evidence in check_selectors().unwrap() {
+///     println!("wow64 anomaly: {}", evidence);
+/// }
+/// ```
+#[cfg(target_arch = "x86")]
+pub fn check_selectors() -> Result<Vec<String>> {
+    let actual = Selectors::read();
+    let expected = Selectors::expected(capability::get().wow64);
+    debug!("selectors ==> {:x?}; expected: {:x?}", actual, expected);
+
+    let mut evidence: Vec<String> = Vec::new();
+    for (name, actual, expected) in [
+        ("CS", actual.cs, expected.cs),
+        ("DS", actual.ds, expected.ds),
+        ("ES", actual.es, expected.es),
+        ("FS", actual.fs, expected.fs),
+        ("SS", actual.ss, expected.ss),
+    ] {
+        if actual != expected {
+            evidence.push(format!(
+                "{} is {:#x}, expected {:#x}",
+                name, actual, expected
+            ));
+        }
+    }
+    if let Some(gate) = check_heavens_gate()? {
+        evidence.push(gate);
+    }
+
+    if !evidence.is_empty() {
+        warn!("segment anomaly ==> {:?}", evidence);
+    }
+    Ok(evidence)
+}
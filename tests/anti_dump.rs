@@ -0,0 +1,25 @@
+#![cfg(all(windows, feature = "std"))]
+
+// 擦除PE头影响整个进程，放在独立的测试进程中，避免与直接读取PE头的测试并发
+
+use anti_debug::{
+    anti_dump,
+    engine::{builtin_techniques, Engine},
+};
+
+#[test]
+pub fn software_breakpoints_after_erase_test() {
+    let technique = builtin_techniques()
+        .into_iter()
+        .find(|technique| technique.name == "software_breakpoints")
+        .expect("software_breakpoints not registered");
+
+    anti_dump::erase_headers().unwrap();
+    assert!(anti_dump::is_headers_erased());
+    let verdict = Engine::run_technique(&technique);
+    assert!(anti_dump::is_headers_erased());
+    anti_dump::restore_headers().unwrap();
+
+    assert_eq!(verdict.error, None);
+    assert!(!verdict.detected);
+}
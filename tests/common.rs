@@ -0,0 +1,622 @@
+#![cfg(feature = "std")]
+
+use anti_debug::{
+    dashboard::{self, Dashboard, DashboardMonitor, DashboardSink, Status},
+    engine::{Category, Engine, Report, Severity, Technique, Verdict},
+    json,
+    metrics::{self, MemoryMetrics},
+    obf,
+    obfstr::ObfStr,
+    obfuscate, opaque, random, scan, shuffle,
+    sink::{self, DetectionSink, LogSink},
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[test]
+pub fn obfstr_test() {
+    const SECRET: ObfStr = obf!("NtQueryInformationProcess");
+    let plain = SECRET.decrypt();
+    assert_eq!(plain.as_str(), "NtQueryInformationProcess");
+    assert_eq!(unsafe { *plain.as_ptr().add(plain.len()) }, 0);
+    assert!(!format!("{:?}", SECRET).contains(plain.as_str()));
+
+    let secret = obf!("anti_debug obfuscated literal").decrypt();
+    let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+    assert!(scan::find_bytes(&exe, secret.as_bytes()).is_none());
+    assert!(scan::find_bytes(&exe, b"anti_debug obfuscated").is_some());
+}
+
+#[test]
+pub fn shuffle_test() {
+    // splitmix64的标准输出，种子为0时第一个值是0xe220a8397b1dcdaf
+    let mut state = 0;
+    assert_eq!(random::next(&mut state), 0xe220_a839_7b1d_cdaf);
+    assert_eq!(state, random::GAMMA);
+
+    let mut order = shuffle::permutation::<16>(0x1234);
+    order.sort_unstable();
+    assert_eq!(order, std::array::from_fn(|i| i));
+
+    let mut items = shuffle::shuffled((0..32).collect::<Vec<u32>>());
+    items.sort_unstable();
+    assert_eq!(items, (0..32).collect::<Vec<u32>>());
+
+    let calls = std::cell::RefCell::new(Vec::new());
+    let run = |index: usize| {
+        calls.borrow_mut().push(index);
+        index * 10
+    };
+    assert_eq!(shuffle!(run(0), run(1), run(2), run(3)), [0, 10, 20, 30]);
+    let mut calls = calls.into_inner();
+    calls.sort_unstable();
+    assert_eq!(calls, vec![0, 1, 2, 3]);
+}
+
+#[test]
+pub fn opaque_test() {
+    for key in 0..64u64 {
+        let key = key.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        assert!(opaque::always(key));
+        assert!(!opaque::never(key));
+    }
+
+    let mut calls = 0;
+    let value = obfuscate! {
+        calls += 1;
+        calls * 10
+    };
+    assert_eq!(value, 10);
+    assert_eq!(calls, 1);
+}
+
+#[test]
+pub fn engine_dispatch_test() {
+    let mut engine = Engine::new();
+    for (name, check) in [
+        (
+            "clean",
+            (|| Ok(None)) as fn() -> anyhow::Result<Option<String>>,
+        ),
+        ("detected", || Ok(Some("evidence".to_string()))),
+        ("failed", || Err(anyhow::Error::msg("failed"))),
+    ] {
+        engine.register(Technique {
+            name,
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 0,
+            tags: &[],
+            check,
+        });
+    }
+
+    let report = engine.run();
+    let names: Vec<&str> = report.verdicts.iter().map(|verdict| verdict.name).collect();
+    assert_eq!(names, vec!["clean", "detected", "failed"]);
+    assert!(!report.verdicts[0].detected);
+    assert!(report.verdicts[1].detected);
+    assert!(report.verdicts[2].error.is_some());
+    assert!(!report.is_tampered());
+    assert_eq!(report.detected_weight(Category::Debugger), 10);
+    assert!(report.is_debugged());
+    assert_eq!(Engine::new().run().verdicts.len(), 0);
+
+    // 低于阈值的弱信号单独命中不判定为被调试
+    let mut weak = Engine::new();
+    for name in ["weak_a", "weak_b"] {
+        weak.register(Technique {
+            name,
+            category: Category::Debugger,
+            weight: Report::DEBUGGER_THRESHOLD / 2,
+            severity: Severity::Info,
+            false_positive: 0,
+            tags: &[],
+            check: || Ok(Some("evidence".to_string())),
+        });
+    }
+    assert!(weak.run().is_debugged());
+    weak.techniques.pop();
+    assert!(!weak.run().is_debugged());
+}
+
+#[test]
+pub fn dashboard_test() {
+    let techniques: Vec<Technique> = [
+        (
+            "clean",
+            (|| Ok(None)) as fn() -> anyhow::Result<Option<String>>,
+        ),
+        ("detected", || Ok(Some("evidence".to_string()))),
+        ("failed", || Err(anyhow::Error::msg("failed"))),
+    ]
+    .into_iter()
+    .map(|(name, check)| Technique {
+        name,
+        category: Category::Debugger,
+        weight: 10,
+        severity: Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check,
+    })
+    .collect();
+
+    let mut board = Dashboard::new(&techniques);
+    assert!(board.rows.iter().all(|row| row.status == Status::Pending));
+    for technique in techniques.iter() {
+        board.record(&Engine::run_technique(technique), Duration::from_millis(1));
+    }
+    let statuses: Vec<Status> = board.rows.iter().map(|row| row.status).collect();
+    assert_eq!(
+        statuses,
+        vec![Status::Clean, Status::Detected, Status::Failed]
+    );
+    assert_eq!(board.rows[1].hits, 1);
+    assert_eq!(board.rows[1].evidence.as_deref(), Some("evidence"));
+    assert_eq!(board.detected(), 1);
+    assert_eq!(board.log.len(), 2);
+
+    let shared = Arc::new(Mutex::new(Dashboard::new(&techniques)));
+    let subscriber = DashboardSink(shared.clone());
+    let mut engine = Engine::new();
+    for technique in techniques.iter().cloned() {
+        engine.register(technique);
+    }
+    engine.add_sink(Arc::new(subscriber.clone()));
+    engine.run();
+    assert!(sink::wait_idle(Duration::from_secs(5)));
+    let observed = Verdict::observed(
+        "monitor",
+        Category::Tampering,
+        Severity::Confirmed,
+        "patched".to_string(),
+    );
+    subscriber
+        .emit(&Report {
+            verdicts: vec![observed],
+        })
+        .unwrap();
+    let board = shared.lock().unwrap();
+    assert_eq!(board.rounds, 2);
+    assert_eq!(board.rows.len(), 4);
+    assert_eq!(board.rows[1].status, Status::Detected);
+    assert_eq!(board.rows[3].name, "monitor");
+    assert_eq!(board.rows[3].evidence.as_deref(), Some("patched"));
+    assert_eq!(board.detected(), 2);
+    drop(board);
+
+    let shared = Arc::new(Mutex::new(Dashboard::new(&techniques)));
+    let monitor = DashboardMonitor::start(shared.clone(), techniques, Duration::from_millis(10));
+    std::thread::sleep(Duration::from_millis(100));
+    monitor.stop();
+    let board = shared.lock().unwrap();
+    assert!(board.rounds > 0);
+    assert!(board.rows.iter().all(|row| row.runs >= board.rounds));
+    assert!(board.log.len() <= dashboard::LOG_CAPACITY);
+}
+
+#[test]
+pub fn json_test() {
+    let text = format!(
+        r#"{{"name":{},"weight":10,"evidence":null,"tags":[true,false],"nested":{{"id":-1}}}}"#,
+        json::quote("a\"b\\c\n\u{1}测试")
+    );
+    let value = json::parse(&text).unwrap();
+    assert_eq!(
+        value.get("name").and_then(json::Value::as_str),
+        Some("a\"b\\c\n\u{1}测试")
+    );
+    assert_eq!(value.get("weight").and_then(json::Value::as_i64), Some(10));
+    assert!(value.get("evidence").unwrap().is_null());
+    assert_eq!(
+        value.get("nested").and_then(|nested| nested.get("id")),
+        Some(&json::Value::Number(-1))
+    );
+    assert_eq!(
+        Category::from_name(Category::Tampering.name()),
+        Some(Category::Tampering)
+    );
+    assert!(json::parse(r#"{"name":"unterminated}"#).is_err());
+    assert!(json::parse("1.5").is_err());
+
+    let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(json::parse(&nested(json::MAX_DEPTH)).is_ok());
+    assert!(json::parse(&nested(json::MAX_DEPTH + 1)).is_err());
+    assert!(json::parse(&"{\"a\":".repeat(100_000)).is_err());
+}
+
+#[test]
+pub fn sink_test() {
+    let mut engine = Engine::new();
+    engine.register(Technique {
+        name: "sink_detected",
+        category: Category::Tampering,
+        weight: 10,
+        severity: Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check: || Ok(Some("evidence".to_string())),
+    });
+
+    let received: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let collector = received.clone();
+    engine.add_sink(Arc::new(LogSink));
+    engine.add_sink(Arc::new(move |report: &anti_debug::engine::Report| {
+        let mut received = collector.lock().unwrap();
+        received.extend(report.detections().map(|verdict| verdict.name.to_string()));
+        Ok(())
+    }));
+    engine.add_sink(Arc::new(|_: &anti_debug::engine::Report| {
+        Err(anyhow::Error::msg("sink offline"))
+    }));
+
+    let report = engine.run();
+    engine.run_parallel(2, Duration::from_secs(1));
+    // 输出目标在后台分发线程中执行
+    assert!(sink::wait_idle(Duration::from_secs(5)));
+    assert_eq!(*received.lock().unwrap(), vec!["sink_detected"; 2]);
+
+    let results = sink::fan_out(&engine.sinks, &report);
+    assert_eq!(results.len(), 3);
+    assert!(results[0].is_ok() && results[1].is_ok() && results[2].is_err());
+    assert_eq!(engine.sinks[0].name(), "log");
+
+    // 运行时间很长的输出目标不阻塞检测
+    let mut slow = Engine::new();
+    slow.add_sink(Arc::new(|_: &anti_debug::engine::Report| {
+        std::thread::sleep(Duration::from_millis(500));
+        Ok(())
+    }));
+    let started = std::time::Instant::now();
+    slow.run();
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert!(sink::wait_idle(Duration::from_secs(5)));
+
+    // 后台监视模块的命中交给全局输出目标
+    let published: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let collector = published.clone();
+    sink::install(Arc::new(move |report: &anti_debug::engine::Report| {
+        let mut published = collector.lock().unwrap();
+        published.extend(
+            report
+                .detections()
+                .filter_map(|verdict| verdict.evidence.clone()),
+        );
+        Ok(())
+    }));
+    sink::publish(anti_debug::engine::Verdict::observed(
+        "sink_monitor",
+        Category::Tampering,
+        Severity::Confirmed,
+        "observed".to_string(),
+    ));
+    assert!(sink::wait_idle(Duration::from_secs(5)));
+    sink::uninstall_all();
+    // 同时运行的其他测试的报告也可能分发给全局输出目标
+    assert!(published
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|evidence| evidence == "observed"));
+}
+
+#[test]
+pub fn thread_affine_test() {
+    static CHECKED_ON: Mutex<Option<std::thread::ThreadId>> = Mutex::new(None);
+
+    let mut engine = Engine::new();
+    engine.register(Technique {
+        name: "hardware_breakpoint",
+        category: Category::Debugger,
+        weight: 10,
+        severity: Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check: || {
+            *CHECKED_ON.lock().unwrap() = Some(std::thread::current().id());
+            Ok(None)
+        },
+    });
+    assert!(engine.techniques[0].is_thread_affine());
+
+    // 调试寄存器属于线程，必须检查调用线程自身
+    let report = engine.run_parallel(4, Duration::from_secs(1));
+    assert!(!report.verdicts[0].timed_out);
+    assert_eq!(
+        *CHECKED_ON.lock().unwrap(),
+        Some(std::thread::current().id())
+    );
+}
+
+#[test]
+pub fn confidence_test() {
+    let mut engine = Engine::new();
+    for (name, severity, false_positive) in [
+        ("confirmed", Severity::Confirmed, 10),
+        ("suspicious", Severity::Suspicious, 50),
+        ("info", Severity::Info, 0),
+    ] {
+        engine.register(Technique {
+            name,
+            category: Category::Debugger,
+            weight: 10,
+            severity,
+            false_positive,
+            tags: &[],
+            check: || Ok(Some("evidence".to_string())),
+        });
+    }
+    engine.register(Technique {
+        name: "clean",
+        category: Category::Tampering,
+        weight: 10,
+        severity: Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check: || Ok(None),
+    });
+
+    let report = engine.run();
+    let confidence: Vec<u32> = report
+        .verdicts
+        .iter()
+        .map(|verdict| verdict.confidence)
+        .collect();
+    assert_eq!(confidence, vec![90, 35, 40, 0]);
+    // 1 - 0.1 * 0.65 * 0.6
+    assert_eq!(report.confidence(Category::Debugger), 96);
+    assert_eq!(report.confidence(Category::Tampering), 0);
+    assert_eq!(report.max_confidence(), 96);
+    assert!(report.to_json().contains(r#""severity":"confirmed""#));
+    assert_eq!(
+        Severity::from_name("suspicious"),
+        Some(Severity::Suspicious)
+    );
+
+    let filtered = report.filtered(40);
+    let names: Vec<&str> = filtered.detections().map(|verdict| verdict.name).collect();
+    assert_eq!(names, vec!["confirmed", "info"]);
+    assert_eq!(filtered.confidence(Category::Debugger), 94);
+
+    assert!(engine.configure("confirmed", Severity::Info, 100));
+    assert!(!engine.configure("missing", Severity::Info, 0));
+    let report = engine.run();
+    assert_eq!(report.verdicts[0].severity, Severity::Info);
+    assert_eq!(report.verdicts[0].confidence, 0);
+
+    let received: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let collector = received.clone();
+    let threshold = sink::Threshold {
+        sink: move |report: &anti_debug::engine::Report| {
+            let mut received = collector.lock().unwrap();
+            received.extend(report.detections().map(|verdict| verdict.name.to_string()));
+            Ok(())
+        },
+        min_confidence: 50,
+    };
+    sink::DetectionSink::emit(&threshold, &report).unwrap();
+    sink::DetectionSink::emit(&threshold, &Engine::new().run()).unwrap();
+    assert_eq!(*received.lock().unwrap(), Vec::<String>::new());
+    sink::DetectionSink::emit(
+        &sink::Threshold {
+            min_confidence: 30,
+            ..threshold
+        },
+        &report,
+    )
+    .unwrap();
+    assert_eq!(*received.lock().unwrap(), vec!["suspicious", "info"]);
+}
+
+#[test]
+pub fn metrics_test() {
+    let registry = Arc::new(MemoryMetrics::default());
+    metrics::install(registry.clone());
+
+    let mut engine = Engine::new();
+    for (name, check) in [
+        (
+            "metrics_detected",
+            (|| Ok(Some("evidence".to_string()))) as fn() -> anyhow::Result<Option<String>>,
+        ),
+        ("metrics_failed", || Err(anyhow::Error::msg("failed"))),
+        ("metrics_slow", || {
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(None)
+        }),
+    ] {
+        engine.register(Technique {
+            name,
+            category: Category::Debugger,
+            weight: 10,
+            severity: Severity::Confirmed,
+            false_positive: 0,
+            tags: &[],
+            check,
+        });
+    }
+    engine.run();
+    engine.run_parallel(3, Duration::from_millis(50));
+    metrics::uninstall();
+
+    let labels = |name| [("technique", name), ("category", "Debugger")];
+    assert_eq!(
+        registry.counter(metrics::CHECKS_EXECUTED, &labels("metrics_detected")),
+        2
+    );
+    assert_eq!(
+        registry.counter(metrics::DETECTIONS, &labels("metrics_detected")),
+        2
+    );
+    assert_eq!(
+        registry.counter(metrics::CHECK_FAILURES, &labels("metrics_failed")),
+        2
+    );
+    assert_eq!(
+        registry.counter(metrics::CHECK_TIMEOUTS, &labels("metrics_slow")),
+        1
+    );
+    let latency = registry
+        .histogram(metrics::CHECK_LATENCY, &labels("metrics_slow"))
+        .unwrap();
+    assert!(latency.min >= 0.2);
+    assert!(registry
+        .histogram(metrics::SCAN_LATENCY, &[("mode", "parallel")])
+        .is_some_and(|histogram| histogram.count >= 1));
+    assert!(registry.to_prometheus().contains(
+        "anti_debug_detections_total{category=\"Debugger\",technique=\"metrics_detected\"} 2"
+    ));
+}
+
+#[cfg(feature = "http-sink")]
+#[test]
+pub fn http_sink_test() {
+    use anti_debug::http_sink::{HttpSink, HttpSinkConfig};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (head, String::from_utf8(body).unwrap())
+    });
+
+    let mut engine = Engine::new();
+    engine.register(Technique {
+        name: "detected",
+        category: Category::Debugger,
+        weight: 30,
+        severity: Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check: || Ok(Some("evidence".to_string())),
+    });
+    let report = engine.run();
+
+    let config = HttpSinkConfig {
+        url: format!("http://127.0.0.1:{}/ingest", port),
+        headers: vec![("Authorization".to_string(), "Bearer test".to_string())],
+        flush_interval: Duration::from_secs(3600),
+        retries: 0,
+        ..Default::default()
+    };
+    let sink = HttpSink::start(config.clone()).unwrap();
+    sink.submit(&report);
+    assert_eq!(sink.pending(), 1);
+    assert_eq!(sink.flush().unwrap(), 1);
+    assert_eq!(sink.pending(), 0);
+
+    let (head, body) = server.join().unwrap();
+    assert!(head.starts_with("POST /ingest HTTP/1.1\r\n"));
+    assert!(head.contains("Authorization: Bearer test\r\n"));
+    let batch = json::parse(&body).unwrap();
+    let json::Value::Array(items) = batch else {
+        panic!("batch is not an array");
+    };
+    let report = items[0].get("report").unwrap();
+    assert_eq!(
+        report.get("debugger_score").and_then(json::Value::as_i64),
+        Some(100)
+    );
+    drop(sink);
+
+    // 服务端已经关闭，报告按原顺序留在离线队列中
+    let sink = HttpSink::start(HttpSinkConfig {
+        batch_size: 1,
+        ..config.clone()
+    })
+    .unwrap();
+    sink.submit(&Engine::new().run());
+    sink.submit(&engine.run());
+    assert!(sink.flush().is_err());
+    assert_eq!(sink.pending(), 2);
+    assert!(HttpSink::start(HttpSinkConfig::default()).is_err());
+
+    for url in [
+        "http://[::1]/ingest",
+        "http://[::1]:8080/ingest",
+        "https://[fe80::1]",
+    ] {
+        assert!(HttpSink::start(HttpSinkConfig {
+            url: url.to_string(),
+            ..Default::default()
+        })
+        .is_ok());
+    }
+    for url in ["http://[::1/ingest", "http://[::1]8080/ingest"] {
+        assert!(HttpSink::start(HttpSinkConfig {
+            url: url.to_string(),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    // 服务端接受连接后不响应，drop最多等待shutdown_timeout
+    let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+    let sink = HttpSink::start(HttpSinkConfig {
+        url: format!("http://{}/ingest", silent.local_addr().unwrap()),
+        timeout: Duration::from_secs(10),
+        shutdown_timeout: Duration::from_millis(200),
+        ..config
+    })
+    .unwrap();
+    sink.submit(&Engine::new().run());
+    let start = std::time::Instant::now();
+    drop(sink);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[cfg(feature = "simulate")]
+#[test]
+pub fn simulate_test() {
+    use anti_debug::simulate;
+
+    let mut engine = Engine::new();
+    engine.register(Technique {
+        name: "simulated_probe",
+        category: Category::Debugger,
+        weight: 10,
+        severity: Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check: || Ok(None),
+    });
+    assert!(!engine.run().is_debugged());
+
+    simulate::force("simulated_probe");
+    assert!(simulate::forced_names().contains(&"simulated_probe".to_string()));
+    let report = engine.run();
+    assert!(report.is_debugged());
+    assert_eq!(report.verdicts[0].evidence.as_deref(), Some("simulated"));
+
+    simulate::release("simulated_probe");
+    assert!(!engine.run().is_debugged());
+}
@@ -0,0 +1,129 @@
+#![cfg(all(windows, feature = "std"))]
+
+mod support;
+
+use anti_debug::engine::{builtin_techniques, Category};
+use support::Mode;
+
+/// 附加调试器后会命中的检测技术
+const ATTACH_TECHNIQUES: &[&str] = &[
+    "peb_being_debugged",
+    "peb_being_debugged_asm",
+    "remote_debugger_present",
+    "debug_port",
+    "debug_object",
+    "debug_object_hunt",
+    "debug_flags",
+    "close_invalid_handle",
+    "debug_break_delivery",
+    "int3_delivery",
+    "trap_flag",
+    "api_trap_flag",
+    "int3_record",
+    "prefixed_int3",
+    "hardware_breakpoint",
+    "honey_thread",
+    "debug_string_latency",
+];
+
+/// 只在部分架构上存在、附加调试器后会命中的检测技术
+#[cfg(target_arch = "x86_64")]
+const ARCH_ATTACH_TECHNIQUES: &[&str] = &["nt_close_syscall"];
+#[cfg(target_arch = "x86")]
+const ARCH_ATTACH_TECHNIQUES: &[&str] = &["mov_ss"];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+const ARCH_ATTACH_TECHNIQUES: &[&str] = &[];
+
+/// 只有在调试器中启动时才会命中的检测技术
+const LAUNCH_TECHNIQUES: &[&str] = &["peb_nt_global_flag", "peb_process_heap"];
+
+/// 测试中的调试器无法触发的检测技术与原因
+const EXCLUDED_TECHNIQUES: &[(&str, &str)] = &[
+    ("msr_probe", "needs a hypervisor-based debugger"),
+    ("ept_hooks", "needs a hypervisor-based debugger"),
+    ("hyperdbg_artifacts", "needs HyperDbg to be installed"),
+    ("intel_pt", "needs an Intel PT tracer"),
+    (
+        "software_breakpoints",
+        "the harness does not write int3 into the module",
+    ),
+    (
+        "environment_anomaly",
+        "the harness neither sets debugger environment variables nor rewrites argv[0]",
+    ),
+    (
+        "symbol_engine",
+        "the harness does not load dbghelp into the child",
+    ),
+    (
+        "debug_privilege",
+        "depends on the privileges of the test runner, not on the debugger",
+    ),
+    ("driver_blacklist", "needs a kernel debugger driver"),
+    ("object_blacklist", "needs kernel debugger devices"),
+    (
+        "console_ownership",
+        "the console belongs to the test runner's terminal",
+    ),
+    (
+        "yield_starvation",
+        "only fires while the debugger single-steps or suspends threads",
+    ),
+];
+
+/// 子进程入口，父进程以外直接运行时什么都不做
+#[test]
+pub fn debugger_child() {
+    support::child_main();
+}
+
+/// 正常运行时不命中，在调试器中启动或者附加调试器后命中
+#[test]
+pub fn real_debugger_test() {
+    for &name in ATTACH_TECHNIQUES
+        .iter()
+        .chain(ARCH_ATTACH_TECHNIQUES)
+        .chain(LAUNCH_TECHNIQUES)
+    {
+        assert!(
+            !support::run_child(name, Mode::Detached).unwrap(),
+            "{}",
+            name
+        );
+        assert!(support::run_child(name, Mode::Launch).unwrap(), "{}", name);
+    }
+    for &name in ATTACH_TECHNIQUES.iter().chain(ARCH_ATTACH_TECHNIQUES) {
+        assert!(support::run_child(name, Mode::Attach).unwrap(), "{}", name);
+    }
+}
+
+/// 每个调试器类别的检测技术都在测试列表或者排除列表中，并且列表中的名称都存在
+#[test]
+pub fn debugger_coverage_test() {
+    let techniques = builtin_techniques();
+    let listed: Vec<&str> = ATTACH_TECHNIQUES
+        .iter()
+        .chain(ARCH_ATTACH_TECHNIQUES)
+        .chain(LAUNCH_TECHNIQUES)
+        .copied()
+        .chain(EXCLUDED_TECHNIQUES.iter().map(|(name, _)| *name))
+        .collect();
+
+    for technique in techniques
+        .iter()
+        .filter(|technique| technique.category == Category::Debugger)
+    {
+        let count = listed
+            .iter()
+            .filter(|&&name| name == technique.name)
+            .count();
+        assert_eq!(count, 1, "{}", technique.name);
+    }
+    for name in listed {
+        assert!(
+            techniques.iter().any(|technique| technique.name == name),
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,175 @@
+#![cfg(all(target_os = "linux", feature = "std"))]
+
+use anti_debug::{engine::Engine, integrity, json, linux, platform, taxonomy, util::BeingDebug};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// 自跟踪会改变整个进程的TracerPid，与其他测试串行执行
+static PROCESS: Mutex<()> = Mutex::new(());
+
+#[test]
+pub fn tracer_pid_test() {
+    let _process = PROCESS.lock().unwrap_or_else(|e| e.into_inner());
+    assert_eq!(linux::tracer_pid().unwrap(), 0);
+
+    let stat = linux::read_proc_stat("/proc/self/stat").unwrap();
+    assert_ne!(stat.state, 't');
+    assert_eq!(stat.ppid, std::os::unix::process::parent_id());
+
+    assert!(linux::find_traced_threads().unwrap().is_empty());
+    assert!(!linux::ProcDebug {}.is_being_debug());
+
+    let report = Engine::default().run();
+    assert!(!report.is_debugged());
+}
+
+#[test]
+pub fn self_trace_test() {
+    let _process = PROCESS.lock().unwrap_or_else(|e| e.into_inner());
+    assert!(!linux::probe_attach().unwrap());
+
+    let self_trace = linux::SelfTrace::start(|reason| panic!("{}", reason)).unwrap();
+    let helper = linux::self_tracer_pid();
+    assert_ne!(helper, 0);
+    assert_eq!(linux::tracer_pid().unwrap(), helper);
+    assert!(linux::probe_attach().unwrap());
+    assert!(!linux::ProcDebug {}.is_being_debug());
+    assert!(!Engine::default().run().is_debugged());
+
+    self_trace.stop();
+    assert_eq!(linux::self_tracer_pid(), 0);
+}
+
+#[test]
+pub fn preload_test() {
+    assert!(linux::preload_env()
+        .iter()
+        .all(|(_, value)| !value.is_empty()));
+    assert!(linux::read_preload_file("/nonexistent/ld.so.preload")
+        .unwrap()
+        .is_empty());
+
+    let path = std::env::temp_dir().join("anti_debug_ld.so.preload");
+    std::fs::write(
+        &path,
+        "# comment\n/tmp/a.so /tmp/b.so\n\n/tmp/c.so # hook\n",
+    )
+    .unwrap();
+    let libraries = linux::read_preload_file(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(libraries, ["/tmp/a.so", "/tmp/b.so", "/tmp/c.so"]);
+
+    let code = preload_test as fn() as usize;
+    let regions = linux::read_maps("/proc/self/maps").unwrap();
+    assert!(regions
+        .iter()
+        .any(|region| region.is_executable() && (region.start..region.end).contains(&code)));
+    assert!(linux::find_injected_mappings().is_ok());
+
+    let exe = std::env::current_exe().unwrap();
+    let exe = exe.to_str().unwrap();
+    let region = |perms: &str, path: String| linux::MapRegion {
+        start: 0x1000,
+        end: 0x2000,
+        perms: perms.to_string(),
+        offset: 0,
+        path,
+    };
+    let suspicious = |perms: &str, path: &str| {
+        linux::is_suspicious_mapping(&region(perms, path.to_string()), exe)
+    };
+    assert!(suspicious("r-xp", ""));
+    assert!(suspicious("r-xp", "/memfd:gadget (deleted)"));
+    assert!(suspicious("r-xp", "/data/local/tmp/frida-agent-64.so"));
+    assert!(!suspicious("rw-p", ""));
+    assert!(!suspicious("r-xp", "[vdso]"));
+    assert!(!suspicious("r-xp", &format!("{} (deleted)", exe)));
+
+    // 被替换的共享库原路径上仍有新文件，加载后被删除的共享库原路径不存在
+    let library = std::env::temp_dir().join("anti_debug_replaced.so");
+    std::fs::write(&library, b"").unwrap();
+    let deleted = format!("{} (deleted)", library.display());
+    assert!(!suspicious("r-xp", &deleted));
+    std::fs::remove_file(&library).unwrap();
+    assert!(suspicious("r-xp", &deleted));
+}
+
+#[test]
+pub fn text_integrity_test() {
+    let code = text_integrity_test as fn() as usize;
+    let mappings = linux::text_mappings().unwrap();
+    assert!(mappings
+        .iter()
+        .any(|region| (region.start..region.end).contains(&code)));
+    assert!(!linux::read_mapping_from_disk(&mappings[0])
+        .unwrap()
+        .is_empty());
+
+    assert!(linux::find_text_breakpoints(16).unwrap().is_empty());
+    assert!(integrity::verify(b"key").is_err());
+    assert_eq!(integrity::verify_with_global_key().unwrap(), None);
+
+    // 签名只写入只读数据中的嵌入区，重复签名得到相同的HMAC
+    let exe = std::env::temp_dir().join("anti_debug_integrity_sign");
+    std::fs::copy(std::env::current_exe().unwrap(), &exe).unwrap();
+    let mac = integrity::sign_file(&exe, b"key").unwrap();
+    assert_eq!(integrity::sign_file(&exe, b"key").unwrap(), mac);
+    assert_ne!(integrity::sign_file(&exe, b"other").unwrap(), mac);
+    std::fs::remove_file(&exe).unwrap();
+}
+
+#[test]
+pub fn platform_test() {
+    let _process = PROCESS.lock().unwrap_or_else(|e| e.into_inner());
+    let detector = platform::current();
+    assert_eq!(detector.platform(), "linux");
+    assert!(!detector.is_debugged());
+    assert!(!detector.report().is_debugged());
+
+    let detected = Arc::new(AtomicBool::new(false));
+    let flag = detected.clone();
+    let watch = detector.watch(
+        Duration::from_millis(10),
+        Box::new(move |_| flag.store(true, Ordering::SeqCst)),
+    );
+    std::thread::sleep(Duration::from_millis(50));
+    watch.stop();
+    assert!(!detected.load(Ordering::SeqCst));
+}
+
+#[test]
+pub fn taxonomy_test() {
+    let techniques = anti_debug::engine::builtin_techniques();
+    assert!(techniques
+        .iter()
+        .all(|technique| !technique.tags.is_empty()));
+    let tracer = techniques
+        .iter()
+        .find(|technique| technique.name == "tracer_pid")
+        .unwrap();
+    assert!(tracer.tags.contains(&taxonomy::DEBUGGER_EVASION));
+    assert_eq!(taxonomy::DEBUGGER_EVASION.to_string(), "mitre-attack:T1622");
+
+    let mut engine = Engine::new();
+    engine.register(tracer.clone());
+    let report = json::parse(&engine.run().to_json()).unwrap();
+    let json::Value::Array(verdicts) = report.get("verdicts").unwrap() else {
+        panic!("verdicts is not an array");
+    };
+    let json::Value::Array(tags) = verdicts[0].get("tags").unwrap() else {
+        panic!("tags is not an array");
+    };
+    assert_eq!(
+        tags[0].get("scheme").and_then(json::Value::as_str),
+        Some("mitre-attack")
+    );
+    assert_eq!(
+        tags[0].get("id").and_then(json::Value::as_str),
+        Some("T1622")
+    );
+}
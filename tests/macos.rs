@@ -0,0 +1,52 @@
+#![cfg(all(target_os = "macos", feature = "std"))]
+
+use anti_debug::{engine::Engine, macos, platform, util::BeingDebug};
+
+#[test]
+pub fn sysctl_test() {
+    assert!(!macos::is_traced().unwrap());
+    assert!(!macos::SysctlDebug {}.is_being_debug());
+
+    let path = macos::process_path(std::process::id() as i32).unwrap();
+    assert_eq!(
+        std::path::Path::new(&path),
+        std::env::current_exe().unwrap().canonicalize().unwrap()
+    );
+    assert_eq!(macos::check_debugger_parent().unwrap(), None);
+
+    let report = Engine::default().run();
+    assert!(!report.is_debugged());
+}
+
+#[test]
+pub fn deny_attach_test() {
+    assert!(!macos::is_attach_denied());
+    assert_eq!(macos::run_denied(|| 7).unwrap(), 7);
+
+    // 检测只查询状态，不会替调用方应用PT_DENY_ATTACH
+    assert!(!Engine::default().run().is_debugged());
+    assert!(!macos::is_attach_denied());
+
+    assert!(!macos::deny_attach().unwrap());
+    assert!(macos::is_attach_denied());
+}
+
+#[test]
+pub fn dyld_image_test() {
+    assert!(macos::dyld_env().is_empty());
+
+    let images = macos::loaded_images();
+    assert!(images.len() > 1);
+    assert!(images
+        .iter()
+        .any(|image| image.name.starts_with("/usr/lib/libSystem")));
+    assert!(macos::find_injected_images().is_empty());
+}
+
+#[test]
+pub fn platform_test() {
+    let detector = platform::current();
+    assert_eq!(detector.platform(), "macos");
+    assert!(!detector.is_debugged());
+    assert!(!detector.report().is_debugged());
+}
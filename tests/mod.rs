@@ -1,5 +1,38 @@
-use anti_debug::{breakpoint, nt_query, peb::*, thread, util::BeingDebug};
-use windows::Win32::System::Threading::GetCurrentThread;
+#![cfg(all(windows, feature = "std"))]
+
+use anti_debug::{
+    anti_dump, breakpoint, cache, capability, clean_ntdll, debug_blocker, debug_object, decoy,
+    engine, environment, exception, handle_watch, hook, hypervisor, imports, inject, integrity,
+    ipc, ipt, launcher, ldr, logging, module, nt_query,
+    peb::*,
+    platform, resolve, response, sandbox, scan, scheduler, signature, snapshot, syscall, thread,
+    thread_monitor, timing,
+    util::{self, BeingDebug},
+    vm, watchdog, wow64,
+};
+use std::time::Duration;
+#[cfg(target_arch = "x86_64")]
+use windows::Win32::System::Diagnostics::Debug::CONTEXT_DEBUG_REGISTERS_AMD64 as CONTEXT_DEBUG_REGISTERS;
+#[cfg(target_arch = "x86")]
+use windows::Win32::System::Diagnostics::Debug::CONTEXT_DEBUG_REGISTERS_X86 as CONTEXT_DEBUG_REGISTERS;
+use windows::{
+    core::HSTRING,
+    Wdk::System::SystemInformation::SystemProcessInformation,
+    Win32::{
+        Foundation::CloseHandle,
+        System::{
+            Diagnostics::Debug::{GetThreadContext, SetThreadContext, CONTEXT},
+            StationsAndDesktops::{
+                CloseDesktop, CreateDesktopW, SetThreadDesktop, DESKTOP_CONTROL_FLAGS,
+                DESKTOP_CREATEWINDOW, DESKTOP_READOBJECTS, HDESK,
+            },
+            Threading::{
+                CreateThread, GetCurrentThread, ResumeThread, WaitForSingleObject, INFINITE,
+                THREAD_CREATE_SUSPENDED,
+            },
+        },
+    },
+};
 
 #[test]
 pub fn peb_being_debugged_test() {
@@ -32,6 +65,13 @@ pub fn peb_process_heap_test() {
     );
 }
 
+#[test]
+pub fn peb_fast_path_test() {
+    assert_eq!(WinPeb::fast_being_debugged(), false);
+    assert_eq!(WinPeb::fast_nt_global_flag(), false);
+    assert_eq!(WinPeb::fast_heap_flags(), false);
+}
+
 #[test]
 pub fn hardware_breakpoint_test() {
     let hthread = unsafe {GetCurrentThread()};
@@ -43,6 +83,91 @@ pub fn hardware_breakpoint_test() {
     assert!(breakpoint::HardwareBreakPoint::clean_hardware_breakpoint(hthread).is_ok());
 }
 
+#[test]
+pub fn spawn_with_clean_context_test() {
+    unsafe extern "system" fn idle(_: *mut std::ffi::c_void) -> u32 {
+        0
+    }
+
+    // 在挂起的线程中预先写入DR0-DR3，恢复运行前应当被清除
+    let hthread =
+        unsafe { CreateThread(None, 0, Some(idle), None, THREAD_CREATE_SUSPENDED, None) }.unwrap();
+    let mut context = CONTEXT {
+        ContextFlags: CONTEXT_DEBUG_REGISTERS,
+        ..Default::default()
+    };
+    unsafe { GetThreadContext(hthread, &mut context) }.unwrap();
+    context.Dr0 = 0x1000;
+    context.Dr1 = 0x2000;
+    context.Dr2 = 0x3000;
+    context.Dr3 = 0x4000;
+    context.Dr7 = 0x55;
+    unsafe { SetThreadContext(hthread, &context) }.unwrap();
+    assert!(breakpoint::DebugRegisters::from_thread(hthread)
+        .unwrap()
+        .is_set());
+
+    assert!(breakpoint::clean_context(hthread).unwrap());
+    assert_eq!(
+        breakpoint::DebugRegisters::from_thread(hthread).unwrap(),
+        breakpoint::DebugRegisters::default()
+    );
+    assert!(!breakpoint::clean_context(hthread).unwrap());
+    unsafe {
+        assert_ne!(ResumeThread(hthread), u32::MAX);
+        WaitForSingleObject(hthread, INFINITE);
+        CloseHandle(hthread).unwrap();
+    }
+
+    let worker = breakpoint::spawn_with_clean_context(|| 42).unwrap();
+    assert!(!worker.had_preset_breakpoints());
+    assert_eq!(worker.join().unwrap(), 42);
+
+    // 线程内部执行前再次清除，并且登记为自己创建的线程
+    let mut monitor = thread_monitor::ThreadMonitor::new().unwrap();
+    let (started, running) = std::sync::mpsc::channel::<()>();
+    let (finish, finished) = std::sync::mpsc::channel::<()>();
+    let worker = breakpoint::spawn_with_clean_context(move || {
+        let registers =
+            breakpoint::DebugRegisters::from_thread(unsafe { GetCurrentThread() }).unwrap();
+        started.send(()).unwrap();
+        let _ = finished.recv();
+        registers.is_set()
+    })
+    .unwrap();
+    running.recv().unwrap();
+    let foreign: Vec<u32> = monitor
+        .poll()
+        .unwrap()
+        .into_iter()
+        .chain(monitor.poll().unwrap())
+        .map(|thread| thread.tid)
+        .collect();
+    finish.send(()).unwrap();
+    assert!(!foreign.contains(&worker.thread_id()));
+    assert!(!worker.join().unwrap());
+}
+
+#[test]
+pub fn debug_register_guard_test() {
+    let mut guard = breakpoint::DebugRegisterGuard::new().unwrap();
+    assert!(guard.baseline().dr.iter().all(|dr| *dr == 0));
+    assert_eq!(guard.check().unwrap(), None);
+    let hthread = unsafe { GetCurrentThread() };
+    assert!(breakpoint::HardwareBreakPoint::clean_hardware_breakpoint(hthread).is_ok());
+    assert_eq!(guard.check().unwrap(), None);
+    assert_eq!(guard.baseline(), breakpoint::DebugRegisters::default());
+
+    let context = breakpoint::DebugRegisters::from_thread(unsafe { GetCurrentThread() }).unwrap();
+    let exception = breakpoint::DebugRegisters::from_exception().unwrap();
+    assert!(context.same_breakpoints(&exception));
+
+    let pid = unsafe { windows::Win32::System::Threading::GetCurrentProcessId() };
+    let threads = breakpoint::process_debug_registers(pid).unwrap();
+    assert!(!threads.is_empty());
+    assert!(threads.iter().all(|(_, registers)| !registers.is_set()));
+}
+
 #[test]
 pub fn nt_query_debug_test() {
     let anti = nt_query::NtQueryDebug {};
@@ -58,3 +183,969 @@ pub fn honey_thread_test() {
         false
     )
 }
+
+#[test]
+pub fn handle_watch_test() {
+    let mut t = thread::HoneyThread::default();
+    t.set_honey_thread_current_process().unwrap();
+    let mut watch = handle_watch::HandleWatch::new(std::process::id());
+    watch.watch(t.thread_handle.unwrap()).unwrap();
+    assert!(watch.poll().unwrap().added.is_empty());
+    assert!(watch.poll().unwrap().is_empty());
+}
+
+#[test]
+pub fn environment_anomaly_test() {
+    // cargo test启动时argv[0]就是测试程序本身
+    assert_eq!(environment::check_command_line(), None);
+    assert_eq!(
+        environment::check_environment_anomaly(),
+        environment::check_env_vars()
+    );
+    assert!(signature::matches(signature::SignatureKind::Env, "_NO_DEBUG_HEAP").is_some());
+}
+
+#[test]
+pub fn symbol_engine_loaded_test() {
+    assert_eq!(module::check_symbol_engine_loaded(&[]), None);
+}
+
+#[test]
+pub fn debug_privilege_audit_test() {
+    let audit = environment::audit_debug_privilege().expect("OpenProcessToken error");
+    assert_eq!(audit.is_being_debug(), false);
+
+    // 服务启动的进程只记录父进程的状态
+    let parent = environment::DebugPrivilegeAudit {
+        parent_enabled: Some(true),
+        parent_service: Some(false),
+        ..Default::default()
+    };
+    assert!(parent.is_being_debug());
+    assert!(!environment::DebugPrivilegeAudit {
+        parent_service: Some(true),
+        ..parent.clone()
+    }
+    .is_being_debug());
+    assert!(!environment::DebugPrivilegeAudit {
+        self_elevated: true,
+        ..parent
+    }
+    .is_being_debug());
+}
+
+#[test]
+pub fn desktop_anomaly_test() {
+    environment::check_desktop_anomaly().expect("GetUserObjectInformationW error");
+
+    // 切换到新建的桌面上执行检查，新桌面不是输入桌面
+    let name = format!("anti_debug_{}", std::process::id());
+    let hdesk = unsafe {
+        CreateDesktopW(
+            &HSTRING::from(name.as_str()),
+            None,
+            None,
+            DESKTOP_CONTROL_FLAGS(0),
+            DESKTOP_READOBJECTS.0 | DESKTOP_CREATEWINDOW.0,
+            None,
+        )
+    }
+    .expect("CreateDesktopW error");
+    let handle = hdesk.0 as usize;
+    let info = std::thread::spawn(move || {
+        unsafe { SetThreadDesktop(HDESK(handle as _)) }.expect("SetThreadDesktop error");
+        environment::check_desktop_anomaly().expect("GetUserObjectInformationW error")
+    })
+    .join()
+    .unwrap();
+    unsafe { CloseDesktop(hdesk) }.unwrap();
+
+    let info = info.expect("new desktop not reported");
+    assert_eq!(info.desktop, name);
+}
+
+#[test]
+pub fn wine_test() {
+    match environment::get_wine_version() {
+        Some(version) => assert_eq!(
+            environment::check_wine(),
+            Some(format!("wine_get_version: {}", version))
+        ),
+        // 原生系统一定会填充KUSER_SHARED_DATA
+        None => assert_ne!(
+            environment::check_wine().as_deref(),
+            Some("KUSER_SHARED_DATA incomplete")
+        ),
+    }
+}
+
+#[test]
+pub fn remote_session_test() {
+    environment::check_remote_session().expect("WTSQuerySessionInformationW error");
+}
+
+#[test]
+pub fn console_ownership_test() {
+    environment::check_console_ownership().expect("GetConsoleProcessList error");
+}
+
+#[test]
+pub fn boot_integrity_test() {
+    let integrity = environment::get_boot_integrity().expect("query code integrity error");
+    assert_eq!(
+        environment::check_driver_signing().unwrap().is_some(),
+        integrity.is_being_debug()
+    );
+
+    let weakened = environment::BootIntegrity {
+        code_integrity_options: environment::CODEINTEGRITY_OPTION_ENABLED
+            | environment::CODEINTEGRITY_OPTION_TESTSIGN,
+        ..Default::default()
+    };
+    assert_eq!(weakened.enforcement_gaps(), vec!["test signing enabled"]);
+}
+
+#[test]
+pub fn signature_match_test() {
+    let mut signatures = signature::Signatures::embedded();
+    signatures
+        .parse("[process]\n+x64dbg.exe\n+*olly*\n+re:^ida(64)?\\.exe$\n-ollyfriendly.exe\n")
+        .expect("parse signatures error");
+
+    let kind = signature::SignatureKind::Process;
+    assert!(signatures.matches(kind, "X64DBG.EXE").is_some());
+    assert!(signatures.matches(kind, "ollydbg.exe").is_some());
+    assert!(signatures.matches(kind, "ida64.exe").is_some());
+    assert_eq!(signatures.matches(kind, "ollyfriendly.exe"), None);
+    assert_eq!(signatures.matches(kind, "notepad.exe"), None);
+    assert!(signatures
+        .matches(signature::SignatureKind::Env, "_NO_DEBUG_HEAP")
+        .is_some());
+
+    // 设备对象只能逐个探测，只保留完整匹配且没有被白名单放行的规则
+    signatures
+        .parse("[object]\n+\\\\.\\Probe*\n+\\\\.\\Allowed\n-\\\\.\\allowed\n")
+        .expect("parse signatures error");
+    let objects = signatures.exact_blacklist(signature::SignatureKind::Object);
+    assert!(objects.contains(&"\\\\.\\titanhide".to_string()));
+    assert!(!objects
+        .iter()
+        .any(|object| object.contains("probe") || object.contains("allowed")));
+    assert!(signatures
+        .matches(signature::SignatureKind::Window, "WinDbgFrameClass")
+        .is_some());
+    assert!(signatures
+        .matches(signature::SignatureKind::Driver, "HyperHideDrv.sys")
+        .is_some());
+}
+
+#[test]
+pub fn cpuid_hypervisor_test() {
+    assert_eq!(
+        vm::check_cpuid_hypervisor().is_some(),
+        vm::is_hypervisor_present()
+    );
+    assert_eq!(vm::Hypervisor::from("KVMKVMKVM\0\0\0"), vm::Hypervisor::Kvm);
+}
+
+#[test]
+pub fn vm_artifacts_test() {
+    assert!(vm::scan_vm_artifacts()
+        .iter()
+        .all(|artifact| !artifact.evidence.is_empty()));
+}
+
+#[test]
+pub fn mac_address_test() {
+    assert!(vm::get_adapters().is_ok());
+    assert!(vm::check_mac_address()
+        .expect("GetAdaptersAddresses error")
+        .iter()
+        .all(|artifact| artifact.hypervisor == vm::Hypervisor::HyperV));
+}
+
+#[test]
+pub fn hardware_profile_test() {
+    let profile = sandbox::HardwareProfile::query().expect("query hardware profile error");
+    assert_eq!(profile.is_being_debug(), false);
+}
+
+#[test]
+pub fn firmware_tables_test() {
+    assert!(vm::scan_firmware_tables()
+        .iter()
+        .all(|artifact| !artifact.evidence.is_empty()));
+}
+
+#[cfg(feature = "wmi")]
+#[test]
+pub fn wmi_environment_test() {
+    anti_debug::wmi::check_wmi_environment().expect("WMI query error");
+}
+
+#[test]
+pub fn liveness_report_test() {
+    let mut observer = sandbox::LivenessObserver::default();
+    observer.sample();
+    let report = observer.report();
+    assert_eq!(report.samples, 1);
+    assert!(report.checks().iter().all(|check| check.name == "uptime"
+        || check.name == "recent_documents"
+        || !check.suspicious));
+
+    // 共享观察器的采样次数随调用累积
+    assert!(sandbox::sample_liveness().samples >= 1);
+}
+
+#[test]
+pub fn sandbox_dlls_test() {
+    let _ = sandbox::check_sandbox_dlls();
+}
+
+#[test]
+pub fn engine_test() {
+    let report = engine::Engine::default().run();
+    assert_eq!(report.is_debugged(), false);
+    assert_eq!(report.debugger_score(), 0);
+    assert!(report.environment_score() <= 100);
+}
+
+#[test]
+pub fn engine_parallel_test() {
+    let mut engine = engine::Engine::new();
+    engine.register(engine::Technique {
+        name: "fast",
+        category: engine::Category::Debugger,
+        weight: 10,
+        severity: engine::Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check: || Ok(None),
+    });
+    engine.register(engine::Technique {
+        name: "slow",
+        category: engine::Category::Debugger,
+        weight: 10,
+        severity: engine::Severity::Confirmed,
+        false_positive: 0,
+        tags: &[],
+        check: || {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(None)
+        },
+    });
+
+    let report = engine.run_parallel(2, Duration::from_millis(50));
+    assert_eq!(report.verdicts[0].timed_out, false);
+    assert_eq!(report.verdicts[1].timed_out, true);
+    assert_eq!(report.is_debugged(), false);
+}
+
+#[test]
+pub fn inline_hooks_test() {
+    hook::scan_system_inline_hooks().expect("scan inline hooks error");
+}
+
+#[test]
+pub fn iat_hooks_test() {
+    hook::scan_iat_hooks().expect("scan iat hooks error");
+
+    let checksum = hook::record_iat_baseline().unwrap();
+    assert_eq!(hook::record_iat_baseline().unwrap(), checksum);
+    assert_eq!(hook::verify_iat_baseline().unwrap(), vec![]);
+}
+
+#[test]
+pub fn eat_hooks_test() {
+    hook::scan_system_eat_hooks().expect("scan eat hooks error");
+}
+
+#[test]
+pub fn trampolines_test() {
+    hook::register_critical_api("kernel32.dll", "Sleep");
+    assert!(hook::critical_apis()
+        .iter()
+        .any(|(module, function)| module == "kernel32.dll" && function == "Sleep"));
+    let _ = hook::scan_trampolines();
+}
+
+#[test]
+pub fn debug_api_neutered_test() {
+    for function in ["IsDebuggerPresent", "CheckRemoteDebuggerPresent"] {
+        hook::check_debug_api_neutered(function).expect("check debug api error");
+    }
+}
+
+#[test]
+pub fn dll_watcher_test() {
+    let policy = module::DllPolicy {
+        trusted_dirs: vec![],
+    };
+    let watcher = module::DllWatcher::start(policy).expect("start dll watcher error");
+    let _ = unsafe {
+        windows::Win32::System::LibraryLoader::LoadLibraryW(windows::core::w!("version.dll"))
+    };
+    // 筛查在工作线程中完成，事件异步到达
+    let mut events = Vec::new();
+    for _ in 0..50 {
+        events.extend(watcher.poll());
+        if !events.is_empty() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(events
+        .iter()
+        .any(|event| event.name.eq_ignore_ascii_case("version.dll")));
+
+    let policy = module::DllPolicy::default();
+    assert_eq!(
+        policy.screen(std::path::Path::new("C:\\Windows\\System32\\version.dll")),
+        None
+    );
+    assert_eq!(
+        policy.screen(std::path::Path::new("C:\\Windows\\Temp\\version.dll")),
+        Some("untrusted path".to_string())
+    );
+}
+
+#[cfg(feature = "eventlog")]
+#[test]
+pub fn eventlog_test() {
+    use anti_debug::eventlog;
+
+    assert_eq!(eventlog::event_id(engine::Category::Tampering), 1003);
+    assert_eq!(eventlog::event_id(engine::Category::Debugger), 1001);
+}
+
+/// 会向系统的应用程序日志写入事件，需要手动运行：`cargo test --features eventlog -- --ignored`
+#[cfg(feature = "eventlog")]
+#[test]
+#[ignore = "writes to the system event log"]
+pub fn eventlog_write_test() {
+    use anti_debug::eventlog;
+
+    let log =
+        eventlog::EventLog::open(eventlog::DEFAULT_SOURCE).expect("RegisterEventSourceW error");
+    assert_eq!(
+        log.report(&engine::Report::default())
+            .expect("ReportEventW error"),
+        1
+    );
+}
+
+#[cfg(feature = "authenticode")]
+#[test]
+pub fn authenticode_test() {
+    anti_debug::authenticode::check_loaded_modules().expect("verify modules error");
+}
+
+#[test]
+pub fn integrity_test() {
+    let mac = integrity::hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+    assert_eq!(
+        mac[..4],
+        [0x5b, 0xdc, 0xc1, 0x46],
+        "HMAC-SHA256 test vector mismatch"
+    );
+
+    // 测试程序没有经过签名工具处理
+    assert!(integrity::verify(b"key").is_err());
+    assert_eq!(integrity::verify_with_global_key().unwrap(), None);
+}
+
+#[test]
+pub fn timing_test() {
+    assert!(timing::tick_count().abs_diff(unsafe {
+        windows::Win32::System::SystemInformation::GetTickCount64()
+    }) < 1000);
+    timing::check_timing_api_hooks().expect("check timing api error");
+    timing::check_time_virtualization(50).expect("check time error");
+
+    let stats = timing::check_yield_starvation(8).unwrap();
+    assert_eq!(stats.rounds, 8);
+    assert!(stats.yielded <= stats.rounds);
+    let half = timing::YieldStats {
+        rounds: 8,
+        yielded: 4,
+    };
+    assert!(!half.is_starved());
+    assert!(timing::YieldStats { yielded: 5, ..half }.is_starved());
+    assert!(timing::single_step_latency(4).unwrap() > 0);
+
+    let latency = timing::debug_string_latency(16);
+    assert_eq!(latency.rounds, 16);
+    assert!(latency.short > 0);
+    assert!(!latency.is_delivered());
+}
+
+#[test]
+pub fn syscall_stubs_test() {
+    assert_eq!(syscall::parse_stub(&[0xc3; 32]), None);
+    syscall::verify_crate_stubs().expect("verify syscall stubs error");
+}
+
+#[test]
+pub fn debug_blocker_test() {
+    // 伪造保护标记(父进程ID正确)，没有保护父进程写回确认值，不能跳过保护
+    let parent = nt_query::get_parent_process_id(unsafe {
+        windows::Win32::System::Threading::GetCurrentProcess()
+    })
+    .expect("get parent process id error");
+    std::env::set_var(debug_blocker::PROTECTED_CHILD_ENV, parent.to_string());
+    assert_eq!(debug_blocker::is_protected_child(), false);
+    assert!(debug_blocker::protect().is_err());
+}
+
+#[test]
+pub fn self_debug_test() {
+    assert_eq!(debug_blocker::is_self_debug_helper(), false);
+    assert!(debug_blocker::run_self_debug_helper().is_err());
+}
+
+#[test]
+pub fn watchdog_test() {
+    use windows::Win32::{Foundation::CloseHandle, System::Threading::*};
+
+    assert_eq!(watchdog::is_watchdog_child(), false);
+
+    // 审计一个没有被调试的子进程，子进程结束后报告退出
+    let mut child = std::process::Command::new("cmd.exe")
+        .args(["/c", "pause"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .expect("spawn child error");
+    let hprocess =
+        unsafe { OpenProcess(PROCESS_ALL_ACCESS, false, child.id()) }.expect("OpenProcess error");
+    let mut audit = watchdog::ProcessAudit::new(hprocess, child.id(), "child", vec![]);
+    let evidence = audit.check().expect("audit child error");
+    assert!(!evidence
+        .iter()
+        .any(|evidence| evidence.contains("being debugged")));
+
+    child.kill().expect("kill child error");
+    child.wait().expect("wait child error");
+    assert_eq!(
+        audit.check().expect("audit child error"),
+        vec![format!("child {} exited", child.id())]
+    );
+    let _ = unsafe { CloseHandle(hprocess) };
+}
+
+#[test]
+pub fn launcher_test() {
+    assert_eq!(
+        launcher::command_line("C:\\a b\\x.exe", &["-n", "", "say \"hi\"", "dir\\"]),
+        r#""C:\a b\x.exe" -n "" "say \"hi\"" dir\"#
+    );
+
+    let config = launcher::ProtectConfig {
+        interval: Duration::from_millis(50),
+        response: launcher::ProtectResponse::Log,
+        ..Default::default()
+    };
+    let cmd = std::env::var("ComSpec").unwrap_or("C:\\Windows\\System32\\cmd.exe".to_string());
+    let target = launcher::Protected::launch(cmd, &["/c", "exit", "7"], config).unwrap();
+    assert!(target.pid() > 0);
+    assert_eq!(target.run(|_, _| {}).unwrap(), 7);
+}
+
+#[test]
+pub fn inject_test() {
+    let pid = std::process::id();
+    let system = std::path::Path::new("C:\\Windows\\System32");
+    for method in [
+        inject::InjectMethod::RemoteThread,
+        inject::InjectMethod::NtCreateThreadEx,
+    ] {
+        inject::inject_process(pid, &system.join("version.dll"), method).unwrap();
+        assert!(
+            inject::inject_process(pid, &system.join("missing_anti_debug.dll"), method).is_err()
+        );
+    }
+    assert!(hook::get_module("version.dll").is_ok());
+}
+
+#[test]
+pub fn detach_debugger_test() {
+    assert!(response::query_debug_object().unwrap().is_none());
+    assert_eq!(response::detach_debugger().unwrap(), false);
+}
+
+#[test]
+pub fn anti_dump_test() {
+    assert_eq!(anti_dump::is_headers_erased(), false);
+    assert_eq!(anti_dump::with_headers(|| 1), 1);
+}
+
+#[test]
+pub fn hide_module_test() {
+    // 测试程序不是DLL，拒绝摘除主模块
+    assert!(ldr::hide_current_module().is_err());
+}
+
+#[test]
+pub fn respond_to_attackers_test() {
+    environment::find_blacklisted_processes().expect("enumerate processes error");
+    // 默认策略只记录不处置
+    let policy = response::ProcessPolicy::default();
+    assert!(response::respond_to_attackers(&policy)
+        .iter()
+        .all(|outcome| outcome.action.is_none()));
+
+    // 启动测试的父进程持有当前进程的句柄，不算攻击者
+    let parent = nt_query::get_parent_process_id(unsafe {
+        windows::Win32::System::Threading::GetCurrentProcess()
+    })
+    .expect("get parent process id error");
+    assert!(response::find_attackers(&policy)
+        .iter()
+        .all(|(pid, _, _, _)| *pid != parent));
+}
+
+#[test]
+pub fn harass_debugger_test() {
+    // 没有调试器时伪造的异常由VEH处理，进程不受影响
+    let config = response::HarassConfig {
+        rate: 100,
+        duration: Duration::from_millis(50),
+        exceptions: true,
+    };
+    assert!(response::harass_debugger(&config).join().unwrap() > 0);
+}
+
+#[test]
+pub fn wipe_test() {
+    let mut secret = *b"secret key";
+    response::wipe(&mut secret);
+    assert!(secret.iter().all(|byte| *byte == 0));
+}
+
+#[test]
+pub fn decoy_test() {
+    for (name, decoy) in decoy::DECOYS {
+        assert_eq!(decoy(), false, "{}", name);
+    }
+    decoy::run_decoys(decoy::DECOYS.len());
+}
+
+#[test]
+pub fn system_information_test() {
+    let current_pid = std::process::id();
+    assert!(util::enumerate_processes()
+        .unwrap()
+        .iter()
+        .any(|(pid, _)| *pid == current_pid));
+
+    // 嵌套调用时内层使用临时缓冲区
+    let class = thread::SYSTEM_EXTENDED_HANDLE_INFORMATION;
+    let nested = util::with_system_information(class, |outer| {
+        util::with_system_information(class, |inner| outer.as_ptr() != inner.as_ptr()).unwrap()
+    });
+    assert!(nested.unwrap());
+}
+
+#[test]
+pub fn cache_test() {
+    cache::set_ttl(SystemProcessInformation, Duration::from_secs(60));
+    // 有效期内两次枚举使用同一份快照
+    assert_eq!(
+        util::enumerate_processes().unwrap(),
+        util::enumerate_processes().unwrap()
+    );
+    cache::set_ttl(SystemProcessInformation, Duration::ZERO);
+    assert!(cache::ttl(SystemProcessInformation).is_zero());
+}
+
+#[test]
+pub fn logging_event_test() {
+    static EVENTS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    assert_eq!(
+        logging::event_id("anti_debug::peb:1"),
+        logging::event_id("anti_debug::peb:1")
+    );
+    assert_ne!(
+        logging::event_id("anti_debug::peb:1"),
+        logging::event_id("anti_debug::peb:2")
+    );
+
+    logging::set_event_hook(Some(|_| {
+        EVENTS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }));
+    WinPeb::get_peb_address();
+    logging::set_event_hook(None);
+    assert!(EVENTS.load(std::sync::atomic::Ordering::Relaxed) > 0);
+}
+
+#[test]
+pub fn capability_test() {
+    let capabilities = capability::get();
+    assert!(capabilities.version.major >= 6);
+    assert!(capabilities.version.build > 0);
+    assert!(std::ptr::eq(capabilities, capability::get()));
+    assert!(capabilities.system_class_available(thread::SYSTEM_EXTENDED_HANDLE_INFORMATION));
+    assert!(!WinPeb::fast_nt_global_flag());
+    assert!(!WinPeb::fast_heap_flags());
+}
+
+#[test]
+pub fn scheduler_test() {
+    let mut scheduler = scheduler::Scheduler::new(scheduler::SchedulerConfig {
+        min_gap: Duration::from_millis(10),
+        max_gap: Duration::from_millis(50),
+        cpu_limit: 0.5,
+    });
+    for technique in engine::builtin_techniques() {
+        if technique.name == "honey_thread" || technique.name == "peb_being_debugged" {
+            scheduler.register(technique);
+        }
+    }
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let scan = scheduler.start(move |verdict| {
+        let _ = sender.send((verdict.name, verdict.detected));
+    });
+    for _ in 0..4 {
+        let (name, detected) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(!detected, "{} detected", name);
+    }
+    scan.stop();
+}
+
+#[test]
+pub fn cache_sweep_test() {
+    let class = capability::SYSTEM_KERNEL_DEBUGGER_INFORMATION;
+    let address = || {
+        cache::with_system_information(class, |data| Ok(data.as_ptr() as usize)).unwrap()
+    };
+
+    let sweep = cache::sweep(&[class]);
+    let first = address();
+    assert_eq!(first, address());
+    drop(sweep);
+    assert_eq!(cache::ttl(class), Duration::ZERO);
+}
+
+#[test]
+pub fn scan_test() {
+    let mut data: Vec<u8> = (0..100_000u32).map(|i| (i % 0xCB) as u8).collect();
+    assert_eq!(scan::find_byte(&data, 0xCC), None);
+    assert_eq!(scan::find_mismatch(&data, &data.clone()), None);
+
+    let copy = data.clone();
+    data[77_777] = 0xCC;
+    assert_eq!(scan::find_byte(&data, 0xCC), Some(77_777));
+    assert_eq!(scan::find_bytes(&data, &data[77_770..77_780]), Some(77_770));
+    assert_eq!(scan::find_differences(&data, &copy, 16), vec![77_777]);
+
+    let hmodule = module::get_loaded_modules().unwrap()[0];
+    let breakpoints = breakpoint::SoftwareBreakPoint::find_in_module(hmodule, 16).unwrap();
+    assert!(breakpoints.is_empty());
+}
+
+#[test]
+pub fn platform_test() {
+    let detector = platform::current();
+    assert_eq!(detector.platform(), "windows");
+    assert_eq!(detector.is_debugged(), false);
+    assert_eq!(detector.report().is_debugged(), false);
+}
+
+#[test]
+pub fn resolve_test() {
+    let ntdll = hook::get_module("ntdll.dll").unwrap();
+    assert_eq!(
+        resolve!("ntdll.dll", "NtQueryInformationProcess"),
+        hook::get_proc_address(ntdll, "NtQueryInformationProcess")
+    );
+    // kernel32!HeapAlloc转发到ntdll!RtlAllocateHeap
+    let kernel32 = hook::get_module("kernel32.dll").unwrap();
+    assert_eq!(
+        resolve!("KERNEL32.DLL", "HeapAlloc"),
+        hook::get_proc_address(kernel32, "HeapAlloc")
+    );
+    // kernel32!AddDllDirectory转发到ApiSet虚拟模块api-ms-win-core-libraryloader-l1-1-0
+    assert_eq!(
+        resolve!("kernel32.dll", "AddDllDirectory"),
+        hook::get_proc_address(kernel32, "AddDllDirectory")
+    );
+    assert!(resolve::api_set_host("api-ms-win-core-synch-l1-2-0.dll")
+        .is_some_and(|host| host.eq_ignore_ascii_case("kernelbase.dll")));
+    assert_eq!(resolve::api_set_host("api-ms-win-not-a-set-l1-1-0"), None);
+    assert_eq!(resolve!("ntdll.dll", "NtNotAnExport"), None);
+    assert!(resolve::find_module(resolve::hash("not_loaded.dll")).is_none());
+}
+
+#[test]
+pub fn ipc_test() {
+    let pid = std::process::id();
+    let name = format!("anti_debug_ipc_test_{}", pid);
+    let server = ipc::EventServer::new(&name, ipc::PeerAuth::Pid(pid));
+    let receiver = std::thread::spawn(move || {
+        let connection = server.accept().expect("accept ipc client error");
+        (
+            connection.peer_pid(),
+            connection.recv().expect("recv event error"),
+        )
+    });
+
+    let event = ipc::Event {
+        pid,
+        technique: "hardware_breakpoint".to_string(),
+        category: engine::Category::Debugger,
+        weight: 30,
+        confidence: 95,
+        evidence: Some("Dr0 = \"0x1000\"".to_string()),
+        timestamp: 1,
+    };
+    assert_eq!(ipc::Event::from_json(&event.to_json()).unwrap(), event);
+    let legacy = event.to_json().replace(",\"confidence\":95", "");
+    assert_eq!(ipc::Event::from_json(&legacy).unwrap().confidence, 0);
+    let mut client = ipc::EventClient::new(&name, ipc::PeerAuth::Pid(pid));
+    client.retry_delay = Duration::from_millis(200);
+    client.retries = 10;
+    assert_eq!(client.send(event.clone()).expect("send event error"), 1);
+    assert_eq!(receiver.join().unwrap(), (pid, event.clone()));
+
+    let exe = std::env::current_exe().unwrap();
+    assert!(ipc::PeerAuth::ImagePath(exe.clone()).verify(pid));
+    assert!(!ipc::PeerAuth::ImagePath(exe.with_file_name("copy").join(exe.file_name().unwrap()))
+        .verify(pid));
+
+    // 守护进程不在线时事件保留在队列中
+    let mut offline = ipc::EventClient::new(&format!("{}_offline", name), ipc::PeerAuth::Any);
+    offline.retries = 0;
+    assert!(offline.send(event).is_err());
+    assert_eq!(offline.pending(), 1);
+}
+
+#[test]
+pub fn imports_test() {
+    assert_eq!(unsafe { imports::IsDebuggerPresent() }.as_bool(), false);
+    let mut present = windows::Win32::Foundation::BOOL::default();
+    let hprocess = unsafe { windows::Win32::System::Threading::GetCurrentProcess() };
+    unsafe { imports::CheckRemoteDebuggerPresent(hprocess, &mut present) }
+        .ok()
+        .unwrap();
+    assert_eq!(present.as_bool(), false);
+
+    let hmodule = unsafe { imports::GetModuleHandleW(None) }.unwrap();
+    assert_eq!(
+        Some(hmodule.0 as usize),
+        hook::get_module_from_address(imports_test as fn() as usize)
+            .map(|module| module.0 as usize)
+    );
+    let hprocess = unsafe {
+        imports::OpenProcess(
+            windows::Win32::System::Threading::PROCESS_QUERY_LIMITED_INFORMATION,
+            false,
+            std::process::id(),
+        )
+    }
+    .unwrap();
+    let _ = unsafe { windows::Win32::Foundation::CloseHandle(hprocess) };
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+pub fn spoof_test() {
+    use anti_debug::spoof;
+
+    let gadget = spoof::gadget().expect("jmp rbx gadget not found");
+    assert_eq!(unsafe { *(gadget as *const [u8; 2]) }, [0xFF, 0xE3]);
+
+    // 不切换全局开关，避免与同时运行的其他测试互相影响
+    let address = resolve!("kernel32.dll", "GetCurrentProcessId").unwrap();
+    let expected = (!spoof::is_shadow_stack_enabled()).then_some(std::process::id() as usize);
+    assert_eq!(unsafe { spoof::call_spoofed(address, &[]) }, expected);
+    assert_eq!(
+        unsafe { spoof::call(address, &[]) } as u32,
+        std::process::id()
+    );
+
+    let snapshot = util::create_snapshot(
+        windows::Win32::System::Diagnostics::ToolHelp::TH32CS_SNAPPROCESS,
+        0,
+    )
+    .expect("create snapshot error");
+    let _ = unsafe { windows::Win32::Foundation::CloseHandle(snapshot) };
+}
+
+#[cfg(all(feature = "core", target_arch = "x86_64"))]
+#[test]
+pub fn bare_test() {
+    use anti_debug::bare;
+
+    assert_eq!(bare::being_debugged(), WinPeb::fast_being_debugged());
+    assert_eq!(bare::heap_flags(), false);
+    let ntdll = bare::find_module(bare::hash(b"NTDLL.DLL")).unwrap();
+    let address = unsafe { bare::find_export(ntdll, bare::hash(b"NtClose")) }.unwrap();
+    assert_eq!(
+        Some(address as usize),
+        hook::get_proc_address(hook::get_module("ntdll.dll").unwrap(), "NtClose")
+    );
+    assert_eq!(bare::debug_port(), Some(false));
+    assert_eq!(bare::hardware_breakpoints(), Some(false));
+    assert_eq!(bare::is_debugged(), false);
+}
+
+#[test]
+pub fn clean_ntdll_test() {
+    let ntdll = clean_ntdll::CleanNtdll::load().expect("load clean ntdll error");
+    let nt_query_information_process: nt_query::NtQueryInformationProcessFn =
+        unsafe { ntdll.get_function("NtQueryInformationProcess") }.expect("resolve error");
+
+    let mut debug_port: u64 = 0;
+    let status = unsafe {
+        nt_query_information_process(
+            windows::Win32::System::Threading::GetCurrentProcess(),
+            windows::Wdk::System::Threading::ProcessDebugPort,
+            std::ptr::addr_of_mut!(debug_port).cast(),
+            8,
+            std::ptr::null_mut(),
+        )
+    };
+    assert!(status.is_ok());
+    assert_eq!(debug_port, 0);
+}
+
+#[test]
+pub fn snapshot_test() {
+    let snapshot = snapshot::Snapshot::capture();
+    assert_eq!(snapshot.peb.being_debugged, 0);
+    assert!(snapshot.modules[0].sha256.is_some());
+    let tid = unsafe { windows::Win32::System::Threading::GetCurrentThreadId() };
+    let current = snapshot
+        .threads
+        .iter()
+        .find(|thread| thread.tid == tid)
+        .unwrap();
+    assert_eq!(current.dr, [0; 4]);
+
+    let value = anti_debug::json::parse(&snapshot.to_json()).unwrap();
+    assert_eq!(
+        value.get("pid").and_then(anti_debug::json::Value::as_i64),
+        Some(snapshot.pid as i64)
+    );
+    assert!(value
+        .get("report")
+        .and_then(|report| report.get("verdicts"))
+        .is_some());
+}
+
+#[test]
+pub fn exception_test() {
+    use windows::Win32::Foundation::EXCEPTION_BREAKPOINT;
+
+    let caught = exception::guarded(&[EXCEPTION_BREAKPOINT], 1, || unsafe {
+        std::arch::asm!("int3")
+    })
+    .unwrap()
+    .unwrap();
+    assert_eq!(caught.code, EXCEPTION_BREAKPOINT);
+    assert_eq!(caught.count, 1);
+    assert!(exception::guarded(&[EXCEPTION_BREAKPOINT], 1, || {})
+        .unwrap()
+        .is_none());
+
+    assert!(!exception::check_breakpoint_delivery(exception::BreakRoute::DebugBreak).unwrap());
+    assert!(!exception::check_breakpoint_delivery(exception::BreakRoute::Int3).unwrap());
+    assert!(!exception::check_trap_flag().unwrap());
+    assert!(!exception::check_api_trap_flag().unwrap());
+    assert!(!exception::check_int3_record().unwrap());
+    assert!(!exception::check_prefixed_int3().unwrap());
+    #[cfg(target_arch = "x86")]
+    assert!(!exception::check_mov_ss().unwrap());
+    assert!(!exception::check_invalid_handle(exception::CloseRoute::CloseHandle).unwrap());
+    #[cfg(target_arch = "x86_64")]
+    assert!(!exception::check_invalid_handle(exception::CloseRoute::Syscall).unwrap());
+}
+
+#[test]
+pub fn thread_monitor_test() {
+    use std::sync::mpsc;
+    use windows::Win32::System::Threading::GetCurrentThreadId;
+
+    let mut monitor = thread_monitor::ThreadMonitor::new().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let (done, wait) = mpsc::channel::<()>();
+    let hidden_sender = sender.clone();
+    let hidden = thread_monitor::spawn_hidden(move || {
+        hidden_sender.send(unsafe { GetCurrentThreadId() }).unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+    });
+    // 本库的工作线程通过util::spawn登记，不对调试器隐藏
+    let registered_sender = sender.clone();
+    let registered = util::spawn(move || {
+        registered_sender
+            .send(unsafe { GetCurrentThreadId() })
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+    });
+    let foreign = std::thread::spawn(move || {
+        sender.send(unsafe { GetCurrentThreadId() }).unwrap();
+        let _ = wait.recv();
+    });
+    let tids: Vec<u32> = receiver.iter().take(3).collect();
+
+    let threads = monitor.poll().unwrap();
+    let threads: Vec<_> = threads
+        .into_iter()
+        .chain(monitor.poll().unwrap())
+        .filter(|thread| tids.contains(&thread.tid))
+        .collect();
+    done.send(()).unwrap();
+    hidden.join().unwrap();
+    registered.join().unwrap();
+    foreign.join().unwrap();
+
+    assert_eq!(threads.len(), 1);
+    assert_eq!(threads[0].kind, thread_monitor::StartKind::Image);
+    assert!(!threads[0].is_suspicious());
+}
+
+#[test]
+pub fn wow64_test() {
+    assert_eq!(wow64::Selectors::expected(true).cs, wow64::WOW64_CS32);
+    assert_eq!(wow64::Selectors::expected(false).fs, wow64::NATIVE_FS);
+
+    let arch = wow64::ProcessArch::current();
+    let hprocess = unsafe { windows::Win32::System::Threading::GetCurrentProcess() };
+    assert_eq!(wow64::ProcessArch::of(hprocess).unwrap(), arch);
+    assert_eq!(capability::get().peb, arch.peb_offsets());
+    assert_eq!(arch.context_api(), wow64::ContextApi::Native);
+    assert_eq!(arch.pointer_size(), std::mem::size_of::<usize>());
+    #[cfg(target_arch = "x86")]
+    assert_eq!(wow64::check_selectors().unwrap(), Vec::<String>::new());
+}
+
+#[test]
+pub fn hypervisor_test() {
+    assert!(hypervisor::check_msr_probe().is_ok());
+    hypervisor::scan_hyperdbg_artifacts();
+
+    let function = hypervisor::check_msr_probe as *const () as usize;
+    hypervisor::ept_read_latency(function, || {}, 8);
+    assert!(hypervisor::check_ept_hooks().len() <= 3);
+}
+
+#[test]
+pub fn intel_pt_test() {
+    assert!(ipt::is_trace_session("WindowsPerf-IPT"));
+    assert!(ipt::is_trace_session("ipt_session"));
+    assert!(!ipt::is_trace_session("ScriptHost"));
+
+    assert!(ipt::check_intel_pt().is_ok());
+}
+
+#[test]
+pub fn debug_object_test() {
+    assert!(debug_object::debug_object_type_index().unwrap() > 0);
+    assert!(debug_object::find_debug_objects()
+        .unwrap()
+        .iter()
+        .all(|handle| handle.target.is_none()));
+    assert_eq!(debug_object::check_debug_objects().unwrap(), Vec::new());
+}
@@ -0,0 +1,272 @@
+//! 真实调试器集成测试的辅助模块
+//!
+//! 父进程把当前测试程序作为子进程重新启动，只运行`CHILD_TEST`测试，通过环境变量告知子进程要执行的检测技术；
+//! 父进程以`DebugActiveProcess`附加或者以`DEBUG_ONLY_THIS_PROCESS`启动子进程，并在`WaitForDebugEvent`循环中
+//! 处理调试事件，子进程执行检测技术后以退出码返回结果
+//!
+//! 调试循环模拟常见的调试器：吞掉硬编码的断点与单步异常，并在每个线程中设置一个硬件断点
+
+use anti_debug::engine::{builtin_techniques, Engine};
+use anyhow::{Error, Result};
+use std::{
+    collections::HashMap,
+    env,
+    os::windows::process::CommandExt,
+    process::{self, Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use windows::Win32::{
+    Foundation::{
+        CloseHandle, DBG_CONTINUE, DBG_EXCEPTION_NOT_HANDLED, EXCEPTION_BREAKPOINT,
+        EXCEPTION_SINGLE_STEP, HANDLE, NTSTATUS, STATUS_WX86_BREAKPOINT,
+    },
+    System::{
+        Diagnostics::Debug::{
+            ContinueDebugEvent, DebugActiveProcess, DebugSetProcessKillOnExit, GetThreadContext,
+            IsDebuggerPresent, SetThreadContext, WaitForDebugEvent, CONTEXT,
+            CREATE_PROCESS_DEBUG_EVENT, CREATE_THREAD_DEBUG_EVENT, DEBUG_EVENT,
+            EXCEPTION_DEBUG_EVENT, EXIT_PROCESS_DEBUG_EVENT, EXIT_THREAD_DEBUG_EVENT,
+            LOAD_DLL_DEBUG_EVENT,
+        },
+        Threading::DEBUG_ONLY_THIS_PROCESS,
+    },
+};
+
+#[cfg(target_arch = "x86_64")]
+use windows::Win32::System::Diagnostics::Debug::{
+    CONTEXT_CONTROL_AMD64 as CONTEXT_CONTROL,
+    CONTEXT_DEBUG_REGISTERS_AMD64 as CONTEXT_DEBUG_REGISTERS,
+};
+#[cfg(target_arch = "x86")]
+use windows::Win32::System::Diagnostics::Debug::{
+    CONTEXT_CONTROL_X86 as CONTEXT_CONTROL, CONTEXT_DEBUG_REGISTERS_X86 as CONTEXT_DEBUG_REGISTERS,
+};
+
+/// 子进程要执行的检测技术名称
+pub const CHILD_TECHNIQUE: &str = "ANTI_DEBUG_CHILD_TECHNIQUE";
+
+/// 设置时子进程先等待调试器附加再执行检测
+pub const CHILD_WAIT: &str = "ANTI_DEBUG_CHILD_WAIT";
+
+/// 子进程中运行的测试名称，测试文件需要定义同名测试并调用`child_main`
+pub const CHILD_TEST: &str = "debugger_child";
+
+/// 子进程的退出码
+const EXIT_CLEAN: i32 = 0;
+const EXIT_DETECTED: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+const EXIT_UNKNOWN: i32 = 3;
+const EXIT_NOT_ATTACHED: i32 = 4;
+
+/// 等待调试器附加与子进程退出的最长时间
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 调试循环在每个线程中设置的硬件断点地址，子进程不会执行到这里，断点不会触发
+const HARDWARE_BREAKPOINT: usize = 0x1000;
+
+/// 子进程的运行方式
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Mode {
+    /// 不调试，用于确认检测技术在正常情况下不命中
+    Detached,
+    /// 子进程启动后以`DebugActiveProcess`附加
+    Attach,
+    /// 以`DEBUG_ONLY_THIS_PROCESS`启动子进程，PEB中的NtGlobalFlag与堆标志只在这种方式下设置
+    Launch,
+}
+
+/// 子进程入口：没有设置`CHILD_TECHNIQUE`时直接返回，否则执行检测技术并以退出码返回结果
+pub fn child_main() {
+    let Ok(name) = env::var(CHILD_TECHNIQUE) else {
+        return;
+    };
+
+    if env::var_os(CHILD_WAIT).is_some() {
+        let start = Instant::now();
+        while !unsafe { IsDebuggerPresent() }.as_bool() {
+            if start.elapsed() > TIMEOUT {
+                process::exit(EXIT_NOT_ATTACHED);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    let code = match builtin_techniques()
+        .iter()
+        .find(|technique| technique.name == name)
+    {
+        Some(technique) => {
+            let verdict = Engine::run_technique(technique);
+            match (verdict.error, verdict.detected) {
+                (Some(_), _) => EXIT_ERROR,
+                (None, true) => EXIT_DETECTED,
+                (None, false) => EXIT_CLEAN,
+            }
+        }
+        None => EXIT_UNKNOWN,
+    };
+    process::exit(code);
+}
+
+/// 在子进程中执行检测技术
+///
+/// # 参数
+///
+/// - `technique`: `builtin_techniques`中的技术名称
+/// - `mode`: 子进程的运行方式
+///
+/// # 返回值
+///
+/// - 检测技术是否命中；检测技术执行失败、不存在或者调试器附加超时时返回错误
+pub fn run_child(technique: &str, mode: Mode) -> Result<bool> {
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args([CHILD_TEST, "--exact", "--test-threads=1"])
+        .env(CHILD_TECHNIQUE, technique)
+        // 调试器启动的进程默认使用调试堆，设置_NO_DEBUG_HEAP会关闭
+        .env_remove("_NO_DEBUG_HEAP")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    match mode {
+        Mode::Detached => {}
+        Mode::Attach => {
+            command.env(CHILD_WAIT, "1");
+        }
+        Mode::Launch => {
+            command.creation_flags(DEBUG_ONLY_THIS_PROCESS.0);
+        }
+    }
+
+    // 调试事件只发给附加或者创建子进程的线程，之后的调试循环必须在当前线程中执行
+    let mut child = command.spawn()?;
+    let code = match mode {
+        Mode::Detached => child.wait()?.code().unwrap_or(EXIT_ERROR),
+        Mode::Attach => {
+            if let Err(e) = unsafe { DebugActiveProcess(child.id()) } {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e.into());
+            }
+            debug_loop(&mut child)?
+        }
+        Mode::Launch => debug_loop(&mut child)?,
+    };
+
+    match code {
+        EXIT_CLEAN => Ok(false),
+        EXIT_DETECTED => Ok(true),
+        EXIT_ERROR => Err(Error::msg(format!("{} failed in child", technique))),
+        EXIT_UNKNOWN => Err(Error::msg(format!("unknown technique {}", technique))),
+        EXIT_NOT_ATTACHED => Err(Error::msg("debugger did not attach in time")),
+        code => Err(Error::msg(format!("child exited with {:#x}", code))),
+    }
+}
+
+/// 处理调试事件，直到子进程退出
+///
+/// 断点异常(包括加载器的初始断点)与单步异常以`DBG_CONTINUE`继续，停在`int3`上的断点先越过`int3`，
+/// 与调试器处理硬编码的断点一致；其他异常交给子进程自己处理。新线程中设置一个不会触发的硬件断点。
+/// 超时或者出错时结束子进程，测试线程退出时调试器断开也会结束子进程
+fn debug_loop(child: &mut Child) -> Result<i32> {
+    let _ = unsafe { DebugSetProcessKillOnExit(true) };
+    let deadline = Instant::now() + TIMEOUT;
+    // 线程句柄由系统在线程退出时关闭
+    let mut threads: HashMap<u32, HANDLE> = HashMap::new();
+    loop {
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            return Err(Error::msg("child did not exit in time"));
+        }
+
+        let mut event = DEBUG_EVENT::default();
+        if unsafe { WaitForDebugEvent(&mut event, 100) }.is_err() {
+            continue;
+        }
+
+        let mut status: NTSTATUS = DBG_CONTINUE;
+        let mut exit_code = None;
+        match event.dwDebugEventCode {
+            EXCEPTION_DEBUG_EVENT => {
+                let record = unsafe { event.u.Exception }.ExceptionRecord;
+                match record.ExceptionCode {
+                    EXCEPTION_BREAKPOINT | STATUS_WX86_BREAKPOINT => {
+                        if let Some(&thread) = threads.get(&event.dwThreadId) {
+                            skip_breakpoint(thread, record.ExceptionAddress as usize)?;
+                        }
+                    }
+                    EXCEPTION_SINGLE_STEP => {}
+                    _ => status = DBG_EXCEPTION_NOT_HANDLED,
+                }
+            }
+            CREATE_PROCESS_DEBUG_EVENT => {
+                let info = unsafe { event.u.CreateProcessInfo };
+                let _ = unsafe { CloseHandle(info.hFile) };
+                set_hardware_breakpoint(info.hThread)?;
+                threads.insert(event.dwThreadId, info.hThread);
+            }
+            CREATE_THREAD_DEBUG_EVENT => {
+                let thread = unsafe { event.u.CreateThread }.hThread;
+                set_hardware_breakpoint(thread)?;
+                threads.insert(event.dwThreadId, thread);
+            }
+            EXIT_THREAD_DEBUG_EVENT => {
+                threads.remove(&event.dwThreadId);
+            }
+            LOAD_DLL_DEBUG_EVENT => {
+                let _ = unsafe { CloseHandle(event.u.LoadDll.hFile) };
+            }
+            EXIT_PROCESS_DEBUG_EVENT => {
+                exit_code = Some(unsafe { event.u.ExitProcess.dwExitCode });
+            }
+            _ => {}
+        }
+
+        unsafe { ContinueDebugEvent(event.dwProcessId, event.dwThreadId, status) }?;
+        if let Some(exit_code) = exit_code {
+            let _ = child.wait();
+            return Ok(exit_code as i32);
+        }
+    }
+}
+
+/// 指令指针停在`int3`上时越过它，继续执行时不会再次触发同一个断点
+///
+/// # 参数
+///
+/// - `thread`: 触发断点的线程
+/// - `address`: 断点的异常地址
+fn skip_breakpoint(thread: HANDLE, address: usize) -> Result<()> {
+    let mut context = CONTEXT {
+        ContextFlags: CONTEXT_CONTROL,
+        ..Default::default()
+    };
+    unsafe { GetThreadContext(thread, &mut context) }?;
+
+    #[cfg(target_arch = "x86_64")]
+    let ip = &mut context.Rip;
+    #[cfg(target_arch = "x86")]
+    let ip = &mut context.Eip;
+    if *ip as usize == address {
+        *ip += 1;
+        unsafe { SetThreadContext(thread, &context) }?;
+    }
+    Ok(())
+}
+
+/// 在DR0中设置一个执行断点
+///
+/// # 参数
+///
+/// - `thread`: 目标线程
+fn set_hardware_breakpoint(thread: HANDLE) -> Result<()> {
+    let mut context = CONTEXT {
+        ContextFlags: CONTEXT_DEBUG_REGISTERS,
+        ..Default::default()
+    };
+    unsafe { GetThreadContext(thread, &mut context) }?;
+    context.Dr0 = HARDWARE_BREAKPOINT as _;
+    context.Dr7 |= 1;
+    unsafe { SetThreadContext(thread, &context) }?;
+    Ok(())
+}